@@ -0,0 +1,176 @@
+use std::sync::Arc;
+
+use criterion::criterion_group;
+use criterion::criterion_main;
+use criterion::measurement::WallTime;
+use criterion::BatchSize;
+use criterion::BenchmarkGroup;
+use criterion::BenchmarkId;
+use criterion::Criterion;
+use criterion::Throughput;
+use mcvk::vulkan::commands::CommandQueue;
+use mcvk::vulkan::commands::RenderCommand;
+use mcvk::vulkan::insn_assembler::RenderInsnAssembler;
+use mcvk::vulkan::sandbox::DrawMode;
+use mcvk::vulkan::sandbox::GLDataType;
+use mcvk::vulkan::sandbox::PointerArrayType;
+use mcvk::vulkan::sandbox::RenderInstruction;
+
+mod common;
+
+use common::FRAME_DRAWS;
+use common::FRAME_DRAW_VERTICES;
+
+const VERTEX_COUNT: u32 = 100_000;
+
+const DATA_TYPES: [GLDataType; 8] = [
+    GLDataType::U8,
+    GLDataType::I8,
+    GLDataType::U16,
+    GLDataType::I16,
+    GLDataType::U32,
+    GLDataType::I32,
+    GLDataType::F32,
+    GLDataType::F64,
+];
+
+/// Fills a client array with `element_count` values per vertex of the given type
+fn client_array(data_type: GLDataType, element_count: u8, vertex_count: u32) -> Arc<Vec<u8>> {
+    let values = (0..vertex_count * element_count as u32).map(|i| (i % 100) as u8);
+
+    let data = match data_type {
+        GLDataType::U8 => values.collect(),
+        GLDataType::I8 => values.flat_map(|v| (v as i8).to_ne_bytes()).collect(),
+        GLDataType::U16 => values.flat_map(|v| (v as u16).to_ne_bytes()).collect(),
+        GLDataType::I16 => values.flat_map(|v| (v as i16).to_ne_bytes()).collect(),
+        GLDataType::U32 => values.flat_map(|v| (v as u32).to_ne_bytes()).collect(),
+        GLDataType::I32 => values.flat_map(|v| (v as i32).to_ne_bytes()).collect(),
+        GLDataType::F32 => values.flat_map(|v| (v as f32).to_ne_bytes()).collect(),
+        GLDataType::F64 => values.flat_map(|v| (v as f64).to_ne_bytes()).collect(),
+    };
+
+    Arc::new(data)
+}
+
+fn set_array(
+    array_type: PointerArrayType,
+    item_type: GLDataType,
+    size: u8,
+    vertex_count: u32,
+) -> [RenderInstruction; 2] {
+    [
+        RenderInstruction::SetClientState {
+            enabled: true,
+            array_type,
+        },
+        RenderInstruction::SetPointer {
+            vec_count: vertex_count,
+            array_type,
+            item_type,
+            data: client_array(item_type, size, vertex_count),
+            size,
+            buffer: None,
+            offset: 0,
+            stride: 0,
+        },
+    ]
+}
+
+/// Draws an interleaved position + color buffer. Texcoords are left out because remapping them
+/// needs a texture lookup, which can't be built without a gpu.
+fn bench_draw(group: &mut BenchmarkGroup<WallTime>, position: GLDataType, color: GLDataType) {
+    let mut insns = Vec::new();
+    insns.extend(set_array(
+        PointerArrayType::Vertex,
+        position,
+        3,
+        VERTEX_COUNT,
+    ));
+    insns.extend(set_array(PointerArrayType::Color, color, 4, VERTEX_COUNT));
+
+    let id = BenchmarkId::from_parameter(format!("{position:?}/{color:?}"));
+
+    group.bench_function(id, |b| {
+        b.iter_batched(
+            || {
+                let mut asm = RenderInsnAssembler::new(CommandQueue::Buffered(Vec::new()), None);
+                asm.feed(&insns);
+                asm
+            },
+            |mut asm| asm.draw_arrays(DrawMode::Tri, 0, VERTEX_COUNT),
+            BatchSize::LargeInput,
+        );
+    });
+}
+
+fn assembly_benchmark(c: &mut Criterion) {
+    let mut group = c.benchmark_group("assemble_position");
+    group.throughput(Throughput::Elements(VERTEX_COUNT as u64));
+
+    for data_type in DATA_TYPES {
+        bench_draw(&mut group, data_type, GLDataType::F32);
+    }
+
+    group.finish();
+
+    let mut group = c.benchmark_group("assemble_color");
+    group.throughput(Throughput::Elements(VERTEX_COUNT as u64));
+
+    for data_type in DATA_TYPES {
+        bench_draw(&mut group, GLDataType::F32, data_type);
+    }
+
+    group.finish();
+}
+
+/// Assembles a frame of small draws, then records them like the recorder does, which hands their
+/// buffers back to the arena if `recycle` is set. The frame retires as soon as it's recorded.
+fn record_frame(asm: &mut RenderInsnAssembler, recycle: bool) {
+    for _ in 0..FRAME_DRAWS {
+        asm.draw_arrays(DrawMode::Tri, 0, FRAME_DRAW_VERTICES);
+    }
+
+    let CommandQueue::Buffered(commands) = &mut asm.commands else {
+        unreachable!()
+    };
+
+    for cmd in commands.drain(..) {
+        match cmd {
+            RenderCommand::Draw { data, .. } if recycle => asm.arena.recycle(data),
+            _ => {}
+        }
+    }
+
+    asm.arena.end_frame();
+}
+
+fn frame_arena_benchmark(c: &mut Criterion) {
+    let mut group = c.benchmark_group("frame_arena");
+    group.throughput(Throughput::Elements(FRAME_DRAWS as u64));
+
+    for (name, recycle) in [("without_arena", false), ("with_arena", true)] {
+        let mut asm = RenderInsnAssembler::new(CommandQueue::Buffered(Vec::new()), None);
+        asm.feed(&set_array(
+            PointerArrayType::Vertex,
+            GLDataType::F32,
+            3,
+            FRAME_DRAW_VERTICES,
+        ));
+        asm.feed(&set_array(
+            PointerArrayType::Color,
+            GLDataType::U8,
+            4,
+            FRAME_DRAW_VERTICES,
+        ));
+
+        // the first frame fills the arena
+        record_frame(&mut asm, recycle);
+
+        group.bench_function(name, |b| b.iter(|| record_frame(&mut asm, recycle)));
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, assembly_benchmark, frame_arena_benchmark);
+criterion_main!(benches);
@@ -0,0 +1,44 @@
+//! What the benchmarks share. Each benchmark only uses some of it.
+#![allow(dead_code)]
+
+use std::sync::Arc;
+
+use vulkano::device::Device;
+use vulkano::device::DeviceCreateInfo;
+use vulkano::device::QueueCreateInfo;
+use vulkano::instance::Instance;
+use vulkano::instance::InstanceCreateFlags;
+use vulkano::instance::InstanceCreateInfo;
+use vulkano::VulkanLibrary;
+
+/// A frame of GUI-sized draws
+pub const FRAME_DRAWS: u32 = 1000;
+pub const FRAME_DRAW_VERTICES: u32 = 24;
+
+/// Any device will do, since nothing is drawn. None if there's no vulkan driver.
+pub fn headless_device() -> Option<Arc<Device>> {
+    let instance = Instance::new(
+        VulkanLibrary::new().ok()?,
+        InstanceCreateInfo {
+            flags: InstanceCreateFlags::ENUMERATE_PORTABILITY,
+            ..Default::default()
+        },
+    )
+    .ok()?;
+
+    let physical_device = instance.enumerate_physical_devices().ok()?.next()?;
+
+    let (device, _) = Device::new(
+        physical_device,
+        DeviceCreateInfo {
+            queue_create_infos: vec![QueueCreateInfo {
+                queue_family_index: 0,
+                ..Default::default()
+            }],
+            ..Default::default()
+        },
+    )
+    .ok()?;
+
+    Some(device)
+}
@@ -0,0 +1,84 @@
+use std::sync::Arc;
+
+use criterion::criterion_group;
+use criterion::criterion_main;
+use criterion::Criterion;
+use criterion::Throughput;
+use mcvk::vulkan::arena::DrawBufferPool;
+use vulkano::buffer::Buffer;
+use vulkano::buffer::BufferCreateInfo;
+use vulkano::buffer::BufferUsage;
+use vulkano::buffer::Subbuffer;
+use vulkano::memory::allocator::AllocationCreateInfo;
+use vulkano::memory::allocator::MemoryTypeFilter;
+use vulkano::memory::allocator::StandardMemoryAllocator;
+
+mod common;
+
+use common::headless_device;
+use common::FRAME_DRAWS;
+use common::FRAME_DRAW_VERTICES;
+
+/// Each vertex is a position and a color
+const DRAW_BYTES: usize = FRAME_DRAW_VERTICES as usize * 16;
+
+/// What the recorder did before the pool: a new buffer for every draw
+fn upload_with_buffers(allocator: &Arc<StandardMemoryAllocator>, data: &[u8]) -> Subbuffer<[u8]> {
+    let buffer = Buffer::new_slice::<u8>(
+        allocator.clone(),
+        BufferCreateInfo {
+            usage: BufferUsage::VERTEX_BUFFER,
+            ..Default::default()
+        },
+        AllocationCreateInfo {
+            memory_type_filter: MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+            ..Default::default()
+        },
+        data.len() as u64,
+    )
+    .unwrap();
+
+    buffer.write().unwrap().copy_from_slice(data);
+
+    buffer
+}
+
+/// Uploads a frame of draws, and drops them all at the end like a finished command buffer does
+fn upload_frame(upload: &mut impl FnMut(&[u8]) -> Subbuffer<[u8]>, data: &[u8]) {
+    let frame = (0..FRAME_DRAWS).map(|_| upload(data)).collect::<Vec<_>>();
+
+    drop(frame);
+}
+
+fn draw_buffers_benchmark(c: &mut Criterion) {
+    let Some(device) = headless_device() else {
+        eprintln!("draw_buffers: skipped, there is no vulkan device");
+        return;
+    };
+
+    let allocator = Arc::new(StandardMemoryAllocator::new_default(device));
+    let pool = DrawBufferPool::new(allocator.clone());
+
+    let data = vec![0x7Fu8; DRAW_BYTES];
+
+    let mut group = c.benchmark_group("draw_buffers");
+    group.throughput(Throughput::Elements(FRAME_DRAWS as u64));
+
+    let mut per_draw = |data: &[u8]| upload_with_buffers(&allocator, data);
+    let mut pooled = |data: &[u8]| pool.upload_vertices(data).unwrap();
+
+    let uploaders: [(&str, &mut dyn FnMut(&[u8]) -> Subbuffer<[u8]>); 2] =
+        [("buffer_per_draw", &mut per_draw), ("pooled", &mut pooled)];
+
+    for (name, mut upload) in uploaders {
+        // the first frame creates the pool's arenas
+        upload_frame(&mut upload, &data);
+
+        group.bench_function(name, |b| b.iter(|| upload_frame(&mut upload, &data)));
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, draw_buffers_benchmark);
+criterion_main!(benches);
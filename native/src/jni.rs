@@ -1,14 +1,19 @@
 use std::sync::RwLock;
 
-use anyhow::Context;
+use vulkano::image::SampleCount;
 
+use crate::vulkan::error::McvkError;
 use crate::vulkan::glfw_window::CreateWindowSurface;
 use crate::vulkan::glfw_window::GLFWFns;
 use crate::vulkan::glfw_window::GLFWWindow;
 use crate::vulkan::glfw_window::GetPhysicalDevicePresentationSupport;
 use crate::vulkan::glfw_window::GetRequiredInstanceExtensions;
 use crate::vulkan::glfw_window::GetWindowSize;
+use crate::vulkan::render_manager::DiagnosticsSnapshot;
 use crate::vulkan::sandbox_jni::jni_prelude::*;
+use crate::vulkan::swapchain::FrameAttachment;
+use crate::vulkan::swapchain::FrameBoundary;
+use crate::vulkan::swapchain::RenderMode;
 use crate::vulkan::swapchain::VsyncMode;
 use crate::vulkan::utils::Ref;
 
@@ -36,13 +41,14 @@ macro_rules! field_cache {
     };
 }
 
+/// Unwraps a result, or throws its error as the matching java exception and returns early.
+/// The error must be convertible into a [`McvkError`](crate::vulkan::error::McvkError).
 macro_rules! throw {
     ($env:expr, $res:expr) => {
         match { $res } {
             Ok(x) => x,
             Err(e) => {
-                $env.throw_new("java/lang/RuntimeException", e.to_string())
-                    .unwrap();
+                crate::jni::throw_error(&mut $env, crate::vulkan::error::McvkError::from(e));
 
                 return Default::default();
             }
@@ -59,33 +65,61 @@ macro_rules! jni_bail {
     };
 }
 
-macro_rules! function {
-    () => {{
-        fn f() {}
-        fn type_name_of<T>(_: T) -> &'static str {
-            std::any::type_name::<T>()
-        }
-        let name = type_name_of(f);
-        name.strip_suffix("::f").unwrap()
-    }};
+pub fn throw_error(env: &mut JNIEnv<'_>, error: McvkError) {
+    if error.is_fatal() {
+        tracing::error!(what = "throwing fatal error into java", %error);
+    }
+
+    env.throw_new(error.java_exception_class(), error.to_string())
+        .unwrap();
 }
 
-macro_rules! jni_todo {
-    ($env:expr, $message:literal) => {
-        $env.throw_new("java/lang/RuntimeException", ($message).to_string())
-            .unwrap();
+/// The bytes between a direct buffer's position and its limit, which is the part LWJGL hands to
+/// GL. Returns None if the buffer is null or isn't direct.
+pub unsafe fn direct_buffer_remaining<'a>(
+    env: &mut JNIEnv<'_>,
+    buffer: &JByteBuffer<'_>,
+) -> Option<&'a [u8]> {
+    let (address, len) = direct_buffer_range(env, buffer)?;
 
-        return Default::default();
-    };
-    ($env:expr) => {
-        $env.throw_new(
-            "java/lang/RuntimeException",
-            format!("{} is not yet implemented", function!()),
-        )
-        .unwrap();
+    Some(std::slice::from_raw_parts(address, len))
+}
 
-        return Default::default();
-    };
+/// Like [`direct_buffer_remaining`], but for buffers GL writes into.
+pub unsafe fn direct_buffer_remaining_mut<'a>(
+    env: &mut JNIEnv<'_>,
+    buffer: &JByteBuffer<'_>,
+) -> Option<&'a mut [u8]> {
+    let (address, len) = direct_buffer_range(env, buffer)?;
+
+    Some(std::slice::from_raw_parts_mut(address, len))
+}
+
+unsafe fn direct_buffer_range(
+    env: &mut JNIEnv<'_>,
+    buffer: &JByteBuffer<'_>,
+) -> Option<(*mut u8, usize)> {
+    if buffer.is_null() {
+        return None;
+    }
+
+    let address = env.get_direct_buffer_address(buffer).ok()?;
+
+    let position = env
+        .call_method(buffer, "position", "()I", &[])
+        .ok()?
+        .i()
+        .ok()?;
+    let limit = env
+        .call_method(buffer, "limit", "()I", &[])
+        .ok()?
+        .i()
+        .ok()?;
+
+    Some((
+        address.add(position.max(0) as usize),
+        (limit - position).max(0) as usize,
+    ))
 }
 
 pub static INSTANCE: RwLock<Option<MCVK>> = RwLock::new(None);
@@ -148,30 +182,224 @@ unsafe fn initialize(
 
     let inst = MCVK::new(window);
 
-    let inst = throw!(env, inst.context("could not create vulkan instance"));
+    let mut inst = throw!(env, inst);
+
+    // the GL calls are made from the thread that initializes the renderer
+    inst.attach_gl_thread();
 
     l.replace(inst);
 }
 
 #[jni_export("com.recursive_pineapple.mcvk.rendering.MCVKNative")]
 pub unsafe fn cleanup(_: JNIEnv<'_>, _: JClass<'_>) {
-    INSTANCE.write().unwrap().take();
+    let inst = INSTANCE.write().unwrap().take();
+
+    if let Some(inst) = inst {
+        if let Err(e) = inst.save_pipeline_cache() {
+            tracing::error!(what = "could not save the pipeline cache", error = %e);
+        }
+    }
 }
 
+/// Where the pipeline cache is kept between runs. Loads it immediately; it is saved in
+/// [`cleanup`].
 #[jni_export("com.recursive_pineapple.mcvk.rendering.MCVKNative")]
-pub unsafe fn setMaxFPS(_: JNIEnv<'_>, _: JClass<'_>, max_fps: jint) {
+pub unsafe fn setPipelineCachePath(mut env: JNIEnv<'_>, _: JClass<'_>, path: JString<'_>) {
+    let path: Option<String> = env.get_string(&path).ok().map(Into::into);
+
+    let Some(path) = path else {
+        jni_bail!(env, "the pipeline cache path must be a non-null string");
+    };
+
     write_instance_into!(inst);
 
-    inst.set_max_fps(if max_fps <= 0 {
-        None
-    } else {
-        Some(max_fps as u32)
-    });
+    inst.set_pipeline_cache_path(path.into());
+}
+
+#[jni_export("com.recursive_pineapple.mcvk.rendering.MCVKNative")]
+pub unsafe fn setMaxFPS(mut env: JNIEnv<'_>, _: JClass<'_>, max_fps: jint) {
+    write_instance_into!(inst);
+
+    throw!(
+        env,
+        inst.set_max_fps(if max_fps <= 0 {
+            None
+        } else {
+            Some(max_fps as u32)
+        })
+    );
+}
+
+#[jni_export("com.recursive_pineapple.mcvk.rendering.MCVKNative")]
+pub unsafe fn setVsyncMode(mut env: JNIEnv<'_>, _: JClass<'_>, vsync_mode: jint) {
+    write_instance_into!(inst);
+
+    throw!(
+        env,
+        inst.set_vsync(VsyncMode::from_i32(vsync_mode).unwrap())
+    );
+}
+
+/// Overrides the vsync mode with plain fifo while enabled
+#[jni_export("com.recursive_pineapple.mcvk.rendering.MCVKNative")]
+pub unsafe fn setForceFifo(mut env: JNIEnv<'_>, _: JClass<'_>, force_fifo: jboolean) {
+    write_instance_into!(inst);
+
+    throw!(env, inst.set_force_fifo(force_fifo != 0));
+}
+
+/// Clamped to the highest sample count the device supports. Only forward rendering is
+/// multisampled.
+#[jni_export("com.recursive_pineapple.mcvk.rendering.MCVKNative")]
+pub unsafe fn setMsaaSamples(mut env: JNIEnv<'_>, _: JClass<'_>, samples: jint) {
+    let Ok(msaa) = SampleCount::try_from(samples as u32) else {
+        jni_bail!(env, format!("invalid sample count {samples}"));
+    };
+
+    write_instance_into!(inst);
+
+    throw!(env, inst.set_msaa(msaa));
+}
+
+#[jni_export("com.recursive_pineapple.mcvk.rendering.MCVKNative")]
+pub unsafe fn setMipmapLevels(mut env: JNIEnv<'_>, _: JClass<'_>, levels: jint) {
+    if levels < 0 {
+        jni_bail!(env, format!("invalid mipmap level count {levels}"));
+    }
+
+    write_instance_into!(inst);
+
+    inst.set_mipmap_levels(levels as u32);
+}
+
+/// Clamped to the device's max anisotropy. 1 or less turns anisotropic filtering off.
+#[jni_export("com.recursive_pineapple.mcvk.rendering.MCVKNative")]
+pub unsafe fn setAnisotropy(_: JNIEnv<'_>, _: JClass<'_>, anisotropy: jint) {
+    write_instance_into!(inst);
+
+    inst.set_anisotropy(anisotropy.max(1) as u32);
+}
+
+/// Reloaded textures are swapped in on the first frame after their upload finishes
+#[jni_export("com.recursive_pineapple.mcvk.rendering.MCVKNative")]
+pub unsafe fn setAsyncTextureReload(_: JNIEnv<'_>, _: JClass<'_>, async_reload: jboolean) {
+    write_instance_into!(inst);
+
+    inst.set_async_texture_reload(async_reload != 0);
+}
+
+#[jni_export("com.recursive_pineapple.mcvk.rendering.MCVKNative")]
+pub unsafe fn setGpuTiming(_: JNIEnv<'_>, _: JClass<'_>, enabled: jboolean) {
+    write_instance_into!(inst);
+
+    inst.set_gpu_timing(enabled != 0);
+}
+
+#[jni_export("com.recursive_pineapple.mcvk.rendering.MCVKNative")]
+pub unsafe fn setFrameBoundary(mut env: JNIEnv<'_>, _: JClass<'_>, boundary: jint) {
+    let Some(boundary) = FrameBoundary::from_i32(boundary) else {
+        jni_bail!(env, format!("invalid frame boundary {boundary}"));
+    };
+
+    write_instance_into!(inst);
+
+    throw!(env, inst.set_frame_boundary(boundary));
+}
+
+/// Deferred rendering shades the g-buffer with the RenderManager's lights, and is never
+/// multisampled
+#[jni_export("com.recursive_pineapple.mcvk.rendering.MCVKNative")]
+pub unsafe fn setRenderMode(mut env: JNIEnv<'_>, _: JClass<'_>, mode: jint) {
+    let Some(mode) = RenderMode::from_i32(mode) else {
+        jni_bail!(env, format!("invalid render mode {mode}"));
+    };
+
+    write_instance_into!(inst);
+
+    throw!(env, inst.set_render_mode(mode));
+}
+
+/// Renders each draw's glLoadName into an extra attachment, which RenderSandbox.pick reads
+#[jni_export("com.recursive_pineapple.mcvk.rendering.MCVKNative")]
+pub unsafe fn setObjectPicking(mut env: JNIEnv<'_>, _: JClass<'_>, enabled: jboolean) {
+    write_instance_into!(inst);
+
+    throw!(env, inst.set_object_picking(enabled != 0));
+}
+
+/// Bounds how many render commands can wait to be recorded, see
+/// [`CommandQueue::Bounded`](crate::vulkan::commands::CommandQueue::Bounded). 0 or less leaves the
+/// queue unbounded.
+#[jni_export("com.recursive_pineapple.mcvk.rendering.MCVKNative")]
+pub unsafe fn setCommandQueueCapacity(mut env: JNIEnv<'_>, _: JClass<'_>, capacity: jint) {
+    write_instance_into!(inst);
+
+    throw!(
+        env,
+        inst.set_command_queue_capacity(if capacity <= 0 {
+            None
+        } else {
+            Some(capacity as usize)
+        })
+    );
 }
 
+/// Presents the frame in progress. Colour clears do this by themselves unless the frame boundary
+/// is explicit.
 #[jni_export("com.recursive_pineapple.mcvk.rendering.MCVKNative")]
-pub unsafe fn setVsyncMode(_: JNIEnv<'_>, _: JClass<'_>, vsync_mode: jint) {
+pub unsafe fn present(_: JNIEnv<'_>, _: JClass<'_>) {
     write_instance_into!(inst);
 
-    inst.set_vsync(VsyncMode::from_i32(vsync_mode).unwrap());
+    inst.present();
+}
+
+#[jni_export("com.recursive_pineapple.mcvk.rendering.MCVKNative")]
+pub unsafe fn setPaused(mut env: JNIEnv<'_>, _: JClass<'_>, paused: jboolean) {
+    write_instance_into!(inst);
+
+    throw!(env, inst.set_paused(paused != 0));
+}
+
+/// Copies a [`DiagnosticsSnapshot`] into `out` at its position. `out` must be a direct buffer in
+/// native order.
+#[jni_export("com.recursive_pineapple.mcvk.rendering.MCVKNative")]
+pub unsafe fn getDiagnostics(mut env: JNIEnv<'_>, _: JClass<'_>, out: JByteBuffer<'_>) {
+    read_instance_into!(inst);
+
+    let snapshot = inst.diagnostics();
+
+    let Some(dest) = direct_buffer_remaining_mut(&mut env, &out) else {
+        jni_bail!(env, "the diagnostics buffer must be a direct buffer");
+    };
+
+    let size = std::mem::size_of::<DiagnosticsSnapshot>();
+
+    if dest.len() < size {
+        jni_bail!(
+            env,
+            format!("the diagnostics buffer must hold at least {size} bytes")
+        );
+    }
+
+    std::ptr::copy_nonoverlapping(
+        &snapshot as *const DiagnosticsSnapshot as *const u8,
+        dest.as_mut_ptr(),
+        size,
+    );
+}
+
+/// Returns the VkFormat of a render pass attachment, or 0 (VK_FORMAT_UNDEFINED) if the current
+/// render mode doesn't use it.
+#[jni_export("com.recursive_pineapple.mcvk.rendering.MCVKNative")]
+pub unsafe fn getAttachmentFormat(mut env: JNIEnv<'_>, _: JClass<'_>, attachment: jint) -> jint {
+    let Some(attachment) = FrameAttachment::from_i32(attachment) else {
+        jni_bail!(env, format!("invalid attachment {attachment}"));
+    };
+
+    read_field_into!(inst; swapchain);
+
+    swapchain
+        .attachment_format(attachment)
+        .map(|format| format as jint)
+        .unwrap_or(0)
 }
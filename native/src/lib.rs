@@ -4,4 +4,8 @@
 
 #[macro_use]
 mod jni;
+// only exposed so that the benchmarks can reach the assembler
+#[cfg(feature = "bench")]
+pub mod vulkan;
+#[cfg(not(feature = "bench"))]
 mod vulkan;
@@ -12,5 +12,5 @@ layout(location = 0) out vec4 f_color;
 void main() {
     vec3 ambient_color = ambient.intensity * ambient.color;
     vec3 combined_color = ambient_color * subpassLoad(u_color).rgb;
-    f_color = vec4(combined_color, 1.0);
+    f_color = vec4(combined_color, subpassLoad(u_color).a);
 }
\ No newline at end of file
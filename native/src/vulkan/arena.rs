@@ -0,0 +1,189 @@
+use std::sync::Arc;
+
+use vulkano::buffer::allocator::SubbufferAllocator;
+use vulkano::buffer::allocator::SubbufferAllocatorCreateInfo;
+use vulkano::buffer::BufferContents;
+use vulkano::buffer::BufferUsage;
+use vulkano::buffer::Subbuffer;
+use vulkano::memory::allocator::DeviceLayout;
+use vulkano::memory::allocator::MemoryTypeFilter;
+use vulkano::memory::allocator::StandardMemoryAllocator;
+
+use super::spinlock::SpinLock;
+
+/// The fewest buffers kept across a frame boundary, so that a frame with few draws doesn't free
+/// the buffers the next busy frame will want back.
+const MIN_RETAINED_BUFFERS: usize = 16;
+
+/// How many transient buffers a frame handed out, and how many of those actually allocated
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ArenaStats {
+    pub taken: u32,
+    pub allocations: u32,
+}
+
+#[derive(Debug, Default)]
+struct ArenaPool {
+    free: Vec<Vec<u8>>,
+    frame: ArenaStats,
+    last_frame: ArenaStats,
+}
+
+/// Recycles the CPU-side buffers that only live for one frame, like the assembled vertex and index
+/// buffers of each draw. Thousands of draws a frame would otherwise each allocate and free their
+/// buffers.
+///
+/// Buffers are handed out as plain [`Vec`]s and only come back once whoever holds the last
+/// reference to them calls [`FrameArena::recycle`], so nothing can be reused while a command
+/// still reads it. Buffers which are never recycled are freed as usual.
+#[derive(Debug, Clone)]
+pub struct FrameArena {
+    pool: Arc<SpinLock<ArenaPool>>,
+}
+
+impl Default for FrameArena {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FrameArena {
+    pub fn new() -> Self {
+        Self {
+            pool: Arc::new(SpinLock::new(ArenaPool::default())),
+        }
+    }
+
+    /// An empty buffer which can hold at least `capacity` bytes without reallocating
+    pub fn take(&self, capacity: usize) -> Vec<u8> {
+        let mut pool = self.pool.lock();
+
+        pool.frame.taken += 1;
+
+        // the free list is short, and a close fit wastes less of the retained memory
+        let best_fit = pool
+            .free
+            .iter()
+            .enumerate()
+            .filter(|(_, buffer)| buffer.capacity() >= capacity)
+            .min_by_key(|(_, buffer)| buffer.capacity())
+            .map(|(i, _)| i);
+
+        match best_fit {
+            Some(i) => pool.free.swap_remove(i),
+            None => {
+                pool.frame.allocations += 1;
+                Vec::with_capacity(capacity)
+            }
+        }
+    }
+
+    /// Gives a buffer back once its command has been recorded. Does nothing if the buffer is still
+    /// shared, since the other owner may still read it.
+    pub fn recycle(&self, buffer: Arc<Vec<u8>>) {
+        if let Ok(mut buffer) = Arc::try_unwrap(buffer) {
+            buffer.clear();
+            self.pool.lock().free.push(buffer);
+        }
+    }
+
+    /// Frees the buffers the last frame didn't need, so that one spike doesn't pin its memory
+    /// forever. Must be called once every frame that used the arena has retired.
+    pub fn end_frame(&self) {
+        let mut pool = self.pool.lock();
+
+        let keep = (pool.frame.taken as usize).max(MIN_RETAINED_BUFFERS);
+
+        if pool.free.len() > keep {
+            // the biggest buffers are the least likely to fit a typical draw snugly
+            pool.free.sort_unstable_by_key(|buffer| buffer.capacity());
+            pool.free.truncate(keep);
+        }
+
+        pool.last_frame = std::mem::take(&mut pool.frame);
+    }
+
+    /// The stats of the last finished frame
+    pub fn last_frame_stats(&self) -> ArenaStats {
+        self.pool.lock().last_frame
+    }
+
+    /// How many buffers are waiting to be reused
+    pub fn free_buffers(&self) -> usize {
+        self.pool.lock().free.len()
+    }
+}
+
+/// The initial size of each [`DrawBufferPool`] arena. A typical frame's draws fit in one.
+const DRAW_ARENA_SIZE: u64 = 4 * 1024 * 1024;
+
+/// Vertex data is suballocated at this alignment, so that every attribute type stays aligned no
+/// matter where the previous draw's data ended
+const VERTEX_ALIGNMENT: u64 = 16;
+
+/// Suballocates the GPU-side vertex and index buffers of each draw from a few persistently mapped
+/// arenas, instead of creating a buffer per draw.
+///
+/// An arena is bumped until it's full, then another one is taken. Arenas come back to the pool by
+/// themselves once every subbuffer in them is dropped, which happens when the command buffer that
+/// used them is freed, so a frame only reuses the memory of frames the gpu has finished with. If
+/// a single draw doesn't fit in an arena, the arenas grow to fit it. Each frame slot keeps its own
+/// pool, see [`RenderManager::take_commands`](super::render_manager::RenderManager::take_commands).
+pub struct DrawBufferPool {
+    allocator: SubbufferAllocator,
+}
+
+impl DrawBufferPool {
+    pub fn new(memory_allocator: Arc<StandardMemoryAllocator>) -> Self {
+        Self {
+            allocator: SubbufferAllocator::new(
+                memory_allocator,
+                SubbufferAllocatorCreateInfo {
+                    arena_size: DRAW_ARENA_SIZE,
+                    buffer_usage: BufferUsage::VERTEX_BUFFER | BufferUsage::INDEX_BUFFER,
+                    memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                        | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                    ..Default::default()
+                },
+            ),
+        }
+    }
+
+    /// Copies a draw's assembled vertices into the pool. None if there aren't any, since a buffer
+    /// can't be empty.
+    pub fn upload_vertices(&self, data: &[u8]) -> Option<Subbuffer<[u8]>> {
+        let layout = DeviceLayout::from_size_alignment(data.len() as u64, VERTEX_ALIGNMENT)?;
+
+        let buffer = self.allocator.allocate(layout).unwrap();
+
+        buffer.write().unwrap().copy_from_slice(data);
+
+        Some(buffer)
+    }
+
+    /// Copies a draw's indices into the pool. None if there aren't any, like
+    /// [`DrawBufferPool::upload_vertices`].
+    pub fn upload_indices<T: BufferContents + Copy>(
+        &self,
+        indices: impl ExactSizeIterator<Item = T>,
+    ) -> Option<Subbuffer<[T]>> {
+        let len = indices.len() as u64;
+
+        if len == 0 {
+            return None;
+        }
+
+        let buffer = self.allocator.allocate_slice::<T>(len).unwrap();
+
+        for (dst, src) in buffer.write().unwrap().iter_mut().zip(indices) {
+            *dst = src;
+        }
+
+        Some(buffer)
+    }
+
+    /// The current size of each arena, which only changes when a draw needed more
+    pub fn arena_size(&self) -> u64 {
+        self.allocator.arena_size()
+    }
+}
@@ -0,0 +1,77 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+thread_local! {
+    /// Buffer objects belong to the GL context, which is only ever current on one thread
+    pub static BUFFER_OBJECTS: RefCell<BufferRegistry> = RefCell::new(BufferRegistry::default());
+}
+
+/// The buffer objects created with glGenBuffers. Their contents are sent to the assembler, which
+/// reads pointer arrays out of them when a draw is assembled; only their sizes are kept here, to
+/// validate glBufferSubData.
+#[derive(Debug, Default)]
+pub struct BufferRegistry {
+    /// The size of each buffer in bytes
+    buffers: HashMap<u32, usize>,
+    next_id: u32,
+    /// The GL_ARRAY_BUFFER binding, 0 while pointers point into client memory
+    array_buffer: u32,
+}
+
+impl BufferRegistry {
+    pub fn gen_buffer(&mut self) -> u32 {
+        // 0 is reserved for 'no buffer'
+        self.next_id += 1;
+        self.buffers.insert(self.next_id, 0);
+        self.next_id
+    }
+
+    /// Deleting the bound buffer unbinds it, like GL does. Returns false if `buffer` doesn't
+    /// exist.
+    pub fn delete_buffer(&mut self, buffer: u32) -> bool {
+        if self.array_buffer == buffer {
+            self.array_buffer = 0;
+        }
+
+        self.buffers.remove(&buffer).is_some()
+    }
+
+    /// Returns false if `buffer` isn't 0 or a buffer from [`BufferRegistry::gen_buffer`]
+    pub fn bind_array_buffer(&mut self, buffer: u32) -> bool {
+        if buffer != 0 && !self.buffers.contains_key(&buffer) {
+            return false;
+        }
+
+        self.array_buffer = buffer;
+
+        true
+    }
+
+    pub fn array_buffer(&self) -> u32 {
+        self.array_buffer
+    }
+
+    /// Records the new size of the buffer bound to GL_ARRAY_BUFFER, returning its id. Returns
+    /// None if nothing is bound.
+    pub fn set_array_buffer_data(&mut self, byte_length: usize) -> Option<u32> {
+        let size = self.buffers.get_mut(&self.array_buffer)?;
+
+        *size = byte_length;
+
+        Some(self.array_buffer)
+    }
+
+    /// Checks that `byte_length` bytes starting `offset` bytes in fit in the buffer bound to
+    /// GL_ARRAY_BUFFER, returning its id. Returns None if nothing is bound or the range doesn't
+    /// fit in the buffer, which is an error in GL.
+    pub fn array_buffer_sub_data(&self, offset: usize, byte_length: usize) -> Option<u32> {
+        let size = self.buffers.get(&self.array_buffer)?;
+
+        (offset.saturating_add(byte_length) <= *size).then_some(self.array_buffer)
+    }
+
+    /// The buffer bound to GL_ARRAY_BUFFER, None if nothing is bound
+    pub fn bound_array_buffer(&self) -> Option<u32> {
+        (self.array_buffer != 0).then_some(self.array_buffer)
+    }
+}
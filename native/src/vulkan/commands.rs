@@ -1,23 +1,60 @@
+use std::array::from_fn;
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::ops::Range;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
 
 use derivative::Derivative;
 use smallvec::smallvec;
+use smallvec::SmallVec;
+use tokio::sync::mpsc::error::TryRecvError;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::mpsc::Receiver;
+use tokio::sync::mpsc::Sender;
 use tokio::sync::mpsc::UnboundedSender;
 use vulkano::buffer::BufferUsage;
+use vulkano::buffer::IndexBuffer;
 use vulkano::command_buffer::allocator::CommandBufferAllocator;
 use vulkano::command_buffer::allocator::StandardCommandBufferAllocator;
 use vulkano::command_buffer::AutoCommandBufferBuilder;
 use vulkano::command_buffer::ClearAttachment;
 use vulkano::command_buffer::ClearRect;
 use vulkano::command_buffer::PrimaryAutoCommandBuffer;
+use vulkano::descriptor_set::allocator::StandardDescriptorSetAllocator;
+use vulkano::descriptor_set::layout::DescriptorSetLayout;
+use vulkano::descriptor_set::PersistentDescriptorSet;
+use vulkano::descriptor_set::WriteDescriptorSet;
+use vulkano::device::Device;
+use vulkano::device::DeviceOwned;
 use vulkano::memory::allocator::MemoryTypeFilter;
 use vulkano::memory::allocator::StandardMemoryAllocator;
+use vulkano::pipeline::graphics::viewport::Scissor;
+use vulkano::pipeline::graphics::viewport::Viewport;
 use vulkano::pipeline::GraphicsPipeline;
+use vulkano::pipeline::PipelineBindPoint;
 
+use super::arena::DrawBufferPool;
+use super::arena::FrameArena;
+use super::devices::device_viewport_count;
+use super::dynamic_shader::pad_mat3;
+use super::dynamic_shader::texture_layers_offset;
+use super::dynamic_shader::ColorMode;
 use super::dynamic_shader::DynamicPipeline;
 use super::dynamic_shader::DynamicPipelinePushConstants;
 use super::dynamic_shader::DynamicPipelineSpec;
+use super::dynamic_shader::DynamicRasterState;
 use super::dynamic_shader::PipelineCompiler;
+use super::dynamic_shader::TextureLayers;
+use super::dynamic_shader::MAX_SHADER_TEXTURE_UNITS;
+use super::instance::MAIN_THREAD;
+use super::render_manager::DrawStats;
+use super::sandbox::GLDataType;
+use super::sandbox::ScissorRect;
+use super::spinlock::SpinLock;
+use super::textures::texture_manager::texture_set_key;
+use super::textures::texture_manager::TextureBinding;
+use super::textures::texture_manager::TextureManager;
 use super::utils::Ref;
 
 #[derive(Derivative, Clone)]
@@ -26,29 +63,98 @@ pub enum RenderCommand {
     BindDynamicGraphicsPipeline {
         pipeline: DynamicPipelineSpec,
         push_constants: DynamicPipelinePushConstants,
+        /// Not part of the pipeline, only recorded when it changes
+        raster_state: DynamicRasterState,
     },
     Draw {
         start_vertex: u32,
         vertex_count: u32,
         data: Arc<Vec<u8>>,
     },
-    ClearDepth,
+    /// `index_type` is either U16 or U32
+    DrawIndexed {
+        index_count: u32,
+        index_type: GLDataType,
+        data: Arc<Vec<u8>>,
+        indices: Arc<Vec<u8>>,
+    },
+    /// Binds one descriptor set of the active pipeline, filled with a uniform buffer per binding
+    /// and the texture of each sampler binding. The set is reused for as long as the buffers'
+    /// contents are, see [`UniformSetCache`].
+    BindUniforms {
+        set: u32,
+        buffers: Vec<(u32, Arc<Vec<u8>>)>,
+        textures: Vec<(u32, Option<i32>)>,
+    },
+    /// The textures sampled by each unit of a textured pipeline, in the order of its
+    /// [`TextureCombine`](super::dynamic_shader::TextureCombine). None samples the white dummy
+    /// texture, and textures past the pipeline's unit count are ignored. Only bound if the
+    /// recorder has a [`TextureManager`] to resolve them with.
+    BindTextureUnits([Option<i32>; MAX_SHADER_TEXTURE_UNITS]),
+    /// Binds the sampled units of the active textured pipeline, in order
+    BindTextures(Vec<TextureBinding>),
+    /// Only valid while the bound pipeline uses constant blend factors
+    SetBlendConstants([f32; 4]),
+    /// None resets the scissor to the whole framebuffer
+    SetScissor(Option<ScissorRect>),
+    /// In GL window coordinates. Lasts until the end of the frame. None sets every viewport, like
+    /// glViewport does.
+    SetViewport {
+        index: Option<u32>,
+        x: i32,
+        y: i32,
+        width: u32,
+        height: u32,
+    },
+    /// None sets the depth range of every viewport, like glDepthRange does
+    SetDepthRange {
+        index: Option<u32>,
+        near: f32,
+        far: f32,
+    },
+    /// Clears the colour attachment to the same value the frame starts with. Clears are clipped
+    /// to the scissor region, None clears the whole framebuffer.
+    ClearColour(Option<ScissorRect>),
+    ClearDepth(Option<ScissorRect>),
 }
 
 #[derive(Debug)]
 pub enum CommandQueue {
     Async(UnboundedSender<RenderCommand>),
+    /// Blocks the producer while the worker is behind, so that commands can't pile up during a
+    /// gpu stall. See [`BoundedSender::send`] for the threads which can't be blocked.
+    Bounded(BoundedSender),
     Immediate(Box<CommandRecorder<PrimaryAutoCommandBuffer>>),
     /// Only used for tests
     Buffered(Vec<RenderCommand>),
 }
 
 impl CommandQueue {
+    /// Creates a [`CommandQueue::Bounded`] which holds at most `capacity` commands
+    pub fn bounded(capacity: usize) -> (Self, BoundedReceiver) {
+        let (tx, rx) = tokio::sync::mpsc::channel(capacity.max(1));
+        let overflow = Arc::new(SpinLock::new(VecDeque::new()));
+
+        (
+            Self::Bounded(BoundedSender {
+                sender: tx,
+                overflow: overflow.clone(),
+            }),
+            BoundedReceiver {
+                receiver: rx,
+                overflow,
+            },
+        )
+    }
+
     pub fn push(&mut self, cmd: RenderCommand) -> anyhow::Result<()> {
         match self {
             CommandQueue::Async(queue) => {
                 queue.send(cmd)?;
             }
+            CommandQueue::Bounded(queue) => {
+                queue.send(cmd)?;
+            }
             CommandQueue::Immediate(recorder) => recorder.feed(cmd),
             CommandQueue::Buffered(v) => {
                 v.push(cmd);
@@ -57,6 +163,89 @@ impl CommandQueue {
 
         Ok(())
     }
+
+    /// Whether commands have overflowed a full [`CommandQueue::Bounded`], see
+    /// [`BoundedSender::send`]
+    pub fn is_backed_up(&self) -> bool {
+        match self {
+            CommandQueue::Bounded(queue) => !queue.overflow.lock().is_empty(),
+            _ => false,
+        }
+    }
+}
+
+/// Whether the current thread can block until the render worker has made room. The worker is
+/// drained on the main render thread, which would be waiting on itself, and blocking within the
+/// tokio runtime panics.
+fn can_wait_for_worker() -> bool {
+    let current = std::thread::current().id().as_u64().get();
+
+    current != MAIN_THREAD.load(Ordering::Acquire) && tokio::runtime::Handle::try_current().is_err()
+}
+
+/// The sending half of a [`CommandQueue::Bounded`]
+#[derive(Debug, Clone)]
+pub struct BoundedSender {
+    sender: Sender<RenderCommand>,
+    /// Commands sent while the channel was full by a thread which can't wait, see
+    /// [`BoundedSender::send`]
+    overflow: Arc<SpinLock<VecDeque<RenderCommand>>>,
+}
+
+impl BoundedSender {
+    /// Sends a command, blocking while the channel is full. Threads which can't wait for the
+    /// worker (see [`can_wait_for_worker`]) put it in the overflow instead, which the worker
+    /// receives from once the channel is empty. Everything after an overflowed command has to
+    /// overflow too, so that the commands stay in order. The render thread records the backlog
+    /// itself once it's overflowed, see [`push_instruction`](super::sandbox::push_instruction).
+    pub fn send(&self, cmd: RenderCommand) -> anyhow::Result<()> {
+        let cmd = {
+            let mut overflow = self.overflow.lock();
+
+            if !overflow.is_empty() {
+                overflow.push_back(cmd);
+                return Ok(());
+            }
+
+            match self.sender.try_send(cmd) {
+                Ok(()) => return Ok(()),
+                Err(TrySendError::Full(cmd)) if !can_wait_for_worker() => {
+                    overflow.push_back(cmd);
+                    return Ok(());
+                }
+                Err(TrySendError::Full(cmd)) => cmd,
+                Err(TrySendError::Closed(_)) => anyhow::bail!("the render worker has shut down"),
+            }
+        };
+
+        self.sender.blocking_send(cmd)?;
+
+        Ok(())
+    }
+}
+
+/// The receiving half of a [`CommandQueue::Bounded`]
+#[derive(Debug)]
+pub struct BoundedReceiver {
+    receiver: Receiver<RenderCommand>,
+    overflow: Arc<SpinLock<VecDeque<RenderCommand>>>,
+}
+
+impl BoundedReceiver {
+    /// Receives from the channel, then from the overflow once the channel is empty. Nothing is
+    /// sent to the channel while the overflow has commands, so they're always newer.
+    pub fn try_recv(&mut self) -> Result<RenderCommand, TryRecvError> {
+        let mut overflow = self.overflow.lock();
+
+        match self.receiver.try_recv() {
+            Ok(cmd) => Ok(cmd),
+            Err(e) => overflow.pop_front().ok_or(e),
+        }
+    }
+
+    pub fn close(&mut self) {
+        self.receiver.close();
+    }
 }
 
 #[derive(Derivative)]
@@ -66,6 +255,8 @@ where
     A: CommandBufferAllocator,
 {
     pub allocator: Arc<StandardMemoryAllocator>,
+    #[derivative(Debug = "ignore")]
+    pub descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
 
     #[derivative(Debug = "ignore")]
     pub builder: AutoCommandBufferBuilder<L, A>,
@@ -75,6 +266,340 @@ where
 
     active_dyn_pipeline: Option<(Arc<DynamicPipeline>, DynamicPipelinePushConstants)>,
     active_gfx_pipeline: Option<Arc<GraphicsPipeline>>,
+    /// The dynamic state that was last set by a pipeline bind
+    raster_state: Option<DynamicRasterState>,
+
+    framebuffer_extent: [u32; 2],
+    viewports: ViewportArray,
+    /// The last [`RenderCommand::SetScissor`]
+    scissor: Option<ScissorRect>,
+
+    /// Everything drawn since the recorder was created
+    pub draw_stats: DrawStats,
+
+    /// The last [`RenderCommand::BindTextureUnits`]
+    pub bound_textures: [Option<i32>; MAX_SHADER_TEXTURE_UNITS],
+    /// Resolves [`RenderCommand::BindTextureUnits`] into the textures to bind
+    #[derivative(Debug = "ignore")]
+    pub textures: Option<Ref<TextureManager>>,
+
+    /// Gets the draw buffers back once they've been copied to the gpu. Must be the assembler's
+    /// arena for any of them to be reused.
+    pub arena: FrameArena,
+    /// Where the draw buffers are copied to, the pool of the frame slot being recorded
+    #[derivative(Debug = "ignore")]
+    pub draw_buffers: DrawBufferPool,
+}
+
+/// Identifies a uniform descriptor set by the address of its layout, of each buffer's contents
+/// and of each texture's view and sampler
+pub type UniformSetKey = (usize, SmallVec<[(u32, usize, usize); 4]>);
+
+pub fn uniform_set_key(
+    layout: &Arc<DescriptorSetLayout>,
+    buffers: &[(u32, Arc<Vec<u8>>)],
+    textures: &[(u32, TextureBinding)],
+) -> UniformSetKey {
+    let buffers = buffers
+        .iter()
+        .map(|(binding, data)| (*binding, Arc::as_ptr(data) as usize, 0));
+    let textures = textures.iter().map(|(binding, texture)| {
+        (
+            *binding,
+            Arc::as_ptr(&texture.view) as usize,
+            Arc::as_ptr(&texture.sampler) as usize,
+        )
+    });
+
+    (
+        Arc::as_ptr(layout) as usize,
+        buffers.chain(textures).collect(),
+    )
+}
+
+/// Past this many sets the cache is emptied, like the texture set cache
+const UNIFORM_SET_CACHE_SIZE: usize = 1024;
+
+/// The descriptor sets of [`RenderCommand::BindUniforms`], so that a program's uniform buffers
+/// are only uploaded again once a uniform changes. The assembler copies a block's contents on
+/// write while they're shared, so a set is looked up by the address of the contents it was made
+/// from, which the cache holds on to. Sets whose contents nothing else holds anymore have been
+/// replaced, and are dropped.
+#[derive(Debug)]
+pub struct UniformSetCache<S = Arc<PersistentDescriptorSet>> {
+    sets: HashMap<UniformSetKey, (Vec<Arc<Vec<u8>>>, S)>,
+}
+
+impl<S> Default for UniformSetCache<S> {
+    fn default() -> Self {
+        Self {
+            sets: HashMap::new(),
+        }
+    }
+}
+
+impl<S: Clone> UniformSetCache<S> {
+    /// Returns the cached set for `key`, or calls `create` to make one out of `contents`.
+    /// Nothing is cached if `create` fails.
+    pub fn get_or_create<E>(
+        &mut self,
+        key: UniformSetKey,
+        contents: Vec<Arc<Vec<u8>>>,
+        create: impl FnOnce() -> Result<S, E>,
+    ) -> Result<S, E> {
+        if let Some((_, set)) = self.sets.get(&key) {
+            return Ok(set.clone());
+        }
+
+        self.sets.retain(|_, (contents, _)| {
+            contents
+                .iter()
+                .all(|contents| Arc::strong_count(contents) > 1)
+        });
+
+        if self.sets.len() >= UNIFORM_SET_CACHE_SIZE {
+            self.sets.clear();
+        }
+
+        let set = create()?;
+        self.sets.insert(key, (contents, set.clone()));
+
+        Ok(set)
+    }
+
+    pub fn len(&self) -> usize {
+        self.sets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sets.is_empty()
+    }
+}
+
+/// The most viewports that can be addressed, which is GL's minimum for GL_MAX_VIEWPORTS
+pub const MAX_VIEWPORTS: usize = 16;
+
+/// One of the GL viewports, in GL window coordinates
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GlViewport {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+    pub depth_range: [f32; 2],
+}
+
+/// The device's limits on viewports, which GL viewports are clamped to like GL clamps them to
+/// GL_MAX_VIEWPORT_DIMS and GL_VIEWPORT_BOUNDS_RANGE
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ViewportLimits {
+    pub max_dimensions: [u32; 2],
+    pub bounds_range: [f32; 2],
+}
+
+impl ViewportLimits {
+    pub fn new(device: &Device) -> Self {
+        let properties = device.physical_device().properties();
+
+        Self {
+            max_dimensions: properties.max_viewport_dimensions,
+            bounds_range: properties.viewport_bounds_range,
+        }
+    }
+}
+
+/// The GL state a recorder keeps, which the next recorder has to carry on with since the
+/// assembler only sends it when it changes
+#[derive(Debug, Clone)]
+pub struct RecorderGlState {
+    pub viewports: ViewportArray,
+    pub scissor: Option<ScissorRect>,
+}
+
+/// The viewports the recorder has set. The indexed functions only change part of one viewport, so
+/// the rest of it has to be remembered to set it again.
+#[derive(Debug, Clone)]
+pub struct ViewportArray {
+    viewports: Vec<GlViewport>,
+    limits: ViewportLimits,
+}
+
+impl ViewportArray {
+    /// Every viewport starts out covering the framebuffer, with the whole depth range
+    pub fn new(count: usize, framebuffer_extent: [u32; 2], limits: ViewportLimits) -> Self {
+        let viewport = GlViewport {
+            x: 0,
+            y: 0,
+            width: framebuffer_extent[0],
+            height: framebuffer_extent[1],
+            depth_range: [0.0, 1.0],
+        };
+
+        Self {
+            viewports: vec![viewport; count.max(1)],
+            limits,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.viewports.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.viewports.is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&GlViewport> {
+        self.viewports.get(index)
+    }
+
+    /// The viewports an index refers to, None if it's past the last one
+    fn targets(&self, index: Option<u32>) -> Option<Range<usize>> {
+        match index {
+            None => Some(0..self.viewports.len()),
+            Some(index) if (index as usize) < self.viewports.len() => {
+                Some(index as usize..index as usize + 1)
+            }
+            Some(_) => None,
+        }
+    }
+
+    /// Returns the viewports which changed, or None if `index` is past the last one
+    pub fn set_rect(
+        &mut self,
+        index: Option<u32>,
+        x: i32,
+        y: i32,
+        width: u32,
+        height: u32,
+    ) -> Option<Range<usize>> {
+        let targets = self.targets(index)?;
+
+        for viewport in &mut self.viewports[targets.clone()] {
+            viewport.x = x;
+            viewport.y = y;
+            viewport.width = width;
+            viewport.height = height;
+        }
+
+        Some(targets)
+    }
+
+    /// Both ends are clamped to [0, 1], like GL does. Returns the viewports which changed, or None
+    /// if `index` is past the last one.
+    pub fn set_depth_range(
+        &mut self,
+        index: Option<u32>,
+        near: f32,
+        far: f32,
+    ) -> Option<Range<usize>> {
+        let targets = self.targets(index)?;
+
+        for viewport in &mut self.viewports[targets.clone()] {
+            viewport.depth_range = [near.clamp(0.0, 1.0), far.clamp(0.0, 1.0)];
+        }
+
+        Some(targets)
+    }
+
+    pub fn to_vulkan(
+        &self,
+        targets: Range<usize>,
+        framebuffer_extent: [u32; 2],
+    ) -> SmallVec<[Viewport; 2]> {
+        self.viewports[targets]
+            .iter()
+            .map(|gl| {
+                let mut viewport = viewport_to_vulkan(
+                    gl.x,
+                    gl.y,
+                    gl.width,
+                    gl.height,
+                    framebuffer_extent,
+                    &self.limits,
+                );
+                viewport.depth_range = gl.depth_range[0]..=gl.depth_range[1];
+                viewport
+            })
+            .collect()
+    }
+}
+
+/// Whether binding `next` switches pipelines. Rebinding the active one is skipped, since only its
+/// push constants can have changed.
+pub fn needs_pipeline_bind(
+    active: Option<&DynamicPipelineSpec>,
+    next: &DynamicPipelineSpec,
+) -> bool {
+    active != Some(next)
+}
+
+/// Converts a GL scissor rectangle (bottom left origin) into a vulkan one (top left origin),
+/// clipping it to the framebuffer.
+pub fn scissor_to_vulkan(rect: &ScissorRect, framebuffer_extent: [u32; 2]) -> Scissor {
+    let clipped = rect.intersect(&ScissorRect {
+        x: 0,
+        y: 0,
+        width: framebuffer_extent[0],
+        height: framebuffer_extent[1],
+    });
+
+    Scissor {
+        offset: [
+            clipped.x as u32,
+            framebuffer_extent[1].saturating_sub(clipped.y as u32 + clipped.height),
+        ],
+        extent: [clipped.width, clipped.height],
+    }
+}
+
+/// The area a clear covers: the scissor region if there is one, otherwise the whole framebuffer
+pub fn clear_rect(region: Option<ScissorRect>, framebuffer_extent: [u32; 2]) -> ClearRect {
+    let (offset, extent) = match region {
+        Some(rect) => {
+            let scissor = scissor_to_vulkan(&rect, framebuffer_extent);
+            (scissor.offset, scissor.extent)
+        }
+        None => ([0; 2], framebuffer_extent),
+    };
+
+    ClearRect {
+        offset,
+        extent,
+        array_layers: 0..1,
+    }
+}
+
+/// Converts a GL viewport (bottom left origin) into a vulkan one (top left origin). Unlike
+/// scissors, viewports may extend past the framebuffer.
+///
+/// The viewport's height is negative so that clip space Y points up like in GL, which also keeps
+/// GL's front-face winding (this needs vulkan 1.1).
+///
+/// The size is clamped to the device's limits. Vulkan has no empty viewports, so an empty GL one
+/// becomes a single pixel. The position is then clamped so the viewport stays within the bounds
+/// range.
+pub fn viewport_to_vulkan(
+    x: i32,
+    y: i32,
+    width: u32,
+    height: u32,
+    framebuffer_extent: [u32; 2],
+    limits: &ViewportLimits,
+) -> Viewport {
+    let width = width.clamp(1, limits.max_dimensions[0].max(1)) as f32;
+    let height = height.clamp(1, limits.max_dimensions[1].max(1)) as f32;
+    let [min, max] = limits.bounds_range;
+
+    let left = (x as f32).clamp(min, (max - width).max(min));
+    let bottom =
+        ((framebuffer_extent[1] as i64 - y as i64) as f32).clamp((min + height).min(max), max);
+
+    Viewport {
+        offset: [left, bottom],
+        extent: [width, -height],
+        depth_range: 0.0..=1.0,
+    }
 }
 
 impl<L, A> CommandRecorder<L, A>
@@ -83,15 +608,269 @@ where
 {
     pub fn new(
         allocator: Arc<StandardMemoryAllocator>,
+        descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
         builder: AutoCommandBufferBuilder<L, A>,
+        draw_buffers: DrawBufferPool,
         pipeline_compiler: Ref<PipelineCompiler>,
+        framebuffer_extent: [u32; 2],
     ) -> Self {
+        let viewport_count = device_viewport_count(builder.device());
+        let viewport_limits = ViewportLimits::new(builder.device());
+
         Self {
+            draw_buffers,
             allocator,
+            descriptor_set_allocator,
             builder,
             pipeline_compiler,
             active_dyn_pipeline: None,
             active_gfx_pipeline: None,
+            raster_state: None,
+            framebuffer_extent,
+            viewports: ViewportArray::new(viewport_count, framebuffer_extent, viewport_limits),
+            scissor: None,
+            draw_stats: DrawStats::default(),
+            bound_textures: [None; MAX_SHADER_TEXTURE_UNITS],
+            textures: None,
+            arena: FrameArena::new(),
+        }
+    }
+
+    /// The GL viewports and scissor, for [`CommandRecorder::restore_gl_state`]
+    pub fn gl_state(&self) -> RecorderGlState {
+        RecorderGlState {
+            viewports: self.viewports.clone(),
+            scissor: self.scissor,
+        }
+    }
+
+    /// Carries on with the GL viewports and scissor of an earlier recorder. They're set again,
+    /// since beginning a render pass resets them to cover the framebuffer.
+    pub fn restore_gl_state(&mut self, state: RecorderGlState) {
+        self.viewports = state.viewports;
+        self.update_viewports(Some(0..self.viewports.len()), None);
+        self.set_scissor(state.scissor);
+    }
+
+    /// glScissor sets the scissor of every viewport
+    fn set_scissor(&mut self, rect: Option<ScissorRect>) {
+        let scissor = match rect {
+            Some(rect) => scissor_to_vulkan(&rect, self.framebuffer_extent),
+            None => Scissor {
+                offset: [0; 2],
+                extent: self.framebuffer_extent,
+            },
+        };
+
+        self.builder
+            .set_scissor(0, smallvec![scissor; self.viewports.len()])
+            .unwrap();
+
+        self.scissor = rect;
+    }
+
+    /// Sets the vulkan viewports for the GL viewports which changed
+    fn update_viewports(&mut self, changed: Option<Range<usize>>, index: Option<u32>) {
+        let Some(changed) = changed else {
+            tracing::warn!(
+                what = "tried to set a viewport past the last one",
+                index,
+                viewports = self.viewports.len()
+            );
+            return;
+        };
+
+        let first = changed.start as u32;
+        let viewports = self.viewports.to_vulkan(changed, self.framebuffer_extent);
+
+        self.builder.set_viewport(first, viewports).unwrap();
+    }
+
+    /// Returns false if there are no vertices, in which case there's nothing to draw
+    fn bind_vertices(&mut self, data: &[u8]) -> bool {
+        let Some(vertex_buffer) = self.draw_buffers.upload_vertices(data) else {
+            return false;
+        };
+
+        self.builder.bind_vertex_buffers(0, vertex_buffer).unwrap();
+
+        true
+    }
+
+    /// Binds the textures of a textured pipeline's units to its sampler descriptors, and pushes
+    /// the layer each unit samples out of its array
+    fn bind_textures(&mut self, textures: Vec<TextureBinding>) {
+        let Some((pipeline, _)) = &self.active_dyn_pipeline else {
+            tracing::warn!(what = "tried to bind textures without a pipeline");
+            return;
+        };
+
+        let ColorMode::Texture {
+            set,
+            binding,
+            units,
+        } = pipeline.spec.color
+        else {
+            tracing::warn!(what = "tried to bind textures to an untextured pipeline");
+            return;
+        };
+
+        if textures.len() < units.unit_count() {
+            tracing::warn!(
+                what = "tried to bind fewer textures than the pipeline samples",
+                textures = textures.len(),
+                units = units.unit_count()
+            );
+            return;
+        }
+
+        let textures = &textures[..units.unit_count()];
+        let layout = pipeline.layout.set_layouts()[set as usize].clone();
+
+        let create = || {
+            // each unit is the binding after the previous unit's
+            let writes = (binding as u32..).zip(textures).map(|(binding, texture)| {
+                WriteDescriptorSet::image_view_sampler(
+                    binding,
+                    texture.view.clone(),
+                    texture.sampler.clone(),
+                )
+            });
+
+            PersistentDescriptorSet::new(&self.descriptor_set_allocator, layout.clone(), writes, [])
+        };
+
+        let descriptor_set = match &self.textures {
+            Some(manager) => manager
+                .write()
+                .texture_sets
+                .get_or_create(texture_set_key(&layout, textures), create),
+            None => create(),
+        };
+
+        let descriptor_set = match descriptor_set {
+            Ok(descriptor_set) => descriptor_set,
+            Err(error) => {
+                tracing::warn!(what = "could not create a texture descriptor set", %error);
+                return;
+            }
+        };
+
+        // units the pipeline doesn't sample are left at layer 0
+        let layers: TextureLayers = from_fn(|unit| textures.get(unit).map_or(0, |t| t.layer));
+
+        self.builder
+            .bind_descriptor_sets(
+                PipelineBindPoint::Graphics,
+                pipeline.layout.clone(),
+                set as u32,
+                descriptor_set,
+            )
+            .unwrap()
+            .push_constants(
+                pipeline.layout.clone(),
+                texture_layers_offset(&pipeline.spec),
+                layers,
+            )
+            .unwrap();
+    }
+
+    fn bind_uniforms(
+        &mut self,
+        set: u32,
+        buffers: Vec<(u32, Arc<Vec<u8>>)>,
+        textures: Vec<(u32, Option<i32>)>,
+    ) {
+        let Some((pipeline, _)) = &self.active_dyn_pipeline else {
+            tracing::warn!(what = "tried to bind uniforms without a pipeline");
+            return;
+        };
+
+        // without a texture manager to resolve the samplers with, the set can't be completed
+        let textures = match (&self.textures, textures.is_empty()) {
+            (_, true) => Vec::new(),
+            (Some(manager), false) => {
+                let mut manager = manager.write();
+
+                let bindings = textures
+                    .iter()
+                    .map(|(binding, texture)| {
+                        Ok((*binding, manager.program_texture_binding(*texture)?))
+                    })
+                    .collect::<anyhow::Result<Vec<_>>>();
+
+                match bindings {
+                    Ok(bindings) => bindings,
+                    Err(error) => {
+                        tracing::warn!(what = "could not bind a program's textures", %error);
+                        return;
+                    }
+                }
+            }
+            (None, false) => return,
+        };
+
+        let layout = pipeline.layout.set_layouts()[set as usize].clone();
+
+        let create = || -> anyhow::Result<_> {
+            let mut writes = Vec::with_capacity(buffers.len() + textures.len());
+
+            for (binding, data) in &buffers {
+                let buffer = vulkano::buffer::Buffer::from_iter(
+                    self.allocator.clone(),
+                    vulkano::buffer::BufferCreateInfo {
+                        usage: BufferUsage::UNIFORM_BUFFER,
+                        ..Default::default()
+                    },
+                    vulkano::memory::allocator::AllocationCreateInfo {
+                        memory_type_filter: MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                        ..Default::default()
+                    },
+                    data.iter().copied(),
+                )?;
+
+                writes.push(WriteDescriptorSet::buffer(*binding, buffer));
+            }
+
+            for (binding, texture) in &textures {
+                writes.push(WriteDescriptorSet::image_view_sampler(
+                    *binding,
+                    texture.view.clone(),
+                    texture.sampler.clone(),
+                ));
+            }
+
+            Ok(PersistentDescriptorSet::new(
+                &self.descriptor_set_allocator,
+                layout.clone(),
+                writes,
+                [],
+            )?)
+        };
+
+        let key = uniform_set_key(&layout, &buffers, &textures);
+        let contents = buffers.iter().map(|(_, data)| data.clone()).collect();
+
+        let descriptor_set = self
+            .pipeline_compiler
+            .write()
+            .uniform_sets
+            .get_or_create(key, contents, create);
+
+        match descriptor_set {
+            Ok(descriptor_set) => {
+                self.builder
+                    .bind_descriptor_sets(
+                        PipelineBindPoint::Graphics,
+                        pipeline.layout.clone(),
+                        set,
+                        descriptor_set,
+                    )
+                    .unwrap();
+            }
+            Err(error) => {
+                tracing::warn!(what = "could not create a uniform descriptor set", %error);
+            }
         }
     }
 
@@ -100,23 +879,41 @@ where
             RenderCommand::BindDynamicGraphicsPipeline {
                 pipeline,
                 push_constants,
+                raster_state,
             } => {
-                let different_pipeline = if let Some((active, _)) = &self.active_dyn_pipeline {
-                    pipeline != active.spec
-                } else {
-                    true
-                };
+                let active = self.active_dyn_pipeline.as_ref().map(|(a, _)| &a.spec);
 
-                if different_pipeline {
-                    let compiled = self.pipeline_compiler.write().compile(&pipeline);
+                if needs_pipeline_bind(active, &pipeline) {
+                    let compiled = match self.pipeline_compiler.write().compile(&pipeline) {
+                        Ok(compiled) => compiled,
+                        Err(error) => {
+                            // the next bind tries again, since nothing is active to compare with
+                            tracing::error!(what = "could not build a pipeline", %error, ?pipeline);
+                            self.active_dyn_pipeline = None;
+                            return;
+                        }
+                    };
                     self.active_dyn_pipeline = Some((compiled.clone(), Default::default()));
 
                     self.builder
                         .bind_pipeline_graphics(compiled.pipeline.clone())
                         .unwrap();
+                    self.draw_stats.bind_pipeline();
                 }
 
-                let (pipeline, pc) = self.active_dyn_pipeline.as_ref().unwrap();
+                if self.raster_state != Some(raster_state) {
+                    self.builder
+                        .set_cull_mode(raster_state.cull_mode)
+                        .unwrap()
+                        .set_front_face(raster_state.front_face)
+                        .unwrap()
+                        .set_line_width(raster_state.line_width)
+                        .unwrap();
+
+                    self.raster_state = Some(raster_state);
+                }
+
+                let (pipeline, pc) = self.active_dyn_pipeline.as_mut().unwrap();
 
                 if pc != &push_constants {
                     let mut offset = 0;
@@ -128,11 +925,62 @@ where
                         offset += size_of_val(mvp) as u32;
                     }
 
+                    if let Some(normal_matrix) = push_constants.normal_matrix.as_ref() {
+                        let padded = pad_mat3(normal_matrix);
+
+                        self.builder
+                            .push_constants(pipeline.layout.clone(), offset, padded)
+                            .unwrap();
+                        offset += size_of_val(&padded) as u32;
+                    }
+
+                    for plane in &push_constants.clip_planes {
+                        self.builder
+                            .push_constants(pipeline.layout.clone(), offset, *plane)
+                            .unwrap();
+                        offset += size_of_val(plane) as u32;
+                    }
+
                     if let Some(color) = push_constants.color.as_ref() {
                         self.builder
                             .push_constants(pipeline.layout.clone(), offset, *color)
                             .unwrap();
+                        offset += size_of_val(color) as u32;
+                    }
+
+                    // the layers are pushed when the textures are bound
+                    if let ColorMode::Texture { .. } = &pipeline.spec.color {
+                        offset += size_of::<TextureLayers>() as u32;
                     }
+
+                    if let Some(texcoord) = push_constants.lightmap_texcoord.as_ref() {
+                        self.builder
+                            .push_constants(pipeline.layout.clone(), offset, *texcoord)
+                            .unwrap();
+                        offset += size_of_val(texcoord) as u32;
+                    }
+
+                    for texcoord in &push_constants.unit_texcoords {
+                        self.builder
+                            .push_constants(pipeline.layout.clone(), offset, *texcoord)
+                            .unwrap();
+                        offset += size_of_val(texcoord) as u32;
+                    }
+
+                    if let Some(scale) = push_constants.normal_scale {
+                        self.builder
+                            .push_constants(pipeline.layout.clone(), offset, scale)
+                            .unwrap();
+                        offset += size_of_val(&scale) as u32;
+                    }
+
+                    if let Some(object_id) = push_constants.object_id {
+                        self.builder
+                            .push_constants(pipeline.layout.clone(), offset, object_id)
+                            .unwrap();
+                    }
+
+                    *pc = push_constants;
                 }
             }
             RenderCommand::Draw {
@@ -140,36 +988,116 @@ where
                 vertex_count,
                 data,
             } => {
-                let vertex_buffer = vulkano::buffer::Buffer::new_slice::<u8>(
-                    self.allocator.clone(),
-                    vulkano::buffer::BufferCreateInfo {
-                        usage: BufferUsage::VERTEX_BUFFER,
-                        ..Default::default()
-                    },
-                    vulkano::memory::allocator::AllocationCreateInfo {
-                        memory_type_filter: MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
-                        ..Default::default()
-                    },
-                    data.len() as u64,
-                )
-                .unwrap();
+                let bound = self.bind_vertices(&data);
+                self.arena.recycle(data);
 
-                {
-                    let mut guard = vertex_buffer.write().unwrap();
-                    guard.copy_from_slice(&data);
+                if bound {
+                    self.builder.draw(vertex_count, 1, start_vertex, 0);
+                    self.draw_stats.draw(vertex_count);
                 }
+            }
+            RenderCommand::DrawIndexed {
+                index_count,
+                index_type,
+                data,
+                indices,
+            } => {
+                let bound = self.bind_vertices(&data);
+                self.arena.recycle(data);
+
+                let index_buffer = match index_type {
+                    GLDataType::U16 => self
+                        .draw_buffers
+                        .upload_indices(
+                            indices
+                                .chunks_exact(2)
+                                .map(|i| u16::from_ne_bytes([i[0], i[1]])),
+                        )
+                        .map(IndexBuffer::U16),
+                    GLDataType::U32 => self
+                        .draw_buffers
+                        .upload_indices(
+                            indices
+                                .chunks_exact(4)
+                                .map(|i| u32::from_ne_bytes([i[0], i[1], i[2], i[3]])),
+                        )
+                        .map(IndexBuffer::U32),
+                    _ => {
+                        tracing::warn!(
+                            what = "tried to draw with an invalid index type",
+                            ?index_type
+                        );
+                        return;
+                    }
+                };
+
+                self.arena.recycle(indices);
 
-                self.builder.bind_vertex_buffers(0, vertex_buffer);
-                self.builder.draw(vertex_count, 1, start_vertex, 0);
+                let (true, Some(index_buffer)) = (bound, index_buffer) else {
+                    return;
+                };
+
+                self.builder.bind_index_buffer(index_buffer).unwrap();
+                self.builder.draw_indexed(index_count, 1, 0, 0, 0).unwrap();
+                self.draw_stats.draw(index_count);
+            }
+            RenderCommand::BindTextureUnits(textures) => {
+                self.bound_textures = textures;
+
+                if let Some(manager) = self.textures.clone() {
+                    let bindings = {
+                        let mut manager = manager.write();
+                        textures
+                            .iter()
+                            .map(|texture| manager.texture_binding(*texture))
+                            .collect::<anyhow::Result<Vec<_>>>()
+                    };
+
+                    match bindings {
+                        Ok(bindings) => self.bind_textures(bindings),
+                        Err(error) => {
+                            tracing::warn!(what = "could not bind textures", %error);
+                        }
+                    }
+                }
+            }
+            RenderCommand::BindTextures(textures) => self.bind_textures(textures),
+            RenderCommand::BindUniforms {
+                set,
+                buffers,
+                textures,
+            } => self.bind_uniforms(set, buffers, textures),
+            RenderCommand::SetScissor(rect) => self.set_scissor(rect),
+            RenderCommand::SetViewport {
+                index,
+                x,
+                y,
+                width,
+                height,
+            } => {
+                let changed = self.viewports.set_rect(index, x, y, width, height);
+                self.update_viewports(changed, index);
+            }
+            RenderCommand::SetDepthRange { index, near, far } => {
+                let changed = self.viewports.set_depth_range(index, near, far);
+                self.update_viewports(changed, index);
             }
-            RenderCommand::ClearDepth => {
+            RenderCommand::SetBlendConstants(constants) => {
+                self.builder.set_blend_constants(constants).unwrap();
+            }
+            RenderCommand::ClearColour(region) => {
                 self.builder.clear_attachments(
-                    smallvec![ClearAttachment::Depth(1f32)],
-                    smallvec![ClearRect {
-                        offset: [0; 2],
-                        extent: [u32::MAX; 2],
-                        array_layers: 0..0
+                    smallvec![ClearAttachment::Color {
+                        color_attachment: 0,
+                        clear_value: [0.0, 0.0, 0.0, 1.0].into(),
                     }],
+                    smallvec![clear_rect(region, self.framebuffer_extent)],
+                );
+            }
+            RenderCommand::ClearDepth(region) => {
+                self.builder.clear_attachments(
+                    smallvec![ClearAttachment::Depth(1f32)],
+                    smallvec![clear_rect(region, self.framebuffer_extent)],
                 );
             }
         }
@@ -5,9 +5,19 @@ use vulkano::device::physical::PhysicalDeviceType;
 use vulkano::device::Device;
 use vulkano::device::DeviceCreateInfo;
 use vulkano::device::DeviceExtensions;
+use vulkano::device::Features;
 use vulkano::device::Queue;
 use vulkano::device::QueueCreateInfo;
 use vulkano::device::QueueFlags;
+use vulkano::format::Format;
+use vulkano::format::FormatFeatures;
+use vulkano::image::ImageFormatInfo;
+use vulkano::instance::debug::DebugUtilsMessageSeverity;
+use vulkano::instance::debug::DebugUtilsMessageType;
+use vulkano::instance::debug::DebugUtilsMessenger;
+use vulkano::instance::debug::DebugUtilsMessengerCallback;
+use vulkano::instance::debug::DebugUtilsMessengerCallbackData;
+use vulkano::instance::debug::DebugUtilsMessengerCreateInfo;
 use vulkano::instance::Instance;
 use vulkano::instance::InstanceCreateFlags;
 use vulkano::instance::InstanceCreateInfo;
@@ -15,17 +25,190 @@ use vulkano::instance::InstanceExtensions;
 use vulkano::Version;
 use vulkano::VulkanLibrary;
 
+use super::commands::MAX_VIEWPORTS;
+use super::dynamic_shader::MAX_SHADER_TEXTURE_UNITS;
 use super::glfw_window::GLFWWindow;
 use super::instance::VulkanInitError;
+use super::swapchain::pick_depth_format;
+use super::swapchain::FrameAttachment;
 use super::utils::Ref;
 
 pub struct Devices {
     pub instance: Arc<Instance>,
     pub device: Arc<Device>,
     pub queue: Arc<Queue>,
+    /// Asynchronous texture reloads are submitted here, see [`graphics_queue_priorities`]. The
+    /// same queue as [`Devices::queue`] if the family only has the one.
+    pub transfer_queue: Arc<Queue>,
+    /// Forwards validation layer messages to the log while validation is enabled
+    pub debug_messenger: Option<DebugUtilsMessenger>,
+}
+
+const VALIDATION_LAYER: &str = "VK_LAYER_KHRONOS_validation";
+
+/// Set to anything other than `0` to run debug builds without the validation layer
+pub const DISABLE_VALIDATION_VAR: &str = "MCVK_DISABLE_VALIDATION";
+
+/// Whether `value`, the contents of [`DISABLE_VALIDATION_VAR`], opts out of validation
+pub fn validation_opted_out(value: Option<&str>) -> bool {
+    value.is_some_and(|value| !value.is_empty() && value != "0")
+}
+
+/// Validation is only enabled in debug builds which haven't opted out, and only if the layer is
+/// installed
+pub fn validation_enabled(debug_build: bool, opted_out: bool, layer_present: bool) -> bool {
+    debug_build && !opted_out && layer_present
+}
+
+/// The queues made in the graphics family, by priority. Texture reloads get a second queue of
+/// their own when the family has one, so that their uploads run alongside the frames instead of
+/// queueing up behind them. It's from the graphics family rather than a transfer-only one since
+/// mipmaps are blitted, and so that the texture arrays never change queue family ownership.
+pub fn graphics_queue_priorities(queue_count: u32) -> Vec<f32> {
+    if queue_count > 1 {
+        vec![1.0, 0.5]
+    } else {
+        vec![1.0]
+    }
+}
+
+/// How many texture units to advertise. Every unit can end up as a sampler in one shader stage
+/// and one descriptor set, so neither limit can be exceeded, and the generated shaders don't
+/// sample more than [`MAX_SHADER_TEXTURE_UNITS`].
+pub fn texture_unit_limit(max_per_stage_sampled_images: u32, max_set_sampled_images: u32) -> usize {
+    (max_per_stage_sampled_images.min(max_set_sampled_images) as usize)
+        .min(MAX_SHADER_TEXTURE_UNITS)
+}
+
+/// How many viewports glViewportIndexedf can address. Without multiViewport there's only the one
+/// glViewport sets.
+pub fn viewport_limit(multi_viewport: bool, max_viewports: u32) -> usize {
+    if !multi_viewport {
+        return 1;
+    }
+
+    (max_viewports as usize).clamp(1, MAX_VIEWPORTS)
+}
+
+/// See [`viewport_limit`]. Every dynamic pipeline has this many viewports, so all of them have to
+/// be set before drawing.
+pub fn device_viewport_count(device: &Device) -> usize {
+    viewport_limit(
+        device.enabled_features().multi_viewport,
+        device.physical_device().properties().max_viewports,
+    )
+}
+
+/// The messenger which routes validation messages into tracing, or None if validation is disabled
+pub fn debug_messenger_create_info(validation: bool) -> Option<DebugUtilsMessengerCreateInfo> {
+    if !validation {
+        return None;
+    }
+
+    Some(DebugUtilsMessengerCreateInfo {
+        message_severity: DebugUtilsMessageSeverity::ERROR
+            | DebugUtilsMessageSeverity::WARNING
+            | DebugUtilsMessageSeverity::INFO,
+        message_type: DebugUtilsMessageType::GENERAL
+            | DebugUtilsMessageType::VALIDATION
+            | DebugUtilsMessageType::PERFORMANCE,
+        // the callback only logs, so it can't unwind or call back into vulkan
+        ..DebugUtilsMessengerCreateInfo::user_callback(unsafe {
+            DebugUtilsMessengerCallback::new(log_debug_message)
+        })
+    })
+}
+
+/// The log level of a validation message. The layer's info messages are mostly noise about which
+/// devices and layers were found, so they're only shown at debug.
+pub fn debug_message_level(severity: DebugUtilsMessageSeverity) -> tracing::Level {
+    if severity.intersects(DebugUtilsMessageSeverity::ERROR) {
+        tracing::Level::ERROR
+    } else if severity.intersects(DebugUtilsMessageSeverity::WARNING) {
+        tracing::Level::WARN
+    } else if severity.intersects(DebugUtilsMessageSeverity::INFO) {
+        tracing::Level::DEBUG
+    } else {
+        tracing::Level::TRACE
+    }
+}
+
+fn log_debug_message(
+    severity: DebugUtilsMessageSeverity,
+    message_type: DebugUtilsMessageType,
+    data: DebugUtilsMessengerCallbackData<'_>,
+) {
+    let id = data.message_id_name.unwrap_or("");
+    let message = data.message;
+
+    match debug_message_level(severity) {
+        tracing::Level::ERROR => {
+            tracing::error!(
+                what = "vulkan validation message",
+                ?message_type,
+                id,
+                message
+            )
+        }
+        tracing::Level::WARN => {
+            tracing::warn!(
+                what = "vulkan validation message",
+                ?message_type,
+                id,
+                message
+            )
+        }
+        tracing::Level::DEBUG => {
+            tracing::debug!(
+                what = "vulkan validation message",
+                ?message_type,
+                id,
+                message
+            )
+        }
+        _ => tracing::trace!(
+            what = "vulkan validation message",
+            ?message_type,
+            id,
+            message
+        ),
+    }
 }
 
 impl Devices {
+    /// See [`texture_unit_limit`]
+    pub fn max_texture_units(&self) -> usize {
+        let properties = self.device.physical_device().properties();
+
+        texture_unit_limit(
+            properties.max_per_stage_descriptor_sampled_images,
+            properties.max_descriptor_set_sampled_images,
+        )
+    }
+
+    /// The most precise depth format that can back the main render pass' depth attachment, see
+    /// [`pick_depth_format`]
+    pub fn depth_format(&self) -> Format {
+        let physical = self.device.physical_device();
+
+        pick_depth_format(|format| {
+            let attachable = physical.format_properties(format).is_ok_and(|properties| {
+                properties
+                    .optimal_tiling_features
+                    .intersects(FormatFeatures::DEPTH_STENCIL_ATTACHMENT)
+            });
+
+            attachable
+                && physical
+                    .image_format_properties(ImageFormatInfo {
+                        format,
+                        usage: FrameAttachment::Depth.usage(),
+                        ..Default::default()
+                    })
+                    .is_ok_and(|properties| properties.is_some())
+        })
+    }
+
     pub fn new(window: &Ref<GLFWWindow>) -> Result<Self, VulkanInitError> {
         let library = VulkanLibrary::new().unwrap();
 
@@ -40,20 +223,27 @@ impl Devices {
 
         let mut inst_layers = Vec::new();
 
-        if cfg!(debug_assertions) {
-            let validation = library
-                .layer_properties()
-                .unwrap()
-                .find(|l| l.name() == "VK_LAYER_KHRONOS_validation");
-
-            inst_layers.push(
-                validation
-                    .expect("expected layer VK_LAYER_KHRONOS_validation to be present")
-                    .name()
-                    .to_owned(),
+        let layer_present = library
+            .layer_properties()
+            .unwrap()
+            .any(|l| l.name() == VALIDATION_LAYER);
+
+        let opted_out = validation_opted_out(std::env::var(DISABLE_VALIDATION_VAR).ok().as_deref());
+
+        if cfg!(debug_assertions) && !opted_out && !layer_present {
+            tracing::warn!(
+                what = "the vulkan validation layer isn't installed, so validation is disabled",
+                layer = VALIDATION_LAYER
             );
         }
 
+        let validation = validation_enabled(cfg!(debug_assertions), opted_out, layer_present);
+
+        if validation {
+            inst_layers.push(VALIDATION_LAYER.to_owned());
+            inst_extensions.ext_debug_utils = true;
+        }
+
         info!(
             what = "creating vulkan instance",
             ?inst_extensions,
@@ -70,6 +260,17 @@ impl Devices {
             },
         )?;
 
+        let debug_messenger = match debug_messenger_create_info(validation) {
+            Some(create_info) => match DebugUtilsMessenger::new(instance.clone(), create_info) {
+                Ok(messenger) => Some(messenger),
+                Err(e) => {
+                    tracing::error!(what = "could not create the vulkan debug messenger", error = %e);
+                    None
+                }
+            },
+            None => None,
+        };
+
         let mut device_extensions = DeviceExtensions {
             khr_swapchain: true,
             ..DeviceExtensions::empty()
@@ -104,12 +305,45 @@ impl Devices {
         let supports_excl_fullscreen = pd_ext.ext_full_screen_exclusive;
         device_extensions.ext_full_screen_exclusive = supports_excl_fullscreen;
 
+        let mut device_features = Features::empty();
+
+        // needed for glPolygonMode(GL_LINE) wireframes
+        device_features.fill_mode_non_solid =
+            physical_device.supported_features().fill_mode_non_solid;
+
+        // for the anisotropic filtering video setting
+        device_features.sampler_anisotropy =
+            physical_device.supported_features().sampler_anisotropy;
+
+        // lets glViewportIndexedf and glDepthRangeIndexed address more than the first viewport
+        device_features.multi_viewport = physical_device.supported_features().multi_viewport;
+
+        // lets glCompressedTexImage2D upload DXT textures without decoding them
+        device_features.texture_compression_bc =
+            physical_device.supported_features().texture_compression_bc;
+
+        // writes the glClipPlane distances from the generated vertex shaders
+        device_features.shader_clip_distance =
+            physical_device.supported_features().shader_clip_distance;
+
+        // GL's default convention is last-vertex, which vulkan only has through this extension
+        if pd_ext.ext_provoking_vertex && physical_device.supported_features().provoking_vertex_last
+        {
+            device_extensions.ext_provoking_vertex = true;
+            device_features.provoking_vertex_last = true;
+        }
+
+        let queue_count =
+            physical_device.queue_family_properties()[queue_family_index as usize].queue_count;
+
         let (device, mut queues) = Device::new(
             physical_device,
             DeviceCreateInfo {
                 enabled_extensions: device_extensions,
+                enabled_features: device_features,
                 queue_create_infos: vec![QueueCreateInfo {
                     queue_family_index,
+                    queues: graphics_queue_priorities(queue_count),
                     ..Default::default()
                 }],
                 ..Default::default()
@@ -118,11 +352,14 @@ impl Devices {
         .unwrap();
 
         let queue = queues.next().unwrap();
+        let transfer_queue = queues.next().unwrap_or_else(|| queue.clone());
 
         Ok(Self {
             instance,
             device,
             queue,
+            transfer_queue,
+            debug_messenger,
         })
     }
 }
@@ -0,0 +1,99 @@
+use vulkano::instance::debug::DebugUtilsMessageSeverity;
+use vulkano::instance::debug::DebugUtilsMessageType;
+
+use crate::vulkan::commands::MAX_VIEWPORTS;
+use crate::vulkan::devices::debug_message_level;
+use crate::vulkan::devices::debug_messenger_create_info;
+use crate::vulkan::devices::graphics_queue_priorities;
+use crate::vulkan::devices::texture_unit_limit;
+use crate::vulkan::devices::validation_enabled;
+use crate::vulkan::devices::validation_opted_out;
+use crate::vulkan::devices::viewport_limit;
+use crate::vulkan::dynamic_shader::MAX_SHADER_TEXTURE_UNITS;
+
+#[test]
+fn messenger_follows_validation() {
+    assert!(validation_enabled(true, false, true));
+    // release builds never validate, and a missing layer mustn't stop the instance from starting
+    assert!(!validation_enabled(false, false, true));
+    assert!(!validation_enabled(true, false, false));
+
+    assert!(debug_messenger_create_info(false).is_none());
+
+    let create_info = debug_messenger_create_info(true).unwrap();
+
+    assert!(create_info
+        .message_severity
+        .contains(DebugUtilsMessageSeverity::ERROR | DebugUtilsMessageSeverity::WARNING));
+    assert!(create_info
+        .message_type
+        .contains(DebugUtilsMessageType::VALIDATION));
+}
+
+#[test]
+fn validation_can_be_opted_out_of() {
+    assert!(!validation_opted_out(None));
+    assert!(!validation_opted_out(Some("")));
+    assert!(!validation_opted_out(Some("0")));
+    assert!(validation_opted_out(Some("1")));
+    assert!(validation_opted_out(Some("true")));
+
+    // opting out wins even if the layer is installed
+    assert!(!validation_enabled(true, true, true));
+
+    // a missing layer that validation was opted out of starts up without a messenger
+    let validation = validation_enabled(true, true, false);
+    assert!(!validation);
+    assert!(debug_messenger_create_info(validation).is_none());
+}
+
+#[test]
+fn validation_severity_maps_to_log_level() {
+    assert_eq!(
+        debug_message_level(DebugUtilsMessageSeverity::ERROR),
+        tracing::Level::ERROR
+    );
+    assert_eq!(
+        debug_message_level(DebugUtilsMessageSeverity::WARNING),
+        tracing::Level::WARN
+    );
+    assert_eq!(
+        debug_message_level(DebugUtilsMessageSeverity::INFO),
+        tracing::Level::DEBUG
+    );
+    assert_eq!(
+        debug_message_level(DebugUtilsMessageSeverity::VERBOSE),
+        tracing::Level::TRACE
+    );
+}
+
+#[test]
+fn texture_units_are_clamped_to_device_limits() {
+    // the spec's minimums are still above what the generated shaders sample
+    assert_eq!(texture_unit_limit(16, 96), MAX_SHADER_TEXTURE_UNITS);
+    assert_eq!(
+        texture_unit_limit(1 << 20, 1 << 20),
+        MAX_SHADER_TEXTURE_UNITS
+    );
+
+    // whichever limit is lower wins
+    assert_eq!(texture_unit_limit(2, 96), 2);
+    assert_eq!(texture_unit_limit(16, 3), 3);
+}
+
+#[test]
+fn viewports_need_multi_viewport() {
+    assert_eq!(viewport_limit(false, 16), 1);
+    assert_eq!(viewport_limit(true, 4), 4);
+    assert_eq!(viewport_limit(true, 1024), MAX_VIEWPORTS);
+}
+
+#[test]
+fn texture_reloads_get_their_own_queue_if_there_is_one() {
+    assert_eq!(graphics_queue_priorities(1), vec![1.0]);
+
+    // the frames outrank the reload
+    let priorities = graphics_queue_priorities(16);
+    assert_eq!(priorities.len(), 2);
+    assert!(priorities[0] > priorities[1]);
+}
@@ -0,0 +1,67 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use super::sandbox::RenderInstruction;
+
+thread_local! {
+    /// Display lists belong to the GL context, which is only ever current on one thread
+    pub static DISPLAY_LISTS: RefCell<DisplayListStore> = RefCell::new(DisplayListStore::default());
+}
+
+/// The display lists created with glGenLists. Each list is the instructions which were pushed
+/// between its glNewList and glEndList, which are fed back into the sandbox by glCallList.
+#[derive(Debug, Default)]
+pub struct DisplayListStore {
+    lists: HashMap<u32, Arc<Vec<RenderInstruction>>>,
+    next_id: u32,
+    /// glListBase, added to every list id glCallLists is given
+    list_base: u32,
+}
+
+impl DisplayListStore {
+    /// Reserves `range` consecutive empty lists and returns the first id, or 0 if `range` is 0
+    pub fn gen_lists(&mut self, range: u32) -> u32 {
+        if range == 0 {
+            return 0;
+        }
+
+        // 0 is reserved for 'no list'
+        let first = self.next_id + 1;
+
+        for list in first..first + range {
+            self.lists.insert(list, Arc::new(Vec::new()));
+        }
+
+        self.next_id += range;
+
+        first
+    }
+
+    pub fn delete_lists(&mut self, first: u32, range: u32) {
+        for list in first..first.saturating_add(range) {
+            self.lists.remove(&list);
+        }
+    }
+
+    /// Replaces the contents of `list`. Like GL, compiling into an id which wasn't generated
+    /// creates it.
+    pub fn set_list(&mut self, list: u32, insns: Vec<RenderInstruction>) {
+        self.lists.insert(list, Arc::new(insns));
+    }
+
+    /// Lists called while compiling another list are flattened into it, so unlike GL the base
+    /// takes effect immediately rather than being compiled
+    pub fn set_list_base(&mut self, base: u32) {
+        self.list_base = base;
+    }
+
+    pub fn list_base(&self) -> u32 {
+        self.list_base
+    }
+
+    /// The instructions to replay for `list`, None if it doesn't exist
+    pub fn get_list(&self, list: u32) -> Option<Arc<Vec<RenderInstruction>>> {
+        self.lists.get(&list).cloned()
+    }
+}
@@ -1,18 +1,26 @@
 use std::collections::HashMap;
+use std::collections::HashSet;
 use std::hash::Hash;
 use std::mem::variant_count;
+use std::num::NonZeroUsize;
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::sync::Weak;
+use std::time::Duration;
+use std::time::Instant;
 
 use concat_string::concat_string;
 use derivative::Derivative;
+use glslang::error::GlslangError;
 use glslang::Compiler;
 use glslang::CompilerOptions;
 use glslang::Program;
 use glslang::ShaderInput;
 use glslang::ShaderSource;
 use lru::LruCache;
+use nalgebra_glm::TMat3;
 use nalgebra_glm::TMat4;
+use nalgebra_glm::Vec2;
 use nalgebra_glm::Vec4;
 use num::ToPrimitive;
 use num_derive::FromPrimitive;
@@ -23,14 +31,23 @@ use vulkano::descriptor_set::layout::DescriptorSetLayoutCreateInfo;
 use vulkano::descriptor_set::layout::DescriptorType;
 use vulkano::device::Device;
 use vulkano::format::Format;
+use vulkano::image::SampleCount;
+use vulkano::pipeline::cache::PipelineCache;
+use vulkano::pipeline::cache::PipelineCacheCreateInfo;
 use vulkano::pipeline::graphics::color_blend::AttachmentBlend;
+use vulkano::pipeline::graphics::color_blend::BlendFactor;
 use vulkano::pipeline::graphics::color_blend::ColorBlendAttachmentState;
 use vulkano::pipeline::graphics::color_blend::ColorBlendState;
+use vulkano::pipeline::graphics::color_blend::ColorComponents;
 use vulkano::pipeline::graphics::depth_stencil::CompareOp;
 use vulkano::pipeline::graphics::depth_stencil::DepthState;
 use vulkano::pipeline::graphics::depth_stencil::DepthStencilState;
+use vulkano::pipeline::graphics::input_assembly::InputAssemblyState;
+use vulkano::pipeline::graphics::input_assembly::PrimitiveTopology;
+use vulkano::pipeline::graphics::multisample::MultisampleState;
 use vulkano::pipeline::graphics::rasterization::CullMode;
 use vulkano::pipeline::graphics::rasterization::FrontFace;
+use vulkano::pipeline::graphics::rasterization::PolygonMode as VkPolygonMode;
 use vulkano::pipeline::graphics::rasterization::RasterizationState;
 use vulkano::pipeline::graphics::subpass::PipelineSubpassType;
 use vulkano::pipeline::graphics::vertex_input::VertexInputAttributeDescription;
@@ -41,22 +58,36 @@ use vulkano::pipeline::graphics::viewport::Scissor;
 use vulkano::pipeline::graphics::viewport::Viewport;
 use vulkano::pipeline::graphics::viewport::ViewportState;
 use vulkano::pipeline::graphics::GraphicsPipelineCreateInfo;
+use vulkano::pipeline::layout::PipelineDescriptorSetLayoutCreateInfo;
 use vulkano::pipeline::layout::PipelineLayoutCreateInfo;
 use vulkano::pipeline::layout::PushConstantRange;
 use vulkano::pipeline::DynamicState;
 use vulkano::pipeline::GraphicsPipeline;
 use vulkano::pipeline::PipelineLayout;
 use vulkano::pipeline::PipelineShaderStageCreateInfo;
+use vulkano::render_pass::RenderPass;
 use vulkano::render_pass::Subpass;
 use vulkano::shader::ShaderModule;
 use vulkano::shader::ShaderModuleCreateInfo;
 use vulkano::shader::ShaderStages;
+use vulkano::Validated;
+use vulkano::VulkanError;
 use weak_table::WeakValueHashMap;
 
+use super::commands::UniformSetCache;
+use super::devices::device_viewport_count;
+use super::provoking_vertex::new_graphics_pipeline;
+use super::sandbox::DepthFunc;
 use super::sandbox::DrawMode;
 use super::sandbox::GLDataType;
 use super::sandbox::PointerArrayType;
+use super::sandbox::PolygonMode;
+use super::sandbox::ProvokingVertex;
+use super::sandbox::TextureEnvMode;
+use super::shaders::programs::LinkedProgram;
 use super::swapchain::SwapchainManager;
+use super::swapchain::NORMALS_LOCATION;
+use super::swapchain::OBJECT_ID_LOCATION;
 use super::utils::Ref;
 
 #[repr(u8)]
@@ -67,6 +98,8 @@ pub enum VertexInputType {
     Color = 2,
     TexCoord = 3,
     TexIndex = 4,
+    /// The lightmap unit's texcoord array, selected with glClientActiveTexture
+    LightmapTexCoord = 5,
 }
 
 impl From<PointerArrayType> for VertexInputType {
@@ -93,19 +126,21 @@ pub struct VertexInputSpec {
 
 impl VertexInputSpec {
     pub fn as_vector(&self) -> VectorDataType {
-        if self.num_elements > 4 {
-            panic!();
+        let size = self.num_elements;
+
+        if !(1..=4).contains(&size) {
+            panic!("vertex inputs must have 1 to 4 elements, but this one has {size}");
         }
 
         match self.data_type {
-            GLDataType::U8 => todo!(),
-            GLDataType::I8 => todo!(),
-            GLDataType::U16 => todo!(),
-            GLDataType::I16 => todo!(),
-            GLDataType::U32 => todo!(),
-            GLDataType::I32 => todo!(),
-            GLDataType::F32 => todo!(),
-            GLDataType::F64 => todo!(),
+            GLDataType::U8 => VectorDataType::U8(size),
+            GLDataType::I8 => VectorDataType::I8(size),
+            GLDataType::U16 => VectorDataType::U16(size),
+            GLDataType::I16 => VectorDataType::I16(size),
+            GLDataType::U32 => VectorDataType::U32(size),
+            GLDataType::I32 => VectorDataType::I32(size),
+            GLDataType::F32 => VectorDataType::F32(size),
+            GLDataType::F64 => VectorDataType::F64(size),
         }
     }
 }
@@ -115,6 +150,9 @@ pub type VertexBufferFields = [Option<VertexInputSpec>; variant_count::<VertexIn
 #[derive(Debug, Clone, PartialEq, Hash, Eq)]
 pub struct VertexBufferLayout {
     pub fields: VertexBufferFields,
+    /// glVertexAttribPointer arrays by attribute index, sorted. Only assembled while a program is
+    /// active, since the generated shaders don't read them.
+    pub generic: Vec<(u32, VertexInputSpec)>,
     pub stride: u8,
 }
 
@@ -141,6 +179,62 @@ impl VertexBufferLayout {
     pub fn texcoord(&self) -> Option<&VertexInputSpec> {
         self.fields[VertexInputType::TexCoord.to_usize().unwrap()].as_ref()
     }
+
+    pub fn texindex(&self) -> Option<&VertexInputSpec> {
+        self.fields[VertexInputType::TexIndex.to_usize().unwrap()].as_ref()
+    }
+
+    pub fn lightmap_texcoord(&self) -> Option<&VertexInputSpec> {
+        self.fields[VertexInputType::LightmapTexCoord.to_usize().unwrap()].as_ref()
+    }
+
+    /// The data fed to a program's input at `location`. A generic attribute array wins over the
+    /// fixed-function array whose [`VertexInputType`] has the same number.
+    pub fn program_input(&self, location: u32) -> Option<&VertexInputSpec> {
+        self.generic
+            .iter()
+            .find(|(index, _)| *index == location)
+            .map(|(_, spec)| spec)
+            .or_else(|| self.fields.get(location as usize)?.as_ref())
+    }
+}
+
+/// Binds the vertex buffer to each input location the program's vertex shader reads
+pub fn program_vertex_input(
+    layout: &VertexBufferLayout,
+    program: &LinkedProgram,
+) -> VertexInputState {
+    let mut vertex_input = VertexInputState::new().binding(
+        0,
+        VertexInputBindingDescription {
+            stride: layout.stride as u32,
+            input_rate: VertexInputRate::Vertex,
+        },
+    );
+
+    for attribute in &program.attributes {
+        match layout.program_input(attribute.location) {
+            Some(input) => {
+                vertex_input = vertex_input.attribute(
+                    attribute.location,
+                    VertexInputAttributeDescription {
+                        binding: 0,
+                        format: input.as_vector().as_format(),
+                        offset: input.offset as u32,
+                    },
+                );
+            }
+            None => {
+                tracing::warn!(
+                    what = "a program reads a vertex attribute that isn't enabled",
+                    name = attribute.name,
+                    location = attribute.location,
+                );
+            }
+        }
+    }
+
+    vertex_input
 }
 
 #[derive(Debug, Clone, PartialEq, Hash, Eq)]
@@ -153,6 +247,9 @@ pub enum DataSource {
 pub enum ShaderMatrixMode {
     /// P * V * M in a mat4
     MVP(DataSource),
+    /// P * V * M in a mat4, followed by the modelview's normal matrix in a mat3. Both are push
+    /// constants. Only used for lit draws, since unlit ones don't need their normals transformed.
+    MVPNormal,
     /// P * V, M in two mat4s
     /// Both DataSources should be the same type
     VP_M(DataSource, DataSource),
@@ -161,14 +258,226 @@ pub enum ShaderMatrixMode {
 #[derive(Debug, Clone, PartialEq, Hash, Eq)]
 pub enum ColorMode {
     Flat(DataSource),
-    Texture { set: u8, binding: u8 },
+    /// Each sampled unit is bound to the binding after the previous unit's
+    Texture {
+        set: u8,
+        binding: u8,
+        units: TextureCombine,
+    },
     Array,
 }
 
+/// The most texture units a generated shader samples
+pub const MAX_SHADER_TEXTURE_UNITS: usize = 4;
+
+/// GL_MAX_CLIP_PLANES, the most user clip planes a draw can have enabled at once
+pub const MAX_CLIP_PLANES: usize = 6;
+
+/// Which texture units a textured pipeline samples, and how they're combined. The base unit's
+/// texture is the starting colour, and every later unit is applied on top of it in order with its
+/// glTexEnv mode. The base and lightmap units are always sampled; the units after them are the
+/// enabled ones, each at its own glMultiTexCoord as seen through its texture matrix.
+#[derive(Debug, Clone, Copy, PartialEq, Hash, Eq)]
+pub struct TextureCombine {
+    /// The modes of the units after the base one, starting with the lightmap's
+    modes: [TextureEnvMode; MAX_SHADER_TEXTURE_UNITS - 1],
+    /// Including the base unit
+    unit_count: u8,
+    /// The base unit holds a GL_TEXTURE_RECTANGLE, which is fetched at texel coordinates
+    rectangle: bool,
+}
+
+impl Default for TextureCombine {
+    fn default() -> Self {
+        Self::new(TextureEnvMode::Modulate)
+    }
+}
+
+impl TextureCombine {
+    pub fn new(lightmap: TextureEnvMode) -> Self {
+        let mut modes = [TextureEnvMode::default(); MAX_SHADER_TEXTURE_UNITS - 1];
+        modes[0] = lightmap;
+
+        Self {
+            modes,
+            unit_count: 2,
+            rectangle: false,
+        }
+    }
+
+    pub fn set_rectangle(&mut self, rectangle: bool) {
+        self.rectangle = rectangle;
+    }
+
+    pub fn is_rectangle(&self) -> bool {
+        self.rectangle
+    }
+
+    /// Samples another unit after the others. Returns false if every unit is already taken.
+    pub fn push(&mut self, mode: TextureEnvMode) -> bool {
+        if self.unit_count as usize == MAX_SHADER_TEXTURE_UNITS {
+            return false;
+        }
+
+        self.modes[self.unit_count as usize - 1] = mode;
+        self.unit_count += 1;
+
+        true
+    }
+
+    pub fn unit_count(&self) -> usize {
+        self.unit_count as usize
+    }
+
+    /// The modes of the units after the base one, in the order they're applied
+    pub fn modes(&self) -> &[TextureEnvMode] {
+        &self.modes[..self.unit_count as usize - 1]
+    }
+
+    /// The units after the lightmap, which get their texcoords from
+    /// [`DynamicPipelinePushConstants::unit_texcoords`]
+    pub fn extra_units(&self) -> std::ops::Range<usize> {
+        2..self.unit_count as usize
+    }
+}
+
+/// Where the texcoords of the units after the lightmap are passed to the fragment shader, one
+/// location per unit starting with unit 2's
+const UNIT_TEXCOORD_LOCATION: usize = 5;
+
+/// Applies a unit's texture `tex` on top of the colour `prev`, like the fixed function pipeline's
+/// glTexEnv modes do
+fn combine_texture(mode: TextureEnvMode, prev: &str, tex: &str) -> String {
+    match mode {
+        TextureEnvMode::Modulate => format!("{prev} * {tex}"),
+        TextureEnvMode::Replace => tex.to_owned(),
+        TextureEnvMode::Add => format!("vec4({prev}.rgb + {tex}.rgb, {prev}.a * {tex}.a)"),
+        TextureEnvMode::Decal => format!("vec4(mix({prev}.rgb, {tex}.rgb, {tex}.a), {prev}.a)"),
+    }
+}
+
+/// How normals are brought back to unit length after the modelview matrix scaled them
+#[derive(Debug, Clone, Copy, PartialEq, Hash, Eq, Default)]
+pub enum NormalScaling {
+    #[default]
+    None,
+    /// GL_RESCALE_NORMAL: multiplies by a factor derived from the modelview matrix. This is
+    /// cheaper, but only correct for uniform scales.
+    Rescale,
+    /// GL_NORMALIZE: a full `normalize()`, which also handles non-uniform scales
+    Normalize,
+}
+
+impl NormalScaling {
+    /// GL_NORMALIZE takes precedence since it gives the same result for uniform scales
+    pub fn from_caps(normalize: bool, rescale: bool) -> Self {
+        match (normalize, rescale) {
+            (true, _) => Self::Normalize,
+            (false, true) => Self::Rescale,
+            (false, false) => Self::None,
+        }
+    }
+}
+
+/// The array layer each unit of a textured pipeline samples, pushed by the recorder when it binds
+/// the textures
+pub type TextureLayers = [u32; MAX_SHADER_TEXTURE_UNITS];
+
+/// The size of the matrices at the start of a generated pipeline's push constants
+pub fn matrix_push_constant_size(matrix: &ShaderMatrixMode) -> usize {
+    match matrix {
+        ShaderMatrixMode::MVP(DataSource::PushConstant) => size_of::<TMat4<f32>>(),
+        ShaderMatrixMode::VP_M(DataSource::PushConstant, DataSource::PushConstant) => {
+            size_of::<TMat4<f32>>() * 2
+        }
+        ShaderMatrixMode::MVPNormal => size_of::<TMat4<f32>>() + size_of::<PaddedMat3>(),
+        _ => 0,
+    }
+}
+
+/// The size of the matrices and clip planes, which start a generated pipeline's push constants
+/// unless they're read from a uniform buffer, see [`DynamicPipelineSpec::transforms`]
+pub fn transform_push_constant_size(spec: &DynamicPipelineSpec) -> usize {
+    match &spec.transforms {
+        DataSource::PushConstant => {
+            matrix_push_constant_size(&spec.matrix) + size_of::<Vec4>() * spec.clip_planes as usize
+        }
+        DataSource::Uniform { .. } => 0,
+    }
+}
+
+/// Where a textured pipeline's [`TextureLayers`] start in its push constants, which is right
+/// after the clip planes
+pub fn texture_layers_offset(spec: &DynamicPipelineSpec) -> u32 {
+    transform_push_constant_size(spec) as u32
+}
+
+/// The most push constant bytes every device supports
+pub const MIN_MAX_PUSH_CONSTANTS_SIZE: u32 = 128;
+
+/// Moves a generated pipeline's matrices and clip planes into a uniform buffer if its push
+/// constants wouldn't fit in the device's `max_push_constants_size` otherwise. Lit, clipped and
+/// textured draws can need more than the 128 bytes every device has.
+pub fn fit_push_constants(spec: &mut DynamicPipelineSpec, max_push_constants_size: u32) {
+    if spec.program.is_none()
+        && generated_push_constant_size(spec) > max_push_constants_size as usize
+    {
+        // textures are bound to set 1
+        spec.transforms = DataSource::Uniform { set: 0, binding: 0 };
+    }
+}
+
+/// A mat3 as it's laid out in a push constant block, where every column is padded to a vec4
+pub type PaddedMat3 = [[f32; 4]; 3];
+
+pub fn pad_mat3(matrix: &TMat3<f32>) -> PaddedMat3 {
+    std::array::from_fn(|col| {
+        let column = matrix.column(col);
+        [column[0], column[1], column[2], 0.0]
+    })
+}
+
 #[derive(Debug, Clone, PartialEq, Default)]
 pub struct DynamicPipelinePushConstants {
     pub mvp: Option<TMat4<f32>>,
+    /// Only used with [`ShaderMatrixMode::MVPNormal`]
+    pub normal_matrix: Option<TMat3<f32>>,
+    /// The enabled glClipPlane equations, already in clip space
+    pub clip_planes: Vec<Vec4>,
     pub color: Option<Vec4>,
+    /// Only used with [`ColorMode::Texture`]
+    pub lightmap_texcoord: Option<Vec2>,
+    /// The texcoords of the units sampled after the lightmap, in the order they're combined, see
+    /// [`TextureCombine::extra_units`]
+    pub unit_texcoords: Vec<Vec2>,
+    /// Only used with [`NormalScaling::Rescale`]
+    pub normal_scale: Option<f32>,
+    /// The glLoadName written into the object id attachment, see
+    /// [`FrameAttachment::ObjectIds`](super::swapchain::FrameAttachment::ObjectIds). Always last,
+    /// and only used by the generated shaders.
+    pub object_id: Option<u32>,
+}
+
+impl DynamicPipelinePushConstants {
+    /// Takes the matrices and clip planes out of the push constants, laid out the way a pipeline
+    /// with [`DataSource::Uniform`] transforms reads them from its uniform buffer
+    pub fn take_transforms(&mut self) -> Vec<u8> {
+        let mut data = Vec::new();
+
+        if let Some(mvp) = self.mvp.take() {
+            data.extend_from_slice(bytemuck::cast_slice(mvp.as_slice()));
+        }
+
+        if let Some(normal_matrix) = self.normal_matrix.take() {
+            data.extend_from_slice(bytemuck::cast_slice(&pad_mat3(&normal_matrix)));
+        }
+
+        for plane in self.clip_planes.drain(..) {
+            data.extend_from_slice(bytemuck::cast_slice(plane.as_slice()));
+        }
+
+        data
+    }
 }
 
 #[derive(Debug, Clone, Hash, PartialEq, Eq)]
@@ -179,8 +488,20 @@ pub struct DynamicPipelineSpec {
     pub color: ColorMode,
 
     pub matrix: ShaderMatrixMode,
+    pub normal_scaling: NormalScaling,
 
     pub rasterization: DynamicPipelineRasterization,
+    pub depth: DynamicPipelineDepth,
+    pub alpha_test: Option<AlphaTest>,
+    /// How many GL_CLIP_PLANEi are enabled, see [`ShaderSpec::clip_planes`]
+    pub clip_planes: u8,
+    /// Where the matrices and clip planes are read from, see [`fit_push_constants`]
+    pub transforms: DataSource,
+    /// Whether the render pass has an object id attachment, see [`ShaderSpec::object_ids`]
+    pub object_ids: bool,
+
+    /// A linked user program whose shaders replace the generated ones
+    pub program: Option<Arc<LinkedProgram>>,
 }
 
 impl DynamicPipelineSpec {
@@ -208,6 +529,19 @@ pub struct ShaderSpec {
     pub color: ColorMode,
 
     pub matrix: ShaderMatrixMode,
+    pub normal_scaling: NormalScaling,
+    pub alpha_test: Option<AlphaTest>,
+    /// How many user clip planes are enabled. Each one gets its own `gl_ClipDistance`, so the
+    /// count changes the shader while the plane equations themselves are push constants. 0 leaves
+    /// `gl_ClipDistance` out entirely.
+    pub clip_planes: u8,
+    /// The matrices and clip planes are push constants, unless they don't fit in the device's
+    /// push constant block alongside everything else. They're then read from a uniform block
+    /// with the same layout instead.
+    pub transforms: DataSource,
+    /// Writes the object_id push constant into [`OBJECT_ID_LOCATION`]. Only while object picking
+    /// is enabled, see [`WindowSettings::writes_object_ids`](super::swapchain::WindowSettings::writes_object_ids).
+    pub object_ids: bool,
 }
 
 impl From<&DynamicPipelineSpec> for ShaderSpec {
@@ -216,24 +550,46 @@ impl From<&DynamicPipelineSpec> for ShaderSpec {
             vertex_buffer: value.vertex_buffer.clone(),
             color: value.color.clone(),
             matrix: value.matrix.clone(),
+            normal_scaling: value.normal_scaling,
+            alpha_test: value.alpha_test,
+            clip_planes: value.clip_planes,
+            transforms: value.transforms.clone(),
+            object_ids: value.object_ids,
         }
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub struct DynamicPipelineRasterization {
+/// The rasterization state which every pipeline leaves dynamic. It isn't part of the spec, so
+/// that e.g. every line width a mod draws with doesn't compile another pipeline; it's sent along
+/// with each pipeline bind and the recorder sets it whenever it changes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DynamicRasterState {
     pub cull_mode: CullMode,
     pub front_face: FrontFace,
-    /// pre-multiplied by 10
-    pub line_width: u32,
+    pub line_width: f32,
+}
+
+impl Default for DynamicRasterState {
+    fn default() -> Self {
+        Self {
+            cull_mode: CullMode::None,
+            front_face: FrontFace::CounterClockwise,
+            line_width: 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DynamicPipelineRasterization {
     pub color_blending: Option<AttachmentBlend>,
+    pub provoking_vertex: ProvokingVertex,
+    /// Colours aren't part of the spec, so every wireframe shares one pipeline regardless of
+    /// which flat colour it's drawn with
+    pub polygon_mode: PolygonMode,
 }
 
 impl Hash for DynamicPipelineRasterization {
     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
-        self.cull_mode.hash(state);
-        self.front_face.hash(state);
-        (self.line_width as i32).hash(state);
         self.color_blending.is_some().hash(state);
         if let Some(blending) = self.color_blending.as_ref() {
             blending.src_color_blend_factor.hash(state);
@@ -243,20 +599,149 @@ impl Hash for DynamicPipelineRasterization {
             blending.dst_alpha_blend_factor.hash(state);
             blending.alpha_blend_op.hash(state);
         }
+        self.provoking_vertex.hash(state);
+        self.polygon_mode.hash(state);
     }
 }
 
+impl DynamicPipelineRasterization {
+    /// Whether the pipeline reads its blend constants, which are set dynamically from glBlendColor
+    pub fn uses_blend_constants(&self) -> bool {
+        self.color_blending.as_ref().is_some_and(|blend| {
+            [
+                blend.src_color_blend_factor,
+                blend.dst_color_blend_factor,
+                blend.src_alpha_blend_factor,
+                blend.dst_alpha_blend_factor,
+            ]
+            .iter()
+            .any(|factor| {
+                matches!(
+                    factor,
+                    BlendFactor::ConstantColor
+                        | BlendFactor::OneMinusConstantColor
+                        | BlendFactor::ConstantAlpha
+                        | BlendFactor::OneMinusConstantAlpha
+                )
+            })
+        })
+    }
+}
+
+/// Maps a glBlendFunc factor to its vulkan equivalent
+pub fn gl_blend_factor(factor: u32) -> Option<BlendFactor> {
+    Some(match factor {
+        gl_constants::GL_ZERO => BlendFactor::Zero,
+        gl_constants::GL_ONE => BlendFactor::One,
+        gl_constants::GL_SRC_COLOR => BlendFactor::SrcColor,
+        gl_constants::GL_ONE_MINUS_SRC_COLOR => BlendFactor::OneMinusSrcColor,
+        gl_constants::GL_DST_COLOR => BlendFactor::DstColor,
+        gl_constants::GL_ONE_MINUS_DST_COLOR => BlendFactor::OneMinusDstColor,
+        gl_constants::GL_SRC_ALPHA => BlendFactor::SrcAlpha,
+        gl_constants::GL_ONE_MINUS_SRC_ALPHA => BlendFactor::OneMinusSrcAlpha,
+        gl_constants::GL_DST_ALPHA => BlendFactor::DstAlpha,
+        gl_constants::GL_ONE_MINUS_DST_ALPHA => BlendFactor::OneMinusDstAlpha,
+        gl_constants::GL_CONSTANT_COLOR => BlendFactor::ConstantColor,
+        gl_constants::GL_ONE_MINUS_CONSTANT_COLOR => BlendFactor::OneMinusConstantColor,
+        gl_constants::GL_CONSTANT_ALPHA => BlendFactor::ConstantAlpha,
+        gl_constants::GL_ONE_MINUS_CONSTANT_ALPHA => BlendFactor::OneMinusConstantAlpha,
+        gl_constants::GL_SRC_ALPHA_SATURATE => BlendFactor::SrcAlphaSaturate,
+        _ => return None,
+    })
+}
+
 impl Default for DynamicPipelineRasterization {
     fn default() -> Self {
         Self {
-            cull_mode: CullMode::Back,
-            front_face: FrontFace::CounterClockwise,
-            line_width: 10,
             color_blending: Some(AttachmentBlend::ignore_source()),
+            provoking_vertex: ProvokingVertex::Last,
+            polygon_mode: PolygonMode::Fill,
+        }
+    }
+}
+
+/// glDepthFunc and glDepthMask. GL_DEPTH_TEST being disabled is the same as always passing
+/// without writing anything.
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub struct DynamicPipelineDepth {
+    pub compare_op: CompareOp,
+    pub write_enable: bool,
+}
+
+impl DynamicPipelineDepth {
+    pub fn disabled() -> Self {
+        Self {
+            compare_op: CompareOp::Always,
+            write_enable: false,
+        }
+    }
+}
+
+impl Default for DynamicPipelineDepth {
+    fn default() -> Self {
+        Self {
+            compare_op: CompareOp::Less,
+            write_enable: true,
         }
     }
 }
 
+/// glAlphaFunc, which the generated fragment shader applies by discarding the fragments that
+/// fail it. None in a spec when GL_ALPHA_TEST is disabled or the test always passes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AlphaTest {
+    pub func: CompareOp,
+    /// Already clamped to [0, 1]
+    pub ref_value: f32,
+}
+
+impl Eq for AlphaTest {}
+
+impl Hash for AlphaTest {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.func.hash(state);
+        self.ref_value.to_bits().hash(state);
+    }
+}
+
+impl AlphaTest {
+    /// The fragment shader line which discards the fragments that fail the test, or None if
+    /// every fragment passes
+    fn discard_line(&self) -> Option<String> {
+        // the opposite of the test's comparison
+        let fails = match self.func {
+            CompareOp::Never => return Some("  discard;\n".to_owned()),
+            CompareOp::Less => ">=",
+            CompareOp::Equal => "!=",
+            CompareOp::LessOrEqual => ">",
+            CompareOp::Greater => "<=",
+            CompareOp::NotEqual => "==",
+            CompareOp::GreaterOrEqual => "<",
+            // CompareOp::Always
+            _ => return None,
+        };
+
+        Some(format!(
+            "  if (frag_color_out.a {fails} {:?}) discard;\n",
+            self.ref_value
+        ))
+    }
+}
+
+/// Maps a glDepthFunc function to its vulkan equivalent
+pub fn gl_compare_op(func: DepthFunc) -> CompareOp {
+    match func {
+        DepthFunc::Never => CompareOp::Never,
+        DepthFunc::Less => CompareOp::Less,
+        DepthFunc::Equal => CompareOp::Equal,
+        DepthFunc::LessOrEqual => CompareOp::LessOrEqual,
+        DepthFunc::Greater => CompareOp::Greater,
+        DepthFunc::NotEqual => CompareOp::NotEqual,
+        DepthFunc::GreaterOrEqual => CompareOp::GreaterOrEqual,
+        DepthFunc::Always => CompareOp::Always,
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum VectorDataType {
     U8(u8),
@@ -296,11 +781,12 @@ impl VectorDataType {
         }
     }
 
+    /// The components that widen this vector to a vec4, using GL's defaults of (0, 0, 0, 1)
     pub fn get_widening_zeroes(&self) -> &'static str {
         match self.size() {
-            1 => ", 0.0, 0.0, 0.0",
-            2 => ", 0.0, 0.0",
-            3 => ", 0.0",
+            1 => ", 0.0, 0.0, 1.0",
+            2 => ", 0.0, 1.0",
+            3 => ", 1.0",
             _ => "",
         }
     }
@@ -392,66 +878,63 @@ impl ShaderSpec {
         self.vertex_buffer.color()
     }
 
-    pub fn get_vertex_shader_code(&self) -> String {
-        let mut code = String::with_capacity(1024);
+    fn lightmap_texcoord(&self) -> Option<&VertexInputSpec> {
+        self.vertex_buffer.lightmap_texcoord()
+    }
 
-        code += "#version 450\n";
+    /// Whether the rescale factor is passed in as a push constant
+    pub fn rescales_normals(&self) -> bool {
+        self.normal_scaling == NormalScaling::Rescale && self.normal().is_some()
+    }
 
-        // VERTEX INPUTS
+    /// The block the matrices and clip planes are read from
+    fn transforms_block(&self) -> &'static str {
+        match &self.transforms {
+            DataSource::PushConstant => "PushConstants",
+            DataSource::Uniform { .. } => "Transforms",
+        }
+    }
 
-        Self::append_input(&mut code, 0, &self.position().as_vector(), "position_in");
+    /// The members holding the matrices and clip planes, which always come first in their block
+    fn transform_members(&self) -> String {
+        let mut members = self.matrix_push_constants().to_owned();
 
-        if let Some(normal) = self.normal() {
-            Self::append_input(&mut code, 1, &normal.as_vector(), "normal_in");
+        // the matrices are a multiple of 16 bytes, so the planes stay aligned
+        if self.clip_planes > 0 {
+            members += &format!("  vec4 clip_planes[{}];\n", self.clip_planes);
         }
 
-        match &self.color {
-            ColorMode::Flat(DataSource::PushConstant) => {}
-            ColorMode::Flat(DataSource::Uniform { .. }) => {}
-            ColorMode::Texture { .. } => {
-                Self::append_input(
-                    &mut code,
-                    2,
-                    &self.texcoord().unwrap().as_vector(),
-                    "texcoord_in",
-                );
-            }
-            ColorMode::Array => {
-                Self::append_input(
-                    &mut code,
-                    2,
-                    &self.color().unwrap().as_vector(),
-                    "colors_in",
-                );
-            }
-        }
+        members
+    }
 
-        // PUSH CONSTANTS
+    /// Declares the transforms block when it isn't part of the push constants
+    fn append_transform_uniforms(&self, code: &mut String) {
+        let DataSource::Uniform { set, binding } = &self.transforms else {
+            return;
+        };
 
-        code += "layout(push_constant) uniform constants {\n";
+        *code += &format!(
+            "layout (set = {set}, binding = {binding}) uniform TransformData {{\n{}}} Transforms;\n",
+            self.transform_members()
+        );
+    }
 
+    /// The members holding the matrices
+    fn matrix_push_constants(&self) -> &'static str {
         match &self.matrix {
-            ShaderMatrixMode::MVP(DataSource::PushConstant) => {
-                code += "  mat4 mvp\n";
-            }
+            ShaderMatrixMode::MVP(DataSource::PushConstant) => "  mat4 mvp;\n",
+            ShaderMatrixMode::MVPNormal => "  mat4 mvp;\n  mat3 normal_matrix;\n",
             ShaderMatrixMode::VP_M(DataSource::PushConstant, DataSource::PushConstant) => {
-                code += "  mat4 model;\n";
-                code += "  mat4 vp;\n";
+                "  mat4 model;\n  mat4 vp;\n"
             }
-            _ => {}
-        }
-
-        if let ColorMode::Flat(DataSource::PushConstant) = &self.color {
-            code += "  vec4 color;\n";
+            _ => "",
         }
+    }
 
-        code += "} PushConstants;\n";
-
-        // UNIFORMS
-
+    fn append_matrix_uniforms(&self, code: &mut String) {
         match &self.matrix {
             ShaderMatrixMode::MVP(DataSource::Uniform { set, binding }) => {
-                code += &format!("layout (set = {set}, binding = {binding}) uniform MVPUniformData {{ mat4 matrix; }} MVPUniform;\n");
+                *code += &format!("layout (set = {set}, binding = {binding}) uniform MVPUniformData {{ mat4 matrix; }} MVPUniform;\n");
             }
             ShaderMatrixMode::VP_M(
                 DataSource::Uniform {
@@ -463,109 +946,292 @@ impl ShaderSpec {
                     binding: model_binding,
                 },
             ) => {
-                code += &format!("layout (set = {vp_set}, binding = {vp_binding}) uniform VPUniformData {{ mat4 matrix; }} VPUniform;\n");
-                code += &format!("layout (set = {model_set}, binding = {model_binding}) uniform MUniformData {{ mat4 matrix; }} MUniform;\n");
-            }
-            _ => {}
-        }
-
-        match &self.color {
-            ColorMode::Flat(DataSource::Uniform { set, binding }) => {
-                code += &format!("layout (set = {set}, binding = {binding}) uniform ColorUniformData {{ vec4 color; }} ColorUniform;");
-            }
-            _ => {}
-        }
-
-        // OUTPUTS TO FRAG SHADER
-
-        match &self.color {
-            ColorMode::Array => {
-                Self::append_output(&mut code, 0, &VectorDataType::F32(4), "frag_color_out");
-            }
-            ColorMode::Texture { .. } => {
-                Self::append_output(&mut code, 0, &VectorDataType::F32(2), "tex_coord_out");
+                *code += &format!("layout (set = {vp_set}, binding = {vp_binding}) uniform VPUniformData {{ mat4 matrix; }} VPUniform;\n");
+                *code += &format!("layout (set = {model_set}, binding = {model_binding}) uniform MUniformData {{ mat4 matrix; }} MUniform;\n");
             }
             _ => {}
         }
+    }
 
-        if self.normal().is_some() {
-            Self::append_output(&mut code, 1, &VectorDataType::F32(3), "normal_out");
-        }
-
-        // CODE
-
-        code += "void main() {\n";
-
+    fn append_position(&self, code: &mut String) {
         match &self.matrix {
-            ShaderMatrixMode::MVP(DataSource::PushConstant) => {
-                code += &concat_string!(
-                    "  gl_Position = vec4(PushConstants.mvp * position_in",
+            ShaderMatrixMode::MVP(DataSource::PushConstant) | ShaderMatrixMode::MVPNormal => {
+                *code += &concat_string!(
+                    "  gl_Position = ",
+                    self.transforms_block(),
+                    ".mvp * vec4(position_in",
                     self.position().as_vector().get_widening_zeroes(),
                     ");\n"
                 );
             }
             ShaderMatrixMode::MVP(DataSource::Uniform { .. }) => {
-                code += &concat_string!(
-                    "  gl_Position = vec4(MVPUniform.mvp * position_in",
+                *code += &concat_string!(
+                    "  gl_Position = MVPUniform.matrix * vec4(position_in",
                     self.position().as_vector().get_widening_zeroes(),
                     ");\n"
                 );
             }
             ShaderMatrixMode::VP_M(DataSource::PushConstant, DataSource::PushConstant) => {
-                code += &concat_string!(
-                    "  gl_Position = vec4(PushConstants.model * PushConstants.vp * position_in",
+                let block = self.transforms_block();
+
+                *code += &concat_string!(
+                    "  gl_Position = ",
+                    block,
+                    ".vp * ",
+                    block,
+                    ".model * vec4(position_in",
                     self.position().as_vector().get_widening_zeroes(),
                     ");\n"
                 );
             }
             ShaderMatrixMode::VP_M(DataSource::Uniform { .. }, DataSource::Uniform { .. }) => {
-                code += &concat_string!(
-                    "  gl_Position = vec4(MUniform.matrix * VPUniform.matrix * position_in",
+                *code += &concat_string!(
+                    "  gl_Position = VPUniform.matrix * MUniform.matrix * vec4(position_in",
                     self.position().as_vector().get_widening_zeroes(),
                     ");\n"
                 );
             }
             _ => {}
         }
+    }
+
+    pub fn get_vertex_shader_code(&self) -> String {
+        let mut code = String::with_capacity(1024);
+
+        code += "#version 450\n";
+
+        // VERTEX INPUTS
+
+        Self::append_input(&mut code, 0, &self.position().as_vector(), "position_in");
+
+        if let Some(normal) = self.normal() {
+            Self::append_input(&mut code, 1, &normal.as_vector(), "normal_in");
+        }
 
         match &self.color {
-            ColorMode::Flat(DataSource::PushConstant) => {
-                code += "  frag_color_out = PushConstants.color;\n";
-            }
-            ColorMode::Flat(DataSource::Uniform { .. }) => {
-                code += "  frag_color_out = ColorUniform.color;\n";
-            }
+            ColorMode::Flat(DataSource::PushConstant) => {}
+            ColorMode::Flat(DataSource::Uniform { .. }) => {}
             ColorMode::Texture { .. } => {
-                code += &format!("  texcoord_out = texcoord_in;\n");
+                Self::append_input(
+                    &mut code,
+                    2,
+                    &self.texcoord().unwrap().as_vector(),
+                    "texcoord_in",
+                );
+
+                if let Some(lightmap_texcoord) = self.lightmap_texcoord() {
+                    Self::append_input(
+                        &mut code,
+                        3,
+                        &lightmap_texcoord.as_vector(),
+                        "lightmap_texcoord_in",
+                    );
+                }
             }
             ColorMode::Array => {
-                code += &format!("  frag_color_out = color_in;\n");
+                Self::append_input(&mut code, 2, &self.color().unwrap().as_vector(), "color_in");
             }
         }
 
-        if self.normal().is_some() {
-            code += &format!("  normal_out = normal_in;\n");
+        // PUSH CONSTANTS
+
+        let mut push_constants = match &self.transforms {
+            DataSource::PushConstant => self.transform_members(),
+            DataSource::Uniform { .. } => String::new(),
+        };
+
+        if let ColorMode::Flat(DataSource::PushConstant) = &self.color {
+            push_constants += "  vec4 color;\n";
         }
 
-        code += "}\n";
+        // color and texture pipelines are exclusive, so the layers always start right after the
+        // clip planes, see texture_layers_offset
+        if let ColorMode::Texture { units, .. } = &self.color {
+            push_constants += "  uvec4 texture_layers;\n";
+            push_constants += "  vec2 lightmap_texcoord;\n";
 
-        code.shrink_to_fit();
+            for unit in units.extra_units() {
+                push_constants += &format!("  vec2 unit{unit}_texcoord;\n");
+            }
+        }
 
-        code
-    }
+        if self.rescales_normals() {
+            push_constants += "  float normal_scale;\n";
+        }
 
-    pub fn get_fragment_shader_code(&self) -> String {
-        let mut code = String::with_capacity(1024);
+        if self.object_ids {
+            push_constants += "  uint object_id;\n";
+        }
 
-        code += "#version 450\n";
+        code += "layout(push_constant) uniform constants {\n";
+        code += &push_constants;
+        code += "} PushConstants;\n";
 
         // UNIFORMS
 
+        self.append_transform_uniforms(&mut code);
+        self.append_matrix_uniforms(&mut code);
+
         match &self.color {
-            ColorMode::Texture { set, binding, .. } => {
+            ColorMode::Flat(DataSource::Uniform { set, binding }) => {
+                code += &format!("layout (set = {set}, binding = {binding}) uniform ColorUniformData {{ vec4 color; }} ColorUniform;\n");
+            }
+            _ => {}
+        }
+
+        // OUTPUTS TO FRAG SHADER
+
+        match &self.color {
+            ColorMode::Flat(_) | ColorMode::Array => {
+                Self::append_output(&mut code, 0, &VectorDataType::F32(4), "frag_color_out");
+            }
+            ColorMode::Texture { units, .. } => {
+                Self::append_output(&mut code, 0, &VectorDataType::F32(2), "texcoord_out");
+                Self::append_output(
+                    &mut code,
+                    2,
+                    &VectorDataType::F32(2),
+                    "lightmap_texcoord_out",
+                );
+
+                for unit in units.extra_units() {
+                    Self::append_output(
+                        &mut code,
+                        (UNIT_TEXCOORD_LOCATION + unit - 2) as u32,
+                        &VectorDataType::F32(2),
+                        &format!("unit{unit}_texcoord_out"),
+                    );
+                }
+            }
+        }
+
+        if self.normal().is_some() {
+            Self::append_output(&mut code, 1, &VectorDataType::F32(3), "normal_out");
+        }
+
+        // integers can't be interpolated
+        if self.object_ids {
+            code += "layout(location = 3) flat out uint object_id_out;\n";
+        }
+
+        if let ColorMode::Texture { .. } = &self.color {
+            code += "layout(location = 4) flat out uvec4 texture_layers_out;\n";
+        }
+
+        if self.clip_planes > 0 {
+            code += &format!(
+                "out gl_PerVertex {{\n  vec4 gl_Position;\n  float gl_ClipDistance[{}];\n}};\n",
+                self.clip_planes
+            );
+        }
+
+        // CODE
+
+        code += "void main() {\n";
+
+        self.append_position(&mut code);
+
+        for plane in 0..self.clip_planes {
+            code += &format!(
+                "  gl_ClipDistance[{plane}] = dot({}.clip_planes[{plane}], gl_Position);\n",
+                self.transforms_block()
+            );
+        }
+
+        if self.object_ids {
+            code += "  object_id_out = PushConstants.object_id;\n";
+        }
+
+        match &self.color {
+            ColorMode::Flat(DataSource::PushConstant) => {
+                code += "  frag_color_out = PushConstants.color;\n";
+            }
+            ColorMode::Flat(DataSource::Uniform { .. }) => {
+                code += "  frag_color_out = ColorUniform.color;\n";
+            }
+            ColorMode::Texture { units, .. } => {
+                code += "  texcoord_out = vec2(texcoord_in);\n";
+
+                // the push constant is the current glMultiTexCoord, for draws without an array
+                code += match self.lightmap_texcoord() {
+                    Some(_) => "  lightmap_texcoord_out = vec2(lightmap_texcoord_in);\n",
+                    None => "  lightmap_texcoord_out = PushConstants.lightmap_texcoord;\n",
+                };
+
+                for unit in units.extra_units() {
+                    code += &format!(
+                        "  unit{unit}_texcoord_out = PushConstants.unit{unit}_texcoord;\n"
+                    );
+                }
+
+                code += "  texture_layers_out = PushConstants.texture_layers;\n";
+            }
+            ColorMode::Array => {
+                code += &concat_string!(
+                    "  frag_color_out = vec4(color_in",
+                    self.color().unwrap().as_vector().get_widening_zeroes(),
+                    ");\n"
+                );
+            }
+        }
+
+        if self.normal().is_some() {
+            let normal = match &self.matrix {
+                ShaderMatrixMode::MVPNormal => {
+                    concat_string!(self.transforms_block(), ".normal_matrix * normal_in")
+                }
+                _ => "normal_in".to_owned(),
+            };
+
+            match self.normal_scaling {
+                NormalScaling::None => {
+                    code += &format!("  normal_out = {normal};\n");
+                }
+                NormalScaling::Rescale => {
+                    code += &format!("  normal_out = {normal} * PushConstants.normal_scale;\n");
+                }
+                NormalScaling::Normalize => {
+                    code += &format!("  normal_out = normalize({normal});\n");
+                }
+            }
+        }
+
+        code += "}\n";
+
+        code.shrink_to_fit();
+
+        code
+    }
+
+    pub fn get_fragment_shader_code(&self) -> String {
+        let mut code = String::with_capacity(1024);
+
+        code += "#version 450\n";
+
+        // UNIFORMS
+
+        match &self.color {
+            ColorMode::Texture {
+                set,
+                binding,
+                units,
+            } => {
+                // each unit is bound to its texture's whole array, and samples its layer
+                code += &format!(
+                    "layout (set = {set}, binding = {binding}) uniform sampler2DArray tex_sampler;\n"
+                );
+                // unused units sample a white dummy texture, so the lightmap is always bound
                 code += &format!(
-                    "layout (set = {set}, binding = {binding}) uniform sampler2D sampler;\n"
+                    "layout (set = {set}, binding = {}) uniform sampler2DArray lightmap_sampler;\n",
+                    binding + 1
                 );
+
+                for unit in 2..units.unit_count() {
+                    code += &format!(
+                        "layout (set = {set}, binding = {}) uniform sampler2DArray unit{unit}_sampler;\n",
+                        *binding as usize + unit
+                    );
+                }
             }
             _ => {}
         }
@@ -576,8 +1242,23 @@ impl ShaderSpec {
             ColorMode::Flat(_) | ColorMode::Array => {
                 Self::append_input(&mut code, 0, &VectorDataType::F32(4), "frag_color_in");
             }
-            ColorMode::Texture { .. } => {
-                Self::append_input(&mut code, 0, &VectorDataType::F32(2), "tex_coord_in");
+            ColorMode::Texture { units, .. } => {
+                Self::append_input(&mut code, 0, &VectorDataType::F32(2), "texcoord_in");
+                Self::append_input(
+                    &mut code,
+                    2,
+                    &VectorDataType::F32(2),
+                    "lightmap_texcoord_in",
+                );
+
+                for unit in units.extra_units() {
+                    Self::append_input(
+                        &mut code,
+                        (UNIT_TEXCOORD_LOCATION + unit - 2) as u32,
+                        &VectorDataType::F32(2),
+                        &format!("unit{unit}_texcoord_in"),
+                    );
+                }
             }
         }
 
@@ -585,12 +1266,34 @@ impl ShaderSpec {
             Self::append_input(&mut code, 1, &VectorDataType::F32(3), "normal_in");
         }
 
+        if self.object_ids {
+            code += "layout(location = 3) flat in uint object_id_in;\n";
+        }
+
+        if let ColorMode::Texture { .. } = &self.color {
+            code += "layout(location = 4) flat in uvec4 texture_layers_in;\n";
+        }
+
         // OUTPUTS TO FRAME BUFFERS
 
         Self::append_output(&mut code, 0, &VectorDataType::F32(4), "frag_color_out");
 
         if self.normal().is_some() {
-            Self::append_input(&mut code, 1, &VectorDataType::F32(3), "normal_out");
+            Self::append_output(
+                &mut code,
+                NORMALS_LOCATION as u32,
+                &VectorDataType::F32(3),
+                "normal_out",
+            );
+        }
+
+        if self.object_ids {
+            Self::append_output(
+                &mut code,
+                OBJECT_ID_LOCATION as u32,
+                &VectorDataType::U32(1),
+                "object_id_out",
+            );
         }
 
         // CODE
@@ -601,21 +1304,100 @@ impl ShaderSpec {
             ColorMode::Flat(_) | ColorMode::Array => {
                 code += "  frag_color_out = frag_color_in;\n";
             }
-            ColorMode::Texture { .. } => {
-                code += "  frag_color_out = texture(sampler, tex_coord_in);\n";
+            ColorMode::Texture { units, .. } => {
+                // rectangle textures have no mips, and their texcoords are already in texels
+                if units.is_rectangle() {
+                    code += "  vec4 color = texelFetch(tex_sampler, ivec3(texcoord_in, texture_layers_in[0]), 0);\n";
+                } else {
+                    code += "  vec4 color = texture(tex_sampler, vec3(texcoord_in, texture_layers_in[0]));\n";
+                }
+
+                for (i, mode) in units.modes().iter().enumerate() {
+                    let unit = i + 1;
+
+                    let tex = match unit {
+                        1 => "texture(lightmap_sampler, vec3(lightmap_texcoord_in, texture_layers_in[1]))".to_owned(),
+                        _ => format!("texture(unit{unit}_sampler, vec3(unit{unit}_texcoord_in, texture_layers_in[{unit}]))"),
+                    };
+
+                    code += &format!("  color = {};\n", combine_texture(*mode, "color", &tex));
+                }
+
+                code += "  frag_color_out = color;\n";
             }
         }
 
+        if let Some(discard) = self.alpha_test.and_then(|test| test.discard_line()) {
+            code += &discard;
+        }
+
         if self.normal().is_some() {
             code += &format!("  normal_out = normal_in;\n");
         }
 
+        if self.object_ids {
+            code += "  object_id_out = object_id_in;\n";
+        }
+
         code += "}\n";
 
         code.shrink_to_fit();
 
         code
     }
+
+    /// A position-only vertex shader for when this spec's shaders fail to compile. It still reads
+    /// the spec's matrices so that the broken geometry shows up where it should be.
+    pub fn get_fallback_vertex_shader_code(&self) -> String {
+        let mut code = String::with_capacity(512);
+
+        code += "#version 450\n";
+
+        Self::append_input(&mut code, 0, &self.position().as_vector(), "position_in");
+
+        let push_constants = self.matrix_push_constants();
+
+        if self.transforms == DataSource::PushConstant && !push_constants.is_empty() {
+            code += "layout(push_constant) uniform constants {\n";
+            code += push_constants;
+            code += "} PushConstants;\n";
+        }
+
+        self.append_transform_uniforms(&mut code);
+        self.append_matrix_uniforms(&mut code);
+
+        code += "void main() {\n";
+
+        self.append_position(&mut code);
+
+        code += "}\n";
+
+        code
+    }
+
+    /// Draws everything in flat magenta, without an object id so that broken draws can't be
+    /// picked
+    pub fn get_fallback_fragment_shader_code(&self) -> String {
+        let mut code = String::with_capacity(256);
+
+        code += "#version 450\n";
+        code += "layout(location = 0) out vec4 frag_color_out;\n";
+
+        if self.object_ids {
+            code += &format!("layout(location = {OBJECT_ID_LOCATION}) out uint object_id_out;\n");
+        }
+
+        code += "void main() {\n";
+        code += "  frag_color_out = vec4(1.0, 0.0, 1.0, 1.0);\n";
+
+        if self.object_ids {
+            code += "  object_id_out = 0;\n";
+        }
+
+        code += "}\n";
+
+        code
+    }
 }
 
 #[derive(Derivative)]
@@ -623,194 +1405,563 @@ impl ShaderSpec {
 pub struct DynamicPipeline {
     pub spec: DynamicPipelineSpec,
 
+    /// The provoking vertex convention the pipeline actually uses, which may differ from the
+    /// spec's if the device doesn't support VK_EXT_provoking_vertex
+    pub provoking_vertex: ProvokingVertex,
+
     pub pipeline: Arc<GraphicsPipeline>,
     pub layout: Arc<PipelineLayout>,
 }
 
+/// Where a [`PipelineCompiler`] gets the render pass its pipelines are drawn in
+pub enum PipelineTarget {
+    /// The swapchain's current render pass, which changes when the window settings do
+    Swapchain(Ref<SwapchainManager>),
+    /// A fixed render pass, for drawing without a window
+    RenderPass(Arc<RenderPass>),
+}
+
+impl PipelineTarget {
+    pub fn render_pass(&self) -> Arc<RenderPass> {
+        match self {
+            Self::Swapchain(swapchain) => swapchain.read().render_pass.as_ref().unwrap().clone(),
+            Self::RenderPass(render_pass) => render_pass.clone(),
+        }
+    }
+}
+
 pub struct PipelineCompiler {
     pub device: Arc<Device>,
-    pub swapchain: Ref<SwapchainManager>,
+    pub target: PipelineTarget,
 
     cache: WeakValueHashMap<DynamicPipelineSpec, Weak<DynamicPipeline>>,
-    vertex_shaders: LruCache<ShaderSpec, Arc<ShaderModule>>,
-    fragment_shaders: LruCache<ShaderSpec, Arc<ShaderModule>>,
+    /// Both are keyed on the generated source, since many specs share the same stages
+    vertex_shaders: LruCache<String, Arc<ShaderModule>>,
+    fragment_shaders: LruCache<String, Arc<ShaderModule>>,
+    fallback_count: usize,
+    timings: CompileTimings,
+    shader_cache_stats: ShaderCacheStats,
+    count_watch: PipelineCountWatch,
+
+    /// Kept across runs in `pipeline_cache_path`, so that a cold start doesn't have to rebuild
+    /// every pipeline from scratch
+    pipeline_cache: Option<Arc<PipelineCache>>,
+    pipeline_cache_path: Option<PathBuf>,
+    /// Whether the pipeline cache was loaded with data, to tell cold and warm starts apart
+    pipeline_cache_warm: bool,
+    /// The time spent in GraphicsPipeline::new, without the shaders
+    pipeline_build_time: Duration,
+    pipelines_built: u32,
+
+    /// Kept with the pipelines, since the sets are made for their layouts
+    pub uniform_sets: UniformSetCache,
 }
 
-impl PipelineCompiler {
-    pub fn compile(&mut self, spec: &DynamicPipelineSpec) -> Arc<DynamicPipeline> {
-        if let Some(pipeline) = self.cache.get(spec) {
-            return pipeline;
-        }
+const SHADER_CACHE_SIZE: usize = 64;
 
-        let mut descriptors = HashMap::new();
+/// The descriptors the generated shaders declare, by (set, binding)
+pub fn generated_descriptors(
+    spec: &DynamicPipelineSpec,
+) -> HashMap<(u8, u8), DescriptorSetLayoutBinding> {
+    let mut descriptors = HashMap::new();
 
-        match &spec.color {
-            ColorMode::Flat(DataSource::Uniform { set, binding }) => {
-                let mut descriptor =
-                    DescriptorSetLayoutBinding::descriptor_type(DescriptorType::UniformBuffer);
+    match &spec.color {
+        ColorMode::Flat(DataSource::Uniform { set, binding }) => {
+            let mut descriptor =
+                DescriptorSetLayoutBinding::descriptor_type(DescriptorType::UniformBuffer);
 
-                descriptor.stages = ShaderStages::VERTEX;
+            descriptor.stages = ShaderStages::VERTEX;
 
-                descriptors.insert((*set, *binding), descriptor);
+            descriptors.insert((*set, *binding), descriptor);
+        }
+        ColorMode::Texture {
+            set,
+            binding,
+            units,
+        } => {
+            let mut descriptor =
+                DescriptorSetLayoutBinding::descriptor_type(DescriptorType::CombinedImageSampler);
+
+            descriptor.stages = ShaderStages::FRAGMENT;
+
+            for unit in 0..units.unit_count() as u8 {
+                descriptors.insert((*set, *binding + unit), descriptor.clone());
             }
-            ColorMode::Texture { set, binding, .. } => {
-                let mut descriptor =
-                    DescriptorSetLayoutBinding::descriptor_type(DescriptorType::SampledImage);
+        }
+        _ => {}
+    }
 
-                descriptor.stages = ShaderStages::FRAGMENT;
+    match &spec.matrix {
+        ShaderMatrixMode::MVP(DataSource::Uniform { set, binding }) => {
+            let mut descriptor =
+                DescriptorSetLayoutBinding::descriptor_type(DescriptorType::UniformBuffer);
 
-                descriptors.insert((*set, *binding), descriptor);
-            }
-            _ => {}
+            descriptor.stages = ShaderStages::VERTEX;
+
+            descriptors.insert((*set, *binding), descriptor);
         }
+        ShaderMatrixMode::VP_M(
+            DataSource::Uniform {
+                set: vp_set,
+                binding: vp_binding,
+            },
+            DataSource::Uniform {
+                set: model_set,
+                binding: model_binding,
+            },
+        ) => {
+            let mut vp_desc =
+                DescriptorSetLayoutBinding::descriptor_type(DescriptorType::UniformBuffer);
+            let mut m_desc =
+                DescriptorSetLayoutBinding::descriptor_type(DescriptorType::UniformBuffer);
 
-        match &spec.matrix {
-            ShaderMatrixMode::MVP(DataSource::Uniform { set, binding }) => {
-                let mut descriptor =
-                    DescriptorSetLayoutBinding::descriptor_type(DescriptorType::UniformBuffer);
+            vp_desc.stages = ShaderStages::VERTEX;
+            m_desc.stages = ShaderStages::VERTEX;
 
-                descriptor.stages = ShaderStages::VERTEX;
+            descriptors.insert((*vp_set, *vp_binding), vp_desc);
+            descriptors.insert((*model_set, *model_binding), m_desc);
+        }
+        _ => {}
+    }
 
-                descriptors.insert((*set, *binding), descriptor);
-            }
-            ShaderMatrixMode::VP_M(
-                DataSource::Uniform {
-                    set: vp_set,
-                    binding: vp_binding,
-                },
-                DataSource::Uniform {
-                    set: model_set,
-                    binding: model_binding,
-                },
-            ) => {
-                let mut vp_desc =
-                    DescriptorSetLayoutBinding::descriptor_type(DescriptorType::UniformBuffer);
-                let mut m_desc =
-                    DescriptorSetLayoutBinding::descriptor_type(DescriptorType::UniformBuffer);
+    if let DataSource::Uniform { set, binding } = &spec.transforms {
+        let mut descriptor =
+            DescriptorSetLayoutBinding::descriptor_type(DescriptorType::UniformBuffer);
 
-                vp_desc.stages = ShaderStages::VERTEX;
-                m_desc.stages = ShaderStages::VERTEX;
+        descriptor.stages = ShaderStages::VERTEX;
 
-                descriptors.insert((*vp_set, *vp_binding), vp_desc);
-                descriptors.insert((*model_set, *model_binding), m_desc);
-            }
-            _ => {}
-        }
+        descriptors.insert((*set, *binding), descriptor);
+    }
 
-        let mut set_layouts = Vec::new();
+    descriptors
+}
 
-        for set in 0..descriptors.keys().map(|x| x.0).max().unwrap_or(0) {
-            let mut create_info = DescriptorSetLayoutCreateInfo::default();
+/// The size of a generated pipeline's push constants, see
+/// [`ShaderSpec::get_vertex_shader_code`] for their layout
+pub fn generated_push_constant_size(spec: &DynamicPipelineSpec) -> usize {
+    let mut size = transform_push_constant_size(spec);
 
-            for ((_, descriptor), desc_type) in descriptors.iter().filter(|x| x.0 .0 == set) {
-                create_info
-                    .bindings
-                    .insert(*descriptor as u32, desc_type.clone());
-            }
+    if let ColorMode::Flat(DataSource::PushConstant) = &spec.color {
+        size += size_of::<Vec4>();
+    }
 
-            set_layouts.push(DescriptorSetLayout::new(self.device.clone(), create_info).unwrap());
-        }
+    if let ColorMode::Texture { units, .. } = &spec.color {
+        size += size_of::<TextureLayers>() + size_of::<Vec2>() * (1 + units.extra_units().len());
+    }
 
-        let mut size = 0;
+    if ShaderSpec::from(spec).rescales_normals() {
+        size += size_of::<f32>();
+    }
 
-        match &spec.matrix {
-            ShaderMatrixMode::MVP(DataSource::PushConstant) => {
-                size += size_of::<TMat4<f32>>();
-            }
-            ShaderMatrixMode::VP_M(DataSource::PushConstant, DataSource::PushConstant) => {
-                size += size_of::<TMat4<f32>>() * 2;
-            }
-            _ => {}
-        }
+    if spec.object_ids {
+        size += size_of::<u32>();
+    }
 
-        if let ColorMode::Flat(DataSource::PushConstant) = &spec.color {
-            size += size_of::<Vec4>();
+    size
+}
+
+/// The pipeline layout of the generated shaders, with the descriptors from
+/// [`generated_descriptors`]
+pub fn generated_layout(device: &Arc<Device>, spec: &DynamicPipelineSpec) -> Arc<PipelineLayout> {
+    let descriptors = generated_descriptors(spec);
+
+    let mut set_layouts = Vec::new();
+
+    // sets below the highest one get an empty layout, even if nothing is bound to them
+    let set_count = descriptors.keys().map(|x| x.0 + 1).max().unwrap_or(0);
+
+    for set in 0..set_count {
+        let mut create_info = DescriptorSetLayoutCreateInfo::default();
+
+        for ((_, descriptor), desc_type) in descriptors.iter().filter(|x| x.0 .0 == set) {
+            create_info
+                .bindings
+                .insert(*descriptor as u32, desc_type.clone());
         }
 
-        let layout = PipelineLayout::new(
-            self.device.clone(),
-            PipelineLayoutCreateInfo {
-                set_layouts,
-                push_constant_ranges: vec![PushConstantRange {
-                    stages: ShaderStages::all_graphics(),
-                    offset: 0,
-                    size: size as u32,
-                }],
-                ..Default::default()
+        set_layouts.push(DescriptorSetLayout::new(device.clone(), create_info).unwrap());
+    }
+
+    PipelineLayout::new(
+        device.clone(),
+        PipelineLayoutCreateInfo {
+            set_layouts,
+            push_constant_ranges: vec![PushConstantRange {
+                stages: ShaderStages::all_graphics(),
+                offset: 0,
+                size: generated_push_constant_size(spec) as u32,
+            }],
+            ..Default::default()
+        },
+    )
+    .unwrap()
+}
+
+/// Binds the vertex buffer to the inputs the generated vertex shader reads
+pub fn generated_vertex_input(spec: &DynamicPipelineSpec) -> VertexInputState {
+    let mut vertex_input = VertexInputState::new()
+        .binding(
+            0,
+            VertexInputBindingDescription {
+                stride: spec.vertex_buffer.stride as u32,
+                input_rate: VertexInputRate::Vertex,
             },
         )
-        .unwrap();
+        .attribute(
+            0,
+            VertexInputAttributeDescription {
+                binding: 0,
+                format: spec.position().as_vector().as_format(),
+                offset: spec.position().offset as u32,
+            },
+        );
 
-        let mut vertex_input = VertexInputState::new()
-            .binding(
-                0,
-                VertexInputBindingDescription {
-                    stride: spec.vertex_buffer.stride as u32,
-                    input_rate: VertexInputRate::Vertex,
-                },
-            )
-            .attribute(
-                0,
+    if let Some(normal) = spec.normal() {
+        vertex_input = vertex_input.attribute(
+            1,
+            VertexInputAttributeDescription {
+                binding: 0,
+                format: normal.as_vector().as_format(),
+                offset: normal.offset as u32,
+            },
+        );
+    }
+
+    match &spec.color {
+        ColorMode::Texture { .. } => {
+            let texcoord = spec.texcoord().unwrap();
+            vertex_input = vertex_input.attribute(
+                2,
                 VertexInputAttributeDescription {
                     binding: 0,
-                    format: spec.position().as_vector().as_format(),
-                    offset: spec.position().offset as u32,
+                    format: texcoord.as_vector().as_format(),
+                    offset: texcoord.offset as u32,
                 },
             );
 
-        if let Some(normal) = spec.normal() {
+            if let Some(lightmap_texcoord) = spec.vertex_buffer.lightmap_texcoord() {
+                vertex_input = vertex_input.attribute(
+                    3,
+                    VertexInputAttributeDescription {
+                        binding: 0,
+                        format: lightmap_texcoord.as_vector().as_format(),
+                        offset: lightmap_texcoord.offset as u32,
+                    },
+                );
+            }
+        }
+        ColorMode::Array => {
+            let color = spec.color().unwrap();
             vertex_input = vertex_input.attribute(
-                1,
+                2,
                 VertexInputAttributeDescription {
                     binding: 0,
-                    format: normal.as_vector().as_format(),
-                    offset: normal.offset as u32,
+                    format: color.as_vector().as_format(),
+                    offset: color.offset as u32,
                 },
             );
         }
+        _ => {}
+    }
 
-        match &spec.color {
-            ColorMode::Texture { .. } => {
-                let texcoord = spec.texcoord().unwrap();
-                vertex_input = vertex_input.attribute(
-                    2,
-                    VertexInputAttributeDescription {
-                        binding: 0,
-                        format: texcoord.as_vector().as_format(),
-                        offset: texcoord.offset as u32,
-                    },
+    vertex_input
+}
+
+/// The vulkan topology of a GL primitive. Vulkan has no line loops, so their closing segment is
+/// left out.
+pub fn primitive_topology(mode: DrawMode) -> PrimitiveTopology {
+    match mode {
+        DrawMode::Points => PrimitiveTopology::PointList,
+        DrawMode::LineStrip | DrawMode::LineLoop => PrimitiveTopology::LineStrip,
+        DrawMode::Lines => PrimitiveTopology::LineList,
+        DrawMode::LineStripAdj => PrimitiveTopology::LineStripWithAdjacency,
+        DrawMode::LinesAdj => PrimitiveTopology::LineListWithAdjacency,
+        DrawMode::TriStrip => PrimitiveTopology::TriangleStrip,
+        DrawMode::TriFan => PrimitiveTopology::TriangleFan,
+        DrawMode::Tri => PrimitiveTopology::TriangleList,
+        DrawMode::TriStripAdj => PrimitiveTopology::TriangleStripWithAdjacency,
+        DrawMode::TriAdj => PrimitiveTopology::TriangleListWithAdjacency,
+    }
+}
+
+type PipelineStages = (
+    Arc<PipelineLayout>,
+    VertexInputState,
+    [PipelineShaderStageCreateInfo; 2],
+    Duration,
+);
+
+/// Compiles slower than this are likely to cause a visible stutter, so they get logged
+pub const SLOW_COMPILE_THRESHOLD: Duration = Duration::from_millis(5);
+
+/// How often a generated shader could be reused instead of going through glslang
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ShaderCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl ShaderCacheStats {
+    /// In [0, 1], or 0 if nothing has been compiled yet
+    pub fn hit_rate(&self) -> f32 {
+        match self.hits + self.misses {
+            0 => 0.0,
+            total => self.hits as f32 / total as f32,
+        }
+    }
+}
+
+/// Past this many live pipelines, something is probably baking per-draw state into the specs
+pub const PIPELINE_SOFT_CAP: usize = 256;
+
+/// Warns when the live pipeline count goes past the soft cap, then again each time it doubles,
+/// so that a compile storm doesn't also flood the log
+#[derive(Debug)]
+pub struct PipelineCountWatch {
+    next_warning: usize,
+}
+
+impl Default for PipelineCountWatch {
+    fn default() -> Self {
+        Self {
+            next_warning: PIPELINE_SOFT_CAP,
+        }
+    }
+}
+
+impl PipelineCountWatch {
+    /// Returns true if `count` should be warned about
+    pub fn observe(&mut self, count: usize) -> bool {
+        if count <= self.next_warning {
+            return false;
+        }
+
+        while self.next_warning < count {
+            self.next_warning *= 2;
+        }
+
+        true
+    }
+}
+
+/// How long each spec has spent in glslang and pipeline creation
+#[derive(Debug, Default)]
+pub struct CompileTimings {
+    per_spec: HashMap<DynamicPipelineSpec, Duration>,
+    total: Duration,
+}
+
+impl CompileTimings {
+    /// Adds the time spent compiling a spec, returning true if this compile was slow
+    pub fn record(&mut self, spec: &DynamicPipelineSpec, elapsed: Duration) -> bool {
+        *self.per_spec.entry(spec.clone()).or_default() += elapsed;
+        self.total += elapsed;
+
+        elapsed > SLOW_COMPILE_THRESHOLD
+    }
+
+    pub fn get(&self, spec: &DynamicPipelineSpec) -> Option<Duration> {
+        self.per_spec.get(spec).copied()
+    }
+
+    pub fn total(&self) -> Duration {
+        self.total
+    }
+}
+
+/// The size of VkPipelineCacheHeaderVersionOne, which every pipeline cache starts with
+const PIPELINE_CACHE_HEADER_SIZE: usize = 32;
+
+/// Whether serialized pipeline cache data was made by this device and driver. Anything else is
+/// thrown away instead of being handed to the driver, since a driver update or a different gpu
+/// invalidates the cache.
+pub fn pipeline_cache_matches(
+    data: &[u8],
+    vendor_id: u32,
+    device_id: u32,
+    pipeline_cache_uuid: &[u8; 16],
+) -> bool {
+    if data.len() < PIPELINE_CACHE_HEADER_SIZE {
+        return false;
+    }
+
+    // the header is always little endian
+    let word = |i: usize| u32::from_le_bytes(data[i * 4..i * 4 + 4].try_into().unwrap());
+
+    word(0) as usize == PIPELINE_CACHE_HEADER_SIZE
+        && word(1) == 1 // VK_PIPELINE_CACHE_HEADER_VERSION_ONE
+        && word(2) == vendor_id
+        && word(3) == device_id
+        && data[16..32] == pipeline_cache_uuid[..]
+}
+
+/// Picks the convention a pipeline can actually be created with. Vulkan's native convention is
+/// first-vertex, so last-vertex needs the `provokingVertexLast` feature.
+pub fn resolve_provoking_vertex(
+    requested: ProvokingVertex,
+    supports_last: bool,
+) -> ProvokingVertex {
+    match requested {
+        ProvokingVertex::First => ProvokingVertex::First,
+        ProvokingVertex::Last if supports_last => ProvokingVertex::Last,
+        ProvokingVertex::Last => ProvokingVertex::First,
+    }
+}
+
+pub fn resolve_polygon_mode(requested: PolygonMode, supports_non_solid: bool) -> VkPolygonMode {
+    match requested {
+        PolygonMode::Fill => VkPolygonMode::Fill,
+        _ if !supports_non_solid => VkPolygonMode::Fill,
+        PolygonMode::Line => VkPolygonMode::Line,
+        PolygonMode::Point => VkPolygonMode::Point,
+    }
+}
+
+impl PipelineCompiler {
+    pub fn new(device: Arc<Device>, swapchain: Ref<SwapchainManager>) -> Self {
+        Self::with_target(device, PipelineTarget::Swapchain(swapchain))
+    }
+
+    pub fn with_target(device: Arc<Device>, target: PipelineTarget) -> Self {
+        Self {
+            device,
+            target,
+            cache: WeakValueHashMap::new(),
+            vertex_shaders: LruCache::new(NonZeroUsize::new(SHADER_CACHE_SIZE).unwrap()),
+            fragment_shaders: LruCache::new(NonZeroUsize::new(SHADER_CACHE_SIZE).unwrap()),
+            fallback_count: 0,
+            shader_cache_stats: ShaderCacheStats::default(),
+            timings: CompileTimings::default(),
+            count_watch: PipelineCountWatch::default(),
+            pipeline_cache: None,
+            pipeline_cache_path: None,
+            pipeline_cache_warm: false,
+            pipeline_build_time: Duration::ZERO,
+            pipelines_built: 0,
+            uniform_sets: UniformSetCache::default(),
+        }
+    }
+
+    /// Starts using the pipeline cache stored at `path`, which [`Self::save_pipeline_cache`]
+    /// writes back to. A missing, corrupt or incompatible file just starts an empty cache.
+    pub fn load_pipeline_cache(&mut self, path: PathBuf) {
+        let properties = self.device.physical_device().properties();
+
+        let initial_data = match std::fs::read(&path) {
+            Ok(data)
+                if pipeline_cache_matches(
+                    &data,
+                    properties.vendor_id,
+                    properties.device_id,
+                    &properties.pipeline_cache_uuid,
+                ) =>
+            {
+                data
+            }
+            Ok(_) => {
+                tracing::warn!(
+                    what = "the pipeline cache is corrupt or was made by another device or driver; starting an empty one",
+                    path = %path.display(),
                 );
+                Vec::new()
             }
-            ColorMode::Array => {
-                let color = spec.color().unwrap();
-                vertex_input = vertex_input.attribute(
-                    2,
-                    VertexInputAttributeDescription {
-                        binding: 0,
-                        format: color.as_vector().as_format(),
-                        offset: color.offset as u32,
-                    },
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => {
+                tracing::warn!(
+                    what = "could not read the pipeline cache; starting an empty one",
+                    path = %path.display(),
+                    error = %e,
                 );
+                Vec::new()
             }
-            _ => {}
+        };
+
+        let warm = !initial_data.is_empty();
+
+        // the header has been checked against this device, and drivers validate the rest
+        let cache = unsafe {
+            PipelineCache::new(
+                self.device.clone(),
+                PipelineCacheCreateInfo {
+                    initial_data,
+                    ..Default::default()
+                },
+            )
+        };
+
+        let (cache, warm) = match cache {
+            Ok(cache) => (Some(cache), warm),
+            Err(e) => {
+                tracing::warn!(
+                    what = "the driver rejected the pipeline cache; starting an empty one",
+                    error = %e,
+                );
+
+                let empty =
+                    unsafe { PipelineCache::new(self.device.clone(), Default::default()) }.ok();
+
+                (empty, false)
+            }
+        };
+
+        self.pipeline_cache = cache;
+        self.pipeline_cache_path = Some(path);
+        self.pipeline_cache_warm = warm;
+    }
+
+    /// Writes the pipeline cache back to the file it was loaded from, if there is one
+    pub fn save_pipeline_cache(&self) -> anyhow::Result<()> {
+        let (Some(cache), Some(path)) = (&self.pipeline_cache, &self.pipeline_cache_path) else {
+            return Ok(());
+        };
+
+        let data = cache.get_data()?;
+
+        // written beside the old cache first, so that a crash can't leave a truncated one behind
+        let partial = path.with_extension("partial");
+        std::fs::write(&partial, data)?;
+        std::fs::rename(&partial, path)?;
+
+        if cfg!(debug_assertions) {
+            tracing::info!(
+                what = "pipeline creation time this session",
+                warm_cache = self.pipeline_cache_warm,
+                pipelines = self.pipelines_built,
+                pipeline_time = ?self.pipeline_build_time,
+            );
         }
 
-        let mut create_info = GraphicsPipelineCreateInfo::layout(layout.clone());
+        Ok(())
+    }
 
-        create_info.vertex_input_state = Some(vertex_input);
+    /// Forgets every compiled pipeline so that they get rebuilt against the current render pass.
+    /// Shader modules don't depend on the render pass, so they're kept.
+    pub fn invalidate(&mut self) {
+        self.cache.clear();
+    }
 
-        let shader_spec = ShaderSpec::from(spec);
+    /// Builds the pipeline for `spec`, or returns the one that's already built for it
+    pub fn compile(
+        &mut self,
+        spec: &DynamicPipelineSpec,
+    ) -> Result<Arc<DynamicPipeline>, Validated<VulkanError>> {
+        if let Some(pipeline) = self.cache.get(spec) {
+            return Ok(pipeline);
+        }
 
-        let vert_shader = self.compile_vertex_shader(&shader_spec);
-        let frag_shader = self.compile_fragment_shader(&shader_spec);
+        let (layout, vertex_input, stages, shader_time) = match &spec.program {
+            Some(program) => self.program_stages(spec, program),
+            None => self.generated_stages(spec),
+        };
 
-        create_info.stages.push(PipelineShaderStageCreateInfo::new(
-            vert_shader.entry_point("main").unwrap(),
-        ));
+        let mut create_info = GraphicsPipelineCreateInfo::layout(layout.clone());
 
-        create_info.stages.push(PipelineShaderStageCreateInfo::new(
-            frag_shader.entry_point("main").unwrap(),
-        ));
+        create_info.vertex_input_state = Some(vertex_input);
+        create_info.input_assembly_state = Some(InputAssemblyState {
+            topology: primitive_topology(spec.draw_mode),
+            ..Default::default()
+        });
+        create_info.stages.extend(stages);
 
         create_info.dynamic_state.insert(DynamicState::Viewport);
         create_info.dynamic_state.insert(DynamicState::Scissor);
@@ -819,121 +1970,440 @@ impl PipelineCompiler {
         create_info.dynamic_state.insert(DynamicState::DepthBounds);
         create_info.dynamic_state.insert(DynamicState::CullMode);
         create_info.dynamic_state.insert(DynamicState::FrontFace);
+
+        if spec.rasterization.uses_blend_constants() {
+            create_info
+                .dynamic_state
+                .insert(DynamicState::BlendConstants);
+        }
+        let viewport_count = device_viewport_count(&self.device);
+
         create_info.viewport_state = Some(ViewportState {
-            viewports: vec![Viewport::default()].into(),
-            scissors: vec![Scissor::default()].into(),
+            viewports: vec![Viewport::default(); viewport_count].into(),
+            scissors: vec![Scissor::default(); viewport_count].into(),
             ..Default::default()
         });
 
+        let provoking_vertex = resolve_provoking_vertex(
+            spec.rasterization.provoking_vertex,
+            self.device.enabled_features().provoking_vertex_last,
+        );
+
+        if provoking_vertex != spec.rasterization.provoking_vertex {
+            tracing::warn!(
+                what = "the device does not support the requested provoking vertex convention; flat-shaded attributes may come from the wrong vertex",
+                requested = ?spec.rasterization.provoking_vertex,
+                actual = ?provoking_vertex,
+            );
+        }
+
+        let supports_non_solid = self.device.enabled_features().fill_mode_non_solid;
+
+        if spec.rasterization.polygon_mode != PolygonMode::Fill && !supports_non_solid {
+            tracing::warn!(
+                what = "the device does not support non-solid polygon modes; polygons will be filled instead",
+                requested = ?spec.rasterization.polygon_mode,
+            );
+        }
+
+        // the cull mode, front face and line width are dynamic, see DynamicRasterState
         create_info.rasterization_state = Some(RasterizationState {
-            cull_mode: spec.rasterization.cull_mode.clone(),
-            front_face: spec.rasterization.front_face.clone(),
-            line_width: (spec.rasterization.line_width as f32) / 10.0f32,
+            polygon_mode: resolve_polygon_mode(spec.rasterization.polygon_mode, supports_non_solid),
             ..Default::default()
         });
 
         create_info.depth_stencil_state = Some(DepthStencilState {
             depth: Some(DepthState {
-                write_enable: true,
-                compare_op: CompareOp::Less,
+                write_enable: spec.depth.write_enable,
+                compare_op: spec.depth.compare_op,
             }),
             ..Default::default()
         });
 
-        let render_pass = self.swapchain.read().render_pass.as_ref().unwrap().clone();
+        let render_pass = self.target.render_pass();
 
+        let subpass = Subpass::from(render_pass.clone(), 0).unwrap();
+
+        // the sample count has to match the subpass' attachments, so it isn't part of the spec,
+        // see RenderInsnAssembler::framebuffer_samples
+        create_info.multisample_state = Some(MultisampleState {
+            rasterization_samples: subpass.num_samples().unwrap_or(SampleCount::Sample1),
+            ..Default::default()
+        });
+
+        // the deferred geometry subpass writes albedo & normals, and both passes write object ids
+        // unless they're multisampled
         create_info.color_blend_state = Some(ColorBlendState {
-            attachments: vec![ColorBlendAttachmentState {
-                blend: spec.rasterization.color_blending.clone(),
-                ..Default::default()
-            }],
+            attachments: (0..subpass.num_color_attachments() as usize)
+                .map(|location| match location {
+                    // integer attachments can't be blended, and user programs don't write ids
+                    OBJECT_ID_LOCATION => ColorBlendAttachmentState {
+                        blend: None,
+                        color_write_mask: if spec.program.is_some() {
+                            ColorComponents::empty()
+                        } else {
+                            ColorComponents::all()
+                        },
+                        ..Default::default()
+                    },
+                    // normals are written as is, blending would skew them towards what's behind
+                    NORMALS_LOCATION => ColorBlendAttachmentState {
+                        blend: None,
+                        ..Default::default()
+                    },
+                    _ => ColorBlendAttachmentState {
+                        blend: spec.rasterization.color_blending.clone(),
+                        ..Default::default()
+                    },
+                })
+                .collect(),
             ..Default::default()
         });
 
-        create_info.subpass = Some(PipelineSubpassType::BeginRenderPass(
-            Subpass::from(render_pass.clone(), 0).unwrap(),
-        ));
+        create_info.subpass = Some(PipelineSubpassType::BeginRenderPass(subpass));
 
-        let pipeline = GraphicsPipeline::new(self.device.clone(), None, create_info).unwrap();
+        let start = Instant::now();
+
+        // first-vertex is vulkan's default, the other convention has to be chained onto the
+        // rasterization state by hand since vulkano doesn't expose it
+        let pipeline = if provoking_vertex == ProvokingVertex::First {
+            GraphicsPipeline::new(
+                self.device.clone(),
+                self.pipeline_cache.clone(),
+                create_info,
+            )?
+        } else {
+            // the create info is the same one vulkano would have validated, and Last is only
+            // resolved when the device enabled provokingVertexLast (and so VK_EXT_provoking_vertex)
+            unsafe {
+                new_graphics_pipeline(
+                    self.device.clone(),
+                    self.pipeline_cache.clone(),
+                    create_info,
+                    provoking_vertex,
+                )
+            }?
+        };
+
+        let pipeline_time = start.elapsed();
+
+        self.pipeline_build_time += pipeline_time;
+        self.pipelines_built += 1;
+
+        if self.timings.record(spec, shader_time + pipeline_time) {
+            tracing::warn!(
+                what = "a pipeline took a long time to compile",
+                ?shader_time,
+                ?pipeline_time,
+                ?spec,
+            );
+        }
 
         let dyn_pipeline = Arc::new(DynamicPipeline {
             spec: spec.clone(),
+            provoking_vertex,
             pipeline,
             layout,
         });
 
         self.cache.insert(spec.clone(), dyn_pipeline.clone());
 
-        dyn_pipeline
-    }
+        let count = self.pipeline_count();
 
-    fn compile_vertex_shader(&mut self, spec: &ShaderSpec) -> Arc<ShaderModule> {
-        if let Some(module) = self.vertex_shaders.get(spec) {
-            return module.clone();
+        if self.count_watch.observe(count) {
+            tracing::warn!(
+                what =
+                    "there are a lot of live pipelines; some state is probably changing every draw",
+                count,
+                soft_cap = PIPELINE_SOFT_CAP,
+                ?spec,
+            );
         }
 
-        let compiler = Compiler::acquire().unwrap();
+        Ok(dyn_pipeline)
+    }
 
-        let source = ShaderSource::try_from(spec.get_vertex_shader_code()).unwrap();
+    pub fn timings(&self) -> &CompileTimings {
+        &self.timings
+    }
 
-        let input = ShaderInput::new(
-            &source,
-            glslang::ShaderStage::Vertex,
-            &CompilerOptions::default(),
-            None,
-            None,
-        );
-        let shader = glslang::Shader::new(&compiler, input.unwrap()).expect("shader init");
+    pub fn shader_cache_stats(&self) -> ShaderCacheStats {
+        self.shader_cache_stats
+    }
+
+    /// How many compiled pipelines are still in use
+    pub fn pipeline_count(&self) -> usize {
+        self.cache.iter().count()
+    }
 
-        let mut program = Program::new(&compiler);
+    /// How many pipelines had to be drawn with the fallback shaders
+    pub fn fallback_count(&self) -> usize {
+        self.fallback_count
+    }
 
-        program.add_shader(&shader);
+    /// Builds the layout, vertex input and stages for the fixed-function emulation shaders
+    fn generated_stages(&mut self, spec: &DynamicPipelineSpec) -> PipelineStages {
+        let layout = generated_layout(&self.device, spec);
 
-        let code = program
-            .compile(glslang::ShaderStage::Vertex)
-            .expect("shader");
+        let vertex_input = generated_vertex_input(spec);
 
-        let module = unsafe {
-            ShaderModule::new(self.device.clone(), ShaderModuleCreateInfo::new(&code[..])).unwrap()
+        let shader_spec = ShaderSpec::from(spec);
+
+        let start = Instant::now();
+
+        let stages = compile_stages_or_fallback(&shader_spec, |stage, code| {
+            self.compile_shader(stage, code)
+        });
+
+        let shader_time = start.elapsed();
+
+        if stages.is_fallback {
+            self.fallback_count += 1;
+        }
+
+        let stages = [
+            PipelineShaderStageCreateInfo::new(stages.vertex.entry_point("main").unwrap()),
+            PipelineShaderStageCreateInfo::new(stages.fragment.entry_point("main").unwrap()),
+        ];
+
+        (layout, vertex_input, stages, shader_time)
+    }
+
+    /// Builds the layout, vertex input and stages for a linked user program. Client arrays are only
+    /// bound to the locations the program's vertex shader actually reads.
+    fn program_stages(
+        &mut self,
+        spec: &DynamicPipelineSpec,
+        program: &LinkedProgram,
+    ) -> PipelineStages {
+        let start = Instant::now();
+
+        let module = |words: &[u32]| unsafe {
+            ShaderModule::new(self.device.clone(), ShaderModuleCreateInfo::new(words)).unwrap()
         };
 
-        self.vertex_shaders.put(spec.clone(), module.clone());
+        let vertex = module(&program.vertex);
+        let fragment = module(&program.fragment);
 
-        module
+        let shader_time = start.elapsed();
+
+        let stages = [
+            PipelineShaderStageCreateInfo::new(vertex.entry_point("main").unwrap()),
+            PipelineShaderStageCreateInfo::new(fragment.entry_point("main").unwrap()),
+        ];
+
+        let layout = PipelineLayout::new(
+            self.device.clone(),
+            PipelineDescriptorSetLayoutCreateInfo::from_stages(&stages)
+                .into_pipeline_layout_create_info(self.device.clone())
+                .unwrap(),
+        )
+        .unwrap();
+
+        let vertex_input = program_vertex_input(&spec.vertex_buffer, program);
+
+        (layout, vertex_input, stages, shader_time)
     }
 
-    fn compile_fragment_shader(&mut self, spec: &ShaderSpec) -> Arc<ShaderModule> {
-        if let Some(module) = self.fragment_shaders.get(spec) {
-            return module.clone();
+    /// Compiles the shaders of every spec across worker threads and puts them in the shader
+    /// caches, so that [`Self::compile`] only has to hit the caches for them afterwards. Shaders
+    /// which are already cached are skipped, and ones glslang rejects are left for `compile` to
+    /// fall back from as usual. Returns how many shaders were compiled.
+    pub fn precompile_shaders(&mut self, specs: &[ShaderSpec]) -> usize {
+        let mut sources = Vec::new();
+        let mut queued = HashSet::new();
+
+        for spec in specs {
+            for (stage, code) in [
+                (glslang::ShaderStage::Vertex, spec.get_vertex_shader_code()),
+                (
+                    glslang::ShaderStage::Fragment,
+                    spec.get_fragment_shader_code(),
+                ),
+            ] {
+                let cache = match stage {
+                    glslang::ShaderStage::Vertex => &self.vertex_shaders,
+                    _ => &self.fragment_shaders,
+                };
+
+                if !cache.contains(&code) && queued.insert((stage, code.clone())) {
+                    sources.push((stage, code));
+                }
+            }
         }
 
-        let compiler = Compiler::acquire().unwrap();
+        let mut compiled = 0;
 
-        let source = ShaderSource::try_from(spec.get_vertex_shader_code()).unwrap();
+        for ((stage, code), words) in compile_spirv_parallel(sources) {
+            let Ok(words) = words else {
+                continue;
+            };
 
-        let input = ShaderInput::new(
-            &source,
-            glslang::ShaderStage::Fragment,
-            &CompilerOptions::default(),
-            None,
-            None,
-        );
-        let shader = glslang::Shader::new(&compiler, input.unwrap()).expect("shader init");
+            // modules are created here rather than on the workers, since they're cheap next to glslang
+            let module = unsafe {
+                ShaderModule::new(self.device.clone(), ShaderModuleCreateInfo::new(&words[..]))
+                    .unwrap()
+            };
 
-        let mut program = Program::new(&compiler);
+            let cache = match stage {
+                glslang::ShaderStage::Vertex => &mut self.vertex_shaders,
+                _ => &mut self.fragment_shaders,
+            };
+
+            self.shader_cache_stats.misses += 1;
+            cache.put(code, module);
+            compiled += 1;
+        }
+
+        compiled
+    }
+
+    fn compile_shader(
+        &mut self,
+        stage: glslang::ShaderStage,
+        code: String,
+    ) -> Result<Arc<ShaderModule>, GlslangError> {
+        let cache = match stage {
+            glslang::ShaderStage::Vertex => &mut self.vertex_shaders,
+            _ => &mut self.fragment_shaders,
+        };
+
+        if let Some(module) = cache.get(&code) {
+            self.shader_cache_stats.hits += 1;
+            return Ok(module.clone());
+        }
 
-        program.add_shader(&shader);
+        self.shader_cache_stats.misses += 1;
 
-        let code = program
-            .compile(glslang::ShaderStage::Fragment)
-            .expect("shader");
+        let words = compile_spirv(stage, code.clone())?;
 
         let module = unsafe {
-            ShaderModule::new(self.device.clone(), ShaderModuleCreateInfo::new(&code[..])).unwrap()
+            ShaderModule::new(self.device.clone(), ShaderModuleCreateInfo::new(&words[..])).unwrap()
         };
 
-        self.fragment_shaders.put(spec.clone(), module.clone());
+        cache.put(code, module.clone());
 
-        module
+        Ok(module)
     }
 }
+
+/// The result of [`compile_stages_or_fallback`]
+pub struct CompiledStages<T> {
+    pub vertex: T,
+    pub fragment: T,
+    /// Whether the spec's own shaders failed to compile and the magenta fallback was used instead
+    pub is_fallback: bool,
+}
+
+/// Compiles a spec's vertex and fragment shaders. If glslang rejects either one, the offending
+/// glsl is logged and the spec's fallback shaders are compiled instead so that the frame can still
+/// be drawn.
+pub fn compile_stages_or_fallback<T>(
+    spec: &ShaderSpec,
+    mut compile: impl FnMut(glslang::ShaderStage, String) -> Result<T, GlslangError>,
+) -> CompiledStages<T> {
+    let mut compile_or_log = |stage: glslang::ShaderStage, code: String| {
+        compile(stage, code.clone())
+            .inspect_err(|error| {
+                tracing::error!(
+                    what = "could not compile a generated shader, its draws will use the magenta fallback instead",
+                    ?stage,
+                    %error,
+                    code,
+                );
+            })
+            .ok()
+    };
+
+    let vertex = compile_or_log(glslang::ShaderStage::Vertex, spec.get_vertex_shader_code());
+    let fragment = compile_or_log(
+        glslang::ShaderStage::Fragment,
+        spec.get_fragment_shader_code(),
+    );
+
+    if let (Some(vertex), Some(fragment)) = (vertex, fragment) {
+        return CompiledStages {
+            vertex,
+            fragment,
+            is_fallback: false,
+        };
+    }
+
+    CompiledStages {
+        vertex: compile(
+            glslang::ShaderStage::Vertex,
+            spec.get_fallback_vertex_shader_code(),
+        )
+        .expect("the fallback vertex shader should always compile"),
+        fragment: compile(
+            glslang::ShaderStage::Fragment,
+            spec.get_fallback_fragment_shader_code(),
+        )
+        .expect("the fallback fragment shader should always compile"),
+        is_fallback: true,
+    }
+}
+
+/// Compiles a single glsl shader stage into a module.
+pub fn compile_glsl(
+    device: &Arc<Device>,
+    stage: glslang::ShaderStage,
+    source: String,
+) -> Arc<ShaderModule> {
+    let code = compile_spirv(stage, source).expect("shader");
+
+    unsafe { ShaderModule::new(device.clone(), ShaderModuleCreateInfo::new(&code[..])).unwrap() }
+}
+
+/// Compiles every source with [`compile_spirv`], split across as many threads as there are cores.
+/// The results are in the same order as `sources`.
+pub fn compile_spirv_parallel(
+    sources: Vec<(glslang::ShaderStage, String)>,
+) -> Vec<(
+    (glslang::ShaderStage, String),
+    Result<Vec<u32>, GlslangError>,
+)> {
+    let threads = std::thread::available_parallelism().map_or(1, NonZeroUsize::get);
+    let chunk_size = sources.len().div_ceil(threads).max(1);
+
+    std::thread::scope(|scope| {
+        let workers = sources
+            .chunks(chunk_size)
+            .map(|chunk| {
+                // each worker acquires the compiler for itself, since glslang keeps its
+                // allocators per thread
+                scope.spawn(move || {
+                    chunk
+                        .iter()
+                        .map(|(stage, code)| compile_spirv(*stage, code.clone()))
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let results = workers
+            .into_iter()
+            .flat_map(|worker| worker.join().expect("a shader compiler thread panicked"));
+
+        sources.iter().cloned().zip(results).collect()
+    })
+}
+
+/// Compiles a single glsl shader stage into spir-v words.
+pub fn compile_spirv(
+    stage: glslang::ShaderStage,
+    source: String,
+) -> Result<Vec<u32>, GlslangError> {
+    let compiler = Compiler::acquire().unwrap();
+
+    let source = ShaderSource::try_from(source).unwrap();
+
+    let input = ShaderInput::new(&source, stage, &CompilerOptions::default(), None, None)?;
+    let shader = glslang::Shader::new(&compiler, input)?;
+
+    let mut program = Program::new(&compiler);
+
+    program.add_shader(&shader);
+
+    program.compile(stage)
+}
@@ -1,29 +1,184 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::hash::Hasher;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+
+use image::Rgba;
+use image::RgbaImage;
+use nalgebra_glm::TMat3;
+use nalgebra_glm::TMat4;
+use nalgebra_glm::Vec4;
+use vulkano::buffer::Buffer;
+use vulkano::buffer::BufferCreateInfo;
+use vulkano::buffer::BufferUsage;
+use vulkano::command_buffer::allocator::StandardCommandBufferAllocator;
+use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::command_buffer::CommandBufferUsage;
+use vulkano::command_buffer::CopyImageToBufferInfo;
+use vulkano::command_buffer::PrimaryCommandBufferAbstract;
+use vulkano::command_buffer::RenderPassBeginInfo;
+use vulkano::command_buffer::SubpassBeginInfo;
+use vulkano::command_buffer::SubpassContents;
+use vulkano::command_buffer::SubpassEndInfo;
+use vulkano::descriptor_set::allocator::StandardDescriptorSetAllocator;
+use vulkano::descriptor_set::layout::DescriptorType;
+use vulkano::descriptor_set::PersistentDescriptorSet;
+use vulkano::descriptor_set::WriteDescriptorSet;
+use vulkano::device::Device;
+use vulkano::device::DeviceCreateInfo;
+use vulkano::device::DeviceExtensions;
+use vulkano::device::Features;
+use vulkano::device::Queue;
+use vulkano::device::QueueCreateInfo;
+use vulkano::device::QueueFlags;
+use vulkano::format::Format;
+use vulkano::image::sampler::Sampler;
+use vulkano::image::view::ImageView;
+use vulkano::image::view::ImageViewType;
+use vulkano::image::Image;
+use vulkano::image::ImageCreateInfo;
+use vulkano::image::ImageUsage;
+use vulkano::image::SampleCount;
+use vulkano::instance::Instance;
+use vulkano::instance::InstanceCreateInfo;
+use vulkano::memory::allocator::AllocationCreateInfo;
+use vulkano::memory::allocator::MemoryTypeFilter;
+use vulkano::memory::allocator::StandardMemoryAllocator;
+use vulkano::pipeline::graphics::color_blend::BlendFactor;
+use vulkano::pipeline::graphics::color_blend::ColorBlendAttachmentState;
+use vulkano::pipeline::graphics::color_blend::ColorBlendState;
+use vulkano::pipeline::graphics::depth_stencil::CompareOp;
+use vulkano::pipeline::graphics::input_assembly::InputAssemblyState;
+use vulkano::pipeline::graphics::multisample::MultisampleState;
+use vulkano::pipeline::graphics::rasterization::RasterizationState;
+use vulkano::pipeline::graphics::viewport::Viewport;
+use vulkano::pipeline::graphics::viewport::ViewportState;
+use vulkano::pipeline::graphics::GraphicsPipelineCreateInfo;
+use vulkano::pipeline::GraphicsPipeline;
+use vulkano::pipeline::Pipeline;
+use vulkano::pipeline::PipelineBindPoint;
+use vulkano::pipeline::PipelineLayout;
+use vulkano::pipeline::PipelineShaderStageCreateInfo;
+use vulkano::render_pass::Framebuffer;
+use vulkano::render_pass::FramebufferCreateInfo;
+use vulkano::render_pass::Subpass;
+use vulkano::shader::reflect;
+use vulkano::shader::spirv::ExecutionModel;
+use vulkano::shader::spirv::Spirv;
+use vulkano::shader::ShaderModule;
+use vulkano::shader::ShaderModuleCreateInfo;
+use vulkano::sync::GpuFuture;
+use vulkano::Validated;
+use vulkano::VulkanLibrary;
+
+use crate::vulkan::arena::DrawBufferPool;
+use crate::vulkan::commands::CommandRecorder;
+use crate::vulkan::commands::RenderCommand;
+use crate::vulkan::commands::UniformSetCache;
 use crate::vulkan::dynamic_shader::*;
+use crate::vulkan::instance::Allocators;
+use crate::vulkan::lighting::light_blend;
+use crate::vulkan::lighting::AMBIENT_FRAG;
+use crate::vulkan::lighting::DIRECTIONAL_FRAG;
+use crate::vulkan::provoking_vertex::new_graphics_pipeline;
+use crate::vulkan::provoking_vertex::provoking_vertex_mode;
+use crate::vulkan::render_manager::DrawStats;
+use crate::vulkan::sandbox::DepthFunc;
+use crate::vulkan::sandbox::DrawMode;
+use crate::vulkan::sandbox::GLDataType;
+use crate::vulkan::sandbox::ProvokingVertex;
+use crate::vulkan::sandbox::TextureEnvMode;
+use crate::vulkan::shaders::programs::ProgramRegistry;
+use crate::vulkan::shaders::programs::UserShaderStage;
+use crate::vulkan::spinlock::SpinLock;
+use crate::vulkan::textures::texture_manager::TextureBinding;
+use crate::vulkan::textures::texture_manager::TextureHandle;
+use crate::vulkan::textures::texture_manager::TextureParams;
+use crate::vulkan::textures::texture_manager::TextureQuality;
+use crate::vulkan::textures::texture_manager::TextureReference;
+use crate::vulkan::textures::texture_manager::TextureStorage;
+use crate::vulkan::textures::texture_manager::RGBA_FORMAT;
+use crate::vulkan::textures::textures::AnimationMetadata;
+use crate::vulkan::textures::textures::TextureImage;
+use crate::vulkan::utils::Ref;
+
+fn hash_of<T: Hash>(value: &T) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn position_only_spec() -> DynamicPipelineSpec {
+    DynamicPipelineSpec {
+        draw_mode: DrawMode::Tri,
+        vertex_buffer: VertexBufferLayout {
+            fields: [
+                Some(VertexInputSpec {
+                    data_type: GLDataType::F32,
+                    num_elements: 3,
+                    offset: 0,
+                }),
+                None,
+                None,
+                None,
+                None,
+                None,
+            ],
+            generic: Vec::new(),
+            stride: 12,
+        },
+        color: ColorMode::Flat(DataSource::PushConstant),
+        matrix: ShaderMatrixMode::MVP(DataSource::PushConstant),
+        normal_scaling: NormalScaling::None,
+        rasterization: DynamicPipelineRasterization::default(),
+        depth: DynamicPipelineDepth::default(),
+        alpha_test: None,
+        clip_planes: 0,
+        transforms: DataSource::PushConstant,
+        object_ids: false,
+        program: None,
+    }
+}
 
 #[test]
 fn shader_test() {
     let shader_spec = ShaderSpec {
-        color: ColorMode::Texture { set: 1, binding: 0 },
+        color: ColorMode::Texture {
+            set: 1,
+            binding: 0,
+            units: TextureCombine::default(),
+        },
         matrix: ShaderMatrixMode::MVP(DataSource::PushConstant),
+        normal_scaling: NormalScaling::None,
+        alpha_test: None,
+        clip_planes: 0,
+        transforms: DataSource::PushConstant,
+        object_ids: false,
         vertex_buffer: VertexBufferLayout {
             fields: [
                 Some(VertexInputSpec {
-                    data_type: VertexDataType::F32,
+                    data_type: GLDataType::F32,
                     num_elements: 3,
                     offset: 0,
                 }),
                 Some(VertexInputSpec {
-                    data_type: VertexDataType::F32,
+                    data_type: GLDataType::F32,
                     num_elements: 3,
                     offset: 12,
                 }),
                 None,
                 Some(VertexInputSpec {
-                    data_type: VertexDataType::F32,
+                    data_type: GLDataType::F32,
                     num_elements: 2,
                     offset: 12 + 12,
                 }),
+                None,
+                None,
             ],
+            generic: Vec::new(),
             stride: 12 + 12 + 8,
         },
     };
@@ -33,3 +188,1681 @@ fn shader_test() {
 
     panic!();
 }
+
+#[test]
+fn provoking_vertex_is_part_of_spec() {
+    let last = position_only_spec();
+    assert_eq!(last.rasterization.provoking_vertex, ProvokingVertex::Last);
+
+    let mut first = last.clone();
+    first.rasterization.provoking_vertex = ProvokingVertex::First;
+
+    assert_ne!(last, first);
+    assert_ne!(hash_of(&last), hash_of(&first));
+
+    assert_eq!(
+        resolve_provoking_vertex(last.rasterization.provoking_vertex, true),
+        ProvokingVertex::Last
+    );
+    assert_eq!(
+        resolve_provoking_vertex(first.rasterization.provoking_vertex, true),
+        ProvokingVertex::First
+    );
+    assert_eq!(
+        resolve_provoking_vertex(last.rasterization.provoking_vertex, false),
+        ProvokingVertex::First
+    );
+
+    // the resolved convention is what gets chained onto the rasterization state
+    assert_eq!(
+        provoking_vertex_mode(ProvokingVertex::Last),
+        ash::vk::ProvokingVertexModeEXT::LAST_VERTEX
+    );
+    assert_eq!(
+        provoking_vertex_mode(ProvokingVertex::First),
+        ash::vk::ProvokingVertexModeEXT::FIRST_VERTEX
+    );
+}
+
+#[test]
+fn pipelines_are_built_with_the_provoking_vertex_the_device_supports() {
+    let Some((device, _queue)) = headless_device() else {
+        return;
+    };
+
+    let render_pass = vulkano::single_pass_renderpass!(
+        device.clone(),
+        attachments: {
+            color: {
+                format: Format::R8G8B8A8_UNORM,
+                samples: 1,
+                load_op: Clear,
+                store_op: Store,
+            },
+            depth: {
+                format: Format::D16_UNORM,
+                samples: 1,
+                load_op: Clear,
+                store_op: DontCare,
+            },
+        },
+        pass: {
+            color: [color],
+            depth_stencil: {depth},
+        },
+    )
+    .unwrap();
+
+    let supports_last = device.enabled_features().provoking_vertex_last;
+
+    let mut compiler =
+        PipelineCompiler::with_target(device.clone(), PipelineTarget::RenderPass(render_pass));
+
+    let mut first_spec = position_only_spec();
+    first_spec.rasterization.provoking_vertex = ProvokingVertex::First;
+
+    // last-vertex pipelines go through new_graphics_pipeline when the device supports them
+    let last = compiler.compile(&position_only_spec()).unwrap();
+    let first = compiler.compile(&first_spec).unwrap();
+
+    assert_eq!(
+        last.provoking_vertex,
+        if supports_last {
+            ProvokingVertex::Last
+        } else {
+            ProvokingVertex::First
+        }
+    );
+    assert_eq!(first.provoking_vertex, ProvokingVertex::First);
+    assert!(!Arc::ptr_eq(&last, &first));
+
+    // a create info it can't build is an error instead of a panic
+    let layout = PipelineLayout::new(device.clone(), Default::default()).unwrap();
+
+    let missing_states = unsafe {
+        new_graphics_pipeline(
+            device,
+            None,
+            GraphicsPipelineCreateInfo::layout(layout),
+            ProvokingVertex::Last,
+        )
+    };
+
+    assert!(matches!(missing_states, Err(Validated::ValidationError(_))));
+}
+
+#[test]
+fn pipelines_are_rasterized_at_their_subpass_sample_count() {
+    let Some((device, _queue)) = headless_device() else {
+        return;
+    };
+
+    // every implementation supports 4 samples for colour and depth attachments
+    let render_pass = vulkano::single_pass_renderpass!(
+        device.clone(),
+        attachments: {
+            color: {
+                format: Format::R8G8B8A8_UNORM,
+                samples: 4,
+                load_op: Clear,
+                store_op: Store,
+            },
+            depth: {
+                format: Format::D16_UNORM,
+                samples: 4,
+                load_op: Clear,
+                store_op: DontCare,
+            },
+        },
+        pass: {
+            color: [color],
+            depth_stencil: {depth},
+        },
+    )
+    .unwrap();
+
+    let mut compiler =
+        PipelineCompiler::with_target(device, PipelineTarget::RenderPass(render_pass));
+
+    let pipeline = compiler.compile(&position_only_spec()).unwrap();
+
+    assert_eq!(
+        pipeline
+            .pipeline
+            .multisample_state()
+            .map(|state| state.rasterization_samples),
+        Some(SampleCount::Sample4)
+    );
+
+    // the sample count comes from the render pass, so there's only one pipeline to share
+    assert!(Arc::ptr_eq(
+        &compiler.compile(&position_only_spec()).unwrap(),
+        &pipeline
+    ));
+}
+
+#[test]
+fn normalize_only_when_enabled() {
+    let mut spec = ShaderSpec::from(&position_only_spec());
+    spec.vertex_buffer.fields[1] = Some(VertexInputSpec {
+        data_type: GLDataType::F32,
+        num_elements: 3,
+        offset: 12,
+    });
+    spec.vertex_buffer.stride = 24;
+
+    assert!(!spec.get_vertex_shader_code().contains("normalize("));
+
+    spec.normal_scaling = NormalScaling::Normalize;
+
+    assert!(spec
+        .get_vertex_shader_code()
+        .contains("normal_out = normalize(normal_in);"));
+
+    // without a normal array there's nothing to normalize
+    let mut spec = ShaderSpec::from(&position_only_spec());
+    spec.normal_scaling = NormalScaling::Normalize;
+
+    assert!(!spec.get_vertex_shader_code().contains("normalize("));
+}
+
+#[test]
+fn rescale_uses_push_constant_factor() {
+    let mut spec = ShaderSpec::from(&position_only_spec());
+    spec.vertex_buffer.fields[1] = Some(VertexInputSpec {
+        data_type: GLDataType::F32,
+        num_elements: 3,
+        offset: 12,
+    });
+    spec.vertex_buffer.stride = 24;
+    spec.normal_scaling = NormalScaling::Rescale;
+
+    let code = spec.get_vertex_shader_code();
+
+    assert!(spec.rescales_normals());
+    assert!(code.contains("float normal_scale;"));
+    assert!(code.contains("normal_out = normal_in * PushConstants.normal_scale;"));
+    assert!(!code.contains("normalize("));
+
+    // GL_NORMALIZE wins when both are enabled
+    assert_eq!(
+        NormalScaling::from_caps(true, true),
+        NormalScaling::Normalize
+    );
+    assert_eq!(
+        NormalScaling::from_caps(false, true),
+        NormalScaling::Rescale
+    );
+
+    spec.normal_scaling = NormalScaling::Normalize;
+
+    let code = spec.get_vertex_shader_code();
+
+    assert!(!spec.rescales_normals());
+    assert!(!code.contains("normal_scale"));
+    assert!(code.contains("normalize(normal_in)"));
+}
+
+#[test]
+fn as_vector_covers_every_type() {
+    let types = [
+        GLDataType::U8,
+        GLDataType::I8,
+        GLDataType::U16,
+        GLDataType::I16,
+        GLDataType::U32,
+        GLDataType::I32,
+        GLDataType::F32,
+        GLDataType::F64,
+    ];
+
+    for data_type in types {
+        for num_elements in 1..=4 {
+            let vector = VertexInputSpec {
+                data_type,
+                num_elements,
+                offset: 0,
+            }
+            .as_vector();
+
+            assert_eq!(vector.ordinal(), data_type);
+            assert_eq!(vector.size(), num_elements);
+        }
+    }
+
+    assert_eq!(
+        VertexInputSpec {
+            data_type: GLDataType::F32,
+            num_elements: 3,
+            offset: 0,
+        }
+        .as_vector(),
+        VectorDataType::F32(3)
+    );
+}
+
+#[test]
+#[should_panic(expected = "has 5")]
+fn as_vector_rejects_too_many_elements() {
+    VertexInputSpec {
+        data_type: GLDataType::F32,
+        num_elements: 5,
+        offset: 0,
+    }
+    .as_vector();
+}
+
+#[test]
+fn fragment_stage_is_compiled_from_fragment_source() {
+    let mut spec = ShaderSpec::from(&position_only_spec());
+    spec.color = ColorMode::Texture {
+        set: 1,
+        binding: 0,
+        units: TextureCombine::default(),
+    };
+    spec.vertex_buffer.fields[3] = Some(VertexInputSpec {
+        data_type: GLDataType::F32,
+        num_elements: 2,
+        offset: 12,
+    });
+    spec.vertex_buffer.stride = 20;
+
+    let words = compile_spirv(
+        glslang::ShaderStage::Fragment,
+        spec.get_fragment_shader_code(),
+    )
+    .unwrap();
+    let spirv = Spirv::new(&words).unwrap();
+
+    let (_, entry_point) = reflect::entry_points(&spirv)
+        .find(|(_, info)| info.execution_model == ExecutionModel::Fragment)
+        .expect("the module should have a fragment entry point");
+
+    let sampler = &entry_point.descriptor_binding_requirements[&(1, 0)];
+
+    assert!(sampler
+        .descriptor_types
+        .contains(&DescriptorType::CombinedImageSampler));
+    assert_eq!(sampler.image_view_type, Some(ImageViewType::Dim2dArray));
+}
+
+#[test]
+fn extra_texture_units_are_combined_in_order() {
+    let mut units = TextureCombine::new(TextureEnvMode::Modulate);
+    assert!(units.push(TextureEnvMode::Add));
+    assert!(units.push(TextureEnvMode::Decal));
+    assert!(!units.push(TextureEnvMode::Replace));
+    assert_eq!(units.unit_count(), MAX_SHADER_TEXTURE_UNITS);
+
+    let mut spec = position_only_spec();
+    spec.color = ColorMode::Texture {
+        set: 1,
+        binding: 0,
+        units,
+    };
+
+    let code = ShaderSpec::from(&spec).get_fragment_shader_code();
+
+    assert!(code.contains("binding = 2) uniform sampler2DArray unit2_sampler"));
+    assert!(code.contains("binding = 3) uniform sampler2DArray unit3_sampler"));
+
+    let lightmap = code.find("texture(lightmap_sampler").unwrap();
+    let unit2 = code.find("texture(unit2_sampler").unwrap();
+    let unit3 = code.find("texture(unit3_sampler").unwrap();
+    assert!(lightmap < unit2 && unit2 < unit3);
+
+    // every unit after the lightmap samples at its own texcoord
+    assert!(code.contains("texture(unit2_sampler, vec3(unit2_texcoord_in, texture_layers_in[2]))"));
+    assert!(code.contains("texture(unit3_sampler, vec3(unit3_texcoord_in, texture_layers_in[3]))"));
+
+    let vertex = ShaderSpec::from(&spec).get_vertex_shader_code();
+    assert!(vertex.contains("vec2 unit2_texcoord;\n  vec2 unit3_texcoord;"));
+    assert!(vertex.contains("unit3_texcoord_out = PushConstants.unit3_texcoord;"));
+
+    let descriptors = generated_descriptors(&spec);
+    for binding in 0..MAX_SHADER_TEXTURE_UNITS as u8 {
+        assert_eq!(
+            descriptors[&(1, binding)].descriptor_type,
+            DescriptorType::CombinedImageSampler
+        );
+    }
+
+    compile_spirv(glslang::ShaderStage::Fragment, code).unwrap();
+}
+
+#[test]
+fn rectangle_textures_fetch_texels() {
+    let mut units = TextureCombine::default();
+    units.set_rectangle(true);
+
+    let mut spec = position_only_spec();
+    spec.color = ColorMode::Texture {
+        set: 1,
+        binding: 0,
+        units,
+    };
+
+    let code = ShaderSpec::from(&spec).get_fragment_shader_code();
+
+    assert!(code.contains(
+        "  vec4 color = texelFetch(tex_sampler, ivec3(texcoord_in, texture_layers_in[0]), 0);\n"
+    ));
+    assert!(!code.contains("texture(tex_sampler"));
+
+    // the other units still sample at normalized coordinates
+    assert!(code
+        .contains("texture(lightmap_sampler, vec3(lightmap_texcoord_in, texture_layers_in[1]))"));
+
+    compile_spirv(glslang::ShaderStage::Fragment, code).unwrap();
+
+    // and it's a different pipeline to the normalized one
+    let mut normalized = spec.clone();
+    normalized.color = ColorMode::Texture {
+        set: 1,
+        binding: 0,
+        units: TextureCombine::default(),
+    };
+
+    assert_ne!(hash_of(&spec), hash_of(&normalized));
+}
+
+#[test]
+fn clip_planes_size_the_clip_distances() {
+    let unclipped = ShaderSpec::from(&position_only_spec());
+
+    // no planes doesn't need the clip distance feature at all
+    let code = unclipped.get_vertex_shader_code();
+    assert!(!code.contains("gl_ClipDistance"));
+    assert!(!code.contains("clip_planes"));
+
+    for planes in 1..=2 {
+        let clipped = ShaderSpec {
+            clip_planes: planes,
+            ..unclipped.clone()
+        };
+
+        let code = clipped.get_vertex_shader_code();
+        assert!(code.contains(&format!("  float gl_ClipDistance[{planes}];\n")));
+        assert!(code.contains(&format!("  vec4 clip_planes[{planes}];\n")));
+        assert!(code.contains(&format!(
+            "  gl_ClipDistance[{}] = dot(PushConstants.clip_planes[{0}], gl_Position);\n",
+            planes - 1
+        )));
+        assert!(!code.contains(&format!("gl_ClipDistance[{planes}] =")));
+
+        compile_spirv(glslang::ShaderStage::Vertex, code).unwrap();
+
+        assert_ne!(clipped, unclipped);
+        assert_ne!(hash_of(&clipped), hash_of(&unclipped));
+    }
+
+    let one = DynamicPipelineSpec {
+        clip_planes: 1,
+        ..position_only_spec()
+    };
+    let two = DynamicPipelineSpec {
+        clip_planes: 2,
+        ..position_only_spec()
+    };
+
+    assert_ne!(hash_of(&one), hash_of(&two));
+    assert_eq!(ShaderSpec::from(&two).clip_planes, 2);
+}
+
+#[test]
+fn oversized_push_constants_move_the_transforms_to_a_uniform_buffer() {
+    // lit, textured and clipped, which needs more than the 128 bytes every device has
+    let mut spec = textured_spec();
+    spec.vertex_buffer.fields[VertexInputType::Normal as usize] = Some(VertexInputSpec {
+        data_type: GLDataType::F32,
+        num_elements: 3,
+        offset: 20,
+    });
+    spec.vertex_buffer.stride = 32;
+    spec.matrix = ShaderMatrixMode::MVPNormal;
+    spec.clip_planes = 1;
+
+    assert!(generated_push_constant_size(&spec) > MIN_MAX_PUSH_CONSTANTS_SIZE as usize);
+
+    let mut fitted = spec.clone();
+    fit_push_constants(&mut fitted, MIN_MAX_PUSH_CONSTANTS_SIZE);
+
+    assert_eq!(
+        fitted.transforms,
+        DataSource::Uniform { set: 0, binding: 0 }
+    );
+    assert!(generated_push_constant_size(&fitted) <= MIN_MAX_PUSH_CONSTANTS_SIZE as usize);
+    assert_eq!(texture_layers_offset(&fitted), 0);
+    assert_ne!(hash_of(&fitted), hash_of(&spec));
+
+    // devices with room for it keep everything in the push constants
+    let mut roomy = spec.clone();
+    fit_push_constants(&mut roomy, 256);
+    assert_eq!(roomy.transforms, DataSource::PushConstant);
+
+    let descriptors = generated_descriptors(&fitted);
+    assert_eq!(
+        descriptors[&(0, 0)].descriptor_type,
+        DescriptorType::UniformBuffer
+    );
+
+    let shader_spec = ShaderSpec::from(&fitted);
+    let code = shader_spec.get_vertex_shader_code();
+
+    assert!(code.contains("uniform TransformData {\n  mat4 mvp;\n  mat3 normal_matrix;\n  vec4 clip_planes[1];\n} Transforms;\n"));
+    assert!(code.contains("  gl_Position = Transforms.mvp * vec4(position_in"));
+    assert!(code.contains("dot(Transforms.clip_planes[0], gl_Position)"));
+    assert!(code.contains("Transforms.normal_matrix * normal_in"));
+    assert!(!code.contains("PushConstants.mvp"));
+
+    let spirv = compile_spirv(glslang::ShaderStage::Vertex, code).unwrap();
+    compile_spirv(
+        glslang::ShaderStage::Fragment,
+        shader_spec.get_fragment_shader_code(),
+    )
+    .unwrap();
+    compile_spirv(
+        glslang::ShaderStage::Vertex,
+        shader_spec.get_fallback_vertex_shader_code(),
+    )
+    .unwrap();
+
+    let spirv = Spirv::new(&spirv).unwrap();
+    let (_, entry_point) = reflect::entry_points(&spirv)
+        .find(|(_, info)| info.execution_model == ExecutionModel::Vertex)
+        .unwrap();
+
+    assert!(entry_point.descriptor_binding_requirements[&(0, 0)]
+        .descriptor_types
+        .contains(&DescriptorType::UniformBuffer));
+
+    // the uniform block is laid out like the push constants it replaces
+    let mut push_constants = DynamicPipelinePushConstants {
+        mvp: Some(TMat4::identity()),
+        normal_matrix: Some(TMat3::identity()),
+        clip_planes: vec![Vec4::new(1.0, 2.0, 3.0, 4.0)],
+        object_id: Some(7),
+        ..Default::default()
+    };
+
+    let data = push_constants.take_transforms();
+    assert_eq!(data.len(), 64 + 48 + 16);
+    let plane: Vec<f32> = data[112..]
+        .chunks_exact(4)
+        .map(|bytes| f32::from_ne_bytes(bytes.try_into().unwrap()))
+        .collect();
+    assert_eq!(plane, [1.0, 2.0, 3.0, 4.0]);
+
+    assert_eq!(push_constants.mvp, None);
+    assert_eq!(push_constants.normal_matrix, None);
+    assert!(push_constants.clip_planes.is_empty());
+    assert_eq!(push_constants.object_id, Some(7));
+}
+
+#[test]
+fn generated_glsl_compiles() {
+    let color_modes = [
+        ColorMode::Flat(DataSource::PushConstant),
+        ColorMode::Flat(DataSource::Uniform { set: 0, binding: 2 }),
+        ColorMode::Texture {
+            set: 1,
+            binding: 0,
+            units: TextureCombine::default(),
+        },
+        ColorMode::Array,
+    ];
+
+    let matrix_modes = [
+        ShaderMatrixMode::MVP(DataSource::PushConstant),
+        ShaderMatrixMode::MVP(DataSource::Uniform { set: 0, binding: 0 }),
+        ShaderMatrixMode::MVPNormal,
+        ShaderMatrixMode::VP_M(DataSource::PushConstant, DataSource::PushConstant),
+        ShaderMatrixMode::VP_M(
+            DataSource::Uniform { set: 0, binding: 0 },
+            DataSource::Uniform { set: 0, binding: 1 },
+        ),
+    ];
+
+    for color in &color_modes {
+        for matrix in &matrix_modes {
+            let mut spec = ShaderSpec::from(&position_only_spec());
+            spec.color = color.clone();
+            spec.matrix = matrix.clone();
+            spec.vertex_buffer.fields[2] = Some(VertexInputSpec {
+                data_type: GLDataType::F32,
+                num_elements: 4,
+                offset: 12,
+            });
+            spec.vertex_buffer.fields[3] = Some(VertexInputSpec {
+                data_type: GLDataType::F32,
+                num_elements: 2,
+                offset: 28,
+            });
+            spec.vertex_buffer.stride = 36;
+
+            // unwrapping shows glslang's log if the source is invalid
+            compile_spirv(glslang::ShaderStage::Vertex, spec.get_vertex_shader_code()).unwrap();
+            compile_spirv(
+                glslang::ShaderStage::Fragment,
+                spec.get_fragment_shader_code(),
+            )
+            .unwrap();
+        }
+    }
+}
+
+#[test]
+fn broken_spec_falls_back_to_magenta() {
+    // glsl won't implicitly narrow the dvec3 normal into the vec3 output
+    let mut spec = ShaderSpec::from(&position_only_spec());
+    spec.vertex_buffer.fields[1] = Some(VertexInputSpec {
+        data_type: GLDataType::F64,
+        num_elements: 3,
+        offset: 12,
+    });
+    spec.vertex_buffer.stride = 36;
+
+    assert!(compile_spirv(glslang::ShaderStage::Vertex, spec.get_vertex_shader_code()).is_err());
+
+    let stages = compile_stages_or_fallback(&spec, compile_spirv);
+
+    assert!(stages.is_fallback);
+    assert!(!stages.vertex.is_empty());
+    assert!(!stages.fragment.is_empty());
+
+    // working specs keep their own shaders
+    let spec = ShaderSpec::from(&position_only_spec());
+    let stages = compile_stages_or_fallback(&spec, compile_spirv);
+
+    assert!(!stages.is_fallback);
+}
+
+#[test]
+fn fragment_shader_writes_normals() {
+    let mut spec = ShaderSpec::from(&position_only_spec());
+    spec.vertex_buffer.fields[1] = Some(VertexInputSpec {
+        data_type: GLDataType::F32,
+        num_elements: 3,
+        offset: 12,
+    });
+    spec.vertex_buffer.stride = 24;
+
+    let output_locations = |code: String| {
+        let words = compile_spirv(glslang::ShaderStage::Fragment, code).unwrap();
+        let spirv = Spirv::new(&words).unwrap();
+
+        let (_, entry_point) = reflect::entry_points(&spirv).next().unwrap();
+
+        let mut locations = entry_point
+            .output_interface
+            .elements()
+            .iter()
+            .map(|output| output.location)
+            .collect::<Vec<_>>();
+        locations.sort();
+        locations
+    };
+
+    // colour and normals
+    assert_eq!(output_locations(spec.get_fragment_shader_code()), [0, 1]);
+    assert!(!spec.get_vertex_shader_code().contains("object_id"));
+    assert_eq!(
+        output_locations(spec.get_fallback_fragment_shader_code()),
+        [0]
+    );
+
+    // the object id only while picking
+    spec.object_ids = true;
+    assert_eq!(output_locations(spec.get_fragment_shader_code()), [0, 1, 2]);
+    assert!(spec.get_vertex_shader_code().contains("object_id"));
+    assert_eq!(
+        output_locations(spec.get_fallback_fragment_shader_code()),
+        [0, 2]
+    );
+}
+
+#[test]
+fn compile_timing_is_recorded() {
+    let spec = position_only_spec();
+    let mut timings = CompileTimings::default();
+
+    assert_eq!(timings.get(&spec), None);
+
+    let start = Instant::now();
+    compile_stages_or_fallback(&ShaderSpec::from(&spec), compile_spirv);
+    let elapsed = start.elapsed();
+
+    timings.record(&spec, elapsed);
+
+    assert_eq!(timings.get(&spec), Some(elapsed));
+    assert_eq!(timings.total(), elapsed);
+
+    let mut other = spec.clone();
+    other.draw_mode = DrawMode::Lines;
+
+    assert!(timings.record(&other, SLOW_COMPILE_THRESHOLD * 2));
+
+    // recompiles after an invalidation add up
+    assert!(!timings.record(&spec, Duration::from_micros(1)));
+
+    assert_eq!(timings.get(&spec), Some(elapsed + Duration::from_micros(1)));
+    assert_eq!(
+        timings.total(),
+        elapsed + Duration::from_micros(1) + SLOW_COMPILE_THRESHOLD * 2
+    );
+}
+
+const USER_VERTEX_SHADER: &str = "#version 450
+
+layout(push_constant) uniform PushConstants {
+    mat4 mvp;
+} pc;
+
+layout(location = 0) in vec3 position;
+layout(location = 2) in vec4 color;
+
+layout(location = 0) out vec4 color_out;
+
+void main() {
+    gl_Position = pc.mvp * vec4(position, 1.0);
+    color_out = color;
+}
+";
+
+const USER_FRAGMENT_SHADER: &str = "#version 450
+
+layout(location = 0) in vec4 color_in;
+
+layout(location = 0) out vec4 frag_color;
+
+void main() {
+    frag_color = color_in;
+}
+";
+
+#[test]
+fn user_program_compiles_and_links() {
+    let mut programs = ProgramRegistry::default();
+
+    let vertex = programs.create_shader(UserShaderStage::Vertex);
+    let fragment = programs.create_shader(UserShaderStage::Fragment);
+
+    programs.shader_mut(vertex).unwrap().source = USER_VERTEX_SHADER.to_owned();
+    programs.shader_mut(fragment).unwrap().source = USER_FRAGMENT_SHADER.to_owned();
+
+    assert!(programs.shader_mut(vertex).unwrap().compile());
+    assert!(programs.shader_mut(fragment).unwrap().compile());
+
+    let program = programs.create_program();
+
+    // linking with a stage missing fails, but can be retried
+    assert!(programs.attach_shader(program, vertex));
+    assert_eq!(programs.link_program(program), Some(false));
+    assert!(!programs.program(program).unwrap().info_log.is_empty());
+
+    assert!(programs.attach_shader(program, fragment));
+    assert_eq!(programs.link_program(program), Some(true));
+
+    let linked = programs.program(program).unwrap().linked.clone().unwrap();
+
+    assert_eq!(linked.attribute_location("position"), Some(0));
+    assert_eq!(linked.attribute_location("color"), Some(2));
+    assert_eq!(linked.attribute_location("normal"), None);
+    assert_eq!(linked.push_constant_size, 64);
+
+    // relinking produces a distinct program so that its pipelines get rebuilt
+    assert_eq!(programs.link_program(program), Some(true));
+    assert_ne!(
+        &*programs.program(program).unwrap().linked.clone().unwrap(),
+        &*linked
+    );
+
+    assert_eq!(programs.link_program(program + 100), None);
+}
+
+#[test]
+fn broken_user_shader_has_info_log() {
+    let mut programs = ProgramRegistry::default();
+
+    let shader = programs.create_shader(UserShaderStage::Fragment);
+    let user_shader = programs.shader_mut(shader).unwrap();
+
+    user_shader.source = "#version 450\nvoid main() { undefined_function(); }\n".to_owned();
+
+    assert!(!user_shader.compile());
+    assert!(user_shader.spirv.is_none());
+    assert!(!user_shader.info_log.is_empty());
+}
+
+#[test]
+fn generic_attribute_is_bound_at_its_location() {
+    let mut programs = ProgramRegistry::default();
+
+    let vertex = programs.create_shader(UserShaderStage::Vertex);
+    let fragment = programs.create_shader(UserShaderStage::Fragment);
+
+    programs.shader_mut(vertex).unwrap().source = "#version 450
+
+layout(location = 0) in vec3 position;
+layout(location = 3) in vec2 uv;
+
+layout(location = 0) out vec4 color_out;
+
+void main() {
+    gl_Position = vec4(position, 1.0);
+    color_out = vec4(uv, 0.0, 1.0);
+}
+"
+    .to_owned();
+    programs.shader_mut(fragment).unwrap().source = USER_FRAGMENT_SHADER.to_owned();
+
+    assert!(programs.shader_mut(vertex).unwrap().compile());
+    assert!(programs.shader_mut(fragment).unwrap().compile());
+
+    let program = programs.create_program();
+    programs.attach_shader(program, vertex);
+    programs.attach_shader(program, fragment);
+    assert_eq!(programs.link_program(program), Some(true));
+
+    let linked = programs.program(program).unwrap().linked.clone().unwrap();
+
+    let mut layout = position_only_spec().vertex_buffer;
+    layout.generic.push((
+        3,
+        VertexInputSpec {
+            data_type: GLDataType::F32,
+            num_elements: 2,
+            offset: 12,
+        },
+    ));
+    layout.stride = 20;
+
+    let input = program_vertex_input(&layout, &linked);
+
+    let uv = &input.attributes[&3];
+    assert_eq!(uv.binding, 0);
+    assert_eq!(uv.format, Format::R32G32_SFLOAT);
+    assert_eq!(uv.offset, 12);
+
+    assert_eq!(input.attributes[&0].format, Format::R32G32B32_SFLOAT);
+    assert_eq!(input.bindings[&0].stride, 20);
+}
+
+#[test]
+fn depth_state_is_part_of_the_cache_key() {
+    let less = position_only_spec();
+
+    let lequal = DynamicPipelineSpec {
+        depth: DynamicPipelineDepth {
+            compare_op: gl_compare_op(DepthFunc::LessOrEqual),
+            ..Default::default()
+        },
+        ..position_only_spec()
+    };
+
+    let no_write = DynamicPipelineSpec {
+        depth: DynamicPipelineDepth {
+            write_enable: false,
+            ..Default::default()
+        },
+        ..position_only_spec()
+    };
+
+    assert_eq!(lequal.depth.compare_op, CompareOp::LessOrEqual);
+
+    let specs = [&less, &lequal, &no_write];
+
+    for (i, a) in specs.iter().enumerate() {
+        for b in &specs[i + 1..] {
+            assert_ne!(a, b);
+            assert_ne!(hash_of(a), hash_of(b));
+        }
+    }
+
+    let mut cache = HashMap::new();
+
+    for spec in specs {
+        cache.insert(spec.clone(), ());
+    }
+
+    assert_eq!(cache.len(), 3);
+}
+
+#[test]
+fn pipeline_count_warnings_back_off() {
+    let mut watch = PipelineCountWatch::default();
+
+    assert!(!watch.observe(PIPELINE_SOFT_CAP));
+    assert!(watch.observe(PIPELINE_SOFT_CAP + 1));
+    // only warns again once the count has doubled
+    assert!(!watch.observe(PIPELINE_SOFT_CAP + 2));
+    assert!(watch.observe(PIPELINE_SOFT_CAP * 2 + 1));
+}
+
+#[test]
+fn alpha_test_discards_in_the_fragment_shader() {
+    let plain = ShaderSpec::from(&position_only_spec());
+
+    assert!(!plain.get_fragment_shader_code().contains("discard"));
+
+    let cutout = ShaderSpec {
+        alpha_test: Some(AlphaTest {
+            func: CompareOp::Greater,
+            ref_value: 0.1,
+        }),
+        ..plain.clone()
+    };
+
+    // the fragment is discarded when the test fails, after its colour has been computed
+    let code = cutout.get_fragment_shader_code();
+    let discard = code.find("if (frag_color_out.a <= 0.1) discard;").unwrap();
+    assert!(code.find("frag_color_out = ").unwrap() < discard);
+
+    // distinct thresholds mustn't share a cached shader
+    let half = ShaderSpec {
+        alpha_test: Some(AlphaTest {
+            func: CompareOp::Greater,
+            ref_value: 0.5,
+        }),
+        ..plain.clone()
+    };
+
+    assert_ne!(cutout, half);
+    assert_ne!(hash_of(&cutout), hash_of(&half));
+    assert_ne!(hash_of(&plain), hash_of(&cutout));
+
+    let never = ShaderSpec {
+        alpha_test: Some(AlphaTest {
+            func: CompareOp::Never,
+            ref_value: 0.0,
+        }),
+        ..plain
+    };
+
+    assert!(never.get_fragment_shader_code().contains("  discard;\n"));
+}
+
+#[test]
+fn lit_normals_go_through_the_normal_matrix() {
+    let mut spec = ShaderSpec::from(&position_only_spec());
+    spec.vertex_buffer.fields[1] = Some(VertexInputSpec {
+        data_type: GLDataType::F32,
+        num_elements: 3,
+        offset: 12,
+    });
+    spec.vertex_buffer.stride = 24;
+
+    // the unlit path is unchanged
+    assert!(spec
+        .get_vertex_shader_code()
+        .contains("  normal_out = normal_in;\n"));
+
+    spec.matrix = ShaderMatrixMode::MVPNormal;
+    spec.normal_scaling = NormalScaling::Normalize;
+
+    let code = spec.get_vertex_shader_code();
+    assert!(code.contains("  mat4 mvp;\n  mat3 normal_matrix;\n"));
+    assert!(code.contains("  normal_out = normalize(PushConstants.normal_matrix * normal_in);\n"));
+
+    compile_spirv(glslang::ShaderStage::Vertex, code).unwrap();
+
+    // each column of a mat3 push constant is padded to a vec4
+    let padded = pad_mat3(&nalgebra_glm::TMat3::identity());
+    assert_eq!(padded[1], [0.0, 1.0, 0.0, 0.0]);
+}
+
+#[test]
+fn lighting_keeps_the_vertex_alpha() {
+    let mut unlit = ShaderSpec::from(&position_only_spec());
+    unlit.vertex_buffer.fields[1] = Some(VertexInputSpec {
+        data_type: GLDataType::F32,
+        num_elements: 3,
+        offset: 12,
+    });
+    unlit.vertex_buffer.fields[2] = Some(VertexInputSpec {
+        data_type: GLDataType::F32,
+        num_elements: 4,
+        offset: 24,
+    });
+    unlit.vertex_buffer.stride = 40;
+    unlit.color = ColorMode::Array;
+
+    let mut lit = unlit.clone();
+    lit.matrix = ShaderMatrixMode::MVPNormal;
+
+    // the vertex colour reaches the g-buffer untouched, lit or not
+    assert!(lit
+        .get_vertex_shader_code()
+        .contains("  frag_color_out = vec4(color_in);\n"));
+    assert_eq!(
+        lit.get_fragment_shader_code(),
+        unlit.get_fragment_shader_code()
+    );
+    assert!(lit
+        .get_fragment_shader_code()
+        .contains("  frag_color_out = frag_color_in;\n"));
+
+    // the lights only change the colour, and pass the albedo's alpha through
+    for frag in [AMBIENT_FRAG, DIRECTIONAL_FRAG] {
+        assert!(frag.contains("f_color = vec4(combined_color, subpassLoad(u_color).a);"));
+
+        compile_spirv(glslang::ShaderStage::Fragment, frag.to_owned()).unwrap();
+    }
+
+    let blend = light_blend();
+    assert_eq!(blend.src_alpha_blend_factor, BlendFactor::One);
+    assert_eq!(blend.dst_alpha_blend_factor, BlendFactor::Zero);
+}
+
+#[test]
+fn textured_layout_matches_the_shader() {
+    let mut spec = position_only_spec();
+    spec.color = ColorMode::Texture {
+        set: 1,
+        binding: 0,
+        units: TextureCombine::default(),
+    };
+
+    let descriptors = generated_descriptors(&spec);
+
+    // the recorder binds both units as combined image samplers, like the shader declares them
+    for binding in [(1, 0), (1, 1)] {
+        assert_eq!(
+            descriptors[&binding].descriptor_type,
+            DescriptorType::CombinedImageSampler
+        );
+    }
+
+    assert!(generated_descriptors(&position_only_spec()).is_empty());
+}
+
+#[test]
+fn pipeline_cache_matches_only_this_device() {
+    let uuid = [7u8; 16];
+
+    let header = |size: u32, version: u32, vendor: u32, device: u32| {
+        let mut data = Vec::new();
+        for word in [size, version, vendor, device] {
+            data.extend_from_slice(&word.to_le_bytes());
+        }
+        data.extend_from_slice(&uuid);
+        // whatever the driver stores after the header
+        data.extend_from_slice(&[0xAB; 64]);
+        data
+    };
+
+    let good = header(32, 1, 0x10DE, 0x2484);
+    assert!(pipeline_cache_matches(&good, 0x10DE, 0x2484, &uuid));
+
+    // another gpu, or the same gpu after a driver update
+    assert!(!pipeline_cache_matches(&good, 0x1002, 0x2484, &uuid));
+    assert!(!pipeline_cache_matches(&good, 0x10DE, 0x2485, &uuid));
+    assert!(!pipeline_cache_matches(&good, 0x10DE, 0x2484, &[8u8; 16]));
+
+    assert!(!pipeline_cache_matches(
+        &header(32, 2, 0x10DE, 0x2484),
+        0x10DE,
+        0x2484,
+        &uuid
+    ));
+    assert!(!pipeline_cache_matches(
+        &header(16, 1, 0x10DE, 0x2484),
+        0x10DE,
+        0x2484,
+        &uuid
+    ));
+
+    // truncated or empty files
+    assert!(!pipeline_cache_matches(&good[..20], 0x10DE, 0x2484, &uuid));
+    assert!(!pipeline_cache_matches(&[], 0x10DE, 0x2484, &uuid));
+}
+
+#[test]
+fn shaders_compile_in_parallel() {
+    // 16 specs which only differ by their alpha test, so each has its own fragment shader
+    let specs = (0..16)
+        .map(|i| {
+            let mut spec = position_only_spec();
+            spec.alpha_test = Some(AlphaTest {
+                func: CompareOp::Greater,
+                ref_value: i as f32 / 16.0,
+            });
+            ShaderSpec::from(&spec)
+        })
+        .collect::<Vec<_>>();
+
+    let sources = specs
+        .iter()
+        .flat_map(|spec| {
+            [
+                (glslang::ShaderStage::Vertex, spec.get_vertex_shader_code()),
+                (
+                    glslang::ShaderStage::Fragment,
+                    spec.get_fragment_shader_code(),
+                ),
+            ]
+        })
+        .collect::<Vec<_>>();
+
+    let results = compile_spirv_parallel(sources.clone());
+
+    assert_eq!(results.len(), 32);
+
+    for ((source, result), expected) in results.into_iter().zip(sources) {
+        // the results come back in the order they were given
+        assert_eq!(source, expected);
+        assert!(!result.unwrap().is_empty());
+    }
+}
+
+#[test]
+fn alternating_pipelines_rebind_every_draw() {
+    let Some((device, queue)) = headless_device() else {
+        return;
+    };
+
+    let memory_allocator = Arc::new(StandardMemoryAllocator::new_default(device.clone()));
+    let command_buffer_allocator =
+        StandardCommandBufferAllocator::new(device.clone(), Default::default());
+
+    let render_pass = vulkano::single_pass_renderpass!(
+        device.clone(),
+        attachments: {
+            color: {
+                format: Format::R8G8B8A8_UNORM,
+                samples: 1,
+                load_op: Clear,
+                store_op: Store,
+            },
+            normals: {
+                format: Format::R8G8B8A8_UNORM,
+                samples: 1,
+                load_op: Clear,
+                store_op: DontCare,
+            },
+            depth: {
+                format: Format::D16_UNORM,
+                samples: 1,
+                load_op: Clear,
+                store_op: DontCare,
+            },
+        },
+        pass: {
+            color: [color, normals],
+            depth_stencil: {depth},
+        },
+    )
+    .unwrap();
+
+    let compiler = Ref::new(PipelineCompiler::with_target(
+        device.clone(),
+        PipelineTarget::RenderPass(render_pass),
+    ));
+
+    let lines = DynamicPipelineSpec {
+        draw_mode: DrawMode::Lines,
+        ..position_only_spec()
+    };
+    let tris = position_only_spec();
+
+    // nothing is submitted, only the binds and draws are counted
+    let record = |specs: &[&DynamicPipelineSpec]| {
+        let builder = AutoCommandBufferBuilder::primary(
+            &command_buffer_allocator,
+            queue.queue_family_index(),
+            CommandBufferUsage::OneTimeSubmit,
+        )
+        .unwrap();
+
+        let mut recorder = CommandRecorder::new(
+            memory_allocator.clone(),
+            Arc::new(StandardDescriptorSetAllocator::new(
+                device.clone(),
+                Default::default(),
+            )),
+            builder,
+            DrawBufferPool::new(memory_allocator.clone()),
+            compiler.clone(),
+            [4, 4],
+        );
+
+        for spec in specs {
+            recorder.feed(RenderCommand::BindDynamicGraphicsPipeline {
+                pipeline: (*spec).clone(),
+                push_constants: DynamicPipelinePushConstants {
+                    mvp: Some(TMat4::identity()),
+                    color: Some(Vec4::repeat(1.0)),
+                    ..Default::default()
+                },
+                raster_state: DynamicRasterState::default(),
+            });
+
+            recorder.feed(RenderCommand::Draw {
+                start_vertex: 0,
+                vertex_count: 3,
+                data: Arc::new(vec![0; 3 * 12]),
+            });
+        }
+
+        recorder.draw_stats
+    };
+
+    let alternating = record(&[&lines, &tris, &lines, &tris, &lines, &tris]);
+    assert_eq!(alternating.draw_calls, 6);
+    assert_eq!(alternating.pipeline_binds, 6);
+    assert_eq!(alternating.binds_per_draw(), 1.0);
+
+    let sorted = record(&[&lines, &lines, &lines, &tris, &tris, &tris]);
+    assert_eq!(sorted.draw_calls, 6);
+    assert_eq!(sorted.pipeline_binds, 2);
+    assert!((sorted.binds_per_draw() - 1.0 / 3.0).abs() < 1e-6);
+
+    assert_eq!(DrawStats::default().binds_per_draw(), 0.0);
+}
+
+fn textured_spec() -> DynamicPipelineSpec {
+    let mut spec = position_only_spec();
+    spec.color = ColorMode::Texture {
+        set: 1,
+        binding: 0,
+        units: TextureCombine::default(),
+    };
+    spec.vertex_buffer.fields[VertexInputType::TexCoord as usize] = Some(VertexInputSpec {
+        data_type: GLDataType::F32,
+        num_elements: 2,
+        offset: 12,
+    });
+    spec.vertex_buffer.stride = 20;
+    spec
+}
+
+#[test]
+fn texture_layers_follow_the_clip_planes() {
+    let mut spec = textured_spec();
+
+    assert_eq!(texture_layers_offset(&spec), 64);
+
+    spec.clip_planes = 2;
+    assert_eq!(texture_layers_offset(&spec), 64 + 32);
+
+    spec.matrix = ShaderMatrixMode::MVPNormal;
+    assert_eq!(texture_layers_offset(&spec), 64 + 48 + 32);
+
+    // the layers and the lightmap texcoord come after the planes, then the object id if picking
+    assert_eq!(
+        generated_push_constant_size(&spec),
+        texture_layers_offset(&spec) as usize + 16 + 8
+    );
+
+    spec.object_ids = true;
+    assert_eq!(
+        generated_push_constant_size(&spec),
+        texture_layers_offset(&spec) as usize + 16 + 8 + 4
+    );
+
+    let shader_spec = ShaderSpec::from(&spec);
+
+    compile_spirv(
+        glslang::ShaderStage::Vertex,
+        shader_spec.get_vertex_shader_code(),
+    )
+    .unwrap();
+    compile_spirv(
+        glslang::ShaderStage::Fragment,
+        shader_spec.get_fragment_shader_code(),
+    )
+    .unwrap();
+}
+
+/// A device without a window, for the tests which draw something. None if there's no vulkan
+/// driver to make one with, in which case those tests don't check anything. Last-vertex pipelines
+/// are enabled the same way [`Devices`](crate::vulkan::devices::Devices) does, when supported.
+fn headless_device() -> Option<(Arc<Device>, Arc<Queue>)> {
+    let library = VulkanLibrary::new().ok()?;
+    let instance = Instance::new(library, InstanceCreateInfo::default()).ok()?;
+
+    let (physical_device, queue_family_index) = instance
+        .enumerate_physical_devices()
+        .ok()?
+        .find_map(|device| {
+            let family = device
+                .queue_family_properties()
+                .iter()
+                .position(|family| family.queue_flags.intersects(QueueFlags::GRAPHICS))?;
+
+            Some((device, family as u32))
+        })?;
+
+    let supports_last = physical_device.supported_extensions().ext_provoking_vertex
+        && physical_device.supported_features().provoking_vertex_last;
+
+    let (device, mut queues) = Device::new(
+        physical_device,
+        DeviceCreateInfo {
+            queue_create_infos: vec![QueueCreateInfo {
+                queue_family_index,
+                ..Default::default()
+            }],
+            enabled_extensions: DeviceExtensions {
+                ext_provoking_vertex: supports_last,
+                ..Default::default()
+            },
+            enabled_features: Features {
+                provoking_vertex_last: supports_last,
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+    )
+    .ok()?;
+
+    Some((device, queues.next()?))
+}
+
+#[test]
+fn textured_draw_samples_its_layer_on_a_headless_device() {
+    let Some((device, queue)) = headless_device() else {
+        return;
+    };
+
+    let memory_allocator = Arc::new(StandardMemoryAllocator::new_default(device.clone()));
+    let allocators = Ref::new(Allocators {
+        memory_allocator: memory_allocator.clone(),
+        descriptor_set_allocator: Arc::new(StandardDescriptorSetAllocator::new(
+            device.clone(),
+            Default::default(),
+        )),
+        command_buffer_allocator: StandardCommandBufferAllocator::new(
+            device.clone(),
+            Default::default(),
+        ),
+    });
+
+    // the first frame is transparent black, so only sampling the second one draws white
+    let mut storage = TextureStorage::new(&allocators);
+    let texture = storage.allocate(4, 4, 2, false, RGBA_FORMAT);
+    let frames = [0x00, 0xFF].map(|value| RgbaImage::from_pixel(4, 4, Rgba([value; 4])));
+
+    storage
+        .enqueue_reference_update(
+            &texture,
+            TextureImage::Frames {
+                width: 4,
+                height: 4,
+                frames: frames.to_vec(),
+                animation: AnimationMetadata {
+                    animation_frames: vec![0, 1],
+                },
+            },
+            None,
+        )
+        .unwrap();
+
+    let indices = texture.unwrap_indices();
+
+    let sampler = Sampler::new(
+        device.clone(),
+        TextureParams::default().sampler_key().create_info(
+            device.physical_device().properties().max_sampler_lod_bias,
+            TextureQuality::default(),
+            None,
+        ),
+    )
+    .unwrap();
+
+    let binding = TextureBinding {
+        view: storage.get_view(indices.array),
+        sampler,
+        layer: indices.slots[1] as u32,
+    };
+
+    // the fragment shader writes colours, normals and object ids
+    let render_pass = vulkano::single_pass_renderpass!(
+        device.clone(),
+        attachments: {
+            color: {
+                format: Format::R8G8B8A8_UNORM,
+                samples: 1,
+                load_op: Clear,
+                store_op: Store,
+            },
+            normals: {
+                format: Format::R8G8B8A8_UNORM,
+                samples: 1,
+                load_op: Clear,
+                store_op: DontCare,
+            },
+            object_ids: {
+                format: Format::R32_UINT,
+                samples: 1,
+                load_op: Clear,
+                store_op: DontCare,
+            },
+        },
+        pass: {
+            color: [color, normals, object_ids],
+            depth_stencil: {},
+        },
+    )
+    .unwrap();
+
+    let attachment = |format, usage| {
+        let image = Image::new(
+            memory_allocator.clone(),
+            ImageCreateInfo {
+                format,
+                extent: [4, 4, 1],
+                usage: ImageUsage::COLOR_ATTACHMENT | usage,
+                ..Default::default()
+            },
+            AllocationCreateInfo::default(),
+        )
+        .unwrap();
+
+        ImageView::new_default(image).unwrap()
+    };
+
+    let color = attachment(Format::R8G8B8A8_UNORM, ImageUsage::TRANSFER_SRC);
+
+    let framebuffer = Framebuffer::new(
+        render_pass.clone(),
+        FramebufferCreateInfo {
+            attachments: vec![
+                color.clone(),
+                attachment(Format::R8G8B8A8_UNORM, ImageUsage::empty()),
+                attachment(Format::R32_UINT, ImageUsage::empty()),
+            ],
+            ..Default::default()
+        },
+    )
+    .unwrap();
+
+    let mut spec = textured_spec();
+    spec.object_ids = true;
+    let layout = generated_layout(&device, &spec);
+
+    let stages = compile_stages_or_fallback(&ShaderSpec::from(&spec), compile_spirv);
+    assert!(!stages.is_fallback);
+
+    let stage = |words: Vec<u32>| {
+        let module = unsafe {
+            ShaderModule::new(device.clone(), ShaderModuleCreateInfo::new(&words)).unwrap()
+        };
+
+        PipelineShaderStageCreateInfo::new(module.entry_point("main").unwrap())
+    };
+
+    let pipeline = GraphicsPipeline::new(
+        device.clone(),
+        None,
+        GraphicsPipelineCreateInfo {
+            stages: [stage(stages.vertex), stage(stages.fragment)]
+                .into_iter()
+                .collect(),
+            vertex_input_state: Some(generated_vertex_input(&spec)),
+            input_assembly_state: Some(InputAssemblyState {
+                topology: primitive_topology(spec.draw_mode),
+                ..Default::default()
+            }),
+            viewport_state: Some(ViewportState {
+                viewports: [Viewport {
+                    offset: [0.0, 0.0],
+                    extent: [4.0, 4.0],
+                    depth_range: 0.0..=1.0,
+                }]
+                .into_iter()
+                .collect(),
+                ..Default::default()
+            }),
+            rasterization_state: Some(RasterizationState::default()),
+            multisample_state: Some(MultisampleState::default()),
+            color_blend_state: Some(ColorBlendState::with_attachment_states(
+                3,
+                ColorBlendAttachmentState::default(),
+            )),
+            subpass: Some(Subpass::from(render_pass, 0).unwrap().into()),
+            ..GraphicsPipelineCreateInfo::layout(layout.clone())
+        },
+    )
+    .unwrap();
+
+    // both units sample the white frame, so the lightmap's modulate leaves it white
+    let descriptor_set = PersistentDescriptorSet::new(
+        &allocators.read().descriptor_set_allocator,
+        layout.set_layouts()[1].clone(),
+        (0..2).map(|unit| {
+            WriteDescriptorSet::image_view_sampler(
+                unit,
+                binding.view.clone(),
+                binding.sampler.clone(),
+            )
+        }),
+        [],
+    )
+    .unwrap();
+
+    // a triangle covering the whole framebuffer, with the texcoords in the middle of the texture
+    let vertices = Buffer::from_iter(
+        memory_allocator.clone(),
+        BufferCreateInfo {
+            usage: BufferUsage::VERTEX_BUFFER,
+            ..Default::default()
+        },
+        AllocationCreateInfo {
+            memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+            ..Default::default()
+        },
+        [
+            [-1.0f32, -1.0, 0.0, 0.5, 0.5],
+            [3.0, -1.0, 0.0, 0.5, 0.5],
+            [-1.0, 3.0, 0.0, 0.5, 0.5],
+        ],
+    )
+    .unwrap();
+
+    let readback = Buffer::new_slice::<u8>(
+        memory_allocator.clone(),
+        BufferCreateInfo {
+            usage: BufferUsage::TRANSFER_DST,
+            ..Default::default()
+        },
+        AllocationCreateInfo {
+            memory_type_filter: MemoryTypeFilter::PREFER_HOST
+                | MemoryTypeFilter::HOST_RANDOM_ACCESS,
+            ..Default::default()
+        },
+        4 * 4 * 4,
+    )
+    .unwrap();
+
+    let layers: TextureLayers = [binding.layer; MAX_SHADER_TEXTURE_UNITS];
+
+    let mut builder = AutoCommandBufferBuilder::primary(
+        &allocators.read().command_buffer_allocator,
+        queue.queue_family_index(),
+        CommandBufferUsage::OneTimeSubmit,
+    )
+    .unwrap();
+
+    storage.record_commands(&mut builder);
+
+    builder
+        .begin_render_pass(
+            RenderPassBeginInfo {
+                clear_values: vec![
+                    Some([0.0; 4].into()),
+                    Some([0.0; 4].into()),
+                    Some([0u32; 4].into()),
+                ],
+                ..RenderPassBeginInfo::framebuffer(framebuffer)
+            },
+            SubpassBeginInfo {
+                contents: SubpassContents::Inline,
+                ..Default::default()
+            },
+        )
+        .unwrap()
+        .bind_pipeline_graphics(pipeline)
+        .unwrap()
+        .bind_descriptor_sets(
+            PipelineBindPoint::Graphics,
+            layout.clone(),
+            1,
+            descriptor_set,
+        )
+        .unwrap()
+        .push_constants(layout.clone(), 0, TMat4::<f32>::identity())
+        .unwrap()
+        .push_constants(layout.clone(), texture_layers_offset(&spec), layers)
+        .unwrap()
+        .push_constants(
+            layout.clone(),
+            texture_layers_offset(&spec) + 16,
+            [0.5f32; 2],
+        )
+        .unwrap()
+        .push_constants(layout.clone(), texture_layers_offset(&spec) + 24, 0u32)
+        .unwrap()
+        .bind_vertex_buffers(0, vertices)
+        .unwrap()
+        .draw(3, 1, 0, 0)
+        .unwrap()
+        .end_render_pass(SubpassEndInfo::default())
+        .unwrap()
+        .copy_image_to_buffer(CopyImageToBufferInfo::image_buffer(
+            color.image().clone(),
+            readback.clone(),
+        ))
+        .unwrap();
+
+    builder
+        .build()
+        .unwrap()
+        .execute(queue)
+        .unwrap()
+        .then_signal_fence_and_flush()
+        .unwrap()
+        .wait(None)
+        .unwrap();
+
+    assert!(readback.read().unwrap().iter().all(|byte| *byte == 0xFF));
+}
+
+#[test]
+fn async_reload_swaps_once_its_upload_fence_signals() {
+    let Some((device, queue)) = headless_device() else {
+        return;
+    };
+
+    let allocators = Ref::new(Allocators {
+        memory_allocator: Arc::new(StandardMemoryAllocator::new_default(device.clone())),
+        descriptor_set_allocator: Arc::new(StandardDescriptorSetAllocator::new(
+            device.clone(),
+            Default::default(),
+        )),
+        command_buffer_allocator: StandardCommandBufferAllocator::new(
+            device.clone(),
+            Default::default(),
+        ),
+    });
+
+    let mut storage = TextureStorage::new(&allocators);
+
+    let handle = Arc::new(TextureHandle {
+        resource_name: Some("reloaded".to_owned()),
+        texture_id: 1,
+        texture: SpinLock::new(Arc::new(TextureReference::None)),
+        animation: None,
+        mipmapped: false,
+        params: SpinLock::new(TextureParams::default()),
+    });
+
+    let image = |value| TextureImage::Static {
+        image: RgbaImage::from_pixel(4, 4, Rgba([value; 4])),
+    };
+
+    storage.enqueue_handle_update(&handle, image(0x00)).unwrap();
+    let old = handle.texture.get();
+
+    storage.defer_swaps();
+    storage.enqueue_handle_update(&handle, image(0xFF)).unwrap();
+
+    let mut builder = AutoCommandBufferBuilder::primary(
+        &allocators.read().command_buffer_allocator,
+        queue.queue_family_index(),
+        CommandBufferUsage::OneTimeSubmit,
+    )
+    .unwrap();
+
+    storage.record_commands(&mut builder);
+
+    // not flushed yet, so nothing can have signaled the fence
+    let upload = Arc::new(
+        builder
+            .build()
+            .unwrap()
+            .execute(queue)
+            .unwrap()
+            .boxed_send_sync()
+            .then_signal_fence(),
+    );
+
+    assert_eq!(storage.submit_deferred_swaps(Box::new(upload.clone())), 1);
+
+    // polling doesn't wait for the upload, and draws keep sampling the old texture
+    assert_eq!(storage.poll_pending_reload().unwrap(), None);
+    assert!(Arc::ptr_eq(&handle.texture.get(), &old));
+
+    // writes to the old texture's slots wait for the swap, since they're still being sampled
+    storage
+        .enqueue_reference_update(&old, image(0x80), None)
+        .unwrap();
+    assert!(!storage.has_pending_updates());
+
+    upload.wait(None).unwrap();
+
+    assert_eq!(storage.poll_pending_reload().unwrap(), Some(1));
+    assert!(!Arc::ptr_eq(&handle.texture.get(), &old));
+    assert!(storage.has_pending_updates());
+
+    // only swapped in once
+    assert_eq!(storage.poll_pending_reload().unwrap(), None);
+}
+
+#[test]
+fn uniform_sets_last_until_their_contents_change() {
+    let mut cache = UniformSetCache::<u32>::default();
+
+    let key = |contents: &Arc<Vec<u8>>| {
+        (
+            1,
+            [(0, Arc::as_ptr(contents) as usize, 0)]
+                .into_iter()
+                .collect(),
+        )
+    };
+
+    let mut contents = Arc::new(vec![0u8; 16]);
+    let old = Arc::as_ptr(&contents);
+
+    let first = cache
+        .get_or_create(key(&contents), vec![contents.clone()], || Ok::<_, ()>(1))
+        .unwrap();
+    let second = cache
+        .get_or_create(key(&contents), vec![contents.clone()], || Ok::<_, ()>(2))
+        .unwrap();
+    assert_eq!((first, second), (1, 1));
+
+    // the cache shares the contents, so writing to them copies them instead of changing the
+    // ones its set was made from
+    Arc::make_mut(&mut contents)[0] = 1;
+    assert_ne!(Arc::as_ptr(&contents), old);
+
+    // nothing else holds the old contents anymore, so their set is dropped
+    let third = cache
+        .get_or_create(key(&contents), vec![contents.clone()], || Ok::<_, ()>(3))
+        .unwrap();
+    assert_eq!(third, 3);
+    assert_eq!(cache.len(), 1);
+}
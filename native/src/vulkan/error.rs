@@ -0,0 +1,96 @@
+use vulkano::Validated;
+use vulkano::VulkanError;
+
+use super::instance::VulkanInitError;
+use super::textures::texture_manager::TextureError;
+
+const EXCEPTION_PACKAGE: &str = "com/recursive_pineapple/mcvk/rendering/";
+
+/// The errors which can cross the JNI boundary. Each variant is thrown as its own java exception
+/// class so that the mod can tell recoverable errors (texture, pipeline, unsupported ops) apart
+/// from fatal ones (init, device lost).
+#[derive(Debug, thiserror::Error)]
+pub enum McvkError {
+    #[error("could not initialize mcvk: {0:#}")]
+    Init(anyhow::Error),
+    #[error("the vulkan device was lost: {0:#}")]
+    DeviceLost(anyhow::Error),
+    #[error("{0}")]
+    UnsupportedOperation(String),
+    #[error("texture error: {0:#}")]
+    Texture(anyhow::Error),
+    #[error("pipeline error: {0:#}")]
+    Pipeline(anyhow::Error),
+}
+
+impl McvkError {
+    pub fn init(e: impl Into<anyhow::Error>) -> Self {
+        Self::categorize(e.into(), Self::Init)
+    }
+
+    pub fn texture(e: impl Into<anyhow::Error>) -> Self {
+        Self::categorize(e.into(), Self::Texture)
+    }
+
+    pub fn pipeline(e: impl Into<anyhow::Error>) -> Self {
+        Self::categorize(e.into(), Self::Pipeline)
+    }
+
+    /// A lost device anywhere in the error chain always wins over the caller's category, since
+    /// nothing else can be recovered once it has happened.
+    fn categorize(e: anyhow::Error, fallback: fn(anyhow::Error) -> Self) -> Self {
+        if is_device_lost(&e) {
+            Self::DeviceLost(e)
+        } else {
+            fallback(e)
+        }
+    }
+
+    pub fn is_fatal(&self) -> bool {
+        matches!(self, Self::Init(_) | Self::DeviceLost(_))
+    }
+
+    pub fn java_exception_name(&self) -> &'static str {
+        match self {
+            Self::Init(_) => "MCVKInitException",
+            Self::DeviceLost(_) => "MCVKDeviceLostException",
+            Self::UnsupportedOperation(_) => "MCVKUnsupportedOperationException",
+            Self::Texture(_) => "MCVKTextureException",
+            Self::Pipeline(_) => "MCVKPipelineException",
+        }
+    }
+
+    pub fn java_exception_class(&self) -> String {
+        format!("{EXCEPTION_PACKAGE}{}", self.java_exception_name())
+    }
+}
+
+fn is_device_lost(e: &anyhow::Error) -> bool {
+    e.chain().any(|cause| {
+        matches!(
+            cause.downcast_ref::<VulkanError>(),
+            Some(VulkanError::DeviceLost)
+        ) || matches!(
+            cause.downcast_ref::<Validated<VulkanError>>(),
+            Some(Validated::Error(VulkanError::DeviceLost))
+        ) || matches!(
+            cause.downcast_ref::<VulkanInitError>(),
+            Some(VulkanInitError::VulkanError(VulkanError::DeviceLost))
+                | Some(VulkanInitError::BadInstanceParams(Validated::Error(
+                    VulkanError::DeviceLost
+                )))
+        )
+    })
+}
+
+impl From<VulkanInitError> for McvkError {
+    fn from(value: VulkanInitError) -> Self {
+        Self::init(value)
+    }
+}
+
+impl From<TextureError> for McvkError {
+    fn from(value: TextureError) -> Self {
+        Self::texture(value)
+    }
+}
@@ -0,0 +1,394 @@
+use std::collections::HashSet;
+use std::time::Duration;
+use std::time::Instant;
+
+use image::Rgba;
+use image::RgbaImage;
+use vulkano::format::Format;
+use vulkano::Validated;
+use vulkano::VulkanError;
+
+use crate::vulkan::render_manager::frame_slot;
+use crate::vulkan::render_manager::frames_to_wait_for;
+use crate::vulkan::render_manager::gl_read_region;
+use crate::vulkan::render_manager::object_id_at;
+use crate::vulkan::render_manager::readback_source;
+use crate::vulkan::render_manager::screenshot_image;
+use crate::vulkan::render_manager::set_paused;
+use crate::vulkan::render_manager::swapchain_pixels_to_image;
+use crate::vulkan::render_manager::timestamp_delta;
+use crate::vulkan::render_manager::CaptureRequests;
+use crate::vulkan::render_manager::DiagnosticsSnapshot;
+use crate::vulkan::render_manager::DrawStats;
+use crate::vulkan::render_manager::FenceSyncs;
+use crate::vulkan::render_manager::FrameLimiter;
+use crate::vulkan::render_manager::FrameStats;
+use crate::vulkan::render_manager::FrameSubmissions;
+use crate::vulkan::render_manager::Pausable;
+use crate::vulkan::render_manager::PauseState;
+use crate::vulkan::render_manager::ReadbackSource;
+use crate::vulkan::render_manager::SyncStatus;
+use crate::vulkan::render_manager::MAX_FRAMES_IN_FLIGHT;
+
+#[test]
+fn frames_are_skipped_while_paused() {
+    let mut state = PauseState::default();
+
+    assert!(state.should_render());
+
+    // the first pause has to flush the frames in flight, later ones don't
+    assert!(state.pause());
+    assert!(!state.pause());
+    assert!(!state.should_render());
+
+    // the swapchain may have gone out of date while paused
+    assert!(state.resume());
+    assert!(!state.resume());
+    assert!(state.should_render());
+}
+
+/// Logs what [`set_paused`] does to the renderer
+#[derive(Default)]
+struct PauseLog {
+    state: PauseState,
+    frame_in_progress: bool,
+    calls: Vec<&'static str>,
+}
+
+impl Pausable for PauseLog {
+    fn is_paused(&self) -> bool {
+        !self.state.should_render()
+    }
+
+    fn is_frame_in_progress(&self) -> bool {
+        self.frame_in_progress
+    }
+
+    fn present(&mut self) {
+        self.frame_in_progress = false;
+        self.calls.push("present");
+    }
+
+    fn pause(&mut self) -> Result<(), Validated<VulkanError>> {
+        self.state.pause();
+        self.calls.push("pause");
+        Ok(())
+    }
+
+    fn resume(&mut self) {
+        self.state.resume();
+        self.calls.push("resume");
+    }
+}
+
+#[test]
+fn pausing_presents_the_frame_in_progress() {
+    let mut renderer = PauseLog {
+        frame_in_progress: true,
+        ..Default::default()
+    };
+
+    // the open frame is presented with what was drawn so far before anything is paused
+    set_paused(&mut renderer, true).unwrap();
+    assert_eq!(renderer.calls, ["present", "pause"]);
+    assert!(!renderer.frame_in_progress);
+    assert!(renderer.is_paused());
+
+    // nothing is started while paused, so pausing again has nothing to present
+    renderer.calls.clear();
+    set_paused(&mut renderer, true).unwrap();
+    assert_eq!(renderer.calls, ["pause"]);
+
+    set_paused(&mut renderer, false).unwrap();
+    assert!(!renderer.is_paused());
+
+    // without an open frame there's nothing to present
+    renderer.calls.clear();
+    set_paused(&mut renderer, true).unwrap();
+    assert_eq!(renderer.calls, ["pause"]);
+}
+
+#[test]
+fn fence_signals_after_its_frame() {
+    let mut syncs = FenceSyncs::default();
+
+    let sync = syncs.create(3);
+
+    assert_ne!(sync, 0);
+    assert_eq!(syncs.frame_of(sync), Some(3));
+    assert!(!syncs.is_signaled(sync));
+
+    syncs.frame_completed(2);
+    assert!(!syncs.is_signaled(sync));
+
+    syncs.frame_completed(3);
+    assert!(syncs.is_signaled(sync));
+
+    // a late completion of an older frame doesn't un-signal anything
+    syncs.frame_completed(1);
+    assert!(syncs.is_signaled(sync));
+
+    syncs.delete(sync);
+    assert_eq!(syncs.frame_of(sync), None);
+
+    assert_eq!(
+        SyncStatus::AlreadySignaled.to_gl(),
+        gl_constants::GL_ALREADY_SIGNALED
+    );
+}
+
+#[test]
+fn diagnostics_reflect_last_frame() {
+    // the java side reads the snapshot at fixed offsets
+    assert_eq!(std::mem::size_of::<DiagnosticsSnapshot>(), 48);
+
+    let mut stats = FrameStats::default();
+    let start = Instant::now();
+
+    stats.end_frame(start);
+
+    let mut draws = DrawStats::default();
+    draws.bind_pipeline();
+    draws.draw(3);
+    draws.draw(6);
+    stats.add_draws(&draws);
+
+    // the frame isn't finished yet, so its draws don't show up
+    assert_eq!(stats.snapshot(0).draw_calls, 0);
+
+    stats.end_frame(start + Duration::from_millis(20));
+
+    let snapshot = stats.snapshot(2);
+
+    assert_eq!(snapshot.draw_calls, 2);
+    assert_eq!(snapshot.vertices, 9);
+    assert_eq!(snapshot.frames_in_flight, 2);
+    assert_eq!(snapshot.pipeline_binds, 1);
+    assert_eq!(snapshot.binds_per_draw, 0.5);
+    assert!((snapshot.fps - 50.0).abs() < 0.01);
+    assert_eq!(snapshot.gpu_frame_time_ms, -1.0);
+
+    // an empty frame resets the draw counts
+    stats.end_frame(start + Duration::from_millis(40));
+    assert_eq!(stats.snapshot(0).draw_calls, 0);
+}
+
+#[test]
+fn frames_in_flight_stay_bounded() {
+    let mut in_flight = HashSet::new();
+
+    for frame in 0..10u32 {
+        let oldest = frames_to_wait_for(in_flight.iter().copied(), MAX_FRAMES_IN_FLIGHT);
+
+        // only the oldest frames are waited on, and only as many as needed
+        if frame as usize >= MAX_FRAMES_IN_FLIGHT {
+            assert_eq!(oldest, vec![frame - MAX_FRAMES_IN_FLIGHT as u32]);
+        } else {
+            assert!(oldest.is_empty());
+        }
+
+        for frame in oldest {
+            assert!(in_flight.remove(&frame));
+        }
+
+        in_flight.insert(frame);
+        assert!(in_flight.len() <= MAX_FRAMES_IN_FLIGHT);
+    }
+
+    assert_eq!(in_flight.len(), MAX_FRAMES_IN_FLIGHT);
+}
+
+#[test]
+fn frames_in_flight_keep_their_slot() {
+    for frame in 0..10u32 {
+        // until the frame being recorded is submitted, the frames before it may still be in flight
+        let in_flight = frame.saturating_sub(MAX_FRAMES_IN_FLIGHT as u32)..frame;
+
+        for other in in_flight {
+            assert_ne!(frame_slot(other), frame_slot(frame), "{other} and {frame}");
+        }
+    }
+}
+
+#[test]
+fn readback_returns_the_latest_draw() {
+    // frame 2 has been drawn to, but frame 0 was the last one captured
+    assert_eq!(
+        readback_source(true, 2, Some(0)),
+        ReadbackSource::CaptureFrame
+    );
+
+    // once it's captured and presented, reading again doesn't need another capture
+    assert_eq!(
+        readback_source(false, 3, Some(2)),
+        ReadbackSource::LastCapture
+    );
+
+    // a stale capture is never returned
+    assert_eq!(
+        readback_source(false, 4, Some(2)),
+        ReadbackSource::Unavailable
+    );
+    assert_eq!(readback_source(false, 4, None), ReadbackSource::Unavailable);
+
+    // a 2x2 bgra frame with a red top row and a blue bottom row
+    let red = [0x00, 0x00, 0xFF, 0xFF];
+    let blue = [0xFF, 0x00, 0x00, 0xFF];
+    let pixels = [red, red, blue, blue].concat();
+
+    let frame = swapchain_pixels_to_image(&pixels, [2, 2], Format::B8G8R8A8_UNORM).unwrap();
+
+    assert_eq!(frame.get_pixel(0, 0), &Rgba([0xFF, 0x00, 0x00, 0xFF]));
+    assert_eq!(frame.get_pixel(1, 1), &Rgba([0x00, 0x00, 0xFF, 0xFF]));
+
+    // gl reads from the bottom left corner
+    let region = gl_read_region(&frame, 0, 0, 2, 2);
+
+    assert_eq!(
+        region,
+        RgbaImage::from_fn(2, 2, |_, y| if y == 0 {
+            Rgba([0x00, 0x00, 0xFF, 0xFF])
+        } else {
+            Rgba([0xFF, 0x00, 0x00, 0xFF])
+        })
+    );
+
+    // rectangles are clamped to the frame
+    assert_eq!(gl_read_region(&frame, 1, 1, 4, 4).dimensions(), (1, 1));
+    assert!(swapchain_pixels_to_image(&pixels, [2, 2], Format::R16G16B16A16_SFLOAT).is_err());
+}
+
+#[test]
+fn frames_are_only_captured_when_asked() {
+    let mut requests = CaptureRequests::default();
+
+    // nothing asked for a copy, so none is recorded
+    assert!(!requests.take_capture());
+    assert_eq!(requests.take_screenshot(), None);
+
+    // glReadPixels captures the frame it submits, and only that one
+    requests.request_readback();
+    assert!(requests.take_capture());
+    assert!(!requests.take_capture());
+
+    // a screenshot captures the next presented frame, then is saved from it
+    requests.request_screenshot("screenshot.png".into());
+    assert!(requests.take_capture());
+    assert_eq!(requests.take_screenshot(), Some("screenshot.png".into()));
+    assert!(!requests.take_capture());
+}
+
+#[test]
+fn screenshots_of_a_red_clear_are_red() {
+    // a frame cleared to red, with whatever alpha the clear left behind
+    let bgra = [0x00, 0x00, 0xFF, 0x00].repeat(4 * 4);
+    let rgba = [0xFF, 0x00, 0x00, 0x80].repeat(4 * 4);
+
+    let red = RgbaImage::from_pixel(4, 4, Rgba([0xFF, 0x00, 0x00, 0xFF]));
+
+    // the encoded bytes are already what's on screen, so sRGB and UNORM read back the same
+    for format in [Format::B8G8R8A8_SRGB, Format::B8G8R8A8_UNORM] {
+        assert_eq!(screenshot_image(&bgra, [4, 4], format).unwrap(), red);
+    }
+
+    for format in [Format::R8G8B8A8_SRGB, Format::R8G8B8A8_UNORM] {
+        assert_eq!(screenshot_image(&rgba, [4, 4], format).unwrap(), red);
+    }
+
+    assert!(screenshot_image(&rgba, [4, 4], Format::R16G16B16A16_SFLOAT).is_err());
+}
+
+#[test]
+fn flushed_frame_is_submitted_twice() {
+    let mut submissions = FrameSubmissions::default();
+
+    assert!(!submissions.begin_pass());
+    assert_eq!(submissions.present(), 1);
+
+    // the first glFlush isn't expected, so its draws are submitted with the rest of the frame
+    assert!(!submissions.begin_pass());
+    assert!(!submissions.flush());
+    assert_eq!(submissions.present(), 1);
+
+    // the next frame expects it, then the rest of the frame is submitted when it's presented
+    assert!(submissions.begin_pass());
+    assert!(submissions.flush());
+    assert!(!submissions.begin_pass());
+    assert_eq!(submissions.present(), 2);
+
+    // a frame without a glFlush still expects one, until it's been presented
+    assert!(submissions.begin_pass());
+    assert_eq!(submissions.present(), 1);
+    assert!(!submissions.begin_pass());
+}
+
+#[test]
+fn frame_limiter_paces_presents() {
+    let mut limiter = FrameLimiter::default();
+    let start = Instant::now();
+
+    for _ in 0..10 {
+        limiter.wait(Some(60));
+    }
+
+    // the first frame has nothing to wait for, the other nine wait ~16.7ms each
+    assert!(start.elapsed() >= Duration::from_millis(149));
+
+    // without a limit nothing waits
+    let start = Instant::now();
+
+    for _ in 0..10 {
+        limiter.wait(None);
+    }
+
+    assert!(start.elapsed() < Duration::from_millis(100));
+}
+
+#[test]
+fn picked_id_is_read_at_its_pixel() {
+    // a 4x3 frame where the object named 5 covers the top right pixel and 2 the one below it
+    let extent = [4, 3];
+    let mut object_ids = vec![0; 12];
+    object_ids[3] = 5;
+    object_ids[7] = 2;
+
+    // gl's origin is the bottom left corner
+    assert_eq!(object_id_at(&object_ids, extent, 3, 2), 5);
+    assert_eq!(object_id_at(&object_ids, extent, 3, 1), 2);
+    assert_eq!(object_id_at(&object_ids, extent, 0, 2), 0);
+
+    // nothing is drawn outside of the frame
+    assert_eq!(object_id_at(&object_ids, extent, 4, 0), 0);
+    assert_eq!(object_id_at(&object_ids, extent, 0, 3), 0);
+}
+
+#[test]
+fn timestamps_convert_to_nanoseconds() {
+    // one tick per nanosecond
+    assert_eq!(
+        timestamp_delta(1_000, 17_000_000, 64, 1.0),
+        Duration::from_nanos(16_999_000)
+    );
+
+    // a period of 52.08ns per tick
+    assert_eq!(
+        timestamp_delta(0, 100_000, 64, 52.08),
+        Duration::from_nanos(5_208_000)
+    );
+
+    // a 32 bit counter wrapping between the two timestamps, with garbage in the high bits
+    let start = 0xdead_0000_ffff_ff00;
+    let end = 0xbeef_0000_0000_0100;
+    assert_eq!(
+        timestamp_delta(start, end, 32, 1.0),
+        Duration::from_nanos(0x200)
+    );
+
+    // the gpu frame time is smoothed like the fps, and shows up in the overlay
+    let mut stats = FrameStats::default();
+    stats.add_gpu_frame_time(Duration::from_millis(10));
+    stats.add_gpu_frame_time(Duration::from_millis(20));
+
+    let snapshot = stats.snapshot(0);
+    assert!((snapshot.gpu_frame_time_ms - 11.0).abs() < 0.01);
+}
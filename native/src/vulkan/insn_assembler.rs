@@ -1,33 +1,67 @@
 use std::array::from_fn;
+use std::collections::HashMap;
 use std::sync::Arc;
 
 use fastset::Set;
 use nalgebra::Orthographic3;
 use nalgebra::UnitQuaternion;
+use nalgebra_glm::TMat3;
 use nalgebra_glm::TMat4;
+use nalgebra_glm::Vec2;
 use nalgebra_glm::Vec3;
 use nalgebra_glm::Vec4;
 
 use num::ToPrimitive;
-
+use vulkano::image::SampleCount;
+use vulkano::pipeline::graphics::color_blend::AttachmentBlend;
+use vulkano::pipeline::graphics::color_blend::BlendFactor;
+use vulkano::pipeline::graphics::color_blend::BlendOp;
+use vulkano::pipeline::graphics::depth_stencil::CompareOp;
+use vulkano::pipeline::graphics::rasterization::CullMode;
+use vulkano::pipeline::graphics::rasterization::FrontFace;
+
+use super::arena::FrameArena;
 use super::commands::CommandQueue;
 use super::commands::RenderCommand;
+use super::dynamic_shader::fit_push_constants;
+use super::dynamic_shader::gl_compare_op;
+use super::dynamic_shader::AlphaTest;
 use super::dynamic_shader::ColorMode;
 use super::dynamic_shader::DataSource;
+use super::dynamic_shader::DynamicPipelineDepth;
 use super::dynamic_shader::DynamicPipelinePushConstants;
 use super::dynamic_shader::DynamicPipelineRasterization;
 use super::dynamic_shader::DynamicPipelineSpec;
+use super::dynamic_shader::DynamicRasterState;
+use super::dynamic_shader::NormalScaling;
+use super::dynamic_shader::ShaderSpec;
 use super::dynamic_shader::ShaderMatrixMode;
+use super::dynamic_shader::TextureCombine;
 use super::dynamic_shader::VertexBufferLayout;
 use super::dynamic_shader::VertexInputSpec;
 use super::dynamic_shader::VertexInputType;
+use super::dynamic_shader::MAX_CLIP_PLANES;
+use super::dynamic_shader::MAX_SHADER_TEXTURE_UNITS;
+use super::dynamic_shader::MIN_MAX_PUSH_CONSTANTS_SIZE;
+use super::sandbox::CullFace;
+use super::sandbox::DepthFunc;
 use super::sandbox::GLDataType;
 use super::sandbox::MatrixMode;
 use super::sandbox::OrthoData;
 use super::sandbox::PointerArrayType;
+use super::sandbox::PolygonMode;
+use super::sandbox::ProvokingVertex;
 use super::sandbox::RenderInstruction;
+use super::sandbox::ScissorRect;
+use super::sandbox::TextureEnvMode;
+use super::sandbox::Winding;
+use super::sandbox_jni::client_arrays::compact_array;
+use super::shaders::programs::GlProgramId;
+use super::shaders::programs::LinkedProgram;
+use super::shaders::programs::UniformValues;
 use super::sandbox_jni::jni_prelude::DrawMode;
-use super::textures::lookup::TextureLookup;
+use super::textures::texture_manager::TextureManager;
+use super::utils::Ref;
 
 #[derive(Debug)]
 struct MatrixStack {
@@ -45,7 +79,7 @@ impl MatrixStack {
 
     pub fn push(&mut self) {
         self.top += 1;
-        if self.top > self.matrices.len() {
+        if self.top >= self.matrices.len() {
             self.matrices.push(self.matrices[self.top - 1]);
         } else {
             self.matrices[self.top] = self.matrices[self.top - 1];
@@ -100,6 +134,14 @@ struct ClientArray {
     pub data_type: GLDataType,
     pub element_count: u8,
     pub data: Option<Arc<Vec<u8>>>,
+    /// Only generic attribute arrays choose this, the fixed-function colour arrays are always
+    /// normalized
+    pub normalized: bool,
+    /// The buffer object the array points into, which `data` is read from whenever a draw is
+    /// assembled
+    pub buffer: Option<u32>,
+    pub offset: usize,
+    pub stride: usize,
 }
 
 impl ClientArray {
@@ -110,8 +152,156 @@ impl ClientArray {
             data_type: GLDataType::U8,
             element_count: 0,
             data: None,
+            normalized: false,
+            buffer: None,
+            offset: 0,
+            stride: 0,
         }
     }
+
+    /// Reads the first `max_vertices` vectors of an array which points into a buffer object from
+    /// the buffer's current contents. Arrays in client memory were already read when their
+    /// pointer was set.
+    fn resolve(&mut self, buffers: &HashMap<u32, Arc<Vec<u8>>>, max_vertices: usize) {
+        let Some(buffer) = self.buffer else {
+            return;
+        };
+
+        let Some(source) = buffers
+            .get(&buffer)
+            .and_then(|contents| contents.get(self.offset..))
+        else {
+            tracing::warn!(
+                what = "a pointer array points at a deleted buffer, or past the end of its buffer",
+                buffer,
+                offset = self.offset
+            );
+            self.data = None;
+            self.vertex_count = 0;
+            return;
+        };
+
+        let vec_byte_size = self.element_count as usize * self.data_type.size() as usize;
+        let stride = if self.stride > 0 {
+            self.stride
+        } else {
+            vec_byte_size
+        };
+
+        // the rest of the buffer isn't read by the draw, so it isn't copied
+        let len = match max_vertices {
+            0 => 0,
+            n => source.len().min(stride * (n - 1) + vec_byte_size),
+        };
+
+        let (data, vec_count) = compact_array(
+            &source[..len],
+            self.element_count as usize,
+            self.stride,
+            self.data_type,
+        );
+
+        self.data = Some(Arc::new(data));
+        self.vertex_count = vec_count as u32;
+    }
+}
+
+/// The current attributes when glVertex was called
+#[derive(Debug, Clone)]
+struct ImmediateVertex {
+    pub position: Vec4,
+    pub color: Vec4,
+    pub texcoord: Vec4,
+    pub normal: Vec3,
+}
+
+/// The vertices emitted between glBegin and glEnd
+#[derive(Debug)]
+struct ImmediateBatch {
+    pub mode: DrawMode,
+    pub vertices: Vec<ImmediateVertex>,
+}
+
+impl ImmediateBatch {
+    /// Converts one attribute of every vertex into a tightly packed f32 client array
+    fn client_array<const N: usize>(
+        &self,
+        attribute: impl Fn(&ImmediateVertex) -> [f32; N],
+    ) -> ClientArray {
+        let data = self
+            .vertices
+            .iter()
+            .flat_map(&attribute)
+            .flat_map(f32::to_ne_bytes)
+            .collect::<Vec<u8>>();
+
+        ClientArray {
+            enabled: true,
+            vertex_count: self.vertices.len() as u32,
+            data_type: GLDataType::F32,
+            element_count: N as u8,
+            normalized: false,
+            data: Some(Arc::new(data)),
+            buffer: None,
+            offset: 0,
+            stride: 0,
+        }
+    }
+}
+
+/// Reads the first `vertex_count` (d)vec2s of a texcoord array as seen through `matrix`, as
+/// tightly packed f32 pairs
+fn transform_texcoords(array: &ClientArray, vertex_count: usize, matrix: &TMat4<f32>) -> Vec<f32> {
+    let src_byte_size = (array.data_type.size() * 2) as usize;
+    let src = array.data.as_ref().unwrap();
+
+    let mut uvs = Vec::with_capacity(vertex_count * 2);
+
+    for vertex_idx in 0..vertex_count {
+        let src_start = vertex_idx * src_byte_size;
+        let src = &src[src_start..src_start + src_byte_size];
+
+        let uv = match array.data_type {
+            GLDataType::F32 => {
+                let src = unsafe { src.align_to::<f32>().1 };
+
+                [src[0], src[1]]
+            }
+            GLDataType::F64 => {
+                let src = unsafe { src.align_to::<f64>().1 };
+
+                [src[0] as f32, src[1] as f32]
+            }
+            _ => panic!(),
+        };
+
+        let uv = matrix * Vec4::new(uv[0], uv[1], 0.0, 1.0);
+
+        uvs.extend_from_slice(&[uv.x / uv.w, uv.y / uv.w]);
+    }
+
+    uvs
+}
+
+/// Reads the first `count` GL indices, or None if there aren't that many
+pub fn decode_indices(index_type: GLDataType, count: usize, data: &[u8]) -> Option<Vec<u32>> {
+    let size = match index_type {
+        GLDataType::U8 | GLDataType::U16 | GLDataType::U32 => index_type.size() as usize,
+        _ => return None,
+    };
+
+    let data = data.get(..count * size)?;
+
+    Some(
+        data.chunks_exact(size)
+            .map(|i| match i {
+                [i] => *i as u32,
+                [a, b] => u16::from_ne_bytes([*a, *b]) as u32,
+                [a, b, c, d] => u32::from_ne_bytes([*a, *b, *c, *d]),
+                _ => unreachable!(),
+            })
+            .collect(),
+    )
 }
 
 const MODELVIEW_MATRIX_IDX: usize = 0;
@@ -178,6 +368,36 @@ fn get_client_array_name(index: usize) -> &'static str {
     }
 }
 
+/// The GL_RESCALE_NORMAL factor: the reciprocal of the length of the third row of the inverse
+/// modelview's upper 3x3, which undoes a uniform scale once normals go through the normal matrix.
+pub fn normal_rescale_factor(modelview: &TMat4<f32>) -> f32 {
+    let Some(inverse) = modelview.fixed_view::<3, 3>(0, 0).clone_owned().try_inverse() else {
+        return 1.0;
+    };
+
+    1.0 / inverse.row(2).norm()
+}
+
+/// The matrix which lit normals are transformed by: the inverse transpose of the modelview's upper
+/// 3x3, so that non-uniform scales don't skew them. A singular modelview leaves them as-is.
+pub fn normal_matrix(modelview: &TMat4<f32>) -> TMat3<f32> {
+    modelview
+        .fixed_view::<3, 3>(0, 0)
+        .clone_owned()
+        .try_inverse()
+        .map(|inverse| inverse.transpose())
+        .unwrap_or_else(TMat3::identity)
+}
+
+/// Moves a plane equation through `matrix`, the inverse transpose of which is what maps planes
+/// between spaces. A singular matrix leaves the plane as-is.
+pub fn transform_plane(matrix: &TMat4<f32>, plane: &Vec4) -> Vec4 {
+    matrix
+        .try_inverse()
+        .map(|inverse| inverse.transpose() * plane)
+        .unwrap_or(*plane)
+}
+
 impl RenderInstruction {
     pub fn is_matrix_mutation(&self) -> bool {
         match self {
@@ -191,12 +411,30 @@ impl RenderInstruction {
             _ => false,
         }
     }
+
+    /// Client arrays and buffer objects, which GL never compiles into display lists
+    pub fn is_client_state(&self) -> bool {
+        matches!(
+            self,
+            RenderInstruction::SetClientState { .. }
+                | RenderInstruction::SetPointer { .. }
+                | RenderInstruction::SetAttribPointer { .. }
+                | RenderInstruction::SetAttribArrayEnabled { .. }
+                | RenderInstruction::SetClientActiveTextureUnit(_)
+                | RenderInstruction::BufferData { .. }
+                | RenderInstruction::BufferSubData { .. }
+                | RenderInstruction::DeleteBuffer(_)
+        )
+    }
 }
 
 #[derive(Debug)]
 struct VertexBufferSlot<'a> {
     pub array: &'a ClientArray,
-    pub array_type: PointerArrayType,
+    /// None for generic attribute arrays. The texindex slot is derived from the texcoord array.
+    pub input_type: Option<VertexInputType>,
+    /// Whether integer data is mapped into [0, 1] or [-1, 1]
+    pub normalized: bool,
     pub data_type: GLDataType,
     pub buffer_offset: u8,
 }
@@ -204,23 +442,180 @@ struct VertexBufferSlot<'a> {
 #[derive(Debug)]
 struct TextureUnit {
     pub bound_texture: Option<i32>,
+    /// GL_TEXTURE_2D is enabled per unit. GL_TEXTURE_RECTANGLE shares the flag, since a unit only
+    /// ever samples one texture.
+    pub enabled: bool,
+    /// Whether the bound texture was bound to GL_TEXTURE_RECTANGLE
+    pub rectangle: bool,
+    /// Each unit has its own GL_TEXTURE matrix, selected with glActiveTexture
+    pub matrix: MatrixStack,
+    /// glMultiTexCoord. Unit 0 uses [`RenderInsnAssembler::texcoord`] instead.
+    pub texcoord: Vec4,
+    /// GL_TEXTURE_ENV_MODE, how this unit combines with the units before it
+    pub env_mode: TextureEnvMode,
 }
 
 impl TextureUnit {
     pub fn new() -> Self {
         Self {
             bound_texture: None,
+            enabled: false,
+            rectangle: false,
+            matrix: MatrixStack::new(),
+            texcoord: [0.0, 0.0, 0.0, 1.0].into(),
+            env_mode: TextureEnvMode::Modulate,
         }
     }
+
+    /// The texture this unit samples from, if it's enabled
+    pub fn sampled_texture(&self) -> Option<i32> {
+        self.bound_texture.filter(|_| self.enabled)
+    }
+}
+
+/// The most texture units glActiveTexture accepts. Devices can support fewer, see
+/// [`RenderInsnAssembler::max_texture_units`].
+pub const MAX_TEXTURE_UNITS: usize = 16;
+
+/// Textured pipelines always sample the base and lightmap units, so that toggling the lightmap
+/// only swaps its texture for the white dummy instead of needing another pipeline
+pub const BASE_TEXTURE_UNIT: usize = 0;
+pub const LIGHTMAP_TEXTURE_UNIT: usize = 1;
+
+/// The targets whose glEnable turns texturing on for the active unit
+fn is_texture_target(target: u32) -> bool {
+    target == gl_constants::GL_TEXTURE_2D || target == gl_constants::GL_TEXTURE_RECTANGLE
+}
+
+fn is_clip_plane(cap: u32) -> bool {
+    (gl_constants::GL_CLIP_PLANE0..gl_constants::GL_CLIP_PLANE0 + MAX_CLIP_PLANES as u32)
+        .contains(&cap)
 }
 
-const MAX_TEXTURE_UNITS: usize = 16;
+/// The capabilities glGetBooleanv can report on
+const QUERYABLE_CAPABILITIES: &[u32] = &[
+    gl_constants::GL_ALPHA_TEST,
+    gl_constants::GL_BLEND,
+    gl_constants::GL_CLIP_PLANE0,
+    gl_constants::GL_CLIP_PLANE1,
+    gl_constants::GL_CLIP_PLANE2,
+    gl_constants::GL_CLIP_PLANE3,
+    gl_constants::GL_CLIP_PLANE4,
+    gl_constants::GL_CLIP_PLANE5,
+    gl_constants::GL_COLOR_MATERIAL,
+    gl_constants::GL_CULL_FACE,
+    gl_constants::GL_DEPTH_TEST,
+    gl_constants::GL_FOG,
+    gl_constants::GL_LIGHTING,
+    gl_constants::GL_LIGHT0,
+    gl_constants::GL_LIGHT1,
+    gl_constants::GL_MULTISAMPLE,
+    gl_constants::GL_NORMALIZE,
+    gl_constants::GL_POLYGON_OFFSET_FILL,
+    gl_constants::GL_RESCALE_NORMAL,
+    gl_constants::GL_SCISSOR_TEST,
+    gl_constants::GL_STENCIL_TEST,
+    gl_constants::GL_TEXTURE_2D,
+];
+
+/// The glPushAttrib groups which are saved. Any other bits are ignored.
+const SUPPORTED_ATTRIB_BITS: u32 = gl_constants::GL_CURRENT_BIT
+    | gl_constants::GL_ENABLE_BIT
+    | gl_constants::GL_COLOR_BUFFER_BIT
+    | gl_constants::GL_DEPTH_BUFFER_BIT
+    | gl_constants::GL_POLYGON_BIT
+    | gl_constants::GL_SCISSOR_BIT
+    | gl_constants::GL_TEXTURE_BIT
+    | gl_constants::GL_TRANSFORM_BIT
+    | gl_constants::GL_LIGHTING_BIT;
+
+/// The capabilities an attribute group saves along with the rest of its state. GL_ENABLE_BIT saves
+/// every capability, so it isn't listed.
+const ATTRIB_CAPABILITIES: &[(u32, &[u32])] = &[
+    (
+        gl_constants::GL_COLOR_BUFFER_BIT,
+        &[gl_constants::GL_ALPHA_TEST, gl_constants::GL_BLEND],
+    ),
+    (
+        gl_constants::GL_DEPTH_BUFFER_BIT,
+        &[gl_constants::GL_DEPTH_TEST],
+    ),
+    (
+        gl_constants::GL_POLYGON_BIT,
+        &[
+            gl_constants::GL_CULL_FACE,
+            gl_constants::GL_POLYGON_OFFSET_FILL,
+        ],
+    ),
+    (
+        gl_constants::GL_SCISSOR_BIT,
+        &[gl_constants::GL_SCISSOR_TEST],
+    ),
+    (
+        gl_constants::GL_TRANSFORM_BIT,
+        &[
+            gl_constants::GL_NORMALIZE,
+            gl_constants::GL_RESCALE_NORMAL,
+            gl_constants::GL_CLIP_PLANE0,
+            gl_constants::GL_CLIP_PLANE1,
+            gl_constants::GL_CLIP_PLANE2,
+            gl_constants::GL_CLIP_PLANE3,
+            gl_constants::GL_CLIP_PLANE4,
+            gl_constants::GL_CLIP_PLANE5,
+        ],
+    ),
+    (
+        gl_constants::GL_LIGHTING_BIT,
+        &[
+            gl_constants::GL_LIGHTING,
+            gl_constants::GL_COLOR_MATERIAL,
+            gl_constants::GL_LIGHT0,
+            gl_constants::GL_LIGHT1,
+            gl_constants::GL_LIGHT2,
+            gl_constants::GL_LIGHT3,
+            gl_constants::GL_LIGHT4,
+            gl_constants::GL_LIGHT5,
+            gl_constants::GL_LIGHT6,
+            gl_constants::GL_LIGHT7,
+        ],
+    ),
+];
+
+/// GL_MAX_ATTRIB_STACK_DEPTH
+const MAX_ATTRIB_STACK_DEPTH: usize = 16;
+
+/// The state saved by one glPushAttrib. Each group is only Some if its bit was in the mask.
+#[derive(Debug, Default)]
+struct AttribSnapshot {
+    /// GL_ENABLE_BIT: every capability, and each unit's GL_TEXTURE_2D
+    enabled: Option<(Set, [bool; MAX_TEXTURE_UNITS])>,
+    /// The capabilities the other groups save, see [`ATTRIB_CAPABILITIES`]
+    capabilities: Vec<(u32, bool)>,
+    /// GL_CURRENT_BIT: the colour, texcoord and normal
+    current: Option<(Vec4, Vec4, Vec3)>,
+    /// GL_COLOR_BUFFER_BIT: the alpha test, blend func, blend colour and colour mask
+    color_buffer: Option<(AlphaTest, (BlendFactor, BlendFactor), Vec4, [bool; 4])>,
+    /// GL_DEPTH_BUFFER_BIT: the depth func and mask
+    depth_buffer: Option<(DepthFunc, bool)>,
+    /// GL_POLYGON_BIT: the cull face, front face and polygon mode
+    polygon: Option<(CullFace, Winding, PolygonMode)>,
+    /// GL_SCISSOR_BIT: the glScissor rectangle
+    scissor: Option<Option<ScissorRect>>,
+    /// GL_TEXTURE_BIT: the active unit, and each unit's binding and env mode
+    texture: Option<(
+        usize,
+        [(Option<i32>, bool, TextureEnvMode); MAX_TEXTURE_UNITS],
+    )>,
+    /// GL_TRANSFORM_BIT: the matrix mode and clip plane equations
+    transform: Option<(usize, [Vec4; MAX_CLIP_PLANES])>,
+}
 
 #[derive(Debug)]
 pub struct RenderInsnAssembler {
     active_flags: Set,
 
     active_matrix: usize,
+    /// The GL_TEXTURE entry is unused since every texture unit has its own stack
     matrix_stacks: [MatrixStack; 4],
     active_mvp_cache: Option<TMat4<f32>>,
 
@@ -229,17 +624,89 @@ pub struct RenderInsnAssembler {
 
     active_color: Vec4,
     texcoord: Vec4,
+    normal: Vec3,
+
+    /// Some while between glBegin and glEnd
+    immediate: Option<ImmediateBatch>,
+
+    provoking_vertex: ProvokingVertex,
+    polygon_mode: PolygonMode,
+    /// Only used while GL_CULL_FACE is enabled
+    cull_face: CullFace,
+    front_face: Winding,
+
+    depth_func: DepthFunc,
+    depth_write: bool,
+    /// Only used while GL_ALPHA_TEST is enabled
+    alpha_test: AlphaTest,
+    /// Only tracked for glGet, the pipeline always writes every channel
+    color_mask: [bool; 4],
+    /// glBlendFunc, only used while GL_BLEND is enabled
+    blend_func: (BlendFactor, BlendFactor),
+    /// glBlendColor, for the constant blend factors
+    blend_color: Vec4,
+    /// glClipPlane equations in eye space, only used while their GL_CLIP_PLANEi is enabled
+    clip_planes: [Vec4; MAX_CLIP_PLANES],
+
+    /// Replaces the generated shaders while set
+    active_program: Option<Arc<LinkedProgram>>,
+    /// Uniform values are per program and survive switching between them, until the program is
+    /// relinked. Keyed by program id, along with the link generation the values belong to.
+    program_uniforms: HashMap<GlProgramId, (u32, UniformValues)>,
+
+    /// Each entry is already intersected with the one below it
+    scissor_stack: Vec<ScissorRect>,
+    /// glScissor, None covers the whole framebuffer
+    gl_scissor: Option<ScissorRect>,
+    /// glPushName, see [`RenderInsnAssembler::object_id`]
+    name_stack: Vec<u32>,
+    /// glPushAttrib
+    attrib_stack: Vec<AttribSnapshot>,
 
     client_arrays: [ClientArray; 8],
+    /// glClientActiveTexture
+    client_active_unit: usize,
+    /// The lightmap unit's texcoord array, set while it's the client active unit. It replaces the
+    /// lightmap's glMultiTexCoord while it's enabled.
+    lightmap_texcoords: ClientArray,
+    /// glVertexAttribPointer arrays, keyed by attribute index
+    generic_arrays: HashMap<u32, ClientArray>,
+    /// The contents of the buffer objects, keyed by buffer id
+    buffer_objects: HashMap<u32, Arc<Vec<u8>>>,
 
     pub commands: CommandQueue,
-    pub texture_lookup: Arc<TextureLookup>,
+    /// Where the atlas lookup is read from, since it's replaced whenever the atlases are
+    /// reloaded. None in tests, where uvs aren't remapped.
+    pub textures: Option<Ref<TextureManager>>,
+    /// Where the draw buffers come from, which should be shared with the recorder so that it can
+    /// hand them back
+    pub arena: FrameArena,
+    /// The sample count of the render pass, which every pipeline is rasterized at. A pipeline's
+    /// sample count has to match its subpass, so disabling GL_MULTISAMPLE can't drop a draw to a
+    /// single sample; the flag is only kept for glIsEnabled.
+    pub framebuffer_samples: SampleCount,
+    /// How many texture units are advertised through GL_MAX_TEXTURE_IMAGE_UNITS, from
+    /// [`Devices::max_texture_units`](super::devices::Devices::max_texture_units). Units past it
+    /// are rejected by glActiveTexture.
+    pub max_texture_units: usize,
+    /// The device's maxPushConstantsSize. Draws whose push constants wouldn't fit read their
+    /// matrices and clip planes from a uniform buffer instead, see [`fit_push_constants`].
+    pub max_push_constants_size: u32,
+    /// Whether the device can write gl_ClipDistance. Without it GL_CLIP_PLANEi are ignored.
+    pub supports_clip_distance: bool,
+    /// Mirrors [`WindowSettings::writes_object_ids`](super::swapchain::WindowSettings::writes_object_ids).
+    /// Draws only carry their glLoadName while the render pass has somewhere to write it.
+    pub object_picking: bool,
 }
 
 impl RenderInsnAssembler {
-    pub fn new(commands: CommandQueue, texture_lookup: Arc<TextureLookup>) -> Self {
+    pub fn new(commands: CommandQueue, textures: Option<Ref<TextureManager>>) -> Self {
+        let mut active_flags = Set::with_capacity(64);
+        // GL_MULTISAMPLE starts enabled
+        active_flags.insert(gl_constants::GL_MULTISAMPLE as usize);
+
         Self {
-            active_flags: Set::with_capacity(64),
+            active_flags,
 
             active_matrix: 0,
             matrix_stacks: from_fn(|_| MatrixStack::new()),
@@ -250,11 +717,47 @@ impl RenderInsnAssembler {
 
             active_color: [1.0; 4].into(),
             texcoord: [0.0; 4].into(),
+            normal: [0.0, 0.0, 1.0].into(),
+
+            immediate: None,
+
+            provoking_vertex: ProvokingVertex::default(),
+            polygon_mode: PolygonMode::default(),
+            cull_face: CullFace::default(),
+            front_face: Winding::default(),
+
+            depth_func: DepthFunc::default(),
+            depth_write: true,
+            alpha_test: AlphaTest {
+                func: CompareOp::Always,
+                ref_value: 0.0,
+            },
+            color_mask: [true; 4],
+            blend_func: (BlendFactor::One, BlendFactor::Zero),
+            blend_color: Vec4::zeros(),
+            clip_planes: [Vec4::zeros(); MAX_CLIP_PLANES],
+            active_program: None,
+            program_uniforms: HashMap::new(),
+
+            scissor_stack: Vec::new(),
+            gl_scissor: None,
+            name_stack: Vec::new(),
+            attrib_stack: Vec::new(),
 
             client_arrays: from_fn(|_| ClientArray::new()),
+            client_active_unit: BASE_TEXTURE_UNIT,
+            lightmap_texcoords: ClientArray::new(),
+            generic_arrays: HashMap::new(),
+            buffer_objects: HashMap::new(),
 
             commands,
-            texture_lookup,
+            textures,
+            arena: FrameArena::new(),
+            framebuffer_samples: SampleCount::Sample1,
+            max_texture_units: MAX_TEXTURE_UNITS,
+            max_push_constants_size: MIN_MAX_PUSH_CONSTANTS_SIZE,
+            supports_clip_distance: true,
+            object_picking: false,
         }
     }
 
@@ -272,27 +775,64 @@ impl RenderInsnAssembler {
                     self.active_matrix = get_matrix_index(mode);
                 }
                 RenderInstruction::PushMatrix => {
-                    self.matrix_stacks[self.active_matrix].push();
+                    self.get_matrix_stack().push();
                 }
                 RenderInstruction::PopMatrix => {
-                    self.matrix_stacks[self.active_matrix].pop();
+                    self.get_matrix_stack().pop();
                 }
                 RenderInstruction::LoadIdentity => {
-                    self.matrix_stacks[self.active_matrix].load_identity();
+                    self.get_matrix_stack().load_identity();
                 }
                 RenderInstruction::Ortho { data } => {
-                    self.matrix_stacks[self.active_matrix].ortho(&*data);
+                    self.get_matrix_stack().ortho(&*data);
                 }
                 RenderInstruction::Translate { delta } => {
-                    self.matrix_stacks[self.active_matrix].translate(delta);
+                    self.get_matrix_stack().translate(delta);
                 }
                 RenderInstruction::Rotate { angle, axis } => {
-                    self.matrix_stacks[self.active_matrix].rotate(axis, *angle);
+                    self.get_matrix_stack().rotate(axis, *angle);
                 }
                 RenderInstruction::Scale { scale } => {
-                    self.matrix_stacks[self.active_matrix].scale(scale);
+                    self.get_matrix_stack().scale(scale);
                 }
 
+                RenderInstruction::Enable(param) if is_texture_target(*param as u32) => {
+                    self.texture_units[self.active_unit].enabled = true;
+                }
+                RenderInstruction::Disable(param) if is_texture_target(*param as u32) => {
+                    self.texture_units[self.active_unit].enabled = false;
+                }
+                RenderInstruction::Enable(param)
+                    if *param as u32 == gl_constants::GL_SCISSOR_TEST =>
+                {
+                    self.active_flags.insert(*param as usize);
+                    self.update_scissor();
+                }
+                RenderInstruction::Disable(param)
+                    if *param as u32 == gl_constants::GL_SCISSOR_TEST =>
+                {
+                    self.active_flags.remove(&(*param as usize));
+                    self.update_scissor();
+                }
+                RenderInstruction::Enable(param)
+                    if is_clip_plane(*param as u32) && !self.supports_clip_distance =>
+                {
+                    tracing::warn!(
+                        what = "the device does not support clip distances; the clip plane will be ignored",
+                        plane = *param as u32 - gl_constants::GL_CLIP_PLANE0
+                    );
+                    self.active_flags.insert(*param as usize);
+                }
+                RenderInstruction::Disable(param)
+                    if *param as u32 == gl_constants::GL_MULTISAMPLE
+                        && self.framebuffer_samples != SampleCount::Sample1 =>
+                {
+                    tracing::warn!(
+                        what = "draws are always rasterized at the render pass' sample count; disabling GL_MULTISAMPLE will be ignored",
+                        samples = ?self.framebuffer_samples,
+                    );
+                    self.active_flags.remove(&(*param as usize));
+                }
                 RenderInstruction::Enable(param) => {
                     self.active_flags.insert(*param as usize);
                 }
@@ -305,7 +845,9 @@ impl RenderInsnAssembler {
                     enabled,
                     array_type,
                 } => {
-                    self.client_arrays[get_client_array_index(array_type)].enabled = *enabled;
+                    if let Some(array) = self.client_array_mut(array_type) {
+                        array.enabled = *enabled;
+                    }
                 }
                 RenderInstruction::SetPointer {
                     size,
@@ -313,49 +855,437 @@ impl RenderInsnAssembler {
                     array_type,
                     item_type,
                     data,
+                    buffer,
+                    offset,
+                    stride,
                 } => {
-                    let array = &mut self.client_arrays[get_client_array_index(array_type)];
+                    let Some(array) = self.client_array_mut(array_type) else {
+                        continue;
+                    };
                     array.element_count = *size;
                     array.vertex_count = *vec_count;
                     array.data_type = item_type.clone();
                     array.data = Some(data.clone());
+                    array.buffer = *buffer;
+                    array.offset = *offset as usize;
+                    array.stride = *stride as usize;
+                }
+                RenderInstruction::SetAttribPointer {
+                    index,
+                    vec_count,
+                    item_type,
+                    normalized,
+                    data,
+                    size,
+                    buffer,
+                    offset,
+                    stride,
+                } => {
+                    let array = self
+                        .generic_arrays
+                        .entry(*index)
+                        .or_insert_with(ClientArray::new);
+                    array.element_count = *size;
+                    array.vertex_count = *vec_count;
+                    array.data_type = *item_type;
+                    array.data = Some(data.clone());
+                    array.normalized = *normalized;
+                    array.buffer = *buffer;
+                    array.offset = *offset as usize;
+                    array.stride = *stride as usize;
+                }
+                RenderInstruction::BufferData { buffer, data } => {
+                    self.buffer_objects.insert(*buffer, data.clone());
+                }
+                RenderInstruction::BufferSubData {
+                    buffer,
+                    offset,
+                    data,
+                } => {
+                    self.buffer_sub_data(*buffer, *offset as usize, data);
+                }
+                RenderInstruction::DeleteBuffer(buffer) => {
+                    self.buffer_objects.remove(buffer);
+                }
+                RenderInstruction::SetAttribArrayEnabled { index, enabled } => {
+                    self.generic_arrays
+                        .entry(*index)
+                        .or_insert_with(ClientArray::new)
+                        .enabled = *enabled;
                 }
                 RenderInstruction::DrawArrays { mode, first, count } => {
                     self.draw_arrays(mode.clone(), *first, *count);
                 }
+                RenderInstruction::DrawElements {
+                    mode,
+                    count,
+                    index_type,
+                    indices,
+                } => {
+                    self.draw_elements(mode.clone(), *count, *index_type, indices);
+                }
 
                 RenderInstruction::SetActiveTextureUnit(unit) => {
-                    self.active_unit = *unit;
+                    if *unit < self.max_texture_units {
+                        self.active_unit = *unit;
+                    } else {
+                        tracing::warn!(
+                            what = "glActiveTexture was called with a texture unit past the number the device supports",
+                            unit,
+                            max = self.max_texture_units
+                        );
+                    }
                 }
-                RenderInstruction::BindTexture(id) => {
-                    if *id == 0 {
-                        self.texture_units[self.active_unit].bound_texture = None;
+                RenderInstruction::SetClientActiveTextureUnit(unit) => {
+                    if *unit < self.max_texture_units {
+                        self.client_active_unit = *unit;
                     } else {
-                        self.texture_units[self.active_unit].bound_texture = Some(*id);
+                        tracing::warn!(
+                            what = "glClientActiveTexture was called with a texture unit past the number the device supports",
+                            unit,
+                            max = self.max_texture_units
+                        );
                     }
                 }
+                RenderInstruction::BindTexture(id) => {
+                    let unit = &mut self.texture_units[self.active_unit];
+
+                    unit.bound_texture = Some(*id).filter(|id| *id != 0);
+                    unit.rectangle = false;
+                }
+                RenderInstruction::BindTextureRectangle(id) => {
+                    let unit = &mut self.texture_units[self.active_unit];
+
+                    unit.bound_texture = Some(*id).filter(|id| *id != 0);
+                    unit.rectangle = unit.bound_texture.is_some();
+                }
+                RenderInstruction::SetTextureEnvMode(mode) => {
+                    self.texture_units[self.active_unit].env_mode = *mode;
+                }
 
                 RenderInstruction::TexCoord(coord) => {
                     self.texcoord = coord.clone();
                 }
+                RenderInstruction::MultiTexCoord { unit, coord } => match *unit {
+                    BASE_TEXTURE_UNIT => self.texcoord = *coord,
+                    unit if unit < self.max_texture_units => {
+                        self.texture_units[unit].texcoord = *coord
+                    }
+                    _ => {
+                        tracing::warn!(
+                            what = "glMultiTexCoord was called with an invalid texture unit",
+                            unit
+                        );
+                    }
+                },
 
                 RenderInstruction::SetColor(color) => {
                     self.active_color = color.clone();
                 }
 
-                RenderInstruction::Begin(mode) => todo!(),
-                RenderInstruction::Vertex(v) => todo!(),
-                RenderInstruction::End => todo!(),
+                RenderInstruction::Normal(normal) => {
+                    self.normal = *normal;
+                }
+
+                RenderInstruction::Begin(mode) => {
+                    if self.immediate.is_some() {
+                        tracing::warn!(
+                            what = "glBegin was called before the previous glBegin was ended; this is invalid and the call has been ignored"
+                        );
+                    } else {
+                        self.immediate = Some(ImmediateBatch {
+                            mode: mode.clone(),
+                            vertices: Vec::new(),
+                        });
+                    }
+                }
+                RenderInstruction::Vertex(position) => {
+                    let vertex = ImmediateVertex {
+                        position: *position,
+                        color: self.active_color,
+                        texcoord: self.texcoord,
+                        normal: self.normal,
+                    };
+
+                    if let Some(batch) = self.immediate.as_mut() {
+                        batch.vertices.push(vertex);
+                    } else {
+                        tracing::warn!(
+                            what = "glVertex was called outside of glBegin/glEnd; this is invalid and the call has been ignored"
+                        );
+                    }
+                }
+                RenderInstruction::End => {
+                    if let Some(batch) = self.immediate.take() {
+                        self.draw_immediate(batch);
+                    } else {
+                        tracing::warn!(
+                            what = "glEnd was called without a matching glBegin; this is invalid and the call has been ignored"
+                        );
+                    }
+                }
+
+                RenderInstruction::AlphaFunc { func, ref_value } => {
+                    self.alpha_test = AlphaTest {
+                        func: *func,
+                        ref_value: ref_value.clamp(0.0, 1.0),
+                    };
+                }
+
+                RenderInstruction::ClipPlane { plane, equation } => {
+                    // planes are kept in eye space, as of the modelview when they were specified
+                    self.clip_planes[*plane] =
+                        transform_plane(self.matrix_stacks[MODELVIEW_MATRIX_IDX].get(), equation);
+                }
 
-                RenderInstruction::AlphaFunc => todo!(),
+                RenderInstruction::ProvokingVertex(mode) => {
+                    self.provoking_vertex = *mode;
+                }
+                RenderInstruction::PolygonMode(mode) => {
+                    self.polygon_mode = *mode;
+                }
+                RenderInstruction::CullFace(face) => {
+                    self.cull_face = *face;
+                }
+                RenderInstruction::FrontFace(winding) => {
+                    self.front_face = *winding;
+                }
+                RenderInstruction::DepthFunc(func) => {
+                    self.depth_func = *func;
+                }
+                RenderInstruction::DepthMask(write) => {
+                    self.depth_write = *write;
+                }
+                RenderInstruction::ColorMask(mask) => {
+                    self.color_mask = *mask;
+                }
+                RenderInstruction::BlendColor(color) => {
+                    self.blend_color = *color;
+                }
+                RenderInstruction::BlendFunc { src, dst } => {
+                    self.blend_func = (*src, *dst);
+                }
+                RenderInstruction::UseProgram(program) => {
+                    self.active_program = program.clone();
+                }
+                RenderInstruction::SetUniform(location, value) => {
+                    let Some(program) = self.active_program.clone() else {
+                        tracing::warn!(
+                            what = "tried to set a uniform without an active program",
+                            location
+                        );
+                        continue;
+                    };
+
+                    let values = self.get_uniform_values(&program);
+
+                    if !program.write_uniform(values, *location, value) {
+                        tracing::warn!(
+                            what = "tried to set a uniform that doesn't exist or has a different type",
+                            program = program.id,
+                            location,
+                            ?value
+                        );
+                    }
+                }
+
+                RenderInstruction::Scissor(rect) => {
+                    self.gl_scissor = Some(*rect);
+                    self.update_scissor();
+                }
+                RenderInstruction::PushScissor(rect) => {
+                    let rect = match self.scissor_stack.last() {
+                        Some(outer) => outer.intersect(rect),
+                        None => *rect,
+                    };
 
+                    self.scissor_stack.push(rect);
+                    self.update_scissor();
+                }
+                RenderInstruction::PopScissor => {
+                    if self.scissor_stack.pop().is_none() {
+                        tracing::warn!(what = "tried to pop an empty scissor stack");
+                        continue;
+                    }
+
+                    self.update_scissor();
+                }
+
+                RenderInstruction::InitNames => {
+                    self.name_stack.clear();
+                }
+                RenderInstruction::PushName(name) => {
+                    self.name_stack.push(*name);
+                }
+                RenderInstruction::PushAttrib(mask) => {
+                    self.push_attrib(*mask);
+                }
+                RenderInstruction::PopAttrib => {
+                    self.pop_attrib();
+                }
+                RenderInstruction::PopName => {
+                    if self.name_stack.pop().is_none() {
+                        tracing::warn!(what = "tried to pop an empty name stack");
+                    }
+                }
+                RenderInstruction::LoadName(name) => match self.name_stack.last_mut() {
+                    Some(top) => *top = *name,
+                    None => {
+                        tracing::warn!(
+                            what = "glLoadName was called with an empty name stack and the call has been ignored!",
+                            name
+                        );
+                    }
+                },
+
+                RenderInstruction::Viewport {
+                    index,
+                    x,
+                    y,
+                    width,
+                    height,
+                } => {
+                    self.commands
+                        .push(RenderCommand::SetViewport {
+                            index: *index,
+                            x: *x,
+                            y: *y,
+                            width: *width,
+                            height: *height,
+                        })
+                        .unwrap();
+                }
+                RenderInstruction::DepthRange { index, near, far } => {
+                    self.commands
+                        .push(RenderCommand::SetDepthRange {
+                            index: *index,
+                            near: *near,
+                            far: *far,
+                        })
+                        .unwrap();
+                }
+                RenderInstruction::ClearColour => {
+                    self.commands
+                        .push(RenderCommand::ClearColour(self.get_scissor()))
+                        .unwrap();
+                }
                 RenderInstruction::ClearDepth => {
-                    self.commands.push(RenderCommand::ClearDepth).unwrap();
+                    self.commands
+                        .push(RenderCommand::ClearDepth(self.get_scissor()))
+                        .unwrap();
                 }
             }
         }
     }
 
+    /// The stack that matrix operations currently apply to
+    fn get_matrix_stack(&mut self) -> &mut MatrixStack {
+        if self.active_matrix == TEXTURE_MATRIX_IDX {
+            &mut self.texture_units[self.active_unit].matrix
+        } else {
+            &mut self.matrix_stacks[self.active_matrix]
+        }
+    }
+
+    pub fn get_texture_matrix(&self, unit: usize) -> &TMat4<f32> {
+        self.texture_units[unit].matrix.get()
+    }
+
+    /// The array glEnableClientState and the pointer calls affect. Texcoord arrays belong to the
+    /// client active unit, and only the base and lightmap units have one.
+    fn client_array_mut(&mut self, array_type: &PointerArrayType) -> Option<&mut ClientArray> {
+        if *array_type != PointerArrayType::TexCoord {
+            return Some(&mut self.client_arrays[get_client_array_index(array_type)]);
+        }
+
+        match self.client_active_unit {
+            BASE_TEXTURE_UNIT => Some(&mut self.client_arrays[TEXCOORD_ARRAY_IDX]),
+            LIGHTMAP_TEXTURE_UNIT => Some(&mut self.lightmap_texcoords),
+            unit => {
+                tracing::warn!(
+                    what = "texcoord arrays are only supported for the base and lightmap units; the call will be ignored",
+                    unit
+                );
+                None
+            }
+        }
+    }
+
+    /// The alpha test of the next draw, or None if it wouldn't discard anything
+    pub fn alpha_test(&self) -> Option<AlphaTest> {
+        if !self.is_enabled(gl_constants::GL_ALPHA_TEST) || self.alpha_test.func == CompareOp::Always
+        {
+            return None;
+        }
+
+        Some(self.alpha_test)
+    }
+
+    /// The dynamic rasterization state of the next draw
+    pub fn raster_state(&self) -> DynamicRasterState {
+        let cull_mode = if self.is_enabled(gl_constants::GL_CULL_FACE) {
+            match self.cull_face {
+                CullFace::Front => CullMode::Front,
+                CullFace::Back => CullMode::Back,
+                CullFace::FrontAndBack => CullMode::FrontAndBack,
+            }
+        } else {
+            CullMode::None
+        };
+
+        DynamicRasterState {
+            cull_mode,
+            // viewports are flipped like GL's (see viewport_to_vulkan), so the winding carries over
+            front_face: match self.front_face {
+                Winding::Clockwise => FrontFace::Clockwise,
+                Winding::CounterClockwise => FrontFace::CounterClockwise,
+            },
+            ..Default::default()
+        }
+    }
+
+    /// The blend state of the next draw, None while GL_BLEND is disabled
+    pub fn color_blending(&self) -> Option<AttachmentBlend> {
+        if !self.is_enabled(gl_constants::GL_BLEND) {
+            return None;
+        }
+
+        let (src, dst) = self.blend_func;
+
+        Some(AttachmentBlend {
+            src_color_blend_factor: src,
+            dst_color_blend_factor: dst,
+            color_blend_op: BlendOp::Add,
+            src_alpha_blend_factor: src,
+            dst_alpha_blend_factor: dst,
+            alpha_blend_op: BlendOp::Add,
+        })
+    }
+
+    /// The equations of the enabled clip planes, in clip space so that the vertex shader can test
+    /// them against gl_Position. Eye space planes are only mapped through the projection here, since
+    /// it can change after glClipPlane. Always empty if the device can't clip.
+    fn enabled_clip_planes(&self) -> Vec<Vec4> {
+        if !self.supports_clip_distance {
+            return Vec::new();
+        }
+
+        let projection = self.matrix_stacks[PROJECTION_MATRIX_IDX].get();
+
+        (0..MAX_CLIP_PLANES)
+            .filter(|plane| self.is_enabled(gl_constants::GL_CLIP_PLANE0 + *plane as u32))
+            .map(|plane| transform_plane(projection, &self.clip_planes[plane]))
+            .collect()
+    }
+
+    /// A unit's current texcoord, as seen through its texture matrix
+    fn unit_texcoord(&self, unit: usize) -> Vec2 {
+        let unit = &self.texture_units[unit];
+        let coord = unit.matrix.get() * unit.texcoord;
+
+        Vec2::new(coord.x / coord.w, coord.y / coord.w)
+    }
+
     fn get_mvp_matrix(&mut self) -> TMat4<f32> {
         if let Some(mat) = self.active_mvp_cache.as_ref() {
             return mat.clone();
@@ -370,12 +1300,45 @@ impl RenderInsnAssembler {
     }
 
     pub fn is_enabled(&self, flag: u32) -> bool {
+        if is_texture_target(flag) {
+            return self.texture_units[self.active_unit].enabled;
+        }
+
         self.active_flags.contains(&(flag as usize))
     }
 
+    /// Returns None for any pname which isn't tracked
+    pub fn get_integer(&self, pname: u32) -> Option<i32> {
+        match pname {
+            gl_constants::GL_DEPTH_FUNC => self.depth_func.to_i32(),
+            gl_constants::GL_CULL_FACE_MODE => self.cull_face.to_i32(),
+            gl_constants::GL_FRONT_FACE => self.front_face.to_i32(),
+            gl_constants::GL_MAX_TEXTURE_IMAGE_UNITS => Some(self.max_texture_units as i32),
+            gl_constants::GL_NAME_STACK_DEPTH => Some(self.name_stack.len() as i32),
+            gl_constants::GL_ATTRIB_STACK_DEPTH => Some(self.attrib_stack.len() as i32),
+            _ => None,
+        }
+    }
+
+    /// Returns None for any pname which isn't tracked. Capabilities are reported as enabled or
+    /// disabled, like glIsEnabled.
+    pub fn get_booleans(&self, pname: u32) -> Option<Vec<bool>> {
+        match pname {
+            gl_constants::GL_DEPTH_WRITEMASK => Some(vec![self.depth_write]),
+            gl_constants::GL_COLOR_WRITEMASK => Some(self.color_mask.to_vec()),
+            cap if QUERYABLE_CAPABILITIES.contains(&cap) => Some(vec![self.is_enabled(cap)]),
+            _ => None,
+        }
+    }
+
+    pub fn get_boolean(&self, pname: u32) -> Option<bool> {
+        self.get_booleans(pname).map(|values| values[0])
+    }
+
     fn get_vertex_buffer_layout(&self) -> (VertexBufferLayout, Vec<VertexBufferSlot>, usize) {
         let mut desc = VertexBufferLayout {
             fields: [const { None }; _],
+            generic: Vec::new(),
             stride: 0,
         };
 
@@ -428,16 +1391,16 @@ impl RenderInsnAssembler {
             }
 
             if array_type == PointerArrayType::TexCoord {
-                if !self.is_enabled(gl_constants::GL_TEXTURE_2D) {
+                if !self.texture_units[BASE_TEXTURE_UNIT].enabled {
                     tracing::info!(
                         what = "will not assemble texcoords because GL_TEXTURE_2D is disabled"
                     );
                     continue;
                 }
 
-                if self.get_active_texture().is_none() {
+                if self.texture_units[BASE_TEXTURE_UNIT].bound_texture.is_none() {
                     tracing::info!(
-                        what = "will not assemble texcoords because there is no active texture"
+                        what = "will not assemble texcoords because there is no bound texture"
                     );
                     continue;
                 }
@@ -452,30 +1415,43 @@ impl RenderInsnAssembler {
                 }
             }
 
-            let size = array.data_type.size();
+            // assemble_buffer converts every integer type to f32, so the layout has to describe
+            // the converted data rather than the client array. texcoords are always remapped into
+            // f32s.
+            let data_type = match array.data_type {
+                GLDataType::F32 | GLDataType::F64 if array_type != PointerArrayType::TexCoord => {
+                    array.data_type
+                }
+                _ => GLDataType::F32,
+            };
+
+            let size = data_type.size();
 
             let field_idx = VertexInputType::from(array_type).to_usize().unwrap();
 
             desc.fields[field_idx] = Some(VertexInputSpec {
                 offset: desc.stride,
-                data_type: array.data_type,
+                data_type,
                 num_elements: array.element_count,
             });
 
             layout.push(VertexBufferSlot {
                 array,
                 buffer_offset: desc.stride,
-                data_type: array.data_type,
-                array_type,
+                data_type,
+                input_type: Some(VertexInputType::from(array_type)),
+                normalized: array_type == PointerArrayType::Color
+                    || array_type == PointerArrayType::SecondaryColor,
             });
 
             desc.stride += size * array.element_count;
             desc.align_to(4);
 
+            // the texture array of each vertex, followed by its slot in the array
             if array_type == PointerArrayType::TexCoord {
                 let data_type = GLDataType::U16;
                 let size = data_type.size();
-                let num_elements = 1;
+                let num_elements = 2;
 
                 let field_idx = VertexInputType::TexIndex.to_usize().unwrap();
 
@@ -489,7 +1465,8 @@ impl RenderInsnAssembler {
                     array,
                     buffer_offset: desc.stride,
                     data_type,
-                    array_type,
+                    input_type: Some(VertexInputType::TexIndex),
+                    normalized: false,
                 });
 
                 desc.stride += size * num_elements;
@@ -497,62 +1474,204 @@ impl RenderInsnAssembler {
             }
         }
 
+        // the lightmap is only sampled by textured pipelines, which need the base texcoords
+        if self.lightmap_texcoords.enabled && desc.texcoord().is_some() {
+            let array = &self.lightmap_texcoords;
+
+            if array.data.is_none() {
+                tracing::warn!(
+                    what = "lightmap texcoord array is enabled, but no data was provided; its data will not be sent to the gpu",
+                );
+            } else if !matches!(array.data_type, GLDataType::F32 | GLDataType::F64)
+                || array.element_count != 2
+            {
+                tracing::info!(
+                    what = "will not assemble lightmap texcoords because they aren't (d)vec2s"
+                );
+            } else {
+                if vertex_count.is_some_and(|vc| vc != array.vertex_count) {
+                    tracing::warn!(
+                        what = "found lightmap texcoord array length mismatch; pruned the longer arrays",
+                        array_length = array.vertex_count,
+                        expected_length = vertex_count,
+                    );
+                }
+
+                vertex_count =
+                    Some(vertex_count.map_or(array.vertex_count, |vc| vc.min(array.vertex_count)));
+
+                let data_type = GLDataType::F32;
+                let field_idx = VertexInputType::LightmapTexCoord.to_usize().unwrap();
+
+                desc.fields[field_idx] = Some(VertexInputSpec {
+                    offset: desc.stride,
+                    data_type,
+                    num_elements: 2,
+                });
+
+                layout.push(VertexBufferSlot {
+                    array,
+                    buffer_offset: desc.stride,
+                    data_type,
+                    input_type: Some(VertexInputType::LightmapTexCoord),
+                    normalized: false,
+                });
+
+                desc.stride += data_type.size() * 2;
+                desc.align_to(4);
+            }
+        }
+
+        if self.active_program.is_some() {
+            let mut indices = self
+                .generic_arrays
+                .iter()
+                .filter(|(_, array)| array.enabled)
+                .map(|(index, _)| *index)
+                .collect::<Vec<_>>();
+            indices.sort();
+
+            for index in indices {
+                let array = &self.generic_arrays[&index];
+
+                if array.data.is_none() {
+                    tracing::warn!(
+                        what = "generic attribute array is enabled, but no data was provided; its data will not be sent to the gpu",
+                        index,
+                    );
+                    continue;
+                }
+
+                if vertex_count.is_some_and(|vc| vc != array.vertex_count) {
+                    tracing::warn!(
+                        what = "found generic attribute array length mismatch; pruned the longer arrays",
+                        index,
+                        array_length = array.vertex_count,
+                        expected_length = vertex_count,
+                    );
+                }
+
+                vertex_count = Some(
+                    vertex_count.map_or(array.vertex_count, |vc| vc.min(array.vertex_count)),
+                );
+
+                let data_type = match array.data_type {
+                    GLDataType::F32 | GLDataType::F64 => array.data_type,
+                    _ => GLDataType::F32,
+                };
+
+                desc.generic.push((
+                    index,
+                    VertexInputSpec {
+                        offset: desc.stride,
+                        data_type,
+                        num_elements: array.element_count,
+                    },
+                ));
+
+                layout.push(VertexBufferSlot {
+                    array,
+                    buffer_offset: desc.stride,
+                    data_type,
+                    input_type: None,
+                    normalized: array.normalized,
+                });
+
+                desc.stride += data_type.size() * array.element_count;
+                desc.align_to(4);
+            }
+        }
+
         (desc, layout, vertex_count.unwrap() as usize)
     }
 
-    fn assemble_buffer(&self) -> (VertexBufferLayout, Vec<u8>) {
+    /// Interleaves the enabled arrays into one vertex buffer, holding at most `max_vertices`
+    fn assemble_buffer(&self, max_vertices: Option<usize>) -> (VertexBufferLayout, Vec<u8>) {
         let (desc, layout, vertex_count) = self.get_vertex_buffer_layout();
 
-        let mut buffer = Vec::new();
-        buffer.resize(vertex_count * (desc.stride as usize), 0);
+        let vertex_count = max_vertices.map_or(vertex_count, |max| vertex_count.min(max));
+
+        let len = vertex_count * (desc.stride as usize);
+
+        let mut buffer = self.arena.take(len);
+        buffer.resize(len, 0);
 
         for slot in &layout {
-            let input_type = VertexInputType::from(slot.array_type);
+            let input_type = slot.input_type.clone();
 
-            if input_type == VertexInputType::TexIndex {
+            if input_type == Some(VertexInputType::TexIndex) {
                 continue;
             }
 
-            if input_type == VertexInputType::TexCoord {
+            if input_type == Some(VertexInputType::TexCoord) {
                 let texcoord = slot;
                 let texindex = layout
                     .iter()
-                    .find(|l| VertexInputType::from(l.array_type) == VertexInputType::TexIndex)
+                    .find(|l| l.input_type == Some(VertexInputType::TexIndex))
                     .unwrap();
 
-                let bound_texture = self.get_active_texture().unwrap();
+                let bound_texture = self.texture_units[BASE_TEXTURE_UNIT]
+                    .sampled_texture()
+                    .unwrap();
 
-                let dest_coord_byte_size = (texcoord.data_type.size() * 2) as usize;
-                let dest_index_byte_size = (texindex.data_type.size() * 2) as usize;
-                let src_byte_size = (texcoord.array.data_type.size() * 2) as usize;
+                let mut uvs = transform_texcoords(
+                    texcoord.array,
+                    vertex_count,
+                    self.get_texture_matrix(BASE_TEXTURE_UNIT),
+                );
 
-                for vertex_idx in 0..vertex_count {
+                // the manager's lock is only held long enough to get the current lookup
+                let lookup = self
+                    .textures
+                    .as_ref()
+                    .and_then(|textures| textures.read().get_lookup());
+
+                // without a lookup (i.e. in tests, or before the atlases have been loaded) there's
+                // no atlas to remap into, so the uvs are passed through as-is
+                let indices = match lookup.as_ref().map(|l| l.read()) {
+                    Some(lookup) => match lookup.transform(bound_texture, &mut uvs) {
+                        Some((_, indices)) => indices,
+                        None => {
+                            tracing::warn!(
+                                what = "could not find the bound texture; drawing missingno instead",
+                                texture = bound_texture,
+                            );
+                            vec![lookup.missingno_index(); vertex_count]
+                        }
+                    },
+                    None => vec![(0, 0); vertex_count],
+                };
+
+                for (vertex_idx, (array, slot)) in indices.into_iter().enumerate() {
                     let vertex_start = vertex_idx * (desc.stride as usize);
                     let dest_coord_start = vertex_start + texcoord.buffer_offset as usize;
                     let dest_index_start = vertex_start + texindex.buffer_offset as usize;
-                    let src_start = vertex_idx * src_byte_size as usize;
 
-                    let uv = match texcoord.array.data_type {
-                        GLDataType::F32 => {
-                            let src = &texcoord.array.data.as_ref().unwrap()
-                                [src_start..src_start + src_byte_size];
+                    let uv = &uvs[vertex_idx * 2..vertex_idx * 2 + 2];
 
-                            let src = unsafe { src.align_to::<f32>().1 };
-
-                            [src[0], src[1]]
-                        }
-                        GLDataType::F64 => {
-                            let src = &texcoord.array.data.as_ref().unwrap()
-                                [src_start..src_start + src_byte_size];
-
-                            let src = unsafe { src.align_to::<f64>().1 };
+                    buffer[dest_coord_start..dest_coord_start + 4]
+                        .copy_from_slice(&uv[0].to_ne_bytes());
+                    buffer[dest_coord_start + 4..dest_coord_start + 8]
+                        .copy_from_slice(&uv[1].to_ne_bytes());
+                    buffer[dest_index_start..dest_index_start + 2]
+                        .copy_from_slice(&array.to_ne_bytes());
+                    buffer[dest_index_start + 2..dest_index_start + 4]
+                        .copy_from_slice(&slot.to_ne_bytes());
+                }
+            } else if input_type == Some(VertexInputType::LightmapTexCoord) {
+                // lightmaps aren't in the atlas, so their uvs are only transformed
+                let uvs = transform_texcoords(
+                    slot.array,
+                    vertex_count,
+                    self.get_texture_matrix(LIGHTMAP_TEXTURE_UNIT),
+                );
 
-                            [src[0] as f32, src[1] as f32]
-                        }
-                        _ => panic!(),
-                    };
+                for (vertex_idx, uv) in uvs.chunks_exact(2).enumerate() {
+                    let dest_start =
+                        vertex_idx * (desc.stride as usize) + slot.buffer_offset as usize;
 
-                    self.texture_lookup.
+                    buffer[dest_start..dest_start + 4].copy_from_slice(&uv[0].to_ne_bytes());
+                    buffer[dest_start + 4..dest_start + 8].copy_from_slice(&uv[1].to_ne_bytes());
                 }
             } else {
                 let array = &slot.array;
@@ -593,9 +1712,7 @@ impl RenderInsnAssembler {
                         }};
                     }
 
-                    if slot.array_type == PointerArrayType::Color
-                        || slot.array_type == PointerArrayType::SecondaryColor
-                    {
+                    if slot.normalized {
                         match slot.array.data_type {
                             GLDataType::U8 => convert_norm!(u8),
                             GLDataType::I8 => convert_norm!(i8),
@@ -627,65 +1744,604 @@ impl RenderInsnAssembler {
         (desc, buffer)
     }
 
+    /// glBufferSubData. Draws which were already assembled keep the old contents.
+    fn buffer_sub_data(&mut self, buffer: u32, offset: usize, data: &[u8]) {
+        let Some(contents) = self.buffer_objects.get_mut(&buffer) else {
+            tracing::warn!(
+                what = "glBufferSubData() was called on a buffer without any contents",
+                buffer
+            );
+            return;
+        };
+
+        let Some(dest) = Arc::make_mut(contents).get_mut(offset..offset + data.len()) else {
+            tracing::warn!(
+                what = "glBufferSubData() was called past the end of its buffer",
+                buffer,
+                offset,
+                byte_length = data.len()
+            );
+            return;
+        };
+
+        dest.copy_from_slice(data);
+    }
+
+    /// Reads the first `max_vertices` vectors of the enabled arrays which point into buffer
+    /// objects, so that draws see every glBufferSubData made before them, including ones made
+    /// after the pointer was set
+    fn resolve_buffer_arrays(&mut self, max_vertices: usize) {
+        let buffers = &self.buffer_objects;
+
+        for array in self
+            .client_arrays
+            .iter_mut()
+            .chain(std::iter::once(&mut self.lightmap_texcoords))
+            .chain(self.generic_arrays.values_mut())
+            .filter(|array| array.enabled)
+        {
+            array.resolve(buffers, max_vertices);
+        }
+    }
+
+    /// Whether something can provide the positions of a draw
+    fn has_position_array(&self) -> bool {
+        // programs can get their positions from a generic attribute instead
+        let has_generic_arrays = self.active_program.is_some()
+            && self.generic_arrays.values().any(|array| array.enabled);
+
+        self.client_arrays[VERTEX_ARRAY_IDX].enabled || has_generic_arrays
+    }
+
     pub fn draw_arrays(&mut self, mode: DrawMode, first: u32, count: u32) {
-        if !self.client_arrays[VERTEX_ARRAY_IDX].enabled {
+        if !self.has_position_array() {
             tracing::warn!(
                 what = "tried to call draw_arrays() without the position array set; this is invalid and the call will be ignored"
             );
             return;
         }
 
-        let (desc, buffer) = self.assemble_buffer();
+        self.resolve_buffer_arrays(first as usize + count as usize);
+
+        let (desc, buffer) = self.assemble_buffer(None);
+
+        self.bind_draw_pipeline(mode, desc);
+
+        self.commands
+            .push(RenderCommand::Draw {
+                start_vertex: first,
+                vertex_count: count,
+                data: Arc::new(buffer),
+            })
+            .unwrap();
+    }
+
+    /// glDrawElements: only the vertices up to the highest referenced index get assembled
+    pub fn draw_elements(
+        &mut self,
+        mode: DrawMode,
+        count: u32,
+        index_type: GLDataType,
+        indices: &[u8],
+    ) {
+        if !self.has_position_array() {
+            tracing::warn!(
+                what = "tried to call draw_elements() without the position array set; this is invalid and the call will be ignored"
+            );
+            return;
+        }
+
+        let Some(indices) = decode_indices(index_type, count as usize, indices) else {
+            tracing::warn!(
+                what = "draw_elements() was called with an invalid index type or too few indices; the call will be ignored",
+                ?index_type,
+                count,
+                byte_length = indices.len()
+            );
+            return;
+        };
+
+        let Some(max_index) = indices.iter().copied().max() else {
+            return;
+        };
 
-        let color = if self
-            .active_flags
-            .contains(&(gl_constants::GL_TEXTURE_2D as usize))
+        self.resolve_buffer_arrays(max_index as usize + 1);
+
+        let (desc, buffer) = self.assemble_buffer(Some(max_index as usize + 1));
+
+        if buffer.len() / (desc.stride as usize).max(1) <= max_index as usize {
+            tracing::warn!(
+                what = "draw_elements() referenced a vertex past the end of the client arrays; the call will be ignored",
+                max_index
+            );
+            return;
+        }
+
+        self.bind_draw_pipeline(mode, desc);
+
+        // vulkan only has u8 indices behind an extension, so they're widened
+        let index_type = match index_type {
+            GLDataType::U32 => GLDataType::U32,
+            _ => GLDataType::U16,
+        };
+
+        let mut index_bytes = self.arena.take(indices.len() * index_type.size() as usize);
+
+        match index_type {
+            GLDataType::U32 => {
+                index_bytes.extend(indices.iter().flat_map(|&i| i.to_ne_bytes()));
+            }
+            _ => {
+                index_bytes.extend(indices.iter().flat_map(|&i| (i as u16).to_ne_bytes()));
+            }
+        }
+
+        self.commands
+            .push(RenderCommand::DrawIndexed {
+                index_count: count,
+                index_type,
+                data: Arc::new(buffer),
+                indices: Arc::new(index_bytes),
+            })
+            .unwrap();
+    }
+
+    /// How a textured draw combines its texture units, along with the texture bound to each
+    /// sampler and the texcoords of the units after the lightmap. The lightmap unit is always
+    /// sampled (a disabled one samples white), while the units after it are only sampled while
+    /// they're enabled and have a texture.
+    fn texture_combine(
+        &self,
+    ) -> (
+        TextureCombine,
+        [Option<i32>; MAX_SHADER_TEXTURE_UNITS],
+        Vec<Vec2>,
+    ) {
+        let lightmap = &self.texture_units[LIGHTMAP_TEXTURE_UNIT];
+
+        // a disabled lightmap samples white, which only leaves the color untouched when modulated
+        let mut combine = TextureCombine::new(match lightmap.sampled_texture() {
+            Some(_) => lightmap.env_mode,
+            None => TextureEnvMode::Modulate,
+        });
+
+        let base = &self.texture_units[BASE_TEXTURE_UNIT];
+
+        combine.set_rectangle(base.rectangle);
+
+        let mut textures = [None; MAX_SHADER_TEXTURE_UNITS];
+        textures[BASE_TEXTURE_UNIT] = base.sampled_texture();
+        textures[LIGHTMAP_TEXTURE_UNIT] = lightmap.sampled_texture();
+
+        let mut texcoords = Vec::new();
+
+        for (unit, texture_unit) in self
+            .texture_units
+            .iter()
+            .enumerate()
+            .skip(LIGHTMAP_TEXTURE_UNIT + 1)
         {
-            if let Some(texture) = self.get_active_texture() {
+            let Some(texture) = texture_unit.sampled_texture() else {
+                continue;
+            };
+
+            if texture_unit.rectangle {
+                tracing::warn!(
+                    what = "only the base texture unit can sample a rectangle texture, it will be sampled at normalized coordinates",
+                    unit
+                );
+            }
+
+            if !combine.push(texture_unit.env_mode) {
+                tracing::warn!(
+                    what = "too many texture units were enabled, the extra units will be ignored",
+                    unit,
+                    max = MAX_SHADER_TEXTURE_UNITS
+                );
+                break;
+            }
+
+            textures[combine.unit_count() - 1] = Some(texture);
+            texcoords.push(self.unit_texcoord(unit));
+        }
+
+        (combine, textures, texcoords)
+    }
+
+    /// Binds the pipeline which draws `desc` with the current state, along with its push constants
+    /// and uniforms
+    fn bind_draw_pipeline(&mut self, mode: DrawMode, desc: VertexBufferLayout) {
+        let (units, textures, unit_texcoords) = self.texture_combine();
+
+        // the color array is only in the layout while its client state is enabled, so disabling
+        // it between draws falls back to the last glColor. while it's enabled it always wins over
+        // glColor, no matter when glColor was called.
+        let untextured_color = if desc.color().is_some() {
+            ColorMode::Array
+        } else {
+            ColorMode::Flat(DataSource::PushConstant)
+        };
+
+        let base_unit = &self.texture_units[BASE_TEXTURE_UNIT];
+
+        let color = if base_unit.enabled {
+            if base_unit.bound_texture.is_some() {
                 if desc.texcoord().is_some() {
-                    ColorMode::Texture { set: 1, binding: 0 }
+                    ColorMode::Texture {
+                        set: 1,
+                        binding: 0,
+                        units,
+                    }
                 } else {
                     tracing::warn!(what = "GL_TEXTURE_2D was enabled but the texcoord client array wasn't enabled/valid");
-                    ColorMode::Flat(DataSource::PushConstant)
+                    untextured_color
                 }
             } else {
-                tracing::warn!(what = "GL_TEXTURE_2D was enabled but the active texture unit didn't have a bound texture", texture_unit = self.active_unit);
-                ColorMode::Flat(DataSource::PushConstant)
+                tracing::warn!(what = "GL_TEXTURE_2D was enabled but the base texture unit didn't have a bound texture", texture_unit = BASE_TEXTURE_UNIT);
+                untextured_color
             }
         } else {
-            ColorMode::Flat(DataSource::PushConstant)
+            untextured_color
+        };
+
+        // unlit normals aren't transformed
+        let lit = self.is_enabled(gl_constants::GL_LIGHTING) && desc.normal().is_some();
+
+        // user programs write their own gl_ClipDistance, if any
+        let clip_planes = match self.active_program {
+            Some(_) => Vec::new(),
+            None => self.enabled_clip_planes(),
         };
 
-        let pipeline = DynamicPipelineSpec {
+        let mut pipeline = DynamicPipelineSpec {
             draw_mode: mode,
             vertex_buffer: desc,
-            matrix: ShaderMatrixMode::MVP(DataSource::PushConstant),
+            matrix: if lit {
+                ShaderMatrixMode::MVPNormal
+            } else {
+                ShaderMatrixMode::MVP(DataSource::PushConstant)
+            },
             color,
-            rasterization: DynamicPipelineRasterization::default(),
+            normal_scaling: NormalScaling::from_caps(
+                self.is_enabled(gl_constants::GL_NORMALIZE),
+                self.is_enabled(gl_constants::GL_RESCALE_NORMAL),
+            ),
+            rasterization: DynamicPipelineRasterization {
+                provoking_vertex: self.provoking_vertex,
+                polygon_mode: self.polygon_mode,
+                color_blending: self.color_blending(),
+            },
+            depth: if self.is_enabled(gl_constants::GL_DEPTH_TEST) {
+                DynamicPipelineDepth {
+                    compare_op: gl_compare_op(self.depth_func),
+                    write_enable: self.depth_write,
+                }
+            } else {
+                DynamicPipelineDepth::disabled()
+            },
+            alpha_test: self.alpha_test(),
+            clip_planes: clip_planes.len() as u8,
+            transforms: DataSource::PushConstant,
+            object_ids: self.object_picking,
+            program: self.active_program.clone(),
         };
 
-        let push_constants = DynamicPipelinePushConstants {
-            mvp: Some(self.get_mvp_matrix()),
-            color: if pipeline.color == ColorMode::Flat(DataSource::PushConstant) {
-                Some(self.active_color.clone().into())
-            } else {
-                None
+        fit_push_constants(&mut pipeline, self.max_push_constants_size);
+
+        let uses_blend_constants = pipeline.rasterization.uses_blend_constants();
+        let is_textured = matches!(pipeline.color, ColorMode::Texture { .. });
+
+        let mut push_constants = match &pipeline.program {
+            // user programs only get the mvp, and only if their push constant block can hold it
+            Some(program) => DynamicPipelinePushConstants {
+                mvp: (program.push_constant_size as usize >= size_of::<TMat4<f32>>())
+                    .then(|| self.get_mvp_matrix()),
+                ..Default::default()
             },
+            None => DynamicPipelinePushConstants {
+                mvp: Some(self.get_mvp_matrix()),
+                normal_matrix: if pipeline.matrix == ShaderMatrixMode::MVPNormal {
+                    Some(normal_matrix(self.matrix_stacks[MODELVIEW_MATRIX_IDX].get()))
+                } else {
+                    None
+                },
+                clip_planes,
+                color: if pipeline.color == ColorMode::Flat(DataSource::PushConstant) {
+                    Some(self.active_color.clone().into())
+                } else {
+                    None
+                },
+                lightmap_texcoord: if is_textured {
+                    Some(self.unit_texcoord(LIGHTMAP_TEXTURE_UNIT))
+                } else {
+                    None
+                },
+                unit_texcoords: if is_textured {
+                    unit_texcoords
+                } else {
+                    Vec::new()
+                },
+                normal_scale: if ShaderSpec::from(&pipeline).rescales_normals() {
+                    Some(normal_rescale_factor(
+                        self.matrix_stacks[MODELVIEW_MATRIX_IDX].get(),
+                    ))
+                } else {
+                    None
+                },
+                object_id: self.object_picking.then(|| self.object_id()),
+            },
+        };
+
+        let transforms = match &pipeline.transforms {
+            DataSource::Uniform { set, binding } => {
+                Some((*set, *binding, push_constants.take_transforms()))
+            }
+            DataSource::PushConstant => None,
         };
 
         self.commands
             .push(RenderCommand::BindDynamicGraphicsPipeline {
                 pipeline,
                 push_constants,
+                raster_state: self.raster_state(),
             })
             .unwrap();
 
+        if let Some((set, binding, data)) = transforms {
+            self.commands
+                .push(RenderCommand::BindUniforms {
+                    set: set as u32,
+                    buffers: vec![(binding as u32, Arc::new(data))],
+                    textures: Vec::new(),
+                })
+                .unwrap();
+        }
+
+        if uses_blend_constants {
+            self.commands
+                .push(RenderCommand::SetBlendConstants(self.blend_color.into()))
+                .unwrap();
+        }
+
+        if is_textured {
+            self.commands
+                .push(RenderCommand::BindTextureUnits(textures))
+                .unwrap();
+        }
+
+        if let Some(program) = self.active_program.clone() {
+            self.bind_uniforms(&program);
+        }
+    }
+
+    fn get_uniform_values(&mut self, program: &LinkedProgram) -> &mut UniformValues {
+        let (generation, values) = self
+            .program_uniforms
+            .entry(program.id)
+            .or_insert_with(|| (program.generation, program.new_uniform_values()));
+
+        if *generation != program.generation {
+            *generation = program.generation;
+            *values = program.new_uniform_values();
+        }
+
+        values
+    }
+
+    /// Binds the current contents of each of the program's uniform blocks, along with the texture
+    /// bound to each sampler's unit, one command per set
+    fn bind_uniforms(&mut self, program: &LinkedProgram) {
+        let values = self.get_uniform_values(program).clone();
+
+        let mut sets = HashMap::<u32, (Vec<_>, Vec<_>)>::new();
+
+        for (&(set, binding), data) in values.buffers.iter() {
+            sets.entry(set).or_default().0.push((binding, data.clone()));
+        }
+
+        for (&(set, binding), &unit) in values.sampler_units.iter() {
+            let texture = self
+                .texture_units
+                .get(unit as usize)
+                .and_then(|unit| unit.bound_texture);
+
+            sets.entry(set).or_default().1.push((binding, texture));
+        }
+
+        for (set, (mut buffers, mut textures)) in sets {
+            buffers.sort_by_key(|(binding, _)| *binding);
+            textures.sort_by_key(|(binding, _)| *binding);
+
+            self.commands
+                .push(RenderCommand::BindUniforms {
+                    set,
+                    buffers,
+                    textures,
+                })
+                .unwrap();
+        }
+    }
+
+    /// Draws a glBegin/glEnd batch by temporarily standing its vertices in for the client arrays,
+    /// so that it's assembled exactly like glDrawArrays would.
+    fn draw_immediate(&mut self, batch: ImmediateBatch) {
+        if batch.vertices.is_empty() {
+            return;
+        }
+
+        let mut arrays = from_fn(|_| ClientArray::new());
+
+        arrays[VERTEX_ARRAY_IDX] = batch.client_array(|v| v.position.into());
+        arrays[COLOR_ARRAY_IDX] = batch.client_array(|v| v.color.into());
+        arrays[TEXCOORD_ARRAY_IDX] = batch.client_array(|v| [v.texcoord.x, v.texcoord.y]);
+        arrays[NORMAL_ARRAY_IDX] = batch.client_array(|v| v.normal.into());
+
+        let client_arrays = std::mem::replace(&mut self.client_arrays, arrays);
+        // immediate vertices get their lightmap texcoord from glMultiTexCoord
+        let lightmap_texcoords =
+            std::mem::replace(&mut self.lightmap_texcoords, ClientArray::new());
+
+        self.draw_arrays(batch.mode, 0, batch.vertices.len() as u32);
+
+        self.client_arrays = client_arrays;
+        self.lightmap_texcoords = lightmap_texcoords;
+    }
+
+    /// The name draws are picked by: the top of the name stack, or 0 if it's empty
+    pub fn object_id(&self) -> u32 {
+        self.name_stack.last().copied().unwrap_or(0)
+    }
+
+    /// The region draws are clipped to: the glScissor rectangle while GL_SCISSOR_TEST is
+    /// enabled, intersected with the top of the scissor stack
+    pub fn get_scissor(&self) -> Option<ScissorRect> {
+        let gl_scissor = self
+            .gl_scissor
+            .filter(|_| self.is_enabled(gl_constants::GL_SCISSOR_TEST));
+
+        match (gl_scissor, self.scissor_stack.last()) {
+            (Some(gl_scissor), Some(top)) => Some(gl_scissor.intersect(top)),
+            (gl_scissor, top) => gl_scissor.or(top.copied()),
+        }
+    }
+
+    fn push_attrib(&mut self, mask: u32) {
+        if self.attrib_stack.len() >= MAX_ATTRIB_STACK_DEPTH {
+            tracing::warn!(
+                what = "glPushAttrib overflowed the attribute stack and the call has been ignored!",
+                mask
+            );
+            return;
+        }
+
+        // GL_ALL_ATTRIB_BITS is expected to include groups which aren't tracked
+        if mask != gl_constants::GL_ALL_ATTRIB_BITS && mask & !SUPPORTED_ATTRIB_BITS != 0 {
+            tracing::warn!(
+                what = "glPushAttrib was called with unsupported attribute bits, they won't be restored",
+                unsupported = mask & !SUPPORTED_ATTRIB_BITS
+            );
+        }
+
+        let has = |bit: u32| mask & bit != 0;
+
+        let snapshot = AttribSnapshot {
+            enabled: has(gl_constants::GL_ENABLE_BIT).then(|| {
+                (
+                    self.active_flags.clone(),
+                    from_fn(|unit| self.texture_units[unit].enabled),
+                )
+            }),
+            capabilities: ATTRIB_CAPABILITIES
+                .iter()
+                .filter(|(bit, _)| has(*bit))
+                .flat_map(|(_, caps)| caps.iter())
+                .map(|cap| (*cap, self.active_flags.contains(&(*cap as usize))))
+                .collect(),
+            current: has(gl_constants::GL_CURRENT_BIT)
+                .then(|| (self.active_color, self.texcoord, self.normal)),
+            color_buffer: has(gl_constants::GL_COLOR_BUFFER_BIT).then(|| {
+                (
+                    self.alpha_test,
+                    self.blend_func,
+                    self.blend_color,
+                    self.color_mask,
+                )
+            }),
+            depth_buffer: has(gl_constants::GL_DEPTH_BUFFER_BIT)
+                .then(|| (self.depth_func, self.depth_write)),
+            polygon: has(gl_constants::GL_POLYGON_BIT)
+                .then(|| (self.cull_face, self.front_face, self.polygon_mode)),
+            scissor: has(gl_constants::GL_SCISSOR_BIT).then(|| self.gl_scissor),
+            texture: has(gl_constants::GL_TEXTURE_BIT).then(|| {
+                (
+                    self.active_unit,
+                    from_fn(|unit| {
+                        let unit = &self.texture_units[unit];
+                        (unit.bound_texture, unit.rectangle, unit.env_mode)
+                    }),
+                )
+            }),
+            transform: has(gl_constants::GL_TRANSFORM_BIT)
+                .then(|| (self.active_matrix, self.clip_planes)),
+        };
+
+        self.attrib_stack.push(snapshot);
+    }
+
+    fn pop_attrib(&mut self) {
+        let Some(snapshot) = self.attrib_stack.pop() else {
+            tracing::warn!(what = "tried to pop an empty attribute stack");
+            return;
+        };
+
+        if let Some((flags, units)) = snapshot.enabled {
+            self.active_flags = flags;
+
+            for (unit, enabled) in self.texture_units.iter_mut().zip(units) {
+                unit.enabled = enabled;
+            }
+        }
+
+        for (cap, enabled) in snapshot.capabilities {
+            if enabled {
+                self.active_flags.insert(cap as usize);
+            } else {
+                self.active_flags.remove(&(cap as usize));
+            }
+        }
+
+        if let Some((color, texcoord, normal)) = snapshot.current {
+            self.active_color = color;
+            self.texcoord = texcoord;
+            self.normal = normal;
+        }
+
+        if let Some((alpha_test, blend_func, blend_color, color_mask)) = snapshot.color_buffer {
+            self.alpha_test = alpha_test;
+            self.blend_func = blend_func;
+            self.blend_color = blend_color;
+            self.color_mask = color_mask;
+        }
+
+        if let Some((depth_func, depth_write)) = snapshot.depth_buffer {
+            self.depth_func = depth_func;
+            self.depth_write = depth_write;
+        }
+
+        if let Some((cull_face, front_face, polygon_mode)) = snapshot.polygon {
+            self.cull_face = cull_face;
+            self.front_face = front_face;
+            self.polygon_mode = polygon_mode;
+        }
+
+        if let Some(scissor) = snapshot.scissor {
+            self.gl_scissor = scissor;
+        }
+
+        if let Some((active_unit, units)) = snapshot.texture {
+            self.active_unit = active_unit;
+
+            for (unit, (bound_texture, rectangle, env_mode)) in
+                self.texture_units.iter_mut().zip(units)
+            {
+                unit.bound_texture = bound_texture;
+                unit.rectangle = rectangle;
+                unit.env_mode = env_mode;
+            }
+        }
+
+        if let Some((active_matrix, clip_planes)) = snapshot.transform {
+            self.active_matrix = active_matrix;
+            self.clip_planes = clip_planes;
+        }
+
+        // GL_SCISSOR_TEST and the rectangle can both come back
+        self.update_scissor();
+    }
+
+    fn update_scissor(&mut self) {
         self.commands
-            .push(RenderCommand::Draw {
-                start_vertex: first,
-                vertex_count: count,
-                data: Arc::new(buffer),
-            })
+            .push(RenderCommand::SetScissor(self.get_scissor()))
             .unwrap();
     }
 
@@ -1,27 +1,68 @@
+use std::path::PathBuf;
 use std::sync::atomic::AtomicU64;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
 
 use anyhow::Result;
 use enum_primitive::*;
+use image::RgbaImage;
 use vulkano::command_buffer::allocator::StandardCommandBufferAllocator;
+use vulkano::command_buffer::PrimaryAutoCommandBuffer;
 use vulkano::descriptor_set::allocator::StandardDescriptorSetAllocator;
-use vulkano::format::Format;
 use vulkano::image::ImageLayout;
+use vulkano::image::SampleCount;
 use vulkano::memory::allocator::FreeListAllocator;
 use vulkano::memory::allocator::GenericMemoryAllocator;
 use vulkano::memory::allocator::StandardMemoryAllocator;
+use vulkano::render_pass::AttachmentDescription;
+use vulkano::render_pass::AttachmentLoadOp;
+use vulkano::render_pass::AttachmentReference;
+use vulkano::render_pass::AttachmentStoreOp;
+use vulkano::render_pass::RenderPass;
+use vulkano::render_pass::RenderPassCreateInfo;
+use vulkano::render_pass::SubpassDependency;
+use vulkano::render_pass::SubpassDescription;
+use vulkano::sync::AccessFlags;
+use vulkano::sync::DependencyFlags;
+use vulkano::sync::PipelineStages;
 use vulkano::LoadingError;
 use vulkano::Validated;
 use vulkano::VulkanError;
 
+use super::commands::CommandRecorder;
+use super::commands::RecorderGlState;
 use super::devices::Devices;
+use super::dynamic_shader::PipelineCompiler;
+use super::error::McvkError;
 use super::glfw_window::GLFWWindow;
+use super::insn_assembler::RenderInsnAssembler;
+use super::render_manager::gl_read_region;
+use super::render_manager::set_paused;
+use super::render_manager::DiagnosticsSnapshot;
+use super::render_manager::Pausable;
 use super::render_manager::RenderManager;
+use super::sandbox::put_sandbox;
+use super::sandbox::take_sandbox;
+use super::sandbox::with_render_sandbox;
+use super::sandbox::RenderSandbox;
+use super::shaders::programs::ProgramRegistry;
+use super::swapchain::clamp_sample_count;
+use super::swapchain::AttachmentFormats;
+use super::swapchain::ColorLoadOp;
+use super::swapchain::FrameAttachment;
+use super::swapchain::FrameBoundary;
+use super::swapchain::RenderMode;
+use super::swapchain::SettingsChanges;
+use super::swapchain::SettingsDelta;
+use super::swapchain::SplitRenderPasses;
 use super::swapchain::SwapchainManager;
 use super::swapchain::VsyncMode;
+use super::swapchain::WindowSettings;
+use super::swapchain::OBJECT_ID_LOCATION;
 use super::textures::texture_manager::TextureManager;
+use super::textures::texture_manager::TextureQuality;
 use super::utils::Ref;
+use super::workers::RenderWorker;
 
 pub static MAIN_THREAD: AtomicU64 = AtomicU64::new(0);
 
@@ -40,7 +81,7 @@ pub enum VulkanInitError {
 #[derive(Debug)]
 pub struct Allocators {
     pub memory_allocator: Arc<GenericMemoryAllocator<FreeListAllocator>>,
-    pub descriptor_set_allocator: StandardDescriptorSetAllocator,
+    pub descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
     pub command_buffer_allocator: StandardCommandBufferAllocator,
 }
 
@@ -50,10 +91,10 @@ impl Allocators {
             memory_allocator: Arc::new(StandardMemoryAllocator::new_default(
                 devices.read().device.clone(),
             )),
-            descriptor_set_allocator: StandardDescriptorSetAllocator::new(
+            descriptor_set_allocator: Arc::new(StandardDescriptorSetAllocator::new(
                 devices.read().device.clone(),
                 Default::default(),
-            ),
+            )),
             command_buffer_allocator: StandardCommandBufferAllocator::new(
                 devices.read().device.clone(),
                 Default::default(),
@@ -69,6 +110,12 @@ pub struct MCVK {
     pub swapchain: Ref<SwapchainManager>,
     pub textures: Ref<TextureManager>,
     pub rendering: Ref<RenderManager>,
+    pub pipelines: Ref<PipelineCompiler>,
+    pub programs: Ref<ProgramRegistry>,
+    /// Records the commands the GL thread's assembler sends, see [`MCVK::attach_gl_thread`]
+    worker: Option<RenderWorker>,
+    /// What the last recorder left the viewports and scissor at
+    gl_state: Option<RecorderGlState>,
 }
 
 unsafe impl Send for MCVK {}
@@ -86,8 +133,10 @@ impl MCVK {
         let memory_allocator = Arc::new(StandardMemoryAllocator::new_default(
             devices.read().device.clone(),
         ));
-        let descriptor_set_allocator =
-            StandardDescriptorSetAllocator::new(devices.read().device.clone(), Default::default());
+        let descriptor_set_allocator = Arc::new(StandardDescriptorSetAllocator::new(
+            devices.read().device.clone(),
+            Default::default(),
+        ));
         let command_buffer_allocator =
             StandardCommandBufferAllocator::new(devices.read().device.clone(), Default::default());
 
@@ -103,50 +152,23 @@ impl MCVK {
             allocators.clone(),
         ));
 
-        let render_pass = vulkano::ordered_passes_renderpass!(devices.read().device.clone(),
-            attachments: {
-                color: {
-                    format: swapchain.read().image_format.clone().unwrap(),
-                    samples: 1,
-                    load_op: Load,
-                    store_op: Store,
-                    initial_layout: ImageLayout::Preinitialized,
-                    final_layout: ImageLayout::ColorAttachmentOptimal
-                },
-                normals: {
-                    format: Format::R16G16B16A16_SFLOAT,
-                    samples: 1,
-                    load_op: Clear,
-                    store_op: DontCare,
-                    initial_layout: ImageLayout::Undefined,
-                    final_layout: ImageLayout::ColorAttachmentOptimal
-                },
-                depth: {
-                    format: Format::D16_UNORM,
-                    samples: 1,
-                    load_op: Clear,
-                    store_op: DontCare,
-                    initial_layout: ImageLayout::Undefined,
-                    final_layout: ImageLayout::DepthStencilAttachmentOptimal
-                }
-            },
-            passes: [
-                {
-                    color: [color],
-                    depth_stencil: {depth},
-                    input: []
-                }
-            ]
-        )
-        .unwrap();
+        let (render_pass, split) = create_render_passes(&devices, &swapchain.read());
 
-        swapchain.write().render_pass = Some(render_pass.clone());
+        swapchain.write().render_pass = Some(render_pass);
+        swapchain.write().split_render_passes = Some(split);
         swapchain.write().create_framebuffers();
 
         let rendering = Ref::new(RenderManager::new(&allocators, &devices, &swapchain));
 
+        let pipelines = Ref::new(PipelineCompiler::new(
+            devices.read().device.clone(),
+            swapchain.clone(),
+        ));
+
         let textures = Ref::new(TextureManager::new(&allocators, &rendering));
 
+        let programs = Ref::new(ProgramRegistry::default());
+
         Ok(Self {
             window,
             devices,
@@ -154,26 +176,762 @@ impl MCVK {
             swapchain,
             textures,
             rendering,
+            pipelines,
+            programs,
+            worker: None,
+            gl_state: None,
         })
     }
 }
 
+/// The main render pass, and the ones a frame is recorded in when glFlush splits it
+fn create_render_passes(
+    devices: &Ref<Devices>,
+    swapchain: &SwapchainManager,
+) -> (Arc<RenderPass>, SplitRenderPasses) {
+    let create_info = render_pass_create_info(
+        &swapchain.window_settings,
+        swapchain.attachment_formats().unwrap(),
+    );
+
+    let device = devices.read().device.clone();
+    let create = |create_info| RenderPass::new(device.clone(), create_info).unwrap();
+
+    let split = SplitRenderPasses {
+        first: create(split_create_info(create_info.clone())),
+        middle: create(split_create_info(continuation_create_info(
+            create_info.clone(),
+        ))),
+        last: create(continuation_create_info(create_info.clone())),
+        first_use: create(first_use_create_info(create_info.clone())),
+        first_use_split: create(first_use_create_info(split_create_info(
+            create_info.clone(),
+        ))),
+    };
+
+    (create(create_info), split)
+}
+
+/// Describes the main render pass. Forward rendering has a single subpass which draws into the
+/// swapchain image, or into a multisampled colour attachment which is resolved into it. Deferred
+/// rendering draws into the g-buffer first, then lights it into the swapchain image in a second
+/// subpass.
+pub fn render_pass_create_info(
+    settings: &WindowSettings,
+    formats: AttachmentFormats,
+) -> RenderPassCreateInfo {
+    let attachments = settings.attachments();
+    let samples = settings.samples();
+    let multisampled = samples != SampleCount::Sample1;
+
+    let index_of = |attachment: FrameAttachment| {
+        attachments.iter().position(|a| *a == attachment).unwrap() as u32
+    };
+
+    let reference = |attachment: FrameAttachment, layout: ImageLayout| {
+        Some(AttachmentReference {
+            attachment: index_of(attachment),
+            layout,
+            ..Default::default()
+        })
+    };
+
+    // None leaves a colour location without an attachment
+    let subpass = |color: &[Option<FrameAttachment>],
+                   resolve: &[FrameAttachment],
+                   depth: bool,
+                   input: &[FrameAttachment]| {
+        let used = color
+            .iter()
+            .flatten()
+            .chain(resolve)
+            .chain(input)
+            .chain(depth.then_some(&FrameAttachment::Depth))
+            .copied()
+            .collect::<Vec<_>>();
+
+        SubpassDescription {
+            color_attachments: color
+                .iter()
+                .map(|a| a.and_then(|a| reference(a, ImageLayout::ColorAttachmentOptimal)))
+                .collect(),
+            color_resolve_attachments: resolve
+                .iter()
+                .map(|a| reference(*a, ImageLayout::ColorAttachmentOptimal))
+                .collect(),
+            depth_stencil_attachment: depth
+                .then(|| {
+                    reference(
+                        FrameAttachment::Depth,
+                        ImageLayout::DepthStencilAttachmentOptimal,
+                    )
+                })
+                .flatten(),
+            input_attachments: input
+                .iter()
+                .map(|a| reference(*a, ImageLayout::ShaderReadOnlyOptimal))
+                .collect(),
+            preserve_attachments: attachments
+                .iter()
+                .filter(|a| !used.contains(a))
+                .map(|a| index_of(*a))
+                .collect(),
+            ..Default::default()
+        }
+    };
+
+    // object ids are written to OBJECT_ID_LOCATION, after the geometry subpass' g-buffer
+    let with_object_ids = |colors: &[Option<FrameAttachment>]| {
+        let mut colors = colors.to_vec();
+
+        if settings.writes_object_ids() {
+            colors.resize(OBJECT_ID_LOCATION, None);
+            colors.push(Some(FrameAttachment::ObjectIds));
+        }
+
+        colors
+    };
+
+    let (subpasses, dependencies) = match settings.render_mode {
+        RenderMode::Forward if multisampled => (
+            vec![subpass(
+                &[Some(FrameAttachment::MultisampledColor)],
+                &[FrameAttachment::Swapchain],
+                true,
+                &[],
+            )],
+            vec![],
+        ),
+        RenderMode::Forward => (
+            vec![subpass(
+                &with_object_ids(&[Some(FrameAttachment::Swapchain)]),
+                &[],
+                true,
+                &[],
+            )],
+            vec![],
+        ),
+        RenderMode::Deferred => (
+            vec![
+                subpass(
+                    &with_object_ids(&[
+                        Some(FrameAttachment::Albedo),
+                        Some(FrameAttachment::Normals),
+                    ]),
+                    &[],
+                    true,
+                    &[],
+                ),
+                subpass(
+                    &[Some(FrameAttachment::Swapchain)],
+                    &[],
+                    false,
+                    &[FrameAttachment::Albedo, FrameAttachment::Normals],
+                ),
+            ],
+            // the lighting subpass reads the g-buffer pixel the geometry subpass wrote
+            vec![SubpassDependency {
+                src_subpass: Some(0),
+                dst_subpass: Some(1),
+                src_stages: PipelineStages::COLOR_ATTACHMENT_OUTPUT,
+                dst_stages: PipelineStages::FRAGMENT_SHADER,
+                src_access: AccessFlags::COLOR_ATTACHMENT_WRITE,
+                dst_access: AccessFlags::INPUT_ATTACHMENT_READ,
+                dependency_flags: DependencyFlags::BY_REGION,
+                ..Default::default()
+            }],
+        ),
+    };
+
+    RenderPassCreateInfo {
+        attachments: attachments
+            .iter()
+            .map(|attachment| {
+                let (load_op, store_op, initial_layout) = match attachment {
+                    // only ever written by the resolve
+                    FrameAttachment::Swapchain if multisampled => (
+                        AttachmentLoadOp::DontCare,
+                        AttachmentStoreOp::Store,
+                        ImageLayout::Undefined,
+                    ),
+                    FrameAttachment::Swapchain => (
+                        match settings.color_load_op {
+                            ColorLoadOp::Clear => AttachmentLoadOp::Clear,
+                            ColorLoadOp::Preserve => AttachmentLoadOp::Load,
+                        },
+                        AttachmentStoreOp::Store,
+                        settings.color_load_op.initial_layout(),
+                    ),
+                    // never presented, so a preserved frame is left where the last one put it
+                    FrameAttachment::MultisampledColor => match settings.color_load_op {
+                        ColorLoadOp::Clear => (
+                            AttachmentLoadOp::Clear,
+                            AttachmentStoreOp::DontCare,
+                            ImageLayout::Undefined,
+                        ),
+                        ColorLoadOp::Preserve => (
+                            AttachmentLoadOp::Load,
+                            AttachmentStoreOp::Store,
+                            ImageLayout::ColorAttachmentOptimal,
+                        ),
+                    },
+                    // read back by pick
+                    FrameAttachment::ObjectIds => (
+                        AttachmentLoadOp::Clear,
+                        AttachmentStoreOp::Store,
+                        ImageLayout::Undefined,
+                    ),
+                    // only needed within the frame, unless a glFlush splits it (see
+                    // split_create_info)
+                    FrameAttachment::Albedo | FrameAttachment::Normals | FrameAttachment::Depth => {
+                        (
+                            AttachmentLoadOp::Clear,
+                            AttachmentStoreOp::DontCare,
+                            ImageLayout::Undefined,
+                        )
+                    }
+                };
+
+                AttachmentDescription {
+                    format: attachment.format(formats),
+                    samples: match attachment {
+                        FrameAttachment::Swapchain => SampleCount::Sample1,
+                        _ => samples,
+                    },
+                    load_op,
+                    store_op,
+                    initial_layout,
+                    final_layout: match attachment {
+                        FrameAttachment::Depth => ImageLayout::DepthStencilAttachmentOptimal,
+                        _ => ImageLayout::ColorAttachmentOptimal,
+                    },
+                    ..Default::default()
+                }
+            })
+            .collect(),
+        subpasses,
+        dependencies,
+        ..Default::default()
+    }
+}
+
+/// A variant of a render pass which ends in a glFlush. Everything is stored, since the rest of the
+/// frame continues from it.
+pub fn split_create_info(mut create_info: RenderPassCreateInfo) -> RenderPassCreateInfo {
+    for attachment in &mut create_info.attachments {
+        attachment.store_op = AttachmentStoreOp::Store;
+    }
+
+    create_info
+}
+
+/// The render pass a frame continues in once glFlush has ended its main one. It's compatible with
+/// the main render pass, so it uses the same framebuffers, but it loads the attachments in the
+/// layout the split render pass left them in instead of clearing them. The resolved swapchain
+/// image is still overwritten.
+pub fn continuation_create_info(mut create_info: RenderPassCreateInfo) -> RenderPassCreateInfo {
+    for attachment in &mut create_info.attachments {
+        if attachment.load_op != AttachmentLoadOp::DontCare {
+            attachment.load_op = AttachmentLoadOp::Load;
+            attachment.initial_layout = attachment.final_layout;
+        }
+    }
+
+    create_info
+}
+
+/// A variant of a render pass for the first frame drawn into a framebuffer. Its images are still
+/// Undefined then, so the attachments a preserved frame would load are cleared instead.
+pub fn first_use_create_info(mut create_info: RenderPassCreateInfo) -> RenderPassCreateInfo {
+    for attachment in &mut create_info.attachments {
+        if attachment.load_op == AttachmentLoadOp::Load {
+            attachment.load_op = AttachmentLoadOp::Clear;
+            attachment.initial_layout = ImageLayout::Undefined;
+        }
+    }
+
+    create_info
+}
+
 impl MCVK {
-    pub fn set_max_fps(&mut self, max_fps: Option<u32>) {
-        self.swapchain.write().window_settings.max_fps = max_fps;
+    /// Applies a settings change and rebuilds whatever depends on the changed settings
+    pub fn apply_settings(&mut self, delta: &SettingsDelta) -> Result<SettingsChanges, McvkError> {
+        let mut delta = delta.clone();
+
+        if let Some(requested) = delta.msaa {
+            // the colour, normal and depth attachments are all multisampled
+            let supported = {
+                let devices = self.devices.read();
+                let properties = devices.device.physical_device().properties();
+
+                properties.framebuffer_color_sample_counts
+                    & properties.framebuffer_depth_sample_counts
+            };
+
+            let msaa = clamp_sample_count(requested, supported);
+
+            if msaa != requested {
+                tracing::warn!(
+                    what = "the device does not support the requested sample count; using the closest one below it",
+                    ?requested,
+                    ?msaa
+                );
+            }
+
+            delta.msaa = Some(msaa);
+        }
+
+        let changes = self.swapchain.write().window_settings.apply(&delta);
+
+        if changes.rebuild_render_pass {
+            // in-flight frames still reference the old framebuffers
+            self.rendering
+                .write()
+                .flush()
+                .map_err(McvkError::pipeline)?;
+
+            let (render_pass, split) = create_render_passes(&self.devices, &self.swapchain.read());
+
+            let mut swapchain = self.swapchain.write();
+            swapchain.render_pass = Some(render_pass);
+            swapchain.split_render_passes = Some(split);
+            swapchain.create_framebuffers();
+        }
+
+        if changes.recreate_swapchain {
+            self.swapchain.write().recreate_swapchain = true;
+        }
+
+        if changes.change_present_mode {
+            self.swapchain.write().present_mode_changed = true;
+        }
+
+        if changes.invalidate_pipelines {
+            self.pipelines.write().invalidate();
+        }
+
+        if changes.replace_command_queue {
+            self.replace_command_queue();
+        }
+
+        with_render_sandbox(|sandbox| {
+            if let Some(asm) = sandbox.assembler_mut() {
+                self.configure_assembler(asm);
+            }
+        });
+
+        Ok(changes)
+    }
+
+    pub fn set_max_fps(&mut self, max_fps: Option<u32>) -> Result<(), McvkError> {
+        self.apply_settings(&SettingsDelta {
+            max_fps: Some(max_fps),
+            ..Default::default()
+        })?;
+
+        Ok(())
+    }
+
+    pub fn set_msaa(&mut self, msaa: SampleCount) -> Result<(), McvkError> {
+        self.apply_settings(&SettingsDelta {
+            msaa: Some(msaa),
+            ..Default::default()
+        })?;
+
+        Ok(())
+    }
+
+    pub fn set_vsync(&mut self, vsync: VsyncMode) -> Result<(), McvkError> {
+        self.apply_settings(&SettingsDelta {
+            vsync: Some(vsync),
+            ..Default::default()
+        })?;
+
+        Ok(())
     }
 
-    pub fn set_vsync(&mut self, vsync: VsyncMode) {
-        self.swapchain.write().window_settings.vsync = vsync;
-        self.swapchain.write().recreate_swapchain = true;
+    pub fn set_force_fifo(&mut self, force_fifo: bool) -> Result<(), McvkError> {
+        self.apply_settings(&SettingsDelta {
+            force_fifo: Some(force_fifo),
+            ..Default::default()
+        })?;
+
+        Ok(())
+    }
+
+    pub fn set_frame_boundary(&mut self, boundary: FrameBoundary) -> Result<(), McvkError> {
+        self.apply_settings(&SettingsDelta {
+            frame_boundary: Some(boundary),
+            ..Default::default()
+        })?;
+
+        Ok(())
+    }
+
+    pub fn set_render_mode(&mut self, mode: RenderMode) -> Result<(), McvkError> {
+        self.apply_settings(&SettingsDelta {
+            render_mode: Some(mode),
+            ..Default::default()
+        })?;
+
+        Ok(())
+    }
+
+    pub fn set_object_picking(&mut self, picking: bool) -> Result<(), McvkError> {
+        self.apply_settings(&SettingsDelta {
+            object_picking: Some(picking),
+            ..Default::default()
+        })?;
+
+        Ok(())
+    }
+
+    pub fn set_command_queue_capacity(&mut self, capacity: Option<usize>) -> Result<(), McvkError> {
+        self.apply_settings(&SettingsDelta {
+            command_queue_capacity: Some(capacity),
+            ..Default::default()
+        })?;
+
+        Ok(())
+    }
+
+    pub fn frame_boundary(&self) -> FrameBoundary {
+        self.swapchain.read().window_settings.frame_boundary
+    }
+
+    /// Minecraft's "Mipmap Levels" video setting
+    pub fn set_mipmap_levels(&mut self, mipmap_levels: u32) {
+        let mut textures = self.textures.write();
+
+        let quality = TextureQuality {
+            mipmap_levels,
+            ..textures.quality
+        };
+
+        textures.set_quality(quality);
+    }
+
+    /// Minecraft's "Anisotropic Filtering" video setting
+    pub fn set_anisotropy(&mut self, anisotropy: u32) {
+        let mut textures = self.textures.write();
+
+        let quality = TextureQuality {
+            anisotropy,
+            ..textures.quality
+        };
+
+        textures.set_quality(quality);
+    }
+
+    /// Whether resource reloads upload their textures in the background instead of stalling
+    /// until they're on the gpu
+    pub fn set_async_texture_reload(&mut self, async_reload: bool) {
+        self.textures.write().async_reload = async_reload;
+    }
+
+    /// Times frames on the gpu for the debug overlay, see [`RenderManager::set_gpu_timing`]
+    pub fn set_gpu_timing(&mut self, enabled: bool) {
+        self.rendering.write().set_gpu_timing(enabled);
+    }
+
+    /// Stops rendering frames until unpaused, for when the window is minimized or unfocused.
+    pub fn set_paused(&mut self, paused: bool) -> Result<(), McvkError> {
+        set_paused(self, paused).map_err(McvkError::pipeline)
+    }
+
+    /// Everything the debug overlay shows, gathered in one go
+    pub fn diagnostics(&self) -> DiagnosticsSnapshot {
+        let pipelines = self.pipelines.read();
+
+        DiagnosticsSnapshot {
+            pipeline_count: pipelines.pipeline_count() as u32,
+            shader_cache_hit_rate: pipelines.shader_cache_stats().hit_rate(),
+            texture_vram_bytes: self.textures.read().texture_storage.vram_estimate(),
+            ..self.rendering.read().diagnostics()
+        }
+    }
+
+    /// Loads the pipeline cache from `path`, and saves it there again on shutdown
+    pub fn set_pipeline_cache_path(&mut self, path: PathBuf) {
+        self.pipelines.write().load_pipeline_cache(path);
+    }
+
+    pub fn save_pipeline_cache(&self) -> Result<(), McvkError> {
+        self.pipelines
+            .read()
+            .save_pipeline_cache()
+            .map_err(McvkError::pipeline)
     }
 }
 
 impl MCVK {
-    /// Colour clears are the boundary marker between frames. We use this to sync pretty much everything.
-    pub fn on_clear_colour(&mut self) {
+    /// Makes the calling thread the one GL calls are made from: its render sandbox assembles them
+    /// into render commands, which are recorded into each frame before it's submitted.
+    pub fn attach_gl_thread(&mut self) {
+        let capacity = self.swapchain.read().window_settings.command_queue_capacity;
+
+        let (mut worker, commands) = RenderWorker::new(capacity);
+        worker.arena = self.rendering.read().arena().clone();
+
+        let mut asm = RenderInsnAssembler::new(commands, Some(self.textures.clone()));
+        asm.arena = worker.arena.clone();
+        self.configure_assembler(&mut asm);
+
+        take_sandbox();
+        put_sandbox(RenderSandbox::Assembler(Box::new(asm)));
+
+        self.worker = Some(worker);
+    }
+
+    /// Hands the GL thread's assembler a queue with the configured capacity. What it sent through
+    /// the old one is still recorded, see [`RenderWorker::replace_queue`].
+    fn replace_command_queue(&mut self) {
+        let Some(worker) = self.worker.as_mut() else {
+            return;
+        };
+
+        let capacity = self.swapchain.read().window_settings.command_queue_capacity;
+
+        with_render_sandbox(|sandbox| {
+            if let Some(asm) = sandbox.assembler_mut() {
+                asm.commands = worker.replace_queue(capacity);
+            }
+        });
+    }
+
+    /// Tells the assembler what the device and the render pass support
+    fn configure_assembler(&self, asm: &mut RenderInsnAssembler) {
+        let window_settings = &self.swapchain.read().window_settings;
+
+        asm.framebuffer_samples = window_settings.samples();
+        asm.object_picking = window_settings.writes_object_ids();
+
+        let devices = self.devices.read();
+
+        asm.max_push_constants_size = devices
+            .device
+            .physical_device()
+            .properties()
+            .max_push_constants_size;
+        asm.supports_clip_distance = devices.device.enabled_features().shader_clip_distance;
+        asm.max_texture_units = devices.max_texture_units();
+    }
+
+    /// Hands the assembler the arena of the frame that's now being recorded, once the last one
+    /// has ended
+    fn switch_arenas(&mut self) {
+        let Some(worker) = self.worker.as_mut() else {
+            return;
+        };
+
+        worker.arena = self.rendering.read().arena().clone();
+
+        with_render_sandbox(|sandbox| {
+            if let Some(asm) = sandbox.assembler_mut() {
+                asm.arena = worker.arena.clone();
+            }
+        });
+    }
+
+    /// Records the commands which have piled up in a full
+    /// [`CommandQueue::Bounded`](super::commands::CommandQueue::Bounded) on the render thread,
+    /// which can't wait for the worker to make room. The frame is started early if it hasn't been
+    /// yet.
+    pub fn record_backlog(&mut self) {
+        {
+            let mut rendering = self.rendering.write();
+
+            if !rendering.is_paused() && !rendering.is_frame_in_progress() {
+                rendering.start_frame();
+            }
+        }
+
+        self.record_commands();
+    }
+
+    /// Records the render commands the GL thread has sent so far into the frame in progress. The
+    /// renderer isn't held while they're recorded, since recording locks the textures.
+    fn record_commands(&mut self) {
+        let Some(worker) = self.worker.as_mut() else {
+            return;
+        };
+
+        if self.rendering.read().is_paused() {
+            // there's no frame to draw them into
+            worker.drain_into(|_| {});
+            return;
+        }
+
+        let Some(recording) = self.rendering.write().take_commands() else {
+            return;
+        };
+
+        let (memory_allocator, descriptor_set_allocator) = {
+            let allocators = self.allocators.read();
+
+            (
+                allocators.memory_allocator.clone(),
+                allocators.descriptor_set_allocator.clone(),
+            )
+        };
+
+        let mut recorder = CommandRecorder::new(
+            memory_allocator,
+            descriptor_set_allocator,
+            recording.commands,
+            recording.draw_buffers,
+            self.pipelines.clone(),
+            recording.framebuffer_extent,
+        );
+        recorder.textures = Some(self.textures.clone());
+        recorder.arena = worker.arena.clone();
+
+        if let Some(state) = self.gl_state.take() {
+            recorder.restore_gl_state(state);
+        }
+
+        worker.drain(&mut recorder);
+
+        self.gl_state = Some(recorder.gl_state());
+
+        self.rendering.write().return_commands(
+            recorder.builder,
+            recorder.draw_buffers,
+            &recorder.draw_stats,
+        );
+    }
+
+    /// Ends the frame in progress and presents it. Colour clears call this unless the frame
+    /// boundary is [`FrameBoundary::Explicit`]. We use this to sync pretty much everything.
+    pub fn present(&mut self) {
+        if self.rendering.read().is_paused() {
+            self.record_commands();
+            return;
+        }
+
         // by this point all possible render insns have been generated, stored, and ideally transformed into render commands
-        // this function should submit the render commands and do all swapchain swapping
-        // for now we will make this call blocking but it must be non-blocking for good performance (record all insns and generate the commands -vsync> submit & draw)
+        // for now this call is blocking but it must be non-blocking for good performance (record all insns and generate the commands -vsync> submit & draw)
+
+        // the textures must be locked before the renderer, same as in upload_texture_updates
+        let uploads = {
+            let mut textures = self.textures.write();
+
+            if let Err(e) = textures.poll_texture_reload() {
+                tracing::error!(what = "could not check on the texture reload", error = %e);
+            }
+
+            match textures.record_texture_updates() {
+                Ok(uploads) => uploads,
+                Err(e) => {
+                    tracing::error!(what = "could not record the texture updates", error = %e);
+                    None
+                }
+            }
+        };
+
+        {
+            let mut rendering = self.rendering.write();
+
+            if !rendering.is_frame_in_progress() {
+                rendering.start_frame();
+            }
+        }
+
+        self.record_commands();
+
+        self.rendering.write().end_frame(uploads);
+        self.switch_arenas();
+    }
+
+    /// glFlush: submits the draws recorded so far without ending the frame
+    pub fn flush(&mut self) -> Result<(), McvkError> {
+        if self.rendering.read().is_paused() {
+            self.record_commands();
+            return Ok(());
+        }
+
+        self.record_commands();
+
+        self.rendering
+            .write()
+            .flush_partial()
+            .map_err(McvkError::pipeline)
+    }
+
+    /// The texture uploads to submit along with a captured frame. They're only submitted if the
+    /// frame in progress is captured.
+    fn capture_uploads(&mut self) -> Result<Option<Arc<PrimaryAutoCommandBuffer>>, McvkError> {
+        if !self.rendering.read().is_frame_in_progress() {
+            return Ok(None);
+        }
+
+        // the draws made so far are part of the captured frame
+        self.record_commands();
+
+        self.textures
+            .write()
+            .record_texture_updates()
+            .map_err(McvkError::texture)
+    }
+
+    /// glReadPixels, which reads the most recently rendered frame whichever buffer is named.
+    /// Rows are returned bottom to top.
+    pub fn read_pixels(
+        &mut self,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    ) -> Result<RgbaImage, McvkError> {
+        let uploads = self.capture_uploads()?;
+
+        let frame = self.rendering.write().read_frame(uploads);
+        self.switch_arenas();
+
+        Ok(gl_read_region(
+            &frame.map_err(McvkError::pipeline)?,
+            x,
+            y,
+            width,
+            height,
+        ))
+    }
+
+    /// The glLoadName of whatever was drawn at a pixel of the most recently rendered frame, in GL
+    /// window coordinates. Like [`MCVK::read_pixels`], this submits the frame in progress.
+    pub fn pick(&mut self, x: u32, y: u32) -> Result<u32, McvkError> {
+        let uploads = self.capture_uploads()?;
+
+        let name = self.rendering.write().pick(uploads, x, y);
+        self.switch_arenas();
+
+        name.map_err(McvkError::pipeline)
+    }
+
+    /// Saves the next presented frame to `path`, see [`RenderManager::request_screenshot`]
+    pub fn request_screenshot(&mut self, path: PathBuf) {
+        self.rendering.write().request_screenshot(path);
+    }
+}
+
+impl Pausable for MCVK {
+    fn is_paused(&self) -> bool {
+        self.rendering.read().is_paused()
+    }
+
+    fn is_frame_in_progress(&self) -> bool {
+        self.rendering.read().is_frame_in_progress()
+    }
+
+    fn present(&mut self) {
+        MCVK::present(self);
+    }
+
+    fn pause(&mut self) -> Result<(), Validated<VulkanError>> {
+        self.rendering.write().pause()
+    }
+
+    fn resume(&mut self) {
+        self.rendering.write().resume();
     }
 }
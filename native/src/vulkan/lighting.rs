@@ -0,0 +1,261 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use vulkano::buffer::Buffer;
+use vulkano::buffer::BufferContents;
+use vulkano::buffer::BufferCreateInfo;
+use vulkano::buffer::BufferUsage;
+use vulkano::buffer::Subbuffer;
+use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::command_buffer::PrimaryAutoCommandBuffer;
+use vulkano::command_buffer::SubpassBeginInfo;
+use vulkano::command_buffer::SubpassContents;
+use vulkano::command_buffer::SubpassEndInfo;
+use vulkano::descriptor_set::PersistentDescriptorSet;
+use vulkano::descriptor_set::WriteDescriptorSet;
+use vulkano::device::Device;
+use vulkano::memory::allocator::AllocationCreateInfo;
+use vulkano::memory::allocator::MemoryTypeFilter;
+use vulkano::pipeline::graphics::color_blend::AttachmentBlend;
+use vulkano::pipeline::graphics::color_blend::BlendFactor;
+use vulkano::pipeline::graphics::color_blend::BlendOp;
+use vulkano::pipeline::graphics::color_blend::ColorBlendAttachmentState;
+use vulkano::pipeline::graphics::color_blend::ColorBlendState;
+use vulkano::pipeline::graphics::input_assembly::InputAssemblyState;
+use vulkano::pipeline::graphics::multisample::MultisampleState;
+use vulkano::pipeline::graphics::rasterization::RasterizationState;
+use vulkano::pipeline::graphics::subpass::PipelineSubpassType;
+use vulkano::pipeline::graphics::vertex_input::VertexInputState;
+use vulkano::pipeline::graphics::viewport::ViewportState;
+use vulkano::pipeline::graphics::GraphicsPipelineCreateInfo;
+use vulkano::pipeline::layout::PipelineDescriptorSetLayoutCreateInfo;
+use vulkano::pipeline::DynamicState;
+use vulkano::pipeline::GraphicsPipeline;
+use vulkano::pipeline::Pipeline;
+use vulkano::pipeline::PipelineBindPoint;
+use vulkano::pipeline::PipelineLayout;
+use vulkano::pipeline::PipelineShaderStageCreateInfo;
+use vulkano::render_pass::Framebuffer;
+use vulkano::render_pass::RenderPass;
+use vulkano::render_pass::Subpass;
+
+use super::dynamic_shader::compile_glsl;
+use super::instance::Allocators;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, BufferContents)]
+pub struct AmbientLight {
+    pub color: [f32; 3],
+    pub intensity: f32,
+}
+
+impl Default for AmbientLight {
+    fn default() -> Self {
+        Self {
+            color: [1.0; 3],
+            intensity: 1.0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, BufferContents)]
+pub struct DirectionalLight {
+    pub position: [f32; 4],
+    pub color: [f32; 3],
+}
+
+pub const AMBIENT_FRAG: &str = include_str!("../shaders/ambient.frag");
+pub const DIRECTIONAL_FRAG: &str = include_str!("../shaders/directional.frag");
+
+/// Lights add onto the colour, but only ever pass the albedo's alpha through, so that lit
+/// translucent geometry keeps its vertex alpha
+pub fn light_blend() -> AttachmentBlend {
+    AttachmentBlend {
+        src_color_blend_factor: BlendFactor::One,
+        dst_color_blend_factor: BlendFactor::One,
+        color_blend_op: BlendOp::Add,
+        src_alpha_blend_factor: BlendFactor::One,
+        dst_alpha_blend_factor: BlendFactor::Zero,
+        alpha_blend_op: BlendOp::Add,
+    }
+}
+
+/// The pipelines for the lighting subpass of [`RenderMode::Deferred`](super::swapchain::RenderMode).
+/// Each light is drawn as a fullscreen triangle which is added onto the swapchain image.
+pub struct LightingPipelines {
+    render_pass: Arc<RenderPass>,
+    ambient: Arc<GraphicsPipeline>,
+    directional: Arc<GraphicsPipeline>,
+}
+
+impl LightingPipelines {
+    pub fn new(device: &Arc<Device>, render_pass: &Arc<RenderPass>) -> Self {
+        let vert = compile_glsl(
+            device,
+            glslang::ShaderStage::Vertex,
+            include_str!("../shaders/fullscreen.vert").to_owned(),
+        );
+
+        let ambient_frag = compile_glsl(
+            device,
+            glslang::ShaderStage::Fragment,
+            AMBIENT_FRAG.to_owned(),
+        );
+
+        let directional_frag = compile_glsl(
+            device,
+            glslang::ShaderStage::Fragment,
+            DIRECTIONAL_FRAG.to_owned(),
+        );
+
+        let subpass = Subpass::from(render_pass.clone(), 1).unwrap();
+
+        let create_pipeline = |frag: PipelineShaderStageCreateInfo| {
+            let stages = [
+                PipelineShaderStageCreateInfo::new(vert.entry_point("main").unwrap()),
+                frag,
+            ];
+
+            let layout = PipelineLayout::new(
+                device.clone(),
+                PipelineDescriptorSetLayoutCreateInfo::from_stages(&stages)
+                    .into_pipeline_layout_create_info(device.clone())
+                    .unwrap(),
+            )
+            .unwrap();
+
+            GraphicsPipeline::new(
+                device.clone(),
+                None,
+                GraphicsPipelineCreateInfo {
+                    stages: stages.into_iter().collect(),
+                    vertex_input_state: Some(VertexInputState::new()),
+                    input_assembly_state: Some(InputAssemblyState::default()),
+                    viewport_state: Some(ViewportState::default()),
+                    rasterization_state: Some(RasterizationState::default()),
+                    multisample_state: Some(MultisampleState::default()),
+                    // every light adds onto what the previous ones drew
+                    color_blend_state: Some(ColorBlendState::with_attachment_states(
+                        subpass.num_color_attachments(),
+                        ColorBlendAttachmentState {
+                            blend: Some(light_blend()),
+                            ..Default::default()
+                        },
+                    )),
+                    dynamic_state: [DynamicState::Viewport].into_iter().collect(),
+                    subpass: Some(PipelineSubpassType::BeginRenderPass(subpass.clone())),
+                    ..GraphicsPipelineCreateInfo::layout(layout)
+                },
+            )
+            .unwrap()
+        };
+
+        Self {
+            render_pass: render_pass.clone(),
+            ambient: create_pipeline(PipelineShaderStageCreateInfo::new(
+                ambient_frag.entry_point("main").unwrap(),
+            )),
+            directional: create_pipeline(PipelineShaderStageCreateInfo::new(
+                directional_frag.entry_point("main").unwrap(),
+            )),
+        }
+    }
+
+    /// Whether these pipelines were built against the given render pass. They have to be rebuilt
+    /// whenever the render pass is.
+    pub fn is_for(&self, render_pass: &Arc<RenderPass>) -> bool {
+        Arc::ptr_eq(&self.render_pass, render_pass)
+    }
+
+    /// Moves to the lighting subpass and lights the g-buffer. The viewport must already be set.
+    pub fn record(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        allocators: &Allocators,
+        framebuffer: &Arc<Framebuffer>,
+        ambient: AmbientLight,
+        directionals: &[DirectionalLight],
+    ) -> Result<()> {
+        // see RenderMode::attachments for the order
+        let albedo = framebuffer.attachments()[1].clone();
+        let normals = framebuffer.attachments()[2].clone();
+
+        builder.next_subpass(
+            SubpassEndInfo::default(),
+            SubpassBeginInfo {
+                contents: SubpassContents::Inline,
+                ..Default::default()
+            },
+        )?;
+
+        let ambient_set = PersistentDescriptorSet::new(
+            &allocators.descriptor_set_allocator,
+            self.ambient.layout().set_layouts()[0].clone(),
+            [
+                WriteDescriptorSet::image_view(0, albedo.clone()),
+                WriteDescriptorSet::buffer(1, uniform_buffer(allocators, ambient)?),
+            ],
+            [],
+        )?;
+
+        builder
+            .bind_pipeline_graphics(self.ambient.clone())?
+            .bind_descriptor_sets(
+                PipelineBindPoint::Graphics,
+                self.ambient.layout().clone(),
+                0,
+                ambient_set,
+            )?
+            .draw(3, 1, 0, 0)?;
+
+        if directionals.is_empty() {
+            return Ok(());
+        }
+
+        builder.bind_pipeline_graphics(self.directional.clone())?;
+
+        for light in directionals {
+            let set = PersistentDescriptorSet::new(
+                &allocators.descriptor_set_allocator,
+                self.directional.layout().set_layouts()[0].clone(),
+                [
+                    WriteDescriptorSet::image_view(0, albedo.clone()),
+                    WriteDescriptorSet::image_view(1, normals.clone()),
+                    WriteDescriptorSet::buffer(2, uniform_buffer(allocators, *light)?),
+                ],
+                [],
+            )?;
+
+            builder
+                .bind_descriptor_sets(
+                    PipelineBindPoint::Graphics,
+                    self.directional.layout().clone(),
+                    0,
+                    set,
+                )?
+                .draw(3, 1, 0, 0)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn uniform_buffer<T>(allocators: &Allocators, data: T) -> Result<Subbuffer<T>>
+where
+    T: BufferContents,
+{
+    Ok(Buffer::from_data(
+        allocators.memory_allocator.clone(),
+        BufferCreateInfo {
+            usage: BufferUsage::UNIFORM_BUFFER,
+            ..Default::default()
+        },
+        AllocationCreateInfo {
+            memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+            ..Default::default()
+        },
+        data,
+    )?)
+}
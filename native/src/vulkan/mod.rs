@@ -1,9 +1,15 @@
+pub mod arena;
+pub mod buffer_objects;
 pub mod commands;
 pub mod devices;
+pub mod display_lists;
 pub mod dynamic_shader;
+pub mod error;
 pub mod glfw_window;
 pub mod insn_assembler;
 pub mod instance;
+pub mod lighting;
+pub mod provoking_vertex;
 pub mod render_manager;
 pub mod sandbox;
 pub mod sandbox_jni;
@@ -14,7 +20,17 @@ pub mod textures;
 pub mod utils;
 pub mod workers;
 
+#[cfg(test)]
+mod devices_tests;
 #[cfg(test)]
 mod dynpipe_tests;
 #[cfg(test)]
+mod frame_tests;
+#[cfg(test)]
+mod settings_tests;
+#[cfg(test)]
 mod shim_tests;
+#[cfg(test)]
+mod spinlock_tests;
+#[cfg(test)]
+mod texture_tests;
@@ -0,0 +1,345 @@
+use std::ffi::CString;
+use std::mem::MaybeUninit;
+use std::ptr;
+use std::sync::Arc;
+
+use vulkano::device::Device;
+use vulkano::pipeline::cache::PipelineCache;
+use vulkano::pipeline::graphics::subpass::PipelineSubpassType;
+use vulkano::pipeline::graphics::GraphicsPipelineCreateInfo;
+use vulkano::pipeline::GraphicsPipeline;
+use vulkano::shader::ShaderStage;
+use vulkano::Validated;
+use vulkano::ValidationError;
+use vulkano::VulkanError;
+use vulkano::VulkanObject;
+
+use super::sandbox::ProvokingVertex;
+
+pub fn provoking_vertex_mode(provoking_vertex: ProvokingVertex) -> ash::vk::ProvokingVertexModeEXT {
+    match provoking_vertex {
+        ProvokingVertex::First => ash::vk::ProvokingVertexModeEXT::FIRST_VERTEX,
+        ProvokingVertex::Last => ash::vk::ProvokingVertexModeEXT::LAST_VERTEX,
+    }
+}
+
+/// Creates a pipeline the way [`GraphicsPipeline::new`] does, with a
+/// VkPipelineRasterizationProvokingVertexStateCreateInfoEXT chained onto its rasterization state,
+/// since vulkano doesn't expose it. Only the states the
+/// [`PipelineCompiler`](super::dynamic_shader::PipelineCompiler) fills in are passed on: no
+/// tessellation, instancing, specialization constants or dynamic rendering.
+///
+/// # Safety
+///
+/// vulkano doesn't validate `create_info`, so it must be one that [`GraphicsPipeline::new`]
+/// accepts. The device must have VK_EXT_provoking_vertex enabled, and `provokingVertexLast` for
+/// [`ProvokingVertex::Last`]. The states it leaves out are reported as a validation error rather
+/// than checked.
+pub unsafe fn new_graphics_pipeline(
+    device: Arc<Device>,
+    cache: Option<Arc<PipelineCache>>,
+    create_info: GraphicsPipelineCreateInfo,
+    provoking_vertex: ProvokingVertex,
+) -> Result<Arc<GraphicsPipeline>, Validated<VulkanError>> {
+    let names = create_info
+        .stages
+        .iter()
+        .map(|stage| {
+            CString::new(stage.entry_point.info().name.as_str()).map_err(|_| {
+                invalid(
+                    "create_info.stages",
+                    "an entry point's name contains a nul byte",
+                )
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let stages = create_info
+        .stages
+        .iter()
+        .zip(&names)
+        .map(|(stage, name)| ash::vk::PipelineShaderStageCreateInfo {
+            flags: stage.flags.into(),
+            stage: ShaderStage::from(stage.entry_point.info().execution_model).into(),
+            module: stage.entry_point.module().handle(),
+            p_name: name.as_ptr(),
+            ..Default::default()
+        })
+        .collect::<Vec<_>>();
+
+    let vertex_input = create_info
+        .vertex_input_state
+        .as_ref()
+        .ok_or_else(|| invalid("create_info.vertex_input_state", "is None"))?;
+
+    let bindings = vertex_input
+        .bindings
+        .iter()
+        .map(|(&binding, desc)| ash::vk::VertexInputBindingDescription {
+            binding,
+            stride: desc.stride,
+            input_rate: desc.input_rate.into(),
+        })
+        .collect::<Vec<_>>();
+
+    let attributes = vertex_input
+        .attributes
+        .iter()
+        .map(
+            |(&location, desc)| ash::vk::VertexInputAttributeDescription {
+                location,
+                binding: desc.binding,
+                format: desc.format.into(),
+                offset: desc.offset,
+            },
+        )
+        .collect::<Vec<_>>();
+
+    let vertex_input_vk = ash::vk::PipelineVertexInputStateCreateInfo {
+        vertex_binding_description_count: bindings.len() as u32,
+        p_vertex_binding_descriptions: bindings.as_ptr(),
+        vertex_attribute_description_count: attributes.len() as u32,
+        p_vertex_attribute_descriptions: attributes.as_ptr(),
+        ..Default::default()
+    };
+
+    let input_assembly = create_info
+        .input_assembly_state
+        .as_ref()
+        .ok_or_else(|| invalid("create_info.input_assembly_state", "is None"))?;
+
+    let input_assembly_vk = ash::vk::PipelineInputAssemblyStateCreateInfo {
+        topology: input_assembly.topology.into(),
+        primitive_restart_enable: input_assembly.primitive_restart_enable as ash::vk::Bool32,
+        ..Default::default()
+    };
+
+    let viewport_state = create_info
+        .viewport_state
+        .as_ref()
+        .ok_or_else(|| invalid("create_info.viewport_state", "is None"))?;
+    let viewports = viewport_state
+        .viewports
+        .iter()
+        .map(Into::into)
+        .collect::<Vec<ash::vk::Viewport>>();
+    let scissors = viewport_state
+        .scissors
+        .iter()
+        .map(Into::into)
+        .collect::<Vec<ash::vk::Rect2D>>();
+
+    let viewport_vk = ash::vk::PipelineViewportStateCreateInfo {
+        viewport_count: viewports.len() as u32,
+        p_viewports: viewports.as_ptr(),
+        scissor_count: scissors.len() as u32,
+        p_scissors: scissors.as_ptr(),
+        ..Default::default()
+    };
+
+    let provoking_vertex_vk = ash::vk::PipelineRasterizationProvokingVertexStateCreateInfoEXT {
+        provoking_vertex_mode: provoking_vertex_mode(provoking_vertex),
+        ..Default::default()
+    };
+
+    let rasterization = create_info
+        .rasterization_state
+        .as_ref()
+        .ok_or_else(|| invalid("create_info.rasterization_state", "is None"))?;
+
+    let (depth_bias_enable, depth_bias_constant_factor, depth_bias_clamp, depth_bias_slope_factor) =
+        match &rasterization.depth_bias {
+            Some(bias) => (
+                ash::vk::TRUE,
+                bias.constant_factor,
+                bias.clamp,
+                bias.slope_factor,
+            ),
+            None => (ash::vk::FALSE, 0.0, 0.0, 0.0),
+        };
+
+    let rasterization_vk = ash::vk::PipelineRasterizationStateCreateInfo {
+        p_next: &provoking_vertex_vk as *const _ as *const _,
+        depth_clamp_enable: rasterization.depth_clamp_enable as ash::vk::Bool32,
+        rasterizer_discard_enable: rasterization.rasterizer_discard_enable as ash::vk::Bool32,
+        polygon_mode: rasterization.polygon_mode.into(),
+        cull_mode: rasterization.cull_mode.into(),
+        front_face: rasterization.front_face.into(),
+        depth_bias_enable,
+        depth_bias_constant_factor,
+        depth_bias_clamp,
+        depth_bias_slope_factor,
+        line_width: rasterization.line_width,
+        ..Default::default()
+    };
+
+    let multisample = create_info
+        .multisample_state
+        .as_ref()
+        .ok_or_else(|| invalid("create_info.multisample_state", "is None"))?;
+
+    let multisample_vk = ash::vk::PipelineMultisampleStateCreateInfo {
+        rasterization_samples: multisample.rasterization_samples.into(),
+        sample_shading_enable: multisample.sample_shading.is_some() as ash::vk::Bool32,
+        min_sample_shading: multisample.sample_shading.unwrap_or(0.0),
+        p_sample_mask: multisample.sample_mask.as_ptr(),
+        alpha_to_coverage_enable: multisample.alpha_to_coverage_enable as ash::vk::Bool32,
+        alpha_to_one_enable: multisample.alpha_to_one_enable as ash::vk::Bool32,
+        ..Default::default()
+    };
+
+    let depth_stencil_vk = create_info.depth_stencil_state.as_ref().map(|state| {
+        let (depth_test_enable, depth_write_enable, depth_compare_op) = match &state.depth {
+            Some(depth) => (
+                ash::vk::TRUE,
+                depth.write_enable as ash::vk::Bool32,
+                depth.compare_op.into(),
+            ),
+            None => (ash::vk::FALSE, ash::vk::FALSE, ash::vk::CompareOp::ALWAYS),
+        };
+
+        let (depth_bounds_test_enable, min_depth_bounds, max_depth_bounds) =
+            match &state.depth_bounds {
+                Some(bounds) => (ash::vk::TRUE, *bounds.start(), *bounds.end()),
+                None => (ash::vk::FALSE, 0.0, 1.0),
+            };
+
+        let (stencil_test_enable, front, back) = match &state.stencil {
+            Some(stencil) => {
+                let [front, back] =
+                    [&stencil.front, &stencil.back].map(|face| ash::vk::StencilOpState {
+                        fail_op: face.ops.fail_op.into(),
+                        pass_op: face.ops.pass_op.into(),
+                        depth_fail_op: face.ops.depth_fail_op.into(),
+                        compare_op: face.ops.compare_op.into(),
+                        compare_mask: face.compare_mask,
+                        write_mask: face.write_mask,
+                        reference: face.reference,
+                    });
+
+                (ash::vk::TRUE, front, back)
+            }
+            None => (ash::vk::FALSE, Default::default(), Default::default()),
+        };
+
+        ash::vk::PipelineDepthStencilStateCreateInfo {
+            flags: state.flags.into(),
+            depth_test_enable,
+            depth_write_enable,
+            depth_compare_op,
+            depth_bounds_test_enable,
+            stencil_test_enable,
+            front,
+            back,
+            min_depth_bounds,
+            max_depth_bounds,
+            ..Default::default()
+        }
+    });
+
+    let color_blend = create_info
+        .color_blend_state
+        .as_ref()
+        .ok_or_else(|| invalid("create_info.color_blend_state", "is None"))?;
+
+    let blend_attachments = color_blend
+        .attachments
+        .iter()
+        .map(|attachment| ash::vk::PipelineColorBlendAttachmentState {
+            color_write_mask: attachment.color_write_mask.into(),
+            ..attachment
+                .blend
+                .map(ash::vk::PipelineColorBlendAttachmentState::from)
+                .unwrap_or_default()
+        })
+        .collect::<Vec<_>>();
+
+    let color_blend_vk = ash::vk::PipelineColorBlendStateCreateInfo {
+        flags: color_blend.flags.into(),
+        logic_op_enable: color_blend.logic_op.is_some() as ash::vk::Bool32,
+        logic_op: color_blend
+            .logic_op
+            .map(ash::vk::LogicOp::from)
+            .unwrap_or_default(),
+        attachment_count: blend_attachments.len() as u32,
+        p_attachments: blend_attachments.as_ptr(),
+        blend_constants: color_blend.blend_constants,
+        ..Default::default()
+    };
+
+    let dynamic_states = create_info
+        .dynamic_state
+        .iter()
+        .copied()
+        .map(Into::into)
+        .collect::<Vec<ash::vk::DynamicState>>();
+
+    let dynamic_vk = ash::vk::PipelineDynamicStateCreateInfo {
+        dynamic_state_count: dynamic_states.len() as u32,
+        p_dynamic_states: dynamic_states.as_ptr(),
+        ..Default::default()
+    };
+
+    let Some(PipelineSubpassType::BeginRenderPass(subpass)) = &create_info.subpass else {
+        return Err(invalid(
+            "create_info.subpass",
+            "pipelines with a provoking vertex mode must be drawn in a render pass",
+        ));
+    };
+
+    let create_info_vk = ash::vk::GraphicsPipelineCreateInfo {
+        flags: create_info.flags.into(),
+        stage_count: stages.len() as u32,
+        p_stages: stages.as_ptr(),
+        p_vertex_input_state: &vertex_input_vk,
+        p_input_assembly_state: &input_assembly_vk,
+        p_viewport_state: &viewport_vk,
+        p_rasterization_state: &rasterization_vk,
+        p_multisample_state: &multisample_vk,
+        p_depth_stencil_state: depth_stencil_vk
+            .as_ref()
+            .map_or(ptr::null(), |state| state as *const _),
+        p_color_blend_state: &color_blend_vk,
+        p_dynamic_state: if dynamic_states.is_empty() {
+            ptr::null()
+        } else {
+            &dynamic_vk
+        },
+        layout: create_info.layout.handle(),
+        render_pass: subpass.render_pass().handle(),
+        subpass: subpass.index(),
+        base_pipeline_index: -1,
+        ..Default::default()
+    };
+
+    let cache_handle = cache
+        .as_ref()
+        .map_or(ash::vk::PipelineCache::null(), |cache| cache.handle());
+
+    let mut output = MaybeUninit::uninit();
+
+    (device.fns().v1_0.create_graphics_pipelines)(
+        device.handle(),
+        cache_handle,
+        1,
+        &create_info_vk,
+        ptr::null(),
+        output.as_mut_ptr(),
+    )
+    .result()
+    .map_err(VulkanError::from)?;
+
+    Ok(GraphicsPipeline::from_handle(
+        device,
+        output.assume_init(),
+        create_info,
+    ))
+}
+
+fn invalid(context: &'static str, problem: &'static str) -> Validated<VulkanError> {
+    Validated::ValidationError(Box::new(ValidationError {
+        context: context.into(),
+        problem: problem.into(),
+        ..Default::default()
+    }))
+}
@@ -1,27 +1,59 @@
 use std::collections::HashMap;
 use std::collections::LinkedList;
+use std::ops::Range;
+use std::path::Path;
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
 
 use anyhow::Result;
 use enum_primitive::*;
+use image::RgbaImage;
 use nalgebra::Matrix4;
 use nalgebra_glm::TMat4;
+use vulkano::buffer::BufferContents;
+use vulkano::buffer::BufferUsage;
+use vulkano::buffer::Subbuffer;
 use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::command_buffer::BufferImageCopy;
 use vulkano::command_buffer::CommandBufferUsage;
+use vulkano::command_buffer::CopyImageToBufferInfo;
 use vulkano::command_buffer::PrimaryAutoCommandBuffer;
 use vulkano::command_buffer::RenderPassBeginInfo;
 use vulkano::command_buffer::SubpassBeginInfo;
 use vulkano::command_buffer::SubpassContents;
+use vulkano::command_buffer::SubpassEndInfo;
+use vulkano::device::Device;
 use vulkano::device::Queue;
-use vulkano::swapchain::SwapchainAcquireFuture;
+use vulkano::format::Format;
+use vulkano::image::ImageAspects;
+use vulkano::image::ImageSubresourceLayers;
+use vulkano::image::ImageUsage;
+use vulkano::memory::allocator::MemoryTypeFilter;
+use vulkano::pipeline::graphics::viewport::Scissor;
+use vulkano::query::QueryPool;
+use vulkano::query::QueryPoolCreateInfo;
+use vulkano::query::QueryResultFlags;
+use vulkano::query::QueryType;
+use vulkano::swapchain::SwapchainPresentInfo;
 use vulkano::sync::future::FenceSignalFuture;
 use vulkano::sync::GpuFuture;
+use vulkano::sync::PipelineStage;
 use vulkano::Validated;
 use vulkano::VulkanError;
 
+use super::arena::DrawBufferPool;
+use super::arena::FrameArena;
+use super::devices::device_viewport_count;
 use super::devices::Devices;
 use super::instance::Allocators;
+use super::lighting::AmbientLight;
+use super::lighting::DirectionalLight;
+use super::lighting::LightingPipelines;
 use super::shaders::uniforms::Uniform;
+use super::swapchain::FrameAttachment;
+use super::swapchain::RenderMode;
 use super::swapchain::SwapchainManager;
 use super::utils::MainRenderThread;
 use super::utils::Ref;
@@ -32,27 +64,669 @@ pub type ResourceReference = Arc<dyn Drop + Send + Sync + 'static>;
 struct Frame {
     pub future: MainRenderThread<FenceSignalFuture<Box<dyn GpuFuture>>>,
     pub resources: LinkedList<ResourceReference>,
+    /// Whether both of the frame's [`GpuTimer`] timestamps were written
+    pub timed: bool,
 }
 
-const MAX_FRAMES_IN_FLIGHT: usize = 2;
+pub const MAX_FRAMES_IN_FLIGHT: usize = 2;
+
+/// The frame's command buffer while it's lent out, see [`RenderManager::take_commands`]
+pub struct FrameRecording {
+    pub commands: AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    pub framebuffer_extent: [u32; 2],
+    /// The draw buffer pool of the frame's slot
+    pub draw_buffers: DrawBufferPool,
+}
+
+/// The gpu frame time is logged once every this many frames
+const GPU_TIME_LOG_INTERVAL: u32 = 600;
+
+/// Every frame in flight needs its own queries, plus one for the frame being recorded
+const GPU_TIMER_SLOTS: u32 = MAX_FRAMES_IN_FLIGHT as u32 + 1;
+
+/// Like [`GPU_TIMER_SLOTS`], each frame in flight and the one being recorded have their own arena
+pub const FRAME_SLOTS: usize = MAX_FRAMES_IN_FLIGHT + 1;
+
+/// The slot of a frame's per-frame resources, which it has until it retires
+pub fn frame_slot(frame_number: u32) -> usize {
+    frame_number as usize % FRAME_SLOTS
+}
+
+/// The time between two timestamp queries. Only the low `valid_bits` of a timestamp are written,
+/// so the counter can wrap between them. `period` is the device's `timestamp_period`, the
+/// nanoseconds per tick.
+pub fn timestamp_delta(start: u64, end: u64, valid_bits: u32, period: f32) -> Duration {
+    let mask = match valid_bits {
+        64.. => u64::MAX,
+        bits => (1 << bits) - 1,
+    };
+
+    let ticks = (end & mask).wrapping_sub(start & mask) & mask;
+
+    Duration::from_nanos((ticks as f64 * period as f64) as u64)
+}
+
+/// Timestamps written around each frame's render passes, which time the frame on the gpu
+struct GpuTimer {
+    pool: Arc<QueryPool>,
+    valid_bits: u32,
+    period: f32,
+}
+
+impl GpuTimer {
+    /// None if the queue can't write timestamps
+    fn new(device: &Arc<Device>, queue: &Arc<Queue>) -> Option<Self> {
+        let physical_device = device.physical_device();
+
+        let valid_bits = physical_device.queue_family_properties()
+            [queue.queue_family_index() as usize]
+            .timestamp_valid_bits?;
+
+        let pool = match QueryPool::new(
+            device.clone(),
+            QueryPoolCreateInfo {
+                query_count: GPU_TIMER_SLOTS * 2,
+                ..QueryPoolCreateInfo::query_type(QueryType::Timestamp)
+            },
+        ) {
+            Ok(pool) => pool,
+            Err(e) => {
+                tracing::warn!(what = "could not create the gpu timer's queries", error = %e);
+                return None;
+            }
+        };
+
+        Some(Self {
+            pool,
+            valid_bits,
+            period: physical_device.properties().timestamp_period,
+        })
+    }
+
+    fn queries(frame: u32) -> Range<u32> {
+        let slot = frame % GPU_TIMER_SLOTS;
+
+        slot * 2..slot * 2 + 2
+    }
+
+    /// Has to be recorded outside of a render pass
+    fn record_start(
+        &self,
+        commands: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        frame: u32,
+    ) -> Result<()> {
+        let queries = Self::queries(frame);
+
+        // the slot's last frame has been retired by now, so its queries aren't in use
+        unsafe {
+            commands
+                .reset_query_pool(self.pool.clone(), queries.clone())?
+                .write_timestamp(self.pool.clone(), queries.start, PipelineStage::TopOfPipe)?;
+        }
+
+        Ok(())
+    }
+
+    fn record_end(
+        &self,
+        commands: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        frame: u32,
+    ) -> Result<()> {
+        unsafe {
+            commands.write_timestamp(
+                self.pool.clone(),
+                Self::queries(frame).start + 1,
+                PipelineStage::BottomOfPipe,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// How long a retired frame took on the gpu, None if its timestamps aren't available
+    fn read(&self, frame: u32) -> Option<Duration> {
+        let mut timestamps = [0u64; 2];
+
+        match self.pool.get_results(
+            Self::queries(frame),
+            &mut timestamps,
+            QueryResultFlags::empty(),
+        ) {
+            Ok(true) => Some(timestamp_delta(
+                timestamps[0],
+                timestamps[1],
+                self.valid_bits,
+                self.period,
+            )),
+            Ok(false) => None,
+            Err(e) => {
+                tracing::warn!(what = "could not read the gpu frame time", error = %e);
+                None
+            }
+        }
+    }
+}
+
+/// A copy of a frame's swapchain image, made right before it was presented
+#[derive(Clone)]
+struct FrameCapture {
+    frame: u32,
+    pixels: Subbuffer<[u8]>,
+    /// None while multisampling, since there's no object id attachment then
+    object_ids: Option<Subbuffer<[u32]>>,
+    extent: [u32; 2],
+    format: Format,
+}
+
+/// Where glReadPixels gets its pixels from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadbackSource {
+    /// Submit the frame in progress along with a copy of its image
+    CaptureFrame,
+    /// The last presented frame was already captured
+    LastCapture,
+    /// The last presented frame couldn't be captured. Presented images belong to the presentation
+    /// engine, so they can't be copied afterwards.
+    Unavailable,
+}
+
+/// GL_BACK is the image being rendered and GL_FRONT is the last one presented. Frames are only
+/// presented on the next colour clear, so the draws which minecraft reads back (screenshots are
+/// taken before the buffers are swapped) are still in the frame in progress. Both names are
+/// mapped onto the most recently rendered image: the frame in progress if there is one,
+/// otherwise the last presented frame, which can only be read if it was captured.
+pub fn readback_source(
+    frame_in_progress: bool,
+    frame_counter: u32,
+    last_capture: Option<u32>,
+) -> ReadbackSource {
+    if frame_in_progress {
+        ReadbackSource::CaptureFrame
+    } else if last_capture.is_some_and(|frame| frame + 1 == frame_counter) {
+        ReadbackSource::LastCapture
+    } else {
+        ReadbackSource::Unavailable
+    }
+}
+
+/// Converts the raw pixels of a swapchain image into an image with the same (top left) origin
+pub fn swapchain_pixels_to_image(
+    pixels: &[u8],
+    extent: [u32; 2],
+    format: Format,
+) -> Result<RgbaImage> {
+    let swizzle: fn([u8; 4]) -> [u8; 4] = match format {
+        Format::R8G8B8A8_UNORM | Format::R8G8B8A8_SRGB => |p| p,
+        Format::B8G8R8A8_UNORM | Format::B8G8R8A8_SRGB => |[b, g, r, a]| [r, g, b, a],
+        _ => anyhow::bail!("swapchain images with the format {format:?} can't be read back"),
+    };
+
+    let mut image = RgbaImage::new(extent[0], extent[1]);
+
+    for (dest, src) in image.pixels_mut().zip(pixels.chunks_exact(4)) {
+        dest.0 = swizzle(src.try_into().unwrap());
+    }
+
+    Ok(image)
+}
+
+/// Converts the raw pixels of a swapchain image into a screenshot. The bytes are kept as they are
+/// in either colour space: an sRGB swapchain stores them already encoded, and a UNORM one is
+/// shown without any conversion, so both match what's on screen. The alpha channel isn't shown,
+/// so it's made opaque.
+pub fn screenshot_image(pixels: &[u8], extent: [u32; 2], format: Format) -> Result<RgbaImage> {
+    let mut image = swapchain_pixels_to_image(pixels, extent, format)?;
+
+    for pixel in image.pixels_mut() {
+        pixel.0[3] = 0xFF;
+    }
+
+    Ok(image)
+}
+
+/// The object id at a pixel of a captured [`FrameAttachment::ObjectIds`], in GL window
+/// coordinates. Pixels outside of the frame didn't have anything drawn to them, so they're 0.
+pub fn object_id_at(object_ids: &[u32], extent: [u32; 2], x: u32, y: u32) -> u32 {
+    let [width, height] = extent;
+
+    if x >= width || y >= height {
+        return 0;
+    }
+
+    let row = height - 1 - y;
+
+    object_ids
+        .get((row * width + x) as usize)
+        .copied()
+        .unwrap_or(0)
+}
+
+/// Cuts a glReadPixels rectangle out of a frame. GL's origin is the bottom left corner, so the
+/// rows are flipped. The rectangle is clamped to the frame.
+pub fn gl_read_region(frame: &RgbaImage, x: u32, y: u32, width: u32, height: u32) -> RgbaImage {
+    let width = width.min(frame.width().saturating_sub(x));
+    let height = height.min(frame.height().saturating_sub(y));
+
+    RgbaImage::from_fn(width, height, |col, row| {
+        *frame.get_pixel(x + col, frame.height() - 1 - (y + row))
+    })
+}
+
+/// The oldest frames which have to finish before another one can be submitted without going over
+/// `limit` frames in flight
+pub fn frames_to_wait_for(in_flight: impl Iterator<Item = u32>, limit: usize) -> Vec<u32> {
+    let mut frames = in_flight.collect::<Vec<_>>();
+    frames.sort_unstable();
+
+    let excess = (frames.len() + 1).saturating_sub(limit);
+    frames.truncate(excess);
+    frames
+}
+
+/// The result of waiting on a sync object, as returned by glClientWaitSync
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncStatus {
+    AlreadySignaled,
+    ConditionSatisfied,
+    TimeoutExpired,
+    WaitFailed,
+}
+
+impl SyncStatus {
+    pub fn to_gl(&self) -> u32 {
+        match self {
+            Self::AlreadySignaled => gl_constants::GL_ALREADY_SIGNALED,
+            Self::ConditionSatisfied => gl_constants::GL_CONDITION_SATISFIED,
+            Self::TimeoutExpired => gl_constants::GL_TIMEOUT_EXPIRED,
+            Self::WaitFailed => gl_constants::GL_WAIT_FAILED,
+        }
+    }
+}
+
+/// GL sync objects. Each one signals once the frame it was created in has finished executing on
+/// the gpu, since frames are the unit of submission.
+#[derive(Debug, Default)]
+pub struct FenceSyncs {
+    next_id: u64,
+    /// sync id -> frame number
+    pending: HashMap<u64, u32>,
+    /// The newest frame which is known to have finished
+    completed_frame: Option<u32>,
+}
+
+impl FenceSyncs {
+    pub fn create(&mut self, frame: u32) -> u64 {
+        // 0 is never a valid sync object
+        self.next_id += 1;
+        self.pending.insert(self.next_id, frame);
+        self.next_id
+    }
+
+    pub fn delete(&mut self, sync: u64) {
+        self.pending.remove(&sync);
+    }
+
+    /// Submissions complete in order, so this also signals every earlier frame
+    pub fn frame_completed(&mut self, frame: u32) {
+        self.completed_frame = Some(self.completed_frame.map_or(frame, |f| f.max(frame)));
+    }
+
+    /// The frame a sync object is waiting on, or None if it doesn't exist
+    pub fn frame_of(&self, sync: u64) -> Option<u32> {
+        self.pending.get(&sync).copied()
+    }
+
+    pub fn is_signaled(&self, sync: u64) -> bool {
+        match (self.frame_of(sync), self.completed_frame) {
+            (Some(frame), Some(completed)) => frame <= completed,
+            _ => false,
+        }
+    }
+}
+
+/// What was drawn while recording some commands
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DrawStats {
+    pub draw_calls: u32,
+    pub vertices: u64,
+    /// Only the binds which switched pipelines, since the recorder skips redundant ones
+    pub pipeline_binds: u32,
+}
+
+impl DrawStats {
+    pub fn draw(&mut self, vertex_count: u32) {
+        self.draw_calls += 1;
+        self.vertices += vertex_count as u64;
+    }
+
+    pub fn bind_pipeline(&mut self) {
+        self.pipeline_binds += 1;
+    }
+
+    pub fn add(&mut self, other: &DrawStats) {
+        self.draw_calls += other.draw_calls;
+        self.vertices += other.vertices;
+        self.pipeline_binds += other.pipeline_binds;
+    }
+
+    /// How well draws were sorted by state: 1 means every draw needed its own pipeline, and the
+    /// lower it is the more draws shared one
+    pub fn binds_per_draw(&self) -> f32 {
+        if self.draw_calls == 0 {
+            0.0
+        } else {
+            self.pipeline_binds as f32 / self.draw_calls as f32
+        }
+    }
+}
+
+/// A snapshot of the renderer's stats for the debug overlay. This is copied as-is into a java
+/// ByteBuffer, so the field order must match `MCVKNative.Diagnostics`.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct DiagnosticsSnapshot {
+    pub fps: f32,
+    /// -1 if the gpu's frame time isn't known
+    pub gpu_frame_time_ms: f32,
+    /// The draws of the last finished frame
+    pub draw_calls: u32,
+    pub pipeline_count: u32,
+    pub vertices: u64,
+    pub texture_vram_bytes: u64,
+    pub shader_cache_hit_rate: f32,
+    pub frames_in_flight: u32,
+    /// The pipeline switches of the last finished frame
+    pub pipeline_binds: u32,
+    /// See [`DrawStats::binds_per_draw`]
+    pub binds_per_draw: f32,
+}
+
+/// Smooths out the frame time so that the fps estimate doesn't jitter every frame
+const FRAME_TIME_SMOOTHING: f32 = 0.1;
+
+/// The per-frame half of [`DiagnosticsSnapshot`]
+#[derive(Debug, Default)]
+pub struct FrameStats {
+    current: DrawStats,
+    last: DrawStats,
+    last_frame_end: Option<Instant>,
+    /// In seconds
+    frame_time: Option<f32>,
+    pub gpu_frame_time: Option<Duration>,
+}
+
+impl FrameStats {
+    pub fn add_draws(&mut self, draws: &DrawStats) {
+        self.current.add(draws);
+    }
+
+    pub fn end_frame(&mut self, now: Instant) {
+        if let Some(prev) = self.last_frame_end {
+            let elapsed = (now - prev).as_secs_f32();
+
+            self.frame_time = Some(match self.frame_time {
+                Some(avg) => avg + (elapsed - avg) * FRAME_TIME_SMOOTHING,
+                None => elapsed,
+            });
+        }
+
+        if self.current.draw_calls > 0 {
+            tracing::trace!(
+                what = "pipeline binds this frame",
+                binds = self.current.pipeline_binds,
+                draws = self.current.draw_calls,
+                binds_per_draw = self.current.binds_per_draw(),
+            );
+        }
+
+        self.last_frame_end = Some(now);
+        self.last = std::mem::take(&mut self.current);
+    }
+
+    /// Smoothed the same way as the fps
+    pub fn add_gpu_frame_time(&mut self, time: Duration) {
+        self.gpu_frame_time = Some(match self.gpu_frame_time {
+            Some(avg) => {
+                avg.mul_f32(1.0 - FRAME_TIME_SMOOTHING) + time.mul_f32(FRAME_TIME_SMOOTHING)
+            }
+            None => time,
+        });
+    }
+
+    pub fn fps(&self) -> f32 {
+        match self.frame_time {
+            Some(frame_time) if frame_time > 0.0 => 1.0 / frame_time,
+            _ => 0.0,
+        }
+    }
+
+    /// Only fills in the fields which are tracked per frame
+    pub fn snapshot(&self, frames_in_flight: usize) -> DiagnosticsSnapshot {
+        DiagnosticsSnapshot {
+            fps: self.fps(),
+            gpu_frame_time_ms: self
+                .gpu_frame_time
+                .map_or(-1.0, |t| t.as_secs_f32() * 1000.0),
+            draw_calls: self.last.draw_calls,
+            vertices: self.last.vertices,
+            pipeline_binds: self.last.pipeline_binds,
+            binds_per_draw: self.last.binds_per_draw(),
+            frames_in_flight: frames_in_flight as u32,
+            ..Default::default()
+        }
+    }
+}
+
+/// How many times the frame in progress has been submitted. glFlush submits the draws recorded so
+/// far, and presenting submits the rest.
+///
+/// Only render passes which end in a glFlush store every attachment, and which ones will is only
+/// known once they've ended. So each frame is assumed to have as many glFlush calls as the last
+/// one, and a glFlush which a render pass wasn't begun for leaves its draws to be submitted with
+/// the rest of the frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FrameSubmissions {
+    flushes: u32,
+    /// glFlush calls in the frame in progress, including the ones which didn't submit anything
+    requested: u32,
+    /// glFlush calls in the last frame
+    expected: u32,
+    /// Whether the render pass in progress was begun to end in a glFlush
+    splittable: bool,
+}
+
+impl FrameSubmissions {
+    /// Called when a render pass is begun. Returns whether it should be one which ends in a
+    /// glFlush.
+    pub fn begin_pass(&mut self) -> bool {
+        self.splittable = self.requested < self.expected;
+        self.splittable
+    }
+
+    /// glFlush. Returns whether the render pass in progress can end here.
+    pub fn flush(&mut self) -> bool {
+        self.requested += 1;
+
+        if self.splittable {
+            self.flushes += 1;
+        }
+
+        self.splittable
+    }
+
+    /// Returns how many submissions the presented frame took, and starts counting the next one
+    pub fn present(&mut self) -> u32 {
+        self.expected = std::mem::take(&mut self.requested);
+        std::mem::take(&mut self.flushes) + 1
+    }
+}
+
+/// Which frames are copied before they're presented. A copy reads the whole swapchain image (and
+/// the object ids) back to the host, so only the frames that glReadPixels, pick, or a screenshot
+/// asked for are captured.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CaptureRequests {
+    /// glReadPixels and pick, which submit the frame in progress early to read it
+    readback: bool,
+    /// Screenshots leave the frame in progress alone, so they're saved once it's presented
+    screenshot: Option<PathBuf>,
+}
+
+impl CaptureRequests {
+    pub fn request_readback(&mut self) {
+        self.readback = true;
+    }
+
+    pub fn request_screenshot(&mut self, path: PathBuf) {
+        self.screenshot = Some(path);
+    }
+
+    /// Called when a frame is submitted. Returns whether it should be captured, which uses up a
+    /// readback request.
+    pub fn take_capture(&mut self) -> bool {
+        std::mem::take(&mut self.readback) || self.screenshot.is_some()
+    }
+
+    /// Called once a frame has been presented. Returns where to save it, if a screenshot was
+    /// waiting for it.
+    pub fn take_screenshot(&mut self) -> Option<PathBuf> {
+        self.screenshot.take()
+    }
+}
+
+/// How long before a frame's deadline the limiter stops sleeping and spins instead, since a sleep
+/// can overshoot by about a scheduler tick
+const FRAME_LIMITER_SPIN: Duration = Duration::from_millis(2);
+
+/// Paces presents so that there are no more than `max_fps` of them per second
+#[derive(Debug, Default)]
+pub struct FrameLimiter {
+    last_present: Option<Instant>,
+}
+
+impl FrameLimiter {
+    /// Blocks until a frame interval has passed since the last call. Frames which took longer than
+    /// the interval aren't made up for by shortening the next one.
+    pub fn wait(&mut self, max_fps: Option<u32>) {
+        if let (Some(last), Some(max_fps)) = (self.last_present, max_fps.filter(|f| *f > 0)) {
+            let deadline = last + Duration::from_secs_f64(1.0 / max_fps as f64);
+
+            let now = Instant::now();
+            if deadline > now + FRAME_LIMITER_SPIN {
+                std::thread::sleep(deadline - now - FRAME_LIMITER_SPIN);
+            }
+
+            while Instant::now() < deadline {
+                std::hint::spin_loop();
+            }
+        }
+
+        self.last_present = Some(Instant::now());
+    }
+}
+
+/// Whether frames are being rendered. Rendering is paused while the window is minimized or
+/// unfocused to save power.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PauseState {
+    #[default]
+    Running,
+    Paused,
+}
+
+impl PauseState {
+    /// Returns true if rendering was running, in which case the frames in flight must be flushed
+    pub fn pause(&mut self) -> bool {
+        std::mem::replace(self, Self::Paused) == Self::Running
+    }
+
+    /// Returns true if rendering was paused, in which case the swapchain must be recreated since
+    /// it may have gone out of date in the meantime
+    pub fn resume(&mut self) -> bool {
+        std::mem::replace(self, Self::Running) == Self::Paused
+    }
+
+    pub fn should_render(&self) -> bool {
+        *self == Self::Running
+    }
+}
+
+/// What [`set_paused`] needs from the instance
+pub trait Pausable {
+    fn is_paused(&self) -> bool;
+
+    fn is_frame_in_progress(&self) -> bool;
+
+    /// Finishes, submits, and presents the frame in progress
+    fn present(&mut self);
+
+    fn pause(&mut self) -> Result<(), Validated<VulkanError>>;
+
+    fn resume(&mut self);
+}
+
+/// Pauses or resumes rendering. The frame in progress would otherwise hold on to its swapchain
+/// image and command buffer until rendering resumes, so it's presented with what was drawn so far
+/// before pausing.
+pub fn set_paused(target: &mut impl Pausable, paused: bool) -> Result<(), Validated<VulkanError>> {
+    if !paused {
+        target.resume();
+        return Ok(());
+    }
+
+    if !target.is_paused() && target.is_frame_in_progress() {
+        target.present();
+    }
+
+    target.pause()
+}
 
 pub struct RenderManager {
     swapchain: Ref<SwapchainManager>,
     allocators: Ref<Allocators>,
 
+    device: Arc<Device>,
     queue: Arc<Queue>,
+    /// See [`Devices::transfer_queue`]
+    transfer_queue: Arc<Queue>,
 
+    /// Keyed by frame number
     frames_in_flight: HashMap<u32, Frame>,
     frame_counter: u32,
+    syncs: FenceSyncs,
+    pause_state: PauseState,
+    stats: FrameStats,
+    limiter: FrameLimiter,
+    /// The copy of each frame slot's last captured image, see [`RenderManager::last_capture`]
+    captures: [Option<FrameCapture>; FRAME_SLOTS],
+    capture_requests: CaptureRequests,
 
     view: Matrix4<f32>,
     vp: Uniform<TMat4<f32>>,
 
     command_buffer: Option<MainRenderThread<AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>>>,
     swapchain_index: Option<u32>,
-    swapchain_future: Option<MainRenderThread<SwapchainAcquireFuture>>,
+    /// The swapchain image's acquisition, followed by whatever glFlush has submitted this frame
+    frame_future: Option<MainRenderThread<Box<dyn GpuFuture>>>,
+    submissions: FrameSubmissions,
 
     used_resources: LinkedList<ResourceReference>,
+
+    /// Only built once the deferred render mode is used
+    lighting: Option<LightingPipelines>,
+    pub ambient_light: AmbientLight,
+    pub directional_lights: Vec<DirectionalLight>,
+
+    /// The transient buffers of each frame slot's draws, see [`RenderManager::arena`]
+    arenas: [FrameArena; FRAME_SLOTS],
+    /// Where each frame slot's draw buffers are copied to. A slot's pool is lent out while its
+    /// frame is recorded, see [`RenderManager::take_commands`].
+    draw_buffers: [Option<MainRenderThread<DrawBufferPool>>; FRAME_SLOTS],
+
+    /// Only created while gpu timing is turned on, see [`RenderManager::set_gpu_timing`]
+    gpu_timer: Option<GpuTimer>,
+    /// Whether the frame in progress started with a timestamp
+    frame_timed: bool,
 }
 
 impl RenderManager {
@@ -65,19 +739,42 @@ impl RenderManager {
             swapchain: swapchain.clone(),
             allocators: allocators.clone(),
 
+            device: device.read().device.clone(),
             queue: device.read().queue.clone(),
+            transfer_queue: device.read().transfer_queue.clone(),
 
             frames_in_flight: HashMap::new(),
             frame_counter: 0,
+            syncs: FenceSyncs::default(),
+            pause_state: PauseState::Running,
+            stats: FrameStats::default(),
+            limiter: FrameLimiter::default(),
+            captures: Default::default(),
+            capture_requests: CaptureRequests::default(),
 
             view: TMat4::identity(),
             vp: Uniform::new(allocators, TMat4::identity()).unwrap(),
 
             command_buffer: None,
             swapchain_index: None,
-            swapchain_future: None,
+            frame_future: None,
+            submissions: FrameSubmissions::default(),
 
             used_resources: LinkedList::new(),
+
+            lighting: None,
+            ambient_light: AmbientLight::default(),
+            directional_lights: Vec::new(),
+
+            arenas: Default::default(),
+            draw_buffers: std::array::from_fn(|_| {
+                Some(MainRenderThread(DrawBufferPool::new(
+                    allocators.read().memory_allocator.clone(),
+                )))
+            }),
+
+            gpu_timer: None,
+            frame_timed: false,
         }
     }
 
@@ -85,22 +782,596 @@ impl RenderManager {
         &self.queue
     }
 
+    pub fn transfer_queue(&self) -> &Arc<Queue> {
+        &self.transfer_queue
+    }
+
     pub fn flush(&mut self) -> Result<(), Validated<VulkanError>> {
-        for (_, frame) in self.frames_in_flight.drain() {
+        let frames = self.frames_in_flight.drain().collect::<Vec<_>>();
+
+        for (frame_number, frame) in frames {
             frame.future.0.wait(None)?;
+            self.frame_completed(frame_number, &frame);
         }
         Ok(())
     }
 
-    pub fn end_frame(&mut self) {
+    /// Times each frame on the gpu with timestamp queries. While it's off nothing extra is
+    /// recorded. Does nothing if the device's queue can't write timestamps.
+    pub fn set_gpu_timing(&mut self, enabled: bool) {
+        if enabled == self.gpu_timer.is_some() {
+            return;
+        }
+
+        if enabled {
+            self.gpu_timer = GpuTimer::new(&self.device, &self.queue);
+        } else {
+            self.gpu_timer = None;
+            self.stats.gpu_frame_time = None;
+        }
+    }
+
+    /// The smoothed gpu time of the last few frames, zero unless gpu timing is on
+    pub fn last_gpu_frame_time(&self) -> Duration {
+        self.stats.gpu_frame_time.unwrap_or_default()
+    }
+
+    /// Called once a frame has finished executing on the gpu
+    fn frame_completed(&mut self, frame_number: u32, frame: &Frame) {
+        self.syncs.frame_completed(frame_number);
+        self.arenas[frame_slot(frame_number)].end_frame();
+
+        let Some(timer) = self.gpu_timer.as_ref().filter(|_| frame.timed) else {
+            return;
+        };
+
+        let Some(time) = timer.read(frame_number) else {
+            return;
+        };
+
+        self.stats.add_gpu_frame_time(time);
+
+        if frame_number % GPU_TIME_LOG_INTERVAL == 0 {
+            tracing::info!(
+                what = "gpu frame time",
+                frame_ms = time.as_secs_f32() * 1000.0,
+                average_ms = self.last_gpu_frame_time().as_secs_f32() * 1000.0
+            );
+        }
+    }
+
+    /// glFenceSync: the sync object signals once everything recorded this frame has executed
+    pub fn fence_sync(&mut self) -> u64 {
+        self.syncs.create(self.frame_counter)
+    }
+
+    pub fn delete_sync(&mut self, sync: u64) {
+        self.syncs.delete(sync);
+    }
+
+    /// glClientWaitSync
+    pub fn client_wait_sync(&mut self, sync: u64, timeout: Duration) -> SyncStatus {
+        let Some(frame_number) = self.syncs.frame_of(sync) else {
+            return SyncStatus::WaitFailed;
+        };
+
+        if self.syncs.is_signaled(sync) {
+            return SyncStatus::AlreadySignaled;
+        }
+
+        // the frame hasn't been submitted yet, so it can't finish while we wait
+        let Some(frame) = self.frames_in_flight.get(&frame_number) else {
+            return SyncStatus::TimeoutExpired;
+        };
+
+        match frame.future.0.wait(Some(timeout)) {
+            Ok(()) => {
+                self.syncs.frame_completed(frame_number);
+                SyncStatus::ConditionSatisfied
+            }
+            Err(Validated::Error(VulkanError::Timeout)) => SyncStatus::TimeoutExpired,
+            Err(e) => {
+                tracing::error!(what = "could not wait for a sync object", error = %e);
+                SyncStatus::WaitFailed
+            }
+        }
+    }
+
+    /// Counts the draws which were recorded into this frame
+    pub fn add_draws(&mut self, draws: &DrawStats) {
+        self.stats.add_draws(draws);
+    }
+
+    pub fn diagnostics(&self) -> DiagnosticsSnapshot {
+        self.stats.snapshot(self.frames_in_flight.len())
+    }
+
+    pub fn is_paused(&self) -> bool {
+        !self.pause_state.should_render()
+    }
+
+    pub fn pause(&mut self) -> Result<(), Validated<VulkanError>> {
+        if self.pause_state.pause() {
+            self.flush()?;
+        }
+
+        Ok(())
+    }
+
+    pub fn resume(&mut self) {
+        if self.pause_state.resume() {
+            self.swapchain.write().recreate_swapchain = true;
+        }
+    }
+
+    /// Where the transient buffers of the frame being recorded come from. Each frame slot has its
+    /// own, which trims what it kept once the slot's frame retires.
+    pub fn arena(&self) -> &FrameArena {
+        &self.arenas[frame_slot(self.frame_counter)]
+    }
+
+    pub fn is_frame_in_progress(&self) -> bool {
+        self.command_buffer.is_some()
+    }
+
+    /// Takes the frame's command buffer, along with what's needed to record into it, so that
+    /// render commands can be recorded without holding the renderer. It has to be given back with
+    /// [`RenderManager::return_commands`] before the frame can go on.
+    pub fn take_commands(&mut self) -> Option<FrameRecording> {
+        let slot = frame_slot(self.frame_counter);
+
+        if self.command_buffer.is_none() || self.draw_buffers[slot].is_none() {
+            return None;
+        }
+
+        let MainRenderThread(commands) = self.command_buffer.take().unwrap();
+
+        let swapchain = self.swapchain.read();
+        let framebuffer =
+            &swapchain.frame_buffers.as_ref().unwrap()[self.swapchain_index.unwrap() as usize];
+
+        Some(FrameRecording {
+            commands,
+            framebuffer_extent: framebuffer.extent(),
+            draw_buffers: self.draw_buffers[slot].take().unwrap().0,
+        })
+    }
+
+    /// Gives back what [`RenderManager::take_commands`] took, and counts the draws that were
+    /// recorded
+    pub fn return_commands(
+        &mut self,
+        commands: AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        draw_buffers: DrawBufferPool,
+        draws: &DrawStats,
+    ) {
+        self.command_buffer = Some(MainRenderThread(commands));
+        self.draw_buffers[frame_slot(self.frame_counter)] = Some(MainRenderThread(draw_buffers));
+        self.add_draws(draws);
+    }
+
+    /// Finishes the frame, then submits and presents it. `uploads` is executed before the frame,
+    /// since transfers can't be recorded within its render pass.
+    pub fn end_frame(&mut self, uploads: Option<Arc<PrimaryAutoCommandBuffer>>) {
+        self.finish_frame(uploads);
+    }
+
+    /// glReadPixels: the most recently rendered frame, see [`readback_source`]. A frame in
+    /// progress is submitted early, so that its draws can be copied.
+    pub fn read_frame(
+        &mut self,
+        uploads: Option<Arc<PrimaryAutoCommandBuffer>>,
+    ) -> Result<RgbaImage> {
+        self.with_capture(uploads, |capture| {
+            swapchain_pixels_to_image(&capture.pixels.read()?, capture.extent, capture.format)
+        })
+    }
+
+    /// Saves the next presented frame to `path` as a PNG, see [`screenshot_image`]. Unlike
+    /// [`RenderManager::read_frame`] this leaves the frame in progress alone, so the screenshot is
+    /// saved once the frame is presented.
+    pub fn request_screenshot(&mut self, path: PathBuf) {
+        self.capture_requests.request_screenshot(path);
+    }
+
+    /// The glLoadName of the draw at a pixel of the most recently rendered frame, which is read
+    /// back the same way as [`RenderManager::read_frame`]. 0 if nothing named was drawn there.
+    pub fn pick(
+        &mut self,
+        uploads: Option<Arc<PrimaryAutoCommandBuffer>>,
+        x: u32,
+        y: u32,
+    ) -> Result<u32> {
+        self.with_capture(uploads, |capture| {
+            let Some(object_ids) = capture.object_ids.as_ref() else {
+                anyhow::bail!("object ids are only rendered while object picking is enabled and the frame isn't multisampled");
+            };
+
+            Ok(object_id_at(&object_ids.read()?, capture.extent, x, y))
+        })
+    }
+
+    fn with_capture<T>(
+        &mut self,
+        uploads: Option<Arc<PrimaryAutoCommandBuffer>>,
+        read: impl FnOnce(&FrameCapture) -> Result<T>,
+    ) -> Result<T> {
+        let source = readback_source(
+            self.is_frame_in_progress(),
+            self.frame_counter,
+            self.last_capture().map(|c| c.frame),
+        );
+
+        match source {
+            ReadbackSource::CaptureFrame => {
+                self.capture_requests.request_readback();
+                self.finish_frame(uploads);
+            }
+            ReadbackSource::LastCapture => {}
+            ReadbackSource::Unavailable => {
+                anyhow::bail!("the last frame was presented without being captured")
+            }
+        }
+
+        read(&self.finished_capture()?)
+    }
+
+    /// The copy of the last presented frame's image, if it was made
+    fn last_capture(&self) -> Option<&FrameCapture> {
+        let frame = self.frame_counter.checked_sub(1)?;
+
+        self.captures[frame_slot(frame)]
+            .as_ref()
+            .filter(|c| c.frame == frame)
+    }
+
+    /// Waits until the copy of the last presented frame has been written
+    fn finished_capture(&mut self) -> Result<FrameCapture> {
+        let Some(capture) = self.last_capture().cloned() else {
+            anyhow::bail!("the frame could not be captured");
+        };
+
+        if let Some(frame) = self.frames_in_flight.remove(&capture.frame) {
+            frame.future.0.wait(None)?;
+            self.frame_completed(capture.frame, &frame);
+        }
+
+        Ok(capture)
+    }
+
+    fn finish_frame(&mut self, uploads: Option<Arc<PrimaryAutoCommandBuffer>>) {
+        if self.swapchain.read().window_settings.render_mode == RenderMode::Deferred {
+            if let Err(e) = self.record_lighting() {
+                tracing::error!(what = "could not light the g-buffer", error = %e);
+            }
+        }
+
+        let capture = self.capture_requests.take_capture();
+
+        if let Err(e) = self.submit_frame(uploads, capture) {
+            tracing::error!(what = "could not submit a frame", error = %e);
+        }
+
+        // a frame which never made it to the gpu retires right away
+        if !self.frames_in_flight.contains_key(&self.frame_counter) {
+            self.arenas[frame_slot(self.frame_counter)].end_frame();
+        }
+
+        self.stats.end_frame(Instant::now());
         self.frame_counter += 1;
+
+        if let Some(path) = self.capture_requests.take_screenshot() {
+            if let Err(e) = self.save_screenshot(&path) {
+                tracing::error!(
+                    what = "could not save a screenshot",
+                    path = %path.display(),
+                    error = %e
+                );
+            }
+        }
+    }
+
+    /// Waits for the frame which was just presented, then saves its capture
+    fn save_screenshot(&mut self, path: &Path) -> Result<()> {
+        let capture = self.finished_capture()?;
+
+        let image = screenshot_image(&capture.pixels.read()?, capture.extent, capture.format)?;
+
+        image.save_with_format(path, image::ImageFormat::Png)?;
+
+        Ok(())
+    }
+
+    fn submit_frame(
+        &mut self,
+        uploads: Option<Arc<PrimaryAutoCommandBuffer>>,
+        capture: bool,
+    ) -> Result<()> {
+        let Some(MainRenderThread(mut commands)) = self.command_buffer.take() else {
+            return Ok(());
+        };
+
+        let (Some(swapchain_index), Some(MainRenderThread(mut future))) =
+            (self.swapchain_index.take(), self.frame_future.take())
+        else {
+            anyhow::bail!("a frame was recorded without a swapchain image");
+        };
+
+        commands.end_render_pass(SubpassEndInfo::default())?;
+
+        let timed = match self.gpu_timer.as_ref().filter(|_| self.frame_timed) {
+            Some(timer) => {
+                timer.record_end(&mut commands, self.frame_counter)?;
+                true
+            }
+            None => false,
+        };
+
+        // the image can't be read once it's presented, so it's copied now if it was asked for.
+        // The frame is still presented if it can't be captured.
+        let capture = match capture.then(|| self.record_capture(&mut commands, swapchain_index)) {
+            Some(Ok(capture)) => capture,
+            Some(Err(e)) => {
+                tracing::error!(what = "could not capture a frame", error = %e);
+                None
+            }
+            None => None,
+        };
+
+        let commands = commands.build()?;
+
+        self.retire_frames()?;
+
+        if let Some(uploads) = uploads {
+            future = future.then_execute(self.queue.clone(), uploads)?.boxed();
+        }
+
+        let (swapchain, max_fps) = {
+            let swapchain = self.swapchain.read();
+            (
+                swapchain.swapchain.clone().unwrap(),
+                swapchain.window_settings.max_fps,
+            )
+        };
+
+        // the frame is submitted before waiting, so that only its present is held back
+        let rendered = future
+            .then_execute(self.queue.clone(), commands)?
+            .then_signal_semaphore_and_flush()?;
+
+        self.swapchain
+            .write()
+            .mark_framebuffer_initialized(swapchain_index);
+
+        self.limiter.wait(max_fps);
+
+        let result = rendered
+            .then_swapchain_present(
+                self.queue.clone(),
+                SwapchainPresentInfo::swapchain_image_index(swapchain, swapchain_index),
+            )
+            .boxed()
+            .then_signal_fence_and_flush();
+
+        let resources = std::mem::take(&mut self.used_resources);
+
+        let submissions = self.submissions.present();
+
+        if submissions > 1 {
+            tracing::trace!(
+                what = "presented a frame which was flushed early",
+                submissions
+            );
+        }
+
+        match result {
+            Ok(future) => {
+                self.frames_in_flight.insert(
+                    self.frame_counter,
+                    Frame {
+                        future: MainRenderThread(future),
+                        resources,
+                        timed,
+                    },
+                );
+
+                if let Some(capture) = capture {
+                    self.captures[frame_slot(self.frame_counter)] = Some(capture);
+                }
+            }
+            Err(Validated::Error(VulkanError::OutOfDate)) => {
+                self.swapchain.write().recreate_swapchain = true;
+            }
+            Err(e) => return Err(e.into()),
+        }
+
+        Ok(())
+    }
+
+    /// Copies the frame's swapchain image into a host-visible buffer, after its render pass
+    /// None if the swapchain images can't be copied from. The buffers of the last capture in the
+    /// frame's slot are reused, since that frame has retired.
+    fn record_capture(
+        &mut self,
+        commands: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        swapchain_index: u32,
+    ) -> Result<Option<FrameCapture>> {
+        let image =
+            self.swapchain.read().images.as_ref().unwrap()[swapchain_index as usize].clone();
+
+        if !image.usage().intersects(ImageUsage::TRANSFER_SRC) {
+            return Ok(None);
+        }
+
+        let [width, height, _] = image.extent();
+
+        let reused = self.captures[frame_slot(self.frame_counter)].take();
+
+        let pixels = self.capture_buffer(
+            reused.as_ref().map(|c| c.pixels.clone()),
+            width as u64 * height as u64 * 4,
+        )?;
+
+        commands.copy_image_to_buffer(CopyImageToBufferInfo::image_buffer(
+            image.clone(),
+            pixels.clone(),
+        ))?;
+
+        let object_ids = self.record_object_id_capture(
+            commands,
+            swapchain_index,
+            reused.and_then(|c| c.object_ids),
+        )?;
+
+        Ok(Some(FrameCapture {
+            frame: self.frame_counter,
+            pixels,
+            object_ids,
+            extent: [width, height],
+            format: image.format(),
+        }))
+    }
+
+    /// A host-visible buffer of `len` elements to copy a capture into, which is `reused` if it's
+    /// the same size
+    fn capture_buffer<T: BufferContents>(
+        &self,
+        reused: Option<Subbuffer<[T]>>,
+        len: u64,
+    ) -> Result<Subbuffer<[T]>> {
+        if let Some(buffer) = reused.filter(|b| b.len() == len) {
+            return Ok(buffer);
+        }
+
+        Ok(vulkano::buffer::Buffer::new_slice::<T>(
+            self.allocators.read().memory_allocator.clone(),
+            vulkano::buffer::BufferCreateInfo {
+                usage: BufferUsage::TRANSFER_DST,
+                ..Default::default()
+            },
+            vulkano::memory::allocator::AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::HOST_RANDOM_ACCESS,
+                ..Default::default()
+            },
+            len,
+        )?)
+    }
+
+    /// Copies the frame's layer of the object id attachment, if the render pass has one
+    fn record_object_id_capture(
+        &self,
+        commands: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        swapchain_index: u32,
+        reused: Option<Subbuffer<[u32]>>,
+    ) -> Result<Option<Subbuffer<[u32]>>> {
+        let swapchain = self.swapchain.read();
+
+        let Some(index) = swapchain
+            .window_settings
+            .attachments()
+            .iter()
+            .position(|a| *a == FrameAttachment::ObjectIds)
+        else {
+            return Ok(None);
+        };
+
+        let framebuffer = &swapchain.frame_buffers.as_ref().unwrap()[swapchain_index as usize];
+        let view = &framebuffer.attachments()[index];
+        let [width, height, _] = view.image().extent();
+
+        let object_ids = self.capture_buffer(reused, width as u64 * height as u64)?;
+
+        // the attachment has one layer per swapchain image
+        commands.copy_image_to_buffer(CopyImageToBufferInfo {
+            regions: [BufferImageCopy {
+                image_subresource: ImageSubresourceLayers {
+                    aspects: ImageAspects::COLOR,
+                    mip_level: 0,
+                    array_layers: view.subresource_range().array_layers.clone(),
+                },
+                image_extent: [width, height, 1],
+                ..Default::default()
+            }]
+            .into(),
+            ..CopyImageToBufferInfo::image_buffer(view.image().clone(), object_ids.clone())
+        })?;
+
+        Ok(Some(object_ids))
+    }
+
+    /// Forgets the frames which have finished, then waits for the oldest ones until there's room
+    /// for another frame in flight.
+    fn retire_frames(&mut self) -> Result<(), Validated<VulkanError>> {
+        let finished = self
+            .frames_in_flight
+            .iter()
+            .filter(|(_, frame)| frame.future.0.is_signaled().unwrap_or(false))
+            .map(|(frame_number, _)| *frame_number)
+            .collect::<Vec<_>>();
+
+        for frame_number in finished {
+            let frame = self.frames_in_flight.remove(&frame_number).unwrap();
+            self.frame_completed(frame_number, &frame);
+        }
+
+        let oldest =
+            frames_to_wait_for(self.frames_in_flight.keys().copied(), MAX_FRAMES_IN_FLIGHT);
+
+        for frame_number in oldest {
+            let frame = self.frames_in_flight.remove(&frame_number).unwrap();
+            frame.future.0.wait(None)?;
+            self.frame_completed(frame_number, &frame);
+        }
+
+        Ok(())
+    }
+
+    /// Lights the g-buffer written by this frame's draws.
+    fn record_lighting(&mut self) -> Result<()> {
+        let swapchain = self.swapchain.read();
+
+        let Some(commands) = self.command_buffer.as_mut() else {
+            return Ok(());
+        };
+
+        let render_pass = swapchain.render_pass.as_ref().unwrap();
+
+        if !self
+            .lighting
+            .as_ref()
+            .is_some_and(|l| l.is_for(render_pass))
+        {
+            self.lighting = Some(LightingPipelines::new(&self.device, render_pass));
+        }
+
+        let framebuffer =
+            &swapchain.frame_buffers.as_ref().unwrap()[self.swapchain_index.unwrap() as usize];
+
+        self.lighting.as_ref().unwrap().record(
+            &mut commands.0,
+            &self.allocators.read(),
+            framebuffer,
+            self.ambient_light,
+            &self.directional_lights,
+        )
     }
 
     pub fn start_frame(&mut self) {
+        if !self.pause_state.should_render() {
+            return;
+        }
+
         let mut swapchain = self.swapchain.write();
 
         if swapchain.recreate_swapchain {
             swapchain.create_swapchain();
+        } else if swapchain.present_mode_changed {
+            swapchain.change_present_mode();
+
+            // a failed change falls back to a full recreation
+            if swapchain.recreate_swapchain {
+                swapchain.create_swapchain();
+            }
         }
 
         self.vp.data = self.view * swapchain.projection;
@@ -108,8 +1379,77 @@ impl RenderManager {
 
         let (swapchain_index, swapchain_future) = swapchain.acquire_image();
         self.swapchain_index = Some(swapchain_index);
-        self.swapchain_future = Some(MainRenderThread(swapchain_future));
+        self.frame_future = Some(MainRenderThread(swapchain_future.boxed()));
+
+        let split = self.submissions.begin_pass();
+        let commands = self.begin_render_pass(&swapchain, swapchain_index, false, split);
+
+        self.frame_timed = self.gpu_timer.is_some();
+        self.command_buffer = Some(MainRenderThread(commands));
+    }
+
+    /// glFlush: submits the draws recorded so far without presenting, so that the gpu can start
+    /// on them while the rest of the frame is recorded. The render pass can't stay open across
+    /// command buffers, so the frame continues in a continuation render pass, which loads what
+    /// the split one stored (see [`SplitRenderPasses`](super::swapchain::SplitRenderPasses)). The submissions are chained, so the
+    /// frame's fence covers all of them.
+    pub fn flush_partial(&mut self) -> Result<()> {
+        if self.command_buffer.is_none() || !self.submissions.flush() {
+            return Ok(());
+        }
+
+        let Some(MainRenderThread(mut commands)) = self.command_buffer.take() else {
+            return Ok(());
+        };
+
+        let (Some(swapchain_index), Some(MainRenderThread(future))) =
+            (self.swapchain_index, self.frame_future.take())
+        else {
+            anyhow::bail!("a frame was recorded without a swapchain image");
+        };
+
+        // the g-buffer is carried over into the continuation, so the whole frame is lit once it's
+        // finished and the lighting subpass is left empty here
+        if self.swapchain.read().window_settings.render_mode == RenderMode::Deferred {
+            commands.next_subpass(
+                SubpassEndInfo::default(),
+                SubpassBeginInfo {
+                    contents: SubpassContents::Inline,
+                    ..Default::default()
+                },
+            )?;
+        }
 
+        commands.end_render_pass(SubpassEndInfo::default())?;
+
+        let future = future.then_execute(self.queue.clone(), commands.build()?)?;
+        future.flush()?;
+
+        self.swapchain
+            .write()
+            .mark_framebuffer_initialized(swapchain_index);
+
+        self.frame_future = Some(MainRenderThread(future.boxed()));
+
+        let split = self.submissions.begin_pass();
+        let swapchain = self.swapchain.read();
+        let commands = self.begin_render_pass(&swapchain, swapchain_index, true, split);
+
+        self.command_buffer = Some(MainRenderThread(commands));
+
+        Ok(())
+    }
+
+    /// Starts a command buffer within the frame's render pass. Its dynamic state is reset to the
+    /// whole framebuffer, like at the start of a frame. `split` picks a render pass which ends in
+    /// a glFlush.
+    fn begin_render_pass(
+        &self,
+        swapchain: &SwapchainManager,
+        swapchain_index: u32,
+        continuation: bool,
+        split: bool,
+    ) -> AutoCommandBufferBuilder<PrimaryAutoCommandBuffer> {
         let mut commands = AutoCommandBufferBuilder::primary(
             &self.allocators.read().command_buffer_allocator,
             self.queue.queue_family_index(),
@@ -117,28 +1457,73 @@ impl RenderManager {
         )
         .unwrap();
 
+        let framebuffer =
+            swapchain.frame_buffers.as_ref().unwrap()[swapchain_index as usize].clone();
+
+        // the framebuffer's images are still Undefined, so a preserved frame can't be loaded
+        let first_use = !continuation && !swapchain.framebuffer_initialized(swapchain_index);
+
+        let clear_values = if continuation {
+            // everything is loaded, so there's nothing to clear
+            vec![None; framebuffer.attachments().len()]
+        } else if first_use {
+            swapchain
+                .window_settings
+                .first_use_clear_values(swapchain.attachment_formats().unwrap())
+        } else {
+            swapchain
+                .window_settings
+                .clear_values(swapchain.attachment_formats().unwrap())
+        };
+
+        let render_pass = swapchain
+            .split_render_passes
+            .as_ref()
+            .unwrap()
+            .select(first_use, continuation, split)
+            .unwrap_or(framebuffer.render_pass())
+            .clone();
+
+        let begin_info = RenderPassBeginInfo {
+            render_pass,
+            clear_values,
+            ..RenderPassBeginInfo::framebuffer(framebuffer.clone())
+        };
+
+        let viewport_count = device_viewport_count(&self.device);
+
+        // continuations are part of a frame that was already started
+        if let Some(timer) = self.gpu_timer.as_ref().filter(|_| !continuation) {
+            if let Err(e) = timer.record_start(&mut commands, self.frame_counter) {
+                tracing::warn!(what = "could not start timing a frame", error = %e);
+            }
+        }
+
         commands
             .begin_render_pass(
-                RenderPassBeginInfo {
-                    clear_values: vec![
-                        Some([0.0, 0.0, 0.0, 1.0].into()),
-                        Some([0.0, 0.0, 0.0, 1.0].into()),
-                        Some([0.0, 0.0, 0.0, 1.0].into()),
-                        Some(1.0.into()),
-                    ],
-                    ..RenderPassBeginInfo::framebuffer(
-                        swapchain.frame_buffers.as_ref().unwrap()[swapchain_index as usize].clone(),
-                    )
-                },
+                begin_info,
                 SubpassBeginInfo {
                     contents: SubpassContents::Inline,
                     ..Default::default()
                 },
             )
             .unwrap()
-            .set_viewport(0, vec![swapchain.viewport.clone()].into())
+            .set_viewport(0, vec![swapchain.viewport.clone(); viewport_count].into())
+            .unwrap()
+            // GL_SCISSOR_TEST starts disabled
+            .set_scissor(
+                0,
+                vec![
+                    Scissor {
+                        offset: [0, 0],
+                        extent: framebuffer.extent(),
+                    };
+                    viewport_count
+                ]
+                .into(),
+            )
             .unwrap();
 
-        self.command_buffer = Some(MainRenderThread(commands));
+        commands
     }
 }
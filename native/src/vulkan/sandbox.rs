@@ -1,23 +1,40 @@
+use std::cell::Cell;
 use std::sync::Arc;
 
 use nalgebra_glm::Vec3;
 use nalgebra_glm::Vec4;
 use num_derive::FromPrimitive;
 use num_derive::ToPrimitive;
+use vulkano::pipeline::graphics::color_blend::BlendFactor;
+use vulkano::pipeline::graphics::depth_stencil::CompareOp;
 
 use super::insn_assembler::RenderInsnAssembler;
+use super::shaders::programs::LinkedProgram;
 use super::spinlock::SpinLock;
+use super::utils::TypedVec;
 
 pub type RenderSandboxStack = Arc<SpinLock<RenderSandbox>>;
 
 thread_local! {
     pub static RENDER_SANDBOX: RenderSandboxStack = Arc::new(SpinLock::new(RenderSandbox::None));
+
+    /// The error glGetError reports. Like the GL context, it's per-thread.
+    static GL_ERROR: Cell<Option<GlError>> = const { Cell::new(None) };
 }
 
 #[derive(Debug)]
 pub enum RenderSandbox {
     Assembler(Box<RenderInsnAssembler>),
     List(Vec<RenderInstruction>),
+    /// Records instructions into a display list between glNewList and glEndList. The sandbox
+    /// which was active beforehand is restored by glEndList, and also receives the
+    /// instructions when the list is compiled with GL_COMPILE_AND_EXECUTE.
+    Compiling {
+        list: u32,
+        execute: bool,
+        recording: Vec<RenderInstruction>,
+        outer: Box<RenderSandbox>,
+    },
     None,
 }
 
@@ -26,6 +43,20 @@ impl RenderSandbox {
         match self {
             Self::Assembler(asm) => asm.feed(&[insn]),
             Self::List(insns) => insns.push(insn),
+            // client state isn't compiled into lists, it takes effect immediately like in GL
+            Self::Compiling { outer, .. } if insn.is_client_state() => outer.push(insn),
+            Self::Compiling {
+                execute,
+                recording,
+                outer,
+                ..
+            } => {
+                if *execute {
+                    outer.push(insn.clone());
+                }
+
+                recording.push(insn);
+            }
             Self::None => {
                 tracing::error!(
                     what = "tried to push render instruction on invalid thread",
@@ -36,29 +67,127 @@ impl RenderSandbox {
         }
     }
 
+    /// Pushes every instruction of a display list. While another list is being compiled the
+    /// called list's contents are recorded in its place.
+    pub fn push_all(&mut self, insns: &[RenderInstruction]) {
+        match self {
+            Self::Assembler(asm) => asm.feed(insns),
+            _ => {
+                for insn in insns {
+                    self.push(insn.clone());
+                }
+            }
+        }
+    }
+
+    /// Starts redirecting pushes into `list`. Returns false if a list is already being compiled,
+    /// since glNewList can't nest.
+    pub fn begin_list(&mut self, list: u32, execute: bool) -> bool {
+        if matches!(self, Self::Compiling { .. }) {
+            return false;
+        }
+
+        let outer = std::mem::replace(self, Self::None);
+
+        *self = Self::Compiling {
+            list,
+            execute,
+            recording: Vec::new(),
+            outer: Box::new(outer),
+        };
+
+        true
+    }
+
+    /// Restores the sandbox which was active before glNewList and returns the compiled list, or
+    /// None if no list was being compiled
+    pub fn end_list(&mut self) -> Option<(u32, Vec<RenderInstruction>)> {
+        if !matches!(self, Self::Compiling { .. }) {
+            return None;
+        }
+
+        let Self::Compiling {
+            list,
+            recording,
+            outer,
+            ..
+        } = std::mem::replace(self, Self::None)
+        else {
+            unreachable!()
+        };
+
+        *self = *outer;
+
+        Some((list, recording))
+    }
+
+    /// The assembler instructions end up in, looking through a list that's being compiled
+    pub fn assembler_mut(&mut self) -> Option<&mut RenderInsnAssembler> {
+        match self {
+            Self::Assembler(asm) => Some(asm),
+            Self::Compiling { outer, .. } => outer.assembler_mut(),
+            _ => None,
+        }
+    }
+
     pub fn get_bound_texture(&self) -> Option<i32> {
         match self {
             Self::Assembler(a) => a.get_active_texture(),
             Self::List(l) => {
                 for insn in l.iter().rev() {
-                    if let RenderInstruction::BindTexture(i) = insn {
+                    if let RenderInstruction::BindTexture(i)
+                    | RenderInstruction::BindTextureRectangle(i) = insn
+                    {
                         return Some(*i);
                     }
                 }
 
                 None
             }
+            Self::Compiling { outer, .. } => outer.get_bound_texture(),
             Self::None => None,
         }
     }
+
+    /// Queries integer state for glGetIntegerv. State is only tracked while assembling.
+    pub fn get_integer(&self, pname: u32) -> Option<i32> {
+        match self {
+            Self::Assembler(a) => a.get_integer(pname),
+            Self::Compiling { outer, .. } => outer.get_integer(pname),
+            _ => None,
+        }
+    }
+
+    /// Queries boolean state for glGetBooleanv. State is only tracked while assembling.
+    pub fn get_booleans(&self, pname: u32) -> Option<Vec<bool>> {
+        match self {
+            Self::Assembler(a) => a.get_booleans(pname),
+            Self::Compiling { outer, .. } => outer.get_booleans(pname),
+            _ => None,
+        }
+    }
 }
 
 pub fn push_instruction(insn: RenderInstruction) {
-    RENDER_SANDBOX.with(|lock| {
+    let backed_up = RENDER_SANDBOX.with(|lock| {
         let mut guard = lock.lock();
 
         guard.push(insn);
+
+        guard
+            .assembler_mut()
+            .is_some_and(|asm| asm.commands.is_backed_up())
     });
+
+    // the render thread can't wait for the worker to drain its queue, so it records the backlog
+    // itself. If the instance is in use further up the stack this is retried on the next push.
+    if backed_up {
+        if let Ok(mut inst) = crate::jni::INSTANCE.try_write() {
+            if let Some(inst) = inst.as_mut() {
+                inst.record_backlog();
+            }
+        }
+    }
 }
 
 pub fn with_render_sandbox<F: FnOnce(&mut RenderSandbox) -> R, R>(f: F) -> R {
@@ -97,6 +226,29 @@ pub fn take_sandbox() -> Option<RenderSandbox> {
     }
 }
 
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromPrimitive, ToPrimitive)]
+pub enum GlError {
+    InvalidEnum = gl_constants::GL_INVALID_ENUM,
+    InvalidValue = gl_constants::GL_INVALID_VALUE,
+    InvalidOperation = gl_constants::GL_INVALID_OPERATION,
+}
+
+/// Records an error for glGetError. Like GL, only the first error is kept until it's been
+/// queried, and later ones are dropped.
+pub fn set_gl_error(error: GlError) {
+    GL_ERROR.with(|flag| {
+        if flag.get().is_none() {
+            flag.set(Some(error));
+        }
+    });
+}
+
+/// Returns the recorded error and clears it
+pub fn take_gl_error() -> Option<GlError> {
+    GL_ERROR.with(|flag| flag.take())
+}
+
 #[repr(u32)]
 #[derive(Debug, Clone, Copy, PartialEq, FromPrimitive, ToPrimitive)]
 pub enum MatrixMode {
@@ -148,6 +300,102 @@ pub enum DrawMode {
     TriAdj = gl_constants::GL_TRIANGLES_ADJACENCY as u8,
 }
 
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, FromPrimitive, ToPrimitive, Hash, Eq, Default)]
+pub enum ProvokingVertex {
+    First = gl_constants::GL_FIRST_VERTEX_CONVENTION,
+    #[default]
+    Last = gl_constants::GL_LAST_VERTEX_CONVENTION,
+}
+
+/// glTexEnv's GL_TEXTURE_ENV_MODE: how a texture unit combines its texture with the colour of
+/// the units before it
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, FromPrimitive, ToPrimitive, Hash, Eq, Default)]
+pub enum TextureEnvMode {
+    #[default]
+    Modulate = gl_constants::GL_MODULATE,
+    Replace = gl_constants::GL_REPLACE,
+    Add = gl_constants::GL_ADD,
+    Decal = gl_constants::GL_DECAL,
+}
+
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, FromPrimitive, ToPrimitive, Hash, Eq, Default)]
+pub enum PolygonMode {
+    Point = gl_constants::GL_POINT,
+    Line = gl_constants::GL_LINE,
+    #[default]
+    Fill = gl_constants::GL_FILL,
+}
+
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, FromPrimitive, ToPrimitive, Hash, Eq, Default)]
+pub enum CullFace {
+    Front = gl_constants::GL_FRONT,
+    #[default]
+    Back = gl_constants::GL_BACK,
+    FrontAndBack = gl_constants::GL_FRONT_AND_BACK,
+}
+
+/// glFrontFace
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, FromPrimitive, ToPrimitive, Hash, Eq, Default)]
+pub enum Winding {
+    Clockwise = gl_constants::GL_CW,
+    #[default]
+    CounterClockwise = gl_constants::GL_CCW,
+}
+
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, FromPrimitive, ToPrimitive, Hash, Eq, Default)]
+pub enum DepthFunc {
+    Never = gl_constants::GL_NEVER,
+    #[default]
+    Less = gl_constants::GL_LESS,
+    Equal = gl_constants::GL_EQUAL,
+    LessOrEqual = gl_constants::GL_LEQUAL,
+    Greater = gl_constants::GL_GREATER,
+    NotEqual = gl_constants::GL_NOTEQUAL,
+    GreaterOrEqual = gl_constants::GL_GEQUAL,
+    Always = gl_constants::GL_ALWAYS,
+}
+
+/// A scissor rectangle in GL window coordinates, so the origin is the bottom left corner.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct ScissorRect {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl ScissorRect {
+    /// Returns the area covered by both rectangles, or an empty rectangle if they don't overlap.
+    pub fn intersect(&self, other: &Self) -> Self {
+        let x0 = self.x.max(other.x) as i64;
+        let y0 = self.y.max(other.y) as i64;
+        let x1 = (self.x as i64 + self.width as i64).min(other.x as i64 + other.width as i64);
+        let y1 = (self.y as i64 + self.height as i64).min(other.y as i64 + other.height as i64);
+
+        if x1 <= x0 || y1 <= y0 {
+            return Self {
+                x: x0 as i32,
+                y: y0 as i32,
+                width: 0,
+                height: 0,
+            };
+        }
+
+        Self {
+            x: x0 as i32,
+            y: y0 as i32,
+            width: (x1 - x0) as u32,
+            height: (y1 - y0) as u32,
+        }
+    }
+}
+
 structstruck::strike! {
     #[strikethrough[derive(Debug, Clone, PartialEq)]]
     pub enum RenderInstruction {
@@ -183,23 +431,82 @@ structstruck::strike! {
             enabled: bool,
             array_type: PointerArrayType,
         },
+        /// `data` holds `vec_count` tightly packed vectors copied from client memory, unless
+        /// `buffer` is set
         SetPointer {
             vec_count: u32,
             array_type: PointerArrayType,
             item_type: GLDataType,
             data: Arc<Vec<u8>>,
             size: u8,
+            /// The buffer bound to GL_ARRAY_BUFFER when the pointer was set. The array is read
+            /// from it when a draw is assembled, starting `offset` bytes in with `stride` bytes
+            /// between vectors (0 for tightly packed).
+            buffer: Option<u32>,
+            offset: u32,
+            stride: u32,
+        },
+        /// glVertexAttribPointer, sourced like [`RenderInstruction::SetPointer`]
+        SetAttribPointer {
+            index: u32,
+            vec_count: u32,
+            item_type: GLDataType,
+            /// Integer data is mapped to [0, 1] or [-1, 1] instead of converted as-is
+            normalized: bool,
+            data: Arc<Vec<u8>>,
+            size: u8,
+            buffer: Option<u32>,
+            offset: u32,
+            stride: u32,
+        },
+        /// glBufferData: replaces the contents of a buffer object
+        BufferData {
+            buffer: u32,
+            data: Arc<Vec<u8>>,
+        },
+        /// glBufferSubData: overwrites part of a buffer object, starting `offset` bytes in
+        BufferSubData {
+            buffer: u32,
+            offset: u32,
+            data: Arc<Vec<u8>>,
+        },
+        /// glDeleteBuffers
+        DeleteBuffer(u32),
+        /// glEnableVertexAttribArray/glDisableVertexAttribArray
+        SetAttribArrayEnabled {
+            index: u32,
+            enabled: bool,
         },
         DrawArrays {
             mode: DrawMode,
             first: u32,
             count: u32,
         },
+        /// `indices` holds `count` indices of `index_type`, which is U8, U16 or U32
+        DrawElements {
+            mode: DrawMode,
+            count: u32,
+            index_type: GLDataType,
+            indices: Arc<Vec<u8>>,
+        },
 
         SetActiveTextureUnit(usize),
+        /// glClientActiveTexture, which unit's texcoord array the client array calls affect
+        SetClientActiveTextureUnit(usize),
         BindTexture(i32),
+        /// glBindTexture(GL_TEXTURE_RECTANGLE): the texture is sampled at unnormalized texel
+        /// coordinates instead of 0-1 ones
+        BindTextureRectangle(i32),
+        /// glTexEnv(GL_TEXTURE_ENV, GL_TEXTURE_ENV_MODE) for the active unit
+        SetTextureEnvMode(TextureEnvMode),
 
         TexCoord(Vec4),
+        /// glMultiTexCoord, `unit` is relative to GL_TEXTURE0
+        MultiTexCoord {
+            unit: usize,
+            coord: Vec4,
+        },
+        Normal(Vec3),
 
         SetColor(Vec4),
 
@@ -207,8 +514,69 @@ structstruck::strike! {
         Vertex(Vec4),
         End,
 
-        AlphaFunc,
+        /// glAlphaFunc, only used while GL_ALPHA_TEST is enabled
+        AlphaFunc {
+            func: CompareOp,
+            ref_value: f32,
+        },
+
+        /// glClipPlane, `plane` is relative to GL_CLIP_PLANE0. The equation is in object space and
+        /// only used while its GL_CLIP_PLANEi is enabled.
+        ClipPlane {
+            plane: usize,
+            equation: Vec4,
+        },
+
+        ProvokingVertex(ProvokingVertex),
+        PolygonMode(PolygonMode),
+        CullFace(CullFace),
+        FrontFace(Winding),
+
+        /// glScissor, only used while GL_SCISSOR_TEST is enabled
+        Scissor(ScissorRect),
+        PushScissor(ScissorRect),
+        PopScissor,
+        /// glPushAttrib, which saves the attribute groups in the GL_*_BIT mask until the matching
+        /// glPopAttrib
+        PushAttrib(u32),
+        PopAttrib,
+        /// The selection name stack. Every draw writes the name on top of it into the object id
+        /// attachment, which is what picking reads back.
+        InitNames,
+        PushName(u32),
+        PopName,
+        LoadName(u32),
+        /// glViewport and glViewportIndexedf, in GL window coordinates. None sets every viewport.
+        Viewport {
+            index: Option<u32>,
+            x: i32,
+            y: i32,
+            width: u32,
+            height: u32,
+        },
+        /// glDepthRange and glDepthRangeIndexed. None sets every viewport.
+        DepthRange {
+            index: Option<u32>,
+            near: f32,
+            far: f32,
+        },
+
+        DepthFunc(DepthFunc),
+        DepthMask(bool),
+        ColorMask([bool; 4]),
+        BlendColor(Vec4),
+        BlendFunc {
+            src: BlendFactor,
+            dst: BlendFactor,
+        },
+
+        /// None goes back to the fixed-function pipeline
+        UseProgram(Option<Arc<LinkedProgram>>),
+        /// Sets a uniform of the active program by location
+        SetUniform(u32, TypedVec),
 
+        /// Only emitted when colour clears aren't the frame boundary
+        ClearColour,
         ClearDepth,
     }
 }
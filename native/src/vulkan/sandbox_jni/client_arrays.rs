@@ -1,5 +1,7 @@
 use std::mem::size_of_val;
 
+use crate::vulkan::buffer_objects::BUFFER_OBJECTS;
+
 use super::jni_prelude::*;
 
 impl PointerArrayType {
@@ -33,8 +35,15 @@ impl GLDataType {
 }
 
 #[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
-unsafe fn glEnableClientState(_: JNIEnv<'_>, _: JClass<'_>, array_type: jint) {
-    let array_type = PointerArrayType::from_i32(array_type).unwrap();
+pub unsafe fn glEnableClientState(_: JNIEnv<'_>, _: JClass<'_>, array_type: jint) {
+    let Some(array_type) = PointerArrayType::from_i32(array_type) else {
+        tracing::warn!(
+            what = "glEnableClientState() was called with an invalid array type",
+            array_type
+        );
+        set_gl_error(GlError::InvalidEnum);
+        return;
+    };
 
     if !array_type.is_supported() {
         return;
@@ -48,7 +57,14 @@ unsafe fn glEnableClientState(_: JNIEnv<'_>, _: JClass<'_>, array_type: jint) {
 
 #[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
 unsafe fn glDisableClientState(_: JNIEnv<'_>, _: JClass<'_>, array_type: jint) {
-    let array_type = PointerArrayType::from_i32(array_type).unwrap();
+    let Some(array_type) = PointerArrayType::from_i32(array_type) else {
+        tracing::warn!(
+            what = "glDisableClientState() was called with an invalid array type",
+            array_type
+        );
+        set_gl_error(GlError::InvalidEnum);
+        return;
+    };
 
     if !array_type.is_supported() {
         return;
@@ -60,6 +76,104 @@ unsafe fn glDisableClientState(_: JNIEnv<'_>, _: JClass<'_>, array_type: jint) {
     });
 }
 
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+pub unsafe fn glClientActiveTexture(_: JNIEnv<'_>, _: JClass<'_>, texture: jint) {
+    let Some(unit) = (texture as u32).checked_sub(GL_TEXTURE0) else {
+        tracing::warn!(
+            what = "glClientActiveTexture() was called with an invalid texture unit",
+            texture
+        );
+        set_gl_error(GlError::InvalidEnum);
+        return;
+    };
+
+    push_instruction(RenderInstruction::SetClientActiveTextureUnit(unit as usize));
+}
+
+/// Copies a (possibly interleaved) array into a tightly packed one, returning it along with its
+/// vector count. The last vector doesn't need a whole stride after it, since an array that starts
+/// partway into an interleaved buffer ends before the buffer does.
+pub fn compact_array(
+    data: &[u8],
+    size: usize,
+    stride: usize,
+    item_type: GLDataType,
+) -> (Vec<u8>, usize) {
+    let vec_byte_size = size * (item_type.size() as usize);
+    let stride = if stride > 0 { stride } else { vec_byte_size };
+
+    let vec_count = data
+        .len()
+        .checked_sub(vec_byte_size)
+        .map_or(0, |rest| rest / stride + 1);
+
+    let mut out = Vec::with_capacity(vec_byte_size * vec_count);
+    out.resize(vec_byte_size * vec_count, 0);
+
+    for vec_idx in 0..vec_count {
+        let dest = &mut out[(vec_idx * vec_byte_size)..(vec_idx * vec_byte_size + vec_byte_size)];
+        let src = &data[(vec_idx * stride)..(vec_idx * stride + vec_byte_size)];
+
+        dest.copy_from_slice(src);
+    }
+
+    (out, vec_count)
+}
+
+/// Where a pointer array's vectors come from
+struct ArraySource {
+    data: Arc<Vec<u8>>,
+    vec_count: u32,
+    buffer: Option<u32>,
+    offset: u32,
+    stride: u32,
+}
+
+/// Copies a pointer array out of client memory. While a buffer is bound to GL_ARRAY_BUFFER the
+/// pointer is a byte offset into it instead, and `byte_length` is ignored: the buffer is only read
+/// when a draw is assembled, so that glBufferSubData calls made after the pointer was set show up
+/// in the draw. Returns None if the offset doesn't fit in a u32.
+unsafe fn read_array_source(
+    start: *const u8,
+    byte_length: usize,
+    size: usize,
+    stride: usize,
+    item_type: GLDataType,
+) -> Option<ArraySource> {
+    let Some(buffer) = BUFFER_OBJECTS.with(|buffers| buffers.borrow().bound_array_buffer()) else {
+        let (data, vec_count) = compact_array(
+            std::slice::from_raw_parts(start, byte_length),
+            size,
+            stride,
+            item_type,
+        );
+
+        return Some(ArraySource {
+            data: Arc::new(data),
+            vec_count: vec_count as u32,
+            buffer: None,
+            offset: 0,
+            stride: 0,
+        });
+    };
+
+    let Ok(offset) = u32::try_from(start as usize) else {
+        tracing::warn!(
+            what = "a pointer array's offset into the bound array buffer is too large",
+            offset = start as usize
+        );
+        return None;
+    };
+
+    Some(ArraySource {
+        data: Arc::default(),
+        vec_count: 0,
+        buffer: Some(buffer),
+        offset,
+        stride: stride as u32,
+    })
+}
+
 #[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
 pub unsafe fn addPointerArray(
     _: JNIEnv<'_>,
@@ -77,7 +191,14 @@ pub unsafe fn addPointerArray(
     let size = size as usize;
     let stride = stride as usize;
     let byte_length = byte_length as usize;
-    let array_type = PointerArrayType::from_i32(array_type).unwrap();
+    let Some(array_type) = PointerArrayType::from_i32(array_type) else {
+        tracing::warn!(
+            what = "a pointer array was added with an invalid array type",
+            array_type
+        );
+        set_gl_error(GlError::InvalidEnum);
+        return;
+    };
 
     if !array_type.is_supported() {
         return;
@@ -85,39 +206,239 @@ pub unsafe fn addPointerArray(
 
     assert!(size <= 4);
 
-    let data = std::slice::from_raw_parts(start, byte_length);
+    let Some(item_type) = GLDataType::from_i32(item_type) else {
+        tracing::warn!(
+            what = "a pointer array was added with an invalid type",
+            item_type
+        );
+        set_gl_error(GlError::InvalidEnum);
+        return;
+    };
 
-    let item_type = GLDataType::from_i32(item_type).unwrap();
-    let item_size = item_type.size();
+    let Some(source) = read_array_source(start, byte_length, size, stride, item_type) else {
+        set_gl_error(GlError::InvalidValue);
+        return;
+    };
 
-    let vec_byte_size = size * (item_size as usize);
-    let stride = if stride > 0 { stride } else { vec_byte_size };
+    push_instruction(RenderInstruction::SetPointer {
+        size: size as u8,
+        vec_count: source.vec_count,
+        array_type,
+        item_type,
+        data: source.data,
+        buffer: source.buffer,
+        offset: source.offset,
+        stride: source.stride,
+    });
+}
 
-    let vec_count = byte_length / stride;
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+pub unsafe fn addAttribPointerArray(
+    _: JNIEnv<'_>,
+    _: JClass<'_>,
+    index: jint,
+    size: jint,
+    item_type: jint,
+    normalized: jboolean,
+    stride: jint,
+    start: *const u8,
+    byte_length: jint,
+) {
+    assert_eq!(size_of_val(&(0 as jlong)), size_of_val(&start));
 
-    let mut out = Vec::with_capacity(vec_byte_size * vec_count);
-    out.resize(vec_byte_size * vec_count, 0);
+    if !(1..=4).contains(&size) || index < 0 {
+        tracing::warn!(
+            what = "glVertexAttribPointer() was called with an invalid index or size",
+            index,
+            size
+        );
+        set_gl_error(GlError::InvalidValue);
+        return;
+    }
 
-    for vec_idx in 0..vec_count {
-        let dest = &mut out[(vec_idx * vec_byte_size)..(vec_idx * vec_byte_size + vec_byte_size)];
-        let src = &data[(vec_idx * stride)..(vec_idx * stride + vec_byte_size)];
+    let Some(item_type) = GLDataType::from_i32(item_type) else {
+        tracing::warn!(
+            what = "glVertexAttribPointer() was called with an unsupported type",
+            item_type
+        );
+        set_gl_error(GlError::InvalidEnum);
+        return;
+    };
 
-        dest.copy_from_slice(src);
-    }
+    let Some(source) = read_array_source(
+        start,
+        byte_length as usize,
+        size as usize,
+        stride as usize,
+        item_type,
+    ) else {
+        set_gl_error(GlError::InvalidValue);
+        return;
+    };
 
-    push_instruction(RenderInstruction::SetPointer {
-        size: size as u8,
-        vec_count: vec_count as u32,
-        array_type,
+    push_instruction(RenderInstruction::SetAttribPointer {
+        index: index as u32,
+        vec_count: source.vec_count,
         item_type,
-        data: Arc::new(out),
+        normalized: normalized != 0,
+        data: source.data,
+        size: size as u8,
+        buffer: source.buffer,
+        offset: source.offset,
+        stride: source.stride,
     });
 }
 
 #[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
-unsafe fn glDrawArrays(_: JNIEnv<'_>, _: JClass<'_>, mode: jint, first: jint, count: jint) {
+pub unsafe fn glGenBuffers(_: JNIEnv<'_>, _: JClass<'_>) -> jint {
+    BUFFER_OBJECTS.with(|buffers| buffers.borrow_mut().gen_buffer()) as jint
+}
+
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+pub unsafe fn glDeleteBuffers(_: JNIEnv<'_>, _: JClass<'_>, buffer: jint) {
+    if BUFFER_OBJECTS.with(|buffers| buffers.borrow_mut().delete_buffer(buffer as u32)) {
+        push_instruction(RenderInstruction::DeleteBuffer(buffer as u32));
+    }
+}
+
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+pub unsafe fn glBindBuffer(_: JNIEnv<'_>, _: JClass<'_>, target: jint, buffer: jint) {
+    if target as u32 != GL_ARRAY_BUFFER {
+        tracing::warn!(
+            what = "glBindBuffer() was called with an unsupported target: this is a no-op!",
+            target
+        );
+        set_gl_error(GlError::InvalidEnum);
+        return;
+    }
+
+    if !BUFFER_OBJECTS.with(|buffers| buffers.borrow_mut().bind_array_buffer(buffer as u32)) {
+        tracing::warn!(
+            what = "glBindBuffer() was called with a buffer that doesn't exist",
+            buffer
+        );
+        set_gl_error(GlError::InvalidOperation);
+    }
+}
+
+/// glBufferData. The usage hint is ignored since the contents stay on the CPU.
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+pub unsafe fn addBufferData(
+    _: JNIEnv<'_>,
+    _: JClass<'_>,
+    target: jint,
+    data: *const u8,
+    byte_length: jint,
+    _usage: jint,
+) {
+    assert_eq!(size_of_val(&(0 as jlong)), size_of_val(&data));
+
+    if target as u32 != GL_ARRAY_BUFFER {
+        tracing::warn!(
+            what = "glBufferData() was called with an unsupported target: this is a no-op!",
+            target
+        );
+        set_gl_error(GlError::InvalidEnum);
+        return;
+    }
+
+    let data = std::slice::from_raw_parts(data, byte_length.max(0) as usize).to_vec();
+
+    let Some(buffer) =
+        BUFFER_OBJECTS.with(|buffers| buffers.borrow_mut().set_array_buffer_data(data.len()))
+    else {
+        tracing::warn!(what = "glBufferData() was called without a bound array buffer");
+        set_gl_error(GlError::InvalidOperation);
+        return;
+    };
+
+    push_instruction(RenderInstruction::BufferData {
+        buffer,
+        data: Arc::new(data),
+    });
+}
+
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+pub unsafe fn addBufferSubData(
+    _: JNIEnv<'_>,
+    _: JClass<'_>,
+    target: jint,
+    offset: jlong,
+    data: *const u8,
+    byte_length: jint,
+) {
+    assert_eq!(size_of_val(&(0 as jlong)), size_of_val(&data));
+
+    if target as u32 != GL_ARRAY_BUFFER {
+        tracing::warn!(
+            what = "glBufferSubData() was called with an unsupported target: this is a no-op!",
+            target
+        );
+        set_gl_error(GlError::InvalidEnum);
+        return;
+    }
+
+    if offset < 0 {
+        tracing::warn!(
+            what = "glBufferSubData() was called with a negative offset",
+            offset
+        );
+        set_gl_error(GlError::InvalidValue);
+        return;
+    }
+
+    let data = std::slice::from_raw_parts(data, byte_length.max(0) as usize);
+
+    let Some(buffer) = BUFFER_OBJECTS.with(|buffers| {
+        buffers
+            .borrow()
+            .array_buffer_sub_data(offset as usize, data.len())
+    }) else {
+        tracing::warn!(
+            what = "glBufferSubData() was called without a bound array buffer, or past its end",
+            offset,
+            byte_length
+        );
+        set_gl_error(GlError::InvalidValue);
+        return;
+    };
+
+    push_instruction(RenderInstruction::BufferSubData {
+        buffer,
+        offset: offset as u32,
+        data: Arc::new(data.to_vec()),
+    });
+}
+
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+unsafe fn glEnableVertexAttribArray(_: JNIEnv<'_>, _: JClass<'_>, index: jint) {
+    push_instruction(RenderInstruction::SetAttribArrayEnabled {
+        index: index as u32,
+        enabled: true,
+    });
+}
+
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+unsafe fn glDisableVertexAttribArray(_: JNIEnv<'_>, _: JClass<'_>, index: jint) {
+    push_instruction(RenderInstruction::SetAttribArrayEnabled {
+        index: index as u32,
+        enabled: false,
+    });
+}
+
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+pub unsafe fn glDrawArrays(_: JNIEnv<'_>, _: JClass<'_>, mode: jint, first: jint, count: jint) {
+    let Some(mode) = DrawMode::from_i32(mode) else {
+        tracing::warn!(
+            what = "glDrawArrays() was called with an invalid mode",
+            mode
+        );
+        set_gl_error(GlError::InvalidEnum);
+        return;
+    };
+
     push_instruction(RenderInstruction::DrawArrays {
-        mode: DrawMode::from_i32(mode).unwrap(),
+        mode,
         first: first as u32,
         count: count as u32,
     });
@@ -0,0 +1,139 @@
+use std::mem::size_of_val;
+
+use crate::vulkan::display_lists::DISPLAY_LISTS;
+
+use super::jni_prelude::*;
+
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+pub unsafe fn glGenLists(_: JNIEnv<'_>, _: JClass<'_>, range: jint) -> jint {
+    if range < 0 {
+        tracing::warn!(
+            what = "glGenLists() was called with a negative range",
+            range
+        );
+        set_gl_error(GlError::InvalidValue);
+        return 0;
+    }
+
+    DISPLAY_LISTS.with(|lists| lists.borrow_mut().gen_lists(range as u32)) as jint
+}
+
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+pub unsafe fn glDeleteLists(_: JNIEnv<'_>, _: JClass<'_>, list: jint, range: jint) {
+    if range < 0 {
+        tracing::warn!(
+            what = "glDeleteLists() was called with a negative range",
+            range
+        );
+        set_gl_error(GlError::InvalidValue);
+        return;
+    }
+
+    DISPLAY_LISTS.with(|lists| lists.borrow_mut().delete_lists(list as u32, range as u32));
+}
+
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+pub unsafe fn glNewList(_: JNIEnv<'_>, _: JClass<'_>, list: jint, mode: jint) {
+    let execute = match mode as u32 {
+        GL_COMPILE => false,
+        GL_COMPILE_AND_EXECUTE => true,
+        _ => {
+            tracing::warn!(what = "glNewList() was called with an invalid mode", mode);
+            set_gl_error(GlError::InvalidEnum);
+            return;
+        }
+    };
+
+    if list == 0 {
+        tracing::warn!(what = "glNewList() was called with list 0");
+        set_gl_error(GlError::InvalidValue);
+        return;
+    }
+
+    if !with_render_sandbox(|s| s.begin_list(list as u32, execute)) {
+        tracing::warn!(
+            what = "glNewList() was called while another list was being compiled",
+            list
+        );
+        set_gl_error(GlError::InvalidOperation);
+    }
+}
+
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+pub unsafe fn glEndList(_: JNIEnv<'_>, _: JClass<'_>) {
+    let Some((list, insns)) = with_render_sandbox(|s| s.end_list()) else {
+        tracing::warn!(what = "glEndList() was called without a matching glNewList()");
+        set_gl_error(GlError::InvalidOperation);
+        return;
+    };
+
+    DISPLAY_LISTS.with(|lists| lists.borrow_mut().set_list(list, insns));
+}
+
+/// Replays a list into the sandbox. Lists which don't exist are ignored, like in GL.
+fn call_list(list: u32) {
+    let Some(insns) = DISPLAY_LISTS.with(|lists| lists.borrow().get_list(list)) else {
+        return;
+    };
+
+    with_render_sandbox(|s| s.push_all(&insns));
+}
+
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+pub unsafe fn glCallList(_: JNIEnv<'_>, _: JClass<'_>, list: jint) {
+    call_list(list as u32);
+}
+
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+pub unsafe fn glListBase(_: JNIEnv<'_>, _: JClass<'_>, base: jint) {
+    DISPLAY_LISTS.with(|lists| lists.borrow_mut().set_list_base(base as u32));
+}
+
+/// glCallLists, which offsets each list by the glListBase
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+pub unsafe fn addCallLists(
+    _: JNIEnv<'_>,
+    _: JClass<'_>,
+    list_type: jint,
+    start: *const u8,
+    byte_length: jint,
+) {
+    assert_eq!(size_of_val(&(0 as jlong)), size_of_val(&start));
+
+    if byte_length < 0 {
+        tracing::warn!(
+            what = "glCallLists() was called with a negative length",
+            byte_length
+        );
+        set_gl_error(GlError::InvalidValue);
+        return;
+    }
+
+    let bytes = std::slice::from_raw_parts(start, byte_length as usize);
+
+    let lists: Vec<u32> = match list_type as u32 {
+        GL_UNSIGNED_BYTE => bytes.iter().map(|b| *b as u32).collect(),
+        GL_UNSIGNED_SHORT => bytes
+            .chunks_exact(2)
+            .map(|b| u16::from_ne_bytes([b[0], b[1]]) as u32)
+            .collect(),
+        GL_UNSIGNED_INT => bytes
+            .chunks_exact(4)
+            .map(|b| u32::from_ne_bytes([b[0], b[1], b[2], b[3]]))
+            .collect(),
+        _ => {
+            tracing::warn!(
+                what = "glCallLists() was called with an invalid list type",
+                list_type
+            );
+            set_gl_error(GlError::InvalidEnum);
+            return;
+        }
+    };
+
+    let base = DISPLAY_LISTS.with(|lists| lists.borrow().list_base());
+
+    for list in lists {
+        call_list(base.wrapping_add(list));
+    }
+}
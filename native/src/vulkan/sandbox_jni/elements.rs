@@ -0,0 +1,52 @@
+use std::mem::size_of_val;
+
+use super::jni_prelude::*;
+
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+pub unsafe fn addDrawElements(
+    _: JNIEnv<'_>,
+    _: JClass<'_>,
+    mode: jint,
+    count: jint,
+    index_type: jint,
+    start: *const u8,
+    byte_length: jint,
+) {
+    assert_eq!(size_of_val(&(0 as jlong)), size_of_val(&start));
+
+    let Some(mode) = DrawMode::from_i32(mode) else {
+        tracing::warn!(
+            what = "glDrawElements() was called with an invalid mode",
+            mode
+        );
+        set_gl_error(GlError::InvalidEnum);
+        return;
+    };
+
+    let index_type = match index_type as u32 {
+        GL_UNSIGNED_BYTE => GLDataType::U8,
+        GL_UNSIGNED_SHORT => GLDataType::U16,
+        GL_UNSIGNED_INT => GLDataType::U32,
+        _ => {
+            tracing::warn!(
+                what = "glDrawElements() was called with an invalid index type",
+                index_type
+            );
+            set_gl_error(GlError::InvalidEnum);
+            return;
+        }
+    };
+
+    if count <= 0 {
+        return;
+    }
+
+    let indices = std::slice::from_raw_parts(start, byte_length as usize);
+
+    push_instruction(RenderInstruction::DrawElements {
+        mode,
+        count: count as u32,
+        index_type,
+        indices: Arc::new(indices.to_vec()),
+    });
+}
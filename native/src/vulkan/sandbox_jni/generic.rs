@@ -1,3 +1,8 @@
+use crate::vulkan::dynamic_shader::gl_blend_factor;
+use crate::vulkan::dynamic_shader::gl_compare_op;
+use crate::vulkan::dynamic_shader::MAX_CLIP_PLANES;
+use crate::vulkan::textures::texture_manager::TextureCompression;
+
 use super::jni_prelude::*;
 
 #[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
@@ -10,6 +15,468 @@ unsafe fn glDisable(_: JNIEnv<'_>, _: JClass<'_>, cap: jint) {
     push_instruction(RenderInstruction::Disable(cap));
 }
 
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+unsafe fn glProvokingVertex(_: JNIEnv<'_>, _: JClass<'_>, mode: jint) {
+    if let Some(mode) = ProvokingVertex::from_i32(mode) {
+        push_instruction(RenderInstruction::ProvokingVertex(mode));
+    } else {
+        tracing::warn!(
+            what = "glProvokingVertex was called with an invalid parameter and the call has been ignored!",
+            mode
+        );
+    }
+}
+
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+unsafe fn glPolygonMode(_: JNIEnv<'_>, _: JClass<'_>, face: jint, mode: jint) {
+    // vulkan only has one polygon mode for both faces, so the front face's mode wins
+    if face as u32 == GL_BACK {
+        tracing::warn!(
+            what = "glPolygonMode was called for back faces only; this is unsupported and the call has been ignored!",
+            mode
+        );
+        return;
+    }
+
+    if let Some(mode) = PolygonMode::from_i32(mode) {
+        push_instruction(RenderInstruction::PolygonMode(mode));
+    } else {
+        tracing::warn!(
+            what =
+                "glPolygonMode was called with an invalid parameter and the call has been ignored!",
+            mode
+        );
+    }
+}
+
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+unsafe fn glCullFace(_: JNIEnv<'_>, _: JClass<'_>, mode: jint) {
+    if let Some(face) = CullFace::from_i32(mode) {
+        push_instruction(RenderInstruction::CullFace(face));
+    } else {
+        tracing::warn!(
+            what = "glCullFace was called with an invalid parameter and the call has been ignored!",
+            mode
+        );
+    }
+}
+
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+unsafe fn glFrontFace(_: JNIEnv<'_>, _: JClass<'_>, mode: jint) {
+    if let Some(winding) = Winding::from_i32(mode) {
+        push_instruction(RenderInstruction::FrontFace(winding));
+    } else {
+        tracing::warn!(
+            what =
+                "glFrontFace was called with an invalid parameter and the call has been ignored!",
+            mode
+        );
+    }
+}
+
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+unsafe fn glDepthFunc(_: JNIEnv<'_>, _: JClass<'_>, func: jint) {
+    if let Some(func) = DepthFunc::from_i32(func) {
+        push_instruction(RenderInstruction::DepthFunc(func));
+    } else {
+        tracing::warn!(
+            what =
+                "glDepthFunc was called with an invalid parameter and the call has been ignored!",
+            func
+        );
+    }
+}
+
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+unsafe fn glAlphaFunc(_: JNIEnv<'_>, _: JClass<'_>, func: jint, ref_value: jfloat) {
+    if let Some(func) = DepthFunc::from_i32(func) {
+        push_instruction(RenderInstruction::AlphaFunc {
+            func: gl_compare_op(func),
+            ref_value,
+        });
+    } else {
+        tracing::warn!(
+            what =
+                "glAlphaFunc was called with an invalid parameter and the call has been ignored!",
+            func
+        );
+    }
+}
+
+/// The equation is passed as four doubles instead of GL's DoubleBuffer
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+unsafe fn glClipPlane(
+    _: JNIEnv<'_>,
+    _: JClass<'_>,
+    plane: jint,
+    a: jdouble,
+    b: jdouble,
+    c: jdouble,
+    d: jdouble,
+) {
+    let plane = (plane as u32).wrapping_sub(GL_CLIP_PLANE0) as usize;
+
+    if plane >= MAX_CLIP_PLANES {
+        tracing::warn!(
+            what = "glClipPlane was called with an invalid plane and the call has been ignored!",
+            plane
+        );
+        return;
+    }
+
+    push_instruction(RenderInstruction::ClipPlane {
+        plane,
+        equation: [a as f32, b as f32, c as f32, d as f32].into(),
+    });
+}
+
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+unsafe fn glDepthMask(_: JNIEnv<'_>, _: JClass<'_>, flag: jboolean) {
+    push_instruction(RenderInstruction::DepthMask(flag != 0));
+}
+
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+unsafe fn glGetInteger(_: JNIEnv<'_>, _: JClass<'_>, pname: jint) -> jint {
+    if let Some(value) = with_render_sandbox(|s| s.get_integer(pname as u32)) {
+        return value;
+    }
+
+    // a device limit rather than state, so it's known without an assembler too
+    if pname as u32 == GL_MAX_TEXTURE_IMAGE_UNITS {
+        read_field_into!(inst; devices);
+
+        return devices.max_texture_units() as jint;
+    }
+
+    tracing::warn!(
+        what = "glGetInteger was called with an unsupported parameter",
+        pname
+    );
+
+    0
+}
+
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+unsafe fn glGetBoolean(_: JNIEnv<'_>, _: JClass<'_>, pname: jint) -> jboolean {
+    match with_render_sandbox(|s| s.get_booleans(pname as u32)) {
+        Some(values) => values[0] as jboolean,
+        None => {
+            tracing::warn!(
+                what = "glGetBoolean was called with an unsupported parameter",
+                pname
+            );
+            JNI_FALSE
+        }
+    }
+}
+
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+unsafe fn glGetBooleanv(env: JNIEnv<'_>, _: JClass<'_>, pname: jint, params: JByteBuffer) {
+    let Some(values) = with_render_sandbox(|s| s.get_booleans(pname as u32)) else {
+        tracing::warn!(
+            what = "glGetBooleanv was called with an unsupported parameter and the call has been ignored!",
+            pname
+        );
+        return;
+    };
+
+    let dest = std::slice::from_raw_parts_mut(
+        env.get_direct_buffer_address(&params).unwrap(),
+        env.get_direct_buffer_capacity(&params).unwrap(),
+    );
+
+    for (dest, value) in dest.iter_mut().zip(values) {
+        *dest = value as u8;
+    }
+}
+
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+unsafe fn glColorMask(
+    _: JNIEnv<'_>,
+    _: JClass<'_>,
+    red: jboolean,
+    green: jboolean,
+    blue: jboolean,
+    alpha: jboolean,
+) {
+    push_instruction(RenderInstruction::ColorMask(
+        [red, green, blue, alpha].map(|c| c != 0),
+    ));
+}
+
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+unsafe fn glBlendColor(
+    _: JNIEnv<'_>,
+    _: JClass<'_>,
+    red: jfloat,
+    green: jfloat,
+    blue: jfloat,
+    alpha: jfloat,
+) {
+    push_instruction(RenderInstruction::BlendColor(
+        [red, green, blue, alpha].map(|c| c.clamp(0.0, 1.0)).into(),
+    ));
+}
+
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+unsafe fn glBlendFunc(_: JNIEnv<'_>, _: JClass<'_>, sfactor: jint, dfactor: jint) {
+    match (
+        gl_blend_factor(sfactor as u32),
+        gl_blend_factor(dfactor as u32),
+    ) {
+        (Some(src), Some(dst)) => {
+            push_instruction(RenderInstruction::BlendFunc { src, dst });
+        }
+        _ => {
+            tracing::warn!(
+                what = "glBlendFunc was called with an invalid parameter and the call has been ignored!",
+                sfactor,
+                dfactor
+            );
+        }
+    }
+}
+
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+unsafe fn glFenceSync(_: JNIEnv<'_>, _: JClass<'_>, condition: jint, flags: jint) -> jlong {
+    if condition as u32 != GL_SYNC_GPU_COMMANDS_COMPLETE || flags != 0 {
+        tracing::warn!(
+            what =
+                "glFenceSync was called with an invalid parameter and the call has been ignored!",
+            condition,
+            flags
+        );
+        return 0;
+    }
+
+    write_field_into!(inst; rendering);
+
+    rendering.fence_sync() as jlong
+}
+
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+unsafe fn glClientWaitSync(
+    _: JNIEnv<'_>,
+    _: JClass<'_>,
+    sync: jlong,
+    _flags: jint,
+    timeout: jlong,
+) -> jint {
+    write_field_into!(inst; rendering);
+
+    // commands are always flushed when a frame is submitted, so GL_SYNC_FLUSH_COMMANDS_BIT
+    // doesn't need any handling
+    rendering
+        .client_wait_sync(
+            sync as u64,
+            std::time::Duration::from_nanos(timeout.max(0) as u64),
+        )
+        .to_gl() as jint
+}
+
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+unsafe fn glDeleteSync(_: JNIEnv<'_>, _: JClass<'_>, sync: jlong) {
+    write_field_into!(inst; rendering);
+
+    rendering.delete_sync(sync as u64);
+}
+
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+unsafe fn glScissor(_: JNIEnv<'_>, _: JClass<'_>, x: jint, y: jint, width: jint, height: jint) {
+    if width < 0 || height < 0 {
+        tracing::warn!(
+            what = "glScissor was called with a negative size and the call has been ignored!",
+            width,
+            height
+        );
+        return;
+    }
+
+    push_instruction(RenderInstruction::Scissor(ScissorRect {
+        x,
+        y,
+        width: width as u32,
+        height: height as u32,
+    }));
+}
+
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+unsafe fn glViewport(_: JNIEnv<'_>, _: JClass<'_>, x: jint, y: jint, width: jint, height: jint) {
+    if width < 0 || height < 0 {
+        tracing::warn!(
+            what = "glViewport was called with a negative size and the call has been ignored!",
+            width,
+            height
+        );
+        return;
+    }
+
+    push_instruction(RenderInstruction::Viewport {
+        index: None,
+        x,
+        y,
+        width: width as u32,
+        height: height as u32,
+    });
+}
+
+/// Viewports past the first are only used by shaders which select them with gl_ViewportIndex, and
+/// only exist while the device supports multiViewport
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+unsafe fn glViewportIndexedf(
+    _: JNIEnv<'_>,
+    _: JClass<'_>,
+    index: jint,
+    x: jfloat,
+    y: jfloat,
+    width: jfloat,
+    height: jfloat,
+) {
+    if index < 0 || width < 0.0 || height < 0.0 {
+        tracing::warn!(
+            what = "glViewportIndexedf was called with a negative index or size and the call has been ignored!",
+            index,
+            width,
+            height
+        );
+        return;
+    }
+
+    // viewports are kept in whole pixels
+    push_instruction(RenderInstruction::Viewport {
+        index: Some(index as u32),
+        x: x.round() as i32,
+        y: y.round() as i32,
+        width: width.round() as u32,
+        height: height.round() as u32,
+    });
+}
+
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+unsafe fn glDepthRange(_: JNIEnv<'_>, _: JClass<'_>, near: jdouble, far: jdouble) {
+    push_instruction(RenderInstruction::DepthRange {
+        index: None,
+        near: near as f32,
+        far: far as f32,
+    });
+}
+
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+unsafe fn glDepthRangeIndexed(
+    _: JNIEnv<'_>,
+    _: JClass<'_>,
+    index: jint,
+    near: jdouble,
+    far: jdouble,
+) {
+    if index < 0 {
+        tracing::warn!(
+            what = "glDepthRangeIndexed was called with a negative index and the call has been ignored!",
+            index
+        );
+        return;
+    }
+
+    push_instruction(RenderInstruction::DepthRange {
+        index: Some(index as u32),
+        near: near as f32,
+        far: far as f32,
+    });
+}
+
+/// Not a GL function: clips to the intersection of the given rectangle and the current scissor
+/// region, so nested GUI elements can't draw outside of their parents.
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+unsafe fn pushScissor(_: JNIEnv<'_>, _: JClass<'_>, x: jint, y: jint, width: jint, height: jint) {
+    if width < 0 || height < 0 {
+        tracing::warn!(
+            what = "pushScissor was called with a negative size and the call has been ignored!",
+            width,
+            height
+        );
+        return;
+    }
+
+    push_instruction(RenderInstruction::PushScissor(ScissorRect {
+        x,
+        y,
+        width: width as u32,
+        height: height as u32,
+    }));
+}
+
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+unsafe fn popScissor(_: JNIEnv<'_>, _: JClass<'_>) {
+    push_instruction(RenderInstruction::PopScissor);
+}
+
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+unsafe fn glPushAttrib(_: JNIEnv<'_>, _: JClass<'_>, mask: jint) {
+    push_instruction(RenderInstruction::PushAttrib(mask as u32));
+}
+
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+unsafe fn glPopAttrib(_: JNIEnv<'_>, _: JClass<'_>) {
+    push_instruction(RenderInstruction::PopAttrib);
+}
+
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+unsafe fn glInitNames(_: JNIEnv<'_>, _: JClass<'_>) {
+    push_instruction(RenderInstruction::InitNames);
+}
+
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+unsafe fn glPushName(_: JNIEnv<'_>, _: JClass<'_>, name: jint) {
+    push_instruction(RenderInstruction::PushName(name as u32));
+}
+
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+unsafe fn glPopName(_: JNIEnv<'_>, _: JClass<'_>) {
+    push_instruction(RenderInstruction::PopName);
+}
+
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+unsafe fn glLoadName(_: JNIEnv<'_>, _: JClass<'_>, name: jint) {
+    push_instruction(RenderInstruction::LoadName(name as u32));
+}
+
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+unsafe fn glHint(_: JNIEnv<'_>, _: JClass<'_>, target: jint, mode: jint) {
+    if target as u32 != GL_TEXTURE_COMPRESSION_HINT {
+        tracing::warn!(
+            what = "glHint was called with an unsupported target and the call has been ignored!",
+            target,
+            mode
+        );
+        return;
+    }
+
+    if let Some(compression) = TextureCompression::from_hint(mode as u32) {
+        write_field_into!(inst; textures);
+
+        textures.texture_storage.compression = compression;
+    } else {
+        tracing::warn!(
+            what = "glHint was called with an invalid parameter and the call has been ignored!",
+            mode
+        );
+    }
+}
+
+/// Returns the first error recorded since the last call, or GL_NO_ERROR, and clears it
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+pub unsafe fn glGetError(_: JNIEnv<'_>, _: JClass<'_>) -> jint {
+    take_gl_error().map_or(GL_NO_ERROR, |error| error as u32) as jint
+}
+
+/// Lets the gpu start on the frame in progress before it's presented
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+unsafe fn glFlush(mut env: JNIEnv<'_>, _: JClass<'_>) {
+    write_instance_into!(inst);
+
+    throw!(env, inst.flush());
+}
+
 #[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
 unsafe fn glClear(_: JNIEnv<'_>, _: JClass<'_>, mask: jint) {
     let mask = mask as u32;
@@ -20,10 +487,17 @@ unsafe fn glClear(_: JNIEnv<'_>, _: JClass<'_>, mask: jint) {
     if colour {
         write_instance_into!(inst);
 
-        inst.on_clear_colour();
-    } else {
-        if depth {
-            push_instruction(RenderInstruction::ClearDepth);
+        // the frame ends on a colour clear whether or not it's scissored, and starting the next
+        // one clears all of it, depth included
+        if inst.frame_boundary().clear_presents() {
+            inst.present();
+            return;
         }
+
+        push_instruction(RenderInstruction::ClearColour);
+    }
+
+    if depth {
+        push_instruction(RenderInstruction::ClearDepth);
     }
 }
@@ -2,9 +2,17 @@ use super::jni_prelude::*;
 
 #[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
 unsafe fn glMatrixMode(_: JNIEnv<'_>, _: JClass<'_>, mode: jint) {
-    push_instruction(RenderInstruction::MatrixMode(
-        MatrixMode::from_i32(mode).unwrap(),
-    ));
+    let Some(mode) = MatrixMode::from_i32(mode) else {
+        tracing::warn!(
+            what =
+                "glMatrixMode was called with an invalid parameter and the call has been ignored!",
+            mode
+        );
+        set_gl_error(GlError::InvalidEnum);
+        return;
+    };
+
+    push_instruction(RenderInstruction::MatrixMode(mode));
 }
 
 #[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
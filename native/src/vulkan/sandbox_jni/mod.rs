@@ -1,6 +1,10 @@
 pub mod client_arrays;
+pub mod display_lists;
+pub mod elements;
 pub mod generic;
 pub mod jni_prelude;
 pub mod matrices;
+pub mod programs;
 pub mod rendering;
+pub mod screenshots;
 pub mod textures;
@@ -0,0 +1,433 @@
+use smallvec::smallvec;
+
+use crate::jni::direct_buffer_remaining;
+use crate::vulkan::shaders::programs::UserShaderStage;
+use crate::vulkan::utils::TypedVec;
+
+use super::jni_prelude::*;
+
+/// Reads the string between the buffer's position and limit, None if the buffer is null
+unsafe fn read_buffer_string(env: &mut JNIEnv<'_>, buffer: &JByteBuffer<'_>) -> Option<String> {
+    let bytes = direct_buffer_remaining(env, buffer)?;
+
+    Some(
+        String::from_utf8_lossy(bytes)
+            .trim_end_matches('\0')
+            .to_owned(),
+    )
+}
+
+fn truncate_log(log: &str, max_length: jint) -> String {
+    log.chars().take(max_length.max(0) as usize).collect()
+}
+
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+pub unsafe fn glCreateShader(_: JNIEnv<'_>, _: JClass<'_>, shader_type: jint) -> jint {
+    let Some(stage) = UserShaderStage::from_gl(shader_type as u32) else {
+        tracing::warn!(
+            what = "glCreateShader() was called with an unsupported shader type",
+            shader_type
+        );
+        return 0;
+    };
+
+    write_field_into!(inst; programs);
+
+    programs.create_shader(stage) as jint
+}
+
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+pub unsafe fn glShaderSource(
+    mut env: JNIEnv<'_>,
+    _: JClass<'_>,
+    shader: jint,
+    source: JByteBuffer<'_>,
+) {
+    let Some(source) = read_buffer_string(&mut env, &source) else {
+        jni_bail!(env, "glShaderSource() was called with a null source");
+    };
+
+    write_field_into!(inst; programs);
+
+    let Some(shader) = programs.shader_mut(shader as u32) else {
+        tracing::warn!(
+            what = "glShaderSource() was called with an invalid shader",
+            shader
+        );
+        return;
+    };
+
+    shader.source = source;
+}
+
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+pub unsafe fn glCompileShader(_: JNIEnv<'_>, _: JClass<'_>, shader: jint) {
+    write_field_into!(inst; programs);
+
+    let Some(user_shader) = programs.shader_mut(shader as u32) else {
+        tracing::warn!(
+            what = "glCompileShader() was called with an invalid shader",
+            shader
+        );
+        return;
+    };
+
+    if !user_shader.compile() {
+        tracing::warn!(
+            what = "a user shader failed to compile",
+            shader,
+            log = user_shader.info_log
+        );
+    }
+}
+
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+pub unsafe fn glGetShaderi(_: JNIEnv<'_>, _: JClass<'_>, shader: jint, param: jint) -> jint {
+    read_field_into!(inst; programs);
+
+    let Some(user_shader) = programs.shader(shader as u32) else {
+        tracing::warn!(
+            what = "glGetShaderi() was called with an invalid shader",
+            shader
+        );
+        return 0;
+    };
+
+    match param as u32 {
+        GL_COMPILE_STATUS => user_shader.spirv.is_some() as jint,
+        GL_INFO_LOG_LENGTH => user_shader.info_log.len() as jint,
+        GL_SHADER_TYPE => user_shader.stage.to_gl() as jint,
+        _ => {
+            tracing::warn!(
+                what = "glGetShaderi() was called with an unsupported param",
+                param
+            );
+            0
+        }
+    }
+}
+
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+pub unsafe fn glGetShaderInfoLog(
+    env: JNIEnv<'_>,
+    _: JClass<'_>,
+    shader: jint,
+    max_length: jint,
+) -> jstring {
+    read_field_into!(inst; programs);
+
+    let log = programs
+        .shader(shader as u32)
+        .map(|s| truncate_log(&s.info_log, max_length))
+        .unwrap_or_default();
+
+    env.new_string(log).unwrap().into_raw()
+}
+
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+pub unsafe fn glDeleteShader(_: JNIEnv<'_>, _: JClass<'_>, shader: jint) {
+    write_field_into!(inst; programs);
+
+    programs.delete_shader(shader as u32);
+}
+
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+pub unsafe fn glCreateProgram(_: JNIEnv<'_>, _: JClass<'_>) -> jint {
+    write_field_into!(inst; programs);
+
+    programs.create_program() as jint
+}
+
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+pub unsafe fn glAttachShader(_: JNIEnv<'_>, _: JClass<'_>, program: jint, shader: jint) {
+    write_field_into!(inst; programs);
+
+    if !programs.attach_shader(program as u32, shader as u32) {
+        tracing::warn!(
+            what = "glAttachShader() was called with an invalid program or shader",
+            program,
+            shader
+        );
+    }
+}
+
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+pub unsafe fn glLinkProgram(_: JNIEnv<'_>, _: JClass<'_>, program: jint) {
+    write_field_into!(inst; programs);
+
+    match programs.link_program(program as u32) {
+        Some(true) => {}
+        Some(false) => {
+            tracing::warn!(
+                what = "a user program failed to link",
+                program,
+                log = programs.program(program as u32).unwrap().info_log
+            );
+        }
+        None => {
+            tracing::warn!(
+                what = "glLinkProgram() was called with an invalid program",
+                program
+            );
+        }
+    }
+}
+
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+pub unsafe fn glGetProgrami(_: JNIEnv<'_>, _: JClass<'_>, program: jint, param: jint) -> jint {
+    read_field_into!(inst; programs);
+
+    let Some(user_program) = programs.program(program as u32) else {
+        tracing::warn!(
+            what = "glGetProgrami() was called with an invalid program",
+            program
+        );
+        return 0;
+    };
+
+    match param as u32 {
+        GL_LINK_STATUS => user_program.linked.is_some() as jint,
+        GL_INFO_LOG_LENGTH => user_program.info_log.len() as jint,
+        GL_ATTACHED_SHADERS => user_program.shaders.len() as jint,
+        _ => {
+            tracing::warn!(
+                what = "glGetProgrami() was called with an unsupported param",
+                param
+            );
+            0
+        }
+    }
+}
+
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+pub unsafe fn glGetProgramInfoLog(
+    env: JNIEnv<'_>,
+    _: JClass<'_>,
+    program: jint,
+    max_length: jint,
+) -> jstring {
+    read_field_into!(inst; programs);
+
+    let log = programs
+        .program(program as u32)
+        .map(|p| truncate_log(&p.info_log, max_length))
+        .unwrap_or_default();
+
+    env.new_string(log).unwrap().into_raw()
+}
+
+/// Binding program 0 goes back to the fixed-function pipeline
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+pub unsafe fn glUseProgram(_: JNIEnv<'_>, _: JClass<'_>, program: jint) {
+    if program == 0 {
+        push_instruction(RenderInstruction::UseProgram(None));
+        return;
+    }
+
+    read_field_into!(inst; programs);
+
+    let Some(linked) = programs
+        .program(program as u32)
+        .and_then(|p| p.linked.clone())
+    else {
+        tracing::warn!(
+            what = "glUseProgram() was called with a program that isn't linked: this is a no-op!",
+            program
+        );
+        return;
+    };
+
+    push_instruction(RenderInstruction::UseProgram(Some(linked)));
+}
+
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+pub unsafe fn glDeleteProgram(_: JNIEnv<'_>, _: JClass<'_>, program: jint) {
+    write_field_into!(inst; programs);
+
+    programs.delete_program(program as u32);
+}
+
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+pub unsafe fn glGetAttribLocation(
+    mut env: JNIEnv<'_>,
+    _: JClass<'_>,
+    program: jint,
+    name: JByteBuffer<'_>,
+) -> jint {
+    let Some(name) = read_buffer_string(&mut env, &name) else {
+        jni_bail!(env, "glGetAttribLocation() was called with a null name");
+    };
+
+    read_field_into!(inst; programs);
+
+    programs
+        .program(program as u32)
+        .and_then(|p| p.linked.as_ref())
+        .and_then(|linked| linked.attribute_location(&name))
+        .map(|location| location as jint)
+        .unwrap_or(-1)
+}
+
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+pub unsafe fn glGetUniformLocation(
+    mut env: JNIEnv<'_>,
+    _: JClass<'_>,
+    program: jint,
+    name: JByteBuffer<'_>,
+) -> jint {
+    let Some(name) = read_buffer_string(&mut env, &name) else {
+        jni_bail!(env, "glGetUniformLocation() was called with a null name");
+    };
+
+    read_field_into!(inst; programs);
+
+    programs
+        .program(program as u32)
+        .and_then(|p| p.linked.as_ref())
+        .and_then(|linked| linked.uniform_location(&name))
+        .map(|location| location as jint)
+        .unwrap_or(-1)
+}
+
+/// Location -1 is silently ignored, like GL does
+fn set_uniform(location: jint, value: TypedVec) {
+    if location < 0 {
+        return;
+    }
+
+    push_instruction(RenderInstruction::SetUniform(location as u32, value));
+}
+
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+pub unsafe fn glUniform1f(_: JNIEnv<'_>, _: JClass<'_>, location: jint, x: jfloat) {
+    set_uniform(location, TypedVec::F32s(smallvec![x]));
+}
+
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+pub unsafe fn glUniform2f(_: JNIEnv<'_>, _: JClass<'_>, location: jint, x: jfloat, y: jfloat) {
+    set_uniform(location, TypedVec::F32s(smallvec![x, y]));
+}
+
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+pub unsafe fn glUniform3f(
+    _: JNIEnv<'_>,
+    _: JClass<'_>,
+    location: jint,
+    x: jfloat,
+    y: jfloat,
+    z: jfloat,
+) {
+    set_uniform(location, TypedVec::F32s(smallvec![x, y, z]));
+}
+
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+pub unsafe fn glUniform4f(
+    _: JNIEnv<'_>,
+    _: JClass<'_>,
+    location: jint,
+    x: jfloat,
+    y: jfloat,
+    z: jfloat,
+    w: jfloat,
+) {
+    set_uniform(location, TypedVec::F32s(smallvec![x, y, z, w]));
+}
+
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+pub unsafe fn glUniform1i(_: JNIEnv<'_>, _: JClass<'_>, location: jint, x: jint) {
+    set_uniform(location, TypedVec::I32s(smallvec![x]));
+}
+
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+pub unsafe fn glUniform2i(_: JNIEnv<'_>, _: JClass<'_>, location: jint, x: jint, y: jint) {
+    set_uniform(location, TypedVec::I32s(smallvec![x, y]));
+}
+
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+pub unsafe fn glUniform3i(_: JNIEnv<'_>, _: JClass<'_>, location: jint, x: jint, y: jint, z: jint) {
+    set_uniform(location, TypedVec::I32s(smallvec![x, y, z]));
+}
+
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+pub unsafe fn glUniform4i(
+    _: JNIEnv<'_>,
+    _: JClass<'_>,
+    location: jint,
+    x: jint,
+    y: jint,
+    z: jint,
+    w: jint,
+) {
+    set_uniform(location, TypedVec::I32s(smallvec![x, y, z, w]));
+}
+
+/// Reads one square matrix from a direct FloatBuffer, transposing it into GL's column-major
+/// order if needed. Uniform arrays aren't supported, so any further matrices are ignored.
+unsafe fn set_matrix_uniform(
+    env: &mut JNIEnv<'_>,
+    location: jint,
+    size: usize,
+    transpose: jboolean,
+    matrices: &JByteBuffer<'_>,
+) {
+    let capacity = env.get_direct_buffer_capacity(matrices).unwrap();
+
+    if capacity < size * size {
+        tracing::warn!(
+            what = "glUniformMatrix*() was called with a buffer that's too small",
+            location,
+            capacity
+        );
+        return;
+    }
+
+    let floats = std::slice::from_raw_parts(
+        env.get_direct_buffer_address(matrices).unwrap() as *const f32,
+        size * size,
+    );
+
+    let value = (0..size * size)
+        .map(|i| {
+            if transpose != 0 {
+                floats[(i % size) * size + i / size]
+            } else {
+                floats[i]
+            }
+        })
+        .collect();
+
+    set_uniform(location, TypedVec::F32s(value));
+}
+
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+pub unsafe fn glUniformMatrix2(
+    mut env: JNIEnv<'_>,
+    _: JClass<'_>,
+    location: jint,
+    transpose: jboolean,
+    matrices: JByteBuffer<'_>,
+) {
+    set_matrix_uniform(&mut env, location, 2, transpose, &matrices);
+}
+
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+pub unsafe fn glUniformMatrix3(
+    mut env: JNIEnv<'_>,
+    _: JClass<'_>,
+    location: jint,
+    transpose: jboolean,
+    matrices: JByteBuffer<'_>,
+) {
+    set_matrix_uniform(&mut env, location, 3, transpose, &matrices);
+}
+
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+pub unsafe fn glUniformMatrix4(
+    mut env: JNIEnv<'_>,
+    _: JClass<'_>,
+    location: jint,
+    transpose: jboolean,
+    matrices: JByteBuffer<'_>,
+) {
+    set_matrix_uniform(&mut env, location, 4, transpose, &matrices);
+}
@@ -11,6 +11,7 @@ pub unsafe fn glBegin(mut env: JNIEnv<'_>, _: JClass<'_>, mode: jint) {
             what = "glBegin was called with an invalid parameter and the call has been ignored!",
             mode
         );
+        set_gl_error(GlError::InvalidEnum);
     }
 }
 
@@ -40,7 +41,7 @@ gl_fn_decl!(
     glNormal,
     [2, 3, 4],
     [0.0, 0.0, 0.0, 1.0],
-    |x, y, z, w| RenderInstruction::TexCoord([x, y, z, w].into())
+    |x, y, z, _w| RenderInstruction::Normal([x, y, z].into())
 );
 
 gl_fn_decl!(
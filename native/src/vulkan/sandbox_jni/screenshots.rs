@@ -0,0 +1,16 @@
+use super::jni_prelude::*;
+
+/// Saves the next presented frame to `path` as a PNG
+#[jni_export("com.recursive_pineapple.mcvk.rendering.MCVKNative")]
+pub unsafe fn captureScreenshot(mut env: JNIEnv<'_>, _: JClass<'_>, path: JString<'_>) {
+    let path: String = match env.get_string(&path) {
+        Ok(path) => path.into(),
+        Err(e) => {
+            jni_bail!(env, format!("invalid screenshot path: {e}"));
+        }
+    };
+
+    write_instance_into!(inst);
+
+    inst.request_screenshot(path.into());
+}
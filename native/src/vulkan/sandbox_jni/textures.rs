@@ -5,8 +5,17 @@ use image::RgbaImage;
 use serde::Deserialize;
 use serde::Serialize;
 
+use crate::jni::direct_buffer_remaining;
+use crate::jni::direct_buffer_remaining_mut;
+use crate::vulkan::error::McvkError;
+use crate::vulkan::textures::texture_manager::upload_in_chunks;
+use crate::vulkan::textures::textures::decode_gl_pixels;
+use crate::vulkan::textures::textures::encode_gl_pixels;
+use crate::vulkan::textures::textures::gl_compressed_format;
+use crate::vulkan::textures::textures::tex_image;
 use crate::vulkan::textures::textures::AnimationMetadata;
 use crate::vulkan::textures::textures::TextureImage;
+use crate::vulkan::textures::textures::TextureLoadError;
 
 use super::jni_prelude::*;
 
@@ -26,19 +35,123 @@ pub unsafe fn glDeleteTextures(_: JNIEnv<'_>, _: JClass<'_>, texture: jint) {
     inst.textures.write().free_texture(transmute(texture));
 }
 
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+pub unsafe fn glGenTexturesBatch(
+    mut env: JNIEnv<'_>,
+    _: JClass<'_>,
+    count: jint,
+    out: JIntArray<'_>,
+) {
+    if out.is_null() {
+        jni_bail!(env, "glGenTexturesBatch() was called with a null array");
+    }
+
+    let length = env.get_array_length(&out).unwrap();
+
+    if count < 0 || count > length {
+        jni_bail!(
+            env,
+            format!("glGenTexturesBatch() was asked for {count} textures with room for {length}")
+        );
+    }
+
+    write_instance_into!(inst);
+
+    let ids = inst.textures.write().create_textures(count as usize);
+
+    env.set_int_array_region(&out, 0, &ids).unwrap();
+}
+
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+pub unsafe fn glDeleteTexturesBatch(mut env: JNIEnv<'_>, _: JClass<'_>, textures: JIntArray<'_>) {
+    if textures.is_null() {
+        jni_bail!(env, "glDeleteTexturesBatch() was called with a null array");
+    }
+
+    let length = env.get_array_length(&textures).unwrap();
+
+    let mut ids = vec![0; length as usize];
+    env.get_int_array_region(&textures, 0, &mut ids).unwrap();
+
+    write_instance_into!(inst);
+
+    let mut textures = inst.textures.write();
+
+    for id in ids {
+        textures.free_texture(id);
+    }
+}
+
+/// Rectangle textures are stored like any other, but never mipmapped: only their sampling
+/// differs, which is why the bind target goes through to the assembler.
+fn is_2d_target(target: jint) -> bool {
+    target as u32 == GL_TEXTURE_2D || target as u32 == GL_TEXTURE_RECTANGLE
+}
+
 #[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
 pub unsafe fn glBindTexture(_: JNIEnv<'_>, _: JClass<'_>, target: jint, texture: jint) {
-    if target as u32 != GL_TEXTURE_2D {
+    match target as u32 {
+        GL_TEXTURE_2D => push_instruction(RenderInstruction::BindTexture(texture)),
+        GL_TEXTURE_RECTANGLE => push_instruction(RenderInstruction::BindTextureRectangle(texture)),
+        _ => {
+            tracing::warn!(
+                what = "glBindTexture() was called with target other than GL_TEXTURE_2D or GL_TEXTURE_RECTANGLE: this is a no-op!",
+                target,
+                texture
+            );
+        }
+    }
+}
+
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+pub unsafe fn glActiveTexture(_: JNIEnv<'_>, _: JClass<'_>, texture: jint) {
+    let Some(unit) = (texture as u32).checked_sub(GL_TEXTURE0) else {
         tracing::warn!(
-            what =
-                "glBindTexture() was called with target other than GL_TEXTURE_2D: this is a no-op!",
-            target,
+            what = "glActiveTexture() was called with an invalid texture unit: this is a no-op!",
             texture
         );
         return;
+    };
+
+    push_instruction(RenderInstruction::SetActiveTextureUnit(unit as usize));
+}
+
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+pub unsafe fn glTexEnvi(_: JNIEnv<'_>, _: JClass<'_>, target: jint, pname: jint, param: jint) {
+    if target as u32 != GL_TEXTURE_ENV || pname as u32 != GL_TEXTURE_ENV_MODE {
+        tracing::warn!(
+            what = "glTexEnvi() was called with an unsupported target or pname: this is a no-op!",
+            target,
+            pname
+        );
+        return;
     }
 
-    push_instruction(RenderInstruction::BindTexture(texture));
+    let Some(mode) = TextureEnvMode::from_i32(param) else {
+        tracing::warn!(
+            what = "glTexEnvi() was called with an unsupported mode: this is a no-op!",
+            param
+        );
+        return;
+    };
+
+    push_instruction(RenderInstruction::SetTextureEnvMode(mode));
+}
+
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+pub unsafe fn glMultiTexCoord2f(_: JNIEnv<'_>, _: JClass<'_>, target: jint, s: jfloat, t: jfloat) {
+    let Some(unit) = (target as u32).checked_sub(GL_TEXTURE0) else {
+        tracing::warn!(
+            what = "glMultiTexCoord2f() was called with an invalid texture unit: this is a no-op!",
+            target
+        );
+        return;
+    };
+
+    push_instruction(RenderInstruction::MultiTexCoord {
+        unit: unit as usize,
+        coord: [s, t, 0.0, 1.0].into(),
+    });
 }
 
 #[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
@@ -53,22 +166,326 @@ pub unsafe fn glTexImage2D(
     height: jint,
     border: jint,
     cpu_format: jint,
+    data_type: jint,
+    data: JByteBuffer,
+) {
+    if !is_2d_target(target) {
+        tracing::warn!(
+            what = "glTexImage2D() was called with target other than GL_TEXTURE_2D or GL_TEXTURE_RECTANGLE: this is a no-op!",
+            target
+        );
+        return;
+    }
+
+    if mip_level != 0 {
+        // mips are generated from the base level when the update is recorded
+        tracing::warn!(
+            what = "glTexImage2D() was called for a mip level other than 0: this is a no-op!",
+            mip_level
+        );
+        return;
+    }
+
+    if width < 0 || height < 0 || border != 0 {
+        jni_bail!(
+            env,
+            format!("glTexImage2D() was called with size {width}x{height} and border {border}")
+        );
+    }
+
+    // gpu_format is ignored, since every texture is stored as RGBA
+    let Some(bound_texture) = with_render_sandbox(|s| s.get_bound_texture()) else {
+        tracing::warn!(what = "tried to call glTexImage2D with no bound texture");
+        return;
+    };
+
+    // a null buffer only allocates the texture
+    let pixels = direct_buffer_remaining(&mut env, &data);
+
+    let image = throw!(
+        env,
+        tex_image(
+            cpu_format as u32,
+            data_type as u32,
+            width as u32,
+            height as u32,
+            pixels
+        )
+        .map_err(McvkError::texture)
+    );
+
+    write_field_into!(inst; textures);
+
+    // the update is recorded along with the next frame
+    throw!(env, textures.enqueue_tex_image(bound_texture, image));
+}
+
+/// DXT1 and DXT5 textures are uploaded as BC1 and BC3, or decoded to RGBA if the device can't
+/// sample BC formats.
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+pub unsafe fn glCompressedTexImage2D(
+    mut env: JNIEnv<'_>,
+    _: JClass<'_>,
+    target: jint,
+    mip_level: jint,
+    internal_format: jint,
+    width: jint,
+    height: jint,
+    border: jint,
     data: JByteBuffer,
 ) {
     if target as u32 != GL_TEXTURE_2D {
         tracing::warn!(
-            what =
-                "glTexImage2D() was called with target other than GL_TEXTURE_2D: this is a no-op!",
+            what = "glCompressedTexImage2D() was called with target other than GL_TEXTURE_2D: this is a no-op!",
             target
         );
         return;
     }
 
-    jni_todo!(env);
+    if mip_level != 0 {
+        tracing::warn!(
+            what = "glCompressedTexImage2D() was called for a mip level other than 0: this is a no-op!",
+            mip_level
+        );
+        return;
+    }
+
+    if width < 0 || height < 0 || border != 0 {
+        jni_bail!(
+            env,
+            format!(
+                "glCompressedTexImage2D() was called with size {width}x{height} and border {border}"
+            )
+        );
+    }
+
+    let Some(format) = gl_compressed_format(internal_format as u32) else {
+        jni_bail!(
+            env,
+            format!(
+                "glCompressedTexImage2D() was called with unsupported format {internal_format}"
+            )
+        );
+    };
+
+    let Some(data) = direct_buffer_remaining(&mut env, &data) else {
+        jni_bail!(env, "glCompressedTexImage2D() was called without any data");
+    };
+
+    let Some(bound_texture) = with_render_sandbox(|s| s.get_bound_texture()) else {
+        tracing::warn!(what = "tried to call glCompressedTexImage2D with no bound texture");
+        return;
+    };
+
+    let image = TextureImage::Compressed {
+        format,
+        data: data.to_vec(),
+        width: width as u32,
+        height: height as u32,
+    };
+
+    write_field_into!(inst; textures);
+
+    // the update is recorded along with the next frame
+    throw!(env, textures.enqueue_tex_image(bound_texture, image));
 }
 
+/// Only whole animation frames can be replaced for now, which is what minecraft's animated
+/// sprites need.
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+pub unsafe fn glTexSubImage2D(
+    mut env: JNIEnv<'_>,
+    _: JClass<'_>,
+    target: jint,
+    mip_level: jint,
+    xoffset: jint,
+    yoffset: jint,
+    width: jint,
+    height: jint,
+    cpu_format: jint,
+    data_type: jint,
+    data: JByteBuffer,
+) {
+    if !is_2d_target(target) {
+        tracing::warn!(
+            what = "glTexSubImage2D() was called with target other than GL_TEXTURE_2D or GL_TEXTURE_RECTANGLE: this is a no-op!",
+            target
+        );
+        return;
+    }
+
+    if mip_level != 0 {
+        // mips are generated from the base level when the update is recorded
+        tracing::warn!(
+            what = "glTexSubImage2D() was called for a mip level other than 0: this is a no-op!",
+            mip_level
+        );
+        return;
+    }
+
+    let Some(bound_texture) = with_render_sandbox(|s| s.get_bound_texture()) else {
+        tracing::warn!(what = "tried to call glTexSubImage2D with no bound texture");
+        return;
+    };
+
+    let Some(pixels) = direct_buffer_remaining(&mut env, &data) else {
+        jni_bail!(env, "glTexSubImage2D() was called without any data");
+    };
+
+    let mut image = throw!(
+        env,
+        decode_gl_pixels(
+            cpu_format as u32,
+            data_type as u32,
+            width as u32,
+            height as u32,
+            pixels
+        )
+        .map_err(McvkError::texture)
+    );
+
+    write_field_into!(inst; textures);
+
+    textures.pixel_transfer.apply(&mut image);
+
+    // the update is recorded along with the next frame
+    throw!(
+        env,
+        textures.enqueue_sub_image(bound_texture, [xoffset as u32, yoffset as u32], image)
+    );
+}
+
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+pub unsafe fn glGetTexImage(
+    mut env: JNIEnv<'_>,
+    _: JClass<'_>,
+    target: jint,
+    mip_level: jint,
+    cpu_format: jint,
+    data_type: jint,
+    data: JByteBuffer,
+) {
+    if !is_2d_target(target) {
+        tracing::warn!(
+            what = "glGetTexImage() was called with target other than GL_TEXTURE_2D or GL_TEXTURE_RECTANGLE: this is a no-op!",
+            target
+        );
+        return;
+    }
+
+    let Some(bound_texture) = with_render_sandbox(|s| s.get_bound_texture()) else {
+        tracing::warn!(what = "tried to call glGetTexImage with no bound texture");
+        return;
+    };
+
+    let image = {
+        write_field_into!(inst; textures);
+
+        let mut image = throw!(
+            env,
+            textures
+                .read_texture(bound_texture, mip_level as u32)
+                .map_err(McvkError::texture)
+        );
+
+        textures.pixel_transfer.apply(&mut image);
+
+        image
+    };
+
+    let pixels = throw!(
+        env,
+        encode_gl_pixels(cpu_format as u32, data_type as u32, &image).map_err(McvkError::texture)
+    );
+
+    let Some(dest) = direct_buffer_remaining_mut(&mut env, &data) else {
+        jni_bail!(env, "glGetTexImage() was called without a buffer");
+    };
+
+    if dest.len() < pixels.len() {
+        crate::jni::throw_error(
+            &mut env,
+            McvkError::texture(TextureLoadError::NotEnoughPixelData(
+                pixels.len(),
+                dest.len(),
+            )),
+        );
+        return;
+    }
+
+    dest[..pixels.len()].copy_from_slice(&pixels);
+}
+
+/// Both GL_FRONT and GL_BACK read the most recently rendered frame, so glReadBuffer is a no-op
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+pub unsafe fn glReadPixels(
+    mut env: JNIEnv<'_>,
+    _: JClass<'_>,
+    x: jint,
+    y: jint,
+    width: jint,
+    height: jint,
+    cpu_format: jint,
+    data_type: jint,
+    data: JByteBuffer,
+) {
+    let mut image = {
+        write_instance_into!(inst);
+
+        throw!(
+            env,
+            inst.read_pixels(
+                x.max(0) as u32,
+                y.max(0) as u32,
+                width.max(0) as u32,
+                height.max(0) as u32
+            )
+        )
+    };
+
+    {
+        read_field_into!(inst; textures);
+
+        textures.pixel_transfer.apply(&mut image);
+    }
+
+    let pixels = throw!(
+        env,
+        encode_gl_pixels(cpu_format as u32, data_type as u32, &image).map_err(McvkError::texture)
+    );
+
+    let Some(dest) = direct_buffer_remaining_mut(&mut env, &data) else {
+        jni_bail!(env, "glReadPixels() was called without a buffer");
+    };
+
+    if dest.len() < pixels.len() {
+        crate::jni::throw_error(
+            &mut env,
+            McvkError::texture(TextureLoadError::NotEnoughPixelData(
+                pixels.len(),
+                dest.len(),
+            )),
+        );
+        return;
+    }
+
+    dest[..pixels.len()].copy_from_slice(&pixels);
+}
+
+/// The glLoadName of the draw at a pixel of the most recently rendered frame, or 0 if nothing
+/// named was drawn there. Stands in for GL_SELECT picking.
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+pub unsafe fn pick(mut env: JNIEnv<'_>, _: JClass<'_>, x: jint, y: jint) -> jint {
+    write_instance_into!(inst);
+
+    let name = throw!(env, inst.pick(x.max(0) as u32, y.max(0) as u32));
+
+    name as jint
+}
+
+/// Replaces any rectangle of the bound texture, unlike glTexSubImage2D which only replaces whole
+/// animation frames
 #[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
-#[allow(unused)]
 pub unsafe fn glTextureSubImage2D(
     mut env: JNIEnv<'_>,
     _: JClass<'_>,
@@ -78,20 +495,95 @@ pub unsafe fn glTextureSubImage2D(
     yoffset: jint,
     width: jint,
     height: jint,
-    border: jint,
     cpu_format: jint,
+    data_type: jint,
     data: JByteBuffer,
 ) {
-    if target as u32 != GL_TEXTURE_2D {
+    if !is_2d_target(target) {
         tracing::warn!(
-            what =
-                "glTexImage2D() was called with target other than GL_TEXTURE_2D: this is a no-op!",
+            what = "glTextureSubImage2D() was called with target other than GL_TEXTURE_2D or GL_TEXTURE_RECTANGLE: this is a no-op!",
             target
         );
         return;
     }
 
-    jni_todo!(env);
+    if mip_level != 0 {
+        // mips are generated from the base level when the update is recorded
+        tracing::warn!(
+            what =
+                "glTextureSubImage2D() was called for a mip level other than 0: this is a no-op!",
+            mip_level
+        );
+        return;
+    }
+
+    if xoffset < 0 || yoffset < 0 || width < 0 || height < 0 {
+        jni_bail!(
+            env,
+            format!("glTextureSubImage2D() was called with a negative offset or size ({xoffset}, {yoffset}, {width}x{height})")
+        );
+    }
+
+    if width == 0 || height == 0 {
+        return;
+    }
+
+    let Some(bound_texture) = with_render_sandbox(|s| s.get_bound_texture()) else {
+        tracing::warn!(what = "tried to call glTextureSubImage2D with no bound texture");
+        return;
+    };
+
+    let Some(pixels) = direct_buffer_remaining(&mut env, &data) else {
+        jni_bail!(env, "glTextureSubImage2D() was called without any data");
+    };
+
+    let mut image = throw!(
+        env,
+        decode_gl_pixels(
+            cpu_format as u32,
+            data_type as u32,
+            width as u32,
+            height as u32,
+            pixels
+        )
+        .map_err(McvkError::texture)
+    );
+
+    write_field_into!(inst; textures);
+
+    textures.pixel_transfer.apply(&mut image);
+
+    // the update is recorded along with the next frame
+    throw!(
+        env,
+        textures.enqueue_subregion(bound_texture, [xoffset as u32, yoffset as u32], image)
+    );
+}
+
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+pub unsafe fn glPixelTransferf(_: JNIEnv<'_>, _: JClass<'_>, pname: jint, param: jfloat) {
+    write_field_into!(inst; textures);
+
+    if !textures.pixel_transfer.set(pname as u32, param) {
+        tracing::warn!(
+            what = "glPixelTransferf was called with an unsupported parameter and the call has been ignored!",
+            pname,
+            param
+        );
+    }
+}
+
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+pub unsafe fn glPixelTransferi(_: JNIEnv<'_>, _: JClass<'_>, pname: jint, param: jint) {
+    write_field_into!(inst; textures);
+
+    if !textures.pixel_transfer.set(pname as u32, param as f32) {
+        tracing::warn!(
+            what = "glPixelTransferi was called with an unsupported parameter and the call has been ignored!",
+            pname,
+            param
+        );
+    }
 }
 
 #[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
@@ -196,6 +688,48 @@ pub unsafe fn glGetTexParameteri(
     }
 }
 
+/// Reports GL_TEXTURE_WIDTH, GL_TEXTURE_HEIGHT and GL_TEXTURE_INTERNAL_FORMAT of the bound texture
+#[jni_export("com.recursive_pineapple.mcvk.rendering.RenderSandbox")]
+pub unsafe fn glGetTexLevelParameteri(
+    _: JNIEnv<'_>,
+    _: JClass<'_>,
+    target: jint,
+    level: jint,
+    pname: jint,
+) -> jint {
+    if !is_2d_target(target) || level < 0 {
+        tracing::warn!(
+            what = "glGetTexLevelParameteri() was called with an invalid target or level: this is a no-op!",
+            target,
+            level
+        );
+        return 0;
+    }
+
+    let Some(bound_texture) = with_render_sandbox(|s| s.get_bound_texture()) else {
+        tracing::warn!(
+            what = "tried to call glGetTexLevelParameteri with no bound texture",
+            pname
+        );
+        return 0;
+    };
+
+    let value = {
+        write_field_into!(inst; textures);
+
+        textures.texture_level_parameter(bound_texture, level as u32, pname as u32)
+    };
+
+    value.unwrap_or_else(|| {
+        tracing::warn!(
+            what = "glGetTexLevelParameteri() was called with an unsupported pname or a texture without an image",
+            texture = bound_texture,
+            pname
+        );
+        0
+    })
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 struct AnimationMetadataSection {
     #[serde(rename = "animationFrames")]
@@ -258,11 +792,53 @@ pub unsafe fn enqueueMissingSprite(
 
     throw!(
         env,
-        textures.enqueue_sprite(
-            env.get_string_unchecked(&name).unwrap().into(),
-            uv,
-            TextureImage::None,
-        )
+        textures
+            .enqueue_sprite(
+                env.get_string_unchecked(&name).unwrap().into(),
+                uv,
+                TextureImage::None,
+            )
+            .map_err(McvkError::texture)
+    );
+}
+
+/// Builds the atlas lookup once a resource reload has registered the atlases' sprites. It's
+/// stored on the texture manager, which every assembler reads it from, so the GL thread starts
+/// remapping atlas uvs right away whichever thread this is called from.
+#[jni_export("com.recursive_pineapple.mcvk.rendering.MCVKNative")]
+pub unsafe fn setAtlasTextures(mut env: JNIEnv<'_>, _: JClass<'_>, blocks: jint, items: jint) {
+    write_field_into!(inst; textures);
+
+    throw!(
+        env,
+        textures
+            .create_lookup(blocks, items)
+            .map_err(McvkError::texture)
+    );
+}
+
+/// Places a sprite that has already been enqueued into an atlas. The rect is in the same [0, 1]
+/// space as the uvs that get drawn with the atlas bound.
+#[jni_export("com.recursive_pineapple.mcvk.rendering.MCVKNative")]
+pub unsafe fn registerAtlasSprite(
+    mut env: JNIEnv<'_>,
+    _: JClass<'_>,
+    atlas: jint,
+    name: JString<'_>,
+    u_min: jfloat,
+    v_min: jfloat,
+    u_max: jfloat,
+    v_max: jfloat,
+) {
+    write_field_into!(inst; textures);
+
+    let name: String = env.get_string_unchecked(&name).unwrap().into();
+
+    throw!(
+        env,
+        textures
+            .register_atlas_sprite(atlas, &name, [u_min, v_min], [u_max, v_max])
+            .map_err(McvkError::texture)
     );
 }
 
@@ -316,6 +892,7 @@ pub unsafe fn enqueueFrameSprite(
                 inst.textures
                     .write()
                     .enqueue_sprite(name, uv, TextureImage::None)
+                    .map_err(McvkError::texture)
             );
             return;
         }
@@ -339,16 +916,19 @@ pub unsafe fn enqueueFrameSprite(
         if let Some(animation) = get_animation_metadata(&mut env, animation) {
             throw!(
                 env,
-                inst.textures.write().enqueue_sprite(
-                    name,
-                    uv,
-                    TextureImage::Frames {
-                        width: width as u32,
-                        height: width as u32,
-                        frames: images,
-                        animation,
-                    },
-                )
+                inst.textures
+                    .write()
+                    .enqueue_sprite(
+                        name,
+                        uv,
+                        TextureImage::Frames {
+                            width: width as u32,
+                            height: width as u32,
+                            frames: images,
+                            animation,
+                        },
+                    )
+                    .map_err(McvkError::texture)
             );
         } else {
             tracing::error!(
@@ -360,18 +940,22 @@ pub unsafe fn enqueueFrameSprite(
                 inst.textures
                     .write()
                     .enqueue_sprite(name, uv, TextureImage::None)
+                    .map_err(McvkError::texture)
             );
         };
     } else {
         throw!(
             env,
-            inst.textures.write().enqueue_sprite(
-                name,
-                uv,
-                TextureImage::Static {
-                    image: images.remove(0),
-                },
-            )
+            inst.textures
+                .write()
+                .enqueue_sprite(
+                    name,
+                    uv,
+                    TextureImage::Static {
+                        image: images.remove(0),
+                    },
+                )
+                .map_err(McvkError::texture)
         );
     }
 }
@@ -396,14 +980,17 @@ pub unsafe fn enqueueRawSprite(
 
     throw!(
         env,
-        inst.textures.write().enqueue_sprite(
-            env.get_string_unchecked(&name).unwrap().into(),
-            uv,
-            TextureImage::Data {
-                data: image.to_owned(),
-                animation: get_animation_metadata(&mut env, animation),
-            },
-        )
+        inst.textures
+            .write()
+            .enqueue_sprite(
+                env.get_string_unchecked(&name).unwrap().into(),
+                uv,
+                TextureImage::Data {
+                    data: image.to_owned(),
+                    animation: get_animation_metadata(&mut env, animation),
+                },
+            )
+            .map_err(McvkError::texture)
     );
 }
 
@@ -414,9 +1001,56 @@ pub unsafe fn beginTextureReload(_: JNIEnv<'_>, _: JClass<'_>) {
     inst.textures.write().begin_texture_reload();
 }
 
+/// Uploads the reloaded textures. `listener` is an optional `ReloadProgressListener`, which is
+/// told how many textures have been uploaded between each chunk. It's called without holding any
+/// locks, so that it can render a loading screen.
 #[jni_export("com.recursive_pineapple.mcvk.rendering.MCVKNative")]
-pub unsafe fn finishTextureReload(mut env: JNIEnv<'_>, _: JClass<'_>) {
-    write_instance_into!(inst);
+pub unsafe fn finishTextureReload(mut env: JNIEnv<'_>, _: JClass<'_>, listener: JObject<'_>) {
+    let (total, chunk_size) = {
+        write_field_into!(inst; textures);
+
+        (textures.begin_reload_upload(), textures.reload_chunk_size())
+    };
+
+    let result = upload_in_chunks(
+        total,
+        chunk_size,
+        |count| {
+            write_field_into!(inst; textures);
+
+            textures.upload_reload_chunk(count)
+        },
+        |progress| {
+            if listener.is_null() {
+                return;
+            }
+
+            let result = env.call_method(
+                &listener,
+                "onProgress",
+                "(II)V",
+                &[
+                    JValue::Int(progress.completed as jint),
+                    JValue::Int(progress.total as jint),
+                ],
+            );
+
+            if let Err(e) = result {
+                tracing::warn!(what = "reload progress listener failed", error = %e);
+
+                // the reload carries on regardless, which needs the exception to be cleared
+                let _ = env.exception_clear();
+            }
+        },
+    );
+
+    if result.is_ok() {
+        write_field_into!(inst; textures);
+
+        textures.end_reload_upload();
+    }
+
+    let result = result.map_err(McvkError::texture);
 
-    throw!(env, inst.textures.write().finish_texture_reload());
+    throw!(env, result);
 }
@@ -0,0 +1,634 @@
+use vulkano::format::ClearValue;
+use vulkano::format::Format;
+use vulkano::image::ImageLayout;
+use vulkano::image::SampleCount;
+use vulkano::image::SampleCounts;
+use vulkano::render_pass::AttachmentLoadOp;
+use vulkano::render_pass::AttachmentStoreOp;
+use vulkano::swapchain::ColorSpace;
+
+use crate::vulkan::instance::continuation_create_info;
+use crate::vulkan::instance::first_use_create_info;
+use crate::vulkan::instance::render_pass_create_info;
+use crate::vulkan::instance::split_create_info;
+
+use crate::vulkan::swapchain::clamp_sample_count;
+use crate::vulkan::swapchain::pick_depth_format;
+use crate::vulkan::swapchain::pick_surface_format;
+use crate::vulkan::swapchain::select_present_mode;
+use crate::vulkan::swapchain::swapchain_image_count;
+use crate::vulkan::swapchain::AttachmentFormats;
+use crate::vulkan::swapchain::ColorLoadOp;
+use crate::vulkan::swapchain::FrameAttachment;
+use crate::vulkan::swapchain::FrameBoundary;
+use crate::vulkan::swapchain::RenderMode;
+use crate::vulkan::swapchain::SettingsChanges;
+use crate::vulkan::swapchain::SettingsDelta;
+use crate::vulkan::swapchain::VsyncMode;
+use crate::vulkan::swapchain::WindowSettings;
+use crate::vulkan::swapchain::OBJECT_ID_LOCATION;
+
+const FORMATS: AttachmentFormats = AttachmentFormats {
+    swapchain: Format::B8G8R8A8_UNORM,
+    depth: Format::D16_UNORM,
+};
+
+fn default_settings() -> WindowSettings {
+    WindowSettings {
+        vsync: VsyncMode::On,
+        force_fifo: false,
+        max_fps: None,
+        msaa: SampleCount::Sample1,
+        color_load_op: ColorLoadOp::Clear,
+        render_mode: RenderMode::Forward,
+        frame_boundary: FrameBoundary::ColourClear,
+        object_picking: false,
+        command_queue_capacity: None,
+    }
+}
+
+#[test]
+fn msaa_rebuilds_render_pass() {
+    let mut settings = default_settings();
+
+    let changes = settings.apply(&SettingsDelta {
+        msaa: Some(SampleCount::Sample4),
+        ..Default::default()
+    });
+
+    assert_eq!(settings.msaa, SampleCount::Sample4);
+    assert!(changes.rebuild_render_pass);
+    assert!(changes.invalidate_pipelines);
+    assert!(changes.recreate_swapchain);
+
+    // re-applying the same value is a no-op
+    let changes = settings.apply(&SettingsDelta {
+        msaa: Some(SampleCount::Sample4),
+        ..Default::default()
+    });
+
+    assert_eq!(changes, SettingsChanges::default());
+}
+
+#[test]
+fn unsupported_sample_count_falls_back() {
+    let supported = SampleCounts::SAMPLE_1 | SampleCounts::SAMPLE_2 | SampleCounts::SAMPLE_4;
+
+    assert_eq!(
+        clamp_sample_count(SampleCount::Sample16, supported),
+        SampleCount::Sample4
+    );
+    assert_eq!(
+        clamp_sample_count(SampleCount::Sample2, supported),
+        SampleCount::Sample2
+    );
+
+    // gaps in the supported counts round down
+    let sparse = SampleCounts::SAMPLE_1 | SampleCounts::SAMPLE_8;
+    assert_eq!(
+        clamp_sample_count(SampleCount::Sample4, sparse),
+        SampleCount::Sample1
+    );
+    assert_eq!(
+        clamp_sample_count(SampleCount::Sample64, SampleCounts::empty()),
+        SampleCount::Sample1
+    );
+}
+
+#[test]
+fn msaa_resolves_into_swapchain_image() {
+    let settings = WindowSettings {
+        msaa: SampleCount::Sample4,
+        ..default_settings()
+    };
+
+    let info = render_pass_create_info(&settings, FORMATS);
+
+    assert_eq!(
+        settings.attachments(),
+        [
+            FrameAttachment::Swapchain,
+            FrameAttachment::Normals,
+            FrameAttachment::Depth,
+            FrameAttachment::MultisampledColor,
+        ]
+    );
+
+    let samples = info
+        .attachments
+        .iter()
+        .map(|a| a.samples)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        samples,
+        [
+            SampleCount::Sample1,
+            SampleCount::Sample4,
+            SampleCount::Sample4,
+            SampleCount::Sample4,
+        ]
+    );
+
+    // draws go into the multisampled attachment, which is resolved into the swapchain image
+    let subpass = &info.subpasses[0];
+    assert_eq!(subpass.color_attachments[0].as_ref().unwrap().attachment, 3);
+    assert_eq!(
+        subpass.color_resolve_attachments[0]
+            .as_ref()
+            .unwrap()
+            .attachment,
+        0
+    );
+    assert_eq!(info.attachments[0].load_op, AttachmentLoadOp::DontCare);
+    assert!(settings.clear_values(FORMATS)[0].is_none());
+
+    // the lighting subpass can't read multisampled input attachments
+    let deferred = WindowSettings {
+        render_mode: RenderMode::Deferred,
+        ..settings
+    };
+
+    assert_eq!(deferred.samples(), SampleCount::Sample1);
+    assert!(!deferred
+        .attachments()
+        .contains(&FrameAttachment::MultisampledColor));
+}
+
+#[test]
+fn vsync_and_fps_changes() {
+    let mut settings = default_settings();
+
+    let changes = settings.apply(&SettingsDelta {
+        vsync: Some(VsyncMode::Off),
+        max_fps: Some(Some(60)),
+        ..Default::default()
+    });
+
+    assert_eq!(settings.vsync, VsyncMode::Off);
+    assert_eq!(settings.max_fps, Some(60));
+    assert_eq!(
+        changes,
+        SettingsChanges {
+            change_present_mode: true,
+            ..Default::default()
+        }
+    );
+}
+
+#[test]
+fn color_attachment_load_op() {
+    let mut settings = default_settings();
+
+    // clearing must discard the old contents instead of claiming they were host-written
+    assert_eq!(settings.color_load_op, ColorLoadOp::Clear);
+    assert_eq!(ColorLoadOp::Clear.initial_layout(), ImageLayout::Undefined);
+
+    // loading from an undefined layout would give garbage
+    assert_ne!(
+        ColorLoadOp::Preserve.initial_layout(),
+        ImageLayout::Undefined
+    );
+    assert_ne!(
+        ColorLoadOp::Preserve.initial_layout(),
+        ImageLayout::Preinitialized
+    );
+
+    let changes = settings.apply(&SettingsDelta {
+        color_load_op: Some(ColorLoadOp::Preserve),
+        ..Default::default()
+    });
+
+    assert_eq!(settings.color_load_op, ColorLoadOp::Preserve);
+    assert!(changes.rebuild_render_pass);
+}
+
+#[test]
+fn preserved_frames_are_cleared_the_first_time_a_framebuffer_is_used() {
+    for msaa in [SampleCount::Sample1, SampleCount::Sample4] {
+        let settings = WindowSettings {
+            msaa,
+            color_load_op: ColorLoadOp::Preserve,
+            ..default_settings()
+        };
+
+        // with msaa it's the multisampled attachment which is loaded, not the swapchain image
+        let main = render_pass_create_info(&settings, FORMATS);
+        let loaded = main
+            .attachments
+            .iter()
+            .filter(|a| a.load_op == AttachmentLoadOp::Load)
+            .count();
+        assert_eq!(loaded, 1);
+
+        // nothing has been drawn into the images yet, so they're still undefined
+        for info in [
+            first_use_create_info(main.clone()),
+            first_use_create_info(split_create_info(main.clone())),
+        ] {
+            for attachment in &info.attachments {
+                assert_ne!(attachment.load_op, AttachmentLoadOp::Load);
+                assert_eq!(attachment.initial_layout, ImageLayout::Undefined);
+            }
+        }
+
+        let clear_values = settings.first_use_clear_values(FORMATS);
+        for (attachment, first_use) in main.attachments.iter().zip(&clear_values) {
+            assert_eq!(
+                first_use.is_some(),
+                attachment.load_op != AttachmentLoadOp::DontCare
+            );
+        }
+    }
+}
+
+#[test]
+fn deferred_mode_has_gbuffer_attachments() {
+    let mut settings = default_settings();
+
+    let forward = render_pass_create_info(&settings, FORMATS);
+
+    assert_eq!(forward.subpasses.len(), 1);
+
+    let changes = settings.apply(&SettingsDelta {
+        render_mode: Some(RenderMode::Deferred),
+        ..Default::default()
+    });
+
+    assert!(changes.rebuild_render_pass);
+    assert!(changes.invalidate_pipelines);
+
+    let deferred = render_pass_create_info(&settings, FORMATS);
+
+    let formats = deferred
+        .attachments
+        .iter()
+        .map(|a| a.format)
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        formats,
+        [
+            Format::B8G8R8A8_UNORM,
+            Format::A2B10G10R10_UNORM_PACK32,
+            Format::R16G16B16A16_SFLOAT,
+            Format::D16_UNORM,
+        ]
+    );
+
+    assert_eq!(deferred.subpasses.len(), 2);
+
+    // the geometry subpass writes albedo & normals
+    let geometry = &deferred.subpasses[0];
+    let targets = geometry
+        .color_attachments
+        .iter()
+        .map(|a| a.as_ref().unwrap().attachment)
+        .collect::<Vec<_>>();
+    assert_eq!(targets, [1, 2]);
+    assert!(geometry.depth_stencil_attachment.is_some());
+
+    // the lighting subpass reads them back and writes the swapchain image
+    let lighting = &deferred.subpasses[1];
+    let inputs = lighting
+        .input_attachments
+        .iter()
+        .map(|a| a.as_ref().unwrap().attachment)
+        .collect::<Vec<_>>();
+    assert_eq!(inputs, [1, 2]);
+    assert_eq!(
+        lighting.color_attachments[0].as_ref().unwrap().attachment,
+        0
+    );
+}
+
+#[test]
+fn object_ids_are_written_while_picking() {
+    let mut settings = default_settings();
+
+    assert!(!settings.writes_object_ids());
+    assert!(!settings.attachments().contains(&FrameAttachment::ObjectIds));
+
+    let targets = render_pass_create_info(&settings, FORMATS).subpasses[0]
+        .color_attachments
+        .len();
+    assert_eq!(targets, 1);
+
+    let changes = settings.apply(&SettingsDelta {
+        object_picking: Some(true),
+        ..Default::default()
+    });
+
+    assert!(changes.rebuild_render_pass);
+    assert!(changes.invalidate_pipelines);
+    assert!(!changes.recreate_swapchain);
+
+    assert_eq!(
+        settings.attachments(),
+        [
+            FrameAttachment::Swapchain,
+            FrameAttachment::Normals,
+            FrameAttachment::Depth,
+            FrameAttachment::ObjectIds,
+        ]
+    );
+
+    let info = render_pass_create_info(&settings, FORMATS);
+
+    // the ids go to the same location as in the deferred geometry subpass
+    let targets = info.subpasses[0]
+        .color_attachments
+        .iter()
+        .map(|a| a.as_ref().map(|a| a.attachment))
+        .collect::<Vec<_>>();
+    assert_eq!(targets, [Some(0), None, Some(3)]);
+    assert_eq!(targets.len(), OBJECT_ID_LOCATION + 1);
+
+    assert_eq!(info.attachments[3].format, Format::R32_UINT);
+    assert_eq!(info.attachments[3].store_op, AttachmentStoreOp::Store);
+    assert!(matches!(
+        settings.clear_values(FORMATS)[3],
+        Some(ClearValue::Uint([0, 0, 0, 0]))
+    ));
+
+    // a resolve can't pick one id out of the samples
+    let multisampled = WindowSettings {
+        msaa: SampleCount::Sample4,
+        ..settings
+    };
+
+    assert!(!multisampled
+        .attachments()
+        .contains(&FrameAttachment::ObjectIds));
+}
+
+#[test]
+fn image_count_follows_vsync_mode() {
+    let count = |vsync: VsyncMode| swapchain_image_count(vsync.present_mode(), 2, Some(8));
+
+    assert_eq!(count(VsyncMode::Off), 2);
+    assert_eq!(count(VsyncMode::On), 3);
+    assert_eq!(count(VsyncMode::Triple), 3);
+
+    // the surface's limits always win
+    let triple = VsyncMode::Triple.present_mode();
+    assert_eq!(swapchain_image_count(triple, 4, Some(8)), 4);
+    assert_eq!(swapchain_image_count(triple, 1, Some(2)), 2);
+    assert_eq!(swapchain_image_count(triple, 1, None), 3);
+}
+
+#[test]
+fn present_mode_falls_back_to_fifo() {
+    use vulkano::swapchain::PresentMode::*;
+
+    let all = [Immediate, Mailbox, Fifo, FifoRelaxed];
+
+    assert_eq!(select_present_mode(VsyncMode::Off, &all), Immediate);
+    assert_eq!(select_present_mode(VsyncMode::On, &all), FifoRelaxed);
+    assert_eq!(select_present_mode(VsyncMode::Triple, &all), Mailbox);
+
+    assert_eq!(
+        select_present_mode(VsyncMode::Off, &[Fifo, FifoRelaxed]),
+        FifoRelaxed
+    );
+    assert_eq!(select_present_mode(VsyncMode::Off, &[Fifo]), Fifo);
+    assert_eq!(select_present_mode(VsyncMode::On, &[Fifo, Mailbox]), Fifo);
+    assert_eq!(
+        select_present_mode(VsyncMode::Triple, &[Fifo, FifoRelaxed]),
+        Fifo
+    );
+
+    // fifo has to be supported, but don't trust the driver to report it
+    assert_eq!(select_present_mode(VsyncMode::Triple, &[]), Fifo);
+}
+
+#[test]
+fn forced_fifo_overrides_every_vsync_mode() {
+    use vulkano::swapchain::PresentMode::*;
+
+    let all = [Immediate, Mailbox, Fifo, FifoRelaxed];
+
+    let mut settings = default_settings();
+
+    let changes = settings.apply(&SettingsDelta {
+        force_fifo: Some(true),
+        ..Default::default()
+    });
+
+    // only the present mode has to change
+    assert_eq!(
+        changes,
+        SettingsChanges {
+            change_present_mode: true,
+            ..Default::default()
+        }
+    );
+
+    for vsync in [VsyncMode::Off, VsyncMode::On, VsyncMode::Triple] {
+        settings.vsync = vsync;
+        assert_eq!(settings.present_mode(&all), Fifo);
+    }
+
+    settings.force_fifo = false;
+    assert_eq!(settings.present_mode(&all), Mailbox);
+}
+
+#[test]
+fn command_queue_capacity_replaces_the_queue() {
+    let mut settings = default_settings();
+
+    let changes = settings.apply(&SettingsDelta {
+        command_queue_capacity: Some(Some(4096)),
+        ..Default::default()
+    });
+
+    // the GL thread gets a new queue, but nothing about the frame has to be rebuilt
+    assert_eq!(
+        changes,
+        SettingsChanges {
+            replace_command_queue: true,
+            ..Default::default()
+        }
+    );
+    assert_eq!(settings.command_queue_capacity, Some(4096));
+
+    // the same capacity again keeps the queue
+    let changes = settings.apply(&SettingsDelta {
+        command_queue_capacity: Some(Some(4096)),
+        ..Default::default()
+    });
+
+    assert!(!changes.replace_command_queue);
+}
+
+#[test]
+fn attachment_formats_match_render_pass() {
+    let formats = AttachmentFormats {
+        swapchain: Format::B8G8R8A8_SRGB,
+        ..FORMATS
+    };
+
+    for render_mode in [RenderMode::Forward, RenderMode::Deferred] {
+        let settings = WindowSettings {
+            render_mode,
+            ..default_settings()
+        };
+
+        let info = render_pass_create_info(&settings, formats);
+
+        for (attachment, description) in render_mode.attachments().iter().zip(&info.attachments) {
+            assert_eq!(
+                render_mode.attachment_format(*attachment, formats),
+                Some(description.format)
+            );
+        }
+    }
+
+    assert_eq!(
+        RenderMode::Forward.attachment_format(FrameAttachment::Swapchain, formats),
+        Some(formats.swapchain)
+    );
+    assert_eq!(
+        RenderMode::Forward.attachment_format(FrameAttachment::Albedo, formats),
+        None
+    );
+}
+
+#[test]
+fn explicit_present_mode_clears_without_presenting() {
+    let mut settings = default_settings();
+
+    assert!(settings.frame_boundary.clear_presents());
+
+    let changes = settings.apply(&SettingsDelta {
+        frame_boundary: Some(FrameBoundary::Explicit),
+        ..Default::default()
+    });
+
+    // the next glClear just sees the new mode
+    assert_eq!(changes, SettingsChanges::default());
+    assert!(!settings.frame_boundary.clear_presents());
+}
+
+#[test]
+fn flushed_frames_continue_where_they_left_off() {
+    for render_mode in [RenderMode::Forward, RenderMode::Deferred] {
+        let settings = WindowSettings {
+            render_mode,
+            ..default_settings()
+        };
+
+        let main = render_pass_create_info(&settings, FORMATS);
+        let split = split_create_info(main.clone());
+        let continuation = continuation_create_info(main.clone());
+
+        // a frame which isn't split only keeps what's presented or read back
+        let stored = main
+            .attachments
+            .iter()
+            .zip(settings.attachments())
+            .filter(|(a, _)| a.store_op == AttachmentStoreOp::Store)
+            .map(|(_, attachment)| attachment)
+            .collect::<Vec<_>>();
+        assert_eq!(stored, [FrameAttachment::Swapchain]);
+
+        // the split render pass has to store what the continuation loads
+        for (first, second) in split.attachments.iter().zip(&continuation.attachments) {
+            assert_eq!(first.store_op, AttachmentStoreOp::Store);
+            assert_eq!(second.load_op, AttachmentLoadOp::Load);
+            assert_eq!(second.initial_layout, first.final_layout);
+            assert_eq!(second.format, first.format);
+        }
+
+        assert_eq!(continuation.subpasses.len(), main.subpasses.len());
+    }
+
+    // the resolve overwrites the swapchain image, so it's never loaded
+    let multisampled = WindowSettings {
+        msaa: SampleCount::Sample4,
+        ..default_settings()
+    };
+
+    let continuation = continuation_create_info(render_pass_create_info(&multisampled, FORMATS));
+    let swapchain = multisampled
+        .attachments()
+        .iter()
+        .position(|a| *a == FrameAttachment::Swapchain)
+        .unwrap();
+    assert_eq!(
+        continuation.attachments[swapchain].load_op,
+        AttachmentLoadOp::DontCare
+    );
+}
+
+#[test]
+fn depth_format_prefers_precision() {
+    let pick = |supported: &[Format]| pick_depth_format(|format| supported.contains(&format));
+
+    assert_eq!(
+        pick(&[
+            Format::D16_UNORM,
+            Format::D24_UNORM_S8_UINT,
+            Format::D32_SFLOAT
+        ]),
+        Format::D32_SFLOAT
+    );
+    assert_eq!(
+        pick(&[Format::D16_UNORM, Format::D24_UNORM_S8_UINT]),
+        Format::D24_UNORM_S8_UINT
+    );
+    assert_eq!(pick(&[Format::D16_UNORM]), Format::D16_UNORM);
+    assert_eq!(pick(&[]), Format::D16_UNORM);
+
+    // the render pass and the clear values follow the picked format
+    let formats = AttachmentFormats {
+        depth: Format::D24_UNORM_S8_UINT,
+        ..FORMATS
+    };
+
+    let settings = default_settings();
+    let info = render_pass_create_info(&settings, formats);
+    let depth = settings
+        .attachments()
+        .iter()
+        .position(|a| *a == FrameAttachment::Depth)
+        .unwrap();
+
+    assert_eq!(info.attachments[depth].format, Format::D24_UNORM_S8_UINT);
+    assert!(matches!(
+        settings.clear_values(formats)[depth],
+        Some(ClearValue::DepthStencil(_))
+    ));
+}
+
+#[test]
+fn surface_format_prefers_srgb() {
+    use ColorSpace::*;
+
+    // shaders already output sRGB colour, so an _SRGB format would encode it twice
+    let available = [
+        (Format::B8G8R8A8_SRGB, SrgbNonLinear),
+        (Format::R8G8B8A8_UNORM, SrgbNonLinear),
+        (Format::B8G8R8A8_UNORM, SrgbNonLinear),
+    ];
+
+    assert_eq!(
+        pick_surface_format(&available),
+        Some((Format::B8G8R8A8_UNORM, SrgbNonLinear))
+    );
+    assert_eq!(
+        pick_surface_format(&available[..2]),
+        Some((Format::R8G8B8A8_UNORM, SrgbNonLinear))
+    );
+
+    // the format has to be shown in the sRGB colour space to count
+    let hdr = [
+        (Format::A2B10G10R10_UNORM_PACK32, Hdr10St2084),
+        (Format::B8G8R8A8_UNORM, DisplayP3NonLinear),
+    ];
+
+    assert_eq!(
+        pick_surface_format(&hdr),
+        Some((Format::A2B10G10R10_UNORM_PACK32, Hdr10St2084))
+    );
+    assert_eq!(pick_surface_format(&[]), None);
+}
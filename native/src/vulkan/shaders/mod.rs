@@ -1 +1,2 @@
+pub mod programs;
 pub mod uniforms;
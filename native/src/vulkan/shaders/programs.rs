@@ -0,0 +1,975 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Arc;
+
+use vulkano::shader::reflect;
+use vulkano::shader::spirv::Decoration;
+use vulkano::shader::spirv::ExecutionModel;
+use vulkano::shader::spirv::Id;
+use vulkano::shader::spirv::Instruction;
+use vulkano::shader::spirv::Spirv;
+use vulkano::shader::spirv::StorageClass;
+
+use crate::vulkan::dynamic_shader::compile_spirv;
+use crate::vulkan::utils::TypedVec;
+
+pub type GlShaderId = u32;
+pub type GlProgramId = u32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum UserShaderStage {
+    Vertex,
+    Fragment,
+}
+
+impl UserShaderStage {
+    /// Only vertex and fragment shaders are supported for now
+    pub fn from_gl(shader_type: u32) -> Option<Self> {
+        match shader_type {
+            gl_constants::GL_VERTEX_SHADER => Some(Self::Vertex),
+            gl_constants::GL_FRAGMENT_SHADER => Some(Self::Fragment),
+            _ => None,
+        }
+    }
+
+    pub fn to_gl(&self) -> u32 {
+        match self {
+            Self::Vertex => gl_constants::GL_VERTEX_SHADER,
+            Self::Fragment => gl_constants::GL_FRAGMENT_SHADER,
+        }
+    }
+
+    fn glslang(&self) -> glslang::ShaderStage {
+        match self {
+            Self::Vertex => glslang::ShaderStage::Vertex,
+            Self::Fragment => glslang::ShaderStage::Fragment,
+        }
+    }
+
+    /// The binding of the stage's [`DEFAULT_UNIFORM_BLOCK`]. Stages are compiled separately, so
+    /// each declares its own.
+    pub fn default_uniform_binding(&self) -> u32 {
+        match self {
+            Self::Vertex => 0,
+            Self::Fragment => 1,
+        }
+    }
+}
+
+/// Loose uniforms are gathered into a block named this, since vulkan's glsl only has blocks
+pub const DEFAULT_UNIFORM_BLOCK: &str = "DefaultUniforms";
+/// The set of each stage's [`DEFAULT_UNIFORM_BLOCK`], which is the last set every device has.
+/// User blocks and samplers must not be bound to its bindings.
+pub const DEFAULT_UNIFORM_SET: u32 = 3;
+
+/// Whether a glsl type can be declared as a loose uniform and set with glUniform*
+fn is_default_uniform_type(ty: &str) -> bool {
+    if matches!(ty, "float" | "double" | "int" | "uint" | "bool") {
+        return true;
+    }
+
+    let is_dimension = |n: &str| matches!(n, "2" | "3" | "4");
+
+    if let Some(n) = ty
+        .strip_prefix(['i', 'u', 'b', 'd'])
+        .unwrap_or(ty)
+        .strip_prefix("vec")
+    {
+        return is_dimension(n);
+    }
+
+    if let Some(dims) = ty.strip_prefix('d').unwrap_or(ty).strip_prefix("mat") {
+        return dims.split('x').count() <= 2 && dims.split('x').all(is_dimension);
+    }
+
+    false
+}
+
+/// Moves the loose (non-opaque) uniforms out of a shader's source into a std140
+/// [`DEFAULT_UNIFORM_BLOCK`], which is how GL's default uniform block is written in vulkan's
+/// glsl. The block takes the first declaration's line and the others are blanked, so the info
+/// log's line numbers still match the source.
+pub fn wrap_default_uniforms(source: &str, stage: UserShaderStage) -> String {
+    let mut lines = source.lines().map(str::to_owned).collect::<Vec<_>>();
+    let mut members = Vec::new();
+    let mut first = None;
+
+    for (i, line) in lines.iter_mut().enumerate() {
+        let code = line.split("//").next().unwrap_or_default().trim();
+
+        let Some(declaration) = code
+            .strip_prefix("uniform ")
+            .and_then(|rest| rest.strip_suffix(';'))
+        else {
+            continue;
+        };
+
+        if declaration.contains(['{', '(']) {
+            continue;
+        }
+
+        let ty = declaration
+            .split_whitespace()
+            .find(|word| !matches!(*word, "lowp" | "mediump" | "highp"));
+
+        if !ty.is_some_and(is_default_uniform_type) {
+            continue;
+        }
+
+        members.push(declaration.trim().to_owned());
+        first.get_or_insert(i);
+        line.clear();
+    }
+
+    let Some(first) = first else {
+        return source.to_owned();
+    };
+
+    lines[first] = format!(
+        "layout(std140, set = {DEFAULT_UNIFORM_SET}, binding = {}) uniform {DEFAULT_UNIFORM_BLOCK} {{ {}; }};",
+        stage.default_uniform_binding(),
+        members.join("; "),
+    );
+
+    lines.join("\n")
+}
+
+/// A shader object created with glCreateShader
+#[derive(Debug)]
+pub struct UserShader {
+    pub stage: UserShaderStage,
+    pub source: String,
+    /// Set once glCompileShader succeeds
+    pub spirv: Option<Arc<Vec<u32>>>,
+    pub info_log: String,
+}
+
+impl UserShader {
+    /// Compiles the current source, returning whether it succeeded
+    pub fn compile(&mut self) -> bool {
+        let source = wrap_default_uniforms(&self.source, self.stage);
+
+        match compile_spirv(self.stage.glslang(), source) {
+            Ok(words) => {
+                self.spirv = Some(Arc::new(words));
+                self.info_log.clear();
+                true
+            }
+            Err(error) => {
+                self.spirv = None;
+                self.info_log = error.to_string();
+                false
+            }
+        }
+    }
+}
+
+/// A named variable of a linked program's interface
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProgramVariable {
+    pub name: String,
+    pub location: u32,
+}
+
+/// A descriptor a linked program expects to be bound
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProgramDescriptor {
+    pub set: u32,
+    pub binding: u32,
+}
+
+/// The glsl type of a uniform. Vectors and matrices have up to 4 components.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UniformType {
+    Float(u32),
+    Int(u32),
+    UInt(u32),
+    /// Each column (or row, if `row_major`) starts `matrix_stride` bytes after the previous one
+    Mat {
+        columns: u32,
+        rows: u32,
+        matrix_stride: u32,
+        row_major: bool,
+    },
+    /// A combined image sampler, which glUniform1i points at a texture unit
+    Sampler,
+}
+
+impl UniformType {
+    pub fn size(&self) -> u32 {
+        match self {
+            Self::Float(n) | Self::Int(n) | Self::UInt(n) => n * 4,
+            Self::Mat {
+                columns,
+                rows,
+                matrix_stride,
+                row_major,
+            } => {
+                if *row_major {
+                    rows * matrix_stride
+                } else {
+                    columns * matrix_stride
+                }
+            }
+            Self::Sampler => 0,
+        }
+    }
+
+    /// How many values a glUniform* call has to provide
+    pub fn component_count(&self) -> u32 {
+        match self {
+            Self::Float(n) | Self::Int(n) | Self::UInt(n) => *n,
+            Self::Mat { columns, rows, .. } => columns * rows,
+            Self::Sampler => 1,
+        }
+    }
+
+    /// The byte offset of each component from the start of the uniform, in the column-major
+    /// order glUniform* provides them in
+    fn component_offsets(&self) -> Vec<u32> {
+        match *self {
+            Self::Mat {
+                columns,
+                rows,
+                matrix_stride,
+                row_major,
+            } => (0..columns)
+                .flat_map(|column| {
+                    (0..rows).map(move |row| {
+                        if row_major {
+                            row * matrix_stride + column * 4
+                        } else {
+                            column * matrix_stride + row * 4
+                        }
+                    })
+                })
+                .collect(),
+            ty => (0..ty.component_count()).map(|i| i * 4).collect(),
+        }
+    }
+}
+
+/// A member of one of a linked program's uniform blocks, or one of its samplers. Its GL location
+/// is its index in [`LinkedProgram::uniforms`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProgramUniform {
+    pub name: String,
+    /// The block's type name, which can prefix the member's name when looking it up. Empty for
+    /// samplers.
+    pub block_name: String,
+    pub set: u32,
+    pub binding: u32,
+    /// The offset into the block, 0 for samplers
+    pub offset: u32,
+    pub ty: UniformType,
+}
+
+/// A uniform block's backing buffer: its bindings and size. The size covers every member, even
+/// the ones glUniform* can't write to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UniformBlock {
+    pub set: u32,
+    pub binding: u32,
+    pub size: u32,
+}
+
+/// What glUniform* has set on a program. Both are keyed by (set, binding).
+#[derive(Debug, Clone, Default)]
+pub struct UniformValues {
+    /// The contents of each uniform block
+    pub buffers: HashMap<(u32, u32), Arc<Vec<u8>>>,
+    /// The texture unit each sampler reads
+    pub sampler_units: HashMap<(u32, u32), u32>,
+}
+
+/// The compiled stages of a successful glLinkProgram, along with their reflected interface.
+/// Compares by id and link generation, so relinking a program produces a new pipeline.
+#[derive(Debug)]
+pub struct LinkedProgram {
+    pub id: GlProgramId,
+    pub generation: u32,
+
+    pub vertex: Arc<Vec<u32>>,
+    pub fragment: Arc<Vec<u32>>,
+
+    /// The vertex shader's inputs. Generic attribute arrays are fed to their index, and client
+    /// arrays to the locations of their
+    /// [`VertexInputType`](crate::vulkan::dynamic_shader::VertexInputType)s.
+    pub attributes: Vec<ProgramVariable>,
+    pub descriptors: Vec<ProgramDescriptor>,
+    pub uniforms: Vec<ProgramUniform>,
+    pub uniform_blocks: Vec<UniformBlock>,
+    /// The size of the push constant block. If it can hold a mat4, its start receives the MVP.
+    pub push_constant_size: u32,
+}
+
+impl PartialEq for LinkedProgram {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id && self.generation == other.generation
+    }
+}
+
+impl Eq for LinkedProgram {}
+
+impl Hash for LinkedProgram {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.id.hash(state);
+        self.generation.hash(state);
+    }
+}
+
+impl LinkedProgram {
+    pub fn attribute_location(&self, name: &str) -> Option<u32> {
+        self.attributes
+            .iter()
+            .find(|a| a.name == name)
+            .map(|a| a.location)
+    }
+
+    /// Uniforms can be named either by their member name or as `Block.member`
+    pub fn uniform_location(&self, name: &str) -> Option<u32> {
+        self.uniforms
+            .iter()
+            .position(|u| {
+                u.name == name
+                    || name
+                        .strip_prefix(&u.block_name)
+                        .and_then(|rest| rest.strip_prefix('.'))
+                        == Some(&u.name)
+            })
+            .map(|i| i as u32)
+    }
+
+    /// Zero-initialized buffers for each uniform block and samplers reading unit 0, as
+    /// glLinkProgram leaves them
+    pub fn new_uniform_values(&self) -> UniformValues {
+        UniformValues {
+            buffers: self
+                .uniform_blocks
+                .iter()
+                .map(|block| {
+                    (
+                        (block.set, block.binding),
+                        Arc::new(vec![0; block.size as usize]),
+                    )
+                })
+                .collect(),
+            sampler_units: self
+                .uniforms
+                .iter()
+                .filter(|u| u.ty == UniformType::Sampler)
+                .map(|u| ((u.set, u.binding), 0))
+                .collect(),
+        }
+    }
+
+    /// Writes a glUniform* value into its block, or points a sampler at a texture unit. Uniforms
+    /// with the same name in both stages' [`DEFAULT_UNIFORM_BLOCK`]s are both written. Returns
+    /// false if the location doesn't exist or the value doesn't match the uniform's type.
+    pub fn write_uniform(
+        &self,
+        values: &mut UniformValues,
+        location: u32,
+        value: &TypedVec,
+    ) -> bool {
+        let Some(uniform) = self.uniforms.get(location as usize) else {
+            return false;
+        };
+
+        let words: Vec<u32> = match (uniform.ty, value) {
+            (UniformType::Float(_) | UniformType::Mat { .. }, TypedVec::F32s(v)) => {
+                v.iter().map(|f| f.to_bits()).collect()
+            }
+            (UniformType::Int(_), TypedVec::I32s(v)) => v.iter().map(|i| *i as u32).collect(),
+            (UniformType::UInt(_), TypedVec::U32s(v)) => v.to_vec(),
+            (UniformType::Sampler, TypedVec::I32s(v)) if v.iter().all(|unit| *unit >= 0) => {
+                v.iter().map(|i| *i as u32).collect()
+            }
+            _ => return false,
+        };
+
+        if words.len() != uniform.ty.component_count() as usize {
+            return false;
+        }
+
+        let targets = self.uniforms.iter().filter(|u| {
+            u.name == uniform.name && u.block_name == uniform.block_name && u.ty == uniform.ty
+        });
+
+        for target in targets {
+            let key = (target.set, target.binding);
+
+            if target.ty == UniformType::Sampler {
+                values.sampler_units.insert(key, words[0]);
+                continue;
+            }
+
+            let Some(buffer) = values.buffers.get_mut(&key) else {
+                return false;
+            };
+
+            let end = target.offset + target.ty.size();
+
+            if end as usize > buffer.len() {
+                return false;
+            }
+
+            let buffer = Arc::make_mut(buffer);
+
+            for (offset, word) in target.ty.component_offsets().into_iter().zip(&words) {
+                let start = (target.offset + offset) as usize;
+
+                buffer[start..start + 4].copy_from_slice(&word.to_ne_bytes());
+            }
+        }
+
+        true
+    }
+}
+
+/// A program object created with glCreateProgram
+#[derive(Debug, Default)]
+pub struct UserProgram {
+    pub shaders: Vec<GlShaderId>,
+    pub linked: Option<Arc<LinkedProgram>>,
+    pub info_log: String,
+    generation: u32,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum LinkError {
+    #[error("program has no compiled {0:?} shader attached")]
+    MissingStage(UserShaderStage),
+    #[error("the {0:?} shader's spir-v could not be reflected: {1}")]
+    Reflection(UserShaderStage, String),
+}
+
+/// Owns the shader and program objects of the programmable pipeline. User shaders must be written
+/// against vulkan's flavour of glsl, since they're compiled as-is.
+#[derive(Debug, Default)]
+pub struct ProgramRegistry {
+    shaders: HashMap<GlShaderId, UserShader>,
+    programs: HashMap<GlProgramId, UserProgram>,
+    next_id: u32,
+}
+
+impl ProgramRegistry {
+    fn next_id(&mut self) -> u32 {
+        // 0 is reserved for 'no object'
+        self.next_id += 1;
+        self.next_id
+    }
+
+    pub fn create_shader(&mut self, stage: UserShaderStage) -> GlShaderId {
+        let id = self.next_id();
+
+        self.shaders.insert(
+            id,
+            UserShader {
+                stage,
+                source: String::new(),
+                spirv: None,
+                info_log: String::new(),
+            },
+        );
+
+        id
+    }
+
+    pub fn delete_shader(&mut self, shader: GlShaderId) {
+        self.shaders.remove(&shader);
+    }
+
+    pub fn shader(&self, shader: GlShaderId) -> Option<&UserShader> {
+        self.shaders.get(&shader)
+    }
+
+    pub fn shader_mut(&mut self, shader: GlShaderId) -> Option<&mut UserShader> {
+        self.shaders.get_mut(&shader)
+    }
+
+    pub fn create_program(&mut self) -> GlProgramId {
+        let id = self.next_id();
+
+        self.programs.insert(id, UserProgram::default());
+
+        id
+    }
+
+    pub fn delete_program(&mut self, program: GlProgramId) {
+        self.programs.remove(&program);
+    }
+
+    pub fn program(&self, program: GlProgramId) -> Option<&UserProgram> {
+        self.programs.get(&program)
+    }
+
+    /// Returns false if either object doesn't exist
+    pub fn attach_shader(&mut self, program: GlProgramId, shader: GlShaderId) -> bool {
+        if !self.shaders.contains_key(&shader) {
+            return false;
+        }
+
+        let Some(program) = self.programs.get_mut(&program) else {
+            return false;
+        };
+
+        if !program.shaders.contains(&shader) {
+            program.shaders.push(shader);
+        }
+
+        true
+    }
+
+    /// Links a program from its attached shaders, returning None if it doesn't exist. On failure
+    /// the program's info log describes what went wrong.
+    pub fn link_program(&mut self, program: GlProgramId) -> Option<bool> {
+        let attached = self.programs.get(&program)?.shaders.clone();
+
+        let stage = |stage: UserShaderStage| {
+            attached
+                .iter()
+                .filter_map(|id| self.shaders.get(id))
+                .filter(|s| s.stage == stage)
+                .find_map(|s| s.spirv.clone())
+                .ok_or(LinkError::MissingStage(stage))
+        };
+
+        let user_program = self.programs.get_mut(&program).unwrap();
+        user_program.generation += 1;
+
+        let result = stage(UserShaderStage::Vertex).and_then(|vertex| {
+            link(
+                program,
+                user_program.generation,
+                vertex,
+                stage(UserShaderStage::Fragment)?,
+            )
+        });
+
+        match result {
+            Ok(linked) => {
+                user_program.linked = Some(Arc::new(linked));
+                user_program.info_log.clear();
+                Some(true)
+            }
+            Err(error) => {
+                user_program.linked = None;
+                user_program.info_log = error.to_string();
+                Some(false)
+            }
+        }
+    }
+}
+
+fn link(
+    id: GlProgramId,
+    generation: u32,
+    vertex: Arc<Vec<u32>>,
+    fragment: Arc<Vec<u32>>,
+) -> Result<LinkedProgram, LinkError> {
+    let mut attributes = Vec::new();
+    let mut descriptors = Vec::new();
+    let mut uniforms = Vec::<ProgramUniform>::new();
+    let mut uniform_blocks = Vec::<UniformBlock>::new();
+    let mut push_constant_size = 0;
+
+    for (stage, code) in [
+        (UserShaderStage::Vertex, &vertex),
+        (UserShaderStage::Fragment, &fragment),
+    ] {
+        let spirv = Spirv::new(code).map_err(|e| LinkError::Reflection(stage, e.to_string()))?;
+
+        let execution_model = match stage {
+            UserShaderStage::Vertex => ExecutionModel::Vertex,
+            UserShaderStage::Fragment => ExecutionModel::Fragment,
+        };
+
+        let (_, info) = reflect::entry_points(&spirv)
+            .find(|(_, info)| info.execution_model == execution_model)
+            .ok_or_else(|| LinkError::Reflection(stage, "no entry point".to_owned()))?;
+
+        if stage == UserShaderStage::Vertex {
+            attributes = info
+                .input_interface
+                .elements()
+                .iter()
+                .map(|input| ProgramVariable {
+                    name: input.name.as_deref().unwrap_or_default().to_owned(),
+                    location: input.location,
+                })
+                .collect();
+        }
+
+        for &(set, binding) in info.descriptor_binding_requirements.keys() {
+            let descriptor = ProgramDescriptor { set, binding };
+
+            if !descriptors.contains(&descriptor) {
+                descriptors.push(descriptor);
+            }
+        }
+
+        if let Some(range) = info.push_constant_requirements {
+            push_constant_size = push_constant_size.max(range.offset + range.size);
+        }
+
+        // both stages can declare the same block, but it only needs one buffer
+        for (block, members) in reflect_uniform_blocks(&spirv) {
+            if uniform_blocks
+                .iter()
+                .any(|b| b.set == block.set && b.binding == block.binding)
+            {
+                continue;
+            }
+
+            uniform_blocks.push(block);
+            uniforms.extend(members);
+        }
+
+        for sampler in reflect_samplers(&spirv) {
+            if !uniforms
+                .iter()
+                .any(|u| u.set == sampler.set && u.binding == sampler.binding)
+            {
+                uniforms.push(sampler);
+            }
+        }
+    }
+
+    Ok(LinkedProgram {
+        id,
+        generation,
+        vertex,
+        fragment,
+        attributes,
+        descriptors,
+        uniforms,
+        uniform_blocks,
+        push_constant_size,
+    })
+}
+
+fn reflect_uniform_type(
+    spirv: &Spirv,
+    ty: Id,
+    matrix_stride: Option<u32>,
+    row_major: bool,
+) -> Option<UniformType> {
+    match *spirv.id(ty).instruction() {
+        Instruction::TypeFloat { width: 32, .. } => Some(UniformType::Float(1)),
+        Instruction::TypeInt {
+            width: 32,
+            signedness,
+            ..
+        } => Some(if signedness == 0 {
+            UniformType::UInt(1)
+        } else {
+            UniformType::Int(1)
+        }),
+        Instruction::TypeVector {
+            component_type,
+            component_count,
+            ..
+        } => match reflect_uniform_type(spirv, component_type, None, false)? {
+            UniformType::Float(1) => Some(UniformType::Float(component_count)),
+            UniformType::Int(1) => Some(UniformType::Int(component_count)),
+            UniformType::UInt(1) => Some(UniformType::UInt(component_count)),
+            _ => None,
+        },
+        Instruction::TypeMatrix {
+            column_type,
+            column_count,
+            ..
+        } => match reflect_uniform_type(spirv, column_type, None, false)? {
+            UniformType::Float(rows) => Some(UniformType::Mat {
+                columns: column_count,
+                rows,
+                matrix_stride: matrix_stride?,
+                row_major,
+            }),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// The size of a block member's type, following the Offset, ArrayStride and MatrixStride
+/// decorations. None for types without a fixed size, like runtime arrays.
+fn block_type_size(
+    spirv: &Spirv,
+    ty: Id,
+    matrix_stride: Option<u32>,
+    row_major: bool,
+) -> Option<u32> {
+    let info = spirv.id(ty);
+
+    match *info.instruction() {
+        Instruction::TypeBool { .. } => Some(4),
+        Instruction::TypeInt { width, .. } | Instruction::TypeFloat { width, .. } => {
+            Some(width / 8)
+        }
+        Instruction::TypeVector {
+            component_type,
+            component_count,
+            ..
+        } => Some(block_type_size(spirv, component_type, None, false)? * component_count),
+        Instruction::TypeMatrix {
+            column_type,
+            column_count,
+            ..
+        } => {
+            let Instruction::TypeVector {
+                component_count: rows,
+                ..
+            } = *spirv.id(column_type).instruction()
+            else {
+                return None;
+            };
+
+            Some(matrix_stride? * if row_major { rows } else { column_count })
+        }
+        Instruction::TypeArray {
+            element_type,
+            length,
+            ..
+        } => {
+            let stride = info.iter_decoration().find_map(|d| match *d {
+                Instruction::Decorate {
+                    decoration: Decoration::ArrayStride { array_stride },
+                    ..
+                } => Some(array_stride),
+                _ => None,
+            });
+
+            let Instruction::Constant { ref value, .. } = *spirv.id(length).instruction() else {
+                return None;
+            };
+
+            let stride = match stride {
+                Some(stride) => stride,
+                None => block_type_size(spirv, element_type, matrix_stride, row_major)?,
+            };
+
+            Some(stride * value.first().copied()?)
+        }
+        Instruction::TypeStruct {
+            ref member_types, ..
+        } => {
+            let mut size = 0;
+
+            for (member, &member_type) in info.iter_members().zip(member_types) {
+                let layout = member_layout(member.iter_decoration());
+
+                size = size.max(
+                    layout.offset?
+                        + block_type_size(
+                            spirv,
+                            member_type,
+                            layout.matrix_stride,
+                            layout.row_major,
+                        )?,
+                );
+            }
+
+            Some(size)
+        }
+        _ => None,
+    }
+}
+
+/// The layout decorations of a struct member
+#[derive(Debug, Default)]
+struct MemberLayout {
+    offset: Option<u32>,
+    matrix_stride: Option<u32>,
+    row_major: bool,
+}
+
+fn member_layout<'a>(decorations: impl Iterator<Item = &'a Instruction>) -> MemberLayout {
+    let mut layout = MemberLayout::default();
+
+    for decoration in decorations {
+        match decoration {
+            Instruction::MemberDecorate {
+                decoration: Decoration::Offset { byte_offset },
+                ..
+            } => layout.offset = Some(*byte_offset),
+            Instruction::MemberDecorate {
+                decoration: Decoration::MatrixStride { matrix_stride },
+                ..
+            } => layout.matrix_stride = Some(*matrix_stride),
+            Instruction::MemberDecorate {
+                decoration: Decoration::RowMajor,
+                ..
+            } => layout.row_major = true,
+            _ => {}
+        }
+    }
+
+    layout
+}
+
+/// The (set, binding) a variable is decorated with
+fn variable_binding(spirv: &Spirv, variable: Id) -> (u32, u32) {
+    let mut set = 0;
+    let mut binding = 0;
+
+    for decoration in spirv.id(variable).iter_decoration() {
+        match decoration {
+            Instruction::Decorate {
+                decoration: Decoration::DescriptorSet { descriptor_set },
+                ..
+            } => set = *descriptor_set,
+            Instruction::Decorate {
+                decoration: Decoration::Binding { binding_point },
+                ..
+            } => binding = *binding_point,
+            _ => {}
+        }
+    }
+
+    (set, binding)
+}
+
+/// Finds each combined image sampler, which glUniform1i can point at a texture unit. Arrays of
+/// samplers aren't supported.
+fn reflect_samplers(spirv: &Spirv) -> Vec<ProgramUniform> {
+    let mut samplers = Vec::new();
+
+    for global in spirv.iter_global() {
+        let Instruction::Variable {
+            result_type_id,
+            result_id,
+            storage_class: StorageClass::UniformConstant,
+            ..
+        } = *global
+        else {
+            continue;
+        };
+
+        let Instruction::TypePointer { ty, .. } = *spirv.id(result_type_id).instruction() else {
+            continue;
+        };
+
+        if !matches!(
+            spirv.id(ty).instruction(),
+            Instruction::TypeSampledImage { .. }
+        ) {
+            continue;
+        }
+
+        let Some(name) = spirv.id(result_id).iter_name().find_map(|n| match n {
+            Instruction::Name { name, .. } => Some(name.clone()),
+            _ => None,
+        }) else {
+            continue;
+        };
+
+        let (set, binding) = variable_binding(spirv, result_id);
+
+        samplers.push(ProgramUniform {
+            name,
+            block_name: String::new(),
+            set,
+            binding,
+            offset: 0,
+            ty: UniformType::Sampler,
+        });
+    }
+
+    samplers
+}
+
+/// Finds each `uniform` block and the members glUniform* can write to. Members of other types
+/// (arrays, nested structs, bools) are left zeroed, but still count towards the block's size.
+fn reflect_uniform_blocks(spirv: &Spirv) -> Vec<(UniformBlock, Vec<ProgramUniform>)> {
+    let mut blocks = Vec::new();
+
+    for global in spirv.iter_global() {
+        let Instruction::Variable {
+            result_type_id,
+            result_id,
+            storage_class: StorageClass::Uniform,
+            ..
+        } = *global
+        else {
+            continue;
+        };
+
+        let Instruction::TypePointer { ty: struct_id, .. } =
+            *spirv.id(result_type_id).instruction()
+        else {
+            continue;
+        };
+
+        let struct_info = spirv.id(struct_id);
+
+        let Instruction::TypeStruct {
+            ref member_types, ..
+        } = *struct_info.instruction()
+        else {
+            continue;
+        };
+
+        let is_block = struct_info.iter_decoration().any(|d| {
+            matches!(
+                d,
+                Instruction::Decorate {
+                    decoration: Decoration::Block,
+                    ..
+                }
+            )
+        });
+
+        if !is_block {
+            continue;
+        }
+
+        let (set, binding) = variable_binding(spirv, result_id);
+
+        let block_name = struct_info
+            .iter_name()
+            .find_map(|n| match n {
+                Instruction::Name { name, .. } => Some(name.clone()),
+                _ => None,
+            })
+            .unwrap_or_default();
+
+        // blocks with runtime arrays are storage buffers, which aren't uniform blocks
+        let Some(size) = block_type_size(spirv, struct_id, None, false) else {
+            continue;
+        };
+
+        let mut members = Vec::new();
+
+        for (member, &member_type) in struct_info.iter_members().zip(member_types) {
+            let layout = member_layout(member.iter_decoration());
+
+            let name = member.iter_name().find_map(|n| match n {
+                Instruction::MemberName { name, .. } => Some(name.clone()),
+                _ => None,
+            });
+
+            let (Some(offset), Some(name), Some(ty)) = (
+                layout.offset,
+                name,
+                reflect_uniform_type(spirv, member_type, layout.matrix_stride, layout.row_major),
+            ) else {
+                continue;
+            };
+
+            members.push(ProgramUniform {
+                name,
+                block_name: block_name.clone(),
+                set,
+                binding,
+                offset,
+                ty,
+            });
+        }
+
+        blocks.push((UniformBlock { set, binding, size }, members));
+    }
+
+    blocks
+}
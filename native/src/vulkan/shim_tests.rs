@@ -10,6 +10,9 @@ use super::commands::CommandQueue;
 use super::dynamic_shader;
 use super::sandbox::{put_sandbox, take_sandbox};
 use super::sandbox_jni::client_arrays;
+use super::sandbox_jni::display_lists;
+use super::sandbox_jni::generic;
+use super::sandbox_jni::rendering;
 use super::{
     insn_assembler::RenderInsnAssembler,
     sandbox::{PointerArrayType, GLDataType},
@@ -74,6 +77,9 @@ fn add_pointer_u8_packed() {
             item_type: GLDataType::U8,
             data: Arc::new(data.clone()),
             size: 3,
+            buffer: None,
+            offset: 0,
+            stride: 0,
         }]);
     }
 }
@@ -111,6 +117,9 @@ fn add_pointer_u8() {
             item_type: GLDataType::U8,
             data: Arc::new(data_compact.clone()),
             size: 3,
+            buffer: None,
+            offset: 0,
+            stride: 0,
         }]);
     }
 }
@@ -152,13 +161,480 @@ fn add_pointer_f32() {
             item_type: GLDataType::F32,
             data: Arc::new(data_compact.clone()),
             size: 3,
+            buffer: None,
+            offset: 0,
+            stride: 0,
         }]);
     }
 }
 
+/// Feeds the instructions recorded by the shims into an assembler, returning the data of every
+/// draw
+fn assemble_recorded_draws() -> Vec<Arc<Vec<u8>>> {
+    use super::commands::RenderCommand;
+
+    let Some(RenderSandbox::List(insns)) = take_sandbox() else {
+        panic!("the sandbox wasn't recording");
+    };
+
+    let mut asm = RenderInsnAssembler::new(CommandQueue::Buffered(Vec::new()), None);
+
+    asm.feed(&insns);
+
+    let CommandQueue::Buffered(commands) = &asm.commands else {
+        panic!();
+    };
+
+    commands
+        .iter()
+        .filter_map(|cmd| match cmd {
+            RenderCommand::Draw { data, .. } => Some(data.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Generates a buffer, binds it to GL_ARRAY_BUFFER and fills it with `data`
+unsafe fn bind_array_buffer(data: &[u8]) -> i32 {
+    use gl_constants::GL_ARRAY_BUFFER;
+    use gl_constants::GL_DYNAMIC_DRAW;
+
+    let buffer =
+        client_arrays::Java_com_recursive_1pineapple_mcvk_rendering_RenderSandbox_glGenBuffers(
+            env(),
+            class(),
+        );
+
+    client_arrays::Java_com_recursive_1pineapple_mcvk_rendering_RenderSandbox_glBindBuffer(
+        env(),
+        class(),
+        GL_ARRAY_BUFFER as i32,
+        buffer,
+    );
+
+    client_arrays::Java_com_recursive_1pineapple_mcvk_rendering_RenderSandbox_addBufferData(
+        env(),
+        class(),
+        GL_ARRAY_BUFFER as i32,
+        data.as_ptr(),
+        data.len() as i32,
+        GL_DYNAMIC_DRAW as i32,
+    );
+
+    buffer
+}
+
+/// Enables a three component f32 pointer array `offset` bytes into the bound array buffer
+unsafe fn add_buffer_pointer(array_type: PointerArrayType, stride: i32, offset: usize) {
+    client_arrays::Java_com_recursive_1pineapple_mcvk_rendering_RenderSandbox_glEnableClientState(
+        env(),
+        class(),
+        array_type.to_i32().unwrap(),
+    );
+
+    client_arrays::Java_com_recursive_1pineapple_mcvk_rendering_RenderSandbox_addPointerArray(
+        env(),
+        class(),
+        3,
+        stride,
+        array_type.to_i32().unwrap(),
+        GLDataType::F32.to_i32().unwrap(),
+        offset as *const u8,
+        0,
+    );
+}
+
+unsafe fn draw_triangle() {
+    client_arrays::Java_com_recursive_1pineapple_mcvk_rendering_RenderSandbox_glDrawArrays(
+        env(),
+        class(),
+        DrawMode::Tri.to_i32().unwrap(),
+        0,
+        3,
+    );
+}
+
+#[test]
+fn add_pointer_from_bound_buffer() {
+    use gl_constants::GL_ARRAY_BUFFER;
+
+    // three interleaved vertices of xyz + rgb
+    let vertices = (0..3 * 6).map(|i| i as f32).collect::<Vec<_>>();
+
+    unsafe {
+        let bytes = vertices.align_to::<u8>().1;
+
+        prepare_sandbox();
+
+        let buffer = bind_array_buffer(bytes);
+
+        // the colours start 12 bytes in, and don't leave a whole stride after the last vertex
+        client_arrays::Java_com_recursive_1pineapple_mcvk_rendering_RenderSandbox_addPointerArray(
+            env(),
+            class(),
+            3,
+            24,
+            PointerArrayType::Color.to_i32().unwrap(),
+            GLDataType::F32.to_i32().unwrap(),
+            12 as *const u8,
+            0,
+        );
+
+        client_arrays::Java_com_recursive_1pineapple_mcvk_rendering_RenderSandbox_glBindBuffer(
+            env(),
+            class(),
+            GL_ARRAY_BUFFER as i32,
+            0,
+        );
+
+        // the buffer is only read when the draw is assembled
+        assert_insns(&vec![
+            RenderInstruction::BufferData {
+                buffer: buffer as u32,
+                data: Arc::new(bytes.to_vec()),
+            },
+            RenderInstruction::SetPointer {
+                vec_count: 0,
+                array_type: PointerArrayType::Color,
+                item_type: GLDataType::F32,
+                data: Arc::default(),
+                size: 3,
+                buffer: Some(buffer as u32),
+                offset: 12,
+                stride: 24,
+            },
+        ]);
+
+        prepare_sandbox();
+
+        bind_array_buffer(bytes);
+        add_buffer_pointer(PointerArrayType::Vertex, 24, 0);
+        add_buffer_pointer(PointerArrayType::Color, 24, 12);
+        draw_triangle();
+
+        // the colour array comes before the positions in the draw buffer
+        let expected = vertices
+            .chunks(6)
+            .flat_map(|v| v[3..].iter().chain(&v[..3]).copied())
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            assemble_recorded_draws(),
+            vec![Arc::new(expected.align_to::<u8>().1.to_vec())]
+        );
+    }
+}
+
+#[test]
+fn buffer_pointer_only_reads_drawn_vertices() {
+    let positions = (0..3 * 10).map(|i| i as f32).collect::<Vec<_>>();
+
+    unsafe {
+        prepare_sandbox();
+
+        bind_array_buffer(positions.align_to::<u8>().1);
+        add_buffer_pointer(PointerArrayType::Vertex, 0, 0);
+        draw_triangle();
+
+        assert_eq!(
+            assemble_recorded_draws(),
+            vec![Arc::new(positions[..9].align_to::<u8>().1.to_vec())]
+        );
+    }
+}
+
+#[test]
+fn buffer_sub_data_after_pointer_shows_up_in_draw() {
+    use gl_constants::GL_ARRAY_BUFFER;
+
+    let positions = (0..3 * 3).map(|i| i as f32).collect::<Vec<_>>();
+    let update = [20.0f32, 21.0, 22.0];
+
+    unsafe {
+        prepare_sandbox();
+
+        bind_array_buffer(positions.align_to::<u8>().1);
+        add_buffer_pointer(PointerArrayType::Vertex, 0, 0);
+        draw_triangle();
+
+        // replaces the second position, after the pointer was set
+        client_arrays::Java_com_recursive_1pineapple_mcvk_rendering_RenderSandbox_addBufferSubData(
+            env(),
+            class(),
+            GL_ARRAY_BUFFER as i32,
+            12,
+            update.align_to::<u8>().1.as_ptr(),
+            12,
+        );
+
+        draw_triangle();
+
+        let mut updated = positions.clone();
+        updated[3..6].copy_from_slice(&update);
+
+        // the draw assembled before the update keeps the old contents
+        assert_eq!(
+            assemble_recorded_draws(),
+            vec![
+                Arc::new(positions.align_to::<u8>().1.to_vec()),
+                Arc::new(updated.align_to::<u8>().1.to_vec()),
+            ]
+        );
+    }
+}
+
+#[test]
+fn call_list_replays_recorded_instructions() {
+    use gl_constants::GL_COMPILE;
+    use gl_constants::GL_COMPILE_AND_EXECUTE;
+
+    use super::sandbox::push_instruction;
+
+    let recorded = vec![
+        RenderInstruction::Begin(DrawMode::Tri),
+        RenderInstruction::End,
+    ];
+
+    unsafe {
+        prepare_sandbox();
+
+        let list =
+            display_lists::Java_com_recursive_1pineapple_mcvk_rendering_RenderSandbox_glGenLists(
+                env(),
+                class(),
+                1,
+            );
+
+        display_lists::Java_com_recursive_1pineapple_mcvk_rendering_RenderSandbox_glNewList(
+            env(),
+            class(),
+            list,
+            GL_COMPILE as i32,
+        );
+
+        for insn in &recorded {
+            push_instruction(insn.clone());
+        }
+
+        display_lists::Java_com_recursive_1pineapple_mcvk_rendering_RenderSandbox_glEndList(
+            env(),
+            class(),
+        );
+
+        // compiling alone doesn't draw anything
+        assert_insns(&vec![]);
+
+        display_lists::Java_com_recursive_1pineapple_mcvk_rendering_RenderSandbox_glCallList(
+            env(),
+            class(),
+            list,
+        );
+
+        assert_insns(&recorded);
+
+        // compiling and executing draws while recording
+        prepare_sandbox();
+
+        display_lists::Java_com_recursive_1pineapple_mcvk_rendering_RenderSandbox_glNewList(
+            env(),
+            class(),
+            list,
+            GL_COMPILE_AND_EXECUTE as i32,
+        );
+
+        push_instruction(RenderInstruction::End);
+
+        display_lists::Java_com_recursive_1pineapple_mcvk_rendering_RenderSandbox_glEndList(
+            env(),
+            class(),
+        );
+
+        display_lists::Java_com_recursive_1pineapple_mcvk_rendering_RenderSandbox_glCallList(
+            env(),
+            class(),
+            list,
+        );
+
+        assert_insns(&vec![RenderInstruction::End, RenderInstruction::End]);
+    }
+}
+
+#[test]
+fn client_state_is_not_compiled_into_lists() {
+    use gl_constants::GL_COMPILE;
+
+    use super::sandbox::push_instruction;
+
+    let enable_vertices = RenderInstruction::SetClientState {
+        enabled: true,
+        array_type: PointerArrayType::Vertex,
+    };
+
+    unsafe {
+        prepare_sandbox();
+
+        let list =
+            display_lists::Java_com_recursive_1pineapple_mcvk_rendering_RenderSandbox_glGenLists(
+                env(),
+                class(),
+                1,
+            );
+
+        display_lists::Java_com_recursive_1pineapple_mcvk_rendering_RenderSandbox_glNewList(
+            env(),
+            class(),
+            list,
+            GL_COMPILE as i32,
+        );
+
+        push_instruction(enable_vertices.clone());
+        push_instruction(RenderInstruction::End);
+
+        display_lists::Java_com_recursive_1pineapple_mcvk_rendering_RenderSandbox_glEndList(
+            env(),
+            class(),
+        );
+
+        // the client state took effect immediately
+        assert_insns(&vec![enable_vertices.clone()]);
+
+        display_lists::Java_com_recursive_1pineapple_mcvk_rendering_RenderSandbox_glCallList(
+            env(),
+            class(),
+            list,
+        );
+
+        assert_insns(&vec![enable_vertices, RenderInstruction::End]);
+    }
+}
+
+#[test]
+fn call_lists_are_offset_by_the_list_base() {
+    use gl_constants::GL_COMPILE;
+    use gl_constants::GL_INVALID_VALUE;
+    use gl_constants::GL_UNSIGNED_BYTE;
+
+    use super::sandbox::push_instruction;
+
+    unsafe {
+        prepare_sandbox();
+
+        let first =
+            display_lists::Java_com_recursive_1pineapple_mcvk_rendering_RenderSandbox_glGenLists(
+                env(),
+                class(),
+                2,
+            );
+
+        for (list, insn) in [
+            (first, RenderInstruction::PushMatrix),
+            (first + 1, RenderInstruction::PopMatrix),
+        ] {
+            display_lists::Java_com_recursive_1pineapple_mcvk_rendering_RenderSandbox_glNewList(
+                env(),
+                class(),
+                list,
+                GL_COMPILE as i32,
+            );
+
+            push_instruction(insn);
+
+            display_lists::Java_com_recursive_1pineapple_mcvk_rendering_RenderSandbox_glEndList(
+                env(),
+                class(),
+            );
+        }
+
+        display_lists::Java_com_recursive_1pineapple_mcvk_rendering_RenderSandbox_glListBase(
+            env(),
+            class(),
+            first,
+        );
+
+        let lists = [1u8, 0];
+
+        display_lists::Java_com_recursive_1pineapple_mcvk_rendering_RenderSandbox_addCallLists(
+            env(),
+            class(),
+            GL_UNSIGNED_BYTE as i32,
+            lists.as_ptr(),
+            lists.len() as i32,
+        );
+
+        assert_insns(&vec![
+            RenderInstruction::PopMatrix,
+            RenderInstruction::PushMatrix,
+        ]);
+
+        display_lists::Java_com_recursive_1pineapple_mcvk_rendering_RenderSandbox_addCallLists(
+            env(),
+            class(),
+            GL_UNSIGNED_BYTE as i32,
+            lists.as_ptr(),
+            -1,
+        );
+
+        assert_eq!(
+            generic::Java_com_recursive_1pineapple_mcvk_rendering_RenderSandbox_glGetError(
+                env(),
+                class()
+            ),
+            GL_INVALID_VALUE as i32
+        );
+    }
+}
+
+#[test]
+fn invalid_draw_mode_sets_gl_error() {
+    use gl_constants::GL_INVALID_ENUM;
+    use gl_constants::GL_NO_ERROR;
+
+    unsafe {
+        prepare_sandbox();
+
+        assert_eq!(
+            generic::Java_com_recursive_1pineapple_mcvk_rendering_RenderSandbox_glGetError(
+                env(),
+                class()
+            ),
+            GL_NO_ERROR as i32
+        );
+
+        rendering::Java_com_recursive_1pineapple_mcvk_rendering_RenderSandbox_glBegin(
+            env(),
+            class(),
+            0xFF,
+        );
+
+        // only the first error is kept until it's queried
+        display_lists::Java_com_recursive_1pineapple_mcvk_rendering_RenderSandbox_glGenLists(
+            env(),
+            class(),
+            -1,
+        );
+
+        assert_insns(&vec![]);
+
+        assert_eq!(
+            generic::Java_com_recursive_1pineapple_mcvk_rendering_RenderSandbox_glGetError(
+                env(),
+                class()
+            ),
+            GL_INVALID_ENUM as i32
+        );
+        assert_eq!(
+            generic::Java_com_recursive_1pineapple_mcvk_rendering_RenderSandbox_glGetError(
+                env(),
+                class()
+            ),
+            GL_NO_ERROR as i32
+        );
+    }
+}
+
 #[test]
 fn vertex_assembly() {
-    let mut asm = RenderInsnAssembler::new(CommandQueue::Buffered(Vec::new()));
+    let mut asm = RenderInsnAssembler::new(CommandQueue::Buffered(Vec::new()), None);
 
     let pos = (0..3 * 10).map(|i| i as f32).collect::<Vec<_>>();
     let color = (0..3 * 10).map(|i| i as f32).rev().collect::<Vec<_>>();
@@ -178,6 +654,9 @@ fn vertex_assembly() {
             item_type: GLDataType::F32,
             data: Arc::new(unsafe { pos.align_to().1.to_owned() }),
             size: 3,
+            buffer: None,
+            offset: 0,
+            stride: 0,
         },
         RenderInstruction::SetPointer {
             vec_count: 10,
@@ -185,6 +664,9 @@ fn vertex_assembly() {
             item_type: GLDataType::F32,
             data: Arc::new(unsafe { color.align_to().1.to_owned() }),
             size: 3,
+            buffer: None,
+            offset: 0,
+            stride: 0,
         },
     ]);
 
@@ -249,3 +731,2733 @@ fn vertex_assembly() {
 
     // assert_eq!(buffer, target);
 }
+
+#[test]
+fn device_lost_maps_to_fatal_exception() {
+    use super::error::McvkError;
+    use super::textures::texture_manager::TextureError;
+
+    let lost = McvkError::texture(
+        anyhow::Error::from(vulkano::VulkanError::DeviceLost).context("could not flush renderer"),
+    );
+
+    assert!(matches!(lost, McvkError::DeviceLost(_)));
+    assert!(lost.is_fatal());
+    assert_eq!(
+        lost.java_exception_class(),
+        "com/recursive_pineapple/mcvk/rendering/MCVKDeviceLostException"
+    );
+
+    let texture = McvkError::from(TextureError::NoTexture);
+
+    assert!(!texture.is_fatal());
+    assert_eq!(
+        texture.java_exception_class(),
+        "com/recursive_pineapple/mcvk/rendering/MCVKTextureException"
+    );
+}
+
+#[test]
+fn coloured_wireframes_share_pipeline() {
+    use super::commands::RenderCommand;
+    use super::sandbox::PolygonMode;
+
+    let mut asm = RenderInsnAssembler::new(CommandQueue::Buffered(Vec::new()), None);
+
+    let pos = (0..3 * 3).map(|i| i as f32).collect::<Vec<_>>();
+
+    asm.feed(&[
+        RenderInstruction::PolygonMode(PolygonMode::Line),
+        RenderInstruction::SetClientState {
+            enabled: true,
+            array_type: PointerArrayType::Vertex,
+        },
+        RenderInstruction::SetPointer {
+            vec_count: 3,
+            array_type: PointerArrayType::Vertex,
+            item_type: GLDataType::F32,
+            data: Arc::new(unsafe { pos.align_to().1.to_owned() }),
+            size: 3,
+            buffer: None,
+            offset: 0,
+            stride: 0,
+        },
+        RenderInstruction::SetColor([1.0, 0.0, 0.0, 1.0].into()),
+        RenderInstruction::DrawArrays {
+            mode: DrawMode::Tri,
+            first: 0,
+            count: 3,
+        },
+        RenderInstruction::SetColor([0.0, 1.0, 0.0, 1.0].into()),
+        RenderInstruction::DrawArrays {
+            mode: DrawMode::Tri,
+            first: 0,
+            count: 3,
+        },
+    ]);
+
+    let CommandQueue::Buffered(commands) = &asm.commands else {
+        panic!();
+    };
+
+    let binds = commands
+        .iter()
+        .filter_map(|cmd| match cmd {
+            RenderCommand::BindDynamicGraphicsPipeline {
+                pipeline,
+                push_constants,
+                ..
+            } => Some((pipeline, push_constants)),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    assert_eq!(binds.len(), 2);
+
+    let (first_spec, first_pc) = binds[0];
+    let (second_spec, second_pc) = binds[1];
+
+    assert_eq!(first_spec.rasterization.polygon_mode, PolygonMode::Line);
+    assert_eq!(first_spec, second_spec);
+
+    assert_eq!(first_pc.color, Some([1.0, 0.0, 0.0, 1.0].into()));
+    assert_eq!(second_pc.color, Some([0.0, 1.0, 0.0, 1.0].into()));
+}
+
+#[test]
+fn nested_scissor_intersects() {
+    use super::commands::scissor_to_vulkan;
+    use super::commands::RenderCommand;
+    use super::sandbox::ScissorRect;
+
+    let outer = ScissorRect {
+        x: 0,
+        y: 0,
+        width: 100,
+        height: 100,
+    };
+    let inner = ScissorRect {
+        x: 50,
+        y: 25,
+        width: 100,
+        height: 50,
+    };
+    let overlap = ScissorRect {
+        x: 50,
+        y: 25,
+        width: 50,
+        height: 50,
+    };
+
+    let mut asm = RenderInsnAssembler::new(CommandQueue::Buffered(Vec::new()), None);
+
+    asm.feed(&[
+        RenderInstruction::PushScissor(outer),
+        RenderInstruction::PushScissor(inner),
+    ]);
+
+    assert_eq!(asm.get_scissor(), Some(overlap));
+
+    asm.feed(&[RenderInstruction::PopScissor]);
+    assert_eq!(asm.get_scissor(), Some(outer));
+
+    asm.feed(&[RenderInstruction::PopScissor]);
+    assert_eq!(asm.get_scissor(), None);
+
+    let CommandQueue::Buffered(commands) = &asm.commands else {
+        panic!();
+    };
+
+    let scissors = commands
+        .iter()
+        .filter_map(|cmd| match cmd {
+            RenderCommand::SetScissor(rect) => Some(*rect),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    assert_eq!(scissors, vec![Some(outer), Some(overlap), Some(outer), None]);
+
+    // disjoint regions clip everything
+    let disjoint = outer.intersect(&ScissorRect {
+        x: 200,
+        y: 0,
+        width: 10,
+        height: 10,
+    });
+    assert_eq!((disjoint.width, disjoint.height), (0, 0));
+
+    // gl's origin is the bottom left, vulkan's is the top left
+    let scissor = scissor_to_vulkan(&overlap, [200, 100]);
+    assert_eq!(scissor.offset, [50, 25]);
+    assert_eq!(scissor.extent, [50, 50]);
+}
+
+#[test]
+fn disabling_color_array_reverts_to_flat() {
+    use super::commands::RenderCommand;
+    use super::dynamic_shader::ColorMode;
+    use super::dynamic_shader::DataSource;
+
+    let mut asm = RenderInsnAssembler::new(CommandQueue::Buffered(Vec::new()), None);
+
+    let pos = (0..3 * 3).map(|i| i as f32).collect::<Vec<_>>();
+    let color = (0..4 * 3).map(|i| i as f32 / 12.0).collect::<Vec<_>>();
+
+    asm.feed(&[
+        RenderInstruction::SetClientState {
+            enabled: true,
+            array_type: PointerArrayType::Vertex,
+        },
+        RenderInstruction::SetPointer {
+            vec_count: 3,
+            array_type: PointerArrayType::Vertex,
+            item_type: GLDataType::F32,
+            data: Arc::new(unsafe { pos.align_to().1.to_owned() }),
+            size: 3,
+            buffer: None,
+            offset: 0,
+            stride: 0,
+        },
+        RenderInstruction::SetClientState {
+            enabled: true,
+            array_type: PointerArrayType::Color,
+        },
+        RenderInstruction::SetPointer {
+            vec_count: 3,
+            array_type: PointerArrayType::Color,
+            item_type: GLDataType::F32,
+            data: Arc::new(unsafe { color.align_to().1.to_owned() }),
+            size: 4,
+            buffer: None,
+            offset: 0,
+            stride: 0,
+        },
+        RenderInstruction::DrawArrays {
+            mode: DrawMode::Tri,
+            first: 0,
+            count: 3,
+        },
+        RenderInstruction::SetClientState {
+            enabled: false,
+            array_type: PointerArrayType::Color,
+        },
+        RenderInstruction::SetColor([0.0, 0.0, 1.0, 1.0].into()),
+        RenderInstruction::DrawArrays {
+            mode: DrawMode::Tri,
+            first: 0,
+            count: 3,
+        },
+    ]);
+
+    let CommandQueue::Buffered(commands) = &asm.commands else {
+        panic!();
+    };
+
+    let binds = commands
+        .iter()
+        .filter_map(|cmd| match cmd {
+            RenderCommand::BindDynamicGraphicsPipeline {
+                pipeline,
+                push_constants,
+                ..
+            } => Some((pipeline, push_constants)),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    assert_eq!(binds.len(), 2);
+
+    let (array_spec, array_pc) = binds[0];
+    let (flat_spec, flat_pc) = binds[1];
+
+    assert_eq!(array_spec.color, ColorMode::Array);
+    assert!(array_spec.color().is_some());
+    assert_eq!(array_pc.color, None);
+
+    assert_eq!(flat_spec.color, ColorMode::Flat(DataSource::PushConstant));
+    assert!(flat_spec.color().is_none());
+    assert_eq!(flat_pc.color, Some([0.0, 0.0, 1.0, 1.0].into()));
+
+    assert_ne!(array_spec, flat_spec);
+}
+
+#[test]
+fn texture_matrix_is_per_unit() {
+    use nalgebra_glm::vec4;
+    use nalgebra_glm::TMat4;
+
+    use super::sandbox::MatrixMode;
+
+    let mut asm = RenderInsnAssembler::new(CommandQueue::Buffered(Vec::new()), None);
+
+    asm.feed(&[
+        RenderInstruction::MatrixMode(MatrixMode::Texture),
+        RenderInstruction::SetActiveTextureUnit(0),
+        RenderInstruction::Translate {
+            delta: [0.5, 0.0, 0.0].into(),
+        },
+        RenderInstruction::SetActiveTextureUnit(1),
+        RenderInstruction::Scale {
+            scale: [2.0, 2.0, 1.0].into(),
+        },
+    ]);
+
+    let base = asm.get_texture_matrix(0);
+    let lightmap = asm.get_texture_matrix(1);
+
+    assert_eq!(base * vec4(0.0, 0.0, 0.0, 1.0), vec4(0.5, 0.0, 0.0, 1.0));
+    assert_eq!(lightmap * vec4(1.0, 1.0, 0.0, 1.0), vec4(2.0, 2.0, 0.0, 1.0));
+
+    // the other units are untouched
+    assert_eq!(asm.get_texture_matrix(2), &TMat4::identity());
+
+    // pushing and popping only affect the active unit
+    asm.feed(&[RenderInstruction::PushMatrix, RenderInstruction::LoadIdentity]);
+    assert_eq!(asm.get_texture_matrix(1), &TMat4::identity());
+    assert_ne!(asm.get_texture_matrix(0), &TMat4::identity());
+
+    asm.feed(&[RenderInstruction::PopMatrix]);
+    assert_eq!(
+        asm.get_texture_matrix(1) * vec4(1.0, 1.0, 0.0, 1.0),
+        vec4(2.0, 2.0, 0.0, 1.0)
+    );
+}
+
+#[test]
+fn color_array_wins_over_gl_color() {
+    use super::commands::RenderCommand;
+    use super::dynamic_shader::ColorMode;
+
+    let mut asm = RenderInsnAssembler::new(CommandQueue::Buffered(Vec::new()), None);
+
+    let pos = (0..3 * 3).map(|i| i as f32).collect::<Vec<_>>();
+    let color = (0..4 * 3).map(|i| i as f32 / 12.0).collect::<Vec<_>>();
+
+    asm.feed(&[
+        RenderInstruction::SetColor([1.0, 0.0, 0.0, 1.0].into()),
+        RenderInstruction::SetClientState {
+            enabled: true,
+            array_type: PointerArrayType::Vertex,
+        },
+        RenderInstruction::SetPointer {
+            vec_count: 3,
+            array_type: PointerArrayType::Vertex,
+            item_type: GLDataType::F32,
+            data: Arc::new(unsafe { pos.align_to().1.to_owned() }),
+            size: 3,
+            buffer: None,
+            offset: 0,
+            stride: 0,
+        },
+        RenderInstruction::SetClientState {
+            enabled: true,
+            array_type: PointerArrayType::Color,
+        },
+        RenderInstruction::SetPointer {
+            vec_count: 3,
+            array_type: PointerArrayType::Color,
+            item_type: GLDataType::F32,
+            data: Arc::new(unsafe { color.align_to().1.to_owned() }),
+            size: 4,
+            buffer: None,
+            offset: 0,
+            stride: 0,
+        },
+        // a glColor after the array was set up must not override it
+        RenderInstruction::SetColor([0.0, 1.0, 0.0, 1.0].into()),
+        RenderInstruction::DrawArrays {
+            mode: DrawMode::Tri,
+            first: 0,
+            count: 3,
+        },
+    ]);
+
+    let CommandQueue::Buffered(commands) = &asm.commands else {
+        panic!();
+    };
+
+    let Some(RenderCommand::BindDynamicGraphicsPipeline {
+        pipeline,
+        push_constants,
+        ..
+    }) = commands
+        .iter()
+        .find(|cmd| matches!(cmd, RenderCommand::BindDynamicGraphicsPipeline { .. }))
+    else {
+        panic!("no pipeline was bound");
+    };
+
+    assert_eq!(pipeline.color, ColorMode::Array);
+    assert_eq!(push_constants.color, None);
+}
+
+#[test]
+fn depth_state_round_trips() {
+    use num::FromPrimitive;
+
+    use super::sandbox::DepthFunc;
+
+    let mut asm = RenderInsnAssembler::new(CommandQueue::Buffered(Vec::new()), None);
+
+    // the GL defaults
+    assert_eq!(
+        asm.get_integer(gl_constants::GL_DEPTH_FUNC),
+        Some(gl_constants::GL_LESS as i32)
+    );
+    assert_eq!(asm.get_boolean(gl_constants::GL_DEPTH_WRITEMASK), Some(true));
+
+    asm.feed(&[
+        RenderInstruction::DepthFunc(DepthFunc::from_u32(gl_constants::GL_LEQUAL).unwrap()),
+        RenderInstruction::DepthMask(false),
+    ]);
+
+    assert_eq!(
+        asm.get_integer(gl_constants::GL_DEPTH_FUNC),
+        Some(gl_constants::GL_LEQUAL as i32)
+    );
+    assert_eq!(asm.get_boolean(gl_constants::GL_DEPTH_WRITEMASK), Some(false));
+
+    // every depth function queries back as the enum it was set with
+    for func in gl_constants::GL_NEVER..=gl_constants::GL_ALWAYS {
+        asm.feed(&[RenderInstruction::DepthFunc(DepthFunc::from_u32(func).unwrap())]);
+
+        assert_eq!(asm.get_integer(gl_constants::GL_DEPTH_FUNC), Some(func as i32));
+    }
+
+    assert_eq!(DepthFunc::from_u32(gl_constants::GL_ALWAYS + 1), None);
+}
+
+#[test]
+fn boolean_queries() {
+    let mut asm = RenderInsnAssembler::new(CommandQueue::Buffered(Vec::new()), None);
+
+    assert_eq!(asm.get_boolean(gl_constants::GL_BLEND), Some(false));
+
+    asm.feed(&[RenderInstruction::Enable(gl_constants::GL_BLEND as i32)]);
+
+    assert_eq!(asm.get_boolean(gl_constants::GL_BLEND), Some(true));
+    assert_eq!(asm.get_boolean(gl_constants::GL_DEPTH_TEST), Some(false));
+
+    asm.feed(&[
+        RenderInstruction::Disable(gl_constants::GL_BLEND as i32),
+        RenderInstruction::DepthMask(false),
+        RenderInstruction::ColorMask([true, false, true, false]),
+    ]);
+
+    assert_eq!(asm.get_boolean(gl_constants::GL_BLEND), Some(false));
+    assert_eq!(asm.get_boolean(gl_constants::GL_DEPTH_WRITEMASK), Some(false));
+    assert_eq!(
+        asm.get_booleans(gl_constants::GL_COLOR_WRITEMASK),
+        Some(vec![true, false, true, false])
+    );
+
+    // not a boolean
+    assert_eq!(asm.get_booleans(gl_constants::GL_DEPTH_FUNC), None);
+}
+
+#[test]
+fn pop_attrib_restores_pushed_state() {
+    let mut asm = RenderInsnAssembler::new(CommandQueue::Buffered(Vec::new()), None);
+
+    asm.feed(&[
+        RenderInstruction::Enable(gl_constants::GL_BLEND as i32),
+        RenderInstruction::PushAttrib(gl_constants::GL_ENABLE_BIT),
+        RenderInstruction::Disable(gl_constants::GL_BLEND as i32),
+        RenderInstruction::Enable(gl_constants::GL_DEPTH_TEST as i32),
+    ]);
+
+    assert_eq!(asm.get_boolean(gl_constants::GL_BLEND), Some(false));
+    assert_eq!(
+        asm.get_integer(gl_constants::GL_ATTRIB_STACK_DEPTH),
+        Some(1)
+    );
+
+    asm.feed(&[RenderInstruction::PopAttrib]);
+
+    assert_eq!(asm.get_boolean(gl_constants::GL_BLEND), Some(true));
+    assert_eq!(asm.get_boolean(gl_constants::GL_DEPTH_TEST), Some(false));
+    assert_eq!(
+        asm.get_integer(gl_constants::GL_ATTRIB_STACK_DEPTH),
+        Some(0)
+    );
+
+    // nested pushes only restore the groups in their own mask
+    asm.feed(&[
+        RenderInstruction::PushAttrib(gl_constants::GL_ENABLE_BIT),
+        RenderInstruction::PushAttrib(gl_constants::GL_DEPTH_BUFFER_BIT),
+        RenderInstruction::DepthMask(false),
+        RenderInstruction::Disable(gl_constants::GL_BLEND as i32),
+        RenderInstruction::PopAttrib,
+    ]);
+
+    assert_eq!(
+        asm.get_boolean(gl_constants::GL_DEPTH_WRITEMASK),
+        Some(true)
+    );
+    assert_eq!(asm.get_boolean(gl_constants::GL_BLEND), Some(false));
+
+    asm.feed(&[RenderInstruction::PopAttrib]);
+
+    assert_eq!(asm.get_boolean(gl_constants::GL_BLEND), Some(true));
+
+    // popping an empty stack is ignored
+    asm.feed(&[RenderInstruction::PopAttrib]);
+
+    assert_eq!(asm.get_boolean(gl_constants::GL_BLEND), Some(true));
+}
+
+#[test]
+fn bounded_queue_blocks_when_full() {
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    use super::commands::RenderCommand;
+    use super::workers::RenderWorker;
+    use super::workers::RenderWorkerStatus;
+
+    let (mut worker, queue) = RenderWorker::new(Some(2));
+
+    // the recorder variant isn't Send, so only the sender moves across
+    let CommandQueue::Bounded(tx) = queue else {
+        panic!();
+    };
+
+    tx.send(RenderCommand::ClearDepth(None)).unwrap();
+    tx.send(RenderCommand::ClearDepth(None)).unwrap();
+
+    let (done_tx, done_rx) = mpsc::channel();
+
+    let producer = std::thread::spawn(move || {
+        tx.send(RenderCommand::ClearColour(None)).unwrap();
+        done_tx.send(()).unwrap();
+    });
+
+    // the queue is full, so the producer has to wait for the worker
+    assert!(done_rx.recv_timeout(Duration::from_millis(100)).is_err());
+
+    let mut received = Vec::new();
+    worker.drain_into(|cmd| received.push(cmd));
+
+    done_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+    producer.join().unwrap();
+
+    // every sender has been dropped
+    assert_eq!(
+        worker.drain_into(|cmd| received.push(cmd)),
+        RenderWorkerStatus::Closed
+    );
+
+    assert!(matches!(
+        received[..],
+        [
+            RenderCommand::ClearDepth(None),
+            RenderCommand::ClearDepth(None),
+            RenderCommand::ClearColour(None)
+        ]
+    ));
+}
+
+#[test]
+fn full_bounded_queue_overflows_instead_of_blocking_the_runtime() {
+    use super::commands::RenderCommand;
+    use super::workers::RenderWorker;
+
+    let (mut worker, mut queue) = RenderWorker::new(Some(1));
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+
+    // blocking_send would panic in here
+    runtime.block_on(async {
+        queue.push(RenderCommand::ClearDepth(None)).unwrap();
+        queue.push(RenderCommand::ClearColour(None)).unwrap();
+        queue.push(RenderCommand::ClearDepth(None)).unwrap();
+    });
+
+    assert!(queue.is_backed_up());
+
+    let mut received = Vec::new();
+    worker.drain_into(|cmd| received.push(cmd));
+
+    // the overflow is received after the channel, in the order it was sent
+    assert!(matches!(
+        received[..],
+        [
+            RenderCommand::ClearDepth(None),
+            RenderCommand::ClearColour(None),
+            RenderCommand::ClearDepth(None)
+        ]
+    ));
+    assert!(!queue.is_backed_up());
+}
+
+#[test]
+fn replaced_queue_keeps_what_was_sent() {
+    use super::commands::RenderCommand;
+    use super::workers::RenderWorker;
+
+    let (mut worker, mut queue) = RenderWorker::new(None);
+
+    queue.push(RenderCommand::ClearDepth(None)).unwrap();
+
+    let mut queue = worker.replace_queue(Some(4));
+    assert!(matches!(queue, CommandQueue::Bounded(_)));
+
+    queue.push(RenderCommand::ClearColour(None)).unwrap();
+
+    let mut received = Vec::new();
+    worker.drain_into(|cmd| received.push(cmd));
+
+    assert!(matches!(
+        received[..],
+        [
+            RenderCommand::ClearDepth(None),
+            RenderCommand::ClearColour(None)
+        ]
+    ));
+}
+
+#[test]
+fn render_worker_coalesces_and_flushes_on_shutdown() {
+    use super::commands::RenderCommand;
+    use super::dynamic_shader::DynamicPipelinePushConstants;
+    use super::workers::RenderWorker;
+    use super::workers::RenderWorkerStatus;
+
+    let mut asm = RenderInsnAssembler::new(CommandQueue::Buffered(Vec::new()), None);
+
+    asm.feed(&[
+        RenderInstruction::SetClientState {
+            enabled: true,
+            array_type: PointerArrayType::Vertex,
+        },
+        RenderInstruction::SetPointer {
+            vec_count: 3,
+            array_type: PointerArrayType::Vertex,
+            item_type: GLDataType::F32,
+            data: Arc::new(vec![0; 36]),
+            size: 3,
+            buffer: None,
+            offset: 0,
+            stride: 0,
+        },
+        RenderInstruction::DrawArrays {
+            mode: DrawMode::Tri,
+            first: 0,
+            count: 3,
+        },
+    ]);
+
+    let CommandQueue::Buffered(commands) = &asm.commands else {
+        panic!();
+    };
+
+    let Some(RenderCommand::BindDynamicGraphicsPipeline { pipeline, .. }) = commands.first() else {
+        panic!("expected a pipeline bind, got {commands:?}");
+    };
+
+    let bind = |color: f32| RenderCommand::BindDynamicGraphicsPipeline {
+        pipeline: pipeline.clone(),
+        push_constants: DynamicPipelinePushConstants {
+            color: Some([color; 4].into()),
+            ..Default::default()
+        },
+        raster_state: Default::default(),
+    };
+
+    let (mut worker, mut queue) = RenderWorker::new(None);
+
+    queue.push(bind(0.0)).unwrap();
+    queue.push(bind(1.0)).unwrap();
+    queue.push(RenderCommand::ClearDepth(None)).unwrap();
+    queue.push(bind(2.0)).unwrap();
+
+    let mut recorded = Vec::new();
+    assert_eq!(
+        worker.drain_into(|cmd| recorded.push(cmd)),
+        RenderWorkerStatus::Open
+    );
+
+    // the first bind is overridden by the second before anything could use it
+    assert_eq!(recorded.len(), 3);
+    let RenderCommand::BindDynamicGraphicsPipeline { push_constants, .. } = &recorded[0] else {
+        panic!();
+    };
+    assert_eq!(push_constants.color, Some([1.0; 4].into()));
+    assert!(matches!(recorded[1], RenderCommand::ClearDepth(None)));
+
+    // commands which were sent before the shutdown still get recorded
+    queue.push(RenderCommand::ClearDepth(None)).unwrap();
+    drop(queue);
+
+    recorded.clear();
+    worker.shutdown_into(|cmd| recorded.push(cmd));
+    assert_eq!(recorded.len(), 1);
+}
+
+#[test]
+fn render_worker_merges_consecutive_draws() {
+    use super::commands::RenderCommand;
+    use super::workers::RenderWorker;
+
+    let mut asm = RenderInsnAssembler::new(CommandQueue::Buffered(Vec::new()), None);
+
+    let triangle = |value: f32| {
+        let data = [value; 9].iter().flat_map(|v| v.to_ne_bytes()).collect();
+
+        [
+            RenderInstruction::SetPointer {
+                vec_count: 3,
+                array_type: PointerArrayType::Vertex,
+                item_type: GLDataType::F32,
+                data: Arc::new(data),
+                size: 3,
+                buffer: None,
+                offset: 0,
+                stride: 0,
+            },
+            RenderInstruction::DrawArrays {
+                mode: DrawMode::Tri,
+                first: 0,
+                count: 3,
+            },
+        ]
+    };
+
+    asm.feed(&[RenderInstruction::SetClientState {
+        enabled: true,
+        array_type: PointerArrayType::Vertex,
+    }]);
+    asm.feed(&triangle(1.0));
+    asm.feed(&triangle(2.0));
+    asm.feed(&triangle(3.0));
+
+    let CommandQueue::Buffered(commands) = &mut asm.commands else {
+        panic!();
+    };
+
+    let (mut worker, mut queue) = RenderWorker::new(None);
+
+    for cmd in commands.drain(..) {
+        queue.push(cmd).unwrap();
+    }
+
+    // a clear has to happen between the draws around it
+    queue.push(RenderCommand::ClearDepth(None)).unwrap();
+
+    let mut recorded = Vec::new();
+    worker.drain_into(|cmd| recorded.push(cmd));
+
+    let draws = recorded
+        .iter()
+        .filter_map(|cmd| match cmd {
+            RenderCommand::Draw {
+                start_vertex,
+                vertex_count,
+                data,
+            } => Some((*start_vertex, *vertex_count, data.clone())),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    assert_eq!(draws.len(), 1, "{recorded:?}");
+
+    let (start_vertex, vertex_count, data) = &draws[0];
+    assert_eq!((*start_vertex, *vertex_count), (0, 9));
+
+    // the vertices are in the order they were drawn in
+    let positions = data
+        .chunks_exact(4)
+        .map(|v| f32::from_ne_bytes(v.try_into().unwrap()))
+        .step_by(3)
+        .collect::<Vec<_>>();
+    assert_eq!(positions, [1.0, 1.0, 1.0, 2.0, 2.0, 2.0, 3.0, 3.0, 3.0]);
+
+    // only the first of the identical binds is left
+    assert!(matches!(
+        recorded[..],
+        [
+            RenderCommand::BindDynamicGraphicsPipeline { .. },
+            RenderCommand::Draw { .. },
+            RenderCommand::ClearDepth(None),
+        ]
+    ));
+}
+
+#[test]
+fn rescale_factor_undoes_uniform_scale() {
+    use nalgebra_glm::vec3;
+    use nalgebra_glm::TMat4;
+
+    use super::insn_assembler::normal_rescale_factor;
+
+    let scaled = nalgebra_glm::scale(&TMat4::identity(), &vec3(2.0, 2.0, 2.0));
+
+    assert!((normal_rescale_factor(&scaled) - 2.0).abs() < 1e-6);
+    assert_eq!(normal_rescale_factor(&TMat4::identity()), 1.0);
+}
+
+#[test]
+fn immediate_mode_snapshots_attributes() {
+    use super::commands::RenderCommand;
+    use super::dynamic_shader::ColorMode;
+
+    let mut asm = RenderInsnAssembler::new(CommandQueue::Buffered(Vec::new()), None);
+
+    asm.feed(&[
+        // both of these are invalid and must be ignored
+        RenderInstruction::Vertex([0.0, 0.0, 0.0, 1.0].into()),
+        RenderInstruction::End,
+        RenderInstruction::Begin(DrawMode::Tri),
+        RenderInstruction::SetColor([1.0, 0.0, 0.0, 1.0].into()),
+        RenderInstruction::Vertex([0.0, 0.0, 0.0, 1.0].into()),
+        RenderInstruction::SetColor([0.0, 1.0, 0.0, 1.0].into()),
+        RenderInstruction::Vertex([1.0, 0.0, 0.0, 1.0].into()),
+        RenderInstruction::Vertex([0.0, 1.0, 0.0, 1.0].into()),
+        RenderInstruction::End,
+    ]);
+
+    let CommandQueue::Buffered(commands) = &asm.commands else {
+        panic!();
+    };
+
+    assert_eq!(commands.len(), 2);
+
+    let RenderCommand::BindDynamicGraphicsPipeline { pipeline, .. } = &commands[0] else {
+        panic!("expected a pipeline bind, got {:?}", commands[0]);
+    };
+
+    let RenderCommand::Draw {
+        vertex_count, data, ..
+    } = &commands[1]
+    else {
+        panic!("expected a draw, got {:?}", commands[1]);
+    };
+
+    assert_eq!(pipeline.color, ColorMode::Array);
+    assert!(pipeline.normal().is_some());
+    assert_eq!(*vertex_count, 3);
+
+    let stride = pipeline.vertex_buffer.stride as usize;
+    let color_offset = pipeline.color().unwrap().offset as usize;
+
+    let color_of = |vertex: usize| {
+        let start = vertex * stride + color_offset;
+        unsafe { data[start..start + 16].align_to::<f32>().1.to_owned() }
+    };
+
+    // each vertex keeps the colour which was current when it was emitted
+    assert_eq!(color_of(0), [1.0, 0.0, 0.0, 1.0]);
+    assert_eq!(color_of(1), [0.0, 1.0, 0.0, 1.0]);
+    assert_eq!(color_of(2), [0.0, 1.0, 0.0, 1.0]);
+
+    // the client arrays are untouched afterwards
+    asm.feed(&[RenderInstruction::DrawArrays {
+        mode: DrawMode::Tri,
+        first: 0,
+        count: 3,
+    }]);
+
+    let CommandQueue::Buffered(commands) = &asm.commands else {
+        panic!();
+    };
+
+    assert_eq!(commands.len(), 2);
+}
+
+#[test]
+fn texcoords_are_assembled_with_their_index() {
+    use super::commands::RenderCommand;
+    use super::sandbox::MatrixMode;
+
+    let mut asm = RenderInsnAssembler::new(CommandQueue::Buffered(Vec::new()), None);
+
+    let pos = (0..3 * 3).map(|i| i as f32).collect::<Vec<_>>();
+    let uvs = [0.0f32, 0.0, 1.0, 0.0, 0.5, 1.0];
+
+    asm.feed(&[
+        RenderInstruction::Enable(gl_constants::GL_TEXTURE_2D as i32),
+        RenderInstruction::BindTexture(5),
+        RenderInstruction::MatrixMode(MatrixMode::Texture),
+        RenderInstruction::Translate {
+            delta: [0.25, 0.0, 0.0].into(),
+        },
+        RenderInstruction::SetClientState {
+            enabled: true,
+            array_type: PointerArrayType::Vertex,
+        },
+        RenderInstruction::SetPointer {
+            vec_count: 3,
+            array_type: PointerArrayType::Vertex,
+            item_type: GLDataType::F32,
+            data: Arc::new(unsafe { pos.align_to().1.to_owned() }),
+            size: 3,
+            buffer: None,
+            offset: 0,
+            stride: 0,
+        },
+        RenderInstruction::SetClientState {
+            enabled: true,
+            array_type: PointerArrayType::TexCoord,
+        },
+        RenderInstruction::SetPointer {
+            vec_count: 3,
+            array_type: PointerArrayType::TexCoord,
+            item_type: GLDataType::F32,
+            data: Arc::new(unsafe { uvs.align_to().1.to_owned() }),
+            size: 2,
+            buffer: None,
+            offset: 0,
+            stride: 0,
+        },
+        RenderInstruction::DrawArrays {
+            mode: DrawMode::Tri,
+            first: 0,
+            count: 3,
+        },
+    ]);
+
+    let CommandQueue::Buffered(commands) = &asm.commands else {
+        panic!();
+    };
+
+    let Some(RenderCommand::BindDynamicGraphicsPipeline { pipeline, .. }) = commands
+        .iter()
+        .find(|cmd| matches!(cmd, RenderCommand::BindDynamicGraphicsPipeline { .. }))
+    else {
+        panic!("no pipeline was bound");
+    };
+
+    let Some(RenderCommand::Draw { data, .. }) = commands
+        .iter()
+        .find(|cmd| matches!(cmd, RenderCommand::Draw { .. }))
+    else {
+        panic!("nothing was drawn");
+    };
+
+    let layout = &pipeline.vertex_buffer;
+    let texcoord = layout.texcoord().unwrap();
+    let texindex = layout.texindex().unwrap();
+
+    assert_eq!(texcoord.data_type, GLDataType::F32);
+    assert_eq!(texindex.data_type, GLDataType::U16);
+    assert_eq!(texindex.num_elements, 2);
+
+    let read_f32 = |at: usize| f32::from_ne_bytes(data[at..at + 4].try_into().unwrap());
+
+    for vertex in 0..3 {
+        let start = vertex * layout.stride as usize;
+        let coord = start + texcoord.offset as usize;
+        let index = start + texindex.offset as usize;
+
+        // the texture matrix is applied before the uvs are written
+        assert_eq!(
+            [read_f32(coord), read_f32(coord + 4)],
+            [uvs[vertex * 2] + 0.25, uvs[vertex * 2 + 1]]
+        );
+        // there's no texture lookup without a gpu, so every vertex gets the first slot of the
+        // first array
+        assert_eq!(
+            [
+                u16::from_ne_bytes(data[index..index + 2].try_into().unwrap()),
+                u16::from_ne_bytes(data[index + 2..index + 4].try_into().unwrap()),
+            ],
+            [0, 0]
+        );
+    }
+}
+
+#[test]
+fn lightmap_texcoord_array_is_assembled_for_the_client_active_unit() {
+    use super::commands::RenderCommand;
+    use super::dynamic_shader::ShaderSpec;
+    use super::sandbox::MatrixMode;
+
+    let mut asm = RenderInsnAssembler::new(CommandQueue::Buffered(Vec::new()), None);
+
+    let pos = (0..3 * 3).map(|i| i as f32).collect::<Vec<_>>();
+    let uvs = [0.0f32, 0.0, 1.0, 0.0, 0.5, 1.0];
+    let lightmap_uvs = [0.125f32, 0.25, 0.5, 0.75, 1.0, 0.0];
+
+    let texcoord_pointer = |uvs: &[f32]| RenderInstruction::SetPointer {
+        vec_count: 3,
+        array_type: PointerArrayType::TexCoord,
+        item_type: GLDataType::F32,
+        data: Arc::new(unsafe { uvs.align_to().1.to_owned() }),
+        size: 2,
+        buffer: None,
+        offset: 0,
+        stride: 0,
+    };
+
+    asm.feed(&[
+        RenderInstruction::Enable(gl_constants::GL_TEXTURE_2D as i32),
+        RenderInstruction::BindTexture(5),
+        RenderInstruction::SetActiveTextureUnit(1),
+        RenderInstruction::Enable(gl_constants::GL_TEXTURE_2D as i32),
+        RenderInstruction::BindTexture(7),
+        RenderInstruction::MatrixMode(MatrixMode::Texture),
+        RenderInstruction::Translate {
+            delta: [0.0, 0.5, 0.0].into(),
+        },
+        RenderInstruction::SetClientState {
+            enabled: true,
+            array_type: PointerArrayType::Vertex,
+        },
+        RenderInstruction::SetPointer {
+            vec_count: 3,
+            array_type: PointerArrayType::Vertex,
+            item_type: GLDataType::F32,
+            data: Arc::new(unsafe { pos.align_to().1.to_owned() }),
+            size: 3,
+            buffer: None,
+            offset: 0,
+            stride: 0,
+        },
+        RenderInstruction::SetClientState {
+            enabled: true,
+            array_type: PointerArrayType::TexCoord,
+        },
+        texcoord_pointer(&uvs),
+        RenderInstruction::SetClientActiveTextureUnit(1),
+        RenderInstruction::SetClientState {
+            enabled: true,
+            array_type: PointerArrayType::TexCoord,
+        },
+        texcoord_pointer(&lightmap_uvs),
+        RenderInstruction::SetClientActiveTextureUnit(0),
+        RenderInstruction::DrawArrays {
+            mode: DrawMode::Tri,
+            first: 0,
+            count: 3,
+        },
+    ]);
+
+    let CommandQueue::Buffered(commands) = &asm.commands else {
+        panic!();
+    };
+
+    let Some(RenderCommand::BindDynamicGraphicsPipeline { pipeline, .. }) = commands
+        .iter()
+        .find(|cmd| matches!(cmd, RenderCommand::BindDynamicGraphicsPipeline { .. }))
+    else {
+        panic!("no pipeline was bound");
+    };
+
+    let Some(RenderCommand::Draw { data, .. }) = commands
+        .iter()
+        .find(|cmd| matches!(cmd, RenderCommand::Draw { .. }))
+    else {
+        panic!("nothing was drawn");
+    };
+
+    let layout = &pipeline.vertex_buffer;
+    let texcoord = layout.texcoord().unwrap();
+    let lightmap_texcoord = layout.lightmap_texcoord().unwrap();
+
+    assert_eq!(lightmap_texcoord.data_type, GLDataType::F32);
+    assert_eq!(lightmap_texcoord.num_elements, 2);
+
+    // the per-vertex lightmap texcoords replace the glMultiTexCoord push constant
+    assert!(ShaderSpec::from(pipeline)
+        .get_vertex_shader_code()
+        .contains("lightmap_texcoord_out = vec2(lightmap_texcoord_in);"));
+
+    let read_f32 = |at: usize| f32::from_ne_bytes(data[at..at + 4].try_into().unwrap());
+
+    for vertex in 0..3 {
+        let start = vertex * layout.stride as usize;
+        let coord = start + texcoord.offset as usize;
+        let lightmap_coord = start + lightmap_texcoord.offset as usize;
+
+        // the base unit's array and texture matrix are left alone
+        assert_eq!(
+            [read_f32(coord), read_f32(coord + 4)],
+            [uvs[vertex * 2], uvs[vertex * 2 + 1]]
+        );
+        // the lightmap goes through its own texture matrix
+        assert_eq!(
+            [read_f32(lightmap_coord), read_f32(lightmap_coord + 4)],
+            [lightmap_uvs[vertex * 2], lightmap_uvs[vertex * 2 + 1] + 0.5]
+        );
+    }
+}
+
+#[test]
+fn mat4_uniform_is_uploaded_to_its_binding() {
+    use super::commands::RenderCommand;
+    use super::shaders::programs::ProgramRegistry;
+    use super::shaders::programs::UserShaderStage;
+    use super::utils::TypedVec;
+
+    let mut programs = ProgramRegistry::default();
+
+    let vertex = programs.create_shader(UserShaderStage::Vertex);
+    let fragment = programs.create_shader(UserShaderStage::Fragment);
+
+    programs.shader_mut(vertex).unwrap().source = "#version 450
+layout(set = 0, binding = 1) uniform Matrices {
+    float time;
+    mat4 model;
+};
+layout(location = 0) in vec3 position;
+void main() {
+    gl_Position = model * vec4(position * time, 1.0);
+}
+"
+    .to_owned();
+    programs.shader_mut(fragment).unwrap().source = "#version 450
+layout(location = 0) out vec4 color;
+void main() {
+    color = vec4(1.0);
+}
+"
+    .to_owned();
+
+    assert!(programs.shader_mut(vertex).unwrap().compile());
+    assert!(programs.shader_mut(fragment).unwrap().compile());
+
+    let program = programs.create_program();
+    programs.attach_shader(program, vertex);
+    programs.attach_shader(program, fragment);
+    assert_eq!(programs.link_program(program), Some(true));
+
+    let linked = programs.program(program).unwrap().linked.clone().unwrap();
+
+    let location = linked.uniform_location("model").unwrap();
+    assert_eq!(linked.uniform_location("Matrices.model"), Some(location));
+    assert_eq!(linked.uniform_location("missing"), None);
+
+    let matrix = (0..16).map(|i| i as f32).collect::<Vec<_>>();
+    let pos = (0..3 * 3).map(|i| i as f32).collect::<Vec<_>>();
+
+    let mut asm = RenderInsnAssembler::new(CommandQueue::Buffered(Vec::new()), None);
+
+    asm.feed(&[
+        RenderInstruction::UseProgram(Some(linked)),
+        RenderInstruction::SetUniform(location, TypedVec::F32s(matrix.iter().copied().collect())),
+        RenderInstruction::SetClientState {
+            enabled: true,
+            array_type: PointerArrayType::Vertex,
+        },
+        RenderInstruction::SetPointer {
+            vec_count: 3,
+            array_type: PointerArrayType::Vertex,
+            item_type: GLDataType::F32,
+            data: Arc::new(unsafe { pos.align_to().1.to_owned() }),
+            size: 3,
+            buffer: None,
+            offset: 0,
+            stride: 0,
+        },
+        RenderInstruction::DrawArrays {
+            mode: DrawMode::Tri,
+            first: 0,
+            count: 3,
+        },
+    ]);
+
+    let CommandQueue::Buffered(commands) = &asm.commands else {
+        panic!();
+    };
+
+    let Some(RenderCommand::BindUniforms { set, buffers, .. }) = commands
+        .iter()
+        .find(|cmd| matches!(cmd, RenderCommand::BindUniforms { .. }))
+    else {
+        panic!("no uniform buffers were bound");
+    };
+
+    assert_eq!(*set, 0);
+    assert_eq!(buffers.len(), 1);
+
+    let (binding, data) = &buffers[0];
+    assert_eq!(*binding, 1);
+
+    // std140 puts the matrix on the next 16 byte boundary after the float
+    let uploaded = data[16..16 + 64]
+        .chunks(4)
+        .map(|c| f32::from_ne_bytes(c.try_into().unwrap()))
+        .collect::<Vec<_>>();
+    assert_eq!(uploaded, matrix);
+    assert_eq!(&data[0..4], &[0; 4]);
+}
+
+/// Compiles and links a program out of a vertex and fragment shader
+fn link_test_program(vertex: &str, fragment: &str) -> Arc<super::shaders::programs::LinkedProgram> {
+    use super::shaders::programs::ProgramRegistry;
+    use super::shaders::programs::UserShaderStage;
+
+    let mut programs = ProgramRegistry::default();
+
+    let program = programs.create_program();
+
+    for (stage, source) in [
+        (UserShaderStage::Vertex, vertex),
+        (UserShaderStage::Fragment, fragment),
+    ] {
+        let shader = programs.create_shader(stage);
+        programs.shader_mut(shader).unwrap().source = source.to_owned();
+
+        let compiled = programs.shader_mut(shader).unwrap().compile();
+        assert!(compiled, "{}", programs.shader(shader).unwrap().info_log);
+
+        programs.attach_shader(program, shader);
+    }
+
+    assert_eq!(programs.link_program(program), Some(true));
+
+    programs.program(program).unwrap().linked.clone().unwrap()
+}
+
+#[test]
+fn loose_uniforms_and_samplers_can_be_set() {
+    use super::commands::RenderCommand;
+    use super::shaders::programs::DEFAULT_UNIFORM_SET;
+    use super::utils::TypedVec;
+
+    let linked = link_test_program(
+        "#version 450
+uniform float time;
+uniform highp vec4 tint; // shared with the fragment shader
+layout(location = 0) in vec3 position;
+layout(location = 0) out vec4 color;
+void main() {
+    gl_Position = vec4(position * time, 1.0);
+    color = tint;
+}
+",
+        "#version 450
+uniform vec4 tint;
+layout(set = 1, binding = 0) uniform sampler2D tex;
+layout(location = 0) in vec4 color;
+layout(location = 0) out vec4 color_out;
+void main() {
+    color_out = color * tint * texture(tex, vec2(0.5));
+}
+",
+    );
+
+    let time = linked.uniform_location("time").unwrap();
+    let tint = linked.uniform_location("tint").unwrap();
+    let tex = linked.uniform_location("tex").unwrap();
+
+    let pos = (0..3 * 3).map(|i| i as f32).collect::<Vec<_>>();
+
+    let mut asm = RenderInsnAssembler::new(CommandQueue::Buffered(Vec::new()), None);
+
+    asm.feed(&[
+        RenderInstruction::UseProgram(Some(linked)),
+        RenderInstruction::SetUniform(time, TypedVec::F32s([2.0].into_iter().collect())),
+        RenderInstruction::SetUniform(
+            tint,
+            TypedVec::F32s([1.0, 2.0, 3.0, 4.0].into_iter().collect()),
+        ),
+        RenderInstruction::SetUniform(tex, TypedVec::I32s([2].into_iter().collect())),
+        RenderInstruction::SetActiveTextureUnit(2),
+        RenderInstruction::BindTexture(5),
+        RenderInstruction::SetClientState {
+            enabled: true,
+            array_type: PointerArrayType::Vertex,
+        },
+        RenderInstruction::SetPointer {
+            vec_count: 3,
+            array_type: PointerArrayType::Vertex,
+            item_type: GLDataType::F32,
+            data: Arc::new(unsafe { pos.align_to().1.to_owned() }),
+            size: 3,
+            buffer: None,
+            offset: 0,
+            stride: 0,
+        },
+        RenderInstruction::DrawArrays {
+            mode: DrawMode::Tri,
+            first: 0,
+            count: 3,
+        },
+    ]);
+
+    let CommandQueue::Buffered(commands) = &asm.commands else {
+        panic!();
+    };
+
+    let binds = commands
+        .iter()
+        .filter_map(|cmd| match cmd {
+            RenderCommand::BindUniforms {
+                set,
+                buffers,
+                textures,
+            } => Some((*set, buffers.clone(), textures.clone())),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    let (_, defaults, _) = binds
+        .iter()
+        .find(|(set, ..)| *set == DEFAULT_UNIFORM_SET)
+        .expect("the loose uniforms should be bound");
+
+    let read_f32s = |data: &[u8]| {
+        data.chunks_exact(4)
+            .map(|c| f32::from_ne_bytes(c.try_into().unwrap()))
+            .collect::<Vec<_>>()
+    };
+
+    // each stage has its own block, and tint is written to both
+    assert_eq!(defaults.len(), 2);
+    assert_eq!(
+        read_f32s(&defaults[0].1),
+        [2.0, 0.0, 0.0, 0.0, 1.0, 2.0, 3.0, 4.0]
+    );
+    assert_eq!(read_f32s(&defaults[1].1), [1.0, 2.0, 3.0, 4.0]);
+
+    // the sampler reads the texture bound to the unit glUniform1i picked
+    let (_, _, textures) = binds.iter().find(|(set, ..)| *set == 1).unwrap();
+    assert_eq!(textures, &[(0, Some(5))]);
+}
+
+#[test]
+fn uniform_blocks_follow_their_layout() {
+    use super::shaders::programs::UniformType;
+    use super::utils::TypedVec;
+
+    let linked = link_test_program(
+        "#version 450
+layout(set = 0, binding = 0, row_major) uniform Transforms {
+    mat2 rotation;
+    float weights[3];
+};
+layout(location = 0) in vec3 position;
+void main() {
+    gl_Position = vec4(rotation * position.xy * weights[2], 0.0, 1.0);
+}
+",
+        "#version 450
+layout(location = 0) out vec4 color;
+void main() {
+    color = vec4(1.0);
+}
+",
+    );
+
+    // the array can't be set, but the block still has room for it
+    let block = linked.uniform_blocks[0];
+    assert_eq!(block.size, 32 + 3 * 16);
+    assert!(linked.uniform_location("weights").is_none());
+
+    let location = linked.uniform_location("rotation").unwrap();
+    assert!(matches!(
+        linked.uniforms[location as usize].ty,
+        UniformType::Mat {
+            row_major: true,
+            ..
+        }
+    ));
+
+    let mut values = linked.new_uniform_values();
+
+    // glUniform* always gives columns, which row-major blocks store as rows
+    assert!(linked.write_uniform(
+        &mut values,
+        location,
+        &TypedVec::F32s([1.0, 2.0, 3.0, 4.0].into_iter().collect())
+    ));
+
+    let data = &values.buffers[&(0, 0)];
+    let read = |at: usize| f32::from_ne_bytes(data[at..at + 4].try_into().unwrap());
+
+    assert_eq!([read(0), read(4), read(16), read(20)], [1.0, 3.0, 2.0, 4.0]);
+
+    // too few values for the matrix
+    assert!(!linked.write_uniform(
+        &mut values,
+        location,
+        &TypedVec::F32s([1.0].into_iter().collect())
+    ));
+}
+
+/// Draws a quad out of 6 vertices through `indices`, returning the indexed draw
+fn draw_indexed_quad(index_type: GLDataType, indices: Vec<u8>) -> super::commands::RenderCommand {
+    use super::commands::RenderCommand;
+
+    let mut asm = RenderInsnAssembler::new(CommandQueue::Buffered(Vec::new()), None);
+
+    let pos = (0..6 * 3).map(|i| i as f32).collect::<Vec<_>>();
+
+    asm.feed(&[
+        RenderInstruction::SetClientState {
+            enabled: true,
+            array_type: PointerArrayType::Vertex,
+        },
+        RenderInstruction::SetPointer {
+            vec_count: 6,
+            array_type: PointerArrayType::Vertex,
+            item_type: GLDataType::F32,
+            data: Arc::new(unsafe { pos.align_to().1.to_owned() }),
+            size: 3,
+            buffer: None,
+            offset: 0,
+            stride: 0,
+        },
+        RenderInstruction::DrawElements {
+            mode: DrawMode::Tri,
+            count: 6,
+            index_type,
+            indices: Arc::new(indices),
+        },
+        // past the end of the vertex array, so it must be ignored
+        RenderInstruction::DrawElements {
+            mode: DrawMode::Tri,
+            count: 1,
+            index_type: GLDataType::U8,
+            indices: Arc::new(vec![6]),
+        },
+    ]);
+
+    let CommandQueue::Buffered(commands) = &mut asm.commands else {
+        panic!();
+    };
+
+    assert_eq!(commands.len(), 2);
+    assert!(matches!(
+        commands[0],
+        RenderCommand::BindDynamicGraphicsPipeline { .. }
+    ));
+
+    commands.pop().unwrap()
+}
+
+#[test]
+fn draw_elements_u16() {
+    use super::commands::RenderCommand;
+
+    let indices = [0u16, 1, 2, 2, 3, 0];
+
+    let RenderCommand::DrawIndexed {
+        index_count,
+        index_type,
+        data,
+        indices,
+    } = draw_indexed_quad(
+        GLDataType::U16,
+        indices.iter().flat_map(|i| i.to_ne_bytes()).collect(),
+    )
+    else {
+        panic!("expected an indexed draw");
+    };
+
+    assert_eq!(index_count, 6);
+    assert_eq!(index_type, GLDataType::U16);
+    assert_eq!(indices.len(), 12);
+
+    // only the vertices up to the highest index are assembled
+    assert_eq!(data.len(), 4 * 3 * 4);
+}
+
+#[test]
+fn draw_elements_u32() {
+    use super::commands::RenderCommand;
+
+    let indices = [5u32, 4, 3, 3, 2, 5];
+
+    let RenderCommand::DrawIndexed {
+        index_type,
+        data,
+        indices: uploaded,
+        ..
+    } = draw_indexed_quad(
+        GLDataType::U32,
+        indices.iter().flat_map(|i| i.to_ne_bytes()).collect(),
+    )
+    else {
+        panic!("expected an indexed draw");
+    };
+
+    assert_eq!(index_type, GLDataType::U32);
+    assert_eq!(data.len(), 6 * 3 * 4);
+
+    let uploaded = uploaded
+        .chunks_exact(4)
+        .map(|i| u32::from_ne_bytes(i.try_into().unwrap()))
+        .collect::<Vec<_>>();
+    assert_eq!(uploaded, indices);
+}
+
+#[test]
+fn blend_color_reaches_blend_constants() {
+    use vulkano::pipeline::graphics::color_blend::BlendFactor;
+
+    use super::commands::RenderCommand;
+    use super::dynamic_shader::gl_blend_factor;
+
+    assert_eq!(
+        gl_blend_factor(gl_constants::GL_CONSTANT_COLOR),
+        Some(BlendFactor::ConstantColor)
+    );
+    assert_eq!(
+        gl_blend_factor(gl_constants::GL_ONE_MINUS_CONSTANT_ALPHA),
+        Some(BlendFactor::OneMinusConstantAlpha)
+    );
+    assert_eq!(gl_blend_factor(gl_constants::GL_TEXTURE_2D), None);
+
+    let mut asm = RenderInsnAssembler::new(CommandQueue::Buffered(Vec::new()), None);
+
+    let pos = (0..3 * 3).map(|i| i as f32).collect::<Vec<_>>();
+
+    asm.feed(&[
+        RenderInstruction::SetClientState {
+            enabled: true,
+            array_type: PointerArrayType::Vertex,
+        },
+        RenderInstruction::SetPointer {
+            vec_count: 3,
+            array_type: PointerArrayType::Vertex,
+            item_type: GLDataType::F32,
+            data: Arc::new(unsafe { pos.align_to().1.to_owned() }),
+            size: 3,
+            buffer: None,
+            offset: 0,
+            stride: 0,
+        },
+        // the default blending doesn't read the constants
+        RenderInstruction::DrawArrays {
+            mode: DrawMode::Tri,
+            first: 0,
+            count: 3,
+        },
+    ]);
+
+    asm.feed(&[
+        RenderInstruction::Enable(gl_constants::GL_BLEND as i32),
+        RenderInstruction::BlendFunc {
+            src: gl_blend_factor(gl_constants::GL_CONSTANT_COLOR).unwrap(),
+            dst: BlendFactor::Zero,
+        },
+        RenderInstruction::BlendColor([0.25, 0.5, 0.75, 1.0].into()),
+        RenderInstruction::DrawArrays {
+            mode: DrawMode::Tri,
+            first: 0,
+            count: 3,
+        },
+    ]);
+
+    let CommandQueue::Buffered(commands) = &asm.commands else {
+        panic!();
+    };
+
+    let constants = commands
+        .iter()
+        .filter_map(|cmd| match cmd {
+            RenderCommand::SetBlendConstants(constants) => Some(*constants),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    assert_eq!(constants, vec![[0.25, 0.5, 0.75, 1.0]]);
+
+    // the constants are set after the pipeline which uses them is bound
+    let bind = commands
+        .iter()
+        .rposition(|cmd| matches!(cmd, RenderCommand::BindDynamicGraphicsPipeline { .. }))
+        .unwrap();
+    assert!(matches!(
+        commands[bind + 1],
+        RenderCommand::SetBlendConstants(_)
+    ));
+
+    let RenderCommand::BindDynamicGraphicsPipeline { pipeline, .. } = &commands[bind] else {
+        unreachable!();
+    };
+    assert!(pipeline.rasterization.uses_blend_constants());
+}
+
+#[test]
+fn lightmap_unit_swaps_texture_not_pipeline() {
+    use super::commands::RenderCommand;
+    use super::dynamic_shader::ColorMode;
+
+    let mut asm = RenderInsnAssembler::new(CommandQueue::Buffered(Vec::new()), None);
+
+    let pos = (0..3 * 3).map(|i| i as f32).collect::<Vec<_>>();
+    let uvs = [0.0f32, 0.0, 1.0, 0.0, 0.5, 1.0];
+
+    let draw = RenderInstruction::DrawArrays {
+        mode: DrawMode::Tri,
+        first: 0,
+        count: 3,
+    };
+
+    asm.feed(&[
+        RenderInstruction::Enable(gl_constants::GL_TEXTURE_2D as i32),
+        RenderInstruction::BindTexture(5),
+        RenderInstruction::SetClientState {
+            enabled: true,
+            array_type: PointerArrayType::Vertex,
+        },
+        RenderInstruction::SetPointer {
+            vec_count: 3,
+            array_type: PointerArrayType::Vertex,
+            item_type: GLDataType::F32,
+            data: Arc::new(unsafe { pos.align_to().1.to_owned() }),
+            size: 3,
+            buffer: None,
+            offset: 0,
+            stride: 0,
+        },
+        RenderInstruction::SetClientState {
+            enabled: true,
+            array_type: PointerArrayType::TexCoord,
+        },
+        RenderInstruction::SetPointer {
+            vec_count: 3,
+            array_type: PointerArrayType::TexCoord,
+            item_type: GLDataType::F32,
+            data: Arc::new(unsafe { uvs.align_to().1.to_owned() }),
+            size: 2,
+            buffer: None,
+            offset: 0,
+            stride: 0,
+        },
+        draw.clone(),
+        RenderInstruction::SetActiveTextureUnit(1),
+        RenderInstruction::Enable(gl_constants::GL_TEXTURE_2D as i32),
+        RenderInstruction::BindTexture(7),
+        RenderInstruction::MultiTexCoord {
+            unit: 1,
+            coord: [0.5, 0.25, 0.0, 1.0].into(),
+        },
+        draw,
+    ]);
+
+    // the enable is per unit
+    assert!(asm.is_enabled(gl_constants::GL_TEXTURE_2D));
+    asm.feed(&[RenderInstruction::SetActiveTextureUnit(2)]);
+    assert!(!asm.is_enabled(gl_constants::GL_TEXTURE_2D));
+
+    let CommandQueue::Buffered(commands) = &asm.commands else {
+        panic!();
+    };
+
+    let binds = commands
+        .iter()
+        .filter_map(|cmd| match cmd {
+            RenderCommand::BindDynamicGraphicsPipeline {
+                pipeline,
+                push_constants,
+                ..
+            } => Some((pipeline, push_constants)),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    let units = commands
+        .iter()
+        .filter_map(|cmd| match cmd {
+            RenderCommand::BindTextureUnits(units) => Some(*units),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    assert_eq!(binds.len(), 2);
+    assert!(matches!(binds[0].0.color, ColorMode::Texture { .. }));
+    assert_eq!(binds[0].0, binds[1].0);
+
+    // the disabled unit samples the dummy texture
+    assert_eq!(
+        units,
+        vec![[Some(5), None, None, None], [Some(5), Some(7), None, None]]
+    );
+
+    assert_eq!(binds[1].1.lightmap_texcoord, Some([0.5, 0.25].into()));
+}
+
+#[test]
+fn enabled_units_past_the_lightmap_are_combined() {
+    use super::commands::RenderCommand;
+    use super::dynamic_shader::ColorMode;
+    use super::sandbox::MatrixMode;
+    use super::sandbox::TextureEnvMode;
+
+    let mut asm = RenderInsnAssembler::new(CommandQueue::Buffered(Vec::new()), None);
+
+    let pos = (0..3 * 3).map(|i| i as f32).collect::<Vec<_>>();
+    let uvs = [0.0f32, 0.0, 1.0, 0.0, 0.5, 1.0];
+
+    asm.feed(&[
+        RenderInstruction::Enable(gl_constants::GL_TEXTURE_2D as i32),
+        RenderInstruction::BindTexture(5),
+        RenderInstruction::SetClientState {
+            enabled: true,
+            array_type: PointerArrayType::Vertex,
+        },
+        RenderInstruction::SetPointer {
+            vec_count: 3,
+            array_type: PointerArrayType::Vertex,
+            item_type: GLDataType::F32,
+            data: Arc::new(unsafe { pos.align_to().1.to_owned() }),
+            size: 3,
+            buffer: None,
+            offset: 0,
+            stride: 0,
+        },
+        RenderInstruction::SetClientState {
+            enabled: true,
+            array_type: PointerArrayType::TexCoord,
+        },
+        RenderInstruction::SetPointer {
+            vec_count: 3,
+            array_type: PointerArrayType::TexCoord,
+            item_type: GLDataType::F32,
+            data: Arc::new(unsafe { uvs.align_to().1.to_owned() }),
+            size: 2,
+            buffer: None,
+            offset: 0,
+            stride: 0,
+        },
+        RenderInstruction::SetActiveTextureUnit(2),
+        RenderInstruction::Enable(gl_constants::GL_TEXTURE_2D as i32),
+        RenderInstruction::BindTexture(9),
+        RenderInstruction::SetTextureEnvMode(TextureEnvMode::Add),
+        RenderInstruction::MultiTexCoord {
+            unit: 2,
+            coord: [0.5, 0.25, 0.0, 1.0].into(),
+        },
+        RenderInstruction::MatrixMode(MatrixMode::Texture),
+        RenderInstruction::Scale {
+            scale: [2.0, 4.0, 1.0].into(),
+        },
+        RenderInstruction::DrawArrays {
+            mode: DrawMode::Tri,
+            first: 0,
+            count: 3,
+        },
+    ]);
+
+    let CommandQueue::Buffered(commands) = &asm.commands else {
+        panic!();
+    };
+
+    let units = commands
+        .iter()
+        .find_map(|cmd| match cmd {
+            RenderCommand::BindDynamicGraphicsPipeline { pipeline, .. } => match pipeline.color {
+                ColorMode::Texture { units, .. } => Some(units),
+                _ => None,
+            },
+            _ => None,
+        })
+        .expect("the draw should be textured");
+
+    assert_eq!(units.unit_count(), 3);
+    assert_eq!(
+        units.modes(),
+        &[TextureEnvMode::Modulate, TextureEnvMode::Add]
+    );
+
+    // the disabled lightmap keeps its binding, and unit 2 takes the one after it
+    let textures = commands
+        .iter()
+        .find_map(|cmd| match cmd {
+            RenderCommand::BindTextureUnits(textures) => Some(*textures),
+            _ => None,
+        })
+        .unwrap();
+
+    assert_eq!(textures, [Some(5), None, Some(9), None]);
+
+    // unit 2 samples at its own texcoord, through its own texture matrix
+    let push_constants = commands
+        .iter()
+        .find_map(|cmd| match cmd {
+            RenderCommand::BindDynamicGraphicsPipeline { push_constants, .. } => {
+                Some(push_constants)
+            }
+            _ => None,
+        })
+        .unwrap();
+
+    assert_eq!(push_constants.unit_texcoords, vec![[1.0, 1.0].into()]);
+}
+
+#[test]
+fn translucent_blend_func_reaches_spec() {
+    use vulkano::pipeline::graphics::color_blend::AttachmentBlend;
+    use vulkano::pipeline::graphics::color_blend::BlendFactor;
+    use vulkano::pipeline::graphics::color_blend::BlendOp;
+
+    use super::commands::RenderCommand;
+    use super::dynamic_shader::gl_blend_factor;
+
+    let mut asm = RenderInsnAssembler::new(CommandQueue::Buffered(Vec::new()), None);
+
+    let pos = (0..3 * 3).map(|i| i as f32).collect::<Vec<_>>();
+
+    let draw = RenderInstruction::DrawArrays {
+        mode: DrawMode::Tri,
+        first: 0,
+        count: 3,
+    };
+
+    asm.feed(&[
+        RenderInstruction::SetClientState {
+            enabled: true,
+            array_type: PointerArrayType::Vertex,
+        },
+        RenderInstruction::SetPointer {
+            vec_count: 3,
+            array_type: PointerArrayType::Vertex,
+            item_type: GLDataType::F32,
+            data: Arc::new(unsafe { pos.align_to().1.to_owned() }),
+            size: 3,
+            buffer: None,
+            offset: 0,
+            stride: 0,
+        },
+        RenderInstruction::BlendFunc {
+            src: gl_blend_factor(gl_constants::GL_SRC_ALPHA).unwrap(),
+            dst: gl_blend_factor(gl_constants::GL_ONE_MINUS_SRC_ALPHA).unwrap(),
+        },
+        // the blend func is ignored until GL_BLEND is enabled
+        draw.clone(),
+        RenderInstruction::Enable(gl_constants::GL_BLEND as i32),
+        draw,
+    ]);
+
+    let CommandQueue::Buffered(commands) = &asm.commands else {
+        panic!();
+    };
+
+    let blending = commands
+        .iter()
+        .filter_map(|cmd| match cmd {
+            RenderCommand::BindDynamicGraphicsPipeline { pipeline, .. } => {
+                Some(pipeline.rasterization.color_blending.clone())
+            }
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        blending,
+        vec![
+            None,
+            Some(AttachmentBlend {
+                src_color_blend_factor: BlendFactor::SrcAlpha,
+                dst_color_blend_factor: BlendFactor::OneMinusSrcAlpha,
+                color_blend_op: BlendOp::Add,
+                src_alpha_blend_factor: BlendFactor::SrcAlpha,
+                dst_alpha_blend_factor: BlendFactor::OneMinusSrcAlpha,
+                alpha_blend_op: BlendOp::Add,
+            }),
+        ]
+    );
+}
+
+#[test]
+fn culling_follows_gl_cull_face() {
+    use vulkano::pipeline::graphics::rasterization::CullMode;
+    use vulkano::pipeline::graphics::rasterization::FrontFace;
+
+    use super::commands::RenderCommand;
+    use super::sandbox::CullFace;
+    use super::sandbox::Winding;
+
+    let mut asm = RenderInsnAssembler::new(CommandQueue::Buffered(Vec::new()), None);
+
+    let pos = (0..3 * 3).map(|i| i as f32).collect::<Vec<_>>();
+
+    let draw = RenderInstruction::DrawArrays {
+        mode: DrawMode::Tri,
+        first: 0,
+        count: 3,
+    };
+
+    asm.feed(&[
+        RenderInstruction::SetClientState {
+            enabled: true,
+            array_type: PointerArrayType::Vertex,
+        },
+        RenderInstruction::SetPointer {
+            vec_count: 3,
+            array_type: PointerArrayType::Vertex,
+            item_type: GLDataType::F32,
+            data: Arc::new(unsafe { pos.align_to().1.to_owned() }),
+            size: 3,
+            buffer: None,
+            offset: 0,
+            stride: 0,
+        },
+        // the face is ignored until GL_CULL_FACE is enabled
+        RenderInstruction::CullFace(CullFace::Front),
+        draw.clone(),
+        RenderInstruction::Enable(gl_constants::GL_CULL_FACE as i32),
+        RenderInstruction::FrontFace(Winding::Clockwise),
+        draw.clone(),
+        RenderInstruction::Disable(gl_constants::GL_CULL_FACE as i32),
+        draw,
+    ]);
+
+    assert_eq!(
+        asm.get_integer(gl_constants::GL_CULL_FACE_MODE),
+        Some(gl_constants::GL_FRONT as i32)
+    );
+    assert_eq!(
+        asm.get_integer(gl_constants::GL_FRONT_FACE),
+        Some(gl_constants::GL_CW as i32)
+    );
+
+    let CommandQueue::Buffered(commands) = &asm.commands else {
+        panic!();
+    };
+
+    let rasterization = commands
+        .iter()
+        .filter_map(|cmd| match cmd {
+            RenderCommand::BindDynamicGraphicsPipeline { raster_state, .. } => {
+                Some((raster_state.cull_mode, raster_state.front_face))
+            }
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        rasterization,
+        vec![
+            (CullMode::None, FrontFace::CounterClockwise),
+            (CullMode::Front, FrontFace::Clockwise),
+            (CullMode::None, FrontFace::Clockwise),
+        ]
+    );
+}
+
+#[test]
+fn gl_scissor_follows_scissor_test() {
+    use super::commands::RenderCommand;
+    use super::sandbox::ScissorRect;
+
+    let rect = ScissorRect {
+        x: 10,
+        y: 20,
+        width: 30,
+        height: 40,
+    };
+    let gui = ScissorRect {
+        x: 0,
+        y: 0,
+        width: 20,
+        height: 100,
+    };
+
+    let mut asm = RenderInsnAssembler::new(CommandQueue::Buffered(Vec::new()), None);
+
+    asm.feed(&[
+        // the rectangle is ignored until GL_SCISSOR_TEST is enabled
+        RenderInstruction::Scissor(rect),
+        RenderInstruction::Enable(gl_constants::GL_SCISSOR_TEST as i32),
+        RenderInstruction::PushScissor(gui),
+        RenderInstruction::PopScissor,
+        RenderInstruction::Disable(gl_constants::GL_SCISSOR_TEST as i32),
+    ]);
+
+    let CommandQueue::Buffered(commands) = &asm.commands else {
+        panic!();
+    };
+
+    let scissors = commands
+        .iter()
+        .filter_map(|cmd| match cmd {
+            RenderCommand::SetScissor(rect) => Some(*rect),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    let clipped = ScissorRect {
+        x: 10,
+        y: 20,
+        width: 10,
+        height: 40,
+    };
+
+    // disabling the test goes back to the whole framebuffer
+    assert_eq!(
+        scissors,
+        vec![None, Some(rect), Some(clipped), Some(rect), None]
+    );
+}
+
+#[test]
+fn cull_mode_reuses_one_pipeline() {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::Hash;
+    use std::hash::Hasher;
+
+    use vulkano::pipeline::graphics::rasterization::CullMode;
+
+    use super::commands::RenderCommand;
+
+    let mut asm = RenderInsnAssembler::new(CommandQueue::Buffered(Vec::new()), None);
+
+    let pos = (0..3 * 3).map(|i| i as f32).collect::<Vec<_>>();
+
+    let draw = RenderInstruction::DrawArrays {
+        mode: DrawMode::Tri,
+        first: 0,
+        count: 3,
+    };
+
+    asm.feed(&[
+        RenderInstruction::SetClientState {
+            enabled: true,
+            array_type: PointerArrayType::Vertex,
+        },
+        RenderInstruction::SetPointer {
+            vec_count: 3,
+            array_type: PointerArrayType::Vertex,
+            item_type: GLDataType::F32,
+            data: Arc::new(unsafe { pos.align_to().1.to_owned() }),
+            size: 3,
+            buffer: None,
+            offset: 0,
+            stride: 0,
+        },
+        draw.clone(),
+        RenderInstruction::Enable(gl_constants::GL_CULL_FACE as i32),
+        draw,
+    ]);
+
+    let CommandQueue::Buffered(commands) = &asm.commands else {
+        panic!();
+    };
+
+    let specs = commands
+        .iter()
+        .filter_map(|cmd| match cmd {
+            RenderCommand::BindDynamicGraphicsPipeline { pipeline, .. } => Some(pipeline),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    let cull_modes = commands
+        .iter()
+        .filter_map(|cmd| match cmd {
+            RenderCommand::BindDynamicGraphicsPipeline { raster_state, .. } => {
+                Some(raster_state.cull_mode)
+            }
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    let hash_of = |spec| {
+        let mut hasher = DefaultHasher::new();
+        Hash::hash(spec, &mut hasher);
+        hasher.finish()
+    };
+
+    assert_eq!(specs[0], specs[1]);
+    assert_eq!(hash_of(specs[0]), hash_of(specs[1]));
+    assert_eq!(cull_modes, vec![CullMode::None, CullMode::Back]);
+}
+
+#[test]
+fn gl_viewport_flips_y() {
+    use super::commands::viewport_to_vulkan;
+    use super::commands::RenderCommand;
+    use super::commands::ViewportLimits;
+
+    let mut asm = RenderInsnAssembler::new(CommandQueue::Buffered(Vec::new()), None);
+
+    asm.feed(&[RenderInstruction::Viewport {
+        index: None,
+        x: 10,
+        y: 20,
+        width: 100,
+        height: 50,
+    }]);
+
+    let CommandQueue::Buffered(commands) = &asm.commands else {
+        unreachable!()
+    };
+
+    assert!(matches!(
+        commands.as_slice(),
+        [RenderCommand::SetViewport {
+            index: None,
+            x: 10,
+            y: 20,
+            width: 100,
+            height: 50
+        }]
+    ));
+
+    let limits = ViewportLimits {
+        max_dimensions: [4096, 4096],
+        bounds_range: [-8192.0, 8191.0],
+    };
+
+    // gl's origin is the bottom left, vulkan's is the top left, so the viewport starts at its
+    // bottom edge and has a negative height
+    let viewport = viewport_to_vulkan(10, 20, 100, 50, [200, 100], &limits);
+    assert_eq!(viewport.offset, [10.0, 80.0]);
+    assert_eq!(viewport.extent, [100.0, -50.0]);
+
+    // the whole framebuffer maps onto itself, upside down
+    let viewport = viewport_to_vulkan(0, 0, 200, 100, [200, 100], &limits);
+    assert_eq!(viewport.offset, [0.0, 100.0]);
+    assert_eq!(viewport.extent, [200.0, -100.0]);
+
+    // viewports aren't clipped, so one that's taller than the framebuffer ends above it
+    let viewport = viewport_to_vulkan(0, 0, 200, 150, [200, 100], &limits);
+    assert_eq!(viewport.offset[1] + viewport.extent[1], -50.0);
+
+    // vulkan has no empty viewports, and oversized ones are clamped to the device's limits
+    let viewport = viewport_to_vulkan(0, 0, 0, 0, [200, 100], &limits);
+    assert_eq!(viewport.extent, [1.0, -1.0]);
+
+    let viewport = viewport_to_vulkan(9000, 0, 10000, 100, [200, 100], &limits);
+    assert_eq!(viewport.extent, [4096.0, -100.0]);
+    assert_eq!(viewport.offset[0] + viewport.extent[0], 8191.0);
+}
+
+#[test]
+fn indexed_viewports_keep_their_own_depth_range() {
+    use super::commands::RenderCommand;
+    use super::commands::ViewportArray;
+    use super::commands::ViewportLimits;
+
+    let mut asm = RenderInsnAssembler::new(CommandQueue::Buffered(Vec::new()), None);
+
+    asm.feed(&[
+        RenderInstruction::DepthRange {
+            index: Some(0),
+            near: 0.0,
+            far: 0.5,
+        },
+        RenderInstruction::DepthRange {
+            index: Some(1),
+            near: 0.5,
+            far: 1.0,
+        },
+    ]);
+
+    let CommandQueue::Buffered(commands) = &asm.commands else {
+        unreachable!()
+    };
+
+    let limits = ViewportLimits {
+        max_dimensions: [4096, 4096],
+        bounds_range: [-8192.0, 8191.0],
+    };
+    let mut viewports = ViewportArray::new(2, [200, 100], limits);
+
+    for command in commands {
+        let RenderCommand::SetDepthRange { index, near, far } = command else {
+            panic!("expected a depth range, got {command:?}");
+        };
+
+        assert_eq!(
+            viewports.set_depth_range(*index, *near, *far),
+            Some(index.unwrap() as usize..index.unwrap() as usize + 1)
+        );
+    }
+
+    assert_eq!(viewports.get(0).unwrap().depth_range, [0.0, 0.5]);
+    assert_eq!(viewports.get(1).unwrap().depth_range, [0.5, 1.0]);
+
+    let vulkan = viewports.to_vulkan(0..2, [200, 100]);
+    assert_eq!(vulkan[0].depth_range, 0.0..=0.5);
+    assert_eq!(vulkan[1].depth_range, 0.5..=1.0);
+
+    // glDepthRange sets every viewport, and indices past the last one are rejected
+    assert_eq!(viewports.set_depth_range(None, 0.25, 0.75), Some(0..2));
+    assert_eq!(viewports.get(1).unwrap().depth_range, [0.25, 0.75]);
+    assert_eq!(viewports.set_depth_range(Some(2), 0.0, 1.0), None);
+}
+
+#[test]
+fn alpha_func_only_applies_with_alpha_test() {
+    use vulkano::pipeline::graphics::depth_stencil::CompareOp;
+
+    let mut asm = RenderInsnAssembler::new(CommandQueue::Buffered(Vec::new()), None);
+
+    asm.feed(&[RenderInstruction::AlphaFunc {
+        func: CompareOp::Greater,
+        ref_value: 1.5,
+    }]);
+
+    assert_eq!(asm.alpha_test(), None);
+
+    asm.feed(&[RenderInstruction::Enable(gl_constants::GL_ALPHA_TEST as i32)]);
+
+    // the reference value is clamped like in GL
+    let test = asm.alpha_test().unwrap();
+    assert_eq!(test.func, CompareOp::Greater);
+    assert_eq!(test.ref_value, 1.0);
+
+    // a test that always passes doesn't need its own shader
+    asm.feed(&[RenderInstruction::AlphaFunc {
+        func: CompareOp::Always,
+        ref_value: 0.5,
+    }]);
+
+    assert_eq!(asm.alpha_test(), None);
+}
+
+#[test]
+fn scissored_clear_only_covers_the_scissor() {
+    use super::commands::clear_rect;
+    use super::commands::RenderCommand;
+    use super::sandbox::ScissorRect;
+
+    let rect = ScissorRect {
+        x: 10,
+        y: 20,
+        width: 30,
+        height: 40,
+    };
+
+    let mut asm = RenderInsnAssembler::new(CommandQueue::Buffered(Vec::new()), None);
+
+    asm.feed(&[
+        RenderInstruction::Scissor(rect),
+        RenderInstruction::ClearDepth,
+        RenderInstruction::Enable(gl_constants::GL_SCISSOR_TEST as i32),
+        RenderInstruction::ClearColour,
+        RenderInstruction::ClearDepth,
+    ]);
+
+    let CommandQueue::Buffered(commands) = &asm.commands else {
+        unreachable!()
+    };
+
+    let clears = commands
+        .iter()
+        .filter_map(|cmd| match cmd {
+            RenderCommand::ClearColour(region) | RenderCommand::ClearDepth(region) => {
+                Some(*region)
+            }
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    // the rectangle is ignored until GL_SCISSOR_TEST is enabled
+    assert_eq!(clears, [None, Some(rect), Some(rect)]);
+
+    let full = clear_rect(None, [200, 100]);
+    assert_eq!((full.offset, full.extent), ([0, 0], [200, 100]));
+
+    // gl's origin is the bottom left, vulkan's is the top left
+    let scissored = clear_rect(Some(rect), [200, 100]);
+    assert_eq!((scissored.offset, scissored.extent), ([10, 40], [30, 40]));
+    assert_eq!(scissored.array_layers, 0..1);
+}
+
+#[test]
+fn lit_draws_get_a_normal_matrix() {
+    use nalgebra_glm::vec3;
+    use nalgebra_glm::TMat3;
+
+    use super::commands::RenderCommand;
+    use super::dynamic_shader::ShaderMatrixMode;
+
+    let mut asm = RenderInsnAssembler::new(CommandQueue::Buffered(Vec::new()), None);
+
+    let pos = (0..3 * 3).map(|i| i as f32).collect::<Vec<_>>();
+    let normals = [0.0f32, 0.0, 1.0].repeat(3);
+
+    let draw = RenderInstruction::DrawArrays {
+        mode: DrawMode::Tri,
+        first: 0,
+        count: 3,
+    };
+
+    asm.feed(&[
+        RenderInstruction::SetClientState {
+            enabled: true,
+            array_type: PointerArrayType::Vertex,
+        },
+        RenderInstruction::SetPointer {
+            vec_count: 3,
+            array_type: PointerArrayType::Vertex,
+            item_type: GLDataType::F32,
+            data: Arc::new(unsafe { pos.align_to().1.to_owned() }),
+            size: 3,
+            buffer: None,
+            offset: 0,
+            stride: 0,
+        },
+        RenderInstruction::SetClientState {
+            enabled: true,
+            array_type: PointerArrayType::Normal,
+        },
+        RenderInstruction::SetPointer {
+            vec_count: 3,
+            array_type: PointerArrayType::Normal,
+            item_type: GLDataType::F32,
+            data: Arc::new(unsafe { normals.align_to().1.to_owned() }),
+            size: 3,
+            buffer: None,
+            offset: 0,
+            stride: 0,
+        },
+        RenderInstruction::Scale {
+            scale: vec3(2.0, 1.0, 1.0),
+        },
+        // unlit draws keep the plain mvp
+        draw.clone(),
+        RenderInstruction::Enable(gl_constants::GL_LIGHTING as i32),
+        draw,
+    ]);
+
+    let CommandQueue::Buffered(commands) = &asm.commands else {
+        unreachable!()
+    };
+
+    let binds = commands
+        .iter()
+        .filter_map(|cmd| match cmd {
+            RenderCommand::BindDynamicGraphicsPipeline {
+                pipeline,
+                push_constants,
+                ..
+            } => Some((pipeline, push_constants)),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    let [(unlit, unlit_constants), (lit, lit_constants)] = binds[..] else {
+        panic!("expected two binds, got {commands:?}");
+    };
+
+    assert_eq!(
+        unlit.matrix,
+        ShaderMatrixMode::MVP(super::dynamic_shader::DataSource::PushConstant)
+    );
+    assert_eq!(unlit_constants.normal_matrix, None);
+
+    assert_eq!(lit.matrix, ShaderMatrixMode::MVPNormal);
+
+    // the inverse transpose of a non-uniform scale
+    let normal_matrix = lit_constants.normal_matrix.unwrap();
+    assert_ne!(normal_matrix, TMat3::identity());
+    assert!((normal_matrix[(0, 0)] - 0.5).abs() < 1e-6);
+    assert_eq!(normal_matrix[(1, 1)], 1.0);
+}
+
+#[test]
+fn draw_buffers_are_recycled() {
+    use super::commands::RenderCommand;
+
+    let mut asm = RenderInsnAssembler::new(CommandQueue::Buffered(Vec::new()), None);
+
+    let pos = (0..3 * 3).map(|i| i as f32).collect::<Vec<_>>();
+
+    asm.feed(&[
+        RenderInstruction::SetClientState {
+            enabled: true,
+            array_type: PointerArrayType::Vertex,
+        },
+        RenderInstruction::SetPointer {
+            vec_count: 3,
+            array_type: PointerArrayType::Vertex,
+            item_type: GLDataType::F32,
+            data: Arc::new(unsafe { pos.align_to().1.to_owned() }),
+            size: 3,
+            buffer: None,
+            offset: 0,
+            stride: 0,
+        },
+    ]);
+
+    let arena = asm.arena.clone();
+
+    // records the draws like the recorder does, handing their buffers back
+    let record_frame = |asm: &mut RenderInsnAssembler| {
+        for _ in 0..4 {
+            asm.draw_arrays(DrawMode::Tri, 0, 3);
+        }
+
+        let CommandQueue::Buffered(commands) = &mut asm.commands else {
+            unreachable!()
+        };
+
+        for cmd in commands.drain(..) {
+            if let RenderCommand::Draw { data, .. } = cmd {
+                arena.recycle(data);
+            }
+        }
+
+        arena.end_frame();
+        arena.last_frame_stats()
+    };
+
+    // the buffers only come back once the whole frame has been recorded
+    let first = record_frame(&mut asm);
+    assert_eq!((first.taken, first.allocations), (4, 4));
+
+    let second = record_frame(&mut asm);
+    assert_eq!((second.taken, second.allocations), (4, 0));
+
+    // a buffer that's still referenced can't be reused
+    let held = Arc::new(arena.take(36));
+    let _reader = held.clone();
+    let free = arena.free_buffers();
+    arena.recycle(held);
+    assert_eq!(arena.free_buffers(), free);
+}
+
+#[test]
+fn gl_multisample_doesnt_fork_pipelines() {
+    use vulkano::image::SampleCount;
+
+    use super::commands::RenderCommand;
+
+    let mut asm = RenderInsnAssembler::new(CommandQueue::Buffered(Vec::new()), None);
+    asm.framebuffer_samples = SampleCount::Sample4;
+
+    let pos = (0..3 * 3).map(|i| i as f32).collect::<Vec<_>>();
+
+    let draw = RenderInstruction::DrawArrays {
+        mode: DrawMode::Tri,
+        first: 0,
+        count: 3,
+    };
+
+    // GL_MULTISAMPLE starts enabled
+    assert_eq!(asm.get_boolean(gl_constants::GL_MULTISAMPLE), Some(true));
+
+    asm.feed(&[
+        RenderInstruction::SetClientState {
+            enabled: true,
+            array_type: PointerArrayType::Vertex,
+        },
+        RenderInstruction::SetPointer {
+            vec_count: 3,
+            array_type: PointerArrayType::Vertex,
+            item_type: GLDataType::F32,
+            data: Arc::new(unsafe { pos.align_to().1.to_owned() }),
+            size: 3,
+            buffer: None,
+            offset: 0,
+            stride: 0,
+        },
+        draw.clone(),
+        RenderInstruction::Disable(gl_constants::GL_MULTISAMPLE as i32),
+        draw.clone(),
+        RenderInstruction::Enable(gl_constants::GL_MULTISAMPLE as i32),
+        draw,
+    ]);
+
+    let CommandQueue::Buffered(commands) = &asm.commands else {
+        panic!();
+    };
+
+    let pipelines = commands
+        .iter()
+        .filter_map(|cmd| match cmd {
+            RenderCommand::BindDynamicGraphicsPipeline { pipeline, .. } => Some(pipeline),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    // every draw is rasterized at the render pass' sample count, so they all share one pipeline
+    assert_eq!(pipelines.len(), 3);
+    assert!(pipelines.iter().all(|pipeline| *pipeline == pipelines[0]));
+
+    assert_eq!(asm.get_boolean(gl_constants::GL_MULTISAMPLE), Some(true));
+}
+
+#[test]
+fn texture_units_past_the_device_limit_are_rejected() {
+    let mut asm = RenderInsnAssembler::new(CommandQueue::Buffered(Vec::new()), None);
+    asm.max_texture_units = 2;
+
+    assert_eq!(
+        asm.get_integer(gl_constants::GL_MAX_TEXTURE_IMAGE_UNITS),
+        Some(2)
+    );
+
+    // the base unit stays active, so the enable lands on it
+    asm.feed(&[
+        RenderInstruction::SetActiveTextureUnit(3),
+        RenderInstruction::Enable(gl_constants::GL_TEXTURE_2D as i32),
+    ]);
+
+    assert!(asm.is_enabled(gl_constants::GL_TEXTURE_2D));
+
+    asm.feed(&[RenderInstruction::SetActiveTextureUnit(1)]);
+    assert!(!asm.is_enabled(gl_constants::GL_TEXTURE_2D));
+}
+
+#[test]
+fn draws_carry_the_top_name() {
+    use super::commands::RenderCommand;
+
+    let mut asm = RenderInsnAssembler::new(CommandQueue::Buffered(Vec::new()), None);
+    asm.object_picking = true;
+
+    let pos = (0..3 * 3).map(|i| i as f32).collect::<Vec<_>>();
+
+    let draw = RenderInstruction::DrawArrays {
+        mode: DrawMode::Tri,
+        first: 0,
+        count: 3,
+    };
+
+    asm.feed(&[
+        RenderInstruction::SetClientState {
+            enabled: true,
+            array_type: PointerArrayType::Vertex,
+        },
+        RenderInstruction::SetPointer {
+            vec_count: 3,
+            array_type: PointerArrayType::Vertex,
+            item_type: GLDataType::F32,
+            data: Arc::new(unsafe { pos.align_to().1.to_owned() }),
+            size: 3,
+            buffer: None,
+            offset: 0,
+            stride: 0,
+        },
+        draw.clone(),
+        // glLoadName needs something on the stack to replace
+        RenderInstruction::LoadName(3),
+        draw.clone(),
+        RenderInstruction::InitNames,
+        RenderInstruction::PushName(7),
+        draw.clone(),
+        RenderInstruction::PushName(8),
+        RenderInstruction::LoadName(9),
+        draw.clone(),
+        RenderInstruction::PopName,
+        draw.clone(),
+    ]);
+
+    assert_eq!(asm.get_integer(gl_constants::GL_NAME_STACK_DEPTH), Some(1));
+
+    let CommandQueue::Buffered(commands) = &asm.commands else {
+        panic!();
+    };
+
+    let ids = commands
+        .iter()
+        .filter_map(|cmd| match cmd {
+            RenderCommand::BindDynamicGraphicsPipeline { push_constants, .. } => {
+                push_constants.object_id
+            }
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    assert_eq!(ids, vec![0, 0, 7, 9, 7]);
+
+    // without an attachment to write them into, draws don't carry names
+    asm.object_picking = false;
+    asm.feed(&[draw]);
+
+    let CommandQueue::Buffered(commands) = &asm.commands else {
+        panic!();
+    };
+
+    let last = commands.iter().rev().find_map(|cmd| match cmd {
+        RenderCommand::BindDynamicGraphicsPipeline {
+            pipeline,
+            push_constants,
+            ..
+        } => Some((pipeline.object_ids, push_constants.object_id)),
+        _ => None,
+    });
+
+    assert_eq!(last, Some((false, None)));
+}
+
+#[test]
+fn jni_draw_reaches_the_command_queue() {
+    use super::commands::RenderCommand;
+
+    take_sandbox();
+    put_sandbox(RenderSandbox::Assembler(Box::new(
+        RenderInsnAssembler::new(CommandQueue::Buffered(Vec::new()), None),
+    )));
+
+    let pos = (0..3 * 3).map(|i| i as f32).collect::<Vec<_>>();
+    let bytes = unsafe { pos.align_to::<u8>().1 };
+
+    unsafe {
+        client_arrays::Java_com_recursive_1pineapple_mcvk_rendering_RenderSandbox_glEnableClientState(
+            env(),
+            class(),
+            PointerArrayType::Vertex.to_i32().unwrap(),
+        );
+
+        client_arrays::Java_com_recursive_1pineapple_mcvk_rendering_RenderSandbox_addPointerArray(
+            env(),
+            class(),
+            3,
+            0,
+            PointerArrayType::Vertex.to_i32().unwrap(),
+            GLDataType::F32.to_i32().unwrap(),
+            bytes.as_ptr(),
+            bytes.len() as i32,
+        );
+
+        client_arrays::Java_com_recursive_1pineapple_mcvk_rendering_RenderSandbox_glDrawArrays(
+            env(),
+            class(),
+            DrawMode::Tri.to_i32().unwrap(),
+            0,
+            3,
+        );
+    }
+
+    let Some(RenderSandbox::Assembler(asm)) = take_sandbox() else {
+        panic!("the assembler should still be installed");
+    };
+
+    let CommandQueue::Buffered(commands) = &asm.commands else {
+        unreachable!()
+    };
+
+    let draws = commands
+        .iter()
+        .filter(|cmd| {
+            matches!(
+                cmd,
+                RenderCommand::BindDynamicGraphicsPipeline { .. } | RenderCommand::Draw { .. }
+            )
+        })
+        .collect::<Vec<_>>();
+
+    assert!(
+        matches!(
+            draws.as_slice(),
+            [
+                RenderCommand::BindDynamicGraphicsPipeline { .. },
+                RenderCommand::Draw {
+                    start_vertex: 0,
+                    vertex_count: 3,
+                    ..
+                }
+            ]
+        ),
+        "{commands:?}"
+    );
+}
+
+#[test]
+fn settings_reach_the_assembler_while_a_list_is_compiled() {
+    let mut sandbox = RenderSandbox::Assembler(Box::new(RenderInsnAssembler::new(
+        CommandQueue::Buffered(Vec::new()),
+        None,
+    )));
+
+    assert!(sandbox.begin_list(1, false));
+
+    sandbox.assembler_mut().unwrap().object_picking = true;
+
+    assert!(sandbox.end_list().is_some());
+
+    let RenderSandbox::Assembler(asm) = sandbox else {
+        panic!("glEndList should restore the assembler");
+    };
+
+    assert!(asm.object_picking);
+    assert!(RenderSandbox::None.assembler_mut().is_none());
+}
+
+#[test]
+fn clip_planes_fit_the_device() {
+    use super::commands::RenderCommand;
+    use super::dynamic_shader::DataSource;
+
+    let pos = (0..3 * 3).map(|i| i as f32).collect::<Vec<_>>();
+
+    let mut insns = vec![
+        RenderInstruction::SetClientState {
+            enabled: true,
+            array_type: PointerArrayType::Vertex,
+        },
+        RenderInstruction::SetPointer {
+            vec_count: 3,
+            array_type: PointerArrayType::Vertex,
+            item_type: GLDataType::F32,
+            data: Arc::new(unsafe { pos.align_to().1.to_owned() }),
+            size: 3,
+            buffer: None,
+            offset: 0,
+            stride: 0,
+        },
+    ];
+
+    // every plane at once is more than the 128 push constant bytes every device has
+    insns.extend(
+        (0..6)
+            .map(|plane| RenderInstruction::Enable((gl_constants::GL_CLIP_PLANE0 + plane) as i32)),
+    );
+
+    insns.push(RenderInstruction::DrawArrays {
+        mode: DrawMode::Tri,
+        first: 0,
+        count: 3,
+    });
+
+    let bound_pipeline = |commands: &[RenderCommand]| {
+        commands
+            .iter()
+            .find_map(|cmd| match cmd {
+                RenderCommand::BindDynamicGraphicsPipeline {
+                    pipeline,
+                    push_constants,
+                    ..
+                } => Some((pipeline.clone(), push_constants.clone())),
+                _ => None,
+            })
+            .expect("the draw should bind a pipeline")
+    };
+
+    let draw = |supports_clip_distance| {
+        let mut asm = RenderInsnAssembler::new(CommandQueue::Buffered(Vec::new()), None);
+        asm.supports_clip_distance = supports_clip_distance;
+
+        asm.feed(&insns);
+
+        let CommandQueue::Buffered(commands) = asm.commands else {
+            panic!();
+        };
+
+        commands
+    };
+
+    let commands = draw(true);
+
+    let (pipeline, push_constants) = bound_pipeline(&commands);
+
+    assert_eq!(pipeline.clip_planes, 6);
+    assert_eq!(
+        pipeline.transforms,
+        DataSource::Uniform { set: 0, binding: 0 }
+    );
+    assert_eq!(push_constants.mvp, None);
+    assert!(push_constants.clip_planes.is_empty());
+
+    // the mvp and the planes go into the uniform buffer instead
+    assert!(commands.iter().any(|cmd| matches!(
+        cmd,
+        RenderCommand::BindUniforms { set: 0, buffers, .. }
+            if buffers.len() == 1 && buffers[0].1.len() == 64 + 6 * 16
+    )));
+
+    // without clip distances the planes are dropped, which leaves the push constants small enough
+    let commands = draw(false);
+
+    let (pipeline, push_constants) = bound_pipeline(&commands);
+
+    assert_eq!(pipeline.clip_planes, 0);
+    assert_eq!(pipeline.transforms, DataSource::PushConstant);
+    assert!(push_constants.mvp.is_some());
+    assert!(!commands
+        .iter()
+        .any(|cmd| matches!(cmd, RenderCommand::BindUniforms { .. })));
+}
@@ -4,16 +4,31 @@ use std::{
     hint::spin_loop,
     ops::{Deref, DerefMut},
     sync::{
-        atomic::{AtomicBool, Ordering},
+        atomic::{AtomicBool, AtomicU64, Ordering},
         Arc,
     },
+    time::{Duration, Instant},
 };
 
 pub struct SpinLock<T> {
     locked: AtomicBool,
+    /// The [`thread_tag`] of the thread holding the lock, or 0. Only tracked in debug builds, to
+    /// catch a thread locking something it already holds, which would spin forever.
+    owner: AtomicU64,
     data: UnsafeCell<T>,
 }
 
+/// A unique non-zero id for the current thread. ThreadId can't be stored in an atomic.
+fn thread_tag() -> u64 {
+    static NEXT_TAG: AtomicU64 = AtomicU64::new(1);
+
+    thread_local! {
+        static TAG: u64 = NEXT_TAG.fetch_add(1, Ordering::Relaxed);
+    }
+
+    TAG.with(|tag| *tag)
+}
+
 pub struct SpinGuard<'a, T> {
     lock: &'a SpinLock<T>,
 }
@@ -22,15 +37,60 @@ impl<T> SpinLock<T> {
     pub fn new(value: T) -> Self {
         Self {
             locked: AtomicBool::new(false),
+            owner: AtomicU64::new(0),
             data: UnsafeCell::new(value),
         }
     }
 
     pub fn lock<'a>(&'a self) -> SpinGuard<'a, T> {
-        while self.locked.swap(true, Ordering::Acquire) {
+        loop {
+            if let Some(guard) = self.try_lock() {
+                return guard;
+            }
+
+            self.assert_not_reentrant();
+            spin_loop();
+        }
+    }
+
+    /// Returns None instead of spinning if the lock is held
+    pub fn try_lock<'a>(&'a self) -> Option<SpinGuard<'a, T>> {
+        if self.locked.swap(true, Ordering::Acquire) {
+            return None;
+        }
+
+        if cfg!(debug_assertions) {
+            self.owner.store(thread_tag(), Ordering::Relaxed);
+        }
+
+        Some(SpinGuard { lock: self })
+    }
+
+    /// Spins for at most `timeout`, then gives up and returns None
+    pub fn lock_timeout<'a>(&'a self, timeout: Duration) -> Option<SpinGuard<'a, T>> {
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            if let Some(guard) = self.try_lock() {
+                return Some(guard);
+            }
+
+            self.assert_not_reentrant();
+
+            if Instant::now() >= deadline {
+                return None;
+            }
+
             spin_loop();
         }
-        SpinGuard { lock: self }
+    }
+
+    fn assert_not_reentrant(&self) {
+        debug_assert_ne!(
+            self.owner.load(Ordering::Relaxed),
+            thread_tag(),
+            "a SpinLock was locked again by the thread holding it"
+        );
     }
 }
 
@@ -52,6 +112,10 @@ impl<T> DerefMut for SpinGuard<'_, T> {
 
 impl<T> Drop for SpinGuard<'_, T> {
     fn drop(&mut self) {
+        if cfg!(debug_assertions) {
+            self.lock.owner.store(0, Ordering::Relaxed);
+        }
+
         self.lock.locked.store(false, Ordering::Release);
     }
 }
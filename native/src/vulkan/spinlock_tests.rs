@@ -0,0 +1,60 @@
+use std::sync::Arc;
+use std::sync::Barrier;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+use crate::vulkan::spinlock::SpinLock;
+
+#[test]
+fn contended_try_lock_fails() {
+    let lock = SpinLock::new(1);
+
+    let guard = lock.try_lock().unwrap();
+    assert_eq!(*guard, 1);
+
+    assert!(lock.try_lock().is_none());
+
+    drop(guard);
+
+    *lock.try_lock().unwrap() = 2;
+    assert_eq!(*lock.lock(), 2);
+}
+
+#[test]
+fn lock_timeout_gives_up() {
+    let lock = Arc::new(SpinLock::new(()));
+    let held = Arc::new(Barrier::new(2));
+    let release = Arc::new(Barrier::new(2));
+
+    let holder = {
+        let (lock, held, release) = (lock.clone(), held.clone(), release.clone());
+
+        thread::spawn(move || {
+            let _guard = lock.lock();
+            held.wait();
+            release.wait();
+        })
+    };
+
+    held.wait();
+
+    let start = Instant::now();
+    assert!(lock.lock_timeout(Duration::from_millis(20)).is_none());
+    assert!(start.elapsed() >= Duration::from_millis(20));
+
+    release.wait();
+    holder.join().unwrap();
+
+    assert!(lock.lock_timeout(Duration::from_millis(20)).is_some());
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic(expected = "locked again")]
+fn reentrant_lock_is_caught() {
+    let lock = SpinLock::new(());
+
+    let _guard = lock.lock();
+    let _again = lock.lock();
+}
@@ -5,19 +5,24 @@ use nalgebra_glm::TMat4;
 use std::sync::Arc;
 use std::time::Duration;
 use tracing::debug;
+use vulkano::format::ClearValue;
 use vulkano::format::Format;
 use vulkano::image::view::ImageView;
 use vulkano::image::view::ImageViewCreateInfo;
 use vulkano::image::view::ImageViewType;
 use vulkano::image::Image;
+use vulkano::image::ImageAspects;
 use vulkano::image::ImageCreateInfo;
 use vulkano::image::ImageLayout;
 use vulkano::image::ImageUsage;
+use vulkano::image::SampleCount;
+use vulkano::image::SampleCounts;
 use vulkano::pipeline::graphics::viewport::Viewport;
 use vulkano::render_pass::Framebuffer;
 use vulkano::render_pass::FramebufferCreateInfo;
 use vulkano::render_pass::RenderPass;
 use vulkano::swapchain::acquire_next_image;
+use vulkano::swapchain::ColorSpace;
 use vulkano::swapchain::FullScreenExclusive;
 use vulkano::swapchain::PresentGravity;
 use vulkano::swapchain::PresentMode;
@@ -29,12 +34,15 @@ use vulkano::swapchain::SwapchainCreateInfo;
 use vulkano::Validated;
 use vulkano::VulkanError;
 
+use super::commands::viewport_to_vulkan;
+use super::commands::ViewportLimits;
 use super::devices::Devices;
 use super::glfw_window::GLFWWindow;
 use super::instance::Allocators;
 use super::utils::Ref;
 
 enum_from_primitive! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
     pub enum VsyncMode {
         Off = 0,
         On,
@@ -42,9 +50,519 @@ enum_from_primitive! {
     }
 }
 
+impl VsyncMode {
+    pub fn present_mode(&self) -> PresentMode {
+        match self {
+            Self::Off => PresentMode::Immediate,
+            Self::On => PresentMode::FifoRelaxed,
+            Self::Triple => PresentMode::Mailbox,
+        }
+    }
+
+    /// The present modes which behave closest to this mode, best first. Fifo is last since every
+    /// surface has to support it.
+    pub fn present_mode_preferences(&self) -> &'static [PresentMode] {
+        match self {
+            Self::Off => &[
+                PresentMode::Immediate,
+                PresentMode::FifoRelaxed,
+                PresentMode::Fifo,
+            ],
+            Self::On => &[PresentMode::FifoRelaxed, PresentMode::Fifo],
+            Self::Triple => &[PresentMode::Mailbox, PresentMode::Fifo],
+        }
+    }
+}
+
+/// Picks the best present mode for `vsync` out of the ones the surface supports
+pub fn select_present_mode(vsync: VsyncMode, supported: &[PresentMode]) -> PresentMode {
+    vsync
+        .present_mode_preferences()
+        .iter()
+        .copied()
+        .find(|mode| supported.contains(mode))
+        .unwrap_or(PresentMode::Fifo)
+}
+
+/// Picks how many swapchain images to ask for: mailbox needs one image to present, one queued and
+/// one to render into, fifo is fine with the same, and immediate never waits on images so it only
+/// needs two. The result is clamped to what the surface supports.
+pub fn swapchain_image_count(
+    present_mode: PresentMode,
+    min_image_count: u32,
+    max_image_count: Option<u32>,
+) -> u32 {
+    let preferred = match present_mode {
+        PresentMode::Immediate => 2,
+        _ => 3,
+    };
+
+    preferred
+        .max(min_image_count)
+        .min(max_image_count.unwrap_or(u32::MAX))
+}
+
+/// The highest sample count the device supports that isn't above `requested`. Every device
+/// supports 1 sample, so that's the last resort.
+pub fn clamp_sample_count(requested: SampleCount, supported: SampleCounts) -> SampleCount {
+    [
+        SampleCount::Sample64,
+        SampleCount::Sample32,
+        SampleCount::Sample16,
+        SampleCount::Sample8,
+        SampleCount::Sample4,
+        SampleCount::Sample2,
+    ]
+    .into_iter()
+    .filter(|count| *count as u32 <= requested as u32)
+    .find(|count| supported.contains_enum(*count))
+    .unwrap_or(SampleCount::Sample1)
+}
+
+/// What happens to the previous contents of a swapchain image when a frame starts rendering
+/// into it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorLoadOp {
+    /// The image is cleared with the colour from `start_frame`.
+    #[default]
+    Clear,
+    /// The last frame presented from this image is kept, for integrations which only redraw
+    /// part of the screen.
+    Preserve,
+}
+
+impl ColorLoadOp {
+    pub fn initial_layout(&self) -> ImageLayout {
+        match self {
+            // the old contents are thrown away, so the layout they were left in doesn't matter
+            Self::Clear => ImageLayout::Undefined,
+            Self::Preserve => ImageLayout::PresentSrc,
+        }
+    }
+}
+
+enum_from_primitive! {
+    /// What ends a frame and presents it.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+    pub enum FrameBoundary {
+        /// A colour clear presents the previous frame, which is what vanilla's render loop expects.
+        #[default]
+        ColourClear = 0,
+        /// Colour clears only clear; frames are presented through MCVKNative.present. For
+        /// integrations which pace frames themselves or clear render targets mid-frame.
+        Explicit,
+    }
+}
+
+impl FrameBoundary {
+    /// Whether a colour clear presents the frame in progress instead of clearing it
+    pub fn clear_presents(&self) -> bool {
+        match self {
+            Self::ColourClear => true,
+            Self::Explicit => false,
+        }
+    }
+}
+
+enum_from_primitive! {
+    /// How draws are shaded.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+    pub enum RenderMode {
+        /// Draws are shaded straight into the swapchain image.
+        #[default]
+        Forward = 0,
+        /// Draws write their albedo and normals into a g-buffer, which a second subpass lights
+        /// with the RenderManager's ambient and directional lights.
+        Deferred,
+    }
+}
+
+impl RenderMode {
+    /// The attachments of the main render pass, in framebuffer order
+    pub fn attachments(&self) -> &'static [FrameAttachment] {
+        match self {
+            Self::Forward => &[
+                FrameAttachment::Swapchain,
+                FrameAttachment::Normals,
+                FrameAttachment::Depth,
+            ],
+            Self::Deferred => &[
+                FrameAttachment::Swapchain,
+                FrameAttachment::Albedo,
+                FrameAttachment::Normals,
+                FrameAttachment::Depth,
+            ],
+        }
+    }
+
+    /// The format of an attachment, or None if this mode's render pass doesn't use it
+    pub fn attachment_format(
+        &self,
+        attachment: FrameAttachment,
+        formats: AttachmentFormats,
+    ) -> Option<Format> {
+        self.attachments()
+            .contains(&attachment)
+            .then(|| attachment.format(formats))
+    }
+}
+
+/// The depth formats the main render pass can use, most precise first
+pub const DEPTH_FORMATS: [Format; 3] = [
+    Format::D32_SFLOAT,
+    Format::D24_UNORM_S8_UINT,
+    Format::D16_UNORM,
+];
+
+/// The first of [`DEPTH_FORMATS`] that `supported` accepts. Every device has to support D16 depth
+/// attachments, so it's the fallback.
+pub fn pick_depth_format(supported: impl Fn(Format) -> bool) -> Format {
+    DEPTH_FORMATS
+        .into_iter()
+        .find(|format| supported(*format))
+        .unwrap_or(Format::D16_UNORM)
+}
+
+/// The swapchain formats we'd rather present with, best first. Textures and vertex colours are
+/// sRGB-encoded and nothing linearizes them, so shaders output sRGB colour, like GL does into its
+/// UNORM default framebuffer. An `*_SRGB` format would encode those values a second time and wash
+/// out every frame, so these are UNORM: the bytes are stored as written and the sRGB colour space
+/// tells the compositor how to show them.
+pub const SURFACE_FORMATS: [Format; 2] = [Format::B8G8R8A8_UNORM, Format::R8G8B8A8_UNORM];
+
+/// The first of [`SURFACE_FORMATS`] the surface offers in the sRGB colour space, or whatever the
+/// surface lists first if it has none of them
+pub fn pick_surface_format(available: &[(Format, ColorSpace)]) -> Option<(Format, ColorSpace)> {
+    SURFACE_FORMATS
+        .into_iter()
+        .map(|format| (format, ColorSpace::SrgbNonLinear))
+        .find(|preferred| available.contains(preferred))
+        .or_else(|| available.first().copied())
+}
+
+/// The attachment formats which depend on the surface and the device, rather than the settings
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AttachmentFormats {
+    pub swapchain: Format,
+    /// See [`pick_depth_format`]
+    pub depth: Format,
+}
+
+enum_from_primitive! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum FrameAttachment {
+        Swapchain = 0,
+        Albedo = 1,
+        Normals = 2,
+        Depth = 3,
+        /// What draws go into while multisampling, which is resolved into the swapchain image
+        MultisampledColor = 4,
+        /// The glLoadName of the draw which covers each pixel, for picking. 0 where nothing named
+        /// was drawn.
+        ObjectIds = 5,
+    }
+}
+
+/// The fragment shader output generated shaders write [`FrameAttachment::Normals`] from in the
+/// deferred geometry subpass
+pub const NORMALS_LOCATION: usize = 1;
+
+/// The fragment shader output generated shaders write [`FrameAttachment::ObjectIds`] from. It
+/// comes after the deferred geometry subpass' albedo and normals.
+pub const OBJECT_ID_LOCATION: usize = 2;
+
+impl FrameAttachment {
+    pub fn format(&self, formats: AttachmentFormats) -> Format {
+        match self {
+            Self::Swapchain | Self::MultisampledColor => formats.swapchain,
+            Self::Albedo => Format::A2B10G10R10_UNORM_PACK32,
+            Self::Normals => Format::R16G16B16A16_SFLOAT,
+            Self::Depth => formats.depth,
+            Self::ObjectIds => Format::R32_UINT,
+        }
+    }
+
+    /// The usage of the image backing this attachment; swapchain images are created by the
+    /// swapchain instead.
+    pub fn usage(&self) -> ImageUsage {
+        match self {
+            Self::Swapchain => ImageUsage::COLOR_ATTACHMENT,
+            // kept between render passes, so that glFlush and preserved frames can load it again
+            Self::MultisampledColor => ImageUsage::COLOR_ATTACHMENT,
+            // the lighting subpass reads these back as input attachments
+            Self::Albedo | Self::Normals => {
+                ImageUsage::COLOR_ATTACHMENT
+                    | ImageUsage::INPUT_ATTACHMENT
+                    | ImageUsage::TRANSIENT_ATTACHMENT
+            }
+            Self::Depth => ImageUsage::DEPTH_STENCIL_ATTACHMENT | ImageUsage::TRANSIENT_ATTACHMENT,
+            // copied out when a frame is captured, see RenderManager::pick
+            Self::ObjectIds => ImageUsage::COLOR_ATTACHMENT | ImageUsage::TRANSFER_SRC,
+        }
+    }
+
+    pub fn clear_value(
+        &self,
+        color_load_op: ColorLoadOp,
+        formats: AttachmentFormats,
+    ) -> Option<ClearValue> {
+        match self {
+            Self::Swapchain | Self::MultisampledColor => match color_load_op {
+                ColorLoadOp::Clear => Some([0.0, 0.0, 0.0, 1.0].into()),
+                ColorLoadOp::Preserve => None,
+            },
+            Self::Albedo | Self::Normals => Some([0.0, 0.0, 0.0, 1.0].into()),
+            // a depth/stencil format has to be cleared with both values
+            Self::Depth if formats.depth.aspects().intersects(ImageAspects::STENCIL) => {
+                Some(ClearValue::DepthStencil((1.0, 0)))
+            }
+            Self::Depth => Some(1.0.into()),
+            Self::ObjectIds => Some(ClearValue::Uint([0; 4])),
+        }
+    }
+}
+
 pub struct WindowSettings {
     pub vsync: VsyncMode,
+    /// Always presents with plain fifo, whatever `vsync` asks for. An escape hatch for recording
+    /// and for drivers which misbehave with the other present modes.
+    pub force_fifo: bool,
     pub max_fps: Option<u32>,
+    /// Only applies to forward rendering, see [`WindowSettings::samples`]
+    pub msaa: SampleCount,
+    pub color_load_op: ColorLoadOp,
+    pub render_mode: RenderMode,
+    pub frame_boundary: FrameBoundary,
+    /// Renders the glLoadName of each draw into [`FrameAttachment::ObjectIds`], for picking. Off
+    /// by default, since every frame and glReadPixels pays for the extra attachment.
+    pub object_picking: bool,
+    /// How many render commands can wait for the render worker, see
+    /// [`CommandQueue::Bounded`](super::commands::CommandQueue::Bounded). None leaves the queue
+    /// unbounded.
+    pub command_queue_capacity: Option<usize>,
+}
+
+/// A partial settings update. Fields which are None are left as-is.
+#[derive(Debug, Clone, Default)]
+pub struct SettingsDelta {
+    pub vsync: Option<VsyncMode>,
+    pub force_fifo: Option<bool>,
+    pub max_fps: Option<Option<u32>>,
+    pub msaa: Option<SampleCount>,
+    pub color_load_op: Option<ColorLoadOp>,
+    pub render_mode: Option<RenderMode>,
+    pub frame_boundary: Option<FrameBoundary>,
+    pub object_picking: Option<bool>,
+    pub command_queue_capacity: Option<Option<usize>>,
+}
+
+/// Everything that has to be rebuilt before a settings change takes effect
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SettingsChanges {
+    pub recreate_swapchain: bool,
+    pub rebuild_render_pass: bool,
+    pub invalidate_pipelines: bool,
+    /// Only the present mode changed, so the swapchain can be recreated without touching the
+    /// surface or the viewport
+    pub change_present_mode: bool,
+    /// The GL thread has to be given a new command queue
+    pub replace_command_queue: bool,
+}
+
+impl WindowSettings {
+    pub fn apply(&mut self, delta: &SettingsDelta) -> SettingsChanges {
+        let mut changes = SettingsChanges::default();
+
+        if let Some(vsync) = delta.vsync {
+            if vsync != self.vsync {
+                self.vsync = vsync;
+                changes.change_present_mode = true;
+            }
+        }
+
+        if let Some(force_fifo) = delta.force_fifo {
+            if force_fifo != self.force_fifo {
+                self.force_fifo = force_fifo;
+                changes.change_present_mode = true;
+            }
+        }
+
+        // the frame limiter reads this every frame, so nothing needs to be rebuilt
+        if let Some(max_fps) = delta.max_fps {
+            self.max_fps = max_fps;
+        }
+
+        if let Some(msaa) = delta.msaa {
+            if msaa != self.msaa {
+                self.msaa = msaa;
+                // pipelines are created against a specific render pass + sample count, and the
+                // multisampled attachments are sized to the swapchain
+                changes.recreate_swapchain = true;
+                changes.rebuild_render_pass = true;
+                changes.invalidate_pipelines = true;
+            }
+        }
+
+        if let Some(load_op) = delta.color_load_op {
+            if load_op != self.color_load_op {
+                self.color_load_op = load_op;
+                changes.rebuild_render_pass = true;
+                changes.invalidate_pipelines = true;
+            }
+        }
+
+        if let Some(mode) = delta.render_mode {
+            if mode != self.render_mode {
+                self.render_mode = mode;
+                // the geometry subpass has a different set of colour attachments
+                changes.rebuild_render_pass = true;
+                changes.invalidate_pipelines = true;
+            }
+        }
+
+        // only read when glClear is called
+        if let Some(boundary) = delta.frame_boundary {
+            self.frame_boundary = boundary;
+        }
+
+        if let Some(picking) = delta.object_picking {
+            if picking != self.object_picking {
+                self.object_picking = picking;
+                // the attachment comes and goes, and the generated shaders only write ids into it
+                // while it's there
+                changes.rebuild_render_pass = true;
+                changes.invalidate_pipelines = true;
+            }
+        }
+
+        if let Some(capacity) = delta.command_queue_capacity {
+            if capacity != self.command_queue_capacity {
+                self.command_queue_capacity = capacity;
+                changes.replace_command_queue = true;
+            }
+        }
+
+        changes
+    }
+
+    /// The present mode to use out of the ones the surface supports, see [`select_present_mode`]
+    pub fn present_mode(&self, supported: &[PresentMode]) -> PresentMode {
+        if self.force_fifo {
+            PresentMode::Fifo
+        } else {
+            select_present_mode(self.vsync, supported)
+        }
+    }
+
+    /// The sample count of the main render pass. The lighting subpass reads the g-buffer as
+    /// single-sampled input attachments, so deferred rendering is never multisampled.
+    pub fn samples(&self) -> SampleCount {
+        match self.render_mode {
+            RenderMode::Forward => self.msaa,
+            RenderMode::Deferred => SampleCount::Sample1,
+        }
+    }
+
+    /// Whether the main render pass has a [`FrameAttachment::ObjectIds`]. Object ids can't be
+    /// averaged by a resolve, so they're only written while single-sampled.
+    pub fn writes_object_ids(&self) -> bool {
+        self.object_picking && self.samples() == SampleCount::Sample1
+    }
+
+    /// The attachments of the main render pass, in framebuffer order. Multisampling draws into
+    /// an extra colour attachment, which is resolved into the swapchain image.
+    pub fn attachments(&self) -> Vec<FrameAttachment> {
+        let mut attachments = self.render_mode.attachments().to_vec();
+
+        if self.samples() != SampleCount::Sample1 {
+            attachments.push(FrameAttachment::MultisampledColor);
+        }
+
+        if self.writes_object_ids() {
+            attachments.push(FrameAttachment::ObjectIds);
+        }
+
+        attachments
+    }
+
+    /// The format of an attachment, or None if the main render pass doesn't use it
+    pub fn attachment_format(
+        &self,
+        attachment: FrameAttachment,
+        formats: AttachmentFormats,
+    ) -> Option<Format> {
+        self.attachments()
+            .contains(&attachment)
+            .then(|| attachment.format(formats))
+    }
+
+    /// What each attachment is cleared to when a frame starts, in framebuffer order
+    pub fn clear_values(&self, formats: AttachmentFormats) -> Vec<Option<ClearValue>> {
+        self.clear_values_with(self.color_load_op, formats)
+    }
+
+    /// The clear values of the first frame drawn into a framebuffer, which clears the colour even
+    /// if it's preserved, see [`first_use_create_info`](super::instance::first_use_create_info)
+    pub fn first_use_clear_values(&self, formats: AttachmentFormats) -> Vec<Option<ClearValue>> {
+        self.clear_values_with(ColorLoadOp::Clear, formats)
+    }
+
+    fn clear_values_with(
+        &self,
+        color_load_op: ColorLoadOp,
+        formats: AttachmentFormats,
+    ) -> Vec<Option<ClearValue>> {
+        let multisampled = self.samples() != SampleCount::Sample1;
+
+        self.attachments()
+            .iter()
+            .map(|attachment| match attachment {
+                // the resolve overwrites all of it
+                FrameAttachment::Swapchain if multisampled => None,
+                _ => attachment.clear_value(color_load_op, formats),
+            })
+            .collect()
+    }
+}
+
+/// The variants of the main render pass a frame is recorded in when glFlush splits it, or when
+/// it's the first frame drawn into a framebuffer. They're compatible with the main render pass
+/// and only differ in their load and store ops, see
+/// [`split_create_info`](super::instance::split_create_info),
+/// [`continuation_create_info`](super::instance::continuation_create_info) and
+/// [`first_use_create_info`](super::instance::first_use_create_info).
+#[derive(Debug, Clone)]
+pub struct SplitRenderPasses {
+    /// Starts a frame, and ends in a glFlush
+    pub first: Arc<RenderPass>,
+    /// Continues a frame, and ends in another glFlush
+    pub middle: Arc<RenderPass>,
+    /// Continues a frame until it's submitted
+    pub last: Arc<RenderPass>,
+    /// Starts the first frame drawn into a framebuffer
+    pub first_use: Arc<RenderPass>,
+    /// Starts the first frame drawn into a framebuffer, and ends in a glFlush
+    pub first_use_split: Arc<RenderPass>,
+}
+
+impl SplitRenderPasses {
+    /// The render pass to begin, or None for the main one. Continuations never use a framebuffer
+    /// first.
+    pub fn select(
+        &self,
+        first_use: bool,
+        continuation: bool,
+        split: bool,
+    ) -> Option<&Arc<RenderPass>> {
+        match (continuation, split) {
+            (false, false) if first_use => Some(&self.first_use),
+            (false, true) if first_use => Some(&self.first_use_split),
+            (false, false) => None,
+            (false, true) => Some(&self.first),
+            (true, true) => Some(&self.middle),
+            (true, false) => Some(&self.last),
+        }
+    }
 }
 
 pub struct SwapchainManager {
@@ -57,39 +575,62 @@ pub struct SwapchainManager {
     pub surface: Option<Arc<Surface>>,
 
     pub render_pass: Option<Arc<RenderPass>>,
+    /// See [`SplitRenderPasses`]
+    pub split_render_passes: Option<SplitRenderPasses>,
 
+    /// See [`pick_surface_format`]
     pub image_format: Option<Format>,
+    pub image_color_space: ColorSpace,
+    /// Picked once, since it only depends on the device
+    pub depth_format: Format,
     pub swapchain: Option<Arc<Swapchain>>,
     pub images: Option<Vec<Arc<Image>>>,
     pub recreate_swapchain: bool,
+    /// See [`SwapchainManager::change_present_mode`]
+    pub present_mode_changed: bool,
     pub acquired_image: Option<i32>,
 
     pub frame_buffers: Option<Vec<Arc<Framebuffer>>>,
+    /// Whether a frame drawn into each framebuffer has been submitted since they were created.
+    /// Until then their images are Undefined, so nothing can be loaded from them.
+    initialized_framebuffers: Vec<bool>,
 
     pub viewport: Viewport,
     pub projection: TMat4<f32>,
 }
 
-const SWAPCHAIN_IMAGE_COUNT: u32 = 4;
-
 impl SwapchainManager {
     pub fn new(window: Ref<GLFWWindow>, devices: Ref<Devices>, allocator: Ref<Allocators>) -> Self {
+        let depth_format = devices.read().depth_format();
+
         let mut this = SwapchainManager {
             window,
             devices,
             allocator,
             window_settings: WindowSettings {
                 vsync: VsyncMode::On,
+                force_fifo: false,
                 max_fps: None,
+                msaa: SampleCount::Sample1,
+                color_load_op: ColorLoadOp::Clear,
+                render_mode: RenderMode::Forward,
+                frame_boundary: FrameBoundary::ColourClear,
+                object_picking: false,
+                command_queue_capacity: None,
             },
             surface: None,
             render_pass: None,
+            split_render_passes: None,
             image_format: None,
+            image_color_space: ColorSpace::SrgbNonLinear,
+            depth_format,
             swapchain: None,
             images: None,
             recreate_swapchain: false,
+            present_mode_changed: false,
             acquired_image: None,
             frame_buffers: None,
+            initialized_framebuffers: Vec::new(),
             viewport: Viewport::default(),
             projection: TMat4::identity(),
         };
@@ -107,28 +648,44 @@ impl SwapchainManager {
                     .create_surface(&self.devices.read().instance),
             );
 
-            self.image_format = Some(
-                self.devices
-                    .read()
-                    .device
-                    .physical_device()
-                    .surface_formats(self.surface.as_ref().unwrap(), Default::default())
-                    .unwrap()[0]
-                    .0,
-            );
+            let surface_formats = self
+                .devices
+                .read()
+                .device
+                .physical_device()
+                .surface_formats(self.surface.as_ref().unwrap(), Default::default())
+                .unwrap();
+
+            let (format, color_space) = pick_surface_format(&surface_formats)
+                .expect("the surface should support at least one format");
+
+            debug!(what = "picked swapchain format", ?format, ?color_space);
+
+            self.image_format = Some(format);
+            self.image_color_space = color_space;
 
             self.swapchain = None;
         }
 
+        let caps = self
+            .devices
+            .read()
+            .device
+            .physical_device()
+            .surface_capabilities(self.surface.as_ref().unwrap(), Default::default())
+            .unwrap();
+
+        let present_mode = self.select_present_mode();
+        let min_image_count =
+            swapchain_image_count(present_mode, caps.min_image_count, caps.max_image_count);
+
         if let Some(current) = self.swapchain.clone() {
             let (new_swapchain, new_images) = match current.recreate(SwapchainCreateInfo {
+                min_image_count,
                 image_extent: self.window.read().get_window_size(),
                 image_format: self.image_format.clone().unwrap(),
-                present_mode: match self.window_settings.vsync {
-                    VsyncMode::Off => PresentMode::Immediate,
-                    VsyncMode::On => PresentMode::FifoRelaxed,
-                    VsyncMode::Triple => PresentMode::Mailbox,
-                },
+                image_color_space: self.image_color_space,
+                present_mode,
                 ..current.create_info()
             }) {
                 Ok(r) => r,
@@ -139,14 +696,6 @@ impl SwapchainManager {
             self.swapchain = Some(new_swapchain);
             self.images = Some(new_images);
         } else {
-            let caps = self
-                .devices
-                .read()
-                .device
-                .physical_device()
-                .surface_capabilities(self.surface.as_ref().unwrap(), Default::default())
-                .unwrap();
-
             let usage = caps.supported_usage_flags;
             let alpha = caps.supported_composite_alpha.into_iter().next().unwrap();
 
@@ -154,16 +703,13 @@ impl SwapchainManager {
                 self.devices.read().device.clone(),
                 self.surface.clone().unwrap(),
                 SwapchainCreateInfo {
-                    min_image_count: SWAPCHAIN_IMAGE_COUNT,
+                    min_image_count,
                     image_format: self.image_format.clone().unwrap(),
+                    image_color_space: self.image_color_space,
                     image_extent: self.window.read().get_window_size(),
                     image_usage: usage,
                     composite_alpha: alpha,
-                    present_mode: match self.window_settings.vsync {
-                        VsyncMode::Off => PresentMode::Immediate,
-                        VsyncMode::On => PresentMode::FifoRelaxed,
-                        VsyncMode::Triple => PresentMode::Mailbox,
-                    },
+                    present_mode,
                     scaling_behavior: Some(PresentScaling::AspectRatioStretch),
                     present_gravity: Some(
                         [PresentGravity::Centered, PresentGravity::Centered].into(),
@@ -192,93 +738,196 @@ impl SwapchainManager {
         self.create_framebuffers();
 
         self.recreate_swapchain = false;
+        self.present_mode_changed = false;
+    }
+
+    /// Applies a vsync change. The old swapchain is retired instead of destroyed so frames which
+    /// are still in flight get presented, and nothing is recreated at all if the surface falls
+    /// back to the present mode that's already in use.
+    pub fn change_present_mode(&mut self) {
+        self.present_mode_changed = false;
+
+        let Some(current) = self.swapchain.clone() else {
+            self.recreate_swapchain = true;
+            return;
+        };
+
+        let present_mode = self.select_present_mode();
+
+        if present_mode == current.present_mode() {
+            return;
+        }
+
+        let caps = self
+            .devices
+            .read()
+            .device
+            .physical_device()
+            .surface_capabilities(self.surface.as_ref().unwrap(), Default::default())
+            .unwrap();
+
+        let (new_swapchain, new_images) = match current.recreate(SwapchainCreateInfo {
+            min_image_count: swapchain_image_count(
+                present_mode,
+                caps.min_image_count,
+                caps.max_image_count,
+            ),
+            present_mode,
+            ..current.create_info()
+        }) {
+            Ok(r) => r,
+            Err(Validated::Error(VulkanError::OutOfDate)) => {
+                self.recreate_swapchain = true;
+                return;
+            }
+            Err(e) => panic!("Failed to recreate swapchain: {:?}", e),
+        };
+
+        self.swapchain = Some(new_swapchain);
+        self.images = Some(new_images);
+
+        // the images changed, but the extent didn't so the viewport is still valid
+        self.create_framebuffers();
+    }
+
+    fn select_present_mode(&self) -> PresentMode {
+        let supported = self
+            .devices
+            .read()
+            .device
+            .physical_device()
+            .surface_present_modes(self.surface.as_ref().unwrap(), Default::default())
+            .unwrap()
+            .collect::<Vec<_>>();
+
+        let vsync = self.window_settings.vsync;
+        let force_fifo = self.window_settings.force_fifo;
+        let present_mode = self.window_settings.present_mode(&supported);
+
+        tracing::info!(
+            what = "selected present mode",
+            ?vsync,
+            force_fifo,
+            ?present_mode
+        );
+
+        present_mode
     }
 
     pub fn update_viewport(&mut self) {
         let extent = self.images.as_ref().unwrap()[0].extent();
-        self.viewport.extent = [extent[0] as f32, extent[1] as f32];
+        let limits = ViewportLimits::new(&self.devices.read().device);
+        self.viewport =
+            viewport_to_vulkan(0, 0, extent[0], extent[1], [extent[0], extent[1]], &limits);
 
         let aspect_ratio = extent[0] as f32 / extent[1] as f32;
         self.projection = perspective(aspect_ratio, half_pi(), 0.01, 100.0);
     }
 
+    /// None until the swapchain has been created
+    pub fn attachment_formats(&self) -> Option<AttachmentFormats> {
+        Some(AttachmentFormats {
+            swapchain: self.image_format?,
+            depth: self.depth_format,
+        })
+    }
+
+    /// The format the current render pass uses for an attachment, or None if it isn't used or
+    /// the swapchain hasn't been created yet
+    pub fn attachment_format(&self, attachment: FrameAttachment) -> Option<Format> {
+        self.window_settings
+            .attachment_format(attachment, self.attachment_formats()?)
+    }
+
+    /// See [`SplitRenderPasses::first_use`]
+    pub fn framebuffer_initialized(&self, index: u32) -> bool {
+        self.initialized_framebuffers
+            .get(index as usize)
+            .copied()
+            .unwrap_or(false)
+    }
+
+    /// Called once a frame drawn into the framebuffer has been submitted
+    pub fn mark_framebuffer_initialized(&mut self, index: u32) {
+        if let Some(initialized) = self.initialized_framebuffers.get_mut(index as usize) {
+            *initialized = true;
+        }
+    }
+
     pub fn create_framebuffers(&mut self) {
         self.frame_buffers = None;
+        self.initialized_framebuffers.clear();
 
         if let Some(render_pass) = self.render_pass.as_ref() {
-            let extent = self.images.as_ref().unwrap()[0].extent();
-
-            let depth_buffer = Image::new(
-                self.allocator.read().memory_allocator.clone(),
-                ImageCreateInfo {
-                    extent,
-                    array_layers: self.images.as_ref().unwrap().len() as u32,
-                    usage: ImageUsage::DEPTH_STENCIL_ATTACHMENT | ImageUsage::TRANSIENT_ATTACHMENT,
-                    format: Format::D16_UNORM,
-                    initial_layout: ImageLayout::Undefined,
-                    ..Default::default()
-                },
-                Default::default(),
-            )
-            .unwrap();
-
-            let normal_buffer = Image::new(
-                self.allocator.read().memory_allocator.clone(),
-                ImageCreateInfo {
-                    extent,
-                    array_layers: self.images.as_ref().unwrap().len() as u32,
-                    usage: ImageUsage::COLOR_ATTACHMENT | ImageUsage::TRANSIENT_ATTACHMENT,
-                    format: Format::R16G16B16A16_SFLOAT,
-                    initial_layout: ImageLayout::Undefined,
-                    ..Default::default()
-                },
-                Default::default(),
-            )
-            .unwrap();
+            let images = self.images.as_ref().unwrap();
+            let extent = images[0].extent();
+            let formats = self.attachment_formats().unwrap();
+
+            let attachments = self.window_settings.attachments();
+            let samples = self.window_settings.samples();
+
+            // every attachment other than the swapchain image gets one layer per swapchain image
+            let buffers = attachments
+                .iter()
+                .map(|attachment| {
+                    if *attachment == FrameAttachment::Swapchain {
+                        return None;
+                    }
+
+                    let image = Image::new(
+                        self.allocator.read().memory_allocator.clone(),
+                        ImageCreateInfo {
+                            extent,
+                            array_layers: images.len() as u32,
+                            usage: attachment.usage(),
+                            format: attachment.format(formats),
+                            samples,
+                            initial_layout: ImageLayout::Undefined,
+                            ..Default::default()
+                        },
+                        Default::default(),
+                    )
+                    .unwrap();
+
+                    Some(image)
+                })
+                .collect::<Vec<_>>();
+
+            self.initialized_framebuffers = vec![false; images.len()];
 
             self.frame_buffers = Some(
-                self.images
-                    .as_ref()
-                    .unwrap()
+                images
                     .iter()
                     .enumerate()
                     .map(|(i, image)| {
                         let i = i as u32;
 
-                        let view = ImageView::new_default(image.clone()).unwrap();
+                        let views = buffers
+                            .iter()
+                            .map(|buffer| match buffer {
+                                Some(buffer) => {
+                                    let mut range = buffer.subresource_range();
+                                    range.array_layers = i..(i + 1);
 
-                        let mut normals_range = normal_buffer.subresource_range();
-                        normals_range.array_layers = i..(i + 1);
-
-                        let mut depth_range = normal_buffer.subresource_range();
-                        depth_range.array_layers = i..(i + 1);
-
-                        Framebuffer::new(
-                            render_pass.clone(),
-                            FramebufferCreateInfo {
-                                attachments: vec![
-                                    view,
-                                    ImageView::new(
-                                        normal_buffer.clone(),
-                                        ImageViewCreateInfo {
-                                            view_type: ImageViewType::Dim2d,
-                                            format: normal_buffer.format(),
-                                            subresource_range: normals_range,
-                                            ..Default::default()
-                                        },
-                                    )
-                                    .unwrap(),
                                     ImageView::new(
-                                        depth_buffer.clone(),
+                                        buffer.clone(),
                                         ImageViewCreateInfo {
                                             view_type: ImageViewType::Dim2d,
-                                            format: normal_buffer.format(),
-                                            subresource_range: depth_range,
+                                            format: buffer.format(),
+                                            subresource_range: range,
                                             ..Default::default()
                                         },
                                     )
-                                    .unwrap(),
-                                ],
+                                    .unwrap()
+                                }
+                                None => ImageView::new_default(image.clone()).unwrap(),
+                            })
+                            .collect();
+
+                        Framebuffer::new(
+                            render_pass.clone(),
+                            FramebufferCreateInfo {
+                                attachments: views,
                                 ..Default::default()
                             },
                         )
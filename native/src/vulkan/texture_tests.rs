@@ -0,0 +1,851 @@
+use std::sync::Arc;
+
+use image::Rgba;
+use image::RgbaImage;
+use vulkano::format::Format;
+use vulkano::image::sampler::Filter;
+use vulkano::image::sampler::SamplerMipmapMode;
+
+use crate::vulkan::spinlock::SpinLock;
+use crate::vulkan::textures::lookup::animation_frame;
+use crate::vulkan::textures::lookup::TextureAtlas;
+use crate::vulkan::textures::lookup::TextureAtlasSprite;
+use crate::vulkan::textures::texture_manager::get_sub_image_frame;
+use crate::vulkan::textures::texture_manager::get_subregion_target;
+use crate::vulkan::textures::texture_manager::mip_extent;
+use crate::vulkan::textures::texture_manager::mip_level_count;
+use crate::vulkan::textures::texture_manager::subregion_copy;
+use crate::vulkan::textures::texture_manager::tex_level_parameter;
+use crate::vulkan::textures::texture_manager::upload_in_chunks;
+use crate::vulkan::textures::texture_manager::DeferredSwaps;
+use crate::vulkan::textures::texture_manager::ReloadProgress;
+use crate::vulkan::textures::texture_manager::SamplerCache;
+use crate::vulkan::textures::texture_manager::SamplerKey;
+use crate::vulkan::textures::texture_manager::TextureCompression;
+use crate::vulkan::textures::texture_manager::TextureError;
+use crate::vulkan::textures::texture_manager::TextureFilter;
+use crate::vulkan::textures::texture_manager::TextureHandle;
+use crate::vulkan::textures::texture_manager::TextureHandles;
+use crate::vulkan::textures::texture_manager::TextureLimits;
+use crate::vulkan::textures::texture_manager::TextureParams;
+use crate::vulkan::textures::texture_manager::TextureQuality;
+use crate::vulkan::textures::texture_manager::TextureReference;
+use crate::vulkan::textures::texture_manager::TextureSetCache;
+use crate::vulkan::textures::texture_manager::RGBA_FORMAT;
+use crate::vulkan::textures::textures::compressed_size;
+use crate::vulkan::textures::textures::decode_gl_pixels;
+use crate::vulkan::textures::textures::encode_gl_pixels;
+use crate::vulkan::textures::textures::pack_gpu_pixel;
+use crate::vulkan::textures::textures::resolve_compression;
+use crate::vulkan::textures::textures::tex_image;
+use crate::vulkan::textures::textures::unpack_gpu_pixel;
+use crate::vulkan::textures::textures::AnimationMetadata;
+use crate::vulkan::textures::textures::PixelTransfer;
+use crate::vulkan::textures::textures::TextureImage;
+
+#[test]
+fn sub_image_selects_one_frame() {
+    // a 16x16 sprite with 4 frames, stacked vertically like the original spritesheet
+    assert_eq!(get_sub_image_frame([16, 16], 4, [0, 0], [16, 16]), Some(0));
+    assert_eq!(get_sub_image_frame([16, 16], 4, [0, 32], [16, 16]), Some(2));
+    assert_eq!(get_sub_image_frame([16, 16], 4, [0, 48], [16, 16]), Some(3));
+
+    // past the last frame
+    assert_eq!(get_sub_image_frame([16, 16], 4, [0, 64], [16, 16]), None);
+    // straddles two frames
+    assert_eq!(get_sub_image_frame([16, 16], 4, [0, 8], [16, 16]), None);
+    // partial frame
+    assert_eq!(get_sub_image_frame([16, 16], 4, [0, 16], [8, 8]), None);
+}
+
+#[test]
+fn subregion_touches_only_its_rectangle() {
+    // a 2x2 update at (1, 1) of a 4x4 texture
+    let (frame, offset) = get_subregion_target([4, 4], 1, [1, 1], [2, 2]).unwrap();
+    assert_eq!((frame, offset), (0, [1, 1]));
+
+    let copy = subregion_copy(3, offset, [2, 2]);
+    assert_eq!(copy.image_offset, [1, 1, 0]);
+    assert_eq!(copy.image_extent, [2, 2, 1]);
+    assert_eq!(copy.image_subresource.array_layers, 3..4);
+    assert_eq!(copy.image_subresource.mip_level, 0);
+    // the staged pixels are only the rectangle's, packed tightly
+    assert_eq!(copy.buffer_row_length, 0);
+
+    // the rectangle may end at the edge but not go past it
+    assert!(get_subregion_target([4, 4], 1, [2, 2], [2, 2]).is_some());
+    assert_eq!(get_subregion_target([4, 4], 1, [3, 1], [2, 2]), None);
+    assert_eq!(get_subregion_target([4, 4], 1, [0, 4], [1, 1]), None);
+
+    // animated textures are addressed like a spritesheet, one frame at a time
+    assert_eq!(
+        get_subregion_target([4, 4], 2, [1, 5], [2, 2]),
+        Some((1, [1, 1]))
+    );
+    assert_eq!(get_subregion_target([4, 4], 2, [0, 3], [2, 2]), None);
+}
+
+#[test]
+fn decode_bgra_frame() {
+    let argb = 0x80_11_22_33_u32;
+
+    let data = [argb; 4]
+        .iter()
+        .flat_map(|p| p.to_ne_bytes())
+        .collect::<Vec<u8>>();
+
+    let image = decode_gl_pixels(
+        gl_constants::GL_BGRA,
+        gl_constants::GL_UNSIGNED_INT_8_8_8_8_REV,
+        2,
+        2,
+        &data,
+    )
+    .unwrap();
+
+    assert_eq!(image.get_pixel(1, 1), &Rgba([0x11, 0x22, 0x33, 0x80]));
+
+    assert!(decode_gl_pixels(
+        gl_constants::GL_BGRA,
+        gl_constants::GL_UNSIGNED_INT_8_8_8_8_REV,
+        4,
+        4,
+        &data,
+    )
+    .is_err());
+}
+
+#[test]
+fn tex_image_uploads_rgba_and_rgb() {
+    // a 4x4 RGBA image where each pixel's red channel is its index
+    let data: Vec<u8> = (0..16).flat_map(|i| [i, 0x20, 0x40, 0x80]).collect();
+
+    let TextureImage::Static { image } = tex_image(
+        gl_constants::GL_RGBA,
+        gl_constants::GL_UNSIGNED_BYTE,
+        4,
+        4,
+        Some(&data),
+    )
+    .unwrap() else {
+        panic!("glTexImage2D should replace the texture with a static image");
+    };
+
+    assert_eq!(image.dimensions(), (4, 4));
+    assert_eq!(image.get_pixel(1, 2), &Rgba([9, 0x20, 0x40, 0x80]));
+
+    // RGB pixels come out opaque
+    let rgb = [0x11, 0x22, 0x33].repeat(4);
+
+    let TextureImage::Static { image } = tex_image(
+        gl_constants::GL_RGB,
+        gl_constants::GL_UNSIGNED_BYTE,
+        2,
+        2,
+        Some(&rgb),
+    )
+    .unwrap() else {
+        unreachable!()
+    };
+
+    assert_eq!(image.get_pixel(1, 1), &Rgba([0x11, 0x22, 0x33, 0xff]));
+
+    // a buffer that's too small for the size is rejected instead of read past
+    assert!(tex_image(
+        gl_constants::GL_RGBA,
+        gl_constants::GL_UNSIGNED_BYTE,
+        8,
+        8,
+        Some(&data)
+    )
+    .is_err());
+
+    // a null buffer only allocates
+    let TextureImage::Static { image } = tex_image(
+        gl_constants::GL_RGBA,
+        gl_constants::GL_UNSIGNED_BYTE,
+        4,
+        4,
+        None,
+    )
+    .unwrap() else {
+        unreachable!()
+    };
+
+    assert_eq!(image.get_pixel(3, 3), &Rgba([0; 4]));
+}
+
+#[test]
+fn readback_matches_upload() {
+    // a 2x2 texture with a distinct pixel in each corner, so a flipped readback would show up
+    let data: Vec<u8> = vec![
+        0xFF, 0x00, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, //
+        0x00, 0x00, 0xFF, 0x80, 0x12, 0x34, 0x56, 0x00,
+    ];
+
+    let uploaded = decode_gl_pixels(
+        gl_constants::GL_RGBA,
+        gl_constants::GL_UNSIGNED_BYTE,
+        2,
+        2,
+        &data,
+    )
+    .unwrap();
+
+    // what the texture array holds after record_commands
+    let gpu: Vec<u32> = uploaded.pixels().map(|p| pack_gpu_pixel(*p)).collect();
+
+    let mut readback = image::RgbaImage::new(2, 2);
+
+    for (dest, src) in readback.pixels_mut().zip(gpu.iter()) {
+        *dest = unpack_gpu_pixel(*src);
+    }
+
+    assert_eq!(readback, uploaded);
+
+    assert_eq!(
+        encode_gl_pixels(
+            gl_constants::GL_RGBA,
+            gl_constants::GL_UNSIGNED_BYTE,
+            &readback
+        )
+        .unwrap(),
+        data
+    );
+
+    let bgra = encode_gl_pixels(
+        gl_constants::GL_BGRA,
+        gl_constants::GL_UNSIGNED_INT_8_8_8_8_REV,
+        &readback,
+    )
+    .unwrap();
+
+    assert_eq!(
+        decode_gl_pixels(
+            gl_constants::GL_BGRA,
+            gl_constants::GL_UNSIGNED_INT_8_8_8_8_REV,
+            2,
+            2,
+            &bgra
+        )
+        .unwrap(),
+        uploaded
+    );
+}
+
+#[test]
+fn compression_hint_selects_encoder() {
+    assert_eq!(
+        TextureCompression::from_hint(gl_constants::GL_FASTEST),
+        Some(TextureCompression::None)
+    );
+    assert_eq!(
+        TextureCompression::from_hint(gl_constants::GL_DONT_CARE),
+        Some(TextureCompression::Fast)
+    );
+    assert_eq!(
+        TextureCompression::from_hint(gl_constants::GL_NICEST),
+        Some(TextureCompression::HighQuality)
+    );
+    assert_eq!(TextureCompression::from_hint(gl_constants::GL_RGBA), None);
+
+    assert_eq!(TextureCompression::default(), TextureCompression::None);
+}
+
+#[test]
+fn compression_hint_recompresses_uploads() {
+    // red rises while green falls, which a bounding box puts on the wrong diagonal
+    let opposed = RgbaImage::from_fn(4, 4, |x, y| {
+        let t = (y * 4 + x) as u8 * 17;
+        Rgba([t, 255 - t, 0, 255])
+    });
+
+    let recompress = |compression: TextureCompression, image: &RgbaImage| {
+        compression.recompress(TextureImage::Static {
+            image: image.clone(),
+        })
+    };
+
+    assert!(matches!(
+        recompress(TextureCompression::None, &opposed),
+        TextureImage::Static { .. }
+    ));
+
+    let error = |compression: TextureCompression| {
+        let compressed = recompress(compression, &opposed);
+
+        assert!(matches!(
+            compressed,
+            TextureImage::Compressed {
+                format: Format::BC1_RGB_UNORM_BLOCK,
+                ..
+            }
+        ));
+
+        let TextureImage::Static { image } = resolve_compression(compressed, false).unwrap() else {
+            unreachable!()
+        };
+
+        image
+            .pixels()
+            .zip(opposed.pixels())
+            .flat_map(|(a, b)| (0..4).map(|i| (a.0[i] as i32 - b.0[i] as i32).pow(2)))
+            .sum::<i32>()
+    };
+
+    assert!(error(TextureCompression::HighQuality) < error(TextureCompression::Fast));
+
+    // translucent texels need BC3's alpha block
+    let translucent = RgbaImage::from_fn(8, 4, |x, _| Rgba([255, 255, 255, x as u8 * 32]));
+
+    let TextureImage::Compressed { format, data, .. } =
+        recompress(TextureCompression::Fast, &translucent)
+    else {
+        panic!("the image should have been compressed");
+    };
+
+    assert_eq!(format, Format::BC3_UNORM_BLOCK);
+    assert_eq!(data.len(), 32);
+
+    // partial blocks are left alone
+    let odd = RgbaImage::new(6, 6);
+    assert!(matches!(
+        recompress(TextureCompression::HighQuality, &odd),
+        TextureImage::Static { .. }
+    ));
+}
+
+#[test]
+fn pixel_transfer_scales_red() {
+    let mut image = decode_gl_pixels(
+        gl_constants::GL_RGBA,
+        gl_constants::GL_UNSIGNED_BYTE,
+        1,
+        1,
+        &[200, 100, 50, 255],
+    )
+    .unwrap();
+
+    let mut transfer = PixelTransfer::default();
+
+    // identity by default, so normal uploads are left alone
+    transfer.apply(&mut image);
+    assert_eq!(image.get_pixel(0, 0), &Rgba([200, 100, 50, 255]));
+
+    assert!(transfer.set(gl_constants::GL_RED_SCALE, 0.5));
+    assert!(transfer.set(gl_constants::GL_BLUE_BIAS, 1.0));
+    assert!(!transfer.set(gl_constants::GL_MAP_COLOR, 1.0));
+
+    transfer.apply(&mut image);
+    assert_eq!(image.get_pixel(0, 0), &Rgba([100, 100, 255, 255]));
+}
+
+#[test]
+fn tex_image_updates_apply_pixel_transfer() {
+    let mut transfer = PixelTransfer::default();
+    assert!(transfer.set(gl_constants::GL_RED_SCALE, 0.5));
+
+    // the pixels start at the buffer's position, which is past a pixel that isn't uploaded
+    let buffer = [1, 2, 3, 4, 200, 100, 50, 255];
+
+    let image = tex_image(
+        gl_constants::GL_RGBA,
+        gl_constants::GL_UNSIGNED_BYTE,
+        1,
+        1,
+        Some(&buffer[4..]),
+    )
+    .unwrap();
+
+    let TextureImage::Static { image } = transfer.apply_to_tex_image(image) else {
+        panic!("expected a static image");
+    };
+
+    assert_eq!(image.get_pixel(0, 0), &Rgba([100, 100, 50, 255]));
+
+    // compressed blocks are enqueued as they are
+    let block = vec![0x00, 0xf8, 0x1f, 0x00, 0, 0, 0, 0];
+
+    let TextureImage::Compressed { data, .. } =
+        transfer.apply_to_tex_image(TextureImage::Compressed {
+            format: Format::BC1_RGB_UNORM_BLOCK,
+            data: block.clone(),
+            width: 4,
+            height: 4,
+        })
+    else {
+        panic!("expected a compressed image");
+    };
+
+    assert_eq!(data, block);
+}
+
+fn sprite_handle(texture_id: i32) -> Arc<TextureHandle> {
+    Arc::new(TextureHandle {
+        resource_name: Some(format!("sprite_{texture_id}")),
+        texture_id,
+        texture: SpinLock::new(Arc::new(TextureReference::None)),
+        animation: None,
+        mipmapped: false,
+        params: SpinLock::new(TextureParams::default()),
+    })
+}
+
+#[test]
+fn atlas_finds_sprite_under_point() {
+    let atlas = TextureAtlas::new(
+        0,
+        vec![
+            Arc::new(TextureAtlasSprite::new(
+                sprite_handle(1),
+                [0.0, 0.0],
+                [0.5, 0.5],
+            )),
+            Arc::new(TextureAtlasSprite::new(
+                sprite_handle(2),
+                [0.5, 0.0],
+                [1.0, 0.5],
+            )),
+        ],
+    );
+
+    let left = atlas.find(0.25, 0.25).unwrap();
+    assert_eq!(left.texture().texture_id, 1);
+    assert_eq!(left.transform([0.25, 0.25]), [0.5, 0.5]);
+
+    let right = atlas.find(0.75, 0.1).unwrap();
+    assert_eq!(right.texture().texture_id, 2);
+    assert_eq!(right.transform([0.75, 0.1]), [0.5, 0.2]);
+
+    // nothing covers the bottom half
+    assert!(atlas.find(0.25, 0.75).is_none());
+}
+
+#[test]
+fn samplers_are_shared_by_params() {
+    // the cache doesn't need a real sampler to tell whether it made a new one
+    let mut cache = SamplerCache::default();
+    let mut get = |params: &TextureParams| {
+        cache
+            .get_or_create(params, |key| Ok::<_, ()>(Arc::new(key)))
+            .unwrap()
+    };
+
+    let a = get(&TextureParams::default());
+    let b = get(&TextureParams::default());
+
+    assert!(Arc::ptr_eq(&a, &b));
+
+    let nearest = TextureParams {
+        mag_filter: TextureFilter::Nearest,
+        ..Default::default()
+    };
+
+    let c = get(&nearest);
+
+    assert!(!Arc::ptr_eq(&a, &c));
+
+    // lods that only differ past the quantization share a sampler
+    let nudged = TextureParams {
+        lod_bias: 1e-5,
+        ..Default::default()
+    };
+
+    assert!(Arc::ptr_eq(&a, &get(&nudged)));
+    assert_eq!(cache.len(), 2);
+}
+
+#[test]
+fn failed_samplers_are_not_cached() {
+    let mut cache = SamplerCache::<Arc<SamplerKey>>::default();
+    let params = TextureParams::default();
+
+    assert!(cache.get_or_create(&params, |_| Err(())).is_err());
+    assert!(cache.is_empty());
+
+    assert!(cache
+        .get_or_create(&params, |key| Ok::<_, ()>(Arc::new(key)))
+        .is_ok());
+    assert_eq!(cache.len(), 1);
+}
+
+#[test]
+fn texture_sets_are_shared_by_key() {
+    let mut cache = TextureSetCache::<Arc<u32>>::default();
+    let key = (1, [(2, 3), (4, 5)].into_iter().collect());
+
+    let first = cache
+        .get_or_create(key.clone(), || Ok::<_, ()>(Arc::new(0)))
+        .unwrap();
+    let second = cache
+        .get_or_create(key, || Ok::<_, ()>(Arc::new(1)))
+        .unwrap();
+    assert!(Arc::ptr_eq(&first, &second));
+
+    // a different view in any unit needs its own set
+    let other = (1, [(2, 3), (6, 5)].into_iter().collect());
+    assert!(cache.get_or_create(other.clone(), || Err(())).is_err());
+    assert_eq!(cache.len(), 1);
+
+    cache
+        .get_or_create(other, || Ok::<_, ()>(Arc::new(2)))
+        .unwrap();
+    assert_eq!(cache.len(), 2);
+}
+
+#[test]
+fn animation_frames_follow_the_tick() {
+    let animation = AnimationMetadata {
+        animation_frames: vec![0, 0, 1, 2],
+    };
+
+    let frames: Vec<_> = (0..6)
+        .map(|tick| animation_frame(Some(&animation), tick))
+        .collect();
+    assert_eq!(frames, [0, 0, 1, 2, 0, 0]);
+
+    assert_eq!(animation_frame(None, 7), 0);
+    assert_eq!(
+        animation_frame(
+            Some(&AnimationMetadata {
+                animation_frames: Vec::new()
+            }),
+            7
+        ),
+        0
+    );
+}
+
+#[test]
+fn sampler_follows_filters() {
+    let create_info =
+        TextureParams::default()
+            .sampler_key()
+            .create_info(16.0, TextureQuality::default(), None);
+
+    assert_eq!(create_info.mag_filter, Filter::Linear);
+    assert_eq!(create_info.min_filter, Filter::Nearest);
+    assert_eq!(create_info.mipmap_mode, SamplerMipmapMode::Linear);
+
+    // without a mipmapped min filter only the base level is sampled
+    let create_info = TextureParams {
+        min_filter: TextureFilter::Linear,
+        ..Default::default()
+    }
+    .sampler_key()
+    .create_info(16.0, TextureQuality::default(), None);
+
+    assert_eq!(create_info.lod, 0.0..=0.25);
+}
+
+#[test]
+fn sampler_follows_video_settings() {
+    let key = TextureParams::default().sampler_key();
+
+    let create_info = key.create_info(16.0, TextureQuality::default(), Some(16.0));
+    assert_eq!(create_info.lod, 0.0..=4.0);
+    assert_eq!(create_info.anisotropy, None);
+
+    // fewer mipmap levels lower the clamp, and anisotropy is limited by the device
+    let quality = TextureQuality {
+        mipmap_levels: 2,
+        anisotropy: 32,
+    };
+
+    let create_info = key.create_info(16.0, quality, Some(16.0));
+    assert_eq!(create_info.lod, 0.0..=2.0);
+    assert_eq!(create_info.anisotropy, Some(16.0));
+
+    // devices without samplerAnisotropy never filter anisotropically
+    assert_eq!(key.create_info(16.0, quality, None).anisotropy, None);
+}
+
+#[test]
+fn base_level_skips_higher_mips() {
+    let handle = sprite_handle(1);
+
+    handle.set_tex_param(gl_constants::GL_TEXTURE_BASE_LEVEL, 1);
+    assert_eq!(
+        handle.get_tex_param::<i32>(gl_constants::GL_TEXTURE_BASE_LEVEL),
+        1
+    );
+
+    let key = handle.params.lock().sampler_key();
+    let create_info = key.create_info(16.0, TextureQuality::default(), None);
+
+    assert_eq!(create_info.lod, 1.0..=4.0);
+
+    // a base level past the mipmap levels setting still samples the base level
+    handle.set_tex_param(gl_constants::GL_TEXTURE_BASE_LEVEL, 6);
+
+    let key = handle.params.lock().sampler_key();
+    let create_info = key.create_info(16.0, TextureQuality::default(), None);
+
+    assert_eq!(create_info.lod, 6.0..=6.0);
+
+    assert_ne!(
+        key,
+        TextureParams::default().sampler_key(),
+        "samplers with different base levels can't be shared"
+    );
+}
+
+#[test]
+fn reload_progress_counts_up() {
+    let mut chunks = Vec::new();
+    let mut reports = Vec::new();
+
+    upload_in_chunks(
+        600,
+        256,
+        |count| {
+            chunks.push(count);
+            Ok::<_, ()>(())
+        },
+        |progress| reports.push(progress),
+    )
+    .unwrap();
+
+    assert_eq!(chunks, [256, 256, 88]);
+
+    let completed = reports.iter().map(|p| p.completed).collect::<Vec<_>>();
+    assert_eq!(completed, [0, 256, 512, 600]);
+    assert!(reports.iter().all(|p| p.total == 600));
+
+    // a failed chunk stops the reload without reporting it as done
+    let mut reports = Vec::new();
+    let mut uploaded = 0;
+    let result = upload_in_chunks(
+        600,
+        256,
+        |count| {
+            uploaded += count;
+            if uploaded > 256 {
+                Err("upload failed")
+            } else {
+                Ok(())
+            }
+        },
+        |progress| reports.push(progress),
+    );
+
+    assert_eq!(result, Err("upload failed"));
+    assert_eq!(
+        reports.last(),
+        Some(&ReloadProgress {
+            completed: 256,
+            total: 600
+        })
+    );
+}
+
+#[test]
+fn oversized_textures_are_rejected() {
+    let limits = TextureLimits {
+        max_dimension: 4096,
+        max_array_layers: 2048,
+    };
+
+    assert!(limits.check(4096, 4096, 1).is_ok());
+    assert!(matches!(
+        limits.check(8192, 8192, 1),
+        Err(TextureError::TooLarge(8192, 8192, 4096))
+    ));
+    assert!(matches!(
+        limits.check(16, 8192, 1),
+        Err(TextureError::TooLarge(16, 8192, 4096))
+    ));
+
+    // every frame of an animation takes a layer in the same array
+    assert!(limits.check(16, 16, 2048).is_ok());
+    assert!(matches!(
+        limits.check(16, 16, 2049),
+        Err(TextureError::TooManyFrames(2049, 2048))
+    ));
+
+    let frames = TextureImage::Frames {
+        width: 16,
+        height: 16,
+        frames: vec![RgbaImage::new(16, 16); 3],
+        animation: AnimationMetadata {
+            animation_frames: vec![0, 1, 2],
+        },
+    };
+
+    let few_layers = TextureLimits {
+        max_dimension: 4096,
+        max_array_layers: 2,
+    };
+
+    assert!(matches!(
+        few_layers.check_image(&frames),
+        Err(TextureError::TooManyFrames(3, 2))
+    ));
+}
+
+#[test]
+fn texture_batches_never_reuse_ids() {
+    let mut handles = TextureHandles::new();
+
+    let first = handles.create_textures(4);
+    assert_eq!(first, [0, 1, 2, 3]);
+
+    for id in &first {
+        handles.free_texture(*id);
+    }
+
+    assert!(handles.get(first[0]).is_none());
+
+    // freeing the first batch only removes its handles, so the next batch still counts up
+    let second = handles.create_textures(4);
+    assert_eq!(second, [4, 5, 6, 7]);
+    assert!(handles.get(second[0]).is_some());
+}
+
+#[test]
+fn compressed_textures_fall_back_to_rgba() {
+    // red and blue endpoints, with the first four texels using each palette entry in turn
+    let block = [0x00, 0xf8, 0x1f, 0x00, 0b11_10_01_00, 0, 0, 0];
+
+    let compressed = |format, data: &[u8]| TextureImage::Compressed {
+        format,
+        data: data.to_vec(),
+        width: 4,
+        height: 4,
+    };
+
+    assert_eq!(compressed_size(Format::BC1_RGB_UNORM_BLOCK, 6, 6), Some(32));
+    assert_eq!(compressed_size(Format::BC3_UNORM_BLOCK, 4, 4), Some(16));
+    assert_eq!(compressed_size(Format::R8G8B8A8_UNORM, 4, 4), None);
+
+    // devices that can sample BC formats get the blocks as they are
+    assert!(matches!(
+        resolve_compression(compressed(Format::BC1_RGB_UNORM_BLOCK, &block), true).unwrap(),
+        TextureImage::Compressed { .. }
+    ));
+
+    let TextureImage::Static { image } =
+        resolve_compression(compressed(Format::BC1_RGB_UNORM_BLOCK, &block), false).unwrap()
+    else {
+        panic!("compressed textures should be decoded when BC isn't supported");
+    };
+
+    assert_eq!(image.dimensions(), (4, 4));
+    assert_eq!(image.get_pixel(0, 0), &Rgba([255, 0, 0, 255]));
+    assert_eq!(image.get_pixel(1, 0), &Rgba([0, 0, 255, 255]));
+    assert_eq!(image.get_pixel(2, 0), &Rgba([170, 0, 85, 255]));
+    assert_eq!(image.get_pixel(3, 0), &Rgba([85, 0, 170, 255]));
+
+    // swapped endpoints switch DXT1 to three colours, and the fourth is transparent with alpha
+    let swapped = [0x1f, 0x00, 0x00, 0xf8, 0b11_10_01_00, 0, 0, 0];
+
+    let TextureImage::Static { image } =
+        resolve_compression(compressed(Format::BC1_RGBA_UNORM_BLOCK, &swapped), false).unwrap()
+    else {
+        unreachable!()
+    };
+
+    assert_eq!(image.get_pixel(2, 0), &Rgba([127, 0, 127, 255]));
+    assert_eq!(image.get_pixel(3, 0), &Rgba([0, 0, 0, 0]));
+
+    // DXT5 takes its alpha from the block before the colours, the second texel using a1
+    let mut dxt5 = vec![0xff, 0x00, 0b0000_1000, 0, 0, 0, 0, 0];
+    dxt5.extend_from_slice(&block);
+
+    let TextureImage::Static { image } =
+        resolve_compression(compressed(Format::BC3_UNORM_BLOCK, &dxt5), false).unwrap()
+    else {
+        unreachable!()
+    };
+
+    assert_eq!(image.get_pixel(0, 0), &Rgba([255, 0, 0, 255]));
+    assert_eq!(image.get_pixel(1, 0), &Rgba([0, 0, 255, 0]));
+
+    // short data is rejected instead of read past
+    assert!(resolve_compression(compressed(Format::BC3_UNORM_BLOCK, &block), false).is_err());
+}
+
+#[test]
+fn rectangular_mip_chains_halve_each_side() {
+    let size = [256, 128];
+
+    let levels = mip_level_count(size).unwrap();
+
+    assert_eq!(levels, 9);
+
+    let chain = (0..levels)
+        .map(|level| mip_extent(size, level))
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        chain,
+        [
+            [256, 128],
+            [128, 64],
+            [64, 32],
+            [32, 16],
+            [16, 8],
+            [8, 4],
+            [4, 2],
+            [2, 1],
+            [1, 1],
+        ]
+    );
+
+    // NPOT textures still don't get mipmaps
+    assert_eq!(mip_level_count([96, 64]), None);
+}
+
+#[test]
+fn level_parameters_halve_per_level() {
+    let size = [32, 32];
+    let query = |level, pname| tex_level_parameter(size, RGBA_FORMAT, level, pname);
+
+    assert_eq!(query(0, gl_constants::GL_TEXTURE_WIDTH), Some(32));
+    assert_eq!(query(1, gl_constants::GL_TEXTURE_WIDTH), Some(16));
+    assert_eq!(query(1, gl_constants::GL_TEXTURE_HEIGHT), Some(16));
+    assert_eq!(query(5, gl_constants::GL_TEXTURE_WIDTH), Some(1));
+
+    // there's no level past 1x1
+    assert_eq!(query(6, gl_constants::GL_TEXTURE_HEIGHT), Some(0));
+
+    assert_eq!(
+        query(1, gl_constants::GL_TEXTURE_INTERNAL_FORMAT),
+        Some(gl_constants::GL_RGBA8 as i32)
+    );
+    assert_eq!(
+        tex_level_parameter(
+            size,
+            Format::BC3_UNORM_BLOCK,
+            0,
+            gl_constants::GL_TEXTURE_INTERNAL_FORMAT
+        ),
+        Some(gl_constants::GL_COMPRESSED_RGBA_S3TC_DXT5_EXT as i32)
+    );
+
+    assert_eq!(query(0, gl_constants::GL_TEXTURE_DEPTH), None);
+}
+
+#[test]
+fn deferred_swaps_wait_for_the_upload() {
+    let handle = sprite_handle(1);
+    let old = handle.texture.get();
+
+    let first = Arc::new(TextureReference::None);
+    let second = Arc::new(TextureReference::None);
+
+    let mut swaps = DeferredSwaps::default();
+    swaps.push(handle.clone(), first.clone());
+    swaps.push(handle.clone(), second.clone());
+
+    assert_eq!(swaps.len(), 2);
+
+    // until the upload's fence signals, draws keep sampling the old texture
+    assert!(Arc::ptr_eq(&handle.texture.get(), &old));
+
+    swaps.apply();
+
+    // the newest upload wins, and nothing holds onto the one it replaced
+    assert!(Arc::ptr_eq(&handle.texture.get(), &second));
+    assert_eq!(Arc::strong_count(&first), 1);
+}
@@ -1,4 +1,5 @@
 use std::collections::HashMap;
+use std::collections::HashSet;
 use std::sync::atomic::AtomicU32;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
@@ -6,8 +7,8 @@ use std::sync::Arc;
 use nalgebra_glm::Vec2;
 use static_aabb2d_index::StaticAABB2DIndex;
 use static_aabb2d_index::StaticAABB2DIndexBuilder;
-use vulkano::image::view::ImageView;
 
+use crate::vulkan::spinlock::SpinLock;
 use crate::vulkan::utils::map;
 use crate::vulkan::utils::Ref;
 
@@ -16,7 +17,9 @@ use super::texture_manager::ArraySlotIndex;
 use super::texture_manager::GlTextureId;
 use super::texture_manager::TextureHandle;
 use super::texture_manager::TextureManager;
+use super::texture_manager::TextureParams;
 use super::texture_manager::TextureReference;
+use super::textures::AnimationMetadata;
 
 #[derive(Debug, Clone)]
 pub struct TextureAtlasSprite {
@@ -26,6 +29,19 @@ pub struct TextureAtlasSprite {
 }
 
 impl TextureAtlasSprite {
+    /// `min` and `max` are the corners of the sprite's rect in the atlas, in [0, 1] space
+    pub fn new(texture: Arc<TextureHandle>, min: [f32; 2], max: [f32; 2]) -> Self {
+        Self {
+            texture,
+            u: Vec2::new(min[0], max[0]),
+            v: Vec2::new(min[1], max[1]),
+        }
+    }
+
+    pub fn texture(&self) -> &Arc<TextureHandle> {
+        &self.texture
+    }
+
     /// Transforms a U,V coordinate from [self.min, self.max] space to [0, 1] space
     pub fn transform(&self, uv: [f32; 2]) -> [f32; 2] {
         [
@@ -36,7 +52,7 @@ impl TextureAtlasSprite {
 }
 
 #[derive(Debug)]
-struct TextureAtlas {
+pub struct TextureAtlas {
     texture_id: GlTextureId,
     lookup: StaticAABB2DIndex<f32>,
     sprites: Vec<Arc<TextureAtlasSprite>>,
@@ -68,22 +84,51 @@ impl TextureAtlas {
     }
 }
 
+/// The arrays that a transform's indices refer to, and the (array, slot) of each vertex. Views of
+/// the arrays come from [`TextureStorage::get_view`](super::texture_manager::TextureStorage::get_view).
+pub type TransformedIndices = (HashSet<ArrayIndex>, Vec<(ArrayIndex, ArraySlotIndex)>);
+
+/// Which of a texture's slots is drawn at `tick`. Textures without an animation only have one.
+pub fn animation_frame(animation: Option<&AnimationMetadata>, tick: u32) -> usize {
+    match animation {
+        Some(anim) if !anim.animation_frames.is_empty() => {
+            anim.animation_frames[tick as usize % anim.animation_frames.len()] as usize
+        }
+        _ => 0,
+    }
+}
+
 #[derive(Debug)]
 pub struct TextureLookup {
-    textures: Arc<TextureManager>,
+    textures_by_id: Ref<HashMap<GlTextureId, Arc<TextureHandle>>>,
 
     blocks: TextureAtlas,
     items: TextureAtlas,
-    missingno: TextureAtlasSprite,
+    missingno: Arc<TextureAtlasSprite>,
 
     tick_counter: AtomicU32,
 }
 
 impl TextureLookup {
-    pub fn new(textures: Arc<TextureManager>, blocks: GlTextureId, items: GlTextureId) -> Self {
+    /// Builds the block and item atlases from the sprites registered into them with
+    /// [`TextureManager::register_atlas_sprite`]. An atlas without any sprites is still valid,
+    /// everything drawn with it just becomes missingno.
+    pub fn new(textures: &TextureManager, blocks: GlTextureId, items: GlTextureId) -> Self {
+        let missingno = Arc::new(TextureHandle {
+            resource_name: None,
+            texture_id: -1,
+            texture: SpinLock::new(textures.texture_storage.get_missingno().clone()),
+            animation: None,
+            mipmapped: false,
+            params: SpinLock::new(TextureParams::default()),
+        });
+
         Self {
-            textures: textures.clone(),
-            blocks: TextureAtlas::new(texture_id, sprites),
+            textures_by_id: textures.handles.by_id.clone(),
+            blocks: TextureAtlas::new(blocks, textures.get_atlas_sprites(blocks)),
+            items: TextureAtlas::new(items, textures.get_atlas_sprites(items)),
+            missingno: Arc::new(TextureAtlasSprite::new(missingno, [0.0, 0.0], [1.0, 1.0])),
+            tick_counter: AtomicU32::new(0),
         }
     }
 
@@ -91,19 +136,11 @@ impl TextureLookup {
         &self,
         sprite: Arc<TextureHandle>,
         uvs: &mut [f32],
-    ) -> Option<(
-        HashMap<ArrayIndex, Arc<ImageView>>,
-        Vec<(ArrayIndex, ArraySlotIndex)>,
-    )> {
-        let mut textures = HashMap::<ArrayIndex, Arc<ImageView>>::new();
+    ) -> Option<TransformedIndices> {
+        let mut textures = HashSet::<ArrayIndex>::new();
         let mut texture_indices = Vec::<(ArrayIndex, ArraySlotIndex)>::with_capacity(uvs.len());
 
-        let tick = self.tick_counter.load(Ordering::Relaxed);
-
-        let slot_index = match sprite.animation.as_ref() {
-            Some(anim) => anim.animation_frames[tick as usize % anim.animation_frames.len()],
-            None => 0,
-        };
+        let slot_index = animation_frame(sprite.animation.as_ref(), self.tick());
 
         let storage = sprite.texture.lock();
 
@@ -118,11 +155,11 @@ impl TextureLookup {
             }
             TextureReference::Managed(storage) => {
                 array = storage.indices.array;
-                slot = storage.indices.slots[slot_index as usize];
+                slot = storage.indices.slots[slot_index];
             }
         }
 
-        textures.insert(array, self.textures.texture_storage.get_view(array));
+        textures.insert(array);
 
         for _ in 0..(uvs.len() / 2) {
             texture_indices.push((array, slot));
@@ -135,14 +172,11 @@ impl TextureLookup {
         &self,
         atlas: &TextureAtlas,
         uvs: &mut [f32],
-    ) -> Option<(
-        HashMap<ArrayIndex, Arc<ImageView>>,
-        Vec<(ArrayIndex, ArraySlotIndex)>,
-    )> {
-        let mut textures = HashMap::<ArrayIndex, Arc<ImageView>>::new();
+    ) -> Option<TransformedIndices> {
+        let mut textures = HashSet::<ArrayIndex>::new();
         let mut texture_indices = Vec::<(ArrayIndex, ArraySlotIndex)>::with_capacity(uvs.len());
 
-        let tick = self.tick_counter.load(Ordering::Relaxed);
+        let tick = self.tick();
 
         for vertex in 0..(uvs.len() / 2) {
             let u = uvs[vertex * 2];
@@ -154,10 +188,7 @@ impl TextureLookup {
             uvs[vertex * 2] = u;
             uvs[vertex * 2 + 1] = v;
 
-            let slot_index = match sprite.texture.animation.as_ref() {
-                Some(anim) => anim.animation_frames[tick as usize % anim.animation_frames.len()],
-                None => 0,
-            };
+            let slot_index = animation_frame(sprite.texture.animation.as_ref(), tick);
 
             let storage = sprite.texture.texture.lock();
 
@@ -172,13 +203,11 @@ impl TextureLookup {
                 }
                 TextureReference::Managed(storage) => {
                     array = storage.indices.array;
-                    slot = storage.indices.slots[slot_index as usize];
+                    slot = storage.indices.slots[slot_index];
                 }
             }
 
-            if !textures.contains_key(&array) {
-                textures.insert(array, self.textures.texture_storage.get_view(array));
-            }
+            textures.insert(array);
 
             texture_indices.push((array, slot));
         }
@@ -186,14 +215,24 @@ impl TextureLookup {
         Some((textures, texture_indices))
     }
 
+    /// The animation tick that animated textures are drawn at
+    pub fn tick(&self) -> u32 {
+        self.tick_counter.load(Ordering::Relaxed)
+    }
+
+    /// The array and slot of the texture that's drawn in place of missing ones
+    pub fn missingno_index(&self) -> (ArrayIndex, ArraySlotIndex) {
+        let storage = self.missingno.texture.texture.lock();
+        let indices = storage.unwrap_indices();
+
+        (indices.array, indices.slots[0])
+    }
+
     pub fn transform(
         &self,
         texture: GlTextureId,
         uvs: &mut [f32],
-    ) -> Option<(
-        HashMap<ArrayIndex, Arc<ImageView>>,
-        Vec<(ArrayIndex, ArraySlotIndex)>,
-    )> {
+    ) -> Option<TransformedIndices> {
         if texture == self.blocks.texture_id {
             return self.transform_atlas_uv(&self.blocks, uvs);
         }
@@ -202,7 +241,7 @@ impl TextureLookup {
             return self.transform_atlas_uv(&self.items, uvs);
         }
 
-        let sprite = self.textures.textures_by_id.read().get(&texture)?.clone();
+        let sprite = self.textures_by_id.read().get(&texture)?.clone();
 
         self.transform_texture(sprite, uvs)
     }
@@ -3,6 +3,7 @@ use std::collections::HashMap;
 use std::collections::HashSet;
 use std::fmt::Debug;
 use std::sync::Arc;
+use std::time::Duration;
 use std::time::Instant;
 
 use anyhow::Context;
@@ -12,19 +13,30 @@ use image::RgbaImage;
 use num::FromPrimitive;
 use num_derive::FromPrimitive;
 use num_derive::ToPrimitive;
+use smallvec::smallvec;
 use smallvec::SmallVec;
+use tracing::debug;
 use tracing::info;
 use tracing::warn;
 use vulkano::buffer::BufferUsage;
 use vulkano::buffer::Subbuffer;
 use vulkano::command_buffer::AutoCommandBufferBuilder;
 use vulkano::command_buffer::BlitImageInfo;
+use vulkano::command_buffer::BufferImageCopy;
 use vulkano::command_buffer::CommandBufferUsage;
 use vulkano::command_buffer::CopyBufferToImageInfo;
+use vulkano::command_buffer::CopyImageToBufferInfo;
 use vulkano::command_buffer::PrimaryAutoCommandBuffer;
 use vulkano::command_buffer::PrimaryCommandBufferAbstract;
+use vulkano::descriptor_set::layout::DescriptorSetLayout;
+use vulkano::descriptor_set::PersistentDescriptorSet;
 use vulkano::device::DeviceOwned;
+use vulkano::format::Format;
+use vulkano::image::sampler::Filter;
 use vulkano::image::sampler::Sampler;
+use vulkano::image::sampler::SamplerAddressMode;
+use vulkano::image::sampler::SamplerCreateInfo;
+use vulkano::image::sampler::SamplerMipmapMode;
 use vulkano::image::view::ImageView;
 use vulkano::image::view::ImageViewCreateInfo;
 use vulkano::image::view::ImageViewType;
@@ -32,50 +44,200 @@ use vulkano::image::Image;
 use vulkano::image::ImageAspects;
 use vulkano::image::ImageFormatInfo;
 use vulkano::image::ImageLayout;
+use vulkano::image::ImageSubresourceLayers;
 use vulkano::image::ImageSubresourceRange;
 use vulkano::image::ImageUsage;
 use vulkano::image::SampleCount;
 use vulkano::memory::allocator::MemoryTypeFilter;
 use vulkano::memory::allocator::StandardMemoryAllocator;
+use vulkano::sync::future::FenceSignalFuture;
 use vulkano::sync::GpuFuture;
 
+use crate::vulkan::dynamic_shader::MAX_SHADER_TEXTURE_UNITS;
 use crate::vulkan::instance::Allocators;
 use crate::vulkan::render_manager::RenderManager;
 use crate::vulkan::spinlock::SpinLock;
 use crate::vulkan::utils::Ref;
 
+use super::lookup::animation_frame;
+use super::lookup::TextureAtlasSprite;
 use super::lookup::TextureLookup;
+use super::textures::compressed_size;
+use super::textures::encode_bc;
+use super::textures::gl_internal_format;
+use super::textures::pack_gpu_pixel;
+use super::textures::resolve_compression;
+use super::textures::unpack_gpu_pixel;
 use super::textures::AnimationMetadata;
+use super::textures::PixelTransfer;
 use super::textures::TextureImage;
 use super::textures::TextureLoadError;
 
 pub type ArrayIndex = u16;
 pub type ArraySlotIndex = u16;
 
+/// The format of every uncompressed texture array, see [`pack_gpu_pixel`]. It's normalized so that
+/// the generated shaders can sample it with linear filtering.
+pub const RGBA_FORMAT: Format = Format::A8B8G8R8_UNORM_PACK32;
+
 pub struct TextureStorage {
     allocator: Arc<StandardMemoryAllocator>,
     next_array: ArrayIndex,
     arrays: HashMap<ArrayIndex, TextureArray>,
     missingno: Arc<TextureReference>,
+    /// A 1x1 white texture, sampled by texture units that are disabled
+    dummy: Arc<TextureReference>,
+    limits: TextureLimits,
+    /// Whether BC textures can be uploaded as is, instead of being decoded to RGBA first
+    supports_bc: bool,
+    /// Compression is opt-in: textures stay uncompressed until a mod asks for it with glHint,
+    /// since recompressing changes how they look
+    pub compression: TextureCompression,
+    /// Some while an asynchronous reload is collecting textures, see [`DeferredSwaps`]
+    deferred_swaps: Option<DeferredSwaps>,
+    /// An asynchronous reload whose upload has been submitted, but maybe not finished
+    pending_reload: Option<PendingReload>,
+    /// Slots allocated for swaps which haven't been made yet, so that nothing samples them
+    unsampled: HashSet<(ArrayIndex, ArraySlotIndex)>,
+}
+
+/// Texture references which replace the ones in their handles once the upload writing them has
+/// finished. Until then the handles keep their old textures, which stay valid since deferred
+/// uploads always go into newly allocated slots. Any other update which would write over a slot
+/// that's still being sampled is held back until the swaps are made.
+#[derive(Debug, Default)]
+pub struct DeferredSwaps {
+    swaps: Vec<(Arc<TextureHandle>, Arc<TextureReference>)>,
+}
+
+impl DeferredSwaps {
+    pub fn push(&mut self, handle: Arc<TextureHandle>, texture: Arc<TextureReference>) {
+        self.swaps.push((handle, texture));
+    }
+
+    /// The texture that will be swapped into a handle, if any
+    pub fn get(&self, handle: &Arc<TextureHandle>) -> Option<&Arc<TextureReference>> {
+        self.swaps
+            .iter()
+            .rev()
+            .find(|(swapped, _)| Arc::ptr_eq(swapped, handle))
+            .map(|(_, texture)| texture)
+    }
+
+    pub fn len(&self) -> usize {
+        self.swaps.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.swaps.is_empty()
+    }
+
+    /// Swaps every texture in, in the order they were pushed. The old textures' slots are freed
+    /// once nothing else refers to them.
+    pub fn apply(self) {
+        for (handle, texture) in self.swaps {
+            handle.texture.set(texture);
+        }
+    }
+}
+
+/// The fence of an asynchronous reload's upload, which is only ever polled
+pub trait UploadFence: Send + Sync {
+    fn is_signaled(&self) -> anyhow::Result<bool>;
+
+    fn wait(&self) -> anyhow::Result<()>;
+}
+
+impl<F: GpuFuture> UploadFence for FenceSignalFuture<F>
+where
+    Self: Send + Sync,
+{
+    fn is_signaled(&self) -> anyhow::Result<bool> {
+        Ok(FenceSignalFuture::is_signaled(self)?)
+    }
+
+    fn wait(&self) -> anyhow::Result<()> {
+        Ok(FenceSignalFuture::wait(self, None)?)
+    }
+}
+
+impl<T: UploadFence + ?Sized> UploadFence for Arc<T> {
+    fn is_signaled(&self) -> anyhow::Result<bool> {
+        T::is_signaled(self)
+    }
+
+    fn wait(&self) -> anyhow::Result<()> {
+        T::wait(self)
+    }
+}
+
+/// An asynchronous reload's upload, and the swaps waiting for it
+struct PendingReload {
+    upload: Box<dyn UploadFence>,
+    swaps: DeferredSwaps,
+}
+
+/// The largest textures the device can store, checked before anything is allocated so that an
+/// oversized texture is an error instead of a panic in [`TextureStorage::allocate`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TextureLimits {
+    pub max_dimension: u32,
+    /// Every frame of an animated texture goes in the same array, so this is also the frame limit
+    pub max_array_layers: u32,
+}
+
+impl TextureLimits {
+    pub fn check(&self, width: u32, height: u32, frames: usize) -> Result<(), TextureError> {
+        if width > self.max_dimension || height > self.max_dimension {
+            return Err(TextureError::TooLarge(width, height, self.max_dimension));
+        }
+
+        if frames > self.max_array_layers as usize {
+            return Err(TextureError::TooManyFrames(frames, self.max_array_layers));
+        }
+
+        Ok(())
+    }
+
+    pub fn check_image(&self, image: &TextureImage) -> Result<(), TextureError> {
+        self.check(image.width(), image.height(), image.frame_count())
+    }
 }
 
 pub struct TextureArray {
     id: ArrayIndex,
     layer_count: u16,
     size: [u32; 2],
+    format: Format,
     image: Arc<Image>,
+    /// Every layer and mip level, made once since the image never changes
+    view: Arc<ImageView>,
+    /// Single-layer views for the samplers of user programs, made the first time they're bound
+    layer_views: HashMap<u32, Arc<ImageView>>,
     updates: HashMap<ArraySlotIndex, TextureUpdate>,
+    /// Updates held back until a reload's swaps are made, see [`DeferredSwaps`]
+    held: HashMap<ArraySlotIndex, TextureUpdate>,
     free: Arc<SpinLock<BTreeSet<ArraySlotIndex>>>,
     mipmapped: bool,
     mip_levels: u32,
 }
 
 struct TextureUpdate {
-    image_data: Subbuffer<[u32]>,
+    /// The whole layer, None if only parts of it changed
+    image_data: Option<Subbuffer<[u32]>>,
+    /// Copied after the whole layer, in the order they were made
+    regions: Vec<RegionUpdate>,
     handle: Option<Arc<TextureHandle>>,
     animation: Option<AnimationMetadata>,
 }
 
+/// A sub-rectangle of a layer, from glTextureSubImage2D
+struct RegionUpdate {
+    image_data: Subbuffer<[u32]>,
+    offset: [u32; 2],
+    extent: [u32; 2],
+}
+
 impl TextureStorage {
     pub fn new(allocators: &Ref<Allocators>) -> Self {
         let mut this = Self {
@@ -83,9 +245,21 @@ impl TextureStorage {
             next_array: 0,
             arrays: HashMap::new(),
             missingno: Arc::new(TextureReference::None),
+            dummy: Arc::new(TextureReference::None),
+            limits: texture_limits(&allocators.read().memory_allocator),
+            supports_bc: allocators
+                .read()
+                .memory_allocator
+                .device()
+                .enabled_features()
+                .texture_compression_bc,
+            compression: TextureCompression::None,
+            deferred_swaps: None,
+            pending_reload: None,
+            unsampled: HashSet::new(),
         };
 
-        let missingno = this.allocate(16, 16, 1, true);
+        let missingno = this.allocate(16, 16, 1, true, RGBA_FORMAT);
         this.enqueue_reference_update(
             &missingno,
             TextureImage::Static {
@@ -96,24 +270,207 @@ impl TextureStorage {
         .unwrap();
         this.missingno = Arc::new(missingno);
 
+        let dummy = this.allocate(1, 1, 1, false, RGBA_FORMAT);
+        this.enqueue_reference_update(
+            &dummy,
+            TextureImage::Static {
+                image: RgbaImage::from_pixel(1, 1, Rgba([0xFF; 4])),
+            },
+            None,
+        )
+        .unwrap();
+        this.dummy = Arc::new(dummy);
+
         this
     }
 
+    pub fn limits(&self) -> TextureLimits {
+        self.limits
+    }
+
+    /// Starts collecting texture swaps instead of making them straight away, see
+    /// [`DeferredSwaps`]
+    pub fn defer_swaps(&mut self) {
+        self.deferred_swaps
+            .get_or_insert_with(DeferredSwaps::default);
+    }
+
+    /// Stops deferring swaps, and makes the ones that were collected
+    pub fn apply_deferred_swaps(&mut self) {
+        let swaps = self.deferred_swaps.take().unwrap_or_default();
+
+        self.apply_swaps(swaps);
+    }
+
+    /// Stops deferring swaps. The ones that were collected are made by
+    /// [`Self::poll_pending_reload`] once `upload` has signaled. Returns how many there are.
+    pub fn submit_deferred_swaps(&mut self, upload: Box<dyn UploadFence>) -> usize {
+        self.finish_pending_reload();
+
+        let swaps = self.deferred_swaps.take().unwrap_or_default();
+        let count = swaps.len();
+
+        self.pending_reload = Some(PendingReload { upload, swaps });
+
+        count
+    }
+
+    /// Makes the swaps of a submitted reload if its upload has finished, without blocking.
+    /// Returns how many were made on the call that made them.
+    pub fn poll_pending_reload(&mut self) -> anyhow::Result<Option<usize>> {
+        let Some(pending) = self.pending_reload.as_ref() else {
+            return Ok(None);
+        };
+
+        if !pending.upload.is_signaled()? {
+            return Ok(None);
+        }
+
+        let swaps = self.pending_reload.take().unwrap().swaps;
+        let count = swaps.len();
+
+        self.apply_swaps(swaps);
+
+        Ok(Some(count))
+    }
+
+    /// Waits for a submitted reload's upload, then makes its swaps
+    pub fn finish_pending_reload(&mut self) {
+        let Some(pending) = self.pending_reload.take() else {
+            return;
+        };
+
+        if let Err(e) = pending.upload.wait() {
+            tracing::error!(what = "previous texture reload failed", error = %e);
+        }
+
+        self.apply_swaps(pending.swaps);
+    }
+
+    /// Whether updates of sampled slots are being held back, see [`DeferredSwaps`]
+    fn is_reloading(&self) -> bool {
+        self.deferred_swaps.is_some() || self.pending_reload.is_some()
+    }
+
+    /// Makes the swaps, then queues the updates that were held back for them
+    fn apply_swaps(&mut self, swaps: DeferredSwaps) {
+        for (_, texture) in &swaps.swaps {
+            if let TextureReference::Managed(tex) = texture.as_ref() {
+                for slot in &tex.indices.slots {
+                    self.unsampled.remove(&(tex.indices.array, *slot));
+                }
+            }
+        }
+
+        swaps.apply();
+
+        if self.is_reloading() {
+            return;
+        }
+
+        for array in self.arrays.values_mut() {
+            for (slot, held) in array.held.drain() {
+                match array.updates.get_mut(&slot) {
+                    Some(update) if held.image_data.is_none() => {
+                        update.regions.extend(held.regions)
+                    }
+                    _ => {
+                        array.updates.insert(slot, held);
+                    }
+                }
+            }
+        }
+    }
+
+    /// The texture a handle has, or will have once a reload's swaps are made. Updates go into
+    /// this one, so that they aren't lost with the texture it replaces.
+    pub fn current_texture(&self, handle: &Arc<TextureHandle>) -> Arc<TextureReference> {
+        self.deferred_swaps
+            .iter()
+            .chain(self.pending_reload.iter().map(|pending| &pending.swaps))
+            .find_map(|swaps| swaps.get(handle))
+            .cloned()
+            .unwrap_or_else(|| handle.texture.get())
+    }
+
+    /// Points a handle at a newly allocated texture. While a reload has swaps waiting for its
+    /// upload, the swap waits too, so that the reload doesn't undo it. Returns whether it waits.
+    fn swap_in(&mut self, handle: &Arc<TextureHandle>, texture: Arc<TextureReference>) -> bool {
+        let swaps = match (self.deferred_swaps.as_mut(), self.pending_reload.as_mut()) {
+            (Some(swaps), _) => swaps,
+            (None, Some(pending)) if pending.swaps.get(handle).is_some() => &mut pending.swaps,
+            _ => {
+                handle.texture.set(texture);
+                return false;
+            }
+        };
+
+        swaps.push(handle.clone(), texture);
+
+        true
+    }
+
+    /// Where an update of a slot is queued. While a reload is in progress, slots that frames may
+    /// be sampling aren't written to until its swaps are made, and neither are slots that already
+    /// have an update held back, so that the updates stay in order.
+    fn update_queue(
+        &mut self,
+        array: ArrayIndex,
+        slot: ArraySlotIndex,
+    ) -> &mut HashMap<ArraySlotIndex, TextureUpdate> {
+        let hold = self.is_reloading() && !self.unsampled.contains(&(array, slot));
+
+        let array = self.arrays.get_mut(&array).unwrap();
+
+        if hold || array.held.contains_key(&slot) {
+            &mut array.held
+        } else {
+            &mut array.updates
+        }
+    }
+
     pub fn get_missingno(&self) -> &Arc<TextureReference> {
         &self.missingno
     }
 
+    pub fn get_dummy(&self) -> &Arc<TextureReference> {
+        &self.dummy
+    }
+
+    /// The memory taken by every texture array, whether its slots are in use or not
+    pub fn vram_estimate(&self) -> u64 {
+        self.arrays
+            .values()
+            .map(|array| {
+                let layers = array.layer_count as u32;
+
+                match compressed_size(array.format, array.size[0], array.size[1]) {
+                    Some(bytes) => bytes as u64 * layers as u64,
+                    None => texture_array_bytes(array.size, layers, array.mip_levels),
+                }
+            })
+            .sum()
+    }
+
     pub fn allocate(
         &mut self,
         width: u32,
         height: u32,
         count: u16,
         mipmapped: bool,
+        format: Format,
     ) -> TextureReference {
+        // blits can't write compressed images, so their mips can't be generated
+        let mipmapped = mipmapped && format == RGBA_FORMAT;
+
         let mut slots = None;
 
         for (_, array) in &mut self.arrays {
-            if array.size[0] == width && array.size[1] == height && array.mipmapped == mipmapped {
+            if array.size[0] == width
+                && array.size[1] == height
+                && array.mipmapped == mipmapped
+                && array.format == format
+            {
                 let mut free = array.free.lock();
 
                 if free.len() >= count as usize {
@@ -129,7 +486,7 @@ impl TextureStorage {
         }
 
         if slots.is_none() {
-            let array = self.create_texture_array(width, height, mipmapped, count);
+            let array = self.create_texture_array(width, height, mipmapped, format, count);
 
             let mut free = array.free.lock();
 
@@ -158,6 +515,7 @@ impl TextureStorage {
         width: u32,
         height: u32,
         mipmapped: bool,
+        format: Format,
         min_layers: u16,
     ) -> &mut TextureArray {
         let layers;
@@ -191,7 +549,7 @@ impl TextureStorage {
             .physical_device()
             .image_format_properties(ImageFormatInfo {
                 usage: ImageUsage::SAMPLED | ImageUsage::TRANSFER_SRC | ImageUsage::TRANSFER_DST,
-                format: vulkano::format::Format::A8B8G8R8_UINT_PACK32,
+                format,
                 image_type: vulkano::image::ImageType::Dim2d,
                 ..Default::default()
             })
@@ -203,16 +561,16 @@ impl TextureStorage {
             .min(image_properties.max_array_layers as u16);
 
         let mip_levels = if mipmapped {
-            if !width.is_power_of_two() || !height.is_power_of_two() {
-                tracing::warn!(
-                    what =
-                        "a texture's width and height must be a power of two to generate mipmaps",
-                    width,
-                    height
-                );
-                1
-            } else {
-                width.max(height).ilog2() + 1
+            match mip_level_count([width, height]) {
+                Some(levels) => levels,
+                None => {
+                    tracing::warn!(
+                        what = "a texture's width and height must be a power of two to generate mipmaps",
+                        width,
+                        height
+                    );
+                    1
+                }
             }
         } else {
             1
@@ -221,7 +579,7 @@ impl TextureStorage {
         let mip_levels = mip_levels.min(image_properties.max_mip_levels);
 
         if layers < min_layers {
-            panic!("{layers} < {min_layers}");
+            panic!("a texture array needs {min_layers} layers but can only have {layers}; the texture should have been rejected by TextureLimits::check");
         }
 
         let texture = Image::new(
@@ -230,7 +588,7 @@ impl TextureStorage {
                 extent: [width as u32, height as u32, 1],
                 array_layers: layers as u32,
                 usage: ImageUsage::SAMPLED | ImageUsage::TRANSFER_SRC | ImageUsage::TRANSFER_DST,
-                format: vulkano::format::Format::A8B8G8R8_UINT_PACK32,
+                format,
                 initial_layout: ImageLayout::Undefined,
                 image_type: vulkano::image::ImageType::Dim2d,
                 samples: SampleCount::Sample1,
@@ -243,6 +601,22 @@ impl TextureStorage {
         )
         .unwrap();
 
+        let view = ImageView::new(
+            texture.clone(),
+            ImageViewCreateInfo {
+                view_type: ImageViewType::Dim2dArray,
+                format,
+                subresource_range: ImageSubresourceRange {
+                    aspects: ImageAspects::COLOR,
+                    array_layers: 0..layers as u32,
+                    mip_levels: 0..mip_levels,
+                },
+                usage: ImageUsage::SAMPLED,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
         let id = self.next_array;
         self.next_array += 1;
 
@@ -250,8 +624,12 @@ impl TextureStorage {
             id,
             layer_count: layers,
             size: [width, height],
+            format,
             image: texture,
+            view,
+            layer_views: HashMap::new(),
             updates: HashMap::new(),
+            held: HashMap::new(),
             free: Arc::new(SpinLock::new((0..layers).collect())),
             mipmapped,
             mip_levels,
@@ -264,25 +642,43 @@ impl TextureStorage {
 }
 
 impl TextureStorage {
+    /// A view of every layer in an array. The generated shaders sample each texture's layer out
+    /// of it, so every texture in an array shares one view.
     pub fn get_view(&self, array: ArrayIndex) -> Arc<ImageView> {
-        let array = self.arrays.get(&array).unwrap();
-        let image = array.image.clone();
+        self.arrays.get(&array).unwrap().view.clone()
+    }
+
+    /// A 2d view of one layer, for user programs' `sampler2D`s which can't pick a layer
+    pub fn get_layer_view(
+        &mut self,
+        array: ArrayIndex,
+        layer: u32,
+    ) -> anyhow::Result<Arc<ImageView>> {
+        let array = self.arrays.get_mut(&array).unwrap();
+
+        if let Some(view) = array.layer_views.get(&layer) {
+            return Ok(view.clone());
+        }
 
-        ImageView::new(
-            image.clone(),
+        let view = ImageView::new(
+            array.image.clone(),
             ImageViewCreateInfo {
-                view_type: ImageViewType::Dim2dArray,
-                format: image.format(),
+                view_type: ImageViewType::Dim2d,
+                format: array.format,
                 subresource_range: ImageSubresourceRange {
                     aspects: ImageAspects::COLOR,
-                    array_layers: 0..(array.layer_count as u32),
+                    array_layers: layer..layer + 1,
                     mip_levels: 0..array.mip_levels,
                 },
                 usage: ImageUsage::SAMPLED,
                 ..Default::default()
             },
         )
-        .unwrap()
+        .context("could not create a texture layer view")?;
+
+        array.layer_views.insert(layer, view.clone());
+
+        Ok(view)
     }
 }
 
@@ -312,6 +708,84 @@ impl TextureStorage {
     }
 }
 
+fn texture_limits(allocator: &Arc<StandardMemoryAllocator>) -> TextureLimits {
+    let physical_device = allocator.device().physical_device();
+
+    let device_limits = physical_device.properties();
+
+    let image_properties = physical_device
+        .image_format_properties(ImageFormatInfo {
+            usage: ImageUsage::SAMPLED | ImageUsage::TRANSFER_SRC | ImageUsage::TRANSFER_DST,
+            format: RGBA_FORMAT,
+            image_type: vulkano::image::ImageType::Dim2d,
+            ..Default::default()
+        })
+        .unwrap()
+        .unwrap();
+
+    TextureLimits {
+        max_dimension: device_limits
+            .max_image_dimension2_d
+            .min(image_properties.max_extent[0])
+            .min(image_properties.max_extent[1]),
+        // slots are indexed with u16s
+        max_array_layers: device_limits
+            .max_image_array_layers
+            .min(image_properties.max_array_layers)
+            .min(ArraySlotIndex::MAX as u32),
+    }
+}
+
+/// How many mip levels a mipmapped texture gets, down to 1x1 along its longest side. None if
+/// either side isn't a power of two, since those can't be halved evenly.
+pub fn mip_level_count(size: [u32; 2]) -> Option<u32> {
+    if !size[0].is_power_of_two() || !size[1].is_power_of_two() {
+        return None;
+    }
+
+    Some(size[0].max(size[1]).ilog2() + 1)
+}
+
+/// The size of one mip level. Each side is halved on its own and stops at 1, so the shorter side
+/// of a rectangular texture bottoms out first.
+pub fn mip_extent(size: [u32; 2], level: u32) -> [u32; 2] {
+    [(size[0] >> level).max(1), (size[1] >> level).max(1)]
+}
+
+/// glGetTexLevelParameter for a texture of `size` and `format`. Each level is halved like
+/// [`mip_extent`] whether or not the array has mips, and levels past the 1x1 one are empty.
+/// Returns None for any pname which isn't tracked.
+pub fn tex_level_parameter(size: [u32; 2], format: Format, level: u32, pname: u32) -> Option<i32> {
+    let has_level = size[0]
+        .max(size[1])
+        .checked_ilog2()
+        .is_some_and(|last| level <= last);
+
+    let [width, height] = if has_level {
+        mip_extent(size, level)
+    } else {
+        [0, 0]
+    };
+
+    match pname {
+        gl_constants::GL_TEXTURE_WIDTH => Some(width as i32),
+        gl_constants::GL_TEXTURE_HEIGHT => Some(height as i32),
+        gl_constants::GL_TEXTURE_INTERNAL_FORMAT => Some(gl_internal_format(format) as i32),
+        _ => None,
+    }
+}
+
+/// The size of a 4 byte per texel array, including every mip level
+pub fn texture_array_bytes(size: [u32; 2], layers: u32, mip_levels: u32) -> u64 {
+    (0..mip_levels)
+        .map(|level| {
+            let [width, height] = mip_extent(size, level);
+            width as u64 * height as u64 * 4
+        })
+        .sum::<u64>()
+        * layers as u64
+}
+
 fn get_missingno() -> RgbaImage {
     let black = Rgba(0x00_00_00_FF_u32.to_ne_bytes());
     let pink = Rgba(0xF8_00_F8_FF_u32.to_ne_bytes());
@@ -340,24 +814,73 @@ impl TextureStorage {
         false
     }
 
+    pub fn pending_update_count(&self) -> usize {
+        self.arrays.values().map(|array| array.updates.len()).sum()
+    }
+
     pub fn record_commands(
         &mut self,
         buffer: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
     ) {
+        self.record_some_commands(buffer, usize::MAX);
+    }
+
+    /// Records at most `max` pending updates, leaving the rest for later. Returns how many were
+    /// taken, including the ones that failed.
+    pub fn record_some_commands(
+        &mut self,
+        buffer: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        max: usize,
+    ) -> usize {
         let mut update_count = 0;
         let mut invalid_count = 0;
 
         for (_, array) in &mut self.arrays {
-            for (idx, update) in array.updates.drain() {
-                let mut copy =
-                    CopyBufferToImageInfo::buffer_image(update.image_data, array.image.clone());
+            let remaining = max - update_count - invalid_count;
+
+            if remaining == 0 {
+                break;
+            }
+
+            let slots = array
+                .updates
+                .keys()
+                .take(remaining)
+                .copied()
+                .collect::<Vec<_>>();
+
+            for idx in slots {
+                let update = array.updates.remove(&idx).unwrap();
 
                 let array_layers = (idx as u32)..((idx + 1) as u32);
 
-                copy.dst_image_layout = ImageLayout::TransferSrcOptimal;
-                copy.regions[0].image_subresource.array_layers = array_layers.clone();
+                let mut copies = Vec::with_capacity(1 + update.regions.len());
 
-                if let Err(e) = buffer.copy_buffer_to_image(copy) {
+                if let Some(image_data) = update.image_data {
+                    let mut copy =
+                        CopyBufferToImageInfo::buffer_image(image_data, array.image.clone());
+
+                    copy.dst_image_layout = ImageLayout::TransferSrcOptimal;
+                    copy.regions[0].image_subresource.array_layers = array_layers.clone();
+
+                    copies.push(copy);
+                }
+
+                for region in update.regions {
+                    let mut copy =
+                        CopyBufferToImageInfo::buffer_image(region.image_data, array.image.clone());
+
+                    copy.dst_image_layout = ImageLayout::TransferSrcOptimal;
+                    copy.regions = smallvec![subregion_copy(idx, region.offset, region.extent)];
+
+                    copies.push(copy);
+                }
+
+                let copied = copies
+                    .into_iter()
+                    .try_for_each(|copy| buffer.copy_buffer_to_image(copy).map(|_| ()));
+
+                if let Err(e) = copied {
                     tracing::error!(what = "failed to upload image data to GPU", why = %e, array = array.id, slot = idx);
 
                     if let Some(handle) = update.handle {
@@ -381,11 +904,13 @@ impl TextureStorage {
 
                     let base = base;
 
-                    for i in 1..array.mip_levels + 1 {
+                    for i in 1..array.mip_levels {
                         let mut region = base.clone();
 
+                        let [width, height] = mip_extent(array.size, i);
+
                         region.dst_subresource.mip_level = i;
-                        region.dst_offsets[1] = [array.size[0] >> i, array.size[1] >> i, 1];
+                        region.dst_offsets[1] = [width, height, 1];
 
                         blit.regions.push(region);
                     }
@@ -407,8 +932,45 @@ impl TextureStorage {
             }
         }
 
-        info!(what = "updating gpu textures", update_count, invalid_count);
+        debug!(what = "updating gpu textures", update_count, invalid_count);
+
+        update_count + invalid_count
+    }
+}
+
+/// How far the texture uploads of a resource reload have got
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReloadProgress {
+    pub completed: usize,
+    pub total: usize,
+}
+
+/// How many textures are uploaded per submission during a resource reload
+pub const RELOAD_CHUNK_SIZE: usize = 256;
+
+/// Calls `upload` with the size of each chunk until `total` items are done, reporting progress
+/// before the first chunk and after every chunk. Stops at the first error.
+pub fn upload_in_chunks<E>(
+    total: usize,
+    chunk_size: usize,
+    mut upload: impl FnMut(usize) -> Result<(), E>,
+    mut progress: impl FnMut(ReloadProgress),
+) -> Result<(), E> {
+    let mut completed = 0;
+
+    progress(ReloadProgress { completed, total });
+
+    while completed < total {
+        let chunk = chunk_size.min(total - completed);
+
+        upload(chunk)?;
+
+        completed += chunk;
+
+        progress(ReloadProgress { completed, total });
     }
+
+    Ok(())
 }
 
 #[derive(Debug, Clone)]
@@ -448,7 +1010,7 @@ impl TextureReference {
     }
 }
 
-#[derive(Debug, Clone, Copy, FromPrimitive, ToPrimitive)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, FromPrimitive, ToPrimitive)]
 #[repr(u32)]
 pub enum TextureFilter {
     Nearest = gl_constants::GL_NEAREST,
@@ -459,7 +1021,7 @@ pub enum TextureFilter {
     LinearMipmapLinear = gl_constants::GL_LINEAR_MIPMAP_LINEAR,
 }
 
-#[derive(Debug, Clone, Copy, FromPrimitive, ToPrimitive)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, FromPrimitive, ToPrimitive)]
 #[repr(u32)]
 pub enum TextureWrapping {
     ClampToEdge = gl_constants::GL_CLAMP_TO_EDGE,
@@ -476,6 +1038,8 @@ pub struct TextureParams {
     pub mag_filter: TextureFilter,
     pub min_lod: f32,
     pub max_lod: f32,
+    /// The highest resolution mip level that's sampled
+    pub base_level: u16,
     pub max_level: u16,
     pub wrap_s: TextureWrapping,
     pub wrap_t: TextureWrapping,
@@ -490,6 +1054,7 @@ impl Default for TextureParams {
             mag_filter: TextureFilter::Linear,
             min_lod: -1000.0,
             max_lod: 1000.0,
+            base_level: 0,
             max_level: 1000,
             wrap_s: TextureWrapping::Repeat,
             wrap_t: TextureWrapping::Repeat,
@@ -498,55 +1063,333 @@ impl Default for TextureParams {
     }
 }
 
-/// Represents a gl texture id.
-/// Returned from glGenTextures and used in glBindTexture.
-pub type GlTextureId = i32;
+impl TextureFilter {
+    /// The filter used within one mip level
+    fn to_vulkan(self) -> Filter {
+        match self {
+            Self::Nearest | Self::NearestMipmapNearest | Self::NearestMipmapLinear => {
+                Filter::Nearest
+            }
+            Self::Linear | Self::LinearMipmapNearest | Self::LinearMipmapLinear => Filter::Linear,
+        }
+    }
 
-#[derive(Debug)]
-/// A reference to a minecraft texture. Represents the resource, not the backing texture.
-pub struct TextureHandle {
-    pub resource_name: Option<String>,
-    pub texture_id: GlTextureId,
-    pub texture: SpinLock<Arc<TextureReference>>,
-    pub animation: Option<AnimationMetadata>,
-    pub mipmapped: bool,
-    pub params: SpinLock<TextureParams>,
+    /// How mip levels are blended, or None if the filter doesn't use mipmaps
+    fn mipmap_mode(self) -> Option<SamplerMipmapMode> {
+        match self {
+            Self::Nearest | Self::Linear => None,
+            Self::NearestMipmapNearest | Self::LinearMipmapNearest => {
+                Some(SamplerMipmapMode::Nearest)
+            }
+            Self::NearestMipmapLinear | Self::LinearMipmapLinear => Some(SamplerMipmapMode::Linear),
+        }
+    }
 }
 
-impl TextureHandle {
-    pub fn set_tex_param<N: num::NumCast + Debug>(&self, pname: u32, param: N) {
-        let mut l = self.params.lock();
+impl TextureWrapping {
+    fn to_vulkan(self) -> SamplerAddressMode {
+        match self {
+            Self::ClampToEdge => SamplerAddressMode::ClampToEdge,
+            Self::ClampToBorder => SamplerAddressMode::ClampToBorder,
+            Self::MirroredRepeat => SamplerAddressMode::MirroredRepeat,
+            Self::Repeat => SamplerAddressMode::Repeat,
+            Self::MirrorClampToEdge => SamplerAddressMode::MirrorClampToEdge,
+        }
+    }
+}
 
-        match pname {
-            gl_constants::GL_TEXTURE_LOD_BIAS => match param.to_f32() {
-                Some(v) => l.lod_bias = v,
-                None => {
-                    tracing::warn!(what = "glTexParameter called with invalid param for pname GL_TEXTURE_LOD_BIAS", param = ?param);
-                }
-            },
-            gl_constants::GL_TEXTURE_MIN_FILTER => {
-                match param.to_u32().and_then(TextureFilter::from_u32) {
-                    Some(v) => {
-                        l.min_filter = v;
-                    }
-                    None => {
-                        tracing::warn!(what = "glTexParameter called with invalid param for pname GL_TEXTURE_MIN_FILTER", param = ?param);
-                    }
-                }
-            }
-            gl_constants::GL_TEXTURE_MAG_FILTER => {
-                match param.to_u32().and_then(TextureFilter::from_u32) {
-                    Some(v) => {
-                        l.mag_filter = v;
-                    }
-                    None => {
-                        tracing::warn!(what = "glTexParameter called with invalid param for pname GL_TEXTURE_MAG_FILTER", param = ?param);
-                    }
-                }
-            }
-            gl_constants::GL_TEXTURE_MIN_LOD => match param.to_f32() {
-                Some(v) => l.min_lod = v,
-                None => {
+/// The texture quality from the video settings, which every sampler follows
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TextureQuality {
+    /// Minecraft's "Mipmap Levels": the highest mip level that's sampled, 0 only samples the base
+    /// level
+    pub mipmap_levels: u32,
+    /// Minecraft's "Anisotropic Filtering", 1 turns it off
+    pub anisotropy: u32,
+}
+
+impl Default for TextureQuality {
+    fn default() -> Self {
+        // minecraft's defaults
+        Self {
+            mipmap_levels: 4,
+            anisotropy: 1,
+        }
+    }
+}
+
+/// The LODs are stored in 1/256ths, which is finer than any gpu's LOD precision
+const LOD_QUANTIZATION: f32 = 256.0;
+
+fn quantize_lod(lod: f32) -> i32 {
+    (lod * LOD_QUANTIZATION).round() as i32
+}
+
+/// The hashable part of [`TextureParams`] which affects a sampler
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SamplerKey {
+    lod_bias: i32,
+    min_filter: TextureFilter,
+    mag_filter: TextureFilter,
+    min_lod: i32,
+    max_lod: i32,
+    base_level: u16,
+    wrap: [TextureWrapping; 3],
+}
+
+impl TextureParams {
+    pub fn sampler_key(&self) -> SamplerKey {
+        SamplerKey {
+            lod_bias: quantize_lod(self.lod_bias),
+            min_filter: self.min_filter,
+            mag_filter: self.mag_filter,
+            min_lod: quantize_lod(self.min_lod),
+            max_lod: quantize_lod(self.max_lod),
+            base_level: self.base_level,
+            wrap: [self.wrap_s, self.wrap_t, self.wrap_r],
+        }
+    }
+}
+
+impl SamplerKey {
+    /// Builds the create info for this key. `max_lod_bias` is the device's limit, since GL
+    /// doesn't have one. `max_anisotropy` is None if the device can't filter anisotropically.
+    pub fn create_info(
+        &self,
+        max_lod_bias: f32,
+        quality: TextureQuality,
+        max_anisotropy: Option<f32>,
+    ) -> SamplerCreateInfo {
+        let lod_bias = self.lod_bias as f32 / LOD_QUANTIZATION;
+        let min_lod = self.min_lod as f32 / LOD_QUANTIZATION;
+        // gl allows an inverted range, vulkan doesn't
+        let max_lod = (self.max_lod as f32 / LOD_QUANTIZATION)
+            .min(quality.mipmap_levels as f32)
+            .max(min_lod);
+
+        let anisotropy = match max_anisotropy {
+            Some(limit) if quality.anisotropy > 1 => Some((quality.anisotropy as f32).min(limit)),
+            _ => None,
+        };
+
+        // the views include every mip level, so levels below the base are skipped by clamping
+        // the LOD instead
+        let base_level = self.base_level as f32;
+
+        let (mipmap_mode, lod) = match self.min_filter.mipmap_mode() {
+            Some(mode) => (mode, min_lod.max(base_level)..=max_lod.max(base_level)),
+            // the spec's recommended way to sample only the base level
+            None => (SamplerMipmapMode::Nearest, base_level..=base_level + 0.25),
+        };
+
+        SamplerCreateInfo {
+            mag_filter: self.mag_filter.to_vulkan(),
+            min_filter: self.min_filter.to_vulkan(),
+            mipmap_mode,
+            address_mode: self.wrap.map(TextureWrapping::to_vulkan),
+            mip_lod_bias: lod_bias.clamp(-max_lod_bias, max_lod_bias),
+            anisotropy,
+            lod,
+            ..Default::default()
+        }
+    }
+}
+
+/// What a texture unit samples
+#[derive(Debug, Clone)]
+pub struct TextureBinding {
+    /// The whole array the texture is in, see [`TextureStorage::get_view`]
+    pub view: Arc<ImageView>,
+    pub sampler: Arc<Sampler>,
+    /// The layer of the array that's sampled, which is the current frame's for animated textures
+    pub layer: u32,
+}
+
+/// Identifies a descriptor set by the address of its layout and of each unit's view and sampler
+pub type TextureSetKey = (usize, SmallVec<[(usize, usize); MAX_SHADER_TEXTURE_UNITS]>);
+
+pub fn texture_set_key(
+    layout: &Arc<DescriptorSetLayout>,
+    textures: &[TextureBinding],
+) -> TextureSetKey {
+    let units = textures
+        .iter()
+        .map(|texture| {
+            (
+                Arc::as_ptr(&texture.view) as usize,
+                Arc::as_ptr(&texture.sampler) as usize,
+            )
+        })
+        .collect();
+
+    (Arc::as_ptr(layout) as usize, units)
+}
+
+/// Past this many sets the cache is emptied, since the layouts of rebuilt pipelines and the
+/// samplers of an old quality are never looked up again
+const TEXTURE_SET_CACHE_SIZE: usize = 1024;
+
+/// The descriptor sets of textured draws. Views are per array and samplers per params, so every
+/// draw sampling the same arrays with the same params shares a set, and only the layers change.
+/// A set holds on to its layout, views and samplers, so the addresses in its key can't be reused
+/// while it's cached.
+#[derive(Debug)]
+pub struct TextureSetCache<S = Arc<PersistentDescriptorSet>> {
+    sets: HashMap<TextureSetKey, S>,
+}
+
+impl<S> Default for TextureSetCache<S> {
+    fn default() -> Self {
+        Self {
+            sets: HashMap::new(),
+        }
+    }
+}
+
+impl<S: Clone> TextureSetCache<S> {
+    /// Returns the cached set for `key`, or calls `create` to make one. Nothing is cached if
+    /// `create` fails.
+    pub fn get_or_create<E>(
+        &mut self,
+        key: TextureSetKey,
+        create: impl FnOnce() -> Result<S, E>,
+    ) -> Result<S, E> {
+        if let Some(set) = self.sets.get(&key) {
+            return Ok(set.clone());
+        }
+
+        if self.sets.len() >= TEXTURE_SET_CACHE_SIZE {
+            self.sets.clear();
+        }
+
+        let set = create()?;
+        self.sets.insert(key, set.clone());
+
+        Ok(set)
+    }
+
+    pub fn len(&self) -> usize {
+        self.sets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sets.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.sets.clear();
+    }
+}
+
+/// Samplers by the params they were made for, so that textures with the same params share one
+#[derive(Debug)]
+pub struct SamplerCache<S = Arc<Sampler>> {
+    samplers: HashMap<SamplerKey, S>,
+}
+
+impl<S> Default for SamplerCache<S> {
+    fn default() -> Self {
+        Self {
+            samplers: HashMap::new(),
+        }
+    }
+}
+
+impl<S: Clone> SamplerCache<S> {
+    /// Returns the cached sampler for `params`, or calls `create` to make one. Nothing is cached
+    /// if `create` fails, so the next call tries again.
+    pub fn get_or_create<E>(
+        &mut self,
+        params: &TextureParams,
+        create: impl FnOnce(SamplerKey) -> Result<S, E>,
+    ) -> Result<S, E> {
+        let key = params.sampler_key();
+
+        if let Some(sampler) = self.samplers.get(&key) {
+            return Ok(sampler.clone());
+        }
+
+        let sampler = create(key)?;
+        self.samplers.insert(key, sampler.clone());
+
+        Ok(sampler)
+    }
+
+    pub fn len(&self) -> usize {
+        self.samplers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samplers.is_empty()
+    }
+
+    /// Drops every sampler, so that they're made again on their next bind
+    pub fn clear(&mut self) {
+        self.samplers.clear();
+    }
+}
+
+/// Represents a gl texture id.
+/// Returned from glGenTextures and used in glBindTexture.
+pub type GlTextureId = i32;
+
+/// Hands out the next texture id. Ids only ever count up, so a freed id is never given to a new
+/// texture while something may still refer to the old one.
+pub fn take_texture_id(next: &mut GlTextureId) -> GlTextureId {
+    let id = *next;
+    *next += 1;
+    id
+}
+
+/// How long glGetTexParameter waits for a texture's params before giving up, since they're only
+/// ever held briefly
+const PARAMS_LOCK_TIMEOUT: Duration = Duration::from_millis(50);
+
+#[derive(Debug)]
+/// A reference to a minecraft texture. Represents the resource, not the backing texture.
+pub struct TextureHandle {
+    pub resource_name: Option<String>,
+    pub texture_id: GlTextureId,
+    pub texture: SpinLock<Arc<TextureReference>>,
+    pub animation: Option<AnimationMetadata>,
+    pub mipmapped: bool,
+    pub params: SpinLock<TextureParams>,
+}
+
+impl TextureHandle {
+    pub fn set_tex_param<N: num::NumCast + Debug>(&self, pname: u32, param: N) {
+        let mut l = self.params.lock();
+
+        match pname {
+            gl_constants::GL_TEXTURE_LOD_BIAS => match param.to_f32() {
+                Some(v) => l.lod_bias = v,
+                None => {
+                    tracing::warn!(what = "glTexParameter called with invalid param for pname GL_TEXTURE_LOD_BIAS", param = ?param);
+                }
+            },
+            gl_constants::GL_TEXTURE_MIN_FILTER => {
+                match param.to_u32().and_then(TextureFilter::from_u32) {
+                    Some(v) => {
+                        l.min_filter = v;
+                    }
+                    None => {
+                        tracing::warn!(what = "glTexParameter called with invalid param for pname GL_TEXTURE_MIN_FILTER", param = ?param);
+                    }
+                }
+            }
+            gl_constants::GL_TEXTURE_MAG_FILTER => {
+                match param.to_u32().and_then(TextureFilter::from_u32) {
+                    Some(v) => {
+                        l.mag_filter = v;
+                    }
+                    None => {
+                        tracing::warn!(what = "glTexParameter called with invalid param for pname GL_TEXTURE_MAG_FILTER", param = ?param);
+                    }
+                }
+            }
+            gl_constants::GL_TEXTURE_MIN_LOD => match param.to_f32() {
+                Some(v) => l.min_lod = v,
+                None => {
                     tracing::warn!(what = "glTexParameter called with invalid param for pname GL_TEXTURE_MIN_LOD", param = ?param);
                 }
             },
@@ -556,6 +1399,12 @@ impl TextureHandle {
                     tracing::warn!(what = "glTexParameter called with invalid param for pname GL_TEXTURE_MAX_LOD", param = ?param);
                 }
             },
+            gl_constants::GL_TEXTURE_BASE_LEVEL => match param.to_u16() {
+                Some(v) => l.base_level = v,
+                None => {
+                    tracing::warn!(what = "glTexParameter called with invalid param for pname GL_TEXTURE_BASE_LEVEL", param = ?param);
+                }
+            },
             gl_constants::GL_TEXTURE_WRAP_S => {
                 match param.to_u32().and_then(TextureWrapping::from_u32) {
                     Some(v) => {
@@ -593,7 +1442,13 @@ impl TextureHandle {
     }
 
     pub fn get_tex_param<N: num::Num + num::NumCast + Debug>(&self, pname: u32) -> N {
-        let l = self.params.lock();
+        let Some(l) = self.params.lock_timeout(PARAMS_LOCK_TIMEOUT) else {
+            tracing::warn!(
+                what = "glGetTexParameter() timed out waiting for the texture's params",
+                pname = pname
+            );
+            return N::zero();
+        };
 
         match pname {
             gl_constants::GL_TEXTURE_LOD_BIAS => N::from(l.lod_bias).unwrap_or(N::zero()),
@@ -601,6 +1456,7 @@ impl TextureHandle {
             gl_constants::GL_TEXTURE_MAG_FILTER => N::from(l.mag_filter).unwrap_or(N::zero()),
             gl_constants::GL_TEXTURE_MIN_LOD => N::from(l.min_lod).unwrap_or(N::zero()),
             gl_constants::GL_TEXTURE_MAX_LOD => N::from(l.max_lod).unwrap_or(N::zero()),
+            gl_constants::GL_TEXTURE_BASE_LEVEL => N::from(l.base_level).unwrap_or(N::zero()),
             gl_constants::GL_TEXTURE_WRAP_S => N::from(l.wrap_s).unwrap_or(N::zero()),
             gl_constants::GL_TEXTURE_WRAP_T => N::from(l.wrap_t).unwrap_or(N::zero()),
             gl_constants::GL_TEXTURE_WRAP_R => N::from(l.wrap_r).unwrap_or(N::zero()),
@@ -625,6 +1481,85 @@ pub enum TextureError {
     LengthMismatch(usize, usize),
     #[error("could not load texture: {0}")]
     LoadError(#[from] TextureLoadError),
+    #[error("sub image did not line up with an animation frame (x = {0}, y = {1}, width = {2}, height = {3})")]
+    BadSubImage(u32, u32, u32, u32),
+    #[error("sub image went past the texture or across animation frames (x = {0}, y = {1}, width = {2}, height = {3})")]
+    BadSubRegion(u32, u32, u32, u32),
+    #[error("texture does not have mip level {0} (it has {1} levels)")]
+    BadMipLevel(u32, u32),
+    #[error("no texture named {0} has been loaded")]
+    UnknownTexture(String),
+    #[error("atlas texture {0} does not exist")]
+    MissingAtlas(GlTextureId),
+    #[error("texture is {0}x{1}, but the device only supports textures up to {2}x{2}")]
+    TooLarge(u32, u32, u32),
+    #[error("texture has {0} frames, but the device only supports up to {1} frames")]
+    TooManyFrames(usize, u32),
+    #[error("compressed textures can only be replaced as a whole")]
+    Compressed,
+}
+
+/// Where a glTextureSubImage2D rectangle lands: the frame it's in, and its offset within that
+/// frame. Frames are addressed like [`get_sub_image_frame`] does. None if the rectangle goes past
+/// the texture or straddles two frames.
+pub fn get_subregion_target(
+    frame_size: [u32; 2],
+    frame_count: usize,
+    offset: [u32; 2],
+    size: [u32; 2],
+) -> Option<(usize, [u32; 2])> {
+    let [width, height] = frame_size;
+
+    if height == 0 || offset[0].checked_add(size[0])? > width {
+        return None;
+    }
+
+    let frame = (offset[1] / height) as usize;
+    let y = offset[1] % height;
+
+    if frame >= frame_count || y.checked_add(size[1])? > height {
+        return None;
+    }
+
+    Some((frame, [offset[0], y]))
+}
+
+/// Copies a tightly packed sub-rectangle into the base level of one layer
+pub fn subregion_copy(slot: ArraySlotIndex, offset: [u32; 2], extent: [u32; 2]) -> BufferImageCopy {
+    BufferImageCopy {
+        image_subresource: ImageSubresourceLayers {
+            aspects: ImageAspects::COLOR,
+            mip_level: 0,
+            array_layers: slot as u32..slot as u32 + 1,
+        },
+        image_offset: [offset[0], offset[1], 0],
+        image_extent: [extent[0], extent[1], 1],
+        ..Default::default()
+    }
+}
+
+/// Finds the animation frame covered by a glTexSubImage2D call. Frames are addressed as if the
+/// texture was still a vertical spritesheet (frame `i` starts at `y = i * height`), which is how
+/// minecraft lays them out.
+pub fn get_sub_image_frame(
+    frame_size: [u32; 2],
+    frame_count: usize,
+    offset: [u32; 2],
+    size: [u32; 2],
+) -> Option<usize> {
+    let height = frame_size[1];
+
+    if size != frame_size || offset[0] != 0 || offset[1] % height != 0 {
+        return None;
+    }
+
+    let frame = (offset[1] / height) as usize;
+
+    if frame < frame_count {
+        Some(frame)
+    } else {
+        None
+    }
 }
 
 impl TextureStorage {
@@ -637,6 +1572,29 @@ impl TextureStorage {
         let image = image.load()?;
 
         image.validate()?;
+        self.limits.check_image(&image)?;
+
+        if let TextureImage::Compressed { format, data, .. } = &image {
+            let array = self.arrays.get(&indices.array).unwrap();
+
+            if array.format != *format || indices.slots.len() != 1 {
+                return Err(TextureError::BadHandle);
+            }
+
+            let source_buffer = self.create_compressed_buffer(*format, array.size, data);
+
+            self.update_queue(indices.array, indices.slots[0]).insert(
+                indices.slots[0],
+                TextureUpdate {
+                    image_data: Some(source_buffer),
+                    regions: Vec::new(),
+                    handle: owning_handle,
+                    animation: None,
+                },
+            );
+
+            return Ok(());
+        }
 
         let frames = image.get_frames();
 
@@ -649,15 +1607,154 @@ impl TextureStorage {
 
         let frame_pixel_size = (image.width() * image.height()) as usize;
 
-        let mut image_data = Vec::with_capacity(frame_pixel_size * frames.len());
+        let source_buffer = self.create_source_buffer(&frames);
+
+        for (i, slot) in indices.slots.iter().enumerate() {
+            self.update_queue(indices.array, *slot).insert(
+                *slot,
+                TextureUpdate {
+                    image_data: Some(
+                        source_buffer.clone().slice(
+                            (i * frame_pixel_size) as u64..((i + 1) * frame_pixel_size) as u64,
+                        ),
+                    ),
+                    regions: Vec::new(),
+                    handle: owning_handle.clone(),
+                    animation: image.get_animation().cloned(),
+                },
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Replaces a single animation frame of an already-uploaded texture
+    pub fn enqueue_frame_update(
+        &mut self,
+        indices: &TextureStorageIndices,
+        frame: usize,
+        image: &RgbaImage,
+        owning_handle: Option<Arc<TextureHandle>>,
+    ) -> Result<(), TextureError> {
+        let Some(slot) = indices.slots.get(frame) else {
+            return Err(TextureError::LengthMismatch(frame + 1, indices.slots.len()));
+        };
+
+        self.check_uncompressed(indices)?;
 
-        for frame in &frames {
+        let source_buffer = self.create_source_buffer(&[image]);
+
+        let updates = self.update_queue(indices.array, *slot);
+
+        let animation = updates.get(slot).and_then(|u| u.animation.clone());
+
+        updates.insert(
+            *slot,
+            TextureUpdate {
+                image_data: Some(source_buffer),
+                regions: Vec::new(),
+                handle: owning_handle,
+                animation,
+            },
+        );
+
+        Ok(())
+    }
+
+    /// Replaces a sub-rectangle of one frame, leaving the rest of it alone. Only the
+    /// rectangle's pixels are staged. `offset` is within the frame, and must already have been
+    /// checked with [`get_subregion_target`].
+    pub fn enqueue_subregion_update(
+        &mut self,
+        indices: &TextureStorageIndices,
+        frame: usize,
+        offset: [u32; 2],
+        image: &RgbaImage,
+        owning_handle: Option<Arc<TextureHandle>>,
+    ) -> Result<(), TextureError> {
+        let Some(slot) = indices.slots.get(frame) else {
+            return Err(TextureError::LengthMismatch(frame + 1, indices.slots.len()));
+        };
+
+        self.check_uncompressed(indices)?;
+
+        let region = RegionUpdate {
+            image_data: self.create_source_buffer(&[image]),
+            offset,
+            extent: [image.width(), image.height()],
+        };
+
+        let updates = self.update_queue(indices.array, *slot);
+
+        // a pending update of the whole layer has to be copied first
+        match updates.get_mut(slot) {
+            Some(update) => update.regions.push(region),
+            None => {
+                updates.insert(
+                    *slot,
+                    TextureUpdate {
+                        image_data: None,
+                        regions: vec![region],
+                        handle: owning_handle,
+                        animation: None,
+                    },
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The size and format of the array the slots are in
+    pub fn slot_format(&self, indices: &TextureStorageIndices) -> ([u32; 2], Format) {
+        let array = self.arrays.get(&indices.array).unwrap();
+
+        (array.size, array.format)
+    }
+
+    fn check_uncompressed(&self, indices: &TextureStorageIndices) -> Result<(), TextureError> {
+        if self.arrays.get(&indices.array).unwrap().format != RGBA_FORMAT {
+            return Err(TextureError::Compressed);
+        }
+
+        Ok(())
+    }
+
+    fn create_source_buffer(&self, frames: &[&RgbaImage]) -> Subbuffer<[u32]> {
+        let mut image_data = Vec::with_capacity(
+            frames
+                .iter()
+                .map(|f| (f.width() * f.height()) as usize)
+                .sum(),
+        );
+
+        for frame in frames {
             for pixel in frame.pixels() {
-                let [r, g, b, a] = pixel.0.clone();
-                image_data.push(u32::from_ne_bytes([a, b, g, r]));
+                image_data.push(pack_gpu_pixel(*pixel));
             }
         }
 
+        self.create_upload_buffer(&image_data)
+    }
+
+    /// Stages BC blocks as they are. Every block is 8 or 16 bytes, so they always fill whole words.
+    fn create_compressed_buffer(
+        &self,
+        format: Format,
+        size: [u32; 2],
+        data: &[u8],
+    ) -> Subbuffer<[u32]> {
+        let len = compressed_size(format, size[0], size[1]).unwrap();
+
+        let words = data[..len]
+            .chunks_exact(4)
+            .map(|word| u32::from_ne_bytes(word.try_into().unwrap()))
+            .collect::<Vec<_>>();
+
+        self.create_upload_buffer(&words)
+    }
+
+    fn create_upload_buffer(&self, image_data: &[u32]) -> Subbuffer<[u32]> {
         let source_buffer = vulkano::buffer::Buffer::new_slice::<u32>(
             self.allocator.clone(),
             vulkano::buffer::BufferCreateInfo {
@@ -674,25 +1771,70 @@ impl TextureStorage {
 
         {
             let mut guard = source_buffer.write().unwrap();
-            guard.copy_from_slice(&image_data);
+            guard.copy_from_slice(image_data);
         }
 
-        let array = self.arrays.get_mut(&indices.array).unwrap();
+        source_buffer
+    }
+
+    pub fn get_array_size(&self, array: ArrayIndex) -> [u32; 2] {
+        self.arrays.get(&array).unwrap().size
+    }
+
+    /// Records a copy of every slot in `indices` into a host-visible buffer. The slots are copied
+    /// back to back, so the buffer holds the frames as one vertical spritesheet.
+    pub fn record_readback(
+        &self,
+        buffer: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        indices: &TextureStorageIndices,
+        mip_level: u32,
+    ) -> anyhow::Result<(Subbuffer<[u32]>, [u32; 2])> {
+        self.check_uncompressed(indices)?;
+
+        let array = self.arrays.get(&indices.array).unwrap();
+
+        // mip_levels counts the base level too
+        let level_count = array.mip_levels;
+
+        if mip_level >= level_count {
+            return Err(TextureError::BadMipLevel(mip_level, level_count).into());
+        }
+
+        let size = mip_extent(array.size, mip_level);
+        let frame_pixel_size = (size[0] * size[1]) as u64;
+
+        let dest_buffer = vulkano::buffer::Buffer::new_slice::<u32>(
+            self.allocator.clone(),
+            vulkano::buffer::BufferCreateInfo {
+                usage: BufferUsage::TRANSFER_DST,
+                ..Default::default()
+            },
+            vulkano::memory::allocator::AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::HOST_RANDOM_ACCESS,
+                ..Default::default()
+            },
+            frame_pixel_size * indices.slots.len() as u64,
+        )?;
 
         for (i, slot) in indices.slots.iter().enumerate() {
-            array.updates.insert(
-                *slot,
-                TextureUpdate {
-                    image_data: source_buffer
-                        .clone()
-                        .slice((i * frame_pixel_size) as u64..((i + 1) * frame_pixel_size) as u64),
-                    handle: owning_handle.clone(),
-                    animation: image.get_animation().cloned(),
-                },
+            let i = i as u64;
+            let mut copy = CopyImageToBufferInfo::image_buffer(
+                array.image.clone(),
+                dest_buffer
+                    .clone()
+                    .slice(i * frame_pixel_size..(i + 1) * frame_pixel_size),
             );
+
+            copy.regions[0].image_subresource.mip_level = mip_level;
+            copy.regions[0].image_subresource.array_layers = (*slot as u32)..(*slot as u32 + 1);
+            copy.regions[0].image_extent = [size[0], size[1], 1];
+
+            buffer.copy_image_to_buffer(copy).with_context(|| {
+                format!("could not copy slot {slot} of texture array {}", array.id)
+            })?;
         }
 
-        Ok(())
+        Ok((dest_buffer, [size[0], size[1] * indices.slots.len() as u32]))
     }
 
     pub fn enqueue_reference_update(
@@ -701,14 +1843,37 @@ impl TextureStorage {
         image: TextureImage,
         owning_handle: Option<Arc<TextureHandle>>,
     ) -> Result<(), TextureError> {
+        // must happen before a new texture is allocated for the image, which can't fail
+        self.limits.check_image(&image)?;
+
+        let image = resolve_compression(image.load()?, self.supports_bc)?;
+
+        // blits can't write compressed images, so mipmapped textures are left uncompressed
+        let image = match owning_handle.as_ref() {
+            Some(handle) if self.supports_bc && !handle.mipmapped => {
+                self.compression.recompress(image)
+            }
+            _ => image,
+        };
+
+        let format = match &image {
+            TextureImage::Compressed { format, .. } => *format,
+            _ => RGBA_FORMAT,
+        };
+
+        // deferred updates can't write over slots which are still being sampled
+        let reallocate = self.deferred_swaps.is_some() && owning_handle.is_some();
+
         let texture = match tex_ref {
             TextureReference::None => None,
+            TextureReference::Managed(_) if reallocate => None,
             TextureReference::Managed(tex) => {
                 let array = self.arrays.get(&tex.indices.array).unwrap();
 
                 if array.size[0] != image.width()
                     || array.size[1] != image.height()
-                    || tex.indices.slots.len() != image.get_frames().len()
+                    || array.format != format
+                    || tex.indices.slots.len() != image.frame_count()
                 {
                     // image isn't compatible with the allocated texture, allocate another one
                     None
@@ -727,11 +1892,19 @@ impl TextureStorage {
                     let tex_ref = Arc::new(self.allocate(
                         image.width(),
                         image.height(),
-                        image.get_frames().len() as u16,
+                        image.frame_count() as u16,
                         handle.mipmapped,
+                        format,
                     ));
 
-                    handle.texture.set(tex_ref.clone());
+                    if self.swap_in(handle, tex_ref.clone()) {
+                        if let TextureReference::Managed(tex) = tex_ref.as_ref() {
+                            let array = tex.indices.array;
+
+                            self.unsampled
+                                .extend(tex.indices.slots.iter().map(|slot| (array, *slot)));
+                        }
+                    }
 
                     temp = Some(tex_ref);
 
@@ -753,12 +1926,115 @@ impl TextureStorage {
         tex_handle: &Arc<TextureHandle>,
         image: TextureImage,
     ) -> Result<(), TextureError> {
-        let texture = tex_handle.texture.get();
+        let texture = self.current_texture(tex_handle);
 
         self.enqueue_reference_update(&texture, image, Some(tex_handle.clone()))
     }
 }
 
+/// How uploaded RGBA textures are re-compressed into BC formats, picked with
+/// `glHint(GL_TEXTURE_COMPRESSION_HINT, ...)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TextureCompression {
+    /// Textures are stored uncompressed.
+    #[default]
+    None,
+    /// Uses the fast BC encoder, trading some quality for upload time.
+    Fast,
+    /// Uses the slow, high quality BC encoder.
+    HighQuality,
+}
+
+impl TextureCompression {
+    pub fn from_hint(hint: u32) -> Option<Self> {
+        match hint {
+            gl_constants::GL_FASTEST => Some(Self::None),
+            gl_constants::GL_DONT_CARE => Some(Self::Fast),
+            gl_constants::GL_NICEST => Some(Self::HighQuality),
+            _ => None,
+        }
+    }
+
+    /// Encodes an uploaded RGBA image into BC blocks. Only single images whose sides are
+    /// multiples of 4 are, and like any compressed texture they can't be updated with
+    /// glTexSubImage2D afterwards.
+    pub fn recompress(self, image: TextureImage) -> TextureImage {
+        match (self, image) {
+            (Self::None, image) => image,
+            (compression, TextureImage::Static { image })
+                if image.width() % 4 == 0 && image.height() % 4 == 0 =>
+            {
+                encode_bc(&image, compression == Self::HighQuality)
+            }
+            (_, image) => image,
+        }
+    }
+}
+
+/// The textures by GL id and by resource name. Unlike the rest of [`TextureManager`], these
+/// don't need the gpu.
+#[derive(Debug)]
+pub struct TextureHandles {
+    pub by_id: Ref<HashMap<GlTextureId, Arc<TextureHandle>>>,
+    pub by_name: Ref<HashMap<String, Arc<TextureHandle>>>,
+    next_id: GlTextureId,
+}
+
+impl Default for TextureHandles {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TextureHandles {
+    pub fn new() -> Self {
+        Self {
+            by_id: Ref::new(HashMap::new()),
+            by_name: Ref::new(HashMap::new()),
+            next_id: 0,
+        }
+    }
+
+    pub fn create_texture(&mut self, resource_name: Option<String>) -> Arc<TextureHandle> {
+        let id = take_texture_id(&mut self.next_id);
+
+        let handle = Arc::new(TextureHandle {
+            resource_name: resource_name.clone(),
+            texture_id: id,
+            texture: SpinLock::new(Arc::new(TextureReference::None)),
+            animation: None,
+            mipmapped: false,
+            params: SpinLock::new(TextureParams::default()),
+        });
+
+        self.by_id.write().insert(id, handle.clone());
+
+        if let Some(name) = resource_name {
+            self.by_name.write().insert(name.clone(), handle.clone());
+        }
+
+        handle
+    }
+
+    pub fn create_textures(&mut self, count: usize) -> Vec<GlTextureId> {
+        (0..count)
+            .map(|_| self.create_texture(None).texture_id)
+            .collect()
+    }
+
+    pub fn free_texture(&mut self, id: GlTextureId) {
+        if let Some(t) = self.by_id.write().remove(&id) {
+            if let Some(name) = t.resource_name.as_ref() {
+                self.by_name.write().remove(name);
+            }
+        }
+    }
+
+    pub fn get(&self, id: GlTextureId) -> Option<Arc<TextureHandle>> {
+        self.by_id.read().get(&id).cloned()
+    }
+}
+
 #[derive(Derivative)]
 #[derivative(Debug)]
 pub struct TextureManager {
@@ -773,11 +2049,26 @@ pub struct TextureManager {
     pub is_resource_pack_reload: bool,
     pub unupdated_textures: HashSet<String>,
 
-    pub textures_by_id: Ref<HashMap<GlTextureId, Arc<TextureHandle>>>,
-    pub textures_by_name: Ref<HashMap<String, Arc<TextureHandle>>>,
-    pub next_texture_id: GlTextureId,
+    pub handles: TextureHandles,
 
     pub lookup: Option<Ref<TextureLookup>>,
+    /// The sprites of each atlas texture, registered by the mod during resource reload
+    pub atlas_sprites: HashMap<GlTextureId, Vec<Arc<TextureAtlasSprite>>>,
+
+    pub pixel_transfer: PixelTransfer,
+
+    /// Change with [`TextureManager::set_quality`], so that the samplers are rebuilt
+    pub quality: TextureQuality,
+    #[derivative(Debug = "ignore")]
+    pub samplers: SamplerCache,
+    #[derivative(Debug = "ignore")]
+    pub texture_sets: TextureSetCache,
+
+    /// Resource reloads submit their uploads without waiting for them, and the reloaded textures
+    /// are swapped in by [`TextureManager::poll_texture_reload`] on a later frame
+    pub async_reload: bool,
+    /// When the reload's uploads began, for logging
+    reload_started: Option<Instant>,
 }
 
 impl TextureManager {
@@ -791,53 +2082,140 @@ impl TextureManager {
             is_resource_pack_reload: false,
             unupdated_textures: HashSet::new(),
 
-            textures_by_id: Ref::new(HashMap::new()),
-            textures_by_name: Ref::new(HashMap::new()),
-            next_texture_id: 0,
+            handles: TextureHandles::new(),
 
             lookup: None,
+            atlas_sprites: HashMap::new(),
+
+            pixel_transfer: PixelTransfer::default(),
+
+            quality: TextureQuality::default(),
+            samplers: SamplerCache::default(),
+            texture_sets: TextureSetCache::default(),
+
+            async_reload: false,
+            reload_started: None,
         }
     }
 
     pub fn begin_texture_reload(&mut self) {
+        // reloads are rare enough that the previous one can be waited for
+        self.texture_storage.finish_pending_reload();
+
+        if self.async_reload {
+            self.texture_storage.defer_swaps();
+        }
+
         self.is_resource_pack_reload = true;
-        self.unupdated_textures = self.textures_by_name.read().keys().cloned().collect();
+        self.unupdated_textures = self.handles.by_name.read().keys().cloned().collect();
+        self.atlas_sprites.clear();
     }
 
     pub fn create_texture(&mut self, resource_name: Option<String>) -> Arc<TextureHandle> {
-        let id = self.next_texture_id;
-        self.next_texture_id += 1;
+        self.handles.create_texture(resource_name)
+    }
 
-        let handle = Arc::new(TextureHandle {
-            resource_name: resource_name.clone(),
-            texture_id: id,
-            texture: SpinLock::new(Arc::new(TextureReference::None)),
-            animation: None,
-            mipmapped: false,
-            params: SpinLock::new(TextureParams::default()),
-        });
+    /// Creates `count` unnamed textures, for glGenTextures calls which ask for several at once
+    pub fn create_textures(&mut self, count: usize) -> Vec<GlTextureId> {
+        self.handles.create_textures(count)
+    }
 
-        self.textures_by_id.write().insert(id, handle.clone());
+    pub fn free_texture(&mut self, id: GlTextureId) {
+        self.handles.free_texture(id);
+    }
 
-        if let Some(name) = resource_name {
-            self.textures_by_name
-                .write()
-                .insert(name.clone(), handle.clone());
-        }
+    pub fn get_texture_handle(&self, id: GlTextureId) -> Option<Arc<TextureHandle>> {
+        self.handles.get(id)
+    }
 
-        handle
+    /// Resolves the texture bound to a unit into the view, sampler and layer to bind for it. Units
+    /// without an uploaded texture sample the white dummy texture. Animated textures are bound
+    /// at the lookup's current frame.
+    pub fn texture_binding(
+        &mut self,
+        texture: Option<GlTextureId>,
+    ) -> anyhow::Result<TextureBinding> {
+        self.resolve_texture(texture).map(|(_, binding)| binding)
     }
 
-    pub fn free_texture(&mut self, id: GlTextureId) {
-        if let Some(t) = self.textures_by_id.write().remove(&id) {
-            if let Some(name) = t.resource_name.as_ref() {
-                self.textures_by_name.write().remove(name);
-            }
-        }
+    /// [`Self::texture_binding`] and the array its view is of
+    fn resolve_texture(
+        &mut self,
+        texture: Option<GlTextureId>,
+    ) -> anyhow::Result<(ArrayIndex, TextureBinding)> {
+        let handle = texture.and_then(|id| self.get_texture_handle(id));
+
+        let reference = match handle.as_ref().map(|handle| handle.texture.lock().clone()) {
+            Some(reference) if matches!(*reference, TextureReference::Managed(_)) => reference,
+            _ => self.texture_storage.get_dummy().clone(),
+        };
+
+        let tick = self
+            .lookup
+            .as_ref()
+            .map_or(0, |lookup| lookup.read().tick());
+        let frame = handle
+            .as_ref()
+            .map_or(0, |handle| animation_frame(handle.animation.as_ref(), tick));
+
+        // params are only ever held briefly, so this doesn't wait long
+        let params = handle
+            .map(|handle| handle.params.lock().clone())
+            .unwrap_or_default();
+
+        let indices = reference.unwrap_indices();
+        let slot = indices.slots.get(frame).unwrap_or(&indices.slots[0]);
+
+        let binding = TextureBinding {
+            view: self.texture_storage.get_view(indices.array),
+            sampler: self.get_sampler(&params)?,
+            layer: *slot as u32,
+        };
+
+        Ok((indices.array, binding))
     }
 
-    pub fn get_texture_handle(&self, id: GlTextureId) -> Option<Arc<TextureHandle>> {
-        self.textures_by_id.read().get(&id).cloned()
+    /// Like [`Self::texture_binding`], but with a view of just the texture's layer
+    pub fn program_texture_binding(
+        &mut self,
+        texture: Option<GlTextureId>,
+    ) -> anyhow::Result<TextureBinding> {
+        let (array, binding) = self.resolve_texture(texture)?;
+
+        Ok(TextureBinding {
+            view: self.texture_storage.get_layer_view(array, binding.layer)?,
+            ..binding
+        })
+    }
+
+    /// Gets the sampler for a texture's params, which is only created the first time those params
+    /// are seen
+    pub fn get_sampler(&mut self, params: &TextureParams) -> anyhow::Result<Arc<Sampler>> {
+        let device = self.allocators.read().memory_allocator.device().clone();
+        let quality = self.quality;
+
+        self.samplers.get_or_create(params, |key| {
+            let properties = device.physical_device().properties();
+            let max_anisotropy = device
+                .enabled_features()
+                .sampler_anisotropy
+                .then_some(properties.max_sampler_anisotropy);
+
+            let create_info =
+                key.create_info(properties.max_sampler_lod_bias, quality, max_anisotropy);
+
+            Sampler::new(device.clone(), create_info).context("could not create a sampler")
+        })
+    }
+
+    /// Existing textures pick up the new quality the next time they're bound, since their
+    /// samplers are rebuilt
+    pub fn set_quality(&mut self, quality: TextureQuality) {
+        if self.quality != quality {
+            self.quality = quality;
+            self.samplers.clear();
+            self.texture_sets.clear();
+        }
     }
 
     pub fn enqueue_sprite(
@@ -848,7 +2226,7 @@ impl TextureManager {
     ) -> Result<Arc<TextureHandle>, anyhow::Error> {
         self.unupdated_textures.remove(&name);
 
-        let handle = self.textures_by_name.read().get(&name).cloned();
+        let handle = self.handles.by_name.read().get(&name).cloned();
         let handle = match handle {
             Some(handle) => handle,
             None => self.create_texture(Some(name.clone())),
@@ -873,15 +2251,62 @@ impl TextureManager {
         Ok(handle)
     }
 
+    /// Adds an already loaded texture to an atlas, covering the [min, max] uv rect
+    pub fn register_atlas_sprite(
+        &mut self,
+        atlas: GlTextureId,
+        name: &str,
+        min: [f32; 2],
+        max: [f32; 2],
+    ) -> Result<(), TextureError> {
+        let handle = self
+            .handles
+            .by_name
+            .read()
+            .get(name)
+            .cloned()
+            .ok_or_else(|| TextureError::UnknownTexture(name.to_owned()))?;
+
+        self.atlas_sprites
+            .entry(atlas)
+            .or_default()
+            .push(Arc::new(TextureAtlasSprite::new(handle, min, max)));
+
+        Ok(())
+    }
+
+    pub fn get_atlas_sprites(&self, atlas: GlTextureId) -> Vec<Arc<TextureAtlasSprite>> {
+        self.atlas_sprites.get(&atlas).cloned().unwrap_or_default()
+    }
+
     pub fn finish_texture_reload(&mut self) -> anyhow::Result<()> {
+        let total = self.begin_reload_upload();
+
+        upload_in_chunks(
+            total,
+            self.reload_chunk_size(),
+            |count| self.upload_reload_chunk(count),
+            |_| {},
+        )?;
+
+        self.end_reload_upload();
+
+        Ok(())
+    }
+
+    /// Drops the textures the reload skipped. Returns how many texture updates it has to upload,
+    /// which are then uploaded with [`Self::upload_reload_chunk`] and finished with
+    /// [`Self::end_reload_upload`]. The steps are separate so that callers can report progress
+    /// between chunks without holding the texture manager.
+    pub fn begin_reload_upload(&mut self) -> usize {
         for skipped in self.unupdated_textures.drain() {
             warn!(
                 what = "texture has been skipped in resource reload",
                 who = skipped
             );
 
-            let handle = self.textures_by_name.write().remove(&skipped).unwrap();
-            self.textures_by_id.write().remove(&handle.texture_id);
+            let handle = self.handles.by_name.write().remove(&skipped).unwrap();
+            self.handles.by_id.write().remove(&handle.texture_id);
 
             // free the backing texture
             handle
@@ -889,8 +2314,217 @@ impl TextureManager {
                 .set(self.texture_storage.get_missingno().clone());
         }
 
-        info!(what = "waiting for all frames to finish for texture reload");
+        self.reload_started = Some(Instant::now());
+
+        self.texture_storage.pending_update_count()
+    }
+
+    /// How many updates [`Self::upload_reload_chunk`] should be given at a time. Asynchronous
+    /// reloads don't block, so they're submitted all at once.
+    pub fn reload_chunk_size(&self) -> usize {
+        if self.async_reload {
+            usize::MAX
+        } else {
+            RELOAD_CHUNK_SIZE
+        }
+    }
+
+    /// Uploads the next `count` updates of a reload. Synchronous reloads wait for the frames in
+    /// flight and then for the upload itself.
+    pub fn upload_reload_chunk(&mut self, count: usize) -> anyhow::Result<()> {
+        if self.async_reload {
+            return self.upload_texture_updates_async();
+        }
+
+        let queue = {
+            let mut renderer = self.rendering.write();
+            renderer.flush()?;
+            renderer.queue().clone()
+        };
+
+        let mut commands = AutoCommandBufferBuilder::primary(
+            &self.allocators.read().command_buffer_allocator,
+            queue.queue_family_index(),
+            CommandBufferUsage::OneTimeSubmit,
+        )?;
+
+        self.texture_storage
+            .record_some_commands(&mut commands, count);
+
+        commands
+            .build()?
+            .execute(queue)?
+            .then_signal_fence_and_flush()?
+            .wait(None)?;
+
+        Ok(())
+    }
+
+    /// Swaps in the textures of a synchronous reload. Asynchronous ones have taken their swaps
+    /// along with the upload, unless there was nothing to upload.
+    pub fn end_reload_upload(&mut self) {
+        self.texture_storage.apply_deferred_swaps();
+
+        info!(
+            what = "uploaded all gpu textures",
+            count = self.handles.by_name.read().len(),
+            upload_duration_secs = self
+                .reload_started
+                .take()
+                .map(|start| start.elapsed().as_secs_f32())
+        );
+    }
+
+    /// Submits every pending texture update to the transfer queue, without waiting for the frames
+    /// in flight or the upload itself
+    fn upload_texture_updates_async(&mut self) -> anyhow::Result<()> {
+        let total = self.texture_storage.pending_update_count();
+
+        let queue = self.rendering.read().transfer_queue().clone();
+
+        let mut commands = AutoCommandBufferBuilder::primary(
+            &self.allocators.read().command_buffer_allocator,
+            queue.queue_family_index(),
+            CommandBufferUsage::OneTimeSubmit,
+        )?;
+
+        self.texture_storage.record_commands(&mut commands);
+
+        let upload = commands
+            .build()?
+            .execute(queue)?
+            .boxed_send_sync()
+            .then_signal_fence_and_flush()?;
+
+        let swaps = self.texture_storage.submit_deferred_swaps(Box::new(upload));
+
+        info!(what = "submitted texture reload", updates = total, swaps);
+
+        Ok(())
+    }
+
+    /// Swaps in the textures of an asynchronous reload once its uploads have finished. Returns
+    /// true on the call that swapped them in, and never blocks.
+    pub fn poll_texture_reload(&mut self) -> anyhow::Result<bool> {
+        let Some(swaps) = self.texture_storage.poll_pending_reload()? else {
+            return Ok(false);
+        };
+
+        info!(what = "swapped in reloaded textures", swaps);
+
+        Ok(true)
+    }
+
+    /// glTexImage2D: replaces the whole texture, which is reallocated if its size changed
+    pub fn enqueue_tex_image(
+        &mut self,
+        id: GlTextureId,
+        image: TextureImage,
+    ) -> Result<(), TextureError> {
+        let handle = self.get_texture_handle(id).ok_or(TextureError::NoTexture)?;
+
+        let image = self.pixel_transfer.apply_to_tex_image(image);
+
+        self.texture_storage.enqueue_handle_update(&handle, image)
+    }
 
+    /// glTextureSubImage2D: replaces part of a texture. Unlike [`TextureManager::enqueue_sub_image`]
+    /// the rectangle doesn't have to cover a whole frame.
+    pub fn enqueue_subregion(
+        &mut self,
+        id: GlTextureId,
+        offset: [u32; 2],
+        image: RgbaImage,
+    ) -> Result<(), TextureError> {
+        let handle = self.get_texture_handle(id).ok_or(TextureError::NoTexture)?;
+
+        let texture = self.texture_storage.current_texture(&handle);
+
+        let TextureReference::Managed(texture) = texture.as_ref() else {
+            return Err(TextureError::NoTexture);
+        };
+
+        let (frame, offset_in_frame) = get_subregion_target(
+            self.texture_storage.get_array_size(texture.indices.array),
+            texture.indices.slots.len(),
+            offset,
+            [image.width(), image.height()],
+        )
+        .ok_or(TextureError::BadSubRegion(
+            offset[0],
+            offset[1],
+            image.width(),
+            image.height(),
+        ))?;
+
+        self.texture_storage.enqueue_subregion_update(
+            &texture.indices,
+            frame,
+            offset_in_frame,
+            &image,
+            Some(handle.clone()),
+        )
+    }
+
+    /// Overwrites one animation frame of a texture; see [`get_sub_image_frame`] for how the
+    /// frame is picked.
+    pub fn enqueue_sub_image(
+        &mut self,
+        id: GlTextureId,
+        offset: [u32; 2],
+        image: RgbaImage,
+    ) -> Result<(), TextureError> {
+        let handle = self.get_texture_handle(id).ok_or(TextureError::NoTexture)?;
+
+        let texture = self.texture_storage.current_texture(&handle);
+
+        let TextureReference::Managed(texture) = texture.as_ref() else {
+            return Err(TextureError::NoTexture);
+        };
+
+        let frame = get_sub_image_frame(
+            self.texture_storage.get_array_size(texture.indices.array),
+            texture.indices.slots.len(),
+            offset,
+            [image.width(), image.height()],
+        )
+        .ok_or(TextureError::BadSubImage(
+            offset[0],
+            offset[1],
+            image.width(),
+            image.height(),
+        ))?;
+
+        self.texture_storage.enqueue_frame_update(
+            &texture.indices,
+            frame,
+            &image,
+            Some(handle.clone()),
+        )
+    }
+
+    /// Records every pending texture update into a command buffer which runs before the next
+    /// frame, or returns None if there's nothing to upload
+    pub fn record_texture_updates(
+        &mut self,
+    ) -> anyhow::Result<Option<Arc<PrimaryAutoCommandBuffer>>> {
+        if !self.texture_storage.has_pending_updates() {
+            return Ok(None);
+        }
+
+        let mut commands = AutoCommandBufferBuilder::primary(
+            &self.allocators.read().command_buffer_allocator,
+            self.rendering.read().queue().queue_family_index(),
+            CommandBufferUsage::OneTimeSubmit,
+        )?;
+
+        self.texture_storage.record_commands(&mut commands);
+
+        Ok(Some(commands.build()?))
+    }
+
+    /// Records and submits every pending texture update, then blocks until they're on the gpu
+    pub fn upload_texture_updates(&mut self) -> anyhow::Result<()> {
         let mut renderer = self.rendering.write();
 
         let mut commands = AutoCommandBufferBuilder::primary(
@@ -921,7 +2555,7 @@ impl TextureManager {
 
         info!(
             what = "uploaded all gpu textures",
-            count = self.textures_by_name.read().len(),
+            count = self.handles.by_name.read().len(),
             record_duration_secs = (post_record - pre_record).as_secs_f32(),
             upload_duration_secs = (post_upload - post_record).as_secs_f32()
         );
@@ -929,11 +2563,90 @@ impl TextureManager {
         Ok(())
     }
 
-    fn create_lookup(&mut self) {
-        todo!()
+    /// See [`tex_level_parameter`]. None if the texture has no image yet, or for untracked pnames.
+    pub fn texture_level_parameter(&self, id: GlTextureId, level: u32, pname: u32) -> Option<i32> {
+        let handle = self.get_texture_handle(id)?;
+
+        let texture = handle.texture.get();
+
+        let TextureReference::Managed(texture) = texture.as_ref() else {
+            return None;
+        };
+
+        let (size, format) = self.texture_storage.slot_format(&texture.indices);
+
+        tex_level_parameter(size, format, level, pname)
+    }
+
+    /// Copies a texture back from the gpu. Animated textures are returned as a vertical
+    /// spritesheet, the same layout [`get_sub_image_frame`] expects on upload. Rows are kept in
+    /// the order they were uploaded in, so nothing needs to be flipped.
+    pub fn read_texture(&mut self, id: GlTextureId, mip_level: u32) -> anyhow::Result<RgbaImage> {
+        let handle = self.get_texture_handle(id).ok_or(TextureError::NoTexture)?;
+
+        let texture = handle.texture.get();
+
+        let TextureReference::Managed(texture) = texture.as_ref() else {
+            return Err(TextureError::NoTexture.into());
+        };
+
+        let mut renderer = self.rendering.write();
+
+        let mut commands = AutoCommandBufferBuilder::primary(
+            &self.allocators.read().command_buffer_allocator,
+            renderer.queue().queue_family_index(),
+            CommandBufferUsage::OneTimeSubmit,
+        )
+        .unwrap();
+
+        let (pixels, size) =
+            self.texture_storage
+                .record_readback(&mut commands, &texture.indices, mip_level)?;
+
+        let commands = commands.build()?;
+
+        renderer.flush()?;
+
+        commands
+            .execute(renderer.queue().clone())?
+            .boxed()
+            .then_signal_fence_and_flush()?
+            .wait(None)?;
+
+        let pixels = pixels.read()?;
+
+        let mut image = RgbaImage::new(size[0], size[1]);
+
+        for (dest, src) in image.pixels_mut().zip(pixels.iter()) {
+            *dest = unpack_gpu_pixel(*src);
+        }
+
+        Ok(image)
+    }
+
+    /// Rebuilds the lookup from the sprites registered into each atlas, replacing the previous
+    /// one. This fails without touching the current lookup if either atlas hasn't been created
+    /// yet, which happens when it's called before the resource reload that loads them finishes.
+    pub fn create_lookup(
+        &mut self,
+        blocks: GlTextureId,
+        items: GlTextureId,
+    ) -> Result<Ref<TextureLookup>, TextureError> {
+        for atlas in [blocks, items] {
+            if !self.handles.by_id.read().contains_key(&atlas) {
+                return Err(TextureError::MissingAtlas(atlas));
+            }
+        }
+
+        let lookup = Ref::new(TextureLookup::new(self, blocks, items));
+
+        self.lookup = Some(lookup.clone());
+
+        Ok(lookup)
     }
 
-    pub fn get_lookup(&self) -> Ref<TextureLookup> {
-        todo!()
+    /// None until [`Self::create_lookup`] has succeeded
+    pub fn get_lookup(&self) -> Option<Ref<TextureLookup>> {
+        self.lookup.clone()
     }
 }
@@ -1,7 +1,8 @@
+use std::array::from_fn;
 use std::io::Cursor;
 
-use image::{GenericImageView, ImageError, ImageReader, RgbaImage};
-use vulkano::{buffer::AllocateBufferError, image::AllocateImageError, Validated};
+use image::{GenericImageView, ImageError, ImageReader, Rgba, RgbaImage};
+use vulkano::{buffer::AllocateBufferError, format::Format, image::AllocateImageError, Validated};
 
 #[derive(Debug, thiserror::Error)]
 pub enum TextureLoadError {
@@ -15,6 +16,544 @@ pub enum TextureLoadError {
     ImageError(#[from] Validated<AllocateImageError>),
     #[error("could not create source buffer: {0}")]
     BufferCreateError(#[from] Validated<AllocateBufferError>),
+    #[error("unsupported pixel format or type (format = {0:#x}, type = {1:#x})")]
+    UnsupportedPixelFormat(u32, u32),
+    #[error("pixel data was too short: expected {0} bytes but got {1}")]
+    NotEnoughPixelData(usize, usize),
+    #[error("unsupported compressed format {0:?}")]
+    UnsupportedCompression(Format),
+}
+
+/// Converts pixels passed to glTexImage2D/glTexSubImage2D into an image.
+/// Only the formats minecraft actually uploads are supported.
+pub fn decode_gl_pixels(
+    format: u32,
+    data_type: u32,
+    width: u32,
+    height: u32,
+    data: &[u8],
+) -> Result<RgbaImage, TextureLoadError> {
+    if (format, data_type) == (gl_constants::GL_RGB, gl_constants::GL_UNSIGNED_BYTE) {
+        // the only format without an alpha channel, which is opaque once converted
+        let expected = (width * height * 3) as usize;
+
+        if data.len() < expected {
+            return Err(TextureLoadError::NotEnoughPixelData(expected, data.len()));
+        }
+
+        let mut image = RgbaImage::new(width, height);
+
+        for (pixel, rgb) in image.pixels_mut().zip(data.chunks_exact(3)) {
+            *pixel = Rgba([rgb[0], rgb[1], rgb[2], 255]);
+        }
+
+        return Ok(image);
+    }
+
+    let decode: fn([u8; 4]) -> [u8; 4] = match (format, data_type) {
+        (gl_constants::GL_RGBA, gl_constants::GL_UNSIGNED_BYTE) => |p| p,
+        (gl_constants::GL_BGRA, gl_constants::GL_UNSIGNED_BYTE) => |[b, g, r, a]| [r, g, b, a],
+        (gl_constants::GL_BGRA, gl_constants::GL_UNSIGNED_INT_8_8_8_8_REV) => |p| {
+            let [a, r, g, b] = u32::from_ne_bytes(p).to_be_bytes();
+            [r, g, b, a]
+        },
+        _ => return Err(TextureLoadError::UnsupportedPixelFormat(format, data_type)),
+    };
+
+    let expected = (width * height * 4) as usize;
+
+    if data.len() < expected {
+        return Err(TextureLoadError::NotEnoughPixelData(expected, data.len()));
+    }
+
+    let mut image = RgbaImage::new(width, height);
+
+    for (pixel, bytes) in image.pixels_mut().zip(data.chunks_exact(4)) {
+        *pixel = Rgba(decode(bytes.try_into().unwrap()));
+    }
+
+    Ok(image)
+}
+
+/// The image glTexImage2D replaces a texture with. A null buffer only allocates the texture, which
+/// starts out transparent.
+pub fn tex_image(
+    format: u32,
+    data_type: u32,
+    width: u32,
+    height: u32,
+    data: Option<&[u8]>,
+) -> Result<TextureImage, TextureLoadError> {
+    let image = match data {
+        Some(data) => decode_gl_pixels(format, data_type, width, height, data)?,
+        None => RgbaImage::new(width, height),
+    };
+
+    Ok(TextureImage::Static { image })
+}
+
+/// The inverse of [`decode_gl_pixels`], used by glGetTexImage.
+pub fn encode_gl_pixels(
+    format: u32,
+    data_type: u32,
+    image: &RgbaImage,
+) -> Result<Vec<u8>, TextureLoadError> {
+    let encode: fn([u8; 4]) -> [u8; 4] = match (format, data_type) {
+        (gl_constants::GL_RGBA, gl_constants::GL_UNSIGNED_BYTE) => |p| p,
+        (gl_constants::GL_BGRA, gl_constants::GL_UNSIGNED_BYTE) => |[r, g, b, a]| [b, g, r, a],
+        (gl_constants::GL_BGRA, gl_constants::GL_UNSIGNED_INT_8_8_8_8_REV) => |[r, g, b, a]| {
+            u32::from_be_bytes([a, r, g, b]).to_ne_bytes()
+        },
+        _ => return Err(TextureLoadError::UnsupportedPixelFormat(format, data_type)),
+    };
+
+    let mut data = Vec::with_capacity((image.width() * image.height() * 4) as usize);
+
+    for pixel in image.pixels() {
+        data.extend_from_slice(&encode(pixel.0));
+    }
+
+    Ok(data)
+}
+
+/// The BC format of a glCompressedTexImage2D internal format, None if it isn't one we can decode
+pub fn gl_compressed_format(internal_format: u32) -> Option<Format> {
+    match internal_format {
+        gl_constants::GL_COMPRESSED_RGB_S3TC_DXT1_EXT => Some(Format::BC1_RGB_UNORM_BLOCK),
+        gl_constants::GL_COMPRESSED_RGBA_S3TC_DXT1_EXT => Some(Format::BC1_RGBA_UNORM_BLOCK),
+        gl_constants::GL_COMPRESSED_RGBA_S3TC_DXT5_EXT => Some(Format::BC3_UNORM_BLOCK),
+        _ => None,
+    }
+}
+
+/// The internal format glGetTexLevelParameter reports for a texture stored as `format`. Anything
+/// that isn't compressed was uploaded as RGBA8.
+pub fn gl_internal_format(format: Format) -> u32 {
+    match format {
+        Format::BC1_RGB_UNORM_BLOCK => gl_constants::GL_COMPRESSED_RGB_S3TC_DXT1_EXT,
+        Format::BC1_RGBA_UNORM_BLOCK => gl_constants::GL_COMPRESSED_RGBA_S3TC_DXT1_EXT,
+        Format::BC3_UNORM_BLOCK => gl_constants::GL_COMPRESSED_RGBA_S3TC_DXT5_EXT,
+        _ => gl_constants::GL_RGBA8,
+    }
+}
+
+/// The size of a BC1 or BC3 image's 4x4 blocks, None for any other format
+pub fn compressed_size(format: Format, width: u32, height: u32) -> Option<usize> {
+    let block_bytes = match format {
+        Format::BC1_RGB_UNORM_BLOCK | Format::BC1_RGBA_UNORM_BLOCK => 8,
+        Format::BC3_UNORM_BLOCK => 16,
+        _ => return None,
+    };
+
+    Some(width.div_ceil(4) as usize * height.div_ceil(4) as usize * block_bytes)
+}
+
+/// Keeps a compressed image compressed only if the device can sample BC formats, otherwise it's
+/// decoded to RGBA
+pub fn resolve_compression(
+    image: TextureImage,
+    supports_bc: bool,
+) -> Result<TextureImage, TextureLoadError> {
+    if supports_bc {
+        Ok(image)
+    } else {
+        image.decompress()
+    }
+}
+
+/// Decodes BC1 or BC3 blocks into RGBA, for devices without textureCompressionBC
+pub fn decode_bc(
+    format: Format,
+    width: u32,
+    height: u32,
+    data: &[u8],
+) -> Result<RgbaImage, TextureLoadError> {
+    let expected = compressed_size(format, width, height)
+        .ok_or(TextureLoadError::UnsupportedCompression(format))?;
+
+    if data.len() < expected {
+        return Err(TextureLoadError::NotEnoughPixelData(expected, data.len()));
+    }
+
+    let block_bytes = if format == Format::BC3_UNORM_BLOCK {
+        16
+    } else {
+        8
+    };
+
+    let blocks_wide = width.div_ceil(4);
+
+    let mut image = RgbaImage::new(width, height);
+
+    for (i, block) in data[..expected].chunks_exact(block_bytes).enumerate() {
+        let texels = match format {
+            Format::BC3_UNORM_BLOCK => {
+                let mut texels = decode_color_block(&block[8..], false, false);
+
+                for (texel, alpha) in texels.iter_mut().zip(decode_alpha_block(&block[..8])) {
+                    texel[3] = alpha;
+                }
+
+                texels
+            }
+            Format::BC1_RGBA_UNORM_BLOCK => decode_color_block(block, true, true),
+            _ => decode_color_block(block, true, false),
+        };
+
+        let block_x = i as u32 % blocks_wide * 4;
+        let block_y = i as u32 / blocks_wide * 4;
+
+        for (t, texel) in texels.into_iter().enumerate() {
+            let x = block_x + t as u32 % 4;
+            let y = block_y + t as u32 / 4;
+
+            // blocks on the right and bottom edges can hang off the image
+            if x < width && y < height {
+                image.put_pixel(x, y, Rgba(texel));
+            }
+        }
+    }
+
+    Ok(image)
+}
+
+fn expand_565(color: u16) -> [u32; 3] {
+    let r = (color >> 11) as u32 & 0x1f;
+    let g = (color >> 5) as u32 & 0x3f;
+    let b = color as u32 & 0x1f;
+
+    [
+        (r << 3) | (r >> 2),
+        (g << 2) | (g >> 4),
+        (b << 3) | (b >> 2),
+    ]
+}
+
+/// The 16 texels of a BC1 colour block, in rows. BC3's colour blocks are always in four colour
+/// mode, and only BC1 with alpha makes the fourth colour of three colour mode transparent.
+fn decode_color_block(block: &[u8], three_color: bool, transparent: bool) -> [[u8; 4]; 16] {
+    let c0 = u16::from_le_bytes([block[0], block[1]]);
+    let c1 = u16::from_le_bytes([block[2], block[3]]);
+    let indices = u32::from_le_bytes([block[4], block[5], block[6], block[7]]);
+
+    let [e0, e1] = [expand_565(c0), expand_565(c1)];
+
+    let mix = |w0: u32, w1: u32| -> [u8; 4] {
+        let channel = |i: usize| ((e0[i] * w0 + e1[i] * w1) / (w0 + w1)) as u8;
+        [channel(0), channel(1), channel(2), 0xff]
+    };
+
+    let palette = if c0 > c1 || !three_color {
+        [mix(1, 0), mix(0, 1), mix(2, 1), mix(1, 2)]
+    } else {
+        let black = [0, 0, 0, if transparent { 0 } else { 0xff }];
+        [mix(1, 0), mix(0, 1), mix(1, 1), black]
+    };
+
+    from_fn(|t| palette[(indices >> (t * 2)) as usize & 0b11])
+}
+
+/// The 16 alphas of a BC3 alpha block, in rows
+fn decode_alpha_block(block: &[u8]) -> [u8; 16] {
+    let a0 = block[0] as u32;
+    let a1 = block[1] as u32;
+
+    let mut bits = [0; 8];
+    bits[..6].copy_from_slice(&block[2..8]);
+    let indices = u64::from_le_bytes(bits);
+
+    let palette: [u8; 8] = if a0 > a1 {
+        from_fn(|i| match i {
+            0 => a0 as u8,
+            1 => a1 as u8,
+            i => (((8 - i as u32) * a0 + (i as u32 - 1) * a1) / 7) as u8,
+        })
+    } else {
+        from_fn(|i| match i {
+            0 => a0 as u8,
+            1 => a1 as u8,
+            6 => 0,
+            7 => 0xff,
+            i => (((6 - i as u32) * a0 + (i as u32 - 1) * a1) / 5) as u8,
+        })
+    };
+
+    from_fn(|t| palette[(indices >> (t * 3)) as usize & 0b111])
+}
+
+/// Encodes an image into BC1 blocks, or BC3 blocks if any texel is translucent. The fast encoder
+/// picks each block's colour endpoints from its bounding box, while the high quality one also
+/// tries the extremes along the block's principal axis and keeps whichever is closer. Both sides
+/// must be multiples of 4.
+pub fn encode_bc(image: &RgbaImage, high_quality: bool) -> TextureImage {
+    let (width, height) = image.dimensions();
+
+    assert!(
+        width % 4 == 0 && height % 4 == 0,
+        "only whole blocks can be encoded"
+    );
+
+    let translucent = image.pixels().any(|pixel| pixel.0[3] != 0xff);
+
+    let format = if translucent {
+        Format::BC3_UNORM_BLOCK
+    } else {
+        Format::BC1_RGB_UNORM_BLOCK
+    };
+
+    let mut data = Vec::with_capacity(compressed_size(format, width, height).unwrap());
+
+    for block_y in (0..height).step_by(4) {
+        for block_x in (0..width).step_by(4) {
+            let texels: [[u8; 4]; 16] = from_fn(|t| {
+                image
+                    .get_pixel(block_x + t as u32 % 4, block_y + t as u32 / 4)
+                    .0
+            });
+
+            if translucent {
+                data.extend_from_slice(&encode_alpha_block(&texels));
+            }
+
+            data.extend_from_slice(&encode_color_block(&texels, high_quality));
+        }
+    }
+
+    TextureImage::Compressed {
+        format,
+        data,
+        width,
+        height,
+    }
+}
+
+fn pack_565(color: [f32; 3]) -> u16 {
+    let r = (color[0].clamp(0.0, 255.0) * 31.0 / 255.0).round() as u16;
+    let g = (color[1].clamp(0.0, 255.0) * 63.0 / 255.0).round() as u16;
+    let b = (color[2].clamp(0.0, 255.0) * 31.0 / 255.0).round() as u16;
+
+    (r << 11) | (g << 5) | b
+}
+
+/// A four colour mode block between two endpoints, along with its squared error
+fn fit_color_block(texels: &[[u8; 4]; 16], endpoints: [[f32; 3]; 2]) -> ([u8; 8], u32) {
+    let [mut c0, mut c1] = endpoints.map(pack_565);
+
+    // c0 <= c1 would switch BC1 to three colour mode
+    if c0 < c1 {
+        std::mem::swap(&mut c0, &mut c1);
+    }
+
+    let [e0, e1] = [expand_565(c0), expand_565(c1)];
+    let mix = |w0: u32, w1: u32| -> [u32; 3] { from_fn(|i| (e0[i] * w0 + e1[i] * w1) / (w0 + w1)) };
+
+    let palette = if c0 == c1 {
+        [e0; 4]
+    } else {
+        [mix(1, 0), mix(0, 1), mix(2, 1), mix(1, 2)]
+    };
+
+    let mut indices = 0u32;
+    let mut error = 0;
+
+    for (t, texel) in texels.iter().enumerate() {
+        let (index, distance) = palette
+            .iter()
+            .map(|color| {
+                (0..3)
+                    .map(|i| (color[i] as i32 - texel[i] as i32).pow(2) as u32)
+                    .sum::<u32>()
+            })
+            .enumerate()
+            .min_by_key(|(_, distance)| *distance)
+            .unwrap();
+
+        indices |= (index as u32) << (t * 2);
+        error += distance;
+    }
+
+    let mut block = [0; 8];
+    block[0..2].copy_from_slice(&c0.to_le_bytes());
+    block[2..4].copy_from_slice(&c1.to_le_bytes());
+    block[4..8].copy_from_slice(&indices.to_le_bytes());
+
+    (block, error)
+}
+
+fn encode_color_block(texels: &[[u8; 4]; 16], high_quality: bool) -> [u8; 8] {
+    let colors = texels.map(|texel| [texel[0] as f32, texel[1] as f32, texel[2] as f32]);
+
+    let bounds = [
+        from_fn(|i| colors.iter().map(|c| c[i]).fold(f32::MAX, f32::min)),
+        from_fn(|i| colors.iter().map(|c| c[i]).fold(f32::MIN, f32::max)),
+    ];
+
+    let (block, error) = fit_color_block(texels, bounds);
+
+    if !high_quality || error == 0 {
+        return block;
+    }
+
+    let mean: [f32; 3] = from_fn(|i| colors.iter().map(|c| c[i]).sum::<f32>() / 16.0);
+
+    let mut covariance = [[0.0f32; 3]; 3];
+    for color in &colors {
+        let d: [f32; 3] = from_fn(|i| color[i] - mean[i]);
+
+        for (row, di) in covariance.iter_mut().zip(d) {
+            for (cell, dj) in row.iter_mut().zip(d) {
+                *cell += di * dj;
+            }
+        }
+    }
+
+    // a few rounds of power iteration are plenty to find the principal axis of 16 texels. The
+    // row of the channel which varies most can't be orthogonal to it, unlike a fixed guess.
+    let widest = (0..3)
+        .max_by(|&i, &j| covariance[i][i].total_cmp(&covariance[j][j]))
+        .unwrap();
+
+    let mut axis = covariance[widest];
+    for _ in 0..8 {
+        let next: [f32; 3] = from_fn(|i| (0..3).map(|j| covariance[i][j] * axis[j]).sum());
+        let length = next.iter().map(|x| x * x).sum::<f32>().sqrt();
+
+        if length == 0.0 {
+            return block;
+        }
+
+        axis = next.map(|x| x / length);
+    }
+
+    let project = |color: &[f32; 3]| (0..3).map(|i| (color[i] - mean[i]) * axis[i]).sum::<f32>();
+
+    let (min, max) = colors
+        .iter()
+        .fold((f32::MAX, f32::MIN), |(min, max), color| {
+            let t = project(color);
+            (min.min(t), max.max(t))
+        });
+
+    let axis_endpoints = [min, max].map(|t| from_fn(|i| mean[i] + axis[i] * t));
+
+    let (axis_block, axis_error) = fit_color_block(texels, axis_endpoints);
+
+    if axis_error < error {
+        axis_block
+    } else {
+        block
+    }
+}
+
+/// An eight alpha mode block between the block's lowest and highest alpha
+fn encode_alpha_block(texels: &[[u8; 4]; 16]) -> [u8; 8] {
+    let a0 = texels.iter().map(|texel| texel[3]).max().unwrap() as u32;
+    let a1 = texels.iter().map(|texel| texel[3]).min().unwrap() as u32;
+
+    let palette: [u32; 8] = from_fn(|i| match i {
+        0 => a0,
+        1 => a1,
+        i => ((8 - i as u32) * a0 + (i as u32 - 1) * a1) / 7,
+    });
+
+    let mut indices = 0u64;
+
+    for (t, texel) in texels.iter().enumerate() {
+        let index = palette
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, alpha)| alpha.abs_diff(texel[3] as u32))
+            .unwrap()
+            .0;
+
+        indices |= (index as u64) << (t * 3);
+    }
+
+    let mut block = [0; 8];
+    block[0] = a0 as u8;
+    block[1] = a1 as u8;
+    block[2..8].copy_from_slice(&indices.to_le_bytes()[..6]);
+
+    block
+}
+
+/// Packs a pixel the way the texture arrays store it (`A8B8G8R8_UNORM`).
+pub fn pack_gpu_pixel(pixel: Rgba<u8>) -> u32 {
+    let [r, g, b, a] = pixel.0;
+    u32::from_ne_bytes([a, b, g, r])
+}
+
+pub fn unpack_gpu_pixel(pixel: u32) -> Rgba<u8> {
+    let [a, b, g, r] = pixel.to_ne_bytes();
+    Rgba([r, g, b, a])
+}
+
+/// The scale & bias set with glPixelTransfer. Each channel is mapped to `c * scale + bias` (with
+/// `c` in 0..1) whenever pixels move between the client and a texture.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PixelTransfer {
+    pub scale: [f32; 4],
+    pub bias: [f32; 4],
+}
+
+impl Default for PixelTransfer {
+    fn default() -> Self {
+        Self {
+            scale: [1.0; 4],
+            bias: [0.0; 4],
+        }
+    }
+}
+
+impl PixelTransfer {
+    /// Updates one of the `GL_*_SCALE`/`GL_*_BIAS` values, returning false for any other pname.
+    pub fn set(&mut self, pname: u32, value: f32) -> bool {
+        let (target, channel) = match pname {
+            gl_constants::GL_RED_SCALE => (&mut self.scale, 0),
+            gl_constants::GL_GREEN_SCALE => (&mut self.scale, 1),
+            gl_constants::GL_BLUE_SCALE => (&mut self.scale, 2),
+            gl_constants::GL_ALPHA_SCALE => (&mut self.scale, 3),
+            gl_constants::GL_RED_BIAS => (&mut self.bias, 0),
+            gl_constants::GL_GREEN_BIAS => (&mut self.bias, 1),
+            gl_constants::GL_BLUE_BIAS => (&mut self.bias, 2),
+            gl_constants::GL_ALPHA_BIAS => (&mut self.bias, 3),
+            _ => return false,
+        };
+
+        target[channel] = value;
+
+        true
+    }
+
+    pub fn is_identity(&self) -> bool {
+        *self == Self::default()
+    }
+
+    pub fn apply(&self, image: &mut RgbaImage) {
+        if self.is_identity() {
+            return;
+        }
+
+        for pixel in image.pixels_mut() {
+            for (channel, value) in pixel.0.iter_mut().enumerate() {
+                let c = *value as f32 / 255.0 * self.scale[channel] + self.bias[channel];
+
+                *value = (c.clamp(0.0, 1.0) * 255.0).round() as u8;
+            }
+        }
+    }
+
+    /// The image glTexImage2D enqueues. Compressed blocks are passed through untouched, since
+    /// they'd have to be decoded first.
+    pub fn apply_to_tex_image(&self, image: TextureImage) -> TextureImage {
+        match image {
+            TextureImage::Static { mut image } => {
+                self.apply(&mut image);
+                TextureImage::Static { image }
+            }
+            image => image,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -42,6 +581,13 @@ pub enum TextureImage {
         frames: Vec<RgbaImage>,
         animation: AnimationMetadata,
     },
+    /// BC1 or BC3 blocks, which are uploaded as is when the device can sample them
+    Compressed {
+        format: Format,
+        data: Vec<u8>,
+        width: u32,
+        height: u32,
+    },
 }
 
 impl TextureImage {
@@ -54,6 +600,21 @@ impl TextureImage {
             ));
         }
 
+        if let Self::Compressed {
+            format,
+            data,
+            width,
+            height,
+        } = self
+        {
+            let expected = compressed_size(*format, *width, *height)
+                .ok_or(TextureLoadError::UnsupportedCompression(*format))?;
+
+            if data.len() < expected {
+                return Err(TextureLoadError::NotEnoughPixelData(expected, data.len()));
+            }
+        }
+
         if let Self::Frames {
             width,
             height,
@@ -79,6 +640,7 @@ impl TextureImage {
         match self {
             Self::Static { image, .. } => image.width(),
             Self::Frames { width, .. } => *width,
+            Self::Compressed { width, .. } => *width,
             _ => {
                 panic!("cannot call width() on an unloaded texture image");
             }
@@ -89,6 +651,7 @@ impl TextureImage {
         match self {
             Self::Static { image, .. } => image.height(),
             Self::Frames { height, .. } => *height,
+            Self::Compressed { height, .. } => *height,
             _ => {
                 panic!("cannot call height() on an unloaded texture image");
             }
@@ -99,12 +662,23 @@ impl TextureImage {
         match self {
             TextureImage::Static { image } => vec![image],
             TextureImage::Frames { frames, .. } => frames.iter().collect(),
+            TextureImage::Compressed { .. } => {
+                panic!("cannot call get_frames() on a compressed texture image");
+            }
             _ => {
                 panic!("cannot call height() on an unloaded texture image");
             }
         }
     }
 
+    /// How many layers the image takes up
+    pub fn frame_count(&self) -> usize {
+        match self {
+            TextureImage::Compressed { .. } => 1,
+            other => other.get_frames().len(),
+        }
+    }
+
     pub fn get_animation(&self) -> Option<&AnimationMetadata> {
         match self {
             TextureImage::None => None,
@@ -112,6 +686,22 @@ impl TextureImage {
             TextureImage::Static { .. } => None,
             TextureImage::Spritesheet { animation, .. } => animation.as_ref(),
             TextureImage::Frames { animation, .. } => Some(animation),
+            TextureImage::Compressed { .. } => None,
+        }
+    }
+
+    /// Decodes a compressed image into RGBA, leaving any other image alone
+    pub fn decompress(self) -> Result<Self, TextureLoadError> {
+        match self {
+            Self::Compressed {
+                format,
+                data,
+                width,
+                height,
+            } => Ok(Self::Static {
+                image: decode_bc(format, width, height, &data)?,
+            }),
+            other => Ok(other),
         }
     }
 
@@ -52,7 +52,7 @@ impl<T: Default> Extract for T {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum TypedVec {
     F32s(SmallVec<[f32; 1]>),
     F64s(SmallVec<[f64; 1]>),
@@ -1,4 +1,375 @@
+use std::ops::Range;
+use std::sync::Arc;
+
+use tokio::sync::mpsc::error::TryRecvError;
+use tokio::sync::mpsc::UnboundedReceiver;
+use vulkano::command_buffer::allocator::CommandBufferAllocator;
+
+use super::arena::FrameArena;
+use super::commands::BoundedReceiver;
+use super::commands::CommandQueue;
+use super::commands::CommandRecorder;
+use super::commands::RenderCommand;
+use super::dynamic_shader::DynamicPipelinePushConstants;
+use super::dynamic_shader::DynamicPipelineSpec;
+use super::dynamic_shader::DynamicRasterState;
+use super::dynamic_shader::MAX_SHADER_TEXTURE_UNITS;
+use super::sandbox::DrawMode;
+
 #[derive(Debug)]
 pub struct RenderWorkerTask {}
 
 pub struct RenderWorkerResult {}
+
+/// The most commands that are pulled out of the channel before they're recorded
+const RENDER_WORKER_BATCH_SIZE: usize = 256;
+
+/// Whether the producers of a [`RenderWorker`] can still send it commands
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenderWorkerStatus {
+    Open,
+    /// Every sender has been dropped and everything they sent has been recorded
+    Closed,
+}
+
+/// The receiving half of a [`RenderWorker`]'s queue
+#[derive(Debug)]
+enum CommandReceiver {
+    Unbounded(UnboundedReceiver<RenderCommand>),
+    Bounded(BoundedReceiver),
+}
+
+impl CommandReceiver {
+    /// The receiver of a queue which holds at most `capacity` commands, or any number of them if
+    /// it's None
+    fn new(capacity: Option<usize>) -> (Self, CommandQueue) {
+        match capacity {
+            Some(capacity) => {
+                let (tx, rx) = CommandQueue::bounded(capacity);
+
+                (Self::Bounded(rx), tx)
+            }
+            None => {
+                let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+
+                (Self::Unbounded(rx), CommandQueue::Async(tx))
+            }
+        }
+    }
+
+    fn try_recv(&mut self) -> Result<RenderCommand, TryRecvError> {
+        match self {
+            Self::Unbounded(rx) => rx.try_recv(),
+            Self::Bounded(rx) => rx.try_recv(),
+        }
+    }
+
+    fn close(&mut self) {
+        match self {
+            Self::Unbounded(rx) => rx.close(),
+            Self::Bounded(rx) => rx.close(),
+        }
+    }
+}
+
+/// Consumes the commands sent through a [`CommandQueue::Async`] or [`CommandQueue::Bounded`] and
+/// records them. The command buffer builder can't leave the main render thread, so the worker is
+/// polled from there, while the instructions are assembled on the JNI threads.
+#[derive(Debug)]
+pub struct RenderWorker {
+    receiver: CommandReceiver,
+    batch: Vec<RenderCommand>,
+    /// Gets the vertex buffers of coalesced draws back. Must be the assembler's arena for any of
+    /// them to be reused.
+    pub arena: FrameArena,
+}
+
+impl RenderWorker {
+    /// Creates a worker and the queue which feeds it. The queue is bounded to `capacity`
+    /// commands if there is one, see [`CommandQueue::Bounded`].
+    pub fn new(capacity: Option<usize>) -> (Self, CommandQueue) {
+        let (receiver, queue) = CommandReceiver::new(capacity);
+
+        (
+            Self {
+                receiver,
+                batch: Vec::with_capacity(RENDER_WORKER_BATCH_SIZE),
+                arena: FrameArena::new(),
+            },
+            queue,
+        )
+    }
+
+    /// Switches to a new queue which holds at most `capacity` commands. Whatever was sent through
+    /// the old one is recorded first on the next drain, so the old queue must not be sent to
+    /// afterwards.
+    pub fn replace_queue(&mut self, capacity: Option<usize>) -> CommandQueue {
+        let (receiver, queue) = CommandReceiver::new(capacity);
+
+        let mut old = std::mem::replace(&mut self.receiver, receiver);
+        old.close();
+
+        while let Ok(cmd) = old.try_recv() {
+            self.batch.push(cmd);
+        }
+
+        queue
+    }
+
+    /// Records every command that's currently available. Must be called before each frame is
+    /// submitted, otherwise that frame's draws end up in the next one.
+    pub fn drain<L, A>(&mut self, recorder: &mut CommandRecorder<L, A>) -> RenderWorkerStatus
+    where
+        A: CommandBufferAllocator,
+    {
+        self.drain_into(|cmd| recorder.feed(cmd))
+    }
+
+    /// Like [`Self::drain`], but hands the commands to `sink` instead of a recorder
+    pub fn drain_into(&mut self, mut sink: impl FnMut(RenderCommand)) -> RenderWorkerStatus {
+        loop {
+            let status = self.fill_batch();
+
+            let batch_len = self.batch.len();
+
+            coalesce_pipeline_binds(&mut self.batch);
+            coalesce_draws(&mut self.batch, &self.arena);
+
+            for cmd in self.batch.drain(..) {
+                sink(cmd);
+            }
+
+            if status == RenderWorkerStatus::Closed {
+                return RenderWorkerStatus::Closed;
+            }
+
+            // the channel is empty for now
+            if batch_len < RENDER_WORKER_BATCH_SIZE {
+                return RenderWorkerStatus::Open;
+            }
+        }
+    }
+
+    /// Stops accepting commands and records everything that was sent before that
+    pub fn shutdown<L, A>(mut self, recorder: &mut CommandRecorder<L, A>)
+    where
+        A: CommandBufferAllocator,
+    {
+        self.receiver.close();
+        self.drain(recorder);
+    }
+
+    /// Like [`Self::shutdown`], but hands the commands to `sink` instead of a recorder
+    pub fn shutdown_into(mut self, sink: impl FnMut(RenderCommand)) {
+        self.receiver.close();
+        self.drain_into(sink);
+    }
+
+    fn fill_batch(&mut self) -> RenderWorkerStatus {
+        while self.batch.len() < RENDER_WORKER_BATCH_SIZE {
+            match self.receiver.try_recv() {
+                Ok(cmd) => self.batch.push(cmd),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => return RenderWorkerStatus::Closed,
+            }
+        }
+
+        RenderWorkerStatus::Open
+    }
+}
+
+/// Binding a pipeline which is immediately rebound with the same spec does nothing, since only
+/// the last push constants are visible to the next draw.
+fn coalesce_pipeline_binds(batch: &mut Vec<RenderCommand>) {
+    batch.dedup_by(|next, prev| {
+        let same_pipeline = match (&*prev, &*next) {
+            (
+                RenderCommand::BindDynamicGraphicsPipeline { pipeline: a, .. },
+                RenderCommand::BindDynamicGraphicsPipeline { pipeline: b, .. },
+            ) => a == b,
+            _ => false,
+        };
+
+        // dedup_by keeps the first of the two, so move the later push constants into it
+        if same_pipeline {
+            std::mem::swap(prev, next);
+        }
+
+        same_pipeline
+    });
+}
+
+/// Whether the vertices of two draws can be appended to each other and drawn in one go, which
+/// isn't the case for strips, fans and loops
+fn is_list_topology(mode: DrawMode) -> bool {
+    matches!(mode, DrawMode::Points | DrawMode::Lines | DrawMode::Tri)
+}
+
+/// What the last pipeline bind left bound, as far as the following draws can tell
+#[derive(Default)]
+struct BoundState {
+    pipeline: Option<(
+        DynamicPipelineSpec,
+        DynamicPipelinePushConstants,
+        DynamicRasterState,
+    )>,
+    blend_constants: Option<[f32; 4]>,
+    textures: Option<[Option<i32>; MAX_SHADER_TEXTURE_UNITS]>,
+}
+
+impl BoundState {
+    /// The vertex stride of the bound pipeline, if its draws can be merged
+    fn list_stride(&self) -> Option<usize> {
+        match &self.pipeline {
+            Some((spec, ..)) if is_list_topology(spec.draw_mode) => {
+                Some(spec.vertex_buffer.stride as usize)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// The bytes of `count` vertices starting at `start`, if a buffer of `len` bytes holds them all
+fn vertex_bytes(start: u32, count: u32, stride: usize, len: usize) -> Option<Range<usize>> {
+    let range = start as usize * stride..(start as usize + count as usize) * stride;
+
+    (range.end <= len).then_some(range)
+}
+
+/// Appends a draw's vertices to the open draw. Returns false if they can't be.
+fn append_draw(
+    open: &mut RenderCommand,
+    start_vertex: u32,
+    vertex_count: u32,
+    data: &[u8],
+    stride: usize,
+    arena: &FrameArena,
+) -> bool {
+    let RenderCommand::Draw {
+        start_vertex: open_start,
+        vertex_count: open_count,
+        data: open_data,
+    } = open
+    else {
+        return false;
+    };
+
+    let Some(appended) = vertex_bytes(start_vertex, vertex_count, stride, data.len()) else {
+        return false;
+    };
+
+    // the open draw is trimmed to just the vertices it draws before anything goes after them
+    if *open_start != 0 || open_data.len() != *open_count as usize * stride {
+        let Some(kept) = vertex_bytes(*open_start, *open_count, stride, open_data.len()) else {
+            return false;
+        };
+
+        let mut trimmed = arena.take(kept.len() + appended.len());
+        trimmed.extend_from_slice(&open_data[kept]);
+
+        arena.recycle(std::mem::replace(open_data, Arc::new(trimmed)));
+
+        *open_start = 0;
+    }
+
+    Arc::make_mut(open_data).extend_from_slice(&data[appended]);
+    *open_count += vertex_count;
+
+    true
+}
+
+/// Merges consecutive draws of the same pipeline into one draw over their concatenated vertices.
+/// The assembler rebinds everything before each draw, so rebinds which don't change anything are
+/// dropped; anything else between two draws (a different pipeline or push constants, uniforms, a
+/// clear) keeps them apart.
+fn coalesce_draws(batch: &mut Vec<RenderCommand>, arena: &FrameArena) {
+    let mut bound = BoundState::default();
+    // the index of the draw which the next draw may be appended to
+    let mut open_draw: Option<usize> = None;
+
+    let mut coalesced = Vec::with_capacity(batch.len());
+
+    for cmd in batch.drain(..) {
+        match cmd {
+            RenderCommand::BindDynamicGraphicsPipeline {
+                pipeline,
+                push_constants,
+                raster_state,
+            } => {
+                let state = (pipeline, push_constants, raster_state);
+
+                if bound.pipeline.as_ref() == Some(&state) {
+                    continue;
+                }
+
+                // the descriptors and blend constants have to be set again for the new pipeline
+                bound = BoundState {
+                    pipeline: Some(state.clone()),
+                    ..Default::default()
+                };
+                open_draw = None;
+
+                let (pipeline, push_constants, raster_state) = state;
+
+                coalesced.push(RenderCommand::BindDynamicGraphicsPipeline {
+                    pipeline,
+                    push_constants,
+                    raster_state,
+                });
+            }
+            RenderCommand::SetBlendConstants(constants) => {
+                if bound.blend_constants == Some(constants) {
+                    continue;
+                }
+
+                bound.blend_constants = Some(constants);
+                open_draw = None;
+                coalesced.push(cmd);
+            }
+            RenderCommand::BindTextureUnits(textures) => {
+                if bound.textures == Some(textures) {
+                    continue;
+                }
+
+                bound.textures = Some(textures);
+                open_draw = None;
+                coalesced.push(cmd);
+            }
+            RenderCommand::Draw {
+                start_vertex,
+                vertex_count,
+                data,
+            } => {
+                let stride = bound.list_stride();
+
+                if let (Some(i), Some(stride)) = (open_draw, stride) {
+                    if append_draw(
+                        &mut coalesced[i],
+                        start_vertex,
+                        vertex_count,
+                        &data,
+                        stride,
+                        arena,
+                    ) {
+                        arena.recycle(data);
+                        continue;
+                    }
+                }
+
+                open_draw = stride.map(|_| coalesced.len());
+
+                coalesced.push(RenderCommand::Draw {
+                    start_vertex,
+                    vertex_count,
+                    data,
+                });
+            }
+            cmd => {
+                open_draw = None;
+                coalesced.push(cmd);
+            }
+        }
+    }
+
+    *batch = coalesced;
+}
@@ -28,6 +28,20 @@ for (buffer_class, buffer_type) in buffer_types:
         members.append(f"    RenderSandbox.addPointerArray(size, stride, RenderSandbox.{array_type}, RenderSandbox.{buffer_type}, MemoryUtil.getAddress(pointer), pointer.remaining());")
         members.append(f"}}")
 
+# while a buffer is bound to GL_ARRAY_BUFFER, the pointer is a byte offset into it instead
+offset_array_types = [
+    ("ARRAY_TYPE_COLOR", "Color", "int size, ", "size"),
+    ("ARRAY_TYPE_COLOR_SECONDARY", "SecondaryColor", "int size, ", "size"),
+    ("ARRAY_TYPE_NORMAL", "Normal", "", "3"),
+    ("ARRAY_TYPE_TEXCOORD", "TexCoord", "int size, ", "size"),
+    ("ARRAY_TYPE_VERTEX", "Vertex", "int size, ", "size"),
+]
+
+for (array_type, method_name, size_param, size) in offset_array_types:
+    members.append(f"public static void gl{method_name}Pointer({size_param}int type, int stride, long pointerBufferOffset) {{")
+    members.append(f"    RenderSandbox.addPointerArray({size}, stride, RenderSandbox.{array_type}, type, pointerBufferOffset, 0);")
+    members.append(f"}}")
+
 gl_fns = [
     ("glVertex", [2, 3, 4]),
     ("glTexCoord", [2, 3, 4]),
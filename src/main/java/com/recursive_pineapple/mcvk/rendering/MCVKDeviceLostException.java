@@ -0,0 +1,13 @@
+package com.recursive_pineapple.mcvk.rendering;
+
+public class MCVKDeviceLostException extends MCVKException {
+
+    public MCVKDeviceLostException(String message) {
+        super(message);
+    }
+
+    @Override
+    public boolean isFatal() {
+        return true;
+    }
+}
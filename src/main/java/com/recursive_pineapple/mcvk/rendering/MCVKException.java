@@ -0,0 +1,16 @@
+package com.recursive_pineapple.mcvk.rendering;
+
+/**
+ * Base class for every exception thrown by the native renderer.
+ * Fatal exceptions mean the renderer can't continue and the game should be shut down.
+ */
+public class MCVKException extends RuntimeException {
+
+    public MCVKException(String message) {
+        super(message);
+    }
+
+    public boolean isFatal() {
+        return false;
+    }
+}
@@ -0,0 +1,13 @@
+package com.recursive_pineapple.mcvk.rendering;
+
+public class MCVKInitException extends MCVKException {
+
+    public MCVKInitException(String message) {
+        super(message);
+    }
+
+    @Override
+    public boolean isFatal() {
+        return true;
+    }
+}
@@ -5,6 +5,7 @@ import java.io.IOException;
 import java.io.InputStream;
 import java.net.URL;
 import java.nio.ByteBuffer;
+import java.nio.ByteOrder;
 import java.nio.file.Files;
 
 import org.jetbrains.annotations.Nullable;
@@ -75,6 +76,11 @@ public class MCVKNative {
 
     public static native void cleanup();
 
+    /**
+     * Loads the pipeline cache kept at {@code path}. It's written back to the same file by {@link #cleanup()}.
+     */
+    public static native void setPipelineCachePath(String path);
+
     public static void setMaxFPS(@Nullable Integer max_fps) {
         setMaxFPS(max_fps == null || max_fps < 0 ? 0 : max_fps);
     }
@@ -105,6 +111,182 @@ public class MCVKNative {
      */
     public static native void setVsyncMode(int mode);
 
+    /**
+     * Always presents with plain FIFO vsync while enabled, whatever {@link #setVsyncMode} asks for.
+     */
+    public static native void setForceFifo(boolean force);
+
+    /**
+     * @param {samples} 1, 2, 4, 8, 16, 32 or 64; clamped to what the device supports. Deferred rendering is never multisampled.
+     */
+    public static native void setMsaaSamples(int samples);
+
+    /**
+     * The highest mip level textures are sampled at, from the "Mipmap Levels" video setting. 0 turns mipmapping off.
+     */
+    public static native void setMipmapLevels(int levels);
+
+    /**
+     * From the "Anisotropic Filtering" video setting. Clamped to what the device supports, 1 turns it off.
+     */
+    public static native void setAnisotropy(int anisotropy);
+
+    /**
+     * When enabled, {@link #finishTextureReload} submits the texture uploads and returns straight away. The old textures
+     * stay in use until the uploads finish, and the new ones are swapped in on the next frame after that.
+     */
+    public static native void setAsyncTextureReload(boolean async);
+
+    /**
+     * Times each frame on the GPU, which fills in {@code gpuFrameTimeMs} in the diagnostics. Off by default, since it
+     * adds two timestamp queries to every frame.
+     */
+    public static native void setGpuTiming(boolean enabled);
+
+    /**
+     * Saves the next presented frame as a PNG. The frame in progress isn't affected, so the file is written once it has
+     * been presented.
+     */
+    public static native void captureScreenshot(String path);
+
+    /**
+     * Stops rendering frames while the window is minimized or unfocused.
+     */
+    public static native void setPaused(boolean paused);
+
+    public static enum FrameBoundary {
+        ColourClear(0),
+        Explicit(1);
+
+        public final int code;
+
+        FrameBoundary(int code) {
+            this.code = code;
+        }
+    }
+
+    public static void setFrameBoundary(FrameBoundary boundary) {
+        setFrameBoundary(boundary.code);
+    }
+
+    /**
+     * @param {boundary} 0 = a colour glClear presents the frame, 1 = frames are only presented by {@link #present()}
+     */
+    public static native void setFrameBoundary(int boundary);
+
+    public static enum RenderMode {
+        Forward(0),
+        Deferred(1);
+
+        public final int code;
+
+        RenderMode(int code) {
+            this.code = code;
+        }
+    }
+
+    public static void setRenderMode(RenderMode mode) {
+        setRenderMode(mode.code);
+    }
+
+    /**
+     * @param {mode} 0 = draws are shaded straight into the swapchain image, 1 = draws fill a g-buffer which is lit by a
+     *               second pass. Deferred rendering is never multisampled.
+     */
+    public static native void setRenderMode(int mode);
+
+    /**
+     * Renders the name on top of each draw's name stack into an extra attachment, which {@link RenderSandbox#pick} reads.
+     * Off by default, since it costs every frame. Object ids aren't rendered while multisampling.
+     */
+    public static native void setObjectPicking(boolean enabled);
+
+    /**
+     * Bounds how many render commands can wait to be recorded, so that they can't pile up while the GPU is behind. Once
+     * the queue is full, the frame in progress is started early and the waiting commands are recorded into it. <=0
+     * leaves the queue unbounded, which is the default.
+     */
+    public static native void setCommandQueueCapacity(int capacity);
+
+    /**
+     * Presents the frame in progress. Only needed when the frame boundary is {@link FrameBoundary#Explicit}.
+     */
+    public static native void present();
+
+    public static enum FrameAttachment {
+        Color(0),
+        Albedo(1),
+        Normals(2),
+        Depth(3),
+        MultisampledColor(4),
+        ObjectIds(5);
+
+        public final int code;
+
+        FrameAttachment(int code) {
+            this.code = code;
+        }
+    }
+
+    public static int getAttachmentFormat(FrameAttachment attachment) {
+        return getAttachmentFormat(attachment.code);
+    }
+
+    /**
+     * @param {attachment} 0 = Color, 1 = Albedo, 2 = Normals, 3 = Depth, 4 = MultisampledColor (resolved into Color),
+     *                    5 = ObjectIds
+     * @return The VkFormat the renderer uses for the attachment, or 0 (VK_FORMAT_UNDEFINED) if the current render
+     *         mode doesn't have it
+     */
+    public static native int getAttachmentFormat(int attachment);
+
+    /**
+     * A snapshot of the renderer's stats, for the debug overlay. Mirrors DiagnosticsSnapshot in render_manager.rs.
+     */
+    public static class Diagnostics {
+
+        /** The size of the native struct, in bytes */
+        public static final int SIZE = 48;
+
+        public float fps;
+        /** -1 if the GPU's frame time isn't known */
+        public float gpuFrameTimeMs;
+        public int drawCalls;
+        public int pipelineCount;
+        public long vertices;
+        public long textureVramBytes;
+        public float shaderCacheHitRate;
+        public int framesInFlight;
+        /** Pipeline switches in the last frame; redundant binds aren't counted */
+        public int pipelineBinds;
+        /** 1 when every draw switched pipelines, lower when draws are well sorted by state */
+        public float bindsPerDraw;
+
+        private void read(ByteBuffer buffer) {
+            fps = buffer.getFloat(0);
+            gpuFrameTimeMs = buffer.getFloat(4);
+            drawCalls = buffer.getInt(8);
+            pipelineCount = buffer.getInt(12);
+            vertices = buffer.getLong(16);
+            textureVramBytes = buffer.getLong(24);
+            shaderCacheHitRate = buffer.getFloat(32);
+            framesInFlight = buffer.getInt(36);
+            pipelineBinds = buffer.getInt(40);
+            bindsPerDraw = buffer.getFloat(44);
+        }
+    }
+
+    private static final ByteBuffer DIAGNOSTICS_BUFFER = ByteBuffer.allocateDirect(Diagnostics.SIZE)
+        .order(ByteOrder.nativeOrder());
+
+    public static Diagnostics getDiagnostics(Diagnostics out) {
+        getDiagnostics(DIAGNOSTICS_BUFFER);
+        out.read(DIAGNOSTICS_BUFFER);
+        return out;
+    }
+
+    public static native void getDiagnostics(ByteBuffer out);
+
     public static native void startFrame(Minecraft mc);
 
     public static native void finishFrame();
@@ -115,7 +297,23 @@ public class MCVKNative {
 
     public static native void enqueueRawSprite(String name, ByteBuffer image, int u, int v, String animationJson);
 
+    public static native void registerAtlasSprite(int atlas, String name, float uMin, float vMin, float uMax, float vMax);
+
+    public static native void setAtlasTextures(int blocks, int items);
+
     public static native void beginTextureReload();
 
-    public static native void finishTextureReload();
+    public static interface ReloadProgressListener {
+
+        /**
+         * Called on the render thread before the first texture is uploaded and after every chunk of uploads.
+         */
+        void onProgress(int completed, int total);
+    }
+
+    public static void finishTextureReload() {
+        finishTextureReload(null);
+    }
+
+    public static native void finishTextureReload(@Nullable ReloadProgressListener listener);
 }
@@ -0,0 +1,8 @@
+package com.recursive_pineapple.mcvk.rendering;
+
+public class MCVKPipelineException extends MCVKException {
+
+    public MCVKPipelineException(String message) {
+        super(message);
+    }
+}
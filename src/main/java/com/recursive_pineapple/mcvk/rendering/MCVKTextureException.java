@@ -0,0 +1,8 @@
+package com.recursive_pineapple.mcvk.rendering;
+
+public class MCVKTextureException extends MCVKException {
+
+    public MCVKTextureException(String message) {
+        super(message);
+    }
+}
@@ -0,0 +1,8 @@
+package com.recursive_pineapple.mcvk.rendering;
+
+public class MCVKUnsupportedOperationException extends MCVKException {
+
+    public MCVKUnsupportedOperationException(String message) {
+        super(message);
+    }
+}
@@ -1,8 +1,14 @@
 package com.recursive_pineapple.mcvk.rendering;
 
 import java.nio.ByteBuffer;
+import java.nio.FloatBuffer;
+import java.nio.IntBuffer;
+import java.nio.ShortBuffer;
 import java.nio.charset.Charset;
 
+import org.lwjgl.MemoryUtil;
+import org.lwjgl.opengl.GL11;
+
 public class RenderSandbox {
     
     private RenderSandbox() {
@@ -35,6 +41,8 @@ public class RenderSandbox {
     public native static void glEnableClientState(int cap);
     public native static void glDisableClientState(int cap);
 
+    public native static void glClientActiveTexture(int texture);
+
     public static final int ARRAY_TYPE_COLOR = 0;
     public static final int ARRAY_TYPE_COLOR_SECONDARY = 1;
     public static final int ARRAY_TYPE_INDEX = 2;
@@ -50,123 +58,223 @@ public class RenderSandbox {
 
     public static native void addPointerArray(int size, int stride, int array_type, int item_type, long start, int item_count);
 
-    public native static void glDrawArrays(int mode, int first, int count);
+    public static native void addAttribPointerArray(int index, int size, int type, boolean normalized, int stride, long start, int byteLength);
 
-    public static void glShadeModel(int mode) {
-        // TODO: this
+    public static void glVertexAttribPointer(int index, int size, boolean normalized, int stride, FloatBuffer buffer) {
+        addAttribPointerArray(index, size, GL11.GL_FLOAT, normalized, stride, MemoryUtil.getAddress(buffer), buffer.remaining() * 4);
     }
 
-    public static void glClearDepth(double value) {
-        // TODO?
+    public static void glVertexAttribPointer(int index, int size, boolean unsigned, boolean normalized, int stride, ByteBuffer buffer) {
+        addAttribPointerArray(index, size, unsigned ? GL11.GL_UNSIGNED_BYTE : GL11.GL_BYTE, normalized, stride, MemoryUtil.getAddress(buffer), buffer.remaining());
     }
 
-    public native static void glClear(int mask);
-
-    public static void glClearColor(float r, float g, float b, float a) {
-        // TODO: this
+    public static void glVertexAttribPointer(int index, int size, int type, boolean normalized, int stride, long bufferBufferOffset) {
+        addAttribPointerArray(index, size, type, normalized, stride, bufferBufferOffset, 0);
     }
 
-    public static void glDepthFunc(int func) {
-        // TODO: this
-    }
+    public native static int glGenBuffers();
 
-    public static void glDepthMask(boolean flag) {
-        // TODO: this
-    }
+    public native static void glDeleteBuffers(int buffer);
 
-    public static void glAlphaFunc(int func, float ref) {
-        // TODO: this
-    }
+    /**
+     * While a buffer is bound to GL_ARRAY_BUFFER, the start of a pointer array is a byte offset into it.
+     */
+    public native static void glBindBuffer(int target, int buffer);
 
-    public static void glBlendFunc(int sfactor, int dfactor) {
-        // TODO: this
+    public static native void addBufferData(int target, long data, int byteLength, int usage);
+
+    public static void glBufferData(int target, ByteBuffer data, int usage) {
+        addBufferData(target, MemoryUtil.getAddress(data), data.remaining(), usage);
     }
 
-    public static void glCullFace(int func) {
-        // TODO: this
+    public static void glBufferData(int target, FloatBuffer data, int usage) {
+        addBufferData(target, MemoryUtil.getAddress(data), data.remaining() * 4, usage);
     }
 
-    public static void glViewport(int a, int b, int c, int d) { /* NO-OP */ }
+    public static native void addBufferSubData(int target, long offset, long data, int byteLength);
 
-    public static void glColor4f(float r, float g, float b, float a) {
-        // TODO: this
+    public static void glBufferSubData(int target, long offset, ByteBuffer data) {
+        addBufferSubData(target, offset, MemoryUtil.getAddress(data), data.remaining());
     }
 
-    public static void glColorMask(boolean r, boolean g, boolean b, boolean a) {
-        // TODO: this
+    public static void glBufferSubData(int target, long offset, FloatBuffer data) {
+        addBufferSubData(target, offset, MemoryUtil.getAddress(data), data.remaining() * 4);
     }
 
-    public static void glFlush() { /* NO-OP? */ }
+    public native static void glEnableVertexAttribArray(int index);
 
-    public static int glGetError() {
-        return 0; // TODO?
+    public native static void glDisableVertexAttribArray(int index);
+
+    public native static void glDrawArrays(int mode, int first, int count);
+
+    public static native void addDrawElements(int mode, int count, int type, long indices, int byteLength);
+
+    public static void glDrawElements(int mode, ByteBuffer indices) {
+        addDrawElements(mode, indices.remaining(), GL11.GL_UNSIGNED_BYTE, MemoryUtil.getAddress(indices), indices.remaining());
     }
 
-    public static void glLineWidth(float width) {
-        // TODO: this
+    public static void glDrawElements(int mode, ShortBuffer indices) {
+        addDrawElements(mode, indices.remaining(), GL11.GL_UNSIGNED_SHORT, MemoryUtil.getAddress(indices), indices.remaining() * 2);
     }
 
-    public static int glGetInteger(int param) {
-        return 0;
+    public static void glDrawElements(int mode, IntBuffer indices) {
+        addDrawElements(mode, indices.remaining(), GL11.GL_UNSIGNED_INT, MemoryUtil.getAddress(indices), indices.remaining() * 4);
     }
 
-    public static float glGetFloat(int param) {
-        return 0f;
+    public static void glDrawElements(int mode, int count, int type, ByteBuffer indices) {
+        addDrawElements(mode, count, type, MemoryUtil.getAddress(indices), indices.remaining());
     }
 
-    public static String glGetString(int param) {
-        return "";
+    public native static void glProvokingVertex(int mode);
+
+    public static void glShadeModel(int mode) {
+        // TODO: this
     }
 
-    public static int glGetProgrami(int program, int param) {
-        return 0;
+    public static void glClearDepth(double value) {
+        // TODO?
     }
 
-    public static void glAttachShader(int program, int shader) {
+    public native static void glClear(int mask);
 
+    public static void glClearColor(float r, float g, float b, float a) {
+        // TODO: this
     }
 
-    public static void glDeleteShader(int shader) { /* NO-OP */ }
+    public native static void glDepthFunc(int func);
 
-    public static int glCreateShader(int type) {
-        return 0;
-    }
+    public native static void glDepthMask(boolean flag);
+
+    public native static void glAlphaFunc(int func, float ref);
+
+    public native static void glClipPlane(int plane, double a, double b, double c, double d);
+
+    public native static void glBlendFunc(int sfactor, int dfactor);
 
-    public static void glShaderSource(int shader, ByteBuffer source) {
-        
+    public native static void glCullFace(int mode);
+
+    public native static void glFrontFace(int mode);
+
+    public native static void glViewport(int x, int y, int width, int height);
+
+    /**
+     * Viewports past the first only exist when the GPU supports multiViewport, and are only drawn to by shaders which
+     * select them with gl_ViewportIndex.
+     */
+    public native static void glViewportIndexedf(int index, float x, float y, float width, float height);
+
+    public native static void glDepthRange(double zNear, double zFar);
+
+    public native static void glDepthRangeIndexed(int index, double zNear, double zFar);
+
+    public static void glColor4f(float r, float g, float b, float a) {
+        // TODO: this
     }
 
-    public static void glCompileShader(int shader) {
+    public native static void glColorMask(boolean r, boolean g, boolean b, boolean a);
+
+    public native static void glBlendColor(float red, float green, float blue, float alpha);
+
+    public native static void glFlush();
+
+    public native static int glGetError();
+
+    public native static void glPolygonMode(int face, int mode);
+
+    public native static void glHint(int target, int mode);
+
+    public native static long glFenceSync(int condition, int flags);
+
+    public native static int glClientWaitSync(long sync, int flags, long timeout);
 
+    public native static void glDeleteSync(long sync);
+
+    public native static void glScissor(int x, int y, int width, int height);
+
+    public native static void pushScissor(int x, int y, int width, int height);
+    public native static void popScissor();
+
+    public native static void glPushAttrib(int mask);
+    public native static void glPopAttrib();
+
+    public native static int glGenLists(int range);
+
+    public native static void glDeleteLists(int list, int range);
+
+    /**
+     * Until glEndList, everything pushed is recorded into the list instead of (GL_COMPILE) or as well as
+     * (GL_COMPILE_AND_EXECUTE) being drawn.
+     */
+    public native static void glNewList(int list, int mode);
+
+    public native static void glEndList();
+
+    public native static void glCallList(int list);
+
+    public native static void glListBase(int base);
+
+    public static native void addCallLists(int type, long lists, int byteLength);
+
+    public static void glCallLists(ByteBuffer lists) {
+        addCallLists(GL11.GL_UNSIGNED_BYTE, MemoryUtil.getAddress(lists), lists.remaining());
     }
 
-    public static int glGetShaderi(int shader, int param) {
-        return 0;
+    public static void glCallLists(ShortBuffer lists) {
+        addCallLists(GL11.GL_UNSIGNED_SHORT, MemoryUtil.getAddress(lists), lists.remaining() * 2);
     }
 
-    public static String glGetShaderInfoLog(int shader, int maxLength) {
-        return "";
+    public static void glCallLists(IntBuffer lists) {
+        addCallLists(GL11.GL_UNSIGNED_INT, MemoryUtil.getAddress(lists), lists.remaining() * 4);
     }
 
-    public static String glGetProgramInfoLog(int program, int maxLength) {
-        return "";
+    public native static void glInitNames();
+    public native static void glPushName(int name);
+    public native static void glPopName();
+    public native static void glLoadName(int name);
+
+    public static void glLineWidth(float width) {
+        // TODO: this
     }
 
-    public static void glUseProgram(int program) {
+    public native static int glGetInteger(int param);
+
+    public native static boolean glGetBoolean(int param);
 
+    public native static void glGetBooleanv(int param, ByteBuffer params);
+
+    public static float glGetFloat(int param) {
+        return 0f;
     }
 
-    public static int glCreateProgram() {
-        return 0;
+    public static String glGetString(int param) {
+        return "";
     }
 
-    public static void glDeleteProgram(int program) {
+    public native static int glGetProgrami(int program, int param);
 
-    }
+    public native static void glAttachShader(int program, int shader);
 
-    public static void glLinkProgram(int program) {
+    public native static void glDeleteShader(int shader);
 
-    }
+    public native static int glCreateShader(int type);
+
+    public native static void glShaderSource(int shader, ByteBuffer source);
+
+    public native static void glCompileShader(int shader);
+
+    public native static int glGetShaderi(int shader, int param);
+
+    public native static String glGetShaderInfoLog(int shader, int maxLength);
+
+    public native static String glGetProgramInfoLog(int program, int maxLength);
+
+    public native static void glUseProgram(int program);
+
+    public native static int glCreateProgram();
+
+    public native static void glDeleteProgram(int program);
+
+    public native static void glLinkProgram(int program);
 
     private static ByteBuffer seqToBuffer(CharSequence seq) {
         byte[] bytes = seq.toString().getBytes(Charset.forName("UTF8"));
@@ -182,17 +290,35 @@ public class RenderSandbox {
         return glGetUniformLocation(program, seqToBuffer(name));
     }
 
-    public static int glGetUniformLocation(int program, ByteBuffer name) {
-        return 0;
-    }
+    public native static int glGetUniformLocation(int program, ByteBuffer name);
+
+    public native static void glUniform1f(int location, float x);
+
+    public native static void glUniform2f(int location, float x, float y);
+
+    public native static void glUniform3f(int location, float x, float y, float z);
+
+    public native static void glUniform4f(int location, float x, float y, float z, float w);
+
+    public native static void glUniform1i(int location, int x);
+
+    public native static void glUniform2i(int location, int x, int y);
+
+    public native static void glUniform3i(int location, int x, int y, int z);
+
+    public native static void glUniform4i(int location, int x, int y, int z, int w);
+
+    public native static void glUniformMatrix2(int location, boolean transpose, FloatBuffer matrices);
+
+    public native static void glUniformMatrix3(int location, boolean transpose, FloatBuffer matrices);
+
+    public native static void glUniformMatrix4(int location, boolean transpose, FloatBuffer matrices);
 
     public static int glGetAttribLocation(int program, CharSequence name) {
         return glGetAttribLocation(program, seqToBuffer(name));
     }
 
-    public static int glGetAttribLocation(int program, ByteBuffer name) {
-        return 0;
-    }
+    public native static int glGetAttribLocation(int program, ByteBuffer name);
 
     public static void glBindFramebuffer(int a, int b) { /* NO-OP */ }
 
@@ -216,12 +342,57 @@ public class RenderSandbox {
 
     public native static int glGenTextures();
 
+    /** Fills the first {@code count} elements of {@code out} with new texture ids. */
+    public native static void glGenTexturesBatch(int count, int[] out);
+
     public native static void glBindTexture(int target, int texture);
 
+    public native static void glActiveTexture(int texture);
+
+    public native static void glMultiTexCoord2f(int target, float s, float t);
+
+    public native static void glTexEnvi(int target, int pname, int param);
+
+    public native static int glGetTexLevelParameteri(int target, int level, int pname);
+
     public native static void glTexImage2D(int target, int level, int internalFormat, int width, int height, int border, int format, int type, ByteBuffer data);
 
+    /**
+     * Supports the DXT1 and DXT5 S3TC formats. They're decoded to RGBA on devices which can't sample BC textures.
+     */
+    public native static void glCompressedTexImage2D(int target, int level, int internalFormat, int width, int height, int border, ByteBuffer data);
+
+    public native static void glTexSubImage2D(int target, int level, int xoffset, int yoffset, int width, int height, int format, int type, ByteBuffer data);
+
+    /**
+     * Like {@link #glTexSubImage2D}, but the rectangle can be anywhere within the texture instead of having to cover a
+     * whole animation frame.
+     */
+    public native static void glTextureSubImage2D(int target, int level, int xoffset, int yoffset, int width, int height, int format, int type, ByteBuffer data);
+
+    public native static void glGetTexImage(int target, int level, int format, int type, ByteBuffer pixels);
+
+    public native static void glReadPixels(int x, int y, int width, int height, int format, int type, ByteBuffer pixels);
+
+    public static void glReadBuffer(int mode) { /* NO-OP: front and back both read the last rendered frame */ }
+
+    /**
+     * Replaces GL_SELECT picking: every draw writes the name on top of the name stack into an object id buffer.
+     * Only works while {@link MCVKNative#setObjectPicking} is enabled, and object ids aren't rendered while
+     * multisampling.
+     *
+     * @return The name of whatever was drawn at the pixel (in window coordinates, from the bottom left) in the most
+     *         recently rendered frame, or 0 if nothing named was drawn there
+     */
+    public native static int pick(int x, int y);
+
+    public native static void glPixelTransferf(int pname, float param);
+    public native static void glPixelTransferi(int pname, int param);
+
     public native static void glDeleteTextures(int texture);
 
+    public native static void glDeleteTexturesBatch(int[] textures);
+
     public native static void glTexParameterf(int texture, int param, float value);
     public native static void glTexParameteri(int texture, int param, int value);
 
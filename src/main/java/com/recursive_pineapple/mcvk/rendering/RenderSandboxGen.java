@@ -98,6 +98,21 @@ public class RenderSandboxGen {
     public static void glVertexPointer(int size, int stride, DoubleBuffer pointer) {
         RenderSandbox.addPointerArray(size, stride, RenderSandbox.ARRAY_TYPE_VERTEX, RenderSandbox.ITEM_TYPE_DOUBLES, MemoryUtil.getAddress(pointer), pointer.remaining());
     }
+    public static void glColorPointer(int size, int type, int stride, long pointerBufferOffset) {
+        RenderSandbox.addPointerArray(size, stride, RenderSandbox.ARRAY_TYPE_COLOR, type, pointerBufferOffset, 0);
+    }
+    public static void glSecondaryColorPointer(int size, int type, int stride, long pointerBufferOffset) {
+        RenderSandbox.addPointerArray(size, stride, RenderSandbox.ARRAY_TYPE_COLOR_SECONDARY, type, pointerBufferOffset, 0);
+    }
+    public static void glNormalPointer(int type, int stride, long pointerBufferOffset) {
+        RenderSandbox.addPointerArray(3, stride, RenderSandbox.ARRAY_TYPE_NORMAL, type, pointerBufferOffset, 0);
+    }
+    public static void glTexCoordPointer(int size, int type, int stride, long pointerBufferOffset) {
+        RenderSandbox.addPointerArray(size, stride, RenderSandbox.ARRAY_TYPE_TEXCOORD, type, pointerBufferOffset, 0);
+    }
+    public static void glVertexPointer(int size, int type, int stride, long pointerBufferOffset) {
+        RenderSandbox.addPointerArray(size, stride, RenderSandbox.ARRAY_TYPE_VERTEX, type, pointerBufferOffset, 0);
+    }
     public static native void glVertex2f(float x, float y);
     public static native void glVertex2d(double x, double y);
     public static native void glVertex2i(int x, int y);